@@ -1,35 +1,189 @@
 use ic_cdk_macros::*;
-use crate::domain::{AgentConfig, AgentHealth, InferenceRequest, InferenceResponse};
+use crate::domain::{AgentInitArgs, AgentConfig, AgentHealth, InferenceRequest, InferenceResponse};
 use crate::domain::instruction::*;
-use crate::services::{BindingService, InferenceService, MemoryService, CacheService, InstructionAnalyzer, AgentFactory, with_state, AgentTaskResult, AgentStatusInfo, AgentSummary, AgentTask, ModelRepoClient, NOVAQValidationResult, NOVAQModelMeta};
-use crate::services::agent_factory::TaskPriority;
-use crate::infra::{Guards, Metrics};
+use crate::services::{AgentRequestService, IntrospectionService, AgentDescription, StandbyService, StandbyConfig, StandbyStatus, KeepaliveService, KeepaliveConfig, KeepaliveStatus, QuotaService, MyQuota, RedactionService, RedactionPolicy, RedactionPolicyInput, ArtifactService, ArtifactPage, AgentEnvService, AgentEnvVarInfo, MetricSeriesService, MetricSeries, MetricRange, SloService, SloEndpoint, SloObjective, SloReport, AlertService, AlertRule, AlertRuleSpec, AgentSnapshotService, AgentSnapshotInfo, AgentSnapshotView, SnapshotQuota, SelfImprovementService, ConfigProposal, SkillService, Skill, JournalService, JournalEntry, JournalPage, JournalRecovery, JournalState, BatchingService, BatchingStatus, PricingService, PricingTable, CostService, CostEstimate, BillingService, BillingBatchPage, BillingStatus, BillingTotals, MarketplaceService, ListingInfo, ListingPage, ListingQuery, PublishRequest, DataSubjectService, DeletionRequest, UserDataPage, PrivacyService, VetKdConfig, PurgeReport, AgentMemoryService, MemoryBatch, MemoryExportPage, MemoryConflictPolicy, MemoryImportReport, SystemCallerService, SystemCaller, SystemAuditPage, SystemOpsService, BatchInferenceResult, UsageExportPage, MigrationReport, AttestationService, CallerEnvelope, CoordinatorInfo, AttestationAuditPage, OutageService, DeferredTask, ReplayService, ReplayOverrides, ReplayReport, TraceService, ExecutionTrace, SigningService, SigningConfig, SigningPublicKey, ShardingService, ShardingConfig, ShardingStatus, Shard, ShardState, ShardLoad, ShardAgentPage, RebalanceReport, SlaService, SlaReport, CalibrationService, CalibrationStats, FeedbackService, TaskFeedback, ApprovalService, ApprovalRequest, ApprovalDecision, ApprovalOutcome, RiskRule, PromptTemplateService, PromptTemplate, PromptTemplateInput, TemplateScope, TemplateValue, ExperimentService, Experiment, ExperimentSpec, ExperimentResults, ModelPoolService, PoolModelStats, CompatibilityService, CompatibilityReport, ValidationHistoryService, ValidationRecord, BenchmarkService, BenchmarkSuite, BenchmarkResult, WarmSetService, WarmSetReport, SettingsService, CanisterSettings, ApiKeyService, ApiKeyInfo, HttpGatewayService, HttpRequest, HttpResponse, DashboardService, Dashboard, CertificationService, CertifiedAgentStatus, CertifiedModelCatalog, GcService, GcConfig, GcReport, ArchiveService, ArchivedAgent, ArchivedAgentInfo, CloneOptions, AgentBudget, BudgetStatus, BindingService, InferenceService, MemoryService, MemoryMatch, MemoryListPage, MemoryVersion, MemoryVersionInfo, MemoryVersioning, EmbeddingService, EmbeddingBackend, CacheService, CacheStats, InstructionAnalyzer, AgentFactory, with_state, AgentTaskResult, AgentStatusInfo, AgentSummary, ModelRepoClient, RepoStatus, NOVAQValidationResult, NOVAQModelMeta, TaskHistoryService, TaskRecord, TaskHistoryPage, WorkflowService, Workflow, DelegationService, Delegation, AccessScope, WebhookService, WebhookEvent, WebhookInfo, WebhookDelivery, ToolService, ToolCall, ToolCallResult, CanisterToolService, CanisterTool, ToolRegistrationStatus, ToolAuditPage, KnowledgeService, KnowledgeDocument, KnowledgeStats, ReindexReport, RetrievedPassage, UploadService, UploadPurpose, UploadStatus, EventService, EventPage, EventSubscription, PaymentService, PaymentConfig, PaymentReceipt, HealthService, DetailedHealth, llm_service, QuantizedModel, ChatMessage, ConversationSession, LlmError, ExportFormat, ConversationExportPage, ConversationListing, SearchFilters, SearchResults, ConversationSettings, MessageStreamPoll};
+use crate::services::agent_factory::TaskOutcome;
+use crate::services::payments::PAYMENT_RECEIPT_KEY;
+use crate::services::skills::SKILL_KEY;
+use crate::infra::{FaultConfig, FaultStatus, Faults, Guards, Metrics, SpanExport, Spans};
+use crate::infra::guards::{MemoryLimits, MemoryUsage, RateLimitStatus};
+use crate::infra::scheduler::{Scheduler, SchedulerStatus};
+use crate::domain::api_version::{ApiError, PageRequest};
 use std::collections::HashMap;
 
+#[init]
+fn init(args: Option<AgentInitArgs>) {
+    // A bad configuration fails the install instead of leaving a half-configured canister
+    if let Err(e) = SettingsService::apply_init_args(args) {
+        ic_cdk::trap(&format!("Invalid init args: {}", e));
+    }
+    // Timers do not survive upgrades: every timer started here is started again in post_upgrade
+    HealthService::start_heartbeat();
+    ArchiveService::start_retention_timer();
+    GcService::start_timer();
+    CertificationService::start_timer();
+    WarmSetService::start_timer();
+    CacheService::start_compaction_timer();
+    CalibrationService::start_timer();
+    ShardingService::start_timer();
+    OutageService::start_timer();
+    AttestationService::start_timer();
+    DataSubjectService::start_timer();
+    BillingService::start_timer();
+    JournalService::start_timer();
+    SelfImprovementService::start_timer();
+    AlertService::start_timer();
+    MetricSeriesService::start_timer();
+    KeepaliveService::start_timer();
+    StandbyService::start_timer();
+    WorkflowService::start_timer();
+    Guards::start_timer();
+    PaymentService::start_timer();
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    AgentFactory::save_to_stable();
+    Guards::save_to_stable();
+    Metrics::save_to_stable();
+}
+
+#[post_upgrade]
+fn post_upgrade(args: Option<AgentInitArgs>) {
+    if let Err(e) = SettingsService::apply_init_args(args) {
+        ic_cdk::trap(&format!("Invalid upgrade args: {}", e));
+    }
+    AgentFactory::restore_from_stable();
+    Guards::restore_from_stable();
+    Metrics::restore_from_stable();
+    WorkflowService::rehydrate_after_upgrade();
+    JournalService::recover(true);
+    // Same timers as init
+    HealthService::start_heartbeat();
+    ArchiveService::start_retention_timer();
+    GcService::start_timer();
+    CertificationService::start_timer();
+    WarmSetService::start_timer();
+    CacheService::start_compaction_timer();
+    CalibrationService::start_timer();
+    ShardingService::start_timer();
+    OutageService::start_timer();
+    AttestationService::start_timer();
+    DataSubjectService::start_timer();
+    BillingService::start_timer();
+    JournalService::start_timer();
+    SelfImprovementService::start_timer();
+    AlertService::start_timer();
+    MetricSeriesService::start_timer();
+    KeepaliveService::start_timer();
+    StandbyService::start_timer();
+    WorkflowService::start_timer();
+    Guards::start_timer();
+    PaymentService::start_timer();
+}
+
 #[update]
 async fn bind_model(model_id: String) -> Result<(), String> {
     Guards::require_caller_authenticated()?;
-    BindingService::bind_model(model_id).await
+    let started_at = crate::infra::clock::time();
+    let result = BindingService::bind_model(model_id).await;
+    SloService::record(SloEndpoint::BindModel, started_at, result.is_ok());
+    result
+}
+
+// Models served alongside the bound model
+
+/// `memory_budget_bytes` of None budgets the whole model
+#[update]
+async fn add_pool_model(model_id: String, memory_budget_bytes: Option<u64>) -> Result<PoolModelStats, String> {
+    Guards::require_admin()?;
+    ModelPoolService::add(&model_id, memory_budget_bytes).await
+}
+
+#[update]
+fn remove_pool_model(model_id: String) -> Result<(), String> {
+    Guards::require_admin()?;
+    ModelPoolService::remove(&model_id)
+}
+
+/// The bound model first, then pool models
+#[query]
+fn get_model_pool() -> Result<Vec<PoolModelStats>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(ModelPoolService::stats())
+}
+
+// Warm standby for failover
+
+/// Keep `prefetch_percent` of a secondary model cached so promoting it is quick
+#[update]
+async fn set_standby_model(config: StandbyConfig) -> Result<StandbyStatus, String> {
+    Guards::require_admin()?;
+    StandbyService::set(config).await
+}
+
+#[update]
+fn clear_standby_model() -> Result<(), String> {
+    Guards::require_admin()?;
+    StandbyService::clear()
+}
+
+/// Bind the standby model; the model bound until now becomes the standby
+#[update]
+async fn promote_standby_model() -> Result<(), String> {
+    Guards::require_admin()?;
+    StandbyService::promote().await
+}
+
+#[query]
+fn get_standby_status() -> Result<Option<StandbyStatus>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(StandbyService::status())
 }
 
 #[update] 
 async fn infer(request: InferenceRequest) -> Result<InferenceResponse, String> {
     Guards::require_caller_authenticated()?;
     Guards::rate_limit_check()?;
+    Guards::refresh_caller_tier().await;
     Guards::validate_prompt_length(&request.prompt)?;
     Guards::validate_msg_id(&request.msg_id)?;
     
-    let result = InferenceService::process_inference(request).await?;
+    let started_at = crate::infra::clock::time();
+    let result = InferenceService::process_inference(request).await;
+    SloService::record(SloEndpoint::Infer, started_at, result.is_ok());
+    let result = result?;
+    Guards::record_token_usage(result.tokens.len() as u64);
     Metrics::increment_inference_count();
     Ok(result)
 }
 
+#[query]
+fn get_my_limits() -> Result<RateLimitStatus, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(Guards::get_my_limits())
+}
+
+/// Token usage, what is left today and this month, agent slots, rate-limit
+/// buckets and when each resets
+#[query]
+fn get_my_quota() -> Result<MyQuota, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(QuotaService::for_principal(ic_cdk::api::caller()))
+}
+
 #[update]
 fn set_config(config: AgentConfig) -> Result<(), String> {
-    Guards::require_caller_authenticated()?;
+    Guards::require_admin()?;
     BindingService::set_config(config)
 }
 
+#[query]
+fn get_settings() -> Result<CanisterSettings, String> {
+    Guards::require_admin()?;
+    Ok(SettingsService::settings())
+}
+
 #[query]
 fn get_config() -> Result<AgentConfig, String> {
     Guards::require_caller_authenticated()?;
@@ -41,36 +195,220 @@ fn health() -> AgentHealth {
     BindingService::get_health()
 }
 
+#[query]
+fn health_detailed() -> DetailedHealth {
+    HealthService::get_detailed_health()
+}
+
 #[query]
 fn repo_canister() -> Result<String, String> {
     Guards::require_caller_authenticated()?;
     Ok(crate::services::with_state(|s| s.config.model_repo_canister_id.clone()))
 }
 
+/// Configured model repos in failover order, with how many chunks each served
+#[query]
+fn get_repo_status() -> Result<Vec<RepoStatus>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(ModelRepoClient::repo_status())
+}
+
+// Prompt templates
+
+/// Global templates need an admin; owner overrides apply to the caller's agents
+#[update]
+fn save_prompt_template(input: PromptTemplateInput) -> Result<PromptTemplate, String> {
+    Guards::require_caller_authenticated()?;
+    if input.scope == TemplateScope::Global {
+        Guards::require_admin()?;
+    }
+    PromptTemplateService::save(input, ic_cdk::api::caller())
+}
+
+#[update]
+fn revert_prompt_template(name: String, scope: TemplateScope, version: u32) -> Result<PromptTemplate, String> {
+    Guards::require_caller_authenticated()?;
+    if scope == TemplateScope::Global {
+        Guards::require_admin()?;
+    }
+    PromptTemplateService::revert(&name, scope, version, ic_cdk::api::caller())
+}
+
+/// Templates in effect for the caller's agents
+#[query]
+fn list_prompt_templates() -> Result<Vec<PromptTemplate>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(PromptTemplateService::list(Some(ic_cdk::api::caller())))
+}
+
+/// Versions of the global template or the caller's override, newest first
+#[query]
+fn get_prompt_template_history(name: String, scope: TemplateScope) -> Result<Vec<PromptTemplate>, String> {
+    Guards::require_caller_authenticated()?;
+    let owner = match scope {
+        TemplateScope::Global => None,
+        TemplateScope::Owner => Some(ic_cdk::api::caller()),
+    };
+    Ok(PromptTemplateService::history(&name, owner))
+}
+
+#[query]
+fn render_prompt_template(name: String, values: Vec<(String, TemplateValue)>) -> Result<String, String> {
+    Guards::require_caller_authenticated()?;
+    PromptTemplateService::render_named(&name, Some(ic_cdk::api::caller()), &values.into_iter().collect())
+}
+
+// A/B experiments on agent tasks
+
+#[update]
+fn create_experiment(spec: ExperimentSpec) -> Result<Experiment, String> {
+    Guards::require_admin()?;
+    ExperimentService::create(spec, ic_cdk::api::caller())
+}
+
+#[update]
+fn stop_experiment(experiment_id: String) -> Result<(), String> {
+    Guards::require_admin()?;
+    ExperimentService::stop(&experiment_id)
+}
+
+#[query]
+fn list_experiments() -> Result<Vec<Experiment>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(ExperimentService::list())
+}
+
+/// Success rate, latency and tokens per variant
+#[query]
+fn get_experiment_results(experiment_id: String) -> Result<ExperimentResults, String> {
+    Guards::require_caller_authenticated()?;
+    ExperimentService::results(&experiment_id)
+}
+
+// Quality benchmarks of the bound model
+
+#[update]
+fn save_benchmark_suite(suite: BenchmarkSuite) -> Result<(), String> {
+    Guards::require_admin()?;
+    BenchmarkService::save_suite(suite)
+}
+
+/// Stored suites, including the built-in "smoke" suite
+#[query]
+fn list_benchmark_suites() -> Result<Vec<BenchmarkSuite>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(BenchmarkService::list_suites())
+}
+
+#[update]
+async fn run_model_benchmark(model_id: String, suite: String) -> Result<BenchmarkResult, String> {
+    Guards::require_admin()?;
+    BenchmarkService::run(&model_id, &suite).await
+}
+
+/// Runs for the model, newest first
+#[query]
+fn get_benchmark_results(model_id: String) -> Result<Vec<BenchmarkResult>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(BenchmarkService::results(&model_id))
+}
+
 #[update]
 async fn prefetch_next(n: u32) -> Result<u32, String> {
     Guards::require_caller_authenticated()?;
     BindingService::prefetch_next(n).await
 }
 
+/// Chunks the loader keeps resident for the bound model and how much of them is cached
+#[query]
+fn get_warm_set() -> WarmSetReport {
+    WarmSetService::report()
+}
+
+/// Layer cache occupancy, with raw and stored (compressed) sizes
+#[query]
+fn get_cache_stats() -> CacheStats {
+    CacheService::stats()
+}
+
+/// Turn idle-time warm-up pings on or off and set how long idle means
+#[update]
+fn set_keepalive_config(config: KeepaliveConfig) -> Result<(), String> {
+    Guards::require_admin()?;
+    KeepaliveService::set_config(config)
+}
+
+/// Keepalive settings, ping counts and how often callers still hit a cold start
+#[query]
+fn get_keepalive_status() -> Result<KeepaliveStatus, String> {
+    Guards::require_admin()?;
+    Ok(KeepaliveService::status())
+}
+
 #[query]
 fn get_loader_stats() -> Result<String, String> {
-    let (bound, loaded, total, cache_util, cache_entries) = with_state(|s| {
+    let util = CacheService::get_utilization();
+    let (bound, loaded, total, reused, saved, cache_util, cache_entries) = with_state(|s| {
         let bound = s.binding.is_some();
         let (loaded, total) = s.binding.as_ref().map(|b| (b.chunks_loaded, b.total_chunks)).unwrap_or((0,0));
-        let util = CacheService::get_utilization();
+        let (reused, saved) = s.binding.as_ref().map(|b| (b.chunks_reused, b.bytes_saved)).unwrap_or((0,0));
         let entries = s.cache_entries.len();
-        (bound, loaded, total, util, entries)
+        (bound, loaded, total, reused, saved, util, entries)
     });
     Ok(serde_json::json!({
         "model_bound": bound,
         "chunks_loaded": loaded,
         "total_chunks": total,
+        "chunks_reused": reused,
+        "bytes_saved": saved,
         "cache_utilization": cache_util,
         "cache_entries": cache_entries
     }).to_string())
 }
 
+// OpenAI-compatible HTTP gateway
+
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    HttpGatewayService::handle_query(request)
+}
+
+#[update]
+async fn http_request_update(request: HttpRequest) -> HttpResponse {
+    HttpGatewayService::handle_update(request).await
+}
+
+/// Create a bearer key for the HTTP gateway that acts as the caller. The
+/// returned secret cannot be retrieved again.
+#[update]
+async fn create_api_key(label: String) -> Result<String, String> {
+    Guards::require_caller_authenticated()?;
+    ApiKeyService::create(ic_cdk::api::caller(), label).await
+}
+
+#[query]
+fn list_api_keys() -> Result<Vec<ApiKeyInfo>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(ApiKeyService::list(ic_cdk::api::caller()))
+}
+
+#[update]
+fn revoke_api_key(key_id: String) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    ApiKeyService::revoke(ic_cdk::api::caller(), &key_id)
+}
+
+/// Health, binding progress, agents, quotas and recent events for `principal`
+/// in one call
+#[query(composite = true)]
+async fn get_dashboard(principal: candid::Principal) -> Result<Dashboard, String> {
+    Guards::require_caller_authenticated()?;
+    if principal != ic_cdk::api::caller() {
+        return Err("Can only view your own dashboard".to_string());
+    }
+    DashboardService::snapshot(principal).await
+}
+
 #[query]
 fn get_memory_stats() -> Result<String, String> {
     Guards::require_caller_authenticated()?;
@@ -84,150 +422,1778 @@ fn clear_memory() -> Result<(), String> {
     Ok(())
 }
 
-// Phase 2: Instruction Analysis and Agent Factory APIs
+/// One page of an agent's key-value memory; pass next_cursor back for the next
+#[query]
+fn export_memory(agent_id: String, cursor: Option<String>) -> Result<MemoryExportPage, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    AgentMemoryService::export(&agent_id, cursor)
+}
 
+/// Apply an exported batch; rejected whole if a digest does not match
 #[update]
-async fn analyze_instruction(instruction: UserInstruction) -> Result<AnalyzedInstruction, String> {
+fn import_memory(agent_id: String, batch: MemoryBatch, policy: MemoryConflictPolicy) -> Result<MemoryImportReport, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentMemoryService::import(&agent_id, batch, policy)
+}
+
+// Memory APIs; keys are scoped to the caller's namespace
+
+/// Write `key`; the value it replaces is kept as an older version. Text
+/// values are redacted first under the caller's redaction policy.
+#[update]
+async fn memory_put(
+    key: String,
+    data: Vec<u8>,
+    ttl_seconds: u64,
+    encrypt: bool,
+    retention_policy: Option<RetentionPolicy>,
+) -> Result<(), String> {
     Guards::require_caller_authenticated()?;
-    InstructionAnalyzer::analyze_instruction(instruction)
+    Guards::rate_limit_check()?;
+    let owner = ic_cdk::api::caller();
+    let data = RedactionService::redact_bytes(&owner, data).await;
+    MemoryService::put(&owner, &key, data, ttl_seconds, encrypt, retention_policy.unwrap_or_default())
 }
 
+/// An update call because reading an expired entry removes it
 #[update]
-async fn create_agent(instruction: UserInstruction) -> Result<String, String> {
+fn memory_get(key: String) -> Result<Vec<u8>, String> {
     Guards::require_caller_authenticated()?;
-    
-    // Analyze the instruction
-    let analysis = InstructionAnalyzer::analyze_instruction(instruction.clone())?;
-    
-    // Create the agent
-    let user_id = instruction.user_id.clone();
-    let agent = AgentFactory::create_agent(user_id, instruction, analysis).await?;
-    
-    Ok(agent.agent_id)
+    MemoryService::retrieve(&MemoryService::namespaced(&ic_cdk::api::caller(), &key)?)
 }
 
-// Compatible endpoint for UI (maps to create_agent)
-#[derive(serde::Deserialize, candid::CandidType)]
-pub struct AgentCreationRequest {
-    pub instruction: String,
-    pub agent_count: Option<u32>,
-    pub capabilities: Option<Vec<String>>,
-    pub priority: Option<String>,
+#[update]
+fn memory_delete(key: String) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    MemoryService::delete(&MemoryService::namespaced(&ic_cdk::api::caller(), &key)?)
 }
 
-#[derive(serde::Serialize, candid::CandidType)]
-pub struct AgentCreationResult {
-    pub agent_id: String,
-    pub status: String,
-    pub capabilities: Vec<String>,
-    pub estimated_completion: Option<u64>,
+#[query]
+fn memory_list(prefix: String, cursor: Option<String>) -> Result<MemoryListPage, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(MemoryService::list(&ic_cdk::api::caller(), &prefix, cursor))
 }
 
-#[update]
-async fn create_agent_from_instruction(request: AgentCreationRequest) -> Result<AgentCreationResult, String> {
+#[query]
+fn get_memory_version(key: String, version: u32) -> Result<MemoryVersion, String> {
     Guards::require_caller_authenticated()?;
-    
-    // Convert to UserInstruction format
-    let user_instruction = UserInstruction {
-        instruction_text: request.instruction,
-        user_id: ic_cdk::api::caller().to_string(),
-        subscription_tier: SubscriptionTier::Basic, // Will be validated by coordinator
-        context: Some(InstructionContext {
-            domain: None,
-            complexity: None,
-            urgency: Some(match request.priority.as_deref() {
-                Some("low") => UrgencyLevel::Low,
-                Some("high") => UrgencyLevel::High,
-                Some("critical") => UrgencyLevel::Critical,
-                _ => UrgencyLevel::Normal,
-            }),
-            collaboration_needed: request.agent_count.unwrap_or(1) > 1,
-            external_tools_required: vec![],
-        }),
-        preferences: Some(AgentPreferences {
-            response_style: ResponseStyle::Conversational,
-            detail_level: DetailLevel::Standard,
-            creativity_level: CreativityLevel::Balanced,
-            safety_level: SafetyLevel::Standard,
-            language: "en".to_string(),
-        }),
-    };
-    
-    // Analyze the instruction
-    let analysis = InstructionAnalyzer::analyze_instruction(user_instruction.clone())?;
-    
-    // Create the agent(s)
-    let agent_count = request.agent_count.unwrap_or(1);
-    let user_id = user_instruction.user_id.clone();
-    
-    if agent_count == 1 {
-        let agent = AgentFactory::create_agent(user_id, user_instruction, analysis).await?;
-        Ok(AgentCreationResult {
-            agent_id: agent.agent_id,
-            status: "Ready".to_string(),
-            capabilities: request.capabilities.unwrap_or_else(|| vec!["General Assistant".to_string()]),
-            estimated_completion: Some(ic_cdk::api::time() + 30_000_000_000), // 30 seconds from now
-        })
-    } else {
-        let agents = AgentFactory::create_coordinated_agents(user_id, user_instruction, analysis).await?;
-        // Return first agent ID (coordinator)
-        let primary_agent = agents.first().ok_or("Failed to create coordinated agents")?;
-        Ok(AgentCreationResult {
-            agent_id: primary_agent.agent_id.clone(),
-            status: "Ready".to_string(),
-            capabilities: request.capabilities.unwrap_or_else(|| vec!["Coordinated Team".to_string()]),
-            estimated_completion: Some(ic_cdk::api::time() + 60_000_000_000), // 60 seconds for coordinated
-        })
-    }
+    let full_key = MemoryService::namespaced(&ic_cdk::api::caller(), &key)?;
+    MemoryService::get_version(&full_key, version).map(|found| MemoryVersion { key, ..found })
 }
 
-#[update]
-async fn create_coordinated_agents(instruction: UserInstruction) -> Result<Vec<String>, String> {
+#[query]
+fn list_memory_versions(key: String) -> Result<Vec<MemoryVersionInfo>, String> {
     Guards::require_caller_authenticated()?;
-    
-    // Analyze the instruction
-    let analysis = InstructionAnalyzer::analyze_instruction(instruction.clone())?;
-    
-    // Create coordinated agents
-    let user_id = instruction.user_id.clone();
-    let agents = AgentFactory::create_coordinated_agents(user_id, instruction, analysis).await?;
-    
-    Ok(agents.into_iter().map(|a| a.agent_id).collect())
+    Ok(MemoryService::list_versions(&MemoryService::namespaced(&ic_cdk::api::caller(), &key)?))
 }
 
+/// Make an older version current again; returns the version number it gets
 #[update]
-async fn execute_agent_task(agent_id: String, task_description: String) -> Result<AgentTaskResult, String> {
+fn restore_memory_version(key: String, version: u32) -> Result<u32, String> {
     Guards::require_caller_authenticated()?;
-    
-    let task = AgentTask {
-        task_id: format!("task-{}", ic_cdk::api::time()),
-        description: task_description,
-        priority: TaskPriority::Normal,
-        deadline: None,
-        context: HashMap::new(),
-    };
-    
-    AgentFactory::execute_task(&agent_id, task).await
+    MemoryService::restore_version(&MemoryService::namespaced(&ic_cdk::api::caller(), &key)?, version)
 }
 
-#[query]
-async fn get_agent_status(agent_id: String) -> Result<AgentStatusInfo, String> {
-    Guards::require_caller_authenticated()?;
-    AgentFactory::get_agent_status(&agent_id).await
+#[update]
+fn set_memory_versioning(versioning: MemoryVersioning) -> Result<(), String> {
+    Guards::require_admin()?;
+    MemoryService::set_versioning(versioning)
 }
 
 #[query]
-async fn list_user_agents(user_id: String) -> Result<Vec<AgentSummary>, String> {
+fn get_memory_versioning() -> Result<MemoryVersioning, String> {
+    Guards::require_admin()?;
+    Ok(MemoryService::versioning())
+}
+
+#[update]
+async fn search_memory(query: String, top_k: Option<u32>) -> Result<Vec<MemoryMatch>, String> {
     Guards::require_caller_authenticated()?;
-    AgentFactory::list_user_agents(&user_id).await
+    MemoryService::search(&ic_cdk::api::caller(), &query, top_k).await
 }
 
-// NOVAQ Validation APIs
+// Embedding APIs
 
+/// Documents embedded with the previous backend fall back to lexical ranking
+/// until reindex_knowledge recomputes their vectors
 #[update]
-async fn validate_novaq_model(model_id: String, model_data: Vec<u8>) -> Result<NOVAQValidationResult, String> {
-    Guards::require_caller_authenticated()?;
-    ModelRepoClient::validate_novaq_model(&model_id, &model_data).await
+async fn set_embedding_backend(backend: EmbeddingBackend) -> Result<(), String> {
+    Guards::require_admin()?;
+    EmbeddingService::set_backend(backend).await
+}
+
+#[query]
+fn get_embedding_backend() -> Result<EmbeddingBackend, String> {
+    Guards::require_admin()?;
+    Ok(EmbeddingService::backend_info())
+}
+
+#[query]
+fn transform_embedding_response(
+    args: ic_cdk::api::management_canister::http_request::TransformArgs,
+) -> ic_cdk::api::management_canister::http_request::HttpResponse {
+    EmbeddingService::transform(args)
+}
+
+// Phase 2: Instruction Analysis and Agent Factory APIs
+
+#[update]
+async fn analyze_instruction(instruction: UserInstruction) -> Result<AnalyzedInstruction, String> {
+    Guards::require_caller_authenticated()?;
+    InstructionAnalyzer::analyze_instruction(instruction)
+}
+
+#[update]
+async fn create_agent(instruction: UserInstruction) -> Result<String, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(AgentRequestService::create_agent(ic_cdk::api::caller(), instruction).await?)
+}
+
+// Result signing APIs
+
+#[update]
+fn set_signing_config(config: SigningConfig) -> Result<(), String> {
+    Guards::require_admin()?;
+    SigningService::set_config(config)
+}
+
+#[query]
+fn get_signing_config() -> Result<SigningConfig, String> {
+    Guards::require_admin()?;
+    Ok(SigningService::config())
+}
+
+/// Key that task result signatures verify against. An update call because the
+/// key comes from the management canister.
+#[update]
+async fn get_signing_public_key() -> Result<SigningPublicKey, String> {
+    Guards::require_caller_authenticated()?;
+    SigningService::public_key().await
+}
+
+// System caller allowlist APIs
+
+#[update]
+fn add_system_caller(canister_id: candid::Principal, label: String) -> Result<SystemCaller, String> {
+    Guards::require_admin()?;
+    SystemCallerService::add(canister_id, label, ic_cdk::api::caller())
+}
+
+#[update]
+fn remove_system_caller(canister_id: candid::Principal) -> Result<(), String> {
+    Guards::require_admin()?;
+    SystemCallerService::remove(canister_id, ic_cdk::api::caller())
+}
+
+#[query]
+fn list_system_callers() -> Result<Vec<SystemCaller>, String> {
+    Guards::require_admin()?;
+    Ok(SystemCallerService::list())
+}
+
+#[query]
+fn list_system_audit(cursor: Option<u64>, limit: Option<u32>) -> Result<SystemAuditPage, String> {
+    Guards::require_admin()?;
+    Ok(SystemCallerService::list_audit(cursor, limit))
+}
+
+// System endpoints; callable only by allowlisted canisters
+
+#[update]
+async fn system_batch_infer(requests: Vec<InferenceRequest>) -> Result<Vec<BatchInferenceResult>, String> {
+    Guards::require_system_caller("system_batch_infer")?;
+    SystemOpsService::batch_infer(requests, ic_cdk::api::caller()).await
+}
+
+#[update]
+fn system_export_usage(page: PageRequest) -> Result<UsageExportPage, String> {
+    Guards::require_system_caller("system_export_usage")?;
+    Ok(SystemOpsService::export_usage(&page))
+}
+
+/// Move agents to another canister that allowlists this one
+#[update]
+async fn system_migrate_agents(agent_ids: Vec<String>, target: candid::Principal) -> Result<MigrationReport, String> {
+    Guards::require_system_caller("system_migrate_agents")?;
+    SystemOpsService::migrate_agents(agent_ids, target).await
+}
+
+#[update]
+fn system_import_agents(agents: Vec<ArchivedAgent>) -> Result<MigrationReport, String> {
+    Guards::require_system_caller("system_import_agents")?;
+    SystemOpsService::import_agents(agents)
+}
+
+/// Billing batches after `cursor`, for reconciling against what was received.
+/// An update so the call is kept in the system audit log.
+#[update]
+fn system_list_billing_batches(cursor: Option<u64>, limit: Option<u32>) -> Result<BillingBatchPage, String> {
+    Guards::require_system_caller("system_list_billing_batches")?;
+    Ok(BillingService::list_batches(cursor, limit))
+}
+
+#[update]
+fn system_get_billing_totals(principal: candid::Principal) -> Result<BillingTotals, String> {
+    Guards::require_system_caller("system_get_billing_totals")?;
+    Ok(BillingService::totals(&principal))
+}
+
+// Billing export APIs
+
+#[query]
+fn get_billing_status() -> Result<BillingStatus, String> {
+    Guards::require_admin()?;
+    Ok(BillingService::status())
+}
+
+/// Seal accrued usage and send pending batches now instead of on the next tick
+#[update]
+async fn flush_billing() -> Result<BillingStatus, String> {
+    Guards::require_admin()?;
+    Ok(BillingService::flush().await)
+}
+
+// Outbound call journal APIs
+
+/// Journaled LLM and model repo calls after `cursor`; `unresolved_only` lists pending ones
+#[query]
+fn list_call_journal(unresolved_only: bool, cursor: Option<u64>, limit: Option<u32>) -> Result<JournalPage, String> {
+    Guards::require_admin()?;
+    Ok(JournalService::list(unresolved_only, cursor, limit))
+}
+
+/// Close a pending entry after reconciling it by hand
+#[update]
+fn resolve_call_journal_entry(id: u64, state: JournalState) -> Result<JournalEntry, String> {
+    Guards::require_admin()?;
+    JournalService::resolve_manually(id, state, ic_cdk::api::caller())
+}
+
+/// Settle orphaned entries now instead of on the next sweep
+#[update]
+fn recover_call_journal() -> Result<JournalRecovery, String> {
+    Guards::require_admin()?;
+    Ok(JournalService::recover(false))
+}
+
+// Caller attestation APIs
+
+/// Trust `canister_id` to act for its users, proven by envelopes signed with `secret`
+#[update]
+fn register_attestation_coordinator(canister_id: candid::Principal, secret: String) -> Result<CoordinatorInfo, String> {
+    Guards::require_admin()?;
+    AttestationService::register_coordinator(canister_id, secret, ic_cdk::api::caller())
+}
+
+#[update]
+fn remove_attestation_coordinator(canister_id: candid::Principal) -> Result<(), String> {
+    Guards::require_admin()?;
+    AttestationService::remove_coordinator(canister_id)
+}
+
+#[query]
+fn list_attestation_coordinators() -> Result<Vec<CoordinatorInfo>, String> {
+    Guards::require_admin()?;
+    Ok(AttestationService::list_coordinators())
+}
+
+#[query]
+fn list_attestation_audit(cursor: Option<u64>, limit: Option<u32>) -> Result<AttestationAuditPage, String> {
+    Guards::require_admin()?;
+    Ok(AttestationService::list_audit(cursor, limit))
+}
+
+/// create_agent called by a coordinator for the user its envelope attests
+#[update]
+async fn attested_create_agent(envelope: CallerEnvelope, instruction: UserInstruction) -> Result<String, String> {
+    let user = Guards::require_attested(&envelope, "attested_create_agent")?;
+    Ok(AgentRequestService::create_agent(user, instruction).await?)
+}
+
+/// execute_agent_task called by a coordinator; access and rate limits are
+/// those of the attested user
+#[update]
+async fn attested_execute_task(envelope: CallerEnvelope, agent_id: String, task_description: String) -> Result<AgentTaskResult, String> {
+    let user = Guards::require_attested(&envelope, "attested_execute_task")?;
+    Ok(AgentRequestService::execute_task(user, &agent_id, task_description, false).await?)
+}
+
+/// invoke_agent_tool called by a coordinator; the tool audit names the attested user
+#[update]
+async fn attested_invoke_agent_tool(
+    envelope: CallerEnvelope,
+    agent_id: String,
+    tool: String,
+    arguments: String,
+) -> Result<ToolCallResult, String> {
+    let user = Guards::require_attested(&envelope, "attested_invoke_agent_tool")?;
+    DelegationService::authorize(&agent_id, &user.to_string(), AccessScope::Execute)?;
+    ToolService::dispatch(&agent_id, tool, arguments, &user.to_string()).await
+}
+
+// Sharding APIs
+
+#[update]
+fn set_sharding_config(config: ShardingConfig) -> Result<(), String> {
+    Guards::require_admin()?;
+    ShardingService::set_config(config)
+}
+
+#[query]
+fn get_sharding_status() -> Result<ShardingStatus, String> {
+    Guards::require_admin()?;
+    Ok(ShardingService::status())
+}
+
+/// Add another ohms-agent canister to the cluster. It must register this
+/// canister too before shard calls work both ways.
+#[update]
+fn register_shard(canister_id: candid::Principal) -> Result<Shard, String> {
+    Guards::require_admin()?;
+    ShardingService::register(canister_id)
+}
+
+#[update]
+fn deregister_shard(canister_id: candid::Principal) -> Result<(), String> {
+    Guards::require_admin()?;
+    ShardingService::deregister(canister_id)
+}
+
+/// Draining shards keep serving their agents but get no new ones
+#[update]
+fn set_shard_state(canister_id: candid::Principal, state: ShardState) -> Result<(), String> {
+    Guards::require_admin()?;
+    ShardingService::set_state(canister_id, state)
+}
+
+/// Move up to `max` of the oldest archived agents to shards with room
+#[update]
+async fn rebalance_archived_agents(max: u32) -> Result<RebalanceReport, String> {
+    Guards::require_admin()?;
+    Ok(ShardingService::rebalance_archived(max).await)
+}
+
+#[query]
+fn shard_load() -> Result<ShardLoad, String> {
+    Guards::require_shard_peer()?;
+    Ok(ShardingService::local_load())
+}
+
+/// create_agent forwarded by a shard for `owner`
+#[update]
+async fn shard_create_agent(owner: candid::Principal, instruction: UserInstruction) -> Result<String, ApiError> {
+    Guards::require_shard_peer()?;
+    ShardingService::accepts_forwarded()?;
+    AgentRequestService::create_local(owner, instruction).await
+}
+
+/// execute_agent_task proxied by a shard for `caller`. The shard has
+/// already rate limited the caller.
+#[update]
+async fn shard_execute_task(
+    caller: candid::Principal,
+    agent_id: String,
+    task_description: String,
+    dry_run: Option<bool>,
+    context: Option<HashMap<String, String>>,
+) -> Result<AgentTaskResult, ApiError> {
+    Guards::require_shard_peer()?;
+    AgentRequestService::execute_local(caller, &agent_id, task_description, context.unwrap_or_default(), dry_run.unwrap_or(false))
+        .await
+}
+
+/// Archived agent moved here by a shard's rebalance
+#[update]
+fn shard_import_archived(archived: ArchivedAgent) -> Result<(), String> {
+    Guards::require_shard_peer()?;
+    ShardingService::import_archived(archived)
+}
+
+/// restore_agent proxied by a shard for `owner`
+#[update]
+async fn shard_restore_agent(owner: candid::Principal, agent_id: String) -> Result<(), String> {
+    Guards::require_shard_peer()?;
+    ArchiveService::restore(&agent_id, &owner.to_string())
+        .await
+        .map(|_| ())
+}
+
+#[query]
+async fn shard_list_user_agents(owner: candid::Principal, page: PageRequest) -> Result<ShardAgentPage, String> {
+    Guards::require_shard_peer()?;
+    let agents = AgentFactory::list_user_agents(&owner.to_string()).await?;
+    Ok(ShardingService::page_of(agents, &page))
+}
+
+#[query]
+async fn shard_get_agent_status(caller: candid::Principal, agent_id: String) -> Result<AgentStatusInfo, ApiError> {
+    Guards::require_shard_peer()?;
+    DelegationService::authorize(&agent_id, &caller.to_string(), AccessScope::Read)?;
+    Ok(AgentFactory::get_agent_status(&agent_id).await?)
+}
+
+/// Duplicate an agent into a new one owned by the caller. Counts against the
+/// caller's agent quota.
+#[update]
+async fn clone_agent(agent_id: String, options: CloneOptions) -> Result<String, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    let caller = ic_cdk::api::caller().to_string();
+    AgentFactory::clone_agent(&agent_id, &caller, options)
+        .await
+        .map(|agent| agent.agent_id)
+}
+
+/// Soft-delete an idle agent. It stops running, keeps its audit trail, and can
+/// be restored for 90 days; it counts toward the agent quota for the first 7.
+#[update]
+fn archive_agent(agent_id: String) -> Result<ArchivedAgentInfo, String> {
+    Guards::require_caller_authenticated()?;
+    ArchiveService::archive(&agent_id, &ic_cdk::api::caller().to_string())
+}
+
+#[update]
+async fn restore_agent(agent_id: String) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    if let Some(shard) = ShardingService::shard_of(&agent_id) {
+        return ShardingService::restore_agent(shard, ic_cdk::api::caller(), &agent_id).await;
+    }
+    ArchiveService::restore(&agent_id, &ic_cdk::api::caller().to_string())
+        .await
+        .map(|_| ())
+}
+
+#[query]
+fn list_archived_agents() -> Result<Vec<ArchivedAgentInfo>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(ArchiveService::list(&ic_cdk::api::caller().to_string()))
+}
+
+// Agent environment APIs
+
+/// Set an env var on the agent; a secret is sealed with the owner's key and
+/// its value is never returned
+#[update]
+async fn set_agent_env(agent_id: String, name: String, value: String, secret: bool) -> Result<AgentEnvVarInfo, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentEnvService::set(&agent_id, name, value, secret).await
+}
+
+#[update]
+fn remove_agent_env(agent_id: String, name: String) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentEnvService::remove(&agent_id, &name)
+}
+
+#[query]
+fn list_agent_env(agent_id: String) -> Result<Vec<AgentEnvVarInfo>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    AgentEnvService::list(&agent_id)
+}
+
+// Agent debug snapshot APIs
+
+/// Capture the agent's configuration, memory and latest task results, for
+/// reproducing a failure later with restore_agent_snapshot
+#[update]
+fn snapshot_agent(agent_id: String, label: Option<String>) -> Result<AgentSnapshotInfo, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentSnapshotService::snapshot(&agent_id, label, ic_cdk::api::caller())
+}
+
+/// Roll the agent's configuration and memory back to a snapshot; task
+/// history, delegations, webhooks and budget usage are kept as they are
+#[update]
+fn restore_agent_snapshot(agent_id: String, snapshot_id: String) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentSnapshotService::restore(&agent_id, &snapshot_id).map(|_| ())
+}
+
+#[query]
+fn list_agent_snapshots(agent_id: String) -> Result<Vec<AgentSnapshotInfo>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(AgentSnapshotService::list(&agent_id))
+}
+
+#[query]
+fn get_agent_snapshot(agent_id: String, snapshot_id: String) -> Result<AgentSnapshotView, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    AgentSnapshotService::open(&agent_id, &snapshot_id)
+}
+
+#[update]
+fn delete_agent_snapshot(agent_id: String, snapshot_id: String) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentSnapshotService::delete(&agent_id, &snapshot_id)
+}
+
+/// The caller's snapshot allowance for their tier and how much of it is used
+#[query]
+fn get_snapshot_quota() -> Result<SnapshotQuota, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(AgentSnapshotService::quota(&ic_cdk::api::caller().to_string()))
+}
+
+// Public agent marketplace APIs
+
+/// List an agent publicly, or refresh its listing with the agent as it is now
+#[update]
+fn publish_agent(agent_id: String, request: PublishRequest) -> Result<ListingInfo, String> {
+    Guards::require_caller_authenticated()?;
+    MarketplaceService::publish(&agent_id, &ic_cdk::api::caller().to_string(), request)
+}
+
+#[update]
+fn unpublish_agent(listing_id: String) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    MarketplaceService::unpublish(&listing_id, &ic_cdk::api::caller().to_string())
+}
+
+#[query]
+fn browse_public_agents(query: ListingQuery, page: PageRequest) -> Result<ListingPage, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(MarketplaceService::browse(&query, &page))
+}
+
+#[query]
+fn get_public_agent(listing_id: String) -> Result<ListingInfo, String> {
+    Guards::require_caller_authenticated()?;
+    MarketplaceService::get(&listing_id)
+}
+
+#[update]
+fn rate_public_agent(listing_id: String, rating: u8) -> Result<ListingInfo, String> {
+    Guards::require_caller_authenticated()?;
+    MarketplaceService::rate(&listing_id, ic_cdk::api::caller(), rating)
+}
+
+/// Create an agent owned by the caller from a listing. Counts against the
+/// caller's agent quota.
+#[update]
+async fn instantiate_public_agent(listing_id: String) -> Result<String, String> {
+    Guards::require_caller_authenticated()?;
+    MarketplaceService::instantiate(&listing_id, &ic_cdk::api::caller().to_string())
+        .await
+        .map(|agent| agent.agent_id)
+}
+
+// Abandoned agent GC APIs
+
+#[update]
+fn set_gc_config(config: GcConfig) -> Result<(), String> {
+    Guards::require_admin()?;
+    GcService::set_config(config)
+}
+
+#[query]
+fn get_gc_config() -> Result<GcConfig, String> {
+    Guards::require_admin()?;
+    Ok(GcService::config())
+}
+
+/// Run a GC pass now instead of waiting for the timer
+#[update]
+fn run_gc() -> Result<GcReport, String> {
+    Guards::require_admin()?;
+    Ok(GcService::run(crate::infra::clock::time()))
+}
+
+// Memory guardrail APIs
+
+#[update]
+fn set_memory_limits(limits: MemoryLimits) -> Result<(), String> {
+    Guards::require_admin()?;
+    Guards::set_memory_limits(limits)
+}
+
+#[query]
+fn get_memory_usage() -> Result<MemoryUsage, String> {
+    Guards::require_admin()?;
+    Ok(Guards::memory_usage())
+}
+
+/// LLM calls running and waiting under the concurrency limit
+#[query]
+fn get_scheduler_status() -> Result<SchedulerStatus, String> {
+    Guards::require_admin()?;
+    Ok(Scheduler::status())
+}
+
+/// Micro-batching settings and how many LLM calls it has saved
+#[query]
+fn get_batching_status() -> Result<BatchingStatus, String> {
+    Guards::require_admin()?;
+    Ok(BatchingService::status())
+}
+
+// Compatible endpoint for UI (maps to create_agent)
+#[derive(serde::Deserialize, candid::CandidType)]
+pub struct AgentCreationRequest {
+    pub instruction: String,
+    pub agent_count: Option<u32>,
+    pub capabilities: Option<Vec<String>>,
+    pub priority: Option<String>,
+}
+
+#[derive(serde::Serialize, candid::CandidType)]
+pub struct AgentCreationResult {
+    pub agent_id: String,
+    pub status: String,
+    pub capabilities: Vec<String>,
+    pub estimated_completion: Option<u64>,
+}
+
+/// How well the analyzer's confidence and duration estimates match task outcomes
+#[query]
+fn get_analyzer_calibration() -> Result<CalibrationStats, String> {
+    Guards::require_admin()?;
+    Ok(CalibrationService::stats())
+}
+
+/// Expected tokens, cycles and fiat cost of running `task_description` on the
+/// agent, with the range past tasks of its type fell within
+#[query]
+fn estimate_task_cost(agent_id: String, task_description: String) -> Result<CostEstimate, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Guards::validate_prompt_length(&task_description)?;
+    CostService::estimate(&agent_id, &task_description)
+}
+
+/// Replace the pricing table; applies to usage priced from now on
+#[update]
+fn set_pricing(table: PricingTable) -> Result<(), String> {
+    Guards::require_admin()?;
+    PricingService::set_table(table)
+}
+
+/// Current rates, for price lists in UIs
+#[query]
+fn get_pricing() -> Result<PricingTable, String> {
+    Ok(PricingService::table())
+}
+
+/// Actual task durations against the analyzer's estimates, per complexity
+/// level and agent type
+#[query]
+fn get_sla_report() -> Result<SlaReport, String> {
+    Guards::require_admin()?;
+    Ok(SlaService::report())
+}
+
+/// Refit now instead of waiting for the timer
+#[update]
+fn recalibrate_analyzer() -> Result<CalibrationStats, String> {
+    Guards::require_admin()?;
+    CalibrationService::recalibrate();
+    Ok(CalibrationService::stats())
+}
+
+#[update]
+async fn create_agent_from_instruction(request: AgentCreationRequest) -> Result<AgentCreationResult, String> {
+    Guards::require_caller_authenticated()?;
+    let caller = ic_cdk::api::caller();
+    // Quotas are checked against the tier, so it must be the caller's real one
+    Guards::refresh_tier_for(caller).await;
+
+    // Convert to UserInstruction format
+    let user_instruction = UserInstruction {
+        instruction_text: request.instruction,
+        user_id: caller.to_string(),
+        subscription_tier: Guards::limits_for(caller).tier,
+        context: Some(InstructionContext {
+            domain: None,
+            complexity: None,
+            urgency: Some(match request.priority.as_deref() {
+                Some("low") => UrgencyLevel::Low,
+                Some("high") => UrgencyLevel::High,
+                Some("critical") => UrgencyLevel::Critical,
+                _ => UrgencyLevel::Normal,
+            }),
+            collaboration_needed: request.agent_count.unwrap_or(1) > 1,
+            external_tools_required: vec![],
+        }),
+        preferences: Some(AgentPreferences {
+            response_style: ResponseStyle::Conversational,
+            detail_level: DetailLevel::Standard,
+            creativity_level: CreativityLevel::Balanced,
+            safety_level: SafetyLevel::Standard,
+            language: "en".to_string(),
+        }),
+        skills: None,
+    };
+    
+    // Create the agent(s)
+    let agent_count = request.agent_count.unwrap_or(1);
+
+    if agent_count == 1 {
+        let agent_id = AgentRequestService::create_agent(caller, user_instruction).await?;
+        Ok(AgentCreationResult {
+            agent_id,
+            status: "Ready".to_string(),
+            capabilities: request.capabilities.unwrap_or_else(|| vec!["General Assistant".to_string()]),
+            estimated_completion: Some(crate::infra::clock::time() + 30_000_000_000), // 30 seconds from now
+        })
+    } else {
+        let analysis = InstructionAnalyzer::analyze_instruction(user_instruction.clone())?;
+        let user_id = user_instruction.user_id.clone();
+        let agents = AgentFactory::create_coordinated_agents(user_id, user_instruction, analysis).await?;
+        // Return first agent ID (coordinator)
+        let primary_agent = agents.first().ok_or("Failed to create coordinated agents")?;
+        Ok(AgentCreationResult {
+            agent_id: primary_agent.agent_id.clone(),
+            status: "Ready".to_string(),
+            capabilities: request.capabilities.unwrap_or_else(|| vec!["Coordinated Team".to_string()]),
+            estimated_completion: Some(crate::infra::clock::time() + 60_000_000_000), // 60 seconds for coordinated
+        })
+    }
+}
+
+#[update]
+async fn create_coordinated_agents(mut instruction: UserInstruction) -> Result<Vec<String>, String> {
+    Guards::require_caller_authenticated()?;
+    instruction.user_id = ic_cdk::api::caller().to_string();
+    
+    // Analyze the instruction
+    let analysis = InstructionAnalyzer::analyze_instruction(instruction.clone())?;
+    
+    // Create coordinated agents
+    let user_id = instruction.user_id.clone();
+    let agents = AgentFactory::create_coordinated_agents(user_id, instruction, analysis).await?;
+    
+    Ok(agents.into_iter().map(|a| a.agent_id).collect())
+}
+
+/// `dry_run` returns the task's plan instead of running it: the rendered
+/// prompts, the tools it would call and its estimated tokens
+#[update]
+async fn execute_agent_task(agent_id: String, task_description: String, dry_run: Option<bool>) -> Result<AgentTaskResult, String> {
+    Guards::require_caller_authenticated()?;
+    let caller = ic_cdk::api::caller();
+    Ok(AgentRequestService::execute_task(caller, &agent_id, task_description, dry_run.unwrap_or(false)).await?)
+}
+
+#[derive(candid::CandidType)]
+pub struct PaidTaskResult {
+    pub result: AgentTaskResult,
+    pub receipt: PaymentReceipt,
+}
+
+/// Charge the configured per-task price via ICRC-2 before running the task.
+/// Failed tasks are refunded minus the ledger fee. A task held for owner
+/// approval or deferred during an LLM outage keeps its charge; it is refunded
+/// if the owner rejects it, it expires, or it fails once it runs.
+#[update]
+async fn execute_paid_task(agent_id: String, task_description: String) -> Result<PaidTaskResult, String> {
+    Guards::require_caller_authenticated()?;
+    let caller = ic_cdk::api::caller();
+    // Receipts and refunds live on this canister, so the agent has to as well
+    if let Some(shard) = ShardingService::shard_of(&agent_id) {
+        return Err(format!("Agent {} is hosted by shard {}; pay for its tasks there", agent_id, shard));
+    }
+    // Checked before charging so a rejected call costs nothing
+    let shard = AgentRequestService::admit(caller, &agent_id).await?;
+
+    // The id is the ledger memo, so two paid calls in one round must not share it
+    let mut task = AgentRequestService::task(task_description, HashMap::new());
+    let receipt = PaymentService::charge(caller, &agent_id, &task.task_id).await?;
+    task.context.insert(PAYMENT_RECEIPT_KEY.to_string(), receipt.receipt_id.clone());
+    match AgentRequestService::run_admitted(caller, &agent_id, shard, task, false).await {
+        Ok(result) if result.success => Ok(PaidTaskResult { result, receipt }),
+        Ok(result) if matches!(result.outcome, TaskOutcome::PendingApproval | TaskOutcome::Deferred) => {
+            Ok(PaidTaskResult { result, receipt })
+        }
+        Ok(result) => {
+            let receipt = PaymentService::refund(receipt).await;
+            Ok(PaidTaskResult { result, receipt })
+        }
+        Err(e) => {
+            let receipt = PaymentService::refund(receipt).await;
+            Err(format!("{} (payment {}: {:?})", e, receipt.receipt_id, receipt.status))
+        }
+    }
+}
+
+#[update]
+async fn reset_agent_health(agent_id: String) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentFactory::reset_agent_health(&agent_id).await
+}
+
+/// Daily token/task limits and a lifetime cycles cap. An agent over budget is
+/// suspended and refuses tasks, including workflow steps, until usage resets.
+#[update]
+async fn set_agent_budget(agent_id: String, budget: AgentBudget) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentFactory::set_budget(&agent_id, budget).await
+}
+
+#[query]
+async fn get_agent_budget(agent_id: String) -> Result<BudgetStatus, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    AgentFactory::get_budget(&agent_id).await
+}
+
+/// When enabled, task answers are critiqued against the agent's behavior rules
+/// and safety constraints and revised before they are returned
+#[update]
+async fn set_agent_self_critique(agent_id: String, enabled: bool) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentFactory::set_self_critique(&agent_id, enabled).await
+}
+
+/// When enabled, recent commented task ratings are quoted in the agent's
+/// later task prompts
+#[update]
+async fn set_agent_feedback_learning(agent_id: String, enabled: bool) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentFactory::set_feedback_learning(&agent_id, enabled).await
+}
+
+/// When enabled, the agent periodically reviews its task history and ratings
+/// and proposes configuration changes for the owner to accept or reject
+#[update]
+async fn set_agent_self_improvement(agent_id: String, enabled: bool) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    AgentFactory::set_self_improvement(&agent_id, enabled).await
+}
+
+#[query]
+fn list_config_proposals(agent_id: String) -> Result<Vec<ConfigProposal>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    SelfImprovementService::list(&agent_id)
+}
+
+/// Review the agent now instead of on the next tick; None when nothing should change
+#[update]
+fn propose_config_update(agent_id: String) -> Result<Option<ConfigProposal>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    SelfImprovementService::review(&agent_id)
+}
+
+/// Approving applies the proposed changes to the agent's configuration
+#[update]
+fn respond_to_config_proposal(agent_id: String, proposal_id: String, decision: ApprovalDecision) -> Result<ConfigProposal, String> {
+    Guards::require_caller_authenticated()?;
+    SelfImprovementService::respond(&agent_id, &ic_cdk::api::caller().to_string(), &proposal_id, decision)
+}
+
+/// Rate a task result from 1 to 5; rating the same task again replaces the rating
+#[update]
+fn submit_task_feedback(agent_id: String, task_id: String, rating: u8, comments: Option<String>) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Execute)?;
+    FeedbackService::submit(&agent_id, &task_id, rating, comments, ic_cdk::api::caller())
+}
+
+#[query]
+fn list_task_feedback(agent_id: String) -> Result<Vec<TaskFeedback>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    FeedbackService::list(&agent_id)
+}
+
+/// Composite so the status of agents on other shards can be read through
+/// this canister
+#[query(composite = true)]
+async fn get_agent_status(agent_id: String) -> Result<AgentStatusInfo, String> {
+    if let Some(shard) = ShardingService::shard_of(&agent_id) {
+        Guards::require_caller_authenticated()?;
+        return Ok(ShardingService::agent_status(shard, ic_cdk::api::caller(), &agent_id).await?);
+    }
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    AgentFactory::get_agent_status(&agent_id).await
+}
+
+/// Capabilities, usable tools, model, memory and knowledge, schedules and
+/// budget of the agent, for UIs that render what it can do
+#[query]
+fn describe_agent(agent_id: String) -> Result<AgentDescription, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    IntrospectionService::describe(&agent_id)
+}
+
+/// Certified snapshot of get_agent_status, at most a few seconds old, that
+/// frontends can verify against the subnet's signature
+#[query]
+fn get_agent_status_certified(agent_id: String) -> Result<CertifiedAgentStatus, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    CertificationService::agent_status(&agent_id)
+}
+
+#[query]
+fn get_model_catalog() -> Result<CertifiedModelCatalog, String> {
+    CertificationService::model_catalog()
+}
+
+/// The agent's dialog with its owner: task turns plus any chat. The owner
+/// continues it with send_message on the returned session_id.
+#[query]
+fn get_agent_thread(agent_id: String) -> Result<ConversationSession, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    llm_service()
+        .get_agent_thread(&agent_id)
+        .ok_or_else(|| format!("Agent {} has no thread yet", agent_id))
+}
+
+#[query]
+async fn list_user_agents(user_id: String) -> Result<Vec<AgentSummary>, String> {
+    Guards::require_caller_authenticated()?;
+    if user_id != ic_cdk::api::caller().to_string() {
+        return Err("Can only list your own agents".to_string());
+    }
+    AgentFactory::list_user_agents(&user_id).await
+}
+
+#[update]
+async fn transfer_agent_ownership(agent_id: String, new_owner: String) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    let new_owner = candid::Principal::from_text(&new_owner)
+        .map_err(|e| format!("Invalid principal {}: {}", new_owner, e))?;
+    if new_owner == candid::Principal::anonymous() {
+        return Err("Cannot transfer an agent to the anonymous principal".to_string());
+    }
+    AgentFactory::transfer_ownership(&agent_id, &ic_cdk::api::caller().to_string(), &new_owner.to_string()).await
+}
+
+/// Request a tool call for the agent. Strict agents queue it for owner approval.
+#[update]
+async fn invoke_agent_tool(agent_id: String, tool: String, arguments: String) -> Result<ToolCallResult, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Execute)?;
+    ToolService::dispatch(&agent_id, tool, arguments, &ic_cdk::api::caller().to_string()).await
+}
+
+#[update]
+async fn approve_pending_tool_call(agent_id: String, call_id: String) -> Result<ToolCallResult, String> {
+    Guards::require_caller_authenticated()?;
+    ToolService::approve(&agent_id, &ic_cdk::api::caller().to_string(), &call_id).await
+}
+
+#[update]
+fn reject_pending_tool_call(agent_id: String, call_id: String) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    ToolService::reject(&agent_id, &ic_cdk::api::caller().to_string(), &call_id)
+}
+
+#[query]
+fn list_pending_tool_calls(agent_id: String) -> Result<Vec<ToolCall>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    ToolService::list_pending(&agent_id)
+}
+
+/// Tasks and tool calls awaiting the owner's decision
+#[query]
+fn list_pending_approvals(agent_id: String) -> Result<Vec<ApprovalRequest>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    ApprovalService::list(&agent_id)
+}
+
+/// Approving runs the held task or tool call and returns its result
+#[update]
+async fn respond_to_approval(request_id: String, decision: ApprovalDecision) -> Result<ApprovalOutcome, String> {
+    Guards::require_caller_authenticated()?;
+    ApprovalService::respond(&ic_cdk::api::caller().to_string(), &request_id, decision).await
+}
+
+/// Tasks or tool calls matching a rule wait for the owner's approval
+#[update]
+fn set_agent_risk_rules(agent_id: String, rules: Vec<RiskRule>) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    ApprovalService::set_rules(&agent_id, rules)
+}
+
+#[query]
+fn get_agent_risk_rules(agent_id: String) -> Result<Vec<RiskRule>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(AgentFactory::find_agent(&agent_id)?.risk_rules)
+}
+
+#[update]
+fn set_agent_tool_access(agent_id: String, tool: String, enabled: bool) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    ToolService::set_tool_access(&agent_id, &tool, enabled)
+}
+
+#[query]
+fn list_tool_audit(agent_id: String, cursor: Option<u64>, limit: Option<u32>) -> Result<ToolAuditPage, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(CanisterToolService::list_audit(&agent_id, cursor, limit))
+}
+
+// Skill APIs
+
+/// Fetch a skill from the model repo and attach it, replacing any other version of it
+#[update]
+async fn attach_agent_skill(agent_id: String, skill: SkillRef) -> Result<Skill, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    SkillService::attach(&agent_id, skill).await
+}
+
+#[update]
+fn detach_agent_skill(agent_id: String, skill_id: String) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    SkillService::detach(&agent_id, &skill_id)
+}
+
+#[query]
+fn list_agent_skills(agent_id: String) -> Result<Vec<Skill>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(SkillService::list_attached(&AgentFactory::find_agent(&agent_id)?))
+}
+
+/// Run an attached skill as a task with `input` as its description
+#[update]
+async fn execute_agent_skill(agent_id: String, skill_id: String, input: String) -> Result<AgentTaskResult, String> {
+    Guards::require_caller_authenticated()?;
+    let task = AgentRequestService::task(input, HashMap::from([(SKILL_KEY.to_string(), skill_id)]));
+    Ok(AgentRequestService::execute(ic_cdk::api::caller(), &agent_id, task, false).await?)
+}
+
+// Knowledge base APIs
+
+/// Add a document to the agent's knowledge base. Tasks and the agent's thread
+/// retrieve from it automatically; set the task context key
+/// "knowledge_namespace" to restrict a task to one namespace.
+#[update]
+async fn ingest_document(agent_id: String, namespace: String, title: String, text: String) -> Result<String, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    KnowledgeService::ingest(&agent_id, &namespace, title, &text).await
+}
+
+#[update]
+fn delete_document(agent_id: String, doc_id: String) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    KnowledgeService::delete(&agent_id, &doc_id)
+}
+
+#[query]
+fn list_documents(agent_id: String, namespace: Option<String>) -> Result<Vec<KnowledgeDocument>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(KnowledgeService::list_documents(&agent_id, namespace.as_deref()))
+}
+
+/// Re-embed documents after an embedding backend change. Processes a bounded
+/// number of documents per call; repeat while `remaining` is non-zero.
+#[update]
+async fn reindex_knowledge(agent_id: String, namespace: Option<String>) -> Result<ReindexReport, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    KnowledgeService::reindex(&agent_id, namespace.as_deref()).await
+}
+
+#[query]
+fn get_knowledge_stats(agent_id: String) -> Result<KnowledgeStats, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(KnowledgeService::stats(Some(&agent_id)))
+}
+
+/// Update call: embedding the query may need an HTTPS outcall
+#[update]
+async fn search_knowledge(
+    agent_id: String,
+    query: String,
+    namespace: Option<String>,
+    top_k: Option<u32>,
+) -> Result<Vec<RetrievedPassage>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(KnowledgeService::retrieve(&agent_id, namespace.as_deref(), &query, top_k).await)
+}
+
+// Chunked upload APIs
+
+#[derive(candid::CandidType)]
+pub enum UploadOutcome {
+    DocumentIngested { doc_id: String },
+    NovaqValidated(NOVAQValidationResult),
+}
+
+fn require_upload_access(purpose: &UploadPurpose) -> Result<(), ApiError> {
+    match purpose {
+        UploadPurpose::KnowledgeDocument { agent_id, .. } => Guards::require_agent_access(agent_id, AccessScope::Manage),
+        UploadPurpose::NovaqModel { .. } => Guards::require_caller_authenticated(),
+    }
+}
+
+/// Start an upload of `chunk_count` chunks totalling `total_size` bytes whose
+/// sha256 (hex) is `sha256`. Send chunks with put_chunk, then call finish_upload.
+#[update]
+fn begin_upload(purpose: UploadPurpose, total_size: u64, chunk_count: u32, sha256: String) -> Result<String, String> {
+    require_upload_access(&purpose)?;
+    UploadService::begin(&ic_cdk::api::caller().to_string(), purpose, total_size, chunk_count, sha256)
+}
+
+#[update]
+fn put_chunk(upload_id: String, index: u32, bytes: Vec<u8>, sha256: String) -> Result<UploadStatus, String> {
+    Guards::require_caller_authenticated()?;
+    UploadService::put_chunk(&ic_cdk::api::caller().to_string(), &upload_id, index, bytes, &sha256)
+}
+
+#[query]
+fn get_upload_status(upload_id: String) -> Result<UploadStatus, String> {
+    Guards::require_caller_authenticated()?;
+    UploadService::status(&ic_cdk::api::caller().to_string(), &upload_id)
+}
+
+#[update]
+fn abort_upload(upload_id: String) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    UploadService::abort(&ic_cdk::api::caller().to_string(), &upload_id)
+}
+
+/// Verify the assembled payload and hand it to the consumer named by its purpose
+#[update]
+async fn finish_upload(upload_id: String) -> Result<UploadOutcome, String> {
+    Guards::require_caller_authenticated()?;
+    let caller = ic_cdk::api::caller().to_string();
+    // Access may have been revoked while chunks were arriving
+    require_upload_access(&UploadService::status(&caller, &upload_id)?.purpose)?;
+
+    let (purpose, payload) = UploadService::finish(&caller, &upload_id)?;
+    match purpose {
+        UploadPurpose::KnowledgeDocument { agent_id, namespace, title } => {
+            let text = String::from_utf8(payload).map_err(|_| "Document is not valid UTF-8 text".to_string())?;
+            let doc_id = KnowledgeService::ingest(&agent_id, &namespace, title, &text).await?;
+            Ok(UploadOutcome::DocumentIngested { doc_id })
+        }
+        UploadPurpose::NovaqModel { model_id } => ModelRepoClient::validate_novaq_model(&model_id, &payload)
+            .await
+            .map(UploadOutcome::NovaqValidated),
+    }
+}
+
+// Canister tool registry APIs
+
+/// Propose a canister method as a tool. It is callable once an admin approves it.
+#[update]
+fn propose_canister_tool(
+    name: String,
+    description: String,
+    target: String,
+    method: String,
+    arg_schema: String,
+    cycles_budget: u64,
+) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    CanisterToolService::propose(
+        &ic_cdk::api::caller().to_string(),
+        name,
+        description,
+        target,
+        method,
+        arg_schema,
+        cycles_budget,
+    )
+}
+
+#[update]
+fn set_canister_tool_status(name: String, status: ToolRegistrationStatus) -> Result<(), String> {
+    Guards::require_admin()?;
+    CanisterToolService::set_status(&name, status, &ic_cdk::api::caller().to_string())
+}
+
+#[query]
+fn list_canister_tools() -> Result<Vec<CanisterTool>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(CanisterToolService::list())
+}
+
+#[update]
+fn grant_agent_access(
+    agent_id: String,
+    principal: String,
+    scope: AccessScope,
+    expires_at: Option<u64>,
+) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    let principal = candid::Principal::from_text(&principal)
+        .map_err(|e| format!("Invalid principal {}: {}", principal, e))?;
+    if principal == candid::Principal::anonymous() {
+        return Err("Cannot delegate access to the anonymous principal".to_string());
+    }
+    DelegationService::grant(
+        &agent_id,
+        &ic_cdk::api::caller().to_string(),
+        &principal.to_string(),
+        scope,
+        expires_at,
+    )
+}
+
+#[update]
+fn revoke_agent_access(agent_id: String, principal: String) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    DelegationService::revoke(&agent_id, &ic_cdk::api::caller().to_string(), &principal)
+}
+
+#[query]
+fn list_agent_delegations(agent_id: String) -> Result<Vec<Delegation>, String> {
+    Guards::require_caller_authenticated()?;
+    DelegationService::list(&agent_id, &ic_cdk::api::caller().to_string())
+}
+
+#[update]
+fn register_webhook(
+    agent_id: String,
+    url: String,
+    secret: String,
+    events: Vec<WebhookEvent>,
+) -> Result<String, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    WebhookService::register(&agent_id, url, secret, events)
+}
+
+#[update]
+fn remove_webhook(agent_id: String, webhook_id: String) -> Result<(), String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    WebhookService::remove(&agent_id, &webhook_id)
+}
+
+#[query]
+fn list_webhooks(agent_id: String) -> Result<Vec<WebhookInfo>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    WebhookService::list(&agent_id)
+}
+
+#[query]
+fn list_webhook_deliveries(agent_id: String) -> Result<Vec<WebhookDelivery>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(WebhookService::list_deliveries(&agent_id))
+}
+
+#[query]
+fn transform_webhook_response(
+    args: ic_cdk::api::management_canister::http_request::TransformArgs,
+) -> ic_cdk::api::management_canister::http_request::HttpResponse {
+    WebhookService::transform(args)
+}
+
+#[query]
+fn get_task_result(agent_id: String, task_id: String) -> Result<TaskRecord, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    TaskHistoryService::get_task_result(&agent_id, &task_id)
+}
+
+/// One chunk of a task output stored as an artifact; pass `next_cursor` back
+/// until it is None and check each chunk against its sha256
+#[query]
+fn get_artifact(artifact_id: String, cursor: Option<u32>) -> Result<ArtifactPage, String> {
+    Guards::require_agent_access(&ArtifactService::agent_of(&artifact_id)?, AccessScope::Read)?;
+    ArtifactService::page(&artifact_id, cursor)
+}
+
+/// Run a task from history again with another model, prompt template version
+/// or decode params and diff the result against the original
+#[update]
+async fn replay_task(agent_id: String, task_id: String, overrides: ReplayOverrides) -> Result<ReplayReport, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Manage)?;
+    Guards::rate_limit_check()?;
+    Guards::refresh_caller_tier().await;
+    let report = ReplayService::replay(&agent_id, &task_id, overrides).await?;
+    Guards::record_token_usage(report.replay.tokens_used);
+    Ok(report)
+}
+
+/// Step-by-step record of a task run. Owner only, since traces hold the full
+/// prompt including retrieved knowledge.
+#[query]
+fn get_task_trace(agent_id: String, task_id: String) -> Result<ExecutionTrace, String> {
+    Guards::require_caller_authenticated()?;
+    AgentFactory::authorize(&agent_id, &ic_cdk::api::caller().to_string())?;
+    TraceService::get(&agent_id, &task_id)
+}
+
+/// Timing spans (bind, chunk_fetch, task, llm_call, tool_call) as one
+/// OTLP-JSON batch for a relay to forward; pass `next_cursor` back for the next
+#[query]
+fn export_traces(since: Option<u64>, cursor: Option<u64>, limit: Option<u32>) -> Result<SpanExport, String> {
+    Guards::require_admin()?;
+    Ok(Spans::export(since, cursor, limit))
+}
+
+/// Counters, gauges and bucketed histograms in the Prometheus text format
+#[query]
+fn get_prometheus_metrics() -> Result<String, String> {
+    Guards::require_admin()?;
+    Ok(Metrics::prometheus_text())
+}
+
+/// History of one counter, gauge or histogram quantile (`name_p95`) at
+/// five-minute resolution over the last thirty days
+#[query]
+fn query_metric_series(name: String, range: MetricRange, step_seconds: u64) -> Result<MetricSeries, String> {
+    Guards::require_admin()?;
+    MetricSeriesService::query(&name, range, step_seconds)
+}
+
+/// Latency and error SLOs of infer, execute_agent_task and bind_model over
+/// their rolling windows, with the error budget left
+#[query]
+fn get_slo_report() -> SloReport {
+    SloService::report()
+}
+
+#[update]
+fn set_slo_objective(objective: SloObjective) -> Result<(), String> {
+    Guards::require_admin()?;
+    SloService::set_objective(objective)
+}
+
+// Fault injection APIs (builds with the chaos feature only)
+
+#[update]
+fn configure_faults(config: FaultConfig) -> Result<(), String> {
+    Guards::require_admin()?;
+    Faults::configure(config)
+}
+
+#[update]
+fn clear_faults() -> Result<(), String> {
+    Guards::require_admin()?;
+    Faults::clear();
+    Ok(())
+}
+
+#[query]
+fn get_fault_status() -> Result<FaultStatus, String> {
+    Guards::require_admin()?;
+    Ok(Faults::status())
+}
+
+// Test seam APIs (builds with the testing feature only; not in the .did)
+
+#[cfg(feature = "testing")]
+#[update]
+fn testing_install_mock_model(model: crate::infra::testing::MockModel) -> Result<(), String> {
+    Guards::require_admin()?;
+    crate::infra::testing::TestSeams::install_model(model);
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+#[update]
+fn testing_install_mock_skill(artifact: crate::services::modelrepo::SkillArtifact) -> Result<(), String> {
+    Guards::require_admin()?;
+    crate::infra::testing::TestSeams::install_skill(artifact);
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+#[update]
+fn testing_script_llm(replies: Vec<crate::infra::testing::MockLlmReply>) -> Result<(), String> {
+    Guards::require_admin()?;
+    crate::infra::testing::TestSeams::script_llm(replies);
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+#[update]
+fn testing_advance_time(seconds: u64) -> Result<u64, String> {
+    Guards::require_admin()?;
+    crate::infra::testing::VirtualClock::advance(seconds.saturating_mul(1_000_000_000));
+    Ok(crate::infra::clock::time())
+}
+
+#[cfg(feature = "testing")]
+#[update]
+fn testing_reset() -> Result<(), String> {
+    Guards::require_admin()?;
+    crate::infra::testing::TestSeams::reset();
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+#[query]
+fn testing_status() -> Result<crate::infra::testing::TestingStatus, String> {
+    Guards::require_admin()?;
+    Ok(crate::infra::testing::TestSeams::status())
+}
+
+// Alerting APIs
+
+/// Raise AlertRaised/AlertResolved events, and Alert webhooks on the caller's
+/// agents, when a metric crosses `threshold` for `sustained_minutes`
+#[update]
+fn add_alert_rule(spec: AlertRuleSpec) -> Result<AlertRule, String> {
+    Guards::require_admin()?;
+    AlertService::add_rule(spec, ic_cdk::api::caller())
+}
+
+#[update]
+fn remove_alert_rule(rule_id: String) -> Result<(), String> {
+    Guards::require_admin()?;
+    AlertService::remove_rule(&rule_id)
+}
+
+/// Rules with their current state: firing, breaching since, last value
+#[query]
+fn list_alert_rules() -> Result<Vec<AlertRule>, String> {
+    Guards::require_admin()?;
+    Ok(AlertService::list_rules())
+}
+
+/// Tasks waiting for the LLM canister to become reachable again, oldest first
+#[query]
+fn list_deferred_tasks(agent_id: String) -> Result<Vec<DeferredTask>, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(OutageService::deferred(&agent_id))
+}
+
+#[query]
+fn list_task_history(agent_id: String, cursor: Option<u64>) -> Result<TaskHistoryPage, String> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(TaskHistoryService::list_task_history(&agent_id, cursor))
+}
+
+// Workflow APIs
+
+#[update]
+async fn start_workflow(agent_ids: Vec<String>, task_description: String) -> Result<Workflow, String> {
+    Guards::require_caller_authenticated()?;
+    for agent_id in &agent_ids {
+        Guards::require_agent_access(agent_id, AccessScope::Execute)?;
+    }
+    WorkflowService::start_workflow(ic_cdk::api::caller().to_string(), agent_ids, task_description).await
+}
+
+#[update]
+async fn start_parallel_workflow(
+    agent_ids: Vec<String>,
+    task_description: String,
+    strategy: Option<AggregationStrategy>,
+) -> Result<Workflow, String> {
+    Guards::require_caller_authenticated()?;
+    for agent_id in &agent_ids {
+        Guards::require_agent_access(agent_id, AccessScope::Execute)?;
+    }
+    WorkflowService::start_parallel(
+        ic_cdk::api::caller().to_string(),
+        agent_ids,
+        task_description,
+        strategy.unwrap_or_default(),
+    )
+    .await
+}
+
+#[update]
+async fn execute_coordinated_task(coordinator_id: String, task_description: String) -> Result<Workflow, String> {
+    Guards::require_agent_access(&coordinator_id, AccessScope::Execute)?;
+    WorkflowService::start_coordinated(ic_cdk::api::caller().to_string(), &coordinator_id, task_description).await
+}
+
+#[update]
+async fn resume_workflow(workflow_id: String) -> Result<Workflow, String> {
+    Guards::require_caller_authenticated()?;
+    if WorkflowService::get_workflow(&workflow_id)?.owner != ic_cdk::api::caller().to_string() {
+        return Err("Only the workflow owner can resume it".to_string());
+    }
+    WorkflowService::resume_workflow(&workflow_id).await
+}
+
+#[query]
+fn get_workflow(workflow_id: String) -> Result<Workflow, String> {
+    Guards::require_caller_authenticated()?;
+    let workflow = WorkflowService::get_workflow(&workflow_id)?;
+    if workflow.owner != ic_cdk::api::caller().to_string() {
+        return Err("Only the workflow owner can view it".to_string());
+    }
+    Ok(workflow)
+}
+
+// Payment APIs
+
+#[update]
+fn set_payment_config(config: PaymentConfig) -> Result<(), String> {
+    Guards::require_admin()?;
+    PaymentService::set_config(config)
+}
+
+#[query]
+fn get_payment_config() -> Result<PaymentConfig, String> {
+    Guards::require_caller_authenticated()?;
+    PaymentService::get_config()
+}
+
+#[query]
+fn get_payment_receipt(receipt_id: String) -> Result<PaymentReceipt, String> {
+    Guards::require_caller_authenticated()?;
+    let receipt = PaymentService::get_receipt(&receipt_id)?;
+    if receipt.payer != ic_cdk::api::caller().to_string() {
+        return Err("Only the payer can view this receipt".to_string());
+    }
+    Ok(receipt)
+}
+
+#[query]
+fn list_my_receipts() -> Result<Vec<PaymentReceipt>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(PaymentService::list_receipts(&ic_cdk::api::caller().to_string()))
+}
+
+// Conversation APIs
+
+fn require_chat_caller() -> Result<candid::Principal, LlmError> {
+    Guards::require_caller_authenticated().map_err(|_| LlmError::AuthenticationFailed)?;
+    Ok(ic_cdk::api::caller())
+}
+
+/// Message content is sealed under the caller's key, derived on first use
+async fn load_chat_key(caller: candid::Principal) -> Result<(), LlmError> {
+    PrivacyService::ensure_key(caller).await.map_err(|message| LlmError::InternalError { message })
+}
+
+#[update]
+async fn create_conversation(model: QuantizedModel, settings: Option<ConversationSettings>) -> Result<String, LlmError> {
+    let caller = require_chat_caller()?;
+    load_chat_key(caller).await?;
+    llm_service().create_conversation(caller, model, settings)
+}
+
+#[update]
+fn update_conversation_settings(session_id: String, settings: ConversationSettings) -> Result<(), LlmError> {
+    let caller = require_chat_caller()?;
+    llm_service().update_conversation_settings(&session_id, settings, caller)
+}
+
+#[update]
+async fn send_message(session_id: String, content: String) -> Result<ChatMessage, LlmError> {
+    let caller = require_chat_caller()?;
+    Guards::validate_prompt_length(&content).map_err(|message| LlmError::InvalidRequest { message })?;
+    load_chat_key(caller).await?;
+    llm_service().send_message(&session_id, content, caller).await
+}
+
+#[update]
+async fn start_message(session_id: String, content: String) -> Result<String, LlmError> {
+    let caller = require_chat_caller()?;
+    Guards::validate_prompt_length(&content).map_err(|message| LlmError::InvalidRequest { message })?;
+    load_chat_key(caller).await?;
+    llm_service().start_message(&session_id, content, caller).await
+}
+
+#[query]
+fn poll_message(handle: String, cursor: Option<u32>) -> Result<MessageStreamPoll, LlmError> {
+    let caller = require_chat_caller()?;
+    llm_service().poll_message(&handle, cursor, caller)
+}
+
+#[update]
+fn fork_conversation(session_id: String, at_message_index: u32) -> Result<String, LlmError> {
+    let caller = require_chat_caller()?;
+    llm_service().fork_conversation(&session_id, at_message_index, caller)
+}
+
+#[update]
+async fn edit_and_regenerate(session_id: String, message_index: u32, new_content: String) -> Result<ChatMessage, LlmError> {
+    let caller = require_chat_caller()?;
+    Guards::validate_prompt_length(&new_content).map_err(|message| LlmError::InvalidRequest { message })?;
+    load_chat_key(caller).await?;
+    llm_service().edit_and_regenerate(&session_id, message_index, new_content, caller).await
+}
+
+#[query]
+fn get_conversation(session_id: String) -> Result<ConversationSession, LlmError> {
+    let caller = require_chat_caller()?;
+    llm_service().get_conversation(&session_id, caller)
+}
+
+#[query]
+fn list_conversations() -> Result<ConversationListing, LlmError> {
+    let caller = require_chat_caller()?;
+    Ok(llm_service().list_conversations(caller))
+}
+
+#[query]
+fn search_conversations(
+    query: String,
+    filters: Option<SearchFilters>,
+    offset: Option<u32>,
+    limit: Option<u32>,
+) -> Result<SearchResults, LlmError> {
+    let caller = require_chat_caller()?;
+    llm_service().search_conversations(caller, &query, filters.unwrap_or_default(), offset, limit)
+}
+
+#[update]
+fn delete_conversation(session_id: String) -> Result<(), LlmError> {
+    let caller = require_chat_caller()?;
+    llm_service().delete_conversation(&session_id, caller)
+}
+
+#[query]
+fn export_conversation(session_id: String, format: ExportFormat, redact_system_prompts: bool) -> Result<String, LlmError> {
+    let caller = require_chat_caller()?;
+    llm_service().export_conversation(&session_id, caller, format, redact_system_prompts)
+}
+
+#[query]
+fn export_all_conversations(
+    format: ExportFormat,
+    redact_system_prompts: bool,
+    after: Option<String>,
+) -> Result<ConversationExportPage, String> {
+    Guards::require_admin()?;
+    Ok(llm_service().export_all_conversations(format, redact_system_prompts, after))
+}
+
+/// Delete the caller's conversations and memory entries and destroy the key
+/// their conversations are sealed under. Cannot be undone.
+#[update]
+fn purge_my_data() -> Result<PurgeReport, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(PrivacyService::purge(ic_cdk::api::caller()))
+}
+
+/// Applies to keys derived from now on; keys already loaded stay in use
+/// until the next upgrade
+#[update]
+fn set_vetkd_config(config: VetKdConfig) -> Result<(), String> {
+    Guards::require_admin()?;
+    PrivacyService::set_config(config)
+}
+
+#[query]
+fn get_vetkd_config() -> Result<VetKdConfig, String> {
+    Guards::require_admin()?;
+    Ok(PrivacyService::config())
+}
+
+// Redaction APIs; a tenant is the principal that owns the conversations and memory
+
+/// Redact the caller's conversations and memory values before they are stored
+#[update]
+fn set_redaction_policy(policy: RedactionPolicyInput) -> Result<RedactionPolicy, String> {
+    Guards::require_caller_authenticated()?;
+    RedactionService::set(&ic_cdk::api::caller(), policy)
+}
+
+#[update]
+fn clear_redaction_policy() -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    RedactionService::clear(&ic_cdk::api::caller())
+}
+
+#[query]
+fn get_redaction_policy() -> Result<Option<RedactionPolicy>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(RedactionService::get(&ic_cdk::api::caller()))
+}
+
+/// Opt in to receiving replies as generated; stored copies stay redacted
+#[update]
+fn set_live_unredacted(enabled: bool) -> Result<RedactionPolicy, String> {
+    Guards::require_caller_authenticated()?;
+    RedactionService::set_live_unredacted(&ic_cdk::api::caller(), enabled)
+}
+
+/// A managed policy the tenant cannot change; None removes it
+#[update]
+fn set_tenant_redaction_policy(tenant: candid::Principal, policy: Option<RedactionPolicyInput>) -> Result<Option<RedactionPolicy>, String> {
+    Guards::require_admin()?;
+    RedactionService::set_managed(&tenant, policy)
+}
+
+#[query]
+fn get_tenant_redaction_policy(tenant: candid::Principal) -> Result<Option<RedactionPolicy>, String> {
+    Guards::require_admin()?;
+    Ok(RedactionService::get(&tenant))
+}
+
+// Data subject APIs; callers act on their own data, admins on anyone's
+
+/// Everything stored about `subject` as JSON records, across pages
+#[update]
+async fn export_user_data(subject: candid::Principal, page: PageRequest) -> Result<UserDataPage, String> {
+    Guards::require_self_or_admin(&subject)?;
+    DataSubjectService::export(subject, &page).await
+}
+
+/// Erase `subject`'s data once the 30-day grace period is over
+#[update]
+fn delete_user_data(subject: candid::Principal) -> Result<DeletionRequest, String> {
+    Guards::require_self_or_admin(&subject)?;
+    DataSubjectService::request_deletion(subject, ic_cdk::api::caller())
+}
+
+#[update]
+fn cancel_user_data_deletion(subject: candid::Principal) -> Result<(), String> {
+    Guards::require_self_or_admin(&subject)?;
+    DataSubjectService::cancel_deletion(&subject)
+}
+
+#[query]
+fn get_user_data_deletion(subject: candid::Principal) -> Result<Option<DeletionRequest>, String> {
+    Guards::require_self_or_admin(&subject)?;
+    Ok(DataSubjectService::pending(&subject))
+}
+
+// Event log APIs
+
+#[query]
+fn list_events(cursor: Option<u64>, limit: Option<u32>) -> Result<EventPage, String> {
+    Guards::require_caller_authenticated()?;
+    let caller = ic_cdk::api::caller();
+    // Subscribed canisters see every event, everyone else only their own
+    if EventService::is_subscriber(&caller) {
+        Ok(EventService::list(cursor, limit, None))
+    } else {
+        Ok(EventService::list(cursor, limit, Some(&caller.to_string())))
+    }
+}
+
+#[update]
+fn subscribe_events(canister_id: String, method: Option<String>) -> Result<(), String> {
+    Guards::require_admin()?;
+    let canister_id = candid::Principal::from_text(&canister_id)
+        .map_err(|e| format!("Invalid canister id {}: {}", canister_id, e))?;
+    EventService::subscribe(canister_id, method)
+}
+
+#[update]
+fn unsubscribe_events(canister_id: String) -> Result<(), String> {
+    Guards::require_admin()?;
+    let canister_id = candid::Principal::from_text(&canister_id)
+        .map_err(|e| format!("Invalid canister id {}: {}", canister_id, e))?;
+    EventService::unsubscribe(canister_id)
+}
+
+#[query]
+fn list_event_subscribers() -> Result<Vec<EventSubscription>, String> {
+    Guards::require_admin()?;
+    Ok(EventService::list_subscribers())
+}
+
+// NOVAQ Validation APIs
+
+#[update]
+async fn validate_novaq_model(model_id: String, model_data: Vec<u8>) -> Result<NOVAQValidationResult, String> {
+    Guards::require_caller_authenticated()?;
+    ModelRepoClient::validate_novaq_model(&model_id, &model_data).await
+}
+
+/// Validations of the model, newest first
+#[query]
+fn get_validation_history(model_id: String) -> Result<Vec<ValidationRecord>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(ValidationHistoryService::history(&model_id))
+}
+
+#[query]
+fn latest_validation(model_id: String) -> Result<Option<ValidationRecord>, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(ValidationHistoryService::latest(&model_id))
+}
+
+/// Allow binding a model whose latest validation failed
+#[update]
+fn override_failed_validation(model_id: String) -> Result<ValidationRecord, String> {
+    Guards::require_admin()?;
+    ValidationHistoryService::override_latest(&model_id, ic_cdk::api::caller())
+}
+
+/// Whether the agent runtime can serve the model, without binding it
+#[update]
+async fn check_model_compatibility(model_id: String) -> Result<CompatibilityReport, String> {
+    Guards::require_caller_authenticated()?;
+    let repos = crate::services::with_state(|s| s.config.model_repos());
+    if repos.is_empty() { return Err("model_repo_canister_id not configured".to_string()); }
+    let (manifest, repos) = ModelRepoClient::get_manifest(&repos, &model_id).await?;
+    let meta = ModelRepoClient::get_model_meta(&repos, &model_id).await?;
+    Ok(CompatibilityService::check(&manifest, &meta))
 }
 
 #[query]
@@ -0,0 +1,124 @@
+use ic_cdk_macros::*;
+use crate::domain::api_version::{api_info, paginate, ApiError, ApiInfo, PageRequest};
+use crate::domain::instruction::UserInstruction;
+use crate::infra::Guards;
+use crate::services::{llm_service, AccessScope, AgentFactory, AgentRequestService, AgentStatusInfo, AgentSummary, AgentTaskResult, ClusterAgentPage, ConversationSession, LlmError, ShardingService, SkippedSession, TaskHistoryService, TaskRecord};
+
+// v2 endpoints: the same operations as v1 with typed errors and cursor
+// pagination. v1 endpoints stay until the sunset reported by get_api_info.
+
+impl From<LlmError> for ApiError {
+    fn from(error: LlmError) -> Self {
+        match error {
+            LlmError::AuthenticationFailed => ApiError::Unauthenticated,
+            LlmError::InvalidRequest { message } => ApiError::InvalidArgument { message },
+            LlmError::RateLimitExceeded { reset_time } => ApiError::RateLimited {
+                message: format!("Daily token limit reached until {}", reset_time),
+            },
+            LlmError::QuotaExceeded => ApiError::QuotaExceeded { message: "Monthly token quota exceeded".to_string() },
+            LlmError::ServiceUnavailable { retry_after } => ApiError::Unavailable {
+                message: "LLM service unavailable".to_string(),
+                retry_after_secs: Some(retry_after),
+            },
+            LlmError::ModelUnavailable { model } => ApiError::Unavailable {
+                message: format!("Model {} is unavailable", model.model_id()),
+                retry_after_secs: None,
+            },
+            LlmError::ContentFiltered => ApiError::InvalidArgument { message: "Content was filtered".to_string() },
+            LlmError::InternalError { message } => ApiError::Failed { message },
+            LlmError::ContextTooLong { prompt_tokens, max_tokens, ctx_window } => ApiError::InvalidArgument {
+                message: format!(
+                    "Context too long: {} prompt tokens plus {} max_tokens exceed the {} token context window",
+                    prompt_tokens, max_tokens, ctx_window
+                ),
+            },
+        }
+    }
+}
+
+#[derive(candid::CandidType)]
+pub struct AgentPage {
+    pub items: Vec<AgentSummary>,
+    pub next_cursor: Option<String>,
+    pub total: u64,
+}
+
+#[derive(candid::CandidType)]
+pub struct TaskRecordPage {
+    pub items: Vec<TaskRecord>,
+    pub next_cursor: Option<String>,
+    pub total: u64,
+}
+
+#[derive(candid::CandidType)]
+pub struct ConversationPage {
+    pub items: Vec<ConversationSession>,
+    pub next_cursor: Option<String>,
+    pub total: u64,
+    pub skipped: Vec<SkippedSession>, // Sessions that could not be opened, on every page
+}
+
+/// Supported API versions and when deprecated v1 methods go away
+#[query]
+fn get_api_info() -> ApiInfo {
+    api_info()
+}
+
+#[update]
+async fn v2_create_agent(instruction: UserInstruction) -> Result<String, ApiError> {
+    Guards::require_caller_authenticated()?;
+    AgentRequestService::create_agent(ic_cdk::api::caller(), instruction).await
+}
+
+#[query(composite = true)]
+async fn v2_get_agent_status(agent_id: String) -> Result<AgentStatusInfo, ApiError> {
+    if let Some(shard) = ShardingService::shard_of(&agent_id) {
+        Guards::require_caller_authenticated()?;
+        return ShardingService::agent_status(shard, ic_cdk::api::caller(), &agent_id).await;
+    }
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    Ok(AgentFactory::get_agent_status(&agent_id).await?)
+}
+
+/// The caller's agents, by agent id
+#[query]
+async fn v2_list_agents(page: PageRequest) -> Result<AgentPage, ApiError> {
+    Guards::require_caller_authenticated()?;
+    let agents = AgentFactory::list_user_agents(&ic_cdk::api::caller().to_string()).await?;
+    let (items, next_cursor, total) = paginate(agents, &page, |agent| agent.agent_id.clone());
+    Ok(AgentPage { items, next_cursor, total })
+}
+
+/// The caller's agents on every shard of the cluster, by agent id. Shards
+/// that do not answer are listed in unavailable_shards instead of failing the
+/// page.
+#[query(composite = true)]
+async fn v2_list_cluster_agents(page: PageRequest) -> Result<ClusterAgentPage, ApiError> {
+    Guards::require_caller_authenticated()?;
+    Ok(ShardingService::list_cluster_agents(ic_cdk::api::caller(), &page).await?)
+}
+
+#[update]
+async fn v2_execute_agent_task(agent_id: String, task_description: String) -> Result<AgentTaskResult, ApiError> {
+    Guards::require_caller_authenticated()?;
+    AgentRequestService::execute_task(ic_cdk::api::caller(), &agent_id, task_description, false).await
+}
+
+/// Unexpired task records, oldest first
+#[query]
+fn v2_list_task_history(agent_id: String, page: PageRequest) -> Result<TaskRecordPage, ApiError> {
+    Guards::require_agent_access(&agent_id, AccessScope::Read)?;
+    let records = TaskHistoryService::live_records(&agent_id);
+    let (items, next_cursor, total) = paginate(records, &page, |record| format!("{:020}", record.sequence));
+    Ok(TaskRecordPage { items, next_cursor, total })
+}
+
+/// The caller's conversations, oldest first
+#[query]
+fn v2_list_conversations(page: PageRequest) -> Result<ConversationPage, ApiError> {
+    Guards::require_caller_authenticated()?;
+    let listing = llm_service().list_conversations(ic_cdk::api::caller());
+    let (items, next_cursor, total) =
+        paginate(listing.sessions, &page, |session| format!("{:020}-{}", session.created_at, session.session_id));
+    Ok(ConversationPage { items, next_cursor, total, skipped: listing.skipped })
+}
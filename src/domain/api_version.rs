@@ -0,0 +1,181 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::fmt;
+
+pub const CURRENT_API_VERSION: &str = "v2";
+// Announced with the v2 release; v1 endpoints keep working until the sunset
+const V1_DEPRECATED_AT: u64 = 1_792_108_800_000_000_000; // 2026-10-16T00:00:00Z
+const V1_SUNSET_AT: u64 = 1_814_400_000_000_000_000; // 2027-07-01T00:00:00Z
+
+const DEFAULT_PAGE_LIMIT: usize = 50;
+const MAX_PAGE_LIMIT: usize = 200;
+
+/// Error type of every v2 endpoint. v1 endpoints return plain text errors.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum ApiError {
+    Unauthenticated,
+    Forbidden { message: String },
+    NotFound { message: String },
+    InvalidArgument { message: String },
+    RateLimited { message: String },
+    QuotaExceeded { message: String },
+    Unavailable { message: String, retry_after_secs: Option<u64> },
+    Failed { message: String },
+}
+
+impl ApiError {
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::Unauthenticated => "Authentication required",
+            ApiError::Forbidden { message }
+            | ApiError::NotFound { message }
+            | ApiError::InvalidArgument { message }
+            | ApiError::RateLimited { message }
+            | ApiError::QuotaExceeded { message }
+            | ApiError::Unavailable { message, .. }
+            | ApiError::Failed { message } => message,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+/// v1 endpoints and services with text errors keep the message
+impl From<ApiError> for String {
+    fn from(error: ApiError) -> Self {
+        error.message().to_string()
+    }
+}
+
+/// Services type the errors a caller can act on where they arise; any other
+/// text error is an internal failure
+impl From<String> for ApiError {
+    fn from(message: String) -> Self {
+        ApiError::Failed { message }
+    }
+}
+
+/// Cursor pagination for v2 list endpoints. Cursors are opaque: pass back the
+/// `next_cursor` of the previous page.
+#[derive(Debug, Clone, Default, CandidType, Deserialize)]
+pub struct PageRequest {
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// Page of `items` sorted by `key`, starting after the request's cursor.
+/// Returns the page, the cursor of the next page and the total item count.
+pub fn paginate<T>(mut items: Vec<T>, request: &PageRequest, key: impl Fn(&T) -> String) -> (Vec<T>, Option<String>, u64) {
+    let total = items.len() as u64;
+    let limit = request.limit.map_or(DEFAULT_PAGE_LIMIT, |l| (l as usize).clamp(1, MAX_PAGE_LIMIT));
+    items.sort_by_key(|item| key(item));
+    if let Some(cursor) = &request.cursor {
+        items.retain(|item| key(item) > *cursor);
+    }
+    let next_cursor = if items.len() > limit {
+        items.truncate(limit);
+        items.last().map(&key)
+    } else {
+        None
+    };
+    (items, next_cursor, total)
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub enum ApiVersionStatus {
+    Current,
+    Deprecated,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ApiVersion {
+    pub version: String,
+    pub status: ApiVersionStatus,
+    pub deprecated_at: Option<u64>,
+    pub sunset_at: Option<u64>, // Endpoints may be removed after this
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct DeprecatedMethod {
+    pub method: String,
+    pub replacement: String,
+    pub sunset_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ApiInfo {
+    pub current_version: String,
+    pub versions: Vec<ApiVersion>,
+    pub deprecated_methods: Vec<DeprecatedMethod>,
+}
+
+pub fn api_info() -> ApiInfo {
+    let replaced = [
+        ("create_agent", "v2_create_agent"),
+        ("get_agent_status", "v2_get_agent_status"),
+        ("list_user_agents", "v2_list_agents"),
+        ("execute_agent_task", "v2_execute_agent_task"),
+        ("list_task_history", "v2_list_task_history"),
+        ("list_conversations", "v2_list_conversations"),
+    ];
+    ApiInfo {
+        current_version: CURRENT_API_VERSION.to_string(),
+        versions: vec![
+            ApiVersion {
+                version: "v1".to_string(),
+                status: ApiVersionStatus::Deprecated,
+                deprecated_at: Some(V1_DEPRECATED_AT),
+                sunset_at: Some(V1_SUNSET_AT),
+            },
+            ApiVersion {
+                version: "v2".to_string(),
+                status: ApiVersionStatus::Current,
+                deprecated_at: None,
+                sunset_at: None,
+            },
+        ],
+        deprecated_methods: replaced
+            .iter()
+            .map(|(method, replacement)| DeprecatedMethod {
+                method: method.to_string(),
+                replacement: replacement.to_string(),
+                sunset_at: V1_SUNSET_AT,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paginate_walks_all_items_once() {
+        let items: Vec<String> = ["c", "a", "e", "b", "d"].iter().map(|s| s.to_string()).collect();
+        let mut request = PageRequest { cursor: None, limit: Some(2) };
+        let mut seen = Vec::new();
+        loop {
+            let (page, next_cursor, total) = paginate(items.clone(), &request, |s| s.clone());
+            assert_eq!(total, 5);
+            seen.extend(page);
+            match next_cursor {
+                Some(cursor) => request.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen, vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn test_typed_errors_keep_their_message_as_text() {
+        let error = ApiError::Unavailable { message: "LLM unavailable".to_string(), retry_after_secs: Some(12) };
+        assert_eq!(String::from(error), "LLM unavailable");
+        assert_eq!(String::from(ApiError::Unauthenticated), "Authentication required");
+        // Untyped text errors are never mistaken for a caller mistake
+        assert!(matches!(ApiError::from("Agent x not found".to_string()), ApiError::Failed { .. }));
+    }
+}
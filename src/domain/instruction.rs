@@ -9,6 +9,15 @@ pub struct UserInstruction {
     pub subscription_tier: SubscriptionTier,
     pub context: Option<InstructionContext>,
     pub preferences: Option<AgentPreferences>,
+    #[serde(default)]
+    pub skills: Option<Vec<SkillRef>>, // Fetched from the model repo and attached at creation
+}
+
+/// A skill in the model repo; no version means its latest
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SkillRef {
+    pub skill_id: String,
+    pub version: Option<String>,
 }
 
 /// Context information for instruction analysis
@@ -32,7 +41,7 @@ pub struct AgentPreferences {
 }
 
 /// Subscription tier information
-#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
 pub enum SubscriptionTier {
     Basic,      // $29/month - 5 agents, 100k tokens
     Pro,        // $99/month - 25 agents, 500k tokens  
@@ -192,10 +201,16 @@ pub struct AgentConfiguration {
     pub memory_configuration: MemoryConfiguration,
     pub tool_access: Vec<String>,
     pub safety_constraints: Vec<String>,
+    #[serde(default)]
+    pub self_critique: bool, // Check task answers against the rules above before returning them
+    #[serde(default)]
+    pub learn_from_feedback: bool, // Quote recent task ratings in task prompts
+    #[serde(default)]
+    pub self_improvement: bool, // Periodically propose configuration changes from task history
 }
 
 /// Types of agents that can be created
-#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
 pub enum AgentType {
     GeneralAssistant,
     CodeAssistant,
@@ -249,8 +264,9 @@ pub struct MemoryConfiguration {
 }
 
 /// Memory retention policies
-#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, CandidType)]
 pub enum RetentionPolicy {
+    #[default]
     Session,        // Clear after session ends
     Daily,          // Clear daily
     Weekly,         // Clear weekly
@@ -265,6 +281,8 @@ pub struct CoordinationRequirements {
     pub agent_count: u32,
     pub communication_protocol: CommunicationProtocol,
     pub task_distribution: TaskDistributionStrategy,
+    #[serde(default)]
+    pub aggregation: AggregationStrategy,
 }
 
 /// Types of coordination needed
@@ -295,6 +313,21 @@ pub enum TaskDistributionStrategy {
     PriorityBased,  // Based on task priority
 }
 
+/// How outputs of agents working on the same task are merged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum AggregationStrategy {
+    Concatenate,    // Every output, one after another
+    Synthesize,     // LLM merges outputs into one answer
+    MajorityVote,   // Most common answer wins
+    BestOfN,        // Highest self-scored answer wins
+}
+
+impl Default for AggregationStrategy {
+    fn default() -> Self {
+        AggregationStrategy::Synthesize
+    }
+}
+
 /// Duration estimates for task completion
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct DurationEstimate {
@@ -1,8 +1,12 @@
 use serde::{Deserialize, Serialize};
 use candid::CandidType;
+use std::collections::HashMap;
 
 pub mod instruction;
+pub mod api_version;
+pub mod segments;
 pub use instruction::*;
+pub use segments::{parse_segments, OutputSegment};
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct AgentConfig {
@@ -12,6 +16,96 @@ pub struct AgentConfig {
     pub concurrency_limit: u32,
     pub ttl_seconds: u64,
     pub model_repo_canister_id: String,
+    #[serde(default)]
+    pub model_repo_mirrors: Option<Vec<String>>, // Tried in order when the primary repo fails
+    #[serde(default)]
+    pub economics_canister_id: Option<String>,
+    #[serde(default)]
+    pub context_policy: Option<ContextPolicy>, // None rejects, like ContextPolicy::default()
+    #[serde(default)]
+    pub cache_compression: Option<CacheCompression>, // None uses CacheCompression::default()
+    #[serde(default)]
+    pub model_cache_compression: Option<HashMap<String, CacheCompression>>, // Overrides by model id
+    #[serde(default)]
+    pub model_pool_budget_bytes: Option<u64>, // Shared by pool models; None uses DEFAULT_POOL_BUDGET_BYTES
+    #[serde(default)]
+    pub batching: Option<BatchingConfig>, // None sends every LLM call on its own
+}
+
+/// Cache budget shared by the models served alongside the bound model
+pub const DEFAULT_POOL_BUDGET_BYTES: u64 = 1024 * 1024 * 1024;
+
+impl AgentConfig {
+    /// Primary repo first, then mirrors, without duplicates
+    pub fn model_repos(&self) -> Vec<String> {
+        let mut repos: Vec<String> = Vec::new();
+        let mirrors = self.model_repo_mirrors.iter().flatten();
+        for repo in std::iter::once(&self.model_repo_canister_id).chain(mirrors) {
+            if !repo.is_empty() && !repos.contains(repo) {
+                repos.push(repo.clone());
+            }
+        }
+        repos
+    }
+
+    pub fn compression_for(&self, model_id: Option<&str>) -> CacheCompression {
+        model_id
+            .and_then(|id| self.model_cache_compression.as_ref()?.get(id))
+            .or(self.cache_compression.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn pool_budget_bytes(&self) -> u64 {
+        self.model_pool_budget_bytes.unwrap_or(DEFAULT_POOL_BUDGET_BYTES)
+    }
+}
+
+/// What to do when prompt_tokens + max_tokens exceed the bound model's context window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum ContextPolicy {
+    #[default]
+    Reject,   // Fail with ContextTooLong
+    Truncate, // Drop the oldest turns, then shorten the prompt
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum CompressionCodec {
+    #[default]
+    None,
+    Lz4,     // Fast to decompress, moderate savings
+    Deflate, // Smaller entries for more CPU on every cold access
+}
+
+/// How cache entries outside the hot tier are stored
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct CacheCompression {
+    pub codec: CompressionCodec,
+    pub hot_tier_seconds: u64, // Entries untouched for longer are compressed
+}
+
+impl Default for CacheCompression {
+    fn default() -> Self {
+        Self { codec: CompressionCodec::Lz4, hot_tier_seconds: 300 }
+    }
+}
+
+/// Micro-batching of LLM calls: identical requests arriving within the
+/// window share one call
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, CandidType)]
+pub struct BatchingConfig {
+    pub window_ms: u64,      // How long the first request waits for others to join
+    pub max_batch_size: u32, // Requests served by one call, the first included
+}
+
+/// Passed on install and, optionally, on upgrade. On upgrade, omitted fields
+/// keep their current values.
+#[derive(Debug, Clone, Default, Deserialize, CandidType)]
+pub struct AgentInitArgs {
+    pub config: Option<AgentConfig>,
+    pub admins: Vec<candid::Principal>,
+    pub economics_canister_id: Option<String>, // Overrides config.economics_canister_id
+    pub llm_canister_id: Option<String>,
 }
 
 impl Default for AgentConfig {
@@ -23,6 +117,13 @@ impl Default for AgentConfig {
             concurrency_limit: 4,
             ttl_seconds: 3600,
             model_repo_canister_id: String::new(),
+            model_repo_mirrors: None,
+            economics_canister_id: None,
+            context_policy: None,
+            cache_compression: None,
+            model_cache_compression: None,
+            model_pool_budget_bytes: None,
+            batching: None,
         }
     }
 }
@@ -38,12 +139,19 @@ pub struct AgentHealth {
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct InferenceRequest {
-    pub seed: u64,
+    #[serde(default)]
+    pub seed: Option<u64>, // Some repeats the first output of an identical request
     pub prompt: String,
     pub decode_params: DecodeParams,
     pub msg_id: String,
+    #[serde(default)]
+    pub model_id: Option<String>, // A model of the serving pool; None uses the bound model
+    #[serde(default)]
+    pub structured_output: Option<bool>, // Some(true) adds segments to the response
 }
 
+// Only max_tokens is applied (it bounds the prompt fit). ic-llm 1.1 takes no
+// sampling parameters, so the others must be left unset
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct DecodeParams {
     pub max_tokens: Option<u32>,
@@ -57,10 +165,34 @@ impl Default for DecodeParams {
     fn default() -> Self {
         Self {
             max_tokens: Some(512),
-            temperature: Some(0.7),
-            top_p: Some(0.9),
-            top_k: Some(50),
-            repetition_penalty: Some(1.1),
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            repetition_penalty: None,
+        }
+    }
+}
+
+impl DecodeParams {
+    /// Fill unset fields from the defaults
+    pub fn resolved(&self) -> Self {
+        Self {
+            max_tokens: self.max_tokens.or(Self::default().max_tokens),
+            ..self.clone()
+        }
+    }
+
+    /// Reject sampling parameters rather than silently ignore them
+    pub fn check_supported(&self) -> Result<(), String> {
+        let unsupported = [
+            ("temperature", self.temperature.is_some()),
+            ("top_p", self.top_p.is_some()),
+            ("top_k", self.top_k.is_some()),
+            ("repetition_penalty", self.repetition_penalty.is_some()),
+        ];
+        match unsupported.iter().find(|(_, set)| *set) {
+            Some((name, _)) => Err(format!("{} is not supported: the LLM canister takes no sampling parameters", name)),
+            None => Ok(()),
         }
     }
 }
@@ -72,6 +204,15 @@ pub struct InferenceResponse {
     pub inference_time_ms: u64,
     pub cache_hits: u32,
     pub cache_misses: u32,
+    #[serde(default)]
+    pub effective_seed: Option<u64>,
+    #[serde(default)]
+    pub replayed: bool, // The output recorded for an earlier identical seeded request
+    pub decode_params: DecodeParams, // As applied
+    #[serde(default)]
+    pub model_id: Option<String>, // Model that served the request, if one is bound
+    #[serde(default)]
+    pub segments: Option<Vec<OutputSegment>>, // Set when the request asked for structured output
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
@@ -82,6 +223,12 @@ pub struct ModelBinding {
     pub chunks_loaded: u32,
     pub total_chunks: u32,
     pub version: String,
+    #[serde(default)]
+    pub repos: Vec<String>, // Repos whose manifest digest matched at bind time, in failover order
+    #[serde(default)]
+    pub chunks_reused: u32, // Already cached with the same content, so not fetched
+    #[serde(default)]
+    pub bytes_saved: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
@@ -91,6 +238,10 @@ pub struct MemoryEntry {
     pub created_at: u64,
     pub expires_at: u64,
     pub encrypted: bool,
+    #[serde(default)]
+    pub version: u32, // Starts at 1; each write of the key adds one
+    #[serde(default)]
+    pub retention_policy: RetentionPolicy, // Decides how many older versions are kept
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]  
@@ -99,5 +250,13 @@ pub struct CacheEntry {
     pub data: Vec<u8>,
     pub last_accessed: u64,
     pub access_count: u32,
-    pub size_bytes: usize,
+    pub size_bytes: usize, // Stored size, after compression
+    #[serde(default)]
+    pub raw_size_bytes: usize,
+    #[serde(default)]
+    pub codec: CompressionCodec,
+    #[serde(default)]
+    pub incompressible: bool, // Compression did not shrink it; not retried
+    #[serde(default)]
+    pub model_id: Option<String>, // Model that first stored it; shared chunks keep its compression settings
 }
\ No newline at end of file
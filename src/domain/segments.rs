@@ -0,0 +1,168 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+/// A block of model output, in the order it appears in the raw text
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum OutputSegment {
+    Text { text: String },
+    Code { language: Option<String>, code: String }, // language is the fence's info string
+    Table { header: Vec<String>, rows: Vec<Vec<String>> },
+    List { ordered: bool, items: Vec<String> },
+}
+
+/// Split markdown output into code blocks, tables, lists and the prose
+/// between them. Unknown or malformed markup stays in text segments, and a
+/// fence left open runs to the end of the output.
+pub fn parse_segments(text: &str) -> Vec<OutputSegment> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut segments = Vec::new();
+    let mut prose: Vec<&str> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let Some((fence, info)) = fence_open(line) {
+            flush_prose(&mut prose, &mut segments);
+            let mut code = Vec::new();
+            i += 1;
+            while i < lines.len() && !fence_closes(lines[i], fence) {
+                code.push(lines[i]);
+                i += 1;
+            }
+            segments.push(OutputSegment::Code {
+                language: Some(info.to_string()).filter(|l| !l.is_empty()),
+                code: code.join("\n"),
+            });
+            i += 1;
+        } else if is_table_row(line) && lines.get(i + 1).is_some_and(|next| is_table_separator(next)) {
+            flush_prose(&mut prose, &mut segments);
+            let header = table_cells(line);
+            i += 2;
+            let mut rows = Vec::new();
+            while i < lines.len() && is_table_row(lines[i]) {
+                rows.push(table_cells(lines[i]));
+                i += 1;
+            }
+            segments.push(OutputSegment::Table { header, rows });
+        } else if let Some((ordered, first)) = list_item(line) {
+            flush_prose(&mut prose, &mut segments);
+            let mut items = vec![first.to_string()];
+            i += 1;
+            while i < lines.len() {
+                match list_item(lines[i]) {
+                    Some((same, item)) if same == ordered => items.push(item.to_string()),
+                    Some(_) => break,
+                    // Indented lines continue the item above
+                    None if lines[i].starts_with([' ', '\t']) && !lines[i].trim().is_empty() => {
+                        let last = items.last_mut().expect("list has an item");
+                        last.push('\n');
+                        last.push_str(lines[i].trim());
+                    }
+                    None => break,
+                }
+                i += 1;
+            }
+            segments.push(OutputSegment::List { ordered, items });
+        } else {
+            prose.push(line);
+            i += 1;
+        }
+    }
+    flush_prose(&mut prose, &mut segments);
+    segments
+}
+
+fn flush_prose(prose: &mut Vec<&str>, segments: &mut Vec<OutputSegment>) {
+    let text = prose.join("\n").trim().to_string();
+    prose.clear();
+    if !text.is_empty() {
+        segments.push(OutputSegment::Text { text });
+    }
+}
+
+/// The fence (``` or ~~~, possibly longer) and its info string
+fn fence_open(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim_start();
+    let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = trimmed.chars().take_while(|c| *c == marker).count();
+    if len < 3 {
+        return None;
+    }
+    Some((&trimmed[..len], trimmed[len..].trim()))
+}
+
+fn fence_closes(line: &str, fence: &str) -> bool {
+    let trimmed = line.trim();
+    let marker = fence.chars().next().expect("fence is not empty");
+    trimmed.len() >= fence.len() && trimmed.chars().all(|c| c == marker)
+}
+
+fn is_table_row(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.starts_with('|') && trimmed.len() > 1
+}
+
+fn is_table_separator(line: &str) -> bool {
+    is_table_row(line)
+        && line.contains('-')
+        && line.trim().chars().all(|c| matches!(c, '|' | '-' | ':' | ' '))
+}
+
+fn table_cells(line: &str) -> Vec<String> {
+    let trimmed = line.trim().trim_start_matches('|');
+    let trimmed = trimmed.strip_suffix('|').unwrap_or(trimmed);
+    trimmed.split('|').map(|cell| cell.trim().to_string()).collect()
+}
+
+/// Whether the item is numbered, and its text
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    let trimmed = line.trim_start();
+    if let Some(item) = ["- ", "* ", "+ "].iter().find_map(|bullet| trimmed.strip_prefix(bullet)) {
+        return Some((false, item.trim()));
+    }
+    let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits == 0 || digits > 9 {
+        return None;
+    }
+    let rest = &trimmed[digits..];
+    let item = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") "))?;
+    Some((true, item.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_segments_splits_code_tables_and_lists() {
+        let output = "Here is the fix:\n\n```rust\nfn main() {\n    println!(\"hi\");\n}\n```\n\n| Name | Size |\n|------|-----:|\n| a | 1 |\n| b | 2 |\n\nSteps:\n1. Build\n2. Deploy\n   to mainnet\n- done";
+        assert_eq!(
+            parse_segments(output),
+            vec![
+                OutputSegment::Text { text: "Here is the fix:".to_string() },
+                OutputSegment::Code {
+                    language: Some("rust".to_string()),
+                    code: "fn main() {\n    println!(\"hi\");\n}".to_string(),
+                },
+                OutputSegment::Table {
+                    header: vec!["Name".to_string(), "Size".to_string()],
+                    rows: vec![vec!["a".to_string(), "1".to_string()], vec!["b".to_string(), "2".to_string()]],
+                },
+                OutputSegment::Text { text: "Steps:".to_string() },
+                OutputSegment::List { ordered: true, items: vec!["Build".to_string(), "Deploy\nto mainnet".to_string()] },
+                OutputSegment::List { ordered: false, items: vec!["done".to_string()] },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unclosed_fence_runs_to_the_end_and_plain_text_stays_text() {
+        assert_eq!(
+            parse_segments("~~~~\nlet x = 1;\n~~~\nstill code"),
+            vec![OutputSegment::Code { language: None, code: "let x = 1;\n~~~\nstill code".to_string() }]
+        );
+        assert_eq!(
+            parse_segments("| not a table\nsee 3.5 for details"),
+            vec![OutputSegment::Text { text: "| not a table\nsee 3.5 for details".to_string() }]
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static LIVE_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// System allocator that counts live heap bytes. Wasm memory only grows, so
+/// `memory_size` reports the heap ever reserved; this reports what is in use
+/// now, which evicting caches actually brings down.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            LIVE_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            LIVE_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE_BYTES.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            LIVE_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+            LIVE_BYTES.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Bytes currently allocated on the heap; 0 off-canister
+pub fn live_heap_bytes() -> u64 {
+    LIVE_BYTES.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_allocations_until_freed() {
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        let before = live_heap_bytes();
+        unsafe {
+            let ptr = CountingAllocator.alloc(layout);
+            assert_eq!(live_heap_bytes(), before + 4096);
+            let ptr = CountingAllocator.realloc(ptr, layout, 1024);
+            assert_eq!(live_heap_bytes(), before + 1024);
+            CountingAllocator.dealloc(ptr, Layout::from_size_align(1024, 8).unwrap());
+        }
+        assert_eq!(live_heap_bytes(), before);
+    }
+}
@@ -0,0 +1,13 @@
+/// Canister time in nanoseconds. Builds with the `testing` feature add an
+/// offset tests can advance, so retention windows, idle thresholds and
+/// schedules can be crossed without waiting; timers still fire on real time.
+pub fn time() -> u64 {
+    #[cfg(feature = "testing")]
+    {
+        ic_cdk::api::time().saturating_add(crate::infra::testing::VirtualClock::offset())
+    }
+    #[cfg(not(feature = "testing"))]
+    {
+        ic_cdk::api::time()
+    }
+}
@@ -0,0 +1,137 @@
+use crate::infra::{Metrics, Resilience};
+use candid::CandidType;
+use ic_cdk::api::call::{CallResult, RejectionCode};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::future::Future;
+
+thread_local! {
+    // Heap only: an upgrade always comes back with fault injection off
+    static INJECTOR: RefCell<Option<(FaultConfig, ChaCha8Rng)>> = RefCell::new(None);
+}
+
+// Fault injection exists only in builds made with the `chaos` feature
+const COMPILED: bool = cfg!(feature = "chaos");
+
+#[derive(Debug, Clone, Copy, PartialEq, CandidType)]
+pub enum Fault {
+    RepoCallFailure,
+    LlmTimeout,
+    ChunkCorruption,
+}
+
+/// Probabilities in [0, 1] that each kind of call is made to fail. The same
+/// seed gives the same sequence of faults for the same sequence of calls.
+#[derive(Debug, Clone, Deserialize, CandidType)]
+pub struct FaultConfig {
+    pub repo_call_failure: f64, // A model repo call is rejected as SysTransient before it is made
+    pub llm_timeout: f64,       // An LLM call times out, counting against the LLM breaker
+    pub chunk_corruption: f64,  // A fetched model chunk has a byte flipped, failing its sha256 check so the next repo is tried
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct FaultStatus {
+    pub compiled: bool,
+    pub config: Option<FaultConfig>,
+    pub injected: Vec<(Fault, u64)>,
+}
+
+/// Admin-controlled fault injection for integration tests, so retries,
+/// circuit breakers and chunk handling can be exercised against a real
+/// canister. Compiled out of production builds: without the `chaos` feature
+/// it cannot be turned on and every hook is a no-op.
+pub struct Faults;
+
+impl Faults {
+    pub fn configure(config: FaultConfig) -> Result<(), String> {
+        if !COMPILED {
+            return Err("Fault injection is not available: build with the chaos feature".to_string());
+        }
+        for (name, p) in [
+            ("repo_call_failure", config.repo_call_failure),
+            ("llm_timeout", config.llm_timeout),
+            ("chunk_corruption", config.chunk_corruption),
+        ] {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("{} must be a probability between 0 and 1", name));
+            }
+        }
+        let rng = ChaCha8Rng::seed_from_u64(config.seed);
+        INJECTOR.with(|i| *i.borrow_mut() = Some((config, rng)));
+        Ok(())
+    }
+
+    pub fn clear() {
+        INJECTOR.with(|i| *i.borrow_mut() = None);
+    }
+
+    pub fn status() -> FaultStatus {
+        FaultStatus {
+            compiled: COMPILED,
+            config: INJECTOR.with(|i| i.borrow().as_ref().map(|(config, _)| config.clone())),
+            injected: [Fault::RepoCallFailure, Fault::LlmTimeout, Fault::ChunkCorruption]
+                .into_iter()
+                .map(|fault| (fault, Metrics::get_counter(&Self::counter(fault))))
+                .collect(),
+        }
+    }
+
+    /// Wrap a model repo call; an injected failure is a transient reject, so
+    /// it is retried and counted by the breaker like a real one
+    pub async fn repo_call<R>(call: impl Future<Output = CallResult<R>>) -> CallResult<R> {
+        if Self::roll(Fault::RepoCallFailure) {
+            return Err((RejectionCode::SysTransient, "injected repo call failure".to_string()));
+        }
+        call.await
+    }
+
+    /// Called before an LLM call to `target`; an injected timeout counts
+    /// against its breaker as a trapped call would
+    pub fn llm_call(target: &str) -> Result<(), String> {
+        if Self::roll(Fault::LlmTimeout) {
+            Resilience::record_failure(target);
+            return Err(format!("LLM call to {} timed out (injected)", target));
+        }
+        Ok(())
+    }
+
+    /// Called on each chunk a repo returns, before it is checked against the
+    /// manifest sha256; a corrupted chunk counts as a bad mirror and the
+    /// fetch moves on to the next repo
+    pub fn corrupt_chunk(bytes: &mut [u8]) {
+        if bytes.is_empty() || !Self::roll(Fault::ChunkCorruption) {
+            return;
+        }
+        let index = INJECTOR.with(|i| i.borrow_mut().as_mut().map_or(0, |(_, rng)| rng.gen_range(0..bytes.len())));
+        bytes[index] ^= 0xFF;
+    }
+
+    fn roll(fault: Fault) -> bool {
+        if !COMPILED {
+            return false;
+        }
+        let hit = INJECTOR.with(|i| {
+            let mut injector = i.borrow_mut();
+            let Some((config, rng)) = injector.as_mut() else {
+                return false;
+            };
+            let p = match fault {
+                Fault::RepoCallFailure => config.repo_call_failure,
+                Fault::LlmTimeout => config.llm_timeout,
+                Fault::ChunkCorruption => config.chunk_corruption,
+            };
+            p > 0.0 && rng.gen_bool(p)
+        });
+        if hit {
+            Metrics::increment_counter(&Self::counter(fault));
+        }
+        hit
+    }
+
+    fn counter(fault: Fault) -> String {
+        format!("faults_injected_total:{:?}", fault)
+    }
+}
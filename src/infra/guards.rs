@@ -1,72 +1,404 @@
-use ic_cdk::api::{caller, time};
+use crate::infra::clock::time;
+use ic_cdk::api::caller;
 use candid::Principal;
+use crate::infra::alloc::live_heap_bytes;
+use crate::infra::stable::{memory, Cbor, Memory, MEMORY_LIMITS_MEMORY_ID, RATE_LIMIT_SNAPSHOT_MEMORY_ID};
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::domain::api_version::ApiError;
+use crate::domain::instruction::SubscriptionTier;
+use crate::services::economics::EconomicsClient;
+use crate::services::{with_state, AccessScope, AttestationService, CallerEnvelope, CacheService, DelegationService, MemoryService, SettingsService, ShardingService, SystemCallerService, WebhookEvent, WebhookService};
+use crate::infra::Metrics;
+use candid::CandidType;
 use std::collections::HashMap;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
 
 thread_local! {
-    static RATE_LIMITS: RefCell<HashMap<Principal, RateLimit>> = RefCell::new(HashMap::new());
+    static LAST_PRESSURE_RELIEF: Cell<u64> = Cell::new(0);
+    static RATE_LIMITS: RefCell<HashMap<Principal, TokenBucket>> = RefCell::new(HashMap::new());
+    static RATE_LIMIT_SNAPSHOT: RefCell<StableBTreeMap<Principal, Cbor<TokenBucket>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(RATE_LIMIT_SNAPSHOT_MEMORY_ID)));
+    static MEMORY_LIMITS: RefCell<StableBTreeMap<u8, Cbor<MemoryLimits>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(MEMORY_LIMITS_MEMORY_ID)));
 }
 
-#[derive(Debug, Clone)]
-struct RateLimit {
-    requests: u32,
-    window_start: u64,
-    blocked_until: u64,
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+const TIER_REFRESH_INTERVAL: u64 = 10 * NANOS_PER_MINUTE;
+// Between lookups of one caller's tier, so a lookup in flight or an economics outage costs one call a minute
+const TIER_RETRY_INTERVAL: u64 = NANOS_PER_MINUTE;
+const MEMORY_LIMITS_KEY: u8 = 0;
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+const GIB: u64 = 1024 * 1024 * 1024;
+// Eviction walks whole maps, so under sustained pressure it runs at most this often
+const PRESSURE_RELIEF_INTERVAL: u64 = NANOS_PER_MINUTE;
+// One bucket per distinct caller; past this new callers are turned away until
+// buckets go idle, which also bounds what pre_upgrade writes
+const MAX_RATE_LIMIT_BUCKETS: usize = 10_000;
+const BUCKET_PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Per-minute allowances for a subscription tier
+#[derive(Debug, Clone, CandidType)]
+pub struct TierLimits {
+    pub requests_per_minute: u32,
+    pub tokens_per_minute: u64,
+}
+
+impl TierLimits {
+    pub fn for_tier(tier: &SubscriptionTier) -> Self {
+        match tier {
+            SubscriptionTier::Basic => Self { requests_per_minute: 30, tokens_per_minute: 20_000 },
+            SubscriptionTier::Pro => Self { requests_per_minute: 120, tokens_per_minute: 100_000 },
+            SubscriptionTier::Enterprise => Self { requests_per_minute: 600, tokens_per_minute: 1_000_000 },
+        }
+    }
+}
+
+/// Two buckets per principal, each refilling continuously up to one minute's allowance.
+/// The token bucket may go negative: usage is only known after a request completes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TokenBucket {
+    tier: SubscriptionTier,
+    requests: f64,
+    tokens: f64,
+    last_refill: u64,
+    tier_refreshed_at: u64,
+    #[serde(default)]
+    tier_lookup_at: u64, // Last lookup started, whether or not it succeeded
+}
+
+impl TokenBucket {
+    fn new(tier: SubscriptionTier, now: u64) -> Self {
+        let limits = TierLimits::for_tier(&tier);
+        Self {
+            tier,
+            requests: limits.requests_per_minute as f64,
+            tokens: limits.tokens_per_minute as f64,
+            last_refill: now,
+            tier_refreshed_at: 0,
+            tier_lookup_at: 0,
+        }
+    }
+
+    fn refill(&mut self, now: u64) {
+        let limits = TierLimits::for_tier(&self.tier);
+        let elapsed_minutes = now.saturating_sub(self.last_refill) as f64 / NANOS_PER_MINUTE as f64;
+        self.requests = (self.requests + elapsed_minutes * limits.requests_per_minute as f64)
+            .min(limits.requests_per_minute as f64);
+        self.tokens = (self.tokens + elapsed_minutes * limits.tokens_per_minute as f64)
+            .min(limits.tokens_per_minute as f64);
+        self.last_refill = now;
+    }
+
+    fn tier_refresh_due(&self, now: u64) -> bool {
+        now.saturating_sub(self.tier_refreshed_at) > TIER_REFRESH_INTERVAL
+            && now.saturating_sub(self.tier_lookup_at) > TIER_RETRY_INTERVAL
+    }
+
+    /// `is_idle` once refilled up to `now`, leaving the bucket untouched
+    fn idle_at(&self, now: u64) -> bool {
+        let mut bucket = self.clone();
+        bucket.refill(now);
+        bucket.is_idle(now)
+    }
+
+    /// Full and without a fresh tier lookup: indistinguishable from a new bucket
+    fn is_idle(&self, now: u64) -> bool {
+        let limits = TierLimits::for_tier(&self.tier);
+        self.requests >= limits.requests_per_minute as f64
+            && self.tokens >= limits.tokens_per_minute as f64
+            && now.saturating_sub(self.tier_refreshed_at) > TIER_REFRESH_INTERVAL
+    }
+}
+
+/// Current bucket state for the caller
+#[derive(Debug, Clone, CandidType)]
+pub struct RateLimitStatus {
+    pub tier: SubscriptionTier,
+    pub limits: TierLimits,
+    pub requests_remaining: u32,
+    pub tokens_remaining: u64,
+    pub tier_refreshed_at: u64,
+}
+
+/// Heap and stable memory thresholds. Past a soft limit caches are evicted;
+/// past the heap hard limit writes are rejected. Stable memory is never
+/// handed back, so its size only raises soft pressure.
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct MemoryLimits {
+    pub heap_soft_bytes: u64,
+    pub heap_hard_bytes: u64,
+    pub stable_soft_bytes: u64,
+}
+
+impl Default for MemoryLimits {
+    fn default() -> Self {
+        Self {
+            heap_soft_bytes: 2 * GIB,
+            heap_hard_bytes: 3 * GIB, // Wasm32 heaps stop at 4 GiB
+            stable_soft_bytes: 200 * GIB,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum MemoryPressure {
+    Normal,
+    Soft,
+    Hard,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct MemoryUsage {
+    pub heap_bytes: u64,          // Live allocations
+    pub heap_reserved_bytes: u64, // Wasm memory grown so far; never shrinks
+    pub stable_bytes: u64,
+    pub limits: MemoryLimits,
+    pub pressure: MemoryPressure,
 }
 
 pub struct Guards;
 
 impl Guards {
-    pub fn require_caller_authenticated() -> Result<(), String> {
+    pub fn require_caller_authenticated() -> Result<(), ApiError> {
         let caller = caller();
         if caller == Principal::anonymous() {
-            return Err("Authentication required".to_string());
+            return Err(ApiError::Unauthenticated);
         }
         Ok(())
     }
     
+    /// The caller must own the agent or hold a delegation covering `scope`
+    pub fn require_agent_access(agent_id: &str, scope: AccessScope) -> Result<(), ApiError> {
+        Self::require_caller_authenticated()?;
+        DelegationService::authorize(agent_id, &caller().to_string(), scope).map(|_| ())
+    }
+
     pub fn require_admin() -> Result<(), String> {
         Self::require_caller_authenticated()?;
-        // TODO: Implement proper admin check with governance canister
+        if !SettingsService::is_admin(&caller()) {
+            return Err("Admin access required".to_string());
+        }
+        Ok(())
+    }
+
+    /// Callers act on their own data; admins on anyone's
+    pub fn require_self_or_admin(subject: &Principal) -> Result<(), String> {
+        Self::require_caller_authenticated()?;
+        let caller = caller();
+        if caller != *subject && !SettingsService::is_admin(&caller) {
+            return Err("Not authorized: only the data subject or an admin can do this".to_string());
+        }
+        Ok(())
+    }
+
+    /// Shard calls come only from canisters in this one's shard registry
+    pub fn require_shard_peer() -> Result<(), ApiError> {
+        if !ShardingService::is_registered(&caller()) {
+            return Err(ApiError::Forbidden { message: "Caller is not a registered shard".to_string() });
+        }
         Ok(())
     }
     
-    pub fn rate_limit_check() -> Result<(), String> {
+    /// System endpoints are reserved for allowlisted canisters; each call is
+    /// audited, which only sticks in update calls
+    pub fn require_system_caller(method: &str) -> Result<(), String> {
         let caller = caller();
+        if !SystemCallerService::is_allowed(&caller) {
+            return Err("Not authorized: caller is not an allowlisted system canister".to_string());
+        }
+        SystemCallerService::record_call(caller, method);
+        Ok(())
+    }
+
+    /// The caller must be a trusted coordinator presenting a valid envelope.
+    /// Returns the user it attests, whom quotas and ownership apply to.
+    pub fn require_attested(envelope: &CallerEnvelope, method: &str) -> Result<Principal, String> {
+        AttestationService::verify(envelope, caller(), method)
+    }
+    
+    /// Take one request from the caller's bucket; fails while either bucket is empty
+    pub fn rate_limit_check() -> Result<(), ApiError> {
+        Self::rate_limit_check_for(caller())
+    }
+
+    /// `rate_limit_check` for a principal other than the caller, such as the
+    /// owner of an API key used on the HTTP gateway
+    pub fn rate_limit_check_for(caller: Principal) -> Result<(), ApiError> {
         let now = time();
-        let window_duration = 60 * 1_000_000_000; // 1 minute in nanoseconds
-        let max_requests_per_window = 100;
-        
+
         RATE_LIMITS.with(|limits| {
             let mut limits = limits.borrow_mut();
-            let limit = limits.entry(caller).or_insert(RateLimit {
-                requests: 0,
-                window_start: now,
-                blocked_until: 0,
-            });
-            
-            // Check if still blocked
-            if now < limit.blocked_until {
-                return Err(format!("Rate limited. Try again in {} seconds", 
-                    (limit.blocked_until - now) / 1_000_000_000));
-            }
-            
-            // Reset window if expired
-            if now - limit.window_start > window_duration {
-                limit.requests = 0;
-                limit.window_start = now;
+            let bucket = bucket_for(&mut limits, caller, SubscriptionTier::Basic, now)?;
+            bucket.refill(now);
+            let tier_limits = TierLimits::for_tier(&bucket.tier);
+
+            if bucket.tokens <= 0.0 {
+                let wait = (-bucket.tokens / tier_limits.tokens_per_minute as f64 * 60.0).ceil() as u64 + 1;
+                return Err(ApiError::RateLimited {
+                    message: format!("Token rate limit exceeded. Try again in {} seconds", wait),
+                });
             }
-            
-            limit.requests += 1;
-            
-            if limit.requests > max_requests_per_window {
-                limit.blocked_until = now + window_duration;
-                return Err("Rate limit exceeded. Try again later".to_string());
+            if bucket.requests < 1.0 {
+                let wait = ((1.0 - bucket.requests) / tier_limits.requests_per_minute as f64 * 60.0).ceil() as u64;
+                return Err(ApiError::RateLimited {
+                    message: format!("Rate limited. Try again in {} seconds", wait.max(1)),
+                });
             }
-            
+
+            bucket.requests -= 1.0;
             Ok(())
         })
     }
+
+    /// Charge tokens used by a completed request against the caller's bucket
+    pub fn record_token_usage(tokens: u64) {
+        Self::record_token_usage_for(caller(), tokens)
+    }
+
+    pub fn record_token_usage_for(caller: Principal, tokens: u64) {
+        let now = time();
+
+        let exhausted = RATE_LIMITS.with(|limits| {
+            let mut limits = limits.borrow_mut();
+            // Only a caller turned away by rate_limit_check lacks a bucket here
+            let Ok(bucket) = bucket_for(&mut limits, caller, SubscriptionTier::Basic, now) else {
+                return false;
+            };
+            bucket.refill(now);
+            let had_tokens = bucket.tokens > 0.0;
+            bucket.tokens -= tokens as f64;
+            had_tokens && bucket.tokens <= 0.0
+        });
+
+        if exhausted {
+            WebhookService::notify_user(&caller.to_string(), WebhookEvent::QuotaExceeded, serde_json::json!({
+                "quota": "tokens_per_minute",
+            }));
+        }
+    }
+
+    /// Re-read the caller's tier from the economics canister when the cached one is stale.
+    /// Call after `rate_limit_check` so rejected requests never wait on a lookup.
+    /// Lookup failures keep the current tier and are retried a minute later.
+    pub async fn refresh_caller_tier() {
+        Self::refresh_tier_for(caller()).await
+    }
+
+    pub async fn refresh_tier_for(caller: Principal) {
+        let Some(economics) = with_state(|s| s.config.economics_canister_id.clone()) else {
+            return;
+        };
+        let now = time();
+        let due = RATE_LIMITS.with(|limits| {
+            let mut limits = limits.borrow_mut();
+            let Ok(bucket) = bucket_for(&mut limits, caller, SubscriptionTier::Basic, now) else {
+                return false;
+            };
+            let due = bucket.tier_refresh_due(now);
+            if due {
+                bucket.tier_lookup_at = now;
+            }
+            due
+        });
+        if !due {
+            return;
+        }
+
+        let tier = match EconomicsClient::get_subscription_tier(&economics, caller).await {
+            Ok(tier) => tier.unwrap_or(SubscriptionTier::Basic),
+            Err(e) => {
+                ic_cdk::println!("tier refresh for {} failed: {}", caller, e);
+                return;
+            }
+        };
+
+        let now = time();
+        RATE_LIMITS.with(|limits| {
+            let mut limits = limits.borrow_mut();
+            let Ok(bucket) = bucket_for(&mut limits, caller, tier.clone(), now) else {
+                return;
+            };
+            bucket.refill(now);
+            bucket.tier = tier;
+            bucket.tier_refreshed_at = now;
+        });
+    }
+
+    /// Drop idle rate-limit buckets on a timer so the map tracks recent
+    /// callers only
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(BUCKET_PRUNE_INTERVAL, || {
+            let now = time();
+            RATE_LIMITS.with(|limits| prune_idle(&mut limits.borrow_mut(), now));
+        });
+    }
+
+    /// Copy active rate-limit buckets into stable memory ahead of an upgrade.
+    /// Idle buckets are dropped since a new bucket starts in the same state,
+    /// and at most MAX_RATE_LIMIT_BUCKETS of the most recently used are kept.
+    pub fn save_to_stable() {
+        let now = time();
+        let mut buckets: Vec<(Principal, TokenBucket)> = RATE_LIMITS.with(|limits| {
+            limits
+                .borrow()
+                .iter()
+                .filter(|(_, bucket)| !bucket.idle_at(now))
+                .map(|(principal, bucket)| (*principal, bucket.clone()))
+                .collect()
+        });
+        // Most recently used first; refilling afterwards would erase the order
+        buckets.sort_by_key(|(_, bucket)| std::cmp::Reverse(bucket.last_refill));
+        buckets.truncate(MAX_RATE_LIMIT_BUCKETS);
+        for (_, bucket) in &mut buckets {
+            bucket.refill(now);
+        }
+
+        RATE_LIMIT_SNAPSHOT.with(|snapshot| {
+            let mut snapshot = snapshot.borrow_mut();
+            let stale: Vec<Principal> = snapshot.iter().map(|(principal, _)| principal).collect();
+            for principal in stale {
+                snapshot.remove(&principal);
+            }
+            for (principal, bucket) in buckets {
+                snapshot.insert(principal, Cbor(bucket));
+            }
+        });
+    }
+
+    /// Restore buckets saved by `save_to_stable` and release the snapshot
+    pub fn restore_from_stable() {
+        let buckets: Vec<(Principal, TokenBucket)> = RATE_LIMIT_SNAPSHOT.with(|snapshot| {
+            let mut snapshot = snapshot.borrow_mut();
+            let entries: Vec<(Principal, TokenBucket)> =
+                snapshot.iter().map(|(principal, bucket)| (principal, bucket.0)).collect();
+            for (principal, _) in &entries {
+                snapshot.remove(principal);
+            }
+            entries
+        });
+        RATE_LIMITS.with(|limits| limits.borrow_mut().extend(buckets));
+    }
+
+    pub fn get_my_limits() -> RateLimitStatus {
+        Self::limits_for(caller())
+    }
+
+    pub fn limits_for(principal: Principal) -> RateLimitStatus {
+        let now = time();
+        let mut bucket = RATE_LIMITS.with(|limits| limits.borrow().get(&principal).cloned())
+            .unwrap_or_else(|| TokenBucket::new(SubscriptionTier::Basic, now));
+        bucket.refill(now);
+
+        RateLimitStatus {
+            limits: TierLimits::for_tier(&bucket.tier),
+            tier: bucket.tier,
+            requests_remaining: bucket.requests.max(0.0) as u32,
+            tokens_remaining: bucket.tokens.max(0.0) as u64,
+            tier_refreshed_at: bucket.tier_refreshed_at,
+        }
+    }
     
     pub fn validate_prompt_length(prompt: &str) -> Result<(), String> {
         const MAX_PROMPT_LENGTH: usize = 10_000; // 10k characters
@@ -91,9 +423,164 @@ impl Guards {
         Ok(())
     }
     
-    pub fn check_memory_limits() -> Result<(), String> {
-        // TODO: Implement actual memory usage checks
-        // For now, just return Ok for bootstrap milestone
+    /// Call before anything that grows heap or stable memory. Evicts caches past
+    /// a soft limit and rejects the write past a hard one.
+    pub fn check_memory_limits() -> Result<(), ApiError> {
+        let usage = Self::memory_usage();
+        match usage.pressure {
+            MemoryPressure::Normal => Ok(()),
+            MemoryPressure::Soft => {
+                Self::relieve_memory_pressure(&usage);
+                Ok(())
+            }
+            MemoryPressure::Hard => {
+                Self::relieve_memory_pressure(&usage);
+                Metrics::increment_counter("memory_writes_rejected_total");
+                Err(ApiError::Unavailable {
+                    message: format!(
+                        "Canister memory limit reached (heap {} bytes, stable {} bytes). Try again later",
+                        usage.heap_bytes, usage.stable_bytes
+                    ),
+                    retry_after_secs: Some(60),
+                })
+            }
+        }
+    }
+
+    pub fn memory_usage() -> MemoryUsage {
+        let limits = Self::memory_limits();
+        let heap_bytes = live_heap_bytes();
+        let stable_bytes = ic_cdk::api::stable::stable64_size() * WASM_PAGE_SIZE;
+        let pressure = memory_pressure(&limits, heap_bytes, stable_bytes);
+        MemoryUsage { heap_bytes, heap_reserved_bytes: Self::heap_memory_bytes(), stable_bytes, limits, pressure }
+    }
+
+    pub fn memory_limits() -> MemoryLimits {
+        MEMORY_LIMITS.with(|l| l.borrow().get(&MEMORY_LIMITS_KEY).map(|l| l.0)).unwrap_or_default()
+    }
+
+    pub fn set_memory_limits(limits: MemoryLimits) -> Result<(), String> {
+        if limits.heap_soft_bytes > limits.heap_hard_bytes {
+            return Err("Soft memory limits must not exceed hard limits".to_string());
+        }
+        MEMORY_LIMITS.with(|l| l.borrow_mut().insert(MEMORY_LIMITS_KEY, Cbor(limits)));
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Drop what can be rebuilt: expired memory entries and the colder half of
+    /// the layer cache. This lowers `heap_bytes`; the reserved heap stays.
+    fn relieve_memory_pressure(usage: &MemoryUsage) {
+        let now = time();
+        if now.saturating_sub(LAST_PRESSURE_RELIEF.with(|t| t.get())) < PRESSURE_RELIEF_INTERVAL {
+            return;
+        }
+        LAST_PRESSURE_RELIEF.with(|t| t.set(now));
+
+        MemoryService::clear_expired();
+        let freed = CacheService::shrink(0.5);
+        Metrics::increment_counter("memory_pressure_alerts_total");
+        Metrics::add_to_counter("memory_pressure_evicted_bytes_total", freed as u64);
+        ic_cdk::println!(
+            "memory pressure {:?}: heap {} bytes, stable {} bytes, evicted {} cache bytes",
+            usage.pressure, usage.heap_bytes, usage.stable_bytes, freed
+        );
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn heap_memory_bytes() -> u64 {
+        core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn heap_memory_bytes() -> u64 {
+        0
+    }
+}
+
+/// The caller's bucket, created with `tier` if missing. A full map is pruned
+/// of idle buckets; if none are idle the new caller is rejected, since dropping
+/// a bucket that is still draining would hand its owner a full one.
+fn bucket_for(
+    limits: &mut HashMap<Principal, TokenBucket>,
+    caller: Principal,
+    tier: SubscriptionTier,
+    now: u64,
+) -> Result<&mut TokenBucket, ApiError> {
+    if !limits.contains_key(&caller) && limits.len() >= MAX_RATE_LIMIT_BUCKETS {
+        prune_idle(limits, now);
+        if limits.len() >= MAX_RATE_LIMIT_BUCKETS {
+            return Err(ApiError::RateLimited {
+                message: "Too many active callers. Try again in 60 seconds".to_string(),
+            });
+        }
+    }
+    Ok(limits.entry(caller).or_insert_with(|| TokenBucket::new(tier, now)))
+}
+
+fn prune_idle(limits: &mut HashMap<Principal, TokenBucket>, now: u64) {
+    limits.retain(|_, bucket| !bucket.idle_at(now));
+}
+
+fn memory_pressure(limits: &MemoryLimits, heap_bytes: u64, stable_bytes: u64) -> MemoryPressure {
+    if heap_bytes >= limits.heap_hard_bytes {
+        MemoryPressure::Hard
+    } else if heap_bytes >= limits.heap_soft_bytes || stable_bytes >= limits.stable_soft_bytes {
+        MemoryPressure::Soft
+    } else {
+        MemoryPressure::Normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_pressure_follows_live_heap() {
+        let limits = MemoryLimits::default();
+        assert_eq!(memory_pressure(&limits, 3 * GIB, 0), MemoryPressure::Hard);
+        // Evicting brings live usage, and with it the pressure, back down
+        assert_eq!(memory_pressure(&limits, GIB, 0), MemoryPressure::Normal);
+        // Stable memory alone never rejects writes
+        assert_eq!(memory_pressure(&limits, GIB, 400 * GIB), MemoryPressure::Soft);
+    }
+
+    #[test]
+    fn test_tier_lookups_back_off_after_an_attempt() {
+        let now = TIER_REFRESH_INTERVAL * 2;
+        let mut bucket = TokenBucket::new(SubscriptionTier::Basic, now);
+        assert!(bucket.tier_refresh_due(now));
+        bucket.tier_lookup_at = now; // In flight or failed
+        assert!(!bucket.tier_refresh_due(now + TIER_RETRY_INTERVAL));
+        assert!(bucket.tier_refresh_due(now + TIER_RETRY_INTERVAL + 1));
+        bucket.tier_refreshed_at = now + TIER_RETRY_INTERVAL;
+        assert!(!bucket.tier_refresh_due(now + TIER_REFRESH_INTERVAL));
+    }
+
+    #[test]
+    fn test_full_bucket_map_rejects_newcomers_until_buckets_idle() {
+        let now = TIER_REFRESH_INTERVAL * 2;
+        let mut limits = HashMap::new();
+        for i in 0..MAX_RATE_LIMIT_BUCKETS as u32 {
+            let bucket = bucket_for(&mut limits, Principal::from_slice(&i.to_be_bytes()), SubscriptionTier::Basic, now + i as u64).unwrap();
+            bucket.requests -= 1.0;
+        }
+        let newcomer = Principal::from_slice(&[0xff; 8]);
+        let later = now + MAX_RATE_LIMIT_BUCKETS as u64;
+        assert!(bucket_for(&mut limits, newcomer, SubscriptionTier::Basic, later).is_err());
+        // No draining bucket is dropped to make room
+        assert_eq!(limits.len(), MAX_RATE_LIMIT_BUCKETS);
+        assert!(limits.contains_key(&Principal::from_slice(&0u32.to_be_bytes())));
+        // Known callers keep their bucket
+        let known = Principal::from_slice(&0u32.to_be_bytes());
+        assert!(bucket_for(&mut limits, known, SubscriptionTier::Basic, later).is_ok());
+
+        // A minute later every bucket has refilled, so the newcomer gets one
+        let refilled = later + NANOS_PER_MINUTE;
+        assert!(bucket_for(&mut limits, newcomer, SubscriptionTier::Basic, refilled).is_ok());
+        assert_eq!(limits.len(), 1);
+
+        prune_idle(&mut limits, refilled + NANOS_PER_MINUTE);
+        assert!(limits.is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Log-linear buckets: each power of two is split into SUB_BUCKETS equal
+// parts, so a quantile is off by at most half a part, about 3%
+const SUB_BUCKETS: u32 = 16;
+const MAX_EXPONENT: u32 = 63;
+
+/// Upper bounds of the cumulative buckets exported to Prometheus
+pub const PROMETHEUS_BOUNDS: &[f64] = &[
+    1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1_000.0, 2_500.0, 5_000.0, 10_000.0, 30_000.0, 60_000.0,
+];
+
+/// HDR-style histogram of non-negative values. Memory is bounded by the
+/// number of distinct buckets hit (at most 64 * SUB_BUCKETS + 1) no matter
+/// how many values are recorded; values below 1 share the first bucket.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Histogram {
+    buckets: BTreeMap<u32, u64>, // Bucket index to count, only non-empty ones
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: f64) {
+        let value = if value.is_finite() { value.max(0.0) } else { return };
+        *self.buckets.entry(bucket_index(value)).or_insert(0) += 1;
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    /// Fold `other` in, as if its values had been recorded here
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.count == 0 {
+            return;
+        }
+        for (&index, &count) in &other.buckets {
+            *self.buckets.entry(index).or_insert(0) += count;
+        }
+        if self.count == 0 {
+            self.min = other.min;
+            self.max = other.max;
+        } else {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    /// Value at quantile `q` in [0, 1], as the midpoint of its bucket
+    /// clamped to the recorded range; None while empty
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (&index, &count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                let (lower, upper) = bucket_bounds(index);
+                return Some(((lower + upper) / 2.0).clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// Values at or below each bound, for Prometheus `le` buckets. A bucket
+    /// straddling a bound is counted below it when its midpoint is.
+    pub fn cumulative(&self, bounds: &[f64]) -> Vec<u64> {
+        bounds
+            .iter()
+            .map(|&bound| {
+                self.buckets
+                    .iter()
+                    .filter(|(&index, _)| {
+                        let (lower, upper) = bucket_bounds(index);
+                        (lower + upper) / 2.0 <= bound
+                    })
+                    .map(|(_, &count)| count)
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+fn bucket_index(value: f64) -> u32 {
+    if value < 1.0 {
+        return 0;
+    }
+    let exponent = (value.log2().floor() as u32).min(MAX_EXPONENT);
+    let base = 2f64.powi(exponent as i32);
+    let sub = (((value / base) - 1.0) * SUB_BUCKETS as f64) as u32;
+    1 + exponent * SUB_BUCKETS + sub.min(SUB_BUCKETS - 1)
+}
+
+fn bucket_bounds(index: u32) -> (f64, f64) {
+    if index == 0 {
+        return (0.0, 1.0);
+    }
+    let exponent = (index - 1) / SUB_BUCKETS;
+    let sub = (index - 1) % SUB_BUCKETS;
+    let base = 2f64.powi(exponent as i32);
+    let width = base / SUB_BUCKETS as f64;
+    (base + sub as f64 * width, base + (sub + 1) as f64 * width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantiles_are_within_bucket_error() {
+        let mut histogram = Histogram::default();
+        for value in 1..=10_000 {
+            histogram.record(value as f64);
+        }
+        for (q, expected) in [(0.5, 5_000.0), (0.95, 9_500.0), (0.99, 9_900.0)] {
+            let estimate = histogram.quantile(q).unwrap();
+            assert!((estimate - expected).abs() / expected < 0.035, "q{} = {}", q, estimate);
+        }
+        assert_eq!(histogram.count(), 10_000);
+        assert_eq!(histogram.min(), 1.0);
+        assert_eq!(histogram.max(), 10_000.0);
+        assert!(histogram.buckets.len() <= 14 * SUB_BUCKETS as usize + 1);
+    }
+
+    #[test]
+    fn test_quantile_of_single_value_is_exact() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        histogram.record(1234.0);
+        assert_eq!(histogram.quantile(0.99), Some(1234.0));
+    }
+
+    #[test]
+    fn test_cumulative_counts_are_monotonic() {
+        let mut histogram = Histogram::default();
+        for value in [0.5, 3.0, 40.0, 40.0, 700.0, 90_000.0] {
+            histogram.record(value);
+        }
+        let cumulative = histogram.cumulative(PROMETHEUS_BOUNDS);
+        assert!(cumulative.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(cumulative[0], 1); // le=1
+        assert_eq!(cumulative[5], 4); // le=50
+        assert_eq!(*cumulative.last().unwrap(), 5); // 90000 only in +Inf
+    }
+}
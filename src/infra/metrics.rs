@@ -1,16 +1,28 @@
-use ic_cdk::api::time;
+use crate::infra::histogram::{Histogram, PROMETHEUS_BOUNDS};
+use crate::infra::stable::{memory, Cbor, Memory, METRICS_SNAPSHOT_MEMORY_ID};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 thread_local! {
     static METRICS: RefCell<SystemMetrics> = RefCell::new(SystemMetrics::default());
+    static METRICS_SNAPSHOT: RefCell<StableBTreeMap<u8, Cbor<SystemMetrics>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(METRICS_SNAPSHOT_MEMORY_ID)));
 }
 
-#[derive(Debug, Default)]
+const SNAPSHOT_KEY: u8 = 0;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SystemMetrics {
     pub counters: HashMap<String, u64>,
     pub gauges: HashMap<String, f64>,
-    pub histograms: HashMap<String, Vec<f64>>,
+    #[serde(default)]
+    pub distributions: HashMap<String, Histogram>,
+    // Raw samples from snapshots taken before histograms were bucketed
+    #[serde(default, rename = "histograms", skip_serializing)]
+    legacy_histograms: HashMap<String, Vec<f64>>,
     pub last_updated: u64,
 }
 
@@ -43,14 +55,7 @@ impl Metrics {
         let now = time();
         METRICS.with(|m| {
             let mut metrics = m.borrow_mut();
-            let hist = metrics.histograms.entry(name.to_string()).or_insert_with(Vec::new);
-            hist.push(value);
-            
-            // Keep only last 1000 values to prevent unbounded growth
-            if hist.len() > 1000 {
-                hist.remove(0);
-            }
-            
+            metrics.distributions.entry(name.to_string()).or_default().record(value);
             metrics.last_updated = now;
         });
     }
@@ -90,35 +95,105 @@ impl Metrics {
     pub fn get_histogram_stats(name: &str) -> Option<HistogramStats> {
         METRICS.with(|m| {
             let metrics = m.borrow();
-            if let Some(values) = metrics.histograms.get(name) {
-                if values.is_empty() {
-                    return None;
+            let histogram = metrics.distributions.get(name)?;
+            Some(HistogramStats {
+                count: histogram.count(),
+                sum: histogram.sum(),
+                mean: histogram.sum() / histogram.count() as f64,
+                min: histogram.min(),
+                max: histogram.max(),
+                p50: histogram.quantile(0.50)?,
+                p95: histogram.quantile(0.95)?,
+                p99: histogram.quantile(0.99)?,
+            })
+        })
+    }
+    
+    /// Current counters, and gauges together with the p50/p95/p99 of each
+    /// histogram as `{name}_p50`-style values, for the metric time series
+    pub fn sample() -> (Vec<(String, u64)>, Vec<(String, f64)>) {
+        METRICS.with(|m| {
+            let metrics = m.borrow();
+            let counters = metrics.counters.iter().map(|(name, value)| (name.clone(), *value)).collect();
+            let mut gauges: Vec<(String, f64)> =
+                metrics.gauges.iter().map(|(name, value)| (name.clone(), *value)).collect();
+            for (name, histogram) in &metrics.distributions {
+                for (suffix, q) in [("p50", 0.50), ("p95", 0.95), ("p99", 0.99)] {
+                    if let Some(value) = histogram.quantile(q) {
+                        gauges.push((format!("{}_{}", name, suffix), value));
+                    }
                 }
-                
-                let mut sorted = values.clone();
-                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
-                
-                let len = sorted.len();
-                let sum: f64 = sorted.iter().sum();
-                let mean = sum / len as f64;
-                
-                let p50 = sorted[len / 2];
-                let p95 = sorted[(len as f64 * 0.95) as usize];
-                let p99 = sorted[(len as f64 * 0.99) as usize];
-                
-                Some(HistogramStats {
-                    count: len as u64,
-                    sum,
-                    mean,
-                    min: sorted[0],
-                    max: sorted[len - 1],
-                    p50,
-                    p95,
-                    p99,
-                })
-            } else {
-                None
             }
+            (counters, gauges)
+        })
+    }
+    
+    /// Copy metrics into stable memory ahead of an upgrade
+    pub fn save_to_stable() {
+        let snapshot = METRICS.with(|m| m.borrow().clone());
+        METRICS_SNAPSHOT.with(|s| {
+            s.borrow_mut().insert(SNAPSHOT_KEY, Cbor(snapshot));
+        });
+    }
+
+    /// Restore metrics saved by `save_to_stable` and release the snapshot
+    pub fn restore_from_stable() {
+        let snapshot = METRICS_SNAPSHOT.with(|s| s.borrow_mut().remove(&SNAPSHOT_KEY));
+        if let Some(Cbor(mut snapshot)) = snapshot {
+            for (name, values) in std::mem::take(&mut snapshot.legacy_histograms) {
+                let histogram = snapshot.distributions.entry(name).or_default();
+                for value in values {
+                    histogram.record(value);
+                }
+            }
+            METRICS.with(|m| *m.borrow_mut() = snapshot);
+        }
+    }
+    
+    /// Counters, gauges and histograms in the Prometheus text format. A
+    /// `name:label` metric becomes `name{label="label"}`.
+    pub fn prometheus_text() -> String {
+        METRICS.with(|m| {
+            let metrics = m.borrow();
+            let mut out = String::new();
+            // Labelled series of one metric share a single TYPE line
+            let mut typed = HashSet::new();
+            let mut type_line = |out: &mut String, name: &str, kind: &str| {
+                if typed.insert(name.to_string()) {
+                    out.push_str(&format!("# TYPE {} {}\n", name, kind));
+                }
+            };
+            let mut counters: Vec<(&String, &u64)> = metrics.counters.iter().collect();
+            counters.sort();
+            for (name, value) in counters {
+                let (name, labels) = prometheus_name(name);
+                type_line(&mut out, &name, "counter");
+                out.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+            let mut gauges: Vec<(&String, &f64)> = metrics.gauges.iter().collect();
+            gauges.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, value) in gauges {
+                let (name, labels) = prometheus_name(name);
+                type_line(&mut out, &name, "gauge");
+                out.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+            let mut distributions: Vec<(&String, &Histogram)> = metrics.distributions.iter().collect();
+            distributions.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, histogram) in distributions {
+                let (name, labels) = prometheus_name(name);
+                let label = |le: &str| match labels.strip_suffix('}') {
+                    Some(labels) => format!("{},le=\"{}\"}}", labels, le),
+                    None => format!("{{le=\"{}\"}}", le),
+                };
+                type_line(&mut out, &name, "histogram");
+                for (bound, count) in PROMETHEUS_BOUNDS.iter().zip(histogram.cumulative(PROMETHEUS_BOUNDS)) {
+                    out.push_str(&format!("{}_bucket{} {}\n", name, label(&bound.to_string()), count));
+                }
+                out.push_str(&format!("{}_bucket{} {}\n", name, label("+Inf"), histogram.count()));
+                out.push_str(&format!("{}_sum{} {}\n", name, labels, histogram.sum()));
+                out.push_str(&format!("{}_count{} {}\n", name, labels, histogram.count()));
+            }
+            out
         })
     }
     
@@ -128,7 +203,7 @@ impl Metrics {
             serde_json::json!({
                 "counters": metrics.counters,
                 "gauges": metrics.gauges,
-                "histogram_count": metrics.histograms.len(),
+                "histogram_count": metrics.distributions.len(),
                 "last_updated": metrics.last_updated
             })
         })
@@ -145,4 +220,13 @@ pub struct HistogramStats {
     pub p50: f64,
     pub p95: f64,
     pub p99: f64,
-}
\ No newline at end of file
+}
+
+/// Metric name and labels in Prometheus syntax, from `name` or `name:label`
+fn prometheus_name(name: &str) -> (String, String) {
+    let sanitize = |s: &str| s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect::<String>();
+    match name.split_once(':') {
+        Some((name, label)) => (sanitize(name), format!("{{label=\"{}\"}}", label.replace('\\', "\\\\").replace('"', "\\\""))),
+        None => (sanitize(name), String::new()),
+    }
+}
@@ -1,5 +1,18 @@
+pub mod alloc;
+pub mod clock;
+pub mod faults;
 pub mod guards;
+pub mod histogram;
 pub mod metrics;
+pub mod resilience;
+pub mod scheduler;
+pub mod spans;
+pub mod stable;
+#[cfg(feature = "testing")]
+pub mod testing;
 
+pub use faults::{Fault, FaultConfig, FaultStatus, Faults};
 pub use guards::Guards;
-pub use metrics::Metrics;
\ No newline at end of file
+pub use metrics::Metrics;
+pub use resilience::Resilience;
+pub use scheduler::{Lane, Scheduler};
\ No newline at end of file
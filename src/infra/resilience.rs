@@ -0,0 +1,419 @@
+use crate::infra::Metrics;
+use candid::CandidType;
+use ic_cdk::api::call::{CallResult, RejectionCode};
+use crate::infra::clock::time;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+thread_local! {
+    static BREAKERS: RefCell<HashMap<String, CircuitBreaker>> = RefCell::new(HashMap::new());
+}
+
+const MAX_ATTEMPTS: u32 = 3;
+const BASE_BACKOFF_MS: u64 = 250;
+const MAX_BACKOFF_MS: u64 = 5_000;
+const FAILURE_THRESHOLD: u32 = 5;
+const OPEN_DURATION_NS: u64 = 30 * 1_000_000_000; // 30 seconds
+
+#[derive(Debug, Clone, PartialEq, CandidType)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone)]
+struct CircuitBreaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: u64,
+    probe_started_at: Option<u64>, // Set while a half-open probe is out
+    in_flight: u32,                // Guarded calls awaiting their callback; never counted as failures
+    last_success_at: Option<u64>,
+    last_failure_at: Option<u64>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            opened_at: 0,
+            probe_started_at: None,
+            in_flight: 0,
+            last_success_at: None,
+            last_failure_at: None,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// Err carries the seconds until a call may be admitted again
+    fn admit(&mut self, now: u64) -> Result<(), u64> {
+        match self.state {
+            BreakerState::Closed => Ok(()),
+            BreakerState::Open if now < self.opened_at + OPEN_DURATION_NS => {
+                Err((self.opened_at + OPEN_DURATION_NS - now).div_ceil(1_000_000_000))
+            }
+            // One probe at a time; a probe that never reported back is replaced after the open duration
+            BreakerState::HalfOpen if self.probe_started_at.is_some_and(|at| now < at + OPEN_DURATION_NS) => {
+                Err((self.probe_started_at.unwrap_or(now) + OPEN_DURATION_NS - now).div_ceil(1_000_000_000))
+            }
+            BreakerState::Open | BreakerState::HalfOpen => {
+                self.state = BreakerState::HalfOpen;
+                self.probe_started_at = Some(now);
+                Ok(())
+            }
+        }
+    }
+
+    fn on_success(&mut self, now: u64) {
+        self.state = BreakerState::Closed;
+        self.consecutive_failures = 0;
+        self.probe_started_at = None;
+        self.last_success_at = Some(now);
+    }
+
+    /// Returns true when this failure opened the breaker
+    fn on_failure(&mut self, now: u64) -> bool {
+        self.consecutive_failures += 1;
+        self.last_failure_at = Some(now);
+        let should_open = self.state == BreakerState::HalfOpen
+            || (self.state == BreakerState::Closed && self.consecutive_failures >= FAILURE_THRESHOLD);
+        if should_open {
+            self.state = BreakerState::Open;
+            self.opened_at = now;
+            self.probe_started_at = None;
+        }
+        should_open
+    }
+}
+
+/// Probe result for a downstream dependency, derived from its breaker
+#[derive(Debug, Clone, CandidType)]
+pub struct DependencyStatus {
+    pub target: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub in_flight: u32,
+    pub last_success_at: Option<u64>,
+    pub last_failure_at: Option<u64>,
+}
+
+/// A guarded call between admission and its callback. ic-cdk drops pending
+/// futures when a callback traps, so a call dropped before it settled is the
+/// trap and is recorded as a failure then.
+struct InFlightCall {
+    target: String,
+    settled: bool,
+    clock: fn() -> u64,
+}
+
+impl InFlightCall {
+    fn start(target: &str, clock: fn() -> u64) -> Result<Self, String> {
+        Resilience::check_breaker_at(target, clock())?;
+        Resilience::with_breaker(target, |breaker| breaker.in_flight += 1);
+        Ok(Self { target: target.to_string(), settled: false, clock })
+    }
+
+    fn succeed(mut self) {
+        self.settled = true;
+        Resilience::record_success_at(&self.target, (self.clock)());
+    }
+}
+
+impl Drop for InFlightCall {
+    fn drop(&mut self) {
+        Resilience::with_breaker(&self.target, |breaker| breaker.in_flight = breaker.in_flight.saturating_sub(1));
+        if !self.settled {
+            Resilience::record_failure_at(&self.target, (self.clock)());
+        }
+    }
+}
+
+/// Retry, backoff and circuit breaking for cross-canister calls
+pub struct Resilience;
+
+impl Resilience {
+    /// Run an inter-canister call with bounded retries and jittered backoff.
+    /// `target` keys the circuit breaker (usually the callee canister id).
+    pub async fn call<R, F, Fut>(target: &str, method: &str, mut op: F) -> Result<R, String>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = CallResult<R>>,
+    {
+        Self::check_breaker(target)?;
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match op().await {
+                Ok(value) => {
+                    Self::record_success(target);
+                    return Ok(value);
+                }
+                Err((code, msg)) => {
+                    // Application-level rejects say nothing about the callee's health
+                    let opened = if code == RejectionCode::CanisterReject {
+                        false
+                    } else {
+                        Self::record_failure(target)
+                    };
+
+                    if opened || attempt >= MAX_ATTEMPTS || !Self::is_transient(&code) {
+                        return Err(format!("xnet {} failed: {:?}", method, (code, msg)));
+                    }
+
+                    Metrics::increment_counter("xnet_retries_total");
+                    sleep(backoff_delay(attempt, time())).await;
+                }
+            }
+        }
+    }
+
+    /// Guard a call that cannot report failure (e.g. ic_llm traps on reject).
+    /// The call is only counted as in flight while it awaits; it becomes a
+    /// failure if its callback traps, and a success once it returns.
+    pub async fn guard<T>(target: &str, fut: impl Future<Output = T>) -> Result<T, String> {
+        Self::guard_with_clock(target, fut, time).await
+    }
+
+    async fn guard_with_clock<T>(target: &str, fut: impl Future<Output = T>, clock: fn() -> u64) -> Result<T, String> {
+        let call = InFlightCall::start(target, clock)?;
+        let out = fut.await;
+        call.succeed();
+        Ok(out)
+    }
+
+    pub fn check_breaker(target: &str) -> Result<(), String> {
+        Self::check_breaker_at(target, time())
+    }
+
+    fn check_breaker_at(target: &str, now: u64) -> Result<(), String> {
+        let state = Self::breaker_state(target);
+        Self::with_breaker(target, |breaker| breaker.admit(now)).map_err(|retry_in| match state {
+            BreakerState::HalfOpen => format!("Circuit half-open for {}, probe in flight. Retry in {} seconds", target, retry_in),
+            _ => format!("Circuit open for {}. Retry in {} seconds", target, retry_in),
+        })
+    }
+
+    pub fn record_success(target: &str) {
+        Self::record_success_at(target, time())
+    }
+
+    fn record_success_at(target: &str, now: u64) {
+        Self::with_breaker(target, |breaker| breaker.on_success(now));
+    }
+
+    /// Returns true when this failure opened the breaker
+    pub fn record_failure(target: &str) -> bool {
+        Self::record_failure_at(target, time())
+    }
+
+    fn record_failure_at(target: &str, now: u64) -> bool {
+        let opened = Self::with_breaker(target, |breaker| breaker.on_failure(now));
+        if opened {
+            Metrics::increment_counter("circuit_breaker_opens_total");
+        }
+        opened
+    }
+
+    /// Seconds until an open breaker lets a probe through; None unless open
+    pub fn retry_after_secs(target: &str) -> Option<u64> {
+        let now = time();
+        BREAKERS.with(|b| {
+            b.borrow()
+                .get(target)
+                .filter(|breaker| breaker.state == BreakerState::Open)
+                .map(|breaker| (breaker.opened_at + OPEN_DURATION_NS).saturating_sub(now).div_ceil(1_000_000_000))
+        })
+    }
+
+    pub fn breaker_state(target: &str) -> BreakerState {
+        BREAKERS.with(|b| {
+            b.borrow()
+                .get(target)
+                .map(|breaker| breaker.state.clone())
+                .unwrap_or(BreakerState::Closed)
+        })
+    }
+
+    pub fn dependency_status(target: &str) -> DependencyStatus {
+        let breaker = BREAKERS.with(|b| b.borrow().get(target).cloned()).unwrap_or_default();
+        DependencyStatus {
+            target: target.to_string(),
+            state: breaker.state,
+            consecutive_failures: breaker.consecutive_failures,
+            in_flight: breaker.in_flight,
+            last_success_at: breaker.last_success_at,
+            last_failure_at: breaker.last_failure_at,
+        }
+    }
+
+    fn with_breaker<R>(target: &str, f: impl FnOnce(&mut CircuitBreaker) -> R) -> R {
+        BREAKERS.with(|b| f(b.borrow_mut().entry(target.to_string()).or_default()))
+    }
+
+    fn is_transient(code: &RejectionCode) -> bool {
+        matches!(code, RejectionCode::SysTransient | RejectionCode::Unknown)
+    }
+}
+
+/// Exponential backoff capped at MAX_BACKOFF_MS, with full jitter in
+/// [capped / 2, capped]
+fn backoff_delay(attempt: u32, seed: u64) -> Duration {
+    let exp = BASE_BACKOFF_MS.saturating_mul(1 << (attempt - 1).min(16));
+    let capped = exp.min(MAX_BACKOFF_MS);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed ^ attempt as u64);
+    Duration::from_millis(rng.gen_range(capped / 2..=capped))
+}
+
+/// Timer-backed sleep; the executor polls the waiting future when the timer fires
+pub fn sleep(delay: Duration) -> Sleep {
+    let state = Rc::new(RefCell::new(SleepState::default()));
+    let timer_state = state.clone();
+    ic_cdk_timers::set_timer(delay, move || {
+        let waker = {
+            let mut s = timer_state.borrow_mut();
+            s.done = true;
+            s.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    });
+    Sleep { state }
+}
+
+#[derive(Default)]
+struct SleepState {
+    done: bool,
+    waker: Option<Waker>,
+}
+
+pub struct Sleep {
+    state: Rc<RefCell<SleepState>>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.borrow_mut();
+        if state.done {
+            Poll::Ready(())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    fn test_breaker_opens_probes_and_closes() {
+        let mut breaker = CircuitBreaker::default();
+        for _ in 1..FAILURE_THRESHOLD {
+            assert!(!breaker.on_failure(SECOND));
+        }
+        assert_eq!(breaker.state, BreakerState::Closed);
+        assert!(breaker.on_failure(SECOND));
+        assert_eq!(breaker.state, BreakerState::Open);
+        assert_eq!(breaker.admit(SECOND + 10 * SECOND), Err(20));
+
+        // One probe after the open duration; others wait for it
+        let reopen_at = SECOND + OPEN_DURATION_NS;
+        assert_eq!(breaker.admit(reopen_at), Ok(()));
+        assert_eq!(breaker.state, BreakerState::HalfOpen);
+        assert!(breaker.admit(reopen_at + SECOND).is_err());
+
+        breaker.on_success(reopen_at + 2 * SECOND);
+        assert_eq!(breaker.state, BreakerState::Closed);
+        assert_eq!(breaker.consecutive_failures, 0);
+        assert_eq!(breaker.admit(reopen_at + 3 * SECOND), Ok(()));
+    }
+
+    #[test]
+    fn test_failed_probe_reopens_and_stale_probe_is_replaced() {
+        let mut breaker = CircuitBreaker::default();
+        (0..FAILURE_THRESHOLD).for_each(|_| {
+            breaker.on_failure(0);
+        });
+        assert_eq!(breaker.admit(OPEN_DURATION_NS), Ok(()));
+        assert!(breaker.on_failure(OPEN_DURATION_NS + SECOND));
+        assert_eq!(breaker.state, BreakerState::Open);
+
+        // A probe whose callback never came back does not block forever
+        let probe_at = 2 * OPEN_DURATION_NS + SECOND;
+        assert_eq!(breaker.admit(probe_at), Ok(()));
+        assert!(breaker.admit(probe_at + OPEN_DURATION_NS - 1).is_err());
+        assert_eq!(breaker.admit(probe_at + OPEN_DURATION_NS), Ok(()));
+    }
+
+    #[test]
+    fn test_in_flight_calls_are_not_failures() {
+        fn clock() -> u64 {
+            SECOND
+        }
+        let target = "in-flight-test";
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let done = Rc::new(RefCell::new(false));
+        let reply = {
+            let done = done.clone();
+            std::future::poll_fn(move |_| if *done.borrow() { Poll::Ready(7) } else { Poll::Pending })
+        };
+        let mut call = Box::pin(Resilience::guard_with_clock(target, reply, clock));
+        assert!(call.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(Resilience::dependency_status(target).in_flight, 1);
+
+        // Other calls fail while this one awaits its callback
+        for _ in 1..FAILURE_THRESHOLD {
+            assert!(!Resilience::record_failure_at(target, SECOND));
+        }
+        let status = Resilience::dependency_status(target);
+        assert_eq!(status.state, BreakerState::Closed);
+        assert_eq!(status.consecutive_failures, FAILURE_THRESHOLD - 1);
+        assert_eq!(status.in_flight, 1);
+
+        *done.borrow_mut() = true;
+        assert_eq!(call.as_mut().poll(&mut cx), Poll::Ready(Ok(7)));
+        let status = Resilience::dependency_status(target);
+        assert_eq!(status.state, BreakerState::Closed);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.in_flight, 0);
+
+        // A call dropped before settling is a trapped callback
+        let mut trapped = Box::pin(Resilience::guard_with_clock(target, std::future::pending::<()>(), clock));
+        assert!(trapped.as_mut().poll(&mut cx).is_pending());
+        drop(trapped);
+        let status = Resilience::dependency_status(target);
+        assert_eq!(status.consecutive_failures, 1);
+        assert_eq!(status.in_flight, 0);
+    }
+
+    #[test]
+    fn test_backoff_grows_within_jitter_and_caps() {
+        for seed in 0..20 {
+            for attempt in 1..=3 {
+                let capped = BASE_BACKOFF_MS << (attempt - 1);
+                let delay = backoff_delay(attempt, seed).as_millis() as u64;
+                assert!((capped / 2..=capped).contains(&delay), "attempt {} gave {}ms", attempt, delay);
+            }
+            let delay = backoff_delay(30, seed).as_millis() as u64;
+            assert!((MAX_BACKOFF_MS / 2..=MAX_BACKOFF_MS).contains(&delay));
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::infra::Metrics;
+use crate::services::SettingsService;
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+thread_local! {
+    static SCHEDULER: RefCell<SchedulerState> = RefCell::new(SchedulerState::default());
+}
+
+// Further LLM calls are refused instead of queued
+const MAX_WAITING: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, CandidType)]
+pub enum Lane {
+    Critical, // Critical-priority tasks; served before any Standard waiter
+    Standard,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct SchedulerStatus {
+    pub concurrency_limit: u32,
+    pub running: u32,
+    pub waiting_critical: u32,
+    pub waiting_standard: u32,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    running: u32,
+    running_by_principal: HashMap<Principal, u32>,
+    waiters: Vec<Waiter>, // Oldest first
+    next_waiter_id: u64,
+}
+
+struct Waiter {
+    id: u64,
+    principal: Principal,
+    lane: Lane,
+    slot: Rc<RefCell<Slot>>,
+}
+
+#[derive(Default)]
+struct Slot {
+    granted: bool,
+    waker: Option<Waker>,
+}
+
+/// Canister-wide limit on concurrent LLM calls, sized by
+/// AgentConfig.concurrency_limit. Callers over the limit wait in line:
+/// Critical waiters go first, and within a lane the principal with the
+/// fewest calls running goes next, oldest first on ties, so one busy
+/// principal cannot starve the others.
+pub struct Scheduler;
+
+impl Scheduler {
+    /// Waits for a slot; the slot is held until the permit is dropped
+    pub async fn acquire(principal: Principal, lane: Lane) -> Result<Permit, String> {
+        let enqueued_at = time();
+        let slot = SCHEDULER.with(|s| {
+            let mut state = s.borrow_mut();
+            if state.waiters.is_empty() && state.running < Self::limit() {
+                state.grant(principal);
+                return Ok(None);
+            }
+            if state.waiters.len() >= MAX_WAITING {
+                return Err("Too many inference calls are waiting; retry later".to_string());
+            }
+            let slot = Rc::new(RefCell::new(Slot::default()));
+            let id = state.next_waiter_id;
+            state.next_waiter_id += 1;
+            state.waiters.push(Waiter { id, principal, lane, slot: slot.clone() });
+            Ok(Some((id, slot)))
+        })?;
+
+        if let Some((id, slot)) = slot {
+            Wait { id, principal, slot, done: false }.await;
+        }
+        let waited_ms = (time().saturating_sub(enqueued_at)) / 1_000_000;
+        Metrics::record_histogram(&format!("inference_wait_ms:{:?}", lane), waited_ms as f64);
+        Ok(Permit { principal })
+    }
+
+    pub fn status() -> SchedulerStatus {
+        SCHEDULER.with(|s| {
+            let state = s.borrow();
+            let waiting = |lane| state.waiters.iter().filter(|w| w.lane == lane).count() as u32;
+            SchedulerStatus {
+                concurrency_limit: Self::limit(),
+                running: state.running,
+                waiting_critical: waiting(Lane::Critical),
+                waiting_standard: waiting(Lane::Standard),
+            }
+        })
+    }
+
+    fn limit() -> u32 {
+        SettingsService::settings().config.concurrency_limit.max(1)
+    }
+
+    /// Hands free slots to waiters. They are woken from a timer so they resume
+    /// in their own message rather than inside the releasing one.
+    fn dispatch() {
+        let limit = Self::limit();
+        let woken: Vec<Waker> = SCHEDULER.with(|s| {
+            let mut state = s.borrow_mut();
+            let mut woken = Vec::new();
+            while state.running < limit {
+                let Some(index) = next_waiter(&state.waiters, &state.running_by_principal) else {
+                    break;
+                };
+                let waiter = state.waiters.remove(index);
+                state.grant(waiter.principal);
+                let mut slot = waiter.slot.borrow_mut();
+                slot.granted = true;
+                woken.extend(slot.waker.take());
+            }
+            woken
+        });
+        for waker in woken {
+            ic_cdk_timers::set_timer(Duration::ZERO, move || waker.wake());
+        }
+    }
+}
+
+impl SchedulerState {
+    fn grant(&mut self, principal: Principal) {
+        self.running += 1;
+        *self.running_by_principal.entry(principal).or_insert(0) += 1;
+    }
+
+    fn release(&mut self, principal: Principal) {
+        self.running = self.running.saturating_sub(1);
+        if let Some(count) = self.running_by_principal.get_mut(&principal) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.running_by_principal.remove(&principal);
+            }
+        }
+    }
+}
+
+/// Waiter to serve next: Critical lane first, then the principal with the
+/// fewest running calls, then the oldest
+fn next_waiter(waiters: &[Waiter], running_by_principal: &HashMap<Principal, u32>) -> Option<usize> {
+    waiters
+        .iter()
+        .enumerate()
+        .min_by_key(|(index, waiter)| {
+            let running = running_by_principal.get(&waiter.principal).copied().unwrap_or(0);
+            (waiter.lane != Lane::Critical, running, *index)
+        })
+        .map(|(index, _)| index)
+}
+
+/// A running LLM call's slot. ic-cdk drops pending futures when a callback
+/// traps, so the slot is released on traps too.
+pub struct Permit {
+    principal: Principal,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        SCHEDULER.with(|s| s.borrow_mut().release(self.principal));
+        Scheduler::dispatch();
+    }
+}
+
+struct Wait {
+    id: u64,
+    principal: Principal,
+    slot: Rc<RefCell<Slot>>,
+    done: bool,
+}
+
+impl Future for Wait {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut slot = self.slot.borrow_mut();
+        if slot.granted {
+            drop(slot);
+            self.done = true;
+            Poll::Ready(())
+        } else {
+            slot.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Wait {
+    /// A waiter dropped before it resumed gives up its place in line, or the
+    /// slot it was granted
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let granted = self.slot.borrow().granted;
+        SCHEDULER.with(|s| {
+            let mut state = s.borrow_mut();
+            if granted {
+                state.release(self.principal);
+            } else {
+                state.waiters.retain(|w| w.id != self.id);
+            }
+        });
+        if granted {
+            Scheduler::dispatch();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waiter(id: u64, principal: u8, lane: Lane) -> Waiter {
+        Waiter {
+            id,
+            principal: Principal::from_slice(&[principal]),
+            lane,
+            slot: Rc::new(RefCell::new(Slot::default())),
+        }
+    }
+
+    #[test]
+    fn test_next_waiter_prefers_critical_then_least_served() {
+        let busy = Principal::from_slice(&[1]);
+        let running = HashMap::from([(busy, 3)]);
+        let waiters = vec![waiter(0, 1, Lane::Standard), waiter(1, 2, Lane::Standard), waiter(2, 1, Lane::Standard)];
+        assert_eq!(next_waiter(&waiters, &running), Some(1));
+
+        let waiters = vec![waiter(0, 2, Lane::Standard), waiter(1, 1, Lane::Critical)];
+        assert_eq!(next_waiter(&waiters, &running), Some(1));
+        assert_eq!(next_waiter(&[], &running), None);
+    }
+}
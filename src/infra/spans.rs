@@ -0,0 +1,277 @@
+use crate::infra::stable::{memory, Cbor, Memory, SPANS_MEMORY_ID};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+
+thread_local! {
+    // Keyed by a sequence number in the order spans ended
+    static SPANS: RefCell<StableBTreeMap<u64, Cbor<SpanRecord>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SPANS_MEMORY_ID)));
+    static ID_SEQ: Cell<u64> = Cell::new(0);
+}
+
+const MAX_RETAINED_SPANS: u64 = 20_000;
+const DEFAULT_EXPORT_BATCH: usize = 200;
+const MAX_EXPORT_BATCH: usize = 1_000;
+const MAX_ATTRIBUTE_CHARS: usize = 256;
+const SERVICE_NAME: &str = "ohms-agent";
+// OTLP SpanKind and StatusCode values
+const SPAN_KIND_INTERNAL: u8 = 1;
+const STATUS_CODE_OK: u8 = 1;
+const STATUS_CODE_ERROR: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SpanValue {
+    Text(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<&str> for SpanValue {
+    fn from(value: &str) -> Self {
+        SpanValue::Text(value.chars().take(MAX_ATTRIBUTE_CHARS).collect())
+    }
+}
+
+impl From<&String> for SpanValue {
+    fn from(value: &String) -> Self {
+        value.as_str().into()
+    }
+}
+
+impl From<String> for SpanValue {
+    fn from(value: String) -> Self {
+        value.as_str().into()
+    }
+}
+
+impl From<u64> for SpanValue {
+    fn from(value: u64) -> Self {
+        SpanValue::Int(value.min(i64::MAX as u64) as i64)
+    }
+}
+
+impl From<bool> for SpanValue {
+    fn from(value: bool) -> Self {
+        SpanValue::Bool(value)
+    }
+}
+
+/// A finished span as stored until it is exported
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpanRecord {
+    pub trace_id: String,               // 32 hex chars
+    pub span_id: String,                // 16 hex chars
+    pub parent_span_id: Option<String>, // None for a root span
+    pub name: String,
+    pub start_ns: u64,
+    pub end_ns: u64,
+    pub attributes: Vec<(String, SpanValue)>,
+    pub error: Option<String>,
+}
+
+/// One OTLP-JSON ExportTraceServiceRequest, ready to POST to a collector's /v1/traces
+#[derive(Debug, Clone, CandidType)]
+pub struct SpanExport {
+    pub otlp_json: String,
+    pub spans: u32,
+    pub next_cursor: Option<u64>,
+}
+
+/// An open span. Children take their trace and parent from it; nothing is
+/// stored until `end`, so a span dropped without ending is never exported.
+#[derive(Debug, Clone)]
+pub struct Span {
+    record: SpanRecord,
+}
+
+impl Span {
+    pub fn root(name: &str) -> Self {
+        let trace_id = hex::encode(&new_id(name)[..16]);
+        Self::open(name, trace_id, None)
+    }
+
+    pub fn child(&self, name: &str) -> Self {
+        Self::open(name, self.record.trace_id.clone(), Some(self.record.span_id.clone()))
+    }
+
+    fn open(name: &str, trace_id: String, parent_span_id: Option<String>) -> Self {
+        Span {
+            record: SpanRecord {
+                span_id: hex::encode(&new_id(&trace_id)[..8]),
+                trace_id,
+                parent_span_id,
+                name: name.to_string(),
+                start_ns: time(),
+                end_ns: 0,
+                attributes: Vec::new(),
+                error: None,
+            },
+        }
+    }
+
+    /// For spans recorded after the fact
+    pub fn starting_at(mut self, start_ns: u64) -> Self {
+        self.record.start_ns = start_ns;
+        self
+    }
+
+    pub fn attribute(mut self, key: &str, value: impl Into<SpanValue>) -> Self {
+        self.record.attributes.push((key.to_string(), value.into()));
+        self
+    }
+
+    pub fn end(self, error: Option<&str>) {
+        self.end_at(time(), error)
+    }
+
+    pub fn end_at(mut self, end_ns: u64, error: Option<&str>) {
+        self.record.end_ns = end_ns.max(self.record.start_ns);
+        self.record.error = error.map(|e| e.chars().take(MAX_ATTRIBUTE_CHARS).collect());
+        Spans::store(self.record);
+    }
+}
+
+/// Timing spans of binds, chunk fetches, tasks, LLM calls and tool calls,
+/// kept in a bounded buffer and exported as OTLP-JSON batches for a relay
+/// to forward to a tracing backend
+pub struct Spans;
+
+impl Spans {
+    fn store(record: SpanRecord) {
+        SPANS.with(|s| {
+            let mut spans = s.borrow_mut();
+            let key = spans.last_key_value().map_or(0, |(key, _)| key + 1);
+            spans.insert(key, Cbor(record));
+            while spans.len() > MAX_RETAINED_SPANS {
+                match spans.first_key_value() {
+                    Some((oldest, _)) => spans.remove(&oldest),
+                    None => break,
+                };
+            }
+        });
+    }
+
+    /// Spans ended after `cursor` that started at or after `since`, oldest first
+    pub fn export(since: Option<u64>, cursor: Option<u64>, limit: Option<u32>) -> SpanExport {
+        let limit = limit.map_or(DEFAULT_EXPORT_BATCH, |l| (l as usize).clamp(1, MAX_EXPORT_BATCH));
+        let start = cursor.map_or(0, |c| c + 1);
+        let since = since.unwrap_or(0);
+        let mut batch: Vec<(u64, SpanRecord)> = SPANS.with(|s| {
+            s.borrow()
+                .range(start..)
+                .map(|(key, record)| (key, record.0))
+                .filter(|(_, record)| record.start_ns >= since)
+                .take(limit + 1)
+                .collect()
+        });
+        let next_cursor = if batch.len() > limit {
+            batch.truncate(limit);
+            batch.last().map(|(key, _)| *key)
+        } else {
+            None
+        };
+        let records: Vec<SpanRecord> = batch.into_iter().map(|(_, record)| record).collect();
+        SpanExport {
+            otlp_json: to_otlp(&records, &ic_cdk::api::id().to_text()).to_string(),
+            spans: records.len() as u32,
+            next_cursor,
+        }
+    }
+}
+
+/// Unique for the canister's lifetime: time never repeats across upgrades,
+/// and the sequence separates ids made within one round
+fn new_id(seed: &str) -> [u8; 32] {
+    let seq = ID_SEQ.with(|s| {
+        let seq = s.get().wrapping_add(1);
+        s.set(seq);
+        seq
+    });
+    Sha256::digest([seed.as_bytes(), &time().to_be_bytes(), &seq.to_be_bytes()].concat()).into()
+}
+
+/// ExportTraceServiceRequest in the OTLP/JSON encoding: hex ids, 64-bit
+/// integers as strings, lowerCamelCase field names
+fn to_otlp(records: &[SpanRecord], instance_id: &str) -> Value {
+    let spans: Vec<Value> = records
+        .iter()
+        .map(|record| {
+            let attributes: Vec<Value> = record.attributes.iter().map(|(key, value)| attribute(key, value)).collect();
+            let status = match &record.error {
+                Some(message) => json!({ "code": STATUS_CODE_ERROR, "message": message }),
+                None => json!({ "code": STATUS_CODE_OK }),
+            };
+            json!({
+                "traceId": record.trace_id,
+                "spanId": record.span_id,
+                "parentSpanId": record.parent_span_id.clone().unwrap_or_default(),
+                "name": record.name,
+                "kind": SPAN_KIND_INTERNAL,
+                "startTimeUnixNano": record.start_ns.to_string(),
+                "endTimeUnixNano": record.end_ns.to_string(),
+                "attributes": attributes,
+                "status": status,
+            })
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", &SpanValue::from(SERVICE_NAME)),
+                    attribute("service.instance.id", &SpanValue::from(instance_id)),
+                ]
+            },
+            "scopeSpans": [{
+                "scope": { "name": SERVICE_NAME },
+                "spans": spans,
+            }]
+        }]
+    })
+}
+
+fn attribute(key: &str, value: &SpanValue) -> Value {
+    let value = match value {
+        SpanValue::Text(text) => json!({ "stringValue": text }),
+        SpanValue::Int(int) => json!({ "intValue": int.to_string() }),
+        SpanValue::Bool(flag) => json!({ "boolValue": flag }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_otlp_encodes_ids_times_and_status() {
+        let record = SpanRecord {
+            trace_id: "0af7651916cd43dd8448eb211c80319c".to_string(),
+            span_id: "b7ad6b7169203331".to_string(),
+            parent_span_id: None,
+            name: "llm_call".to_string(),
+            start_ns: 1_000,
+            end_ns: 2_000,
+            attributes: vec![
+                ("ohms.tokens".to_string(), SpanValue::Int(42)),
+                ("ohms.model_id".to_string(), SpanValue::Text("llama".to_string())),
+            ],
+            error: Some("timeout".to_string()),
+        };
+        let export = to_otlp(&[record], "aaaaa-aa");
+        let span = &export["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(span["parentSpanId"], "");
+        assert_eq!(span["startTimeUnixNano"], "1000");
+        assert_eq!(span["attributes"][0]["value"]["intValue"], "42");
+        assert_eq!(span["attributes"][1]["value"]["stringValue"], "llama");
+        assert_eq!(span["status"]["code"], STATUS_CODE_ERROR);
+        assert_eq!(span["status"]["message"], "timeout");
+        assert_eq!(export["resourceSpans"][0]["resource"]["attributes"][0]["value"]["stringValue"], SERVICE_NAME);
+    }
+}
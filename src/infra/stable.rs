@@ -0,0 +1,102 @@
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, Storable};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Virtual memory regions - never reuse or renumber an id once deployed
+pub const WORKFLOWS_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const AGENT_SNAPSHOT_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const EVENTS_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const EVENT_SUBSCRIBERS_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const PAYMENT_RECEIPTS_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const RATE_LIMIT_SNAPSHOT_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub const METRICS_SNAPSHOT_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub const TOOL_REGISTRY_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub const TOOL_AUDIT_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub const KNOWLEDGE_DOCUMENTS_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub const KNOWLEDGE_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub const UPLOAD_SESSIONS_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub const UPLOAD_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub const ARCHIVED_AGENTS_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub const API_KEYS_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub const CANISTER_SETTINGS_MEMORY_ID: MemoryId = MemoryId::new(15);
+pub const BENCHMARK_SUITES_MEMORY_ID: MemoryId = MemoryId::new(16);
+pub const BENCHMARK_RESULTS_MEMORY_ID: MemoryId = MemoryId::new(17);
+pub const VALIDATION_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(18);
+pub const EXPERIMENTS_MEMORY_ID: MemoryId = MemoryId::new(19);
+pub const PROMPT_TEMPLATES_MEMORY_ID: MemoryId = MemoryId::new(20);
+pub const CALIBRATION_OUTCOMES_MEMORY_ID: MemoryId = MemoryId::new(21);
+pub const CALIBRATION_MEMORY_ID: MemoryId = MemoryId::new(22);
+pub const SLA_DURATIONS_MEMORY_ID: MemoryId = MemoryId::new(23);
+pub const SHARDING_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(24);
+pub const AGENT_ROUTES_MEMORY_ID: MemoryId = MemoryId::new(25);
+pub const SHARD_REGISTRY_MEMORY_ID: MemoryId = MemoryId::new(26);
+pub const SIGNING_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(27);
+pub const EXECUTION_TRACES_MEMORY_ID: MemoryId = MemoryId::new(28);
+pub const DEFERRED_TASKS_MEMORY_ID: MemoryId = MemoryId::new(29);
+pub const TRUSTED_COORDINATORS_MEMORY_ID: MemoryId = MemoryId::new(30);
+pub const ATTESTATION_NONCES_MEMORY_ID: MemoryId = MemoryId::new(31);
+pub const ATTESTATION_AUDIT_MEMORY_ID: MemoryId = MemoryId::new(32);
+pub const SYSTEM_CALLERS_MEMORY_ID: MemoryId = MemoryId::new(33);
+pub const SYSTEM_AUDIT_MEMORY_ID: MemoryId = MemoryId::new(34);
+pub const USER_KEY_SALTS_MEMORY_ID: MemoryId = MemoryId::new(35);
+pub const VETKD_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(36);
+pub const USER_DATA_DELETIONS_MEMORY_ID: MemoryId = MemoryId::new(37);
+pub const PUBLIC_LISTINGS_MEMORY_ID: MemoryId = MemoryId::new(38);
+pub const PUBLIC_LISTING_RATINGS_MEMORY_ID: MemoryId = MemoryId::new(39);
+pub const BILLING_ACCRUALS_MEMORY_ID: MemoryId = MemoryId::new(40);
+pub const BILLING_OUTBOX_MEMORY_ID: MemoryId = MemoryId::new(41);
+pub const BILLING_TOTALS_MEMORY_ID: MemoryId = MemoryId::new(42);
+pub const COST_SAMPLES_MEMORY_ID: MemoryId = MemoryId::new(43);
+pub const PRICING_TABLE_MEMORY_ID: MemoryId = MemoryId::new(44);
+pub const CALL_JOURNAL_MEMORY_ID: MemoryId = MemoryId::new(45);
+pub const SKILLS_MEMORY_ID: MemoryId = MemoryId::new(46);
+pub const AGENT_DEBUG_SNAPSHOTS_MEMORY_ID: MemoryId = MemoryId::new(47);
+pub const SPANS_MEMORY_ID: MemoryId = MemoryId::new(48);
+pub const ALERT_RULES_MEMORY_ID: MemoryId = MemoryId::new(49);
+pub const SLO_OBJECTIVES_MEMORY_ID: MemoryId = MemoryId::new(50);
+pub const SLO_WINDOWS_MEMORY_ID: MemoryId = MemoryId::new(51);
+pub const METRIC_SERIES_MEMORY_ID: MemoryId = MemoryId::new(52);
+pub const ARTIFACTS_MEMORY_ID: MemoryId = MemoryId::new(53);
+pub const ARTIFACT_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(54);
+pub const REDACTION_POLICIES_MEMORY_ID: MemoryId = MemoryId::new(55);
+pub const KEEPALIVE_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(56);
+pub const STANDBY_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(57);
+pub const GC_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(58);
+pub const MEMORY_LIMITS_MEMORY_ID: MemoryId = MemoryId::new(59);
+pub const PAYMENT_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(60);
+pub const TASK_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(61);
+pub const TASK_HISTORY_SEQUENCES_MEMORY_ID: MemoryId = MemoryId::new(62);
+pub const EMBEDDING_BACKEND_MEMORY_ID: MemoryId = MemoryId::new(63);
+pub const SEEDED_OUTPUTS_MEMORY_ID: MemoryId = MemoryId::new(64);
+pub const SEEDED_OUTPUT_ORDER_MEMORY_ID: MemoryId = MemoryId::new(65);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+/// CBOR-encoded wrapper so serde types can be stored in stable structures
+#[derive(Debug, Clone)]
+pub struct Cbor<T>(pub T);
+
+impl<T: Serialize + DeserializeOwned> Storable for Cbor<T> {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(serde_cbor::to_vec(&self.0).expect("failed to encode stable value"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Cbor(serde_cbor::from_slice(&bytes).expect("failed to decode stable value"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
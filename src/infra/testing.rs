@@ -0,0 +1,209 @@
+use crate::services::modelrepo::{ModelManifest, ModelMeta, SkillArtifact};
+use candid::CandidType;
+use ic_llm::ChatMessage as LlmChatMessage;
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+
+thread_local! {
+    static CLOCK_OFFSET: Cell<u64> = Cell::new(0);
+    static REPO: RefCell<Option<MockRepo>> = RefCell::new(None);
+    static LLM: RefCell<Option<MockLlm>> = RefCell::new(None);
+}
+
+/// A model as the mock repo serves it
+#[derive(Debug, Clone, Deserialize, CandidType)]
+pub struct MockModel {
+    pub manifest: ModelManifest,
+    pub meta: ModelMeta,
+    pub chunks: Vec<(String, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone, Deserialize, CandidType)]
+pub enum MockLlmReply {
+    Text(String),
+    Error(String), // Surfaces like an LLM outage
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct TestingStatus {
+    pub clock_offset_ns: u64,
+    pub mock_models: Vec<String>,
+    pub mock_skills: Vec<String>,
+    pub llm_mocked: bool,
+    pub llm_calls: u64,
+    pub llm_replies_queued: u32,
+}
+
+#[derive(Default)]
+struct MockRepo {
+    models: HashMap<String, MockModel>,
+    skills: HashMap<String, Vec<SkillArtifact>>, // Oldest version first
+}
+
+#[derive(Default)]
+struct MockLlm {
+    replies: VecDeque<MockLlmReply>,
+    calls: u64,
+}
+
+/// Offset added to canister time by `clock::time`
+pub struct VirtualClock;
+
+impl VirtualClock {
+    pub fn offset() -> u64 {
+        CLOCK_OFFSET.with(|c| c.get())
+    }
+
+    /// Time only moves forward, so an advance cannot be undone
+    pub fn advance(nanos: u64) {
+        CLOCK_OFFSET.with(|c| c.set(c.get().saturating_add(nanos)));
+    }
+}
+
+/// Deterministic stand-ins for the canisters the agent depends on, for
+/// end-to-end tests under PocketIC. Compiled in only with the `testing`
+/// feature; once a mock is installed ModelRepoClient or the LLM path never
+/// leaves the canister.
+pub struct TestSeams;
+
+impl TestSeams {
+    pub fn install_model(model: MockModel) {
+        REPO.with(|r| {
+            let mut repo = r.borrow_mut();
+            let repo = repo.get_or_insert_with(MockRepo::default);
+            repo.models.insert(model.manifest.model_id.clone(), model);
+        });
+    }
+
+    pub fn install_skill(artifact: SkillArtifact) {
+        REPO.with(|r| {
+            let mut repo = r.borrow_mut();
+            let versions = repo.get_or_insert_with(MockRepo::default).skills.entry(artifact.skill_id.clone()).or_default();
+            versions.retain(|v| v.version != artifact.version);
+            versions.push(artifact);
+        });
+    }
+
+    /// Replies are used in order; once they run out the mock echoes the
+    /// last user message
+    pub fn script_llm(replies: Vec<MockLlmReply>) {
+        LLM.with(|l| l.borrow_mut().get_or_insert_with(MockLlm::default).replies.extend(replies));
+    }
+
+    /// Back to the real dependencies. The clock offset stays: canister time
+    /// must never go backwards.
+    pub fn reset() {
+        REPO.with(|r| *r.borrow_mut() = None);
+        LLM.with(|l| *l.borrow_mut() = None);
+    }
+
+    pub fn status() -> TestingStatus {
+        let (mut mock_models, mut mock_skills) = REPO.with(|r| {
+            r.borrow().as_ref().map_or((Vec::new(), Vec::new()), |repo| {
+                (repo.models.keys().cloned().collect(), repo.skills.keys().cloned().collect())
+            })
+        });
+        mock_models.sort();
+        mock_skills.sort();
+        let (llm_mocked, llm_calls, llm_replies_queued) = LLM.with(|l| {
+            l.borrow().as_ref().map_or((false, 0, 0), |llm| (true, llm.calls, llm.replies.len() as u32))
+        });
+        TestingStatus {
+            clock_offset_ns: VirtualClock::offset(),
+            mock_models,
+            mock_skills,
+            llm_mocked,
+            llm_calls,
+            llm_replies_queued,
+        }
+    }
+
+    // Hooks below return None while no mock is installed, leaving the real path to run
+
+    pub fn repo_manifest(model_id: &str) -> Option<Result<ModelManifest, String>> {
+        Self::with_repo(|repo| repo.models.get(model_id).map(|m| m.manifest.clone()).ok_or_else(|| "manifest not found".to_string()))
+    }
+
+    pub fn repo_meta(model_id: &str) -> Option<Result<ModelMeta, String>> {
+        Self::with_repo(|repo| repo.models.get(model_id).map(|m| m.meta.clone()).ok_or_else(|| "meta not found".to_string()))
+    }
+
+    pub fn repo_chunk(model_id: &str, chunk_id: &str) -> Option<Result<Vec<u8>, String>> {
+        Self::with_repo(|repo| {
+            repo.models
+                .get(model_id)
+                .and_then(|m| m.chunks.iter().find(|(id, _)| id == chunk_id))
+                .map(|(_, bytes)| bytes.clone())
+                .ok_or_else(|| format!("Chunk {} unavailable: not in the mock repo", chunk_id))
+        })
+    }
+
+    pub fn repo_skill(skill_id: &str, version: Option<&str>) -> Option<Result<SkillArtifact, String>> {
+        Self::with_repo(|repo| {
+            let versions = repo.skills.get(skill_id);
+            match version {
+                Some(version) => versions.and_then(|v| v.iter().find(|a| a.version == version)),
+                None => versions.and_then(|v| v.last()),
+            }
+            .cloned()
+            .ok_or_else(|| format!("Skill {} unavailable: not in the mock repo", skill_id))
+        })
+    }
+
+    pub fn llm_reply(messages: &[LlmChatMessage]) -> Option<Result<String, String>> {
+        LLM.with(|l| {
+            let mut llm = l.borrow_mut();
+            let llm = llm.as_mut()?;
+            llm.calls += 1;
+            Some(match llm.replies.pop_front() {
+                Some(MockLlmReply::Text(text)) => Ok(text),
+                Some(MockLlmReply::Error(e)) => Err(e),
+                None => {
+                    let last_user = messages.iter().rev().find_map(|m| match m {
+                        LlmChatMessage::User { content } => Some(content.as_str()),
+                        _ => None,
+                    });
+                    Ok(format!("mock reply: {}", last_user.unwrap_or_default()))
+                }
+            })
+        })
+    }
+
+    fn with_repo<T>(f: impl FnOnce(&MockRepo) -> T) -> Option<T> {
+        REPO.with(|r| r.borrow().as_ref().map(f))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_llm_mock_plays_script_then_echoes() {
+        TestSeams::reset();
+        assert!(TestSeams::llm_reply(&[]).is_none());
+        TestSeams::script_llm(vec![MockLlmReply::Text("first".to_string()), MockLlmReply::Error("down".to_string())]);
+        let messages = [LlmChatMessage::User { content: "hello".to_string() }];
+        assert_eq!(TestSeams::llm_reply(&messages), Some(Ok("first".to_string())));
+        assert_eq!(TestSeams::llm_reply(&messages), Some(Err("down".to_string())));
+        assert_eq!(TestSeams::llm_reply(&messages), Some(Ok("mock reply: hello".to_string())));
+        assert_eq!(TestSeams::status().llm_calls, 3);
+    }
+
+    #[test]
+    fn test_skill_without_version_is_the_latest_installed() {
+        TestSeams::reset();
+        for version in ["1.0.0", "1.1.0"] {
+            TestSeams::install_skill(SkillArtifact {
+                skill_id: "summarize".to_string(),
+                version: version.to_string(),
+                definition: Vec::new(),
+                sha256: String::new(),
+            });
+        }
+        assert_eq!(TestSeams::repo_skill("summarize", None).unwrap().unwrap().version, "1.1.0");
+        assert_eq!(TestSeams::repo_skill("summarize", Some("1.0.0")).unwrap().unwrap().version, "1.0.0");
+        assert!(TestSeams::repo_skill("missing", None).unwrap().is_err());
+    }
+}
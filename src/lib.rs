@@ -1,4 +1,5 @@
 pub mod api;
+pub mod api_v2;
 pub mod domain;
 pub mod services;
 pub mod infra;
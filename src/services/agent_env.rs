@@ -0,0 +1,202 @@
+use crate::infra::clock::time;
+use crate::services::agent_factory::{AgentFactory, AutonomousAgent};
+use crate::services::privacy::PrivacyService;
+use crate::services::with_state_mut;
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+const MAX_VARS: usize = 50;
+const MAX_NAME_LEN: usize = 64;
+const MAX_VALUE_CHARS: usize = 4_000;
+// Template variable a var renders into: PROJECT_NAME is {{env_PROJECT_NAME}}
+const TEMPLATE_PREFIX: &str = "env_";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EnvValue {
+    Plain(String),
+    Sealed(Vec<u8>), // Under the owner's vetKD key
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentEnvVar {
+    pub name: String,
+    pub value: EnvValue,
+    pub updated_at: u64,
+}
+
+impl AgentEnvVar {
+    pub fn is_secret(&self) -> bool {
+        matches!(self.value, EnvValue::Sealed(_))
+    }
+}
+
+/// An env var as its owner sees it; secret values are never returned
+#[derive(Debug, Clone, CandidType)]
+pub struct AgentEnvVarInfo {
+    pub name: String,
+    pub secret: bool,
+    pub value: Option<String>,
+    pub updated_at: u64,
+}
+
+/// Fixed per-agent context such as a project name, repo URL or brand voice.
+/// Plain vars render into prompt templates that declare `{{env_NAME}}` and
+/// expand `${NAME}` in tool arguments. Secret vars are sealed with the
+/// owner's key: they only expand into the arguments of canister tool calls
+/// the owner requested, never into a delegate's call, a prompt or the code
+/// sandbox, and are dropped when the agent changes hands.
+pub struct AgentEnvService;
+
+impl AgentEnvService {
+    pub async fn set(agent_id: &str, name: String, value: String, secret: bool) -> Result<AgentEnvVarInfo, String> {
+        if name.is_empty()
+            || name.len() > MAX_NAME_LEN
+            || !name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+            || name.starts_with(|c: char| c.is_ascii_digit())
+        {
+            return Err(format!(
+                "Env var names are 1 to {} characters of A-Z, 0-9 and '_', not starting with a digit",
+                MAX_NAME_LEN
+            ));
+        }
+        if value.chars().count() > MAX_VALUE_CHARS {
+            return Err(format!("Env var value exceeds {} characters", MAX_VALUE_CHARS));
+        }
+        let agent = AgentFactory::find_agent(agent_id)?;
+        if agent.env.len() >= MAX_VARS && !agent.env.iter().any(|v| v.name == name) {
+            return Err(format!("Env var limit reached. Maximum: {}", MAX_VARS));
+        }
+        let value = if secret {
+            let owner = Self::owner(&agent)?;
+            PrivacyService::ensure_key(owner).await?;
+            EnvValue::Sealed(PrivacyService::seal(&owner, value.as_bytes())?)
+        } else {
+            EnvValue::Plain(value)
+        };
+        let var = AgentEnvVar { name, value, updated_at: time() };
+        let info = Self::info(&var);
+        // The agent may have changed while the key was derived
+        with_state_mut(|state| {
+            let agent = state.agents.get_mut(agent_id).ok_or_else(|| format!("Agent {} not found", agent_id))?;
+            agent.env.retain(|v| v.name != var.name);
+            agent.env.push(var);
+            agent.env.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(info)
+        })
+    }
+
+    pub fn remove(agent_id: &str, name: &str) -> Result<(), String> {
+        with_state_mut(|state| {
+            let agent = state.agents.get_mut(agent_id).ok_or_else(|| format!("Agent {} not found", agent_id))?;
+            let before = agent.env.len();
+            agent.env.retain(|v| v.name != name);
+            if agent.env.len() == before {
+                return Err(format!("Env var {} not set on agent {}", name, agent_id));
+            }
+            Ok(())
+        })
+    }
+
+    pub fn list(agent_id: &str) -> Result<Vec<AgentEnvVarInfo>, String> {
+        Ok(AgentFactory::find_agent(agent_id)?.env.iter().map(Self::info).collect())
+    }
+
+    /// Plain vars as `env_NAME` template values
+    pub fn template_values(agent: &AutonomousAgent) -> Vec<(String, String)> {
+        agent
+            .env
+            .iter()
+            .filter_map(|var| match &var.value {
+                EnvValue::Plain(value) => Some((format!("{}{}", TEMPLATE_PREFIX, var.name), value.clone())),
+                EnvValue::Sealed(_) => None,
+            })
+            .collect()
+    }
+
+    /// Replace `${NAME}` with the agent's var NAME; unknown names are left as
+    /// they are. A secret is opened only when `allow_secrets`; otherwise
+    /// referencing one is an error.
+    pub async fn expand(agent: &AutonomousAgent, arguments: &str, allow_secrets: bool) -> Result<String, String> {
+        let referenced: Vec<&AgentEnvVar> =
+            agent.env.iter().filter(|var| arguments.contains(&format!("${{{}}}", var.name))).collect();
+        if referenced.is_empty() {
+            return Ok(arguments.to_string());
+        }
+        let mut values = Vec::new();
+        for var in referenced {
+            let value = match &var.value {
+                EnvValue::Plain(value) => value.clone(),
+                EnvValue::Sealed(_) if !allow_secrets => {
+                    return Err(format!(
+                        "Secret env var {} is only expanded in canister tool calls made by the agent owner",
+                        var.name
+                    ));
+                }
+                EnvValue::Sealed(sealed) => {
+                    let owner = Self::owner(agent)?;
+                    PrivacyService::ensure_key(owner).await?;
+                    let plaintext = PrivacyService::open(&owner, sealed)?;
+                    String::from_utf8(plaintext).map_err(|_| format!("Secret env var {} is not valid UTF-8", var.name))?
+                }
+            };
+            values.push((var.name.as_str(), value));
+        }
+        Ok(substitute(arguments, &values))
+    }
+
+    fn info(var: &AgentEnvVar) -> AgentEnvVarInfo {
+        AgentEnvVarInfo {
+            name: var.name.clone(),
+            secret: var.is_secret(),
+            value: match &var.value {
+                EnvValue::Plain(value) => Some(value.clone()),
+                EnvValue::Sealed(_) => None,
+            },
+            updated_at: var.updated_at,
+        }
+    }
+
+    fn owner(agent: &AutonomousAgent) -> Result<Principal, String> {
+        Principal::from_text(&agent.user_id).map_err(|_| format!("Agent {} has no principal owner", agent.agent_id))
+    }
+}
+
+/// One pass over `text`, so values containing `${...}` are not expanded again
+fn substitute(text: &str, values: &[(&str, String)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let replaced = after.find('}').and_then(|end| {
+            let value = values.iter().find(|(name, _)| *name == &after[..end])?;
+            Some((value.1.as_str(), end))
+        });
+        match replaced {
+            Some((value, end)) => {
+                out.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push_str("${");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute_replaces_known_names_once() {
+        let values = [("REPO", "github.com/ohms".to_string()), ("LOOP", "${REPO}".to_string())];
+        assert_eq!(
+            substitute(r#"{"url": "${REPO}", "x": "${LOOP}", "y": "${UNSET}", "z": "${"}"#, &values),
+            r#"{"url": "github.com/ohms", "x": "${REPO}", "y": "${UNSET}", "z": "${"}"#
+        );
+    }
+}
@@ -1,14 +1,59 @@
+use crate::domain::api_version::ApiError;
 use crate::domain::instruction::*;
-use crate::domain::{AgentConfig, ModelBinding};
-use crate::services::{BindingService, with_state, with_state_mut};
+use crate::domain::{parse_segments, AgentConfig, DecodeParams, ModelBinding, OutputSegment};
+use crate::services::{ModelPoolService, llm_service, with_state, with_state_mut};
+use crate::services::{CertificationService, TaskHistoryService, WorkflowService};
+use crate::services::delegation::Delegation;
+use crate::services::tools::{ToolCall, ToolPermission, ToolService};
+use crate::services::knowledge::KnowledgeService;
+use crate::services::attachments::AttachmentService;
+use crate::services::artifacts::{ArtifactRef, ArtifactService};
+use crate::services::translation::{Translation, TranslationService, INCLUDE_ORIGINAL_KEY};
+use crate::services::redaction::RedactionService;
+use crate::services::provenance::{Provenance, ProvenanceSource};
+use crate::services::guardrails::{CritiqueRecord, GuardrailService};
+use crate::services::archive::ArchiveService;
+use crate::services::budget::{AgentBudget, BudgetService, BudgetStatus, BudgetUsage};
+use crate::services::dfinity_llm::QuantizedModel;
+use crate::services::code_sandbox::{CodeExecutionRequest, CodeSandbox, CODE_EXEC_TOOL};
+use crate::services::webhook::{Webhook, WebhookEvent, WebhookService};
+use crate::services::events::{AgentEventKind, EventService};
+use crate::services::experiments::{ExperimentService, ExperimentVariant};
+use crate::services::approvals::{ApprovalService, PendingTask, RiskRule};
+use crate::services::feedback::{FeedbackService, TaskFeedback};
+use crate::services::calibration::CalibrationService;
+use crate::services::sla::SlaService;
+use crate::services::sharding::ShardingService;
+use crate::services::degradation::{Degradation, DegradationService};
+use crate::services::signing::{ResultSignature, SigningService};
+use crate::services::trace::{TraceRecorder, TraceService, TraceStepKind};
+use crate::services::outage::OutageService;
+use crate::services::privacy::PrivacyService;
+use crate::services::marketplace::AgentOrigin;
+use crate::services::cost::CostService;
+use crate::services::context_window::ContextWindow;
+use crate::services::self_improvement::ConfigProposal;
+use crate::services::agent_env::{AgentEnvService, AgentEnvVar};
+use crate::services::skills::{AttachedSkill, SkillService, SkillStep, SKILL_KEY};
+use crate::services::prompt_templates::{PromptTemplateService, TemplateValue, PERSONA_TEMPLATE};
+use crate::infra::{Guards, Lane, Metrics};
 use std::collections::HashMap;
 use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use ic_stable_structures::StableBTreeMap;
+use crate::infra::stable::{memory, Cbor, Memory, AGENT_SNAPSHOT_MEMORY_ID};
+use std::cell::RefCell;
+
+thread_local! {
+    static AGENT_SNAPSHOT: RefCell<StableBTreeMap<String, Cbor<AutonomousAgent>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(AGENT_SNAPSHOT_MEMORY_ID)));
+}
 
 /// Service for creating autonomous agents from analyzed instructions
 pub struct AgentFactory;
 
 /// Autonomous agent instance with full configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AutonomousAgent {
     pub agent_id: String,
     pub user_id: String,
@@ -21,10 +66,86 @@ pub struct AutonomousAgent {
     pub last_active: u64,
     pub memory: HashMap<String, Vec<u8>>,
     pub performance_metrics: AgentPerformanceMetrics,
+    #[serde(default)]
+    pub recent_tasks: Vec<TaskSample>,
+    #[serde(default)]
+    pub member_ids: Vec<String>, // Member agents when this is a coordinator
+    #[serde(default)]
+    pub delegations: Vec<Delegation>,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    #[serde(default)]
+    pub pending_tool_calls: Vec<ToolCall>, // Awaiting owner approval
+    #[serde(default)]
+    pub budget: AgentBudget,
+    #[serde(default)]
+    pub budget_usage: BudgetUsage,
+    #[serde(default)]
+    pub idle_flagged_at: Option<u64>, // Set by GC; cleared when the agent runs again
+    #[serde(default)]
+    pub pending_tasks: Vec<PendingTask>, // Held by a risk rule until the owner responds
+    #[serde(default)]
+    pub risk_rules: Vec<RiskRule>,
+    #[serde(default)]
+    pub feedback: Vec<TaskFeedback>, // Latest task ratings, oldest first
+    #[serde(default)]
+    pub origin: Option<AgentOrigin>, // Set when instantiated from a public listing
+    #[serde(default)]
+    pub skills: Vec<AttachedSkill>,
+    #[serde(default)]
+    pub config_proposals: Vec<ConfigProposal>, // Self-improvement proposals, oldest first
+    #[serde(default)]
+    pub env: Vec<AgentEnvVar>, // Sorted by name
+}
+
+/// Rolling window entry used for success rate and latency percentiles
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskSample {
+    pub success: bool,
+    pub latency_ms: u64,
+}
+
+/// Counts a task as in flight until dropped. ic-cdk drops pending futures
+/// when a callback traps, so the count is released on traps too.
+struct InFlightTask {
+    agent_id: String,
+}
+
+impl InFlightTask {
+    fn start(agent_id: &str) -> Self {
+        with_state_mut(|state| {
+            *state.tasks_in_flight.entry(agent_id.to_string()).or_insert(0) += 1;
+        });
+        CertificationService::mark_dirty();
+        Self { agent_id: agent_id.to_string() }
+    }
+}
+
+impl Drop for InFlightTask {
+    fn drop(&mut self) {
+        with_state_mut(|state| {
+            if let Some(count) = state.tasks_in_flight.get_mut(&self.agent_id) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    state.tasks_in_flight.remove(&self.agent_id);
+                }
+            }
+        });
+        CertificationService::mark_dirty();
+    }
 }
 
+const PERFORMANCE_WINDOW: usize = 50;
+// Task context key restricting retrieval to one knowledge namespace
+pub const KNOWLEDGE_NAMESPACE_KEY: &str = "knowledge_namespace";
+// Task context key; "true" adds markdown segments of the result to it
+pub const STRUCTURED_OUTPUT_KEY: &str = "structured_output";
+const MAX_CONSECUTIVE_FAILURES: u32 = 5;
+// Pro tier limit until quotas come from the economics canister
+pub const MAX_AGENTS_PER_USER: usize = 25;
+
 /// Agent status tracking
-#[derive(Debug, Clone, CandidType)]
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub enum AgentStatus {
     Creating,       // Agent is being initialized
     Ready,          // Agent is ready to receive tasks
@@ -32,16 +153,34 @@ pub enum AgentStatus {
     Paused,         // Agent is paused by user
     Completed,      // Agent has completed its task
     Error(String),  // Agent encountered an error
+    Suspended(SuspendReason), // Agent refuses tasks until the reason clears
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum SuspendReason {
+    BudgetExceeded, // Lifted once the daily counters reset or the owner raises the budget
 }
 
 /// Performance metrics for agent monitoring
-#[derive(Debug, Clone, Default, CandidType)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, CandidType)]
 pub struct AgentPerformanceMetrics {
     pub tasks_completed: u32,
     pub total_tokens_used: u64,
     pub average_response_time_ms: f64,
     pub success_rate: f32,
     pub last_task_timestamp: u64,
+    #[serde(default)]
+    pub tasks_timed_out: u32,
+    #[serde(default)]
+    pub p95_response_time_ms: u64,
+    #[serde(default)]
+    pub consecutive_failures: u32,
+    #[serde(default)]
+    pub health_score: f32,
+    #[serde(default)]
+    pub feedback_count: u32,
+    #[serde(default)]
+    pub average_rating: f32, // Over the retained ratings; 0 before any
 }
 
 impl AgentFactory {
@@ -50,7 +189,8 @@ impl AgentFactory {
         user_id: String,
         instruction: UserInstruction,
         analysis: AnalyzedInstruction,
-    ) -> Result<AutonomousAgent, String> {
+    ) -> Result<AutonomousAgent, ApiError> {
+        Guards::check_memory_limits()?;
         // Validate user subscription and quotas
         Self::validate_user_quotas(&user_id, &instruction.subscription_tier).await?;
 
@@ -69,11 +209,29 @@ impl AgentFactory {
             config,
             model_binding: None,
             status: AgentStatus::Creating,
-            created_at: ic_cdk::api::time(),
-            last_active: ic_cdk::api::time(),
+            created_at: crate::infra::clock::time(),
+            last_active: crate::infra::clock::time(),
             memory: HashMap::new(),
             performance_metrics: AgentPerformanceMetrics::default(),
+            recent_tasks: Vec::new(),
+            member_ids: Vec::new(),
+            delegations: Vec::new(),
+            webhooks: Vec::new(),
+            pending_tool_calls: Vec::new(),
+            budget: AgentBudget::default(),
+            budget_usage: BudgetUsage::default(),
+            idle_flagged_at: None,
+            pending_tasks: Vec::new(),
+            risk_rules: Vec::new(),
+            feedback: Vec::new(),
+            origin: None,
+            skills: Vec::new(),
+            config_proposals: Vec::new(),
+            env: Vec::new(),
         };
+        if let Some(skills) = agent.instruction.skills.clone() {
+            agent.skills = SkillService::resolve_for_new_agent(&agent, &skills).await?;
+        }
 
         // Bind to appropriate NOVAQ model
         agent.model_binding = Self::bind_novaq_model(&agent).await?;
@@ -83,7 +241,89 @@ impl AgentFactory {
 
         // Store agent in state
         Self::store_agent(agent.clone()).await?;
+        EventService::publish(Some(&agent.agent_id), &agent.user_id, AgentEventKind::AgentCreated {
+            agent_type: format!("{:?}", agent.analysis.agent_configuration.agent_type),
+        });
+
+        Ok(agent)
+    }
+
+    /// Copy an agent's configuration into a new agent owned by `owner`. Metrics,
+    /// grants, webhooks and pending calls start empty; memory and knowledge
+    /// namespaces are copied only when `options` asks for them.
+    pub async fn clone_agent(agent_id: &str, owner: &str, options: CloneOptions) -> Result<AutonomousAgent, String> {
+        let source = Self::get_agent(agent_id).await?;
+        if !source.member_ids.is_empty() {
+            return Err("Coordinator agents cannot be cloned; clone their members instead".to_string());
+        }
+        Guards::check_memory_limits()?;
+        Self::validate_user_quotas(owner, &source.instruction.subscription_tier).await?;
+
+        let now = crate::infra::clock::time();
+        let mut agent = AutonomousAgent {
+            agent_id: Self::generate_agent_id(owner),
+            user_id: owner.to_string(),
+            status: AgentStatus::Ready,
+            created_at: now,
+            last_active: now,
+            memory: if options.copy_memory { source.memory.clone() } else { HashMap::new() },
+            performance_metrics: AgentPerformanceMetrics::default(),
+            recent_tasks: Vec::new(),
+            delegations: Vec::new(),
+            webhooks: Vec::new(),
+            pending_tool_calls: Vec::new(),
+            budget_usage: BudgetUsage::default(),
+            idle_flagged_at: None,
+            pending_tasks: Vec::new(),
+            feedback: Vec::new(),
+            config_proposals: Vec::new(),
+            // Secrets are sealed under the source owner's key
+            env: source.env.iter().filter(|v| !v.is_secret() || source.user_id == owner).cloned().collect(),
+            ..source.clone()
+        };
+        agent.instruction.user_id = owner.to_string();
+
+        let mut namespaces = options.knowledge_namespaces;
+        namespaces.sort();
+        namespaces.dedup();
+        for namespace in &namespaces {
+            KnowledgeService::copy_namespace(&source.agent_id, &agent.agent_id, namespace)?;
+        }
+
+        Self::store_agent(agent.clone()).await?;
+        Metrics::increment_counter("agents_cloned_total");
+        EventService::publish(Some(&agent.agent_id), &agent.user_id, AgentEventKind::AgentCreated {
+            agent_type: format!("{:?}", agent.analysis.agent_configuration.agent_type),
+        });
+        Ok(agent)
+    }
+
+    /// Create an agent for `owner` from a published template, attributed to
+    /// the listing it came from
+    pub async fn instantiate_template(
+        template: AutonomousAgent,
+        owner: &str,
+        origin: AgentOrigin,
+    ) -> Result<AutonomousAgent, String> {
+        Guards::check_memory_limits()?;
+        Self::validate_user_quotas(owner, &template.instruction.subscription_tier).await?;
+
+        let now = crate::infra::clock::time();
+        let mut agent = AutonomousAgent {
+            agent_id: Self::generate_agent_id(owner),
+            user_id: owner.to_string(),
+            status: AgentStatus::Ready,
+            created_at: now,
+            last_active: now,
+            origin: Some(origin),
+            ..template
+        };
+        agent.instruction.user_id = owner.to_string();
 
+        Self::store_agent(agent.clone()).await?;
+        EventService::publish(Some(&agent.agent_id), &agent.user_id, AgentEventKind::AgentCreated {
+            agent_type: format!("{:?}", agent.analysis.agent_configuration.agent_type),
+        });
         Ok(agent)
     }
 
@@ -132,55 +372,472 @@ impl AgentFactory {
             agents.push(agent);
         }
 
+        // The coordinator decomposes tasks across the members and leads the team
+        let mut coordinator_analysis = analysis.clone();
+        coordinator_analysis.agent_configuration.agent_type = AgentType::Coordinator;
+        let mut coordinator = Self::create_agent(user_id, instruction, coordinator_analysis).await?;
+        coordinator.member_ids = agents.iter().map(|a| a.agent_id.clone()).collect();
+        Self::update_agent(&coordinator).await?;
+
+        agents.insert(0, coordinator);
         Ok(agents)
     }
 
-    /// Execute a task with the autonomous agent
+    /// Execute a task with the autonomous agent. While the LLM canister is
+    /// unreachable the task is queued and a Deferred result is returned.
     pub async fn execute_task(
         agent_id: &str,
         task: AgentTask,
-    ) -> Result<AgentTaskResult, String> {
+    ) -> Result<AgentTaskResult, ApiError> {
+        Self::run_task(agent_id, task, false, true).await
+    }
+
+    /// Like execute_task, for callers that need the result now: an LLM
+    /// outage fails the call instead of deferring the task
+    pub async fn execute_task_now(agent_id: &str, task: AgentTask) -> Result<AgentTaskResult, ApiError> {
+        Self::run_task(agent_id, task, false, false).await
+    }
+
+    /// Run a task queued during an LLM outage; approval was settled before it was queued
+    pub async fn execute_deferred_task(agent_id: &str, task: AgentTask) -> Result<AgentTaskResult, ApiError> {
+        Self::run_task(agent_id, task, true, false).await
+    }
+
+    /// Run a recorded task again with `variant`'s model, prompt or decode
+    /// params, for comparison with the original. Only budget usage is
+    /// recorded; metrics, history, traces and experiments are left alone.
+    pub async fn rerun_task(agent_id: &str, task: &AgentTask, variant: &ExperimentVariant) -> Result<AgentTaskResult, String> {
         let mut agent = Self::get_agent(agent_id).await?;
+        let started_at = crate::infra::clock::time();
+        if let Err(e) = BudgetService::check(&mut agent, started_at) {
+            Self::update_agent(&agent).await?;
+            return Err(e);
+        }
+        let instructions_before = ic_cdk::api::performance_counter(1);
+        let result = Self::run_inference_task(&agent, task, Some(variant), None, &mut TraceRecorder::new()).await;
+        // Only the usage is written back; the rest may have changed during the rerun
+        let mut agent = Self::find_agent(agent_id).unwrap_or(agent);
+        BudgetService::record(
+            &mut agent,
+            result.tokens_used,
+            ic_cdk::api::performance_counter(1).saturating_sub(instructions_before),
+            crate::infra::clock::time(),
+        );
+        with_state_mut(|state| {
+            if let Some(stored) = state.agents.get_mut(agent_id) {
+                stored.budget_usage = agent.budget_usage.clone();
+            }
+        });
+        Ok(result)
+    }
+
+    /// Run a task the owner approved through respond_to_approval
+    pub async fn execute_approved_task(agent_id: &str, task: AgentTask) -> Result<AgentTaskResult, ApiError> {
+        Self::run_task(agent_id, task, true, true).await
+    }
+
+    /// Dry run: render the prompts, resolve the tools the task would call and
+    /// estimate its tokens without calling the LLM or any tool. Nothing is
+    /// recorded against the agent's budget, metrics or history.
+    pub async fn plan_task(agent_id: &str, task: AgentTask) -> Result<AgentTaskResult, ApiError> {
+        let agent = Self::get_agent(agent_id).await.map_err(|message| ApiError::NotFound { message })?;
+        if let AgentStatus::Error(reason) = &agent.status {
+            return Err(Self::unhealthy(agent_id, reason));
+        }
+        let plan = match task.context.get(SKILL_KEY) {
+            Some(skill_id) => Self::plan_skill_task(&agent, skill_id, &task)?,
+            None => Self::plan_inference_task(&agent, &task).await?,
+        };
+        Metrics::increment_counter("tasks_dry_run_total");
+        Ok(AgentTaskResult {
+            task_id: task.task_id,
+            success: true,
+            result: String::new(),
+            tokens_used: 0,
+            execution_time_ms: 0,
+            error_message: None,
+            outcome: TaskOutcome::DryRun,
+            provenance: None,
+            critique: None,
+            degradation: None,
+            signature: None,
+            plan: Some(plan),
+            artifacts: Vec::new(),
+            segments: None,
+            translation: None,
+        })
+    }
+
+    /// Mirrors run_inference_task up to the LLM call
+    async fn plan_inference_task(agent: &AutonomousAgent, task: &AgentTask) -> Result<TaskPlan, String> {
+        let namespace = task.context.get(KNOWLEDGE_NAMESPACE_KEY).map(String::as_str);
+        let passages = KnowledgeService::retrieve(&agent.agent_id, namespace, &task.description, None).await;
+        let task_prompt = Self::task_prompt(agent, task).map_err(|e| format!("Prompt template failed: {}", e))?;
+        let task_prompt = match FeedbackService::prompt_guidance(agent) {
+            Some(guidance) => format!("{}\n\n{}", guidance, task_prompt),
+            None => task_prompt,
+        };
+        let model_id = ModelPoolService::route_preferring(None, &agent.analysis.model_requirements.recommended_models)
+            .ok()
+            .flatten();
+
+        let max_tokens = DecodeParams::default().max_tokens.unwrap_or(agent.config.max_tokens);
+        let degradation = DegradationService::preview(agent, max_tokens, crate::infra::clock::time());
+        let (prompt, _) = AttachmentService::attach(
+            agent,
+            task,
+            KnowledgeService::augment_prompt(&task_prompt, &passages),
+            model_id.as_deref(),
+            Some(degradation.as_ref().map_or(max_tokens, |d| d.max_tokens)),
+        )
+        .map_err(|e| format!("Attachment failed: {}", e))?;
+        let mut max_completion_tokens = degradation.as_ref().map_or(max_tokens, |d| d.max_tokens) as u64;
+        // The critique pass reads the answer and may rewrite it
+        if agent.analysis.agent_configuration.self_critique {
+            max_completion_tokens *= 2;
+        }
+        if TranslationService::target_language(agent).is_some() {
+            max_completion_tokens += degradation.as_ref().map_or(max_tokens, |d| d.max_tokens) as u64;
+        }
+        let mut tool_calls = Vec::new();
+        if matches!(agent.analysis.agent_configuration.agent_type, AgentType::CodeAssistant)
+            && DegradationService::allows_tool(agent, degradation.as_ref(), CODE_EXEC_TOOL)
+        {
+            tool_calls.push(Self::plan_tool_call(agent, CODE_EXEC_TOOL, None));
+        }
+        Ok(TaskPlan {
+            skill_id: None,
+            model_id,
+            estimated_prompt_tokens: ContextWindow::estimate_tokens(&prompt),
+            prompts: vec![prompt],
+            knowledge_passages: passages.len() as u32,
+            tool_calls,
+            max_completion_tokens,
+            approval_rule: ApprovalService::task_rule(agent, task),
+            degradation,
+        })
+    }
+
+    /// Mirrors run_skill_task; a step's input from the step before shows as a placeholder
+    fn plan_skill_task(agent: &AutonomousAgent, skill_id: &str, task: &AgentTask) -> Result<TaskPlan, String> {
+        let skill = SkillService::attached(agent, skill_id)?;
+        let mut prompts = Vec::new();
+        let mut tool_calls = Vec::new();
+        let mut previous = String::new();
+        for (index, step) in skill.definition.steps.iter().enumerate() {
+            match step {
+                SkillStep::Prompt { template } => {
+                    prompts.push(SkillService::render(template, &task.description, &previous));
+                }
+                SkillStep::Tool { tool, arguments } => {
+                    let arguments = SkillService::render(arguments, &task.description, &previous);
+                    tool_calls.push(Self::plan_tool_call(agent, tool, Some(arguments)));
+                }
+            }
+            previous = format!("<output of step {}>", index + 1);
+        }
+        Ok(TaskPlan {
+            skill_id: Some(skill_id.to_string()),
+            model_id: None,
+            estimated_prompt_tokens: prompts.iter().map(|p| ContextWindow::estimate_tokens(p)).sum(),
+            max_completion_tokens: prompts.len() as u64 * DecodeParams::default().max_tokens.unwrap_or(agent.config.max_tokens) as u64,
+            prompts,
+            knowledge_passages: 0,
+            tool_calls,
+            approval_rule: ApprovalService::task_rule(agent, task),
+            degradation: None,
+        })
+    }
+
+    fn plan_tool_call(agent: &AutonomousAgent, tool: &str, arguments: Option<String>) -> PlannedToolCall {
+        let (requires_approval, denied) = match ToolService::permission(agent, tool) {
+            ToolPermission::Allowed => {
+                let rule = arguments.as_deref().and_then(|a| ApprovalService::tool_call_rule(agent, tool, a));
+                (rule.is_some(), None)
+            }
+            ToolPermission::RequiresApproval => (true, None),
+            ToolPermission::Denied(reason) => (false, Some(reason)),
+        };
+        PlannedToolCall { tool: tool.to_string(), arguments, requires_approval, denied }
+    }
+
+    async fn run_task(agent_id: &str, task: AgentTask, approved: bool, deferrable: bool) -> Result<AgentTaskResult, ApiError> {
+        let mut agent = Self::get_agent(agent_id).await.map_err(|message| ApiError::NotFound { message })?;
+        if let AgentStatus::Error(reason) = &agent.status {
+            return Err(Self::unhealthy(agent_id, reason));
+        }
+
+        let started_at = crate::infra::clock::time();
+        if let Err(message) = BudgetService::check(&mut agent, started_at) {
+            Self::update_agent(&agent).await?;
+            return Err(ApiError::QuotaExceeded { message });
+        }
+        if let Some(deadline) = task.deadline {
+            if started_at >= deadline {
+                return Err(ApiError::InvalidArgument { message: format!("Task {} deadline has already passed", task.task_id) });
+            }
+        }
+        if !approved {
+            if let Some(request_id) = ApprovalService::gate_task(&mut agent, &task)? {
+                Self::update_agent(&agent).await?;
+                return Ok(AgentTaskResult {
+                    task_id: task.task_id,
+                    success: false,
+                    result: String::new(),
+                    tokens_used: 0,
+                    execution_time_ms: 0,
+                    error_message: Some(format!("Awaiting owner approval: {}", request_id)),
+                    outcome: TaskOutcome::PendingApproval,
+                    provenance: None,
+                    critique: None,
+                    degradation: None,
+                    signature: None,
+                    plan: None,
+                    artifacts: Vec::new(),
+                    segments: None,
+                    translation: None,
+                });
+            }
+        }
+        if OutageService::llm_unavailable() {
+            if !deferrable || task.deadline.is_some() {
+                return Err(OutageService::unavailable("the task was not run"));
+            }
+            let position = OutageService::defer(agent_id, &task)?;
+            return Ok(AgentTaskResult {
+                task_id: task.task_id,
+                success: false,
+                result: String::new(),
+                tokens_used: 0,
+                execution_time_ms: 0,
+                error_message: Some(format!(
+                    "LLM unavailable; task queued at position {} and retried in about {} seconds",
+                    position,
+                    OutageService::retry_after_secs()
+                )),
+                outcome: TaskOutcome::Deferred,
+                provenance: None,
+                critique: None,
+                degradation: None,
+                signature: None,
+                plan: None,
+                artifacts: Vec::new(),
+                segments: None,
+                translation: None,
+            });
+        }
+        let deadline = Self::effective_deadline(&agent, &task, started_at);
+        let _in_flight = InFlightTask::start(agent_id);
+        let instructions_before = ic_cdk::api::performance_counter(1);
 
         // Update agent status
         agent.status = AgentStatus::Active;
-        agent.last_active = ic_cdk::api::time();
-        Self::update_agent(&agent).await?;
+        agent.last_active = crate::infra::clock::time();
+        agent.idle_flagged_at = None;
+        Self::store_task_state(&agent);
 
         // Execute the task based on agent type and capabilities
-        let result = match agent.analysis.agent_configuration.agent_type {
-            AgentType::CodeAssistant => Self::execute_code_task(&agent, &task).await?,
-            AgentType::DataAnalyst => Self::execute_data_task(&agent, &task).await?,
-            AgentType::ContentCreator => Self::execute_content_task(&agent, &task).await?,
-            AgentType::ProblemSolver => Self::execute_problem_task(&agent, &task).await?,
-            AgentType::Researcher => Self::execute_research_task(&agent, &task).await?,
-            AgentType::Planner => Self::execute_planning_task(&agent, &task).await?,
-            _ => Self::execute_general_task(&agent, &task).await?,
-        };
+        let mut result = Self::execute_inference_task(&agent, &task).await;
+
+        // A call already sent cannot be cancelled on the IC, so an overrun
+        // result is discarded and reported as timed out instead
+        let finished_at = crate::infra::clock::time();
+        let timed_out = finished_at > deadline;
+        if timed_out {
+            result = AgentTaskResult {
+                success: false,
+                result: String::new(),
+                error_message: Some(format!(
+                    "Task exceeded its deadline by {} ms",
+                    (finished_at - deadline) / 1_000_000
+                )),
+                outcome: TaskOutcome::TimedOut,
+                provenance: None,
+                ..result
+            };
+            Metrics::increment_counter("tasks_timed_out_total");
+        }
 
-        // Update performance metrics
-        agent.performance_metrics.tasks_completed += 1;
-        agent.performance_metrics.total_tokens_used += result.tokens_used;
-        agent.performance_metrics.last_task_timestamp = ic_cdk::api::time();
-        agent.status = AgentStatus::Ready;
+        Self::record_run_metrics(agent_id, &result, timed_out);
+        // Grants, budget and config may have changed while the task ran; only
+        // the fields a run owns are written back, by `store_task_state`
+        let mut agent = Self::find_agent(agent_id).unwrap_or(agent);
+        CalibrationService::record_outcome(&agent, &result);
+        SlaService::record(&agent, &result);
+        let cycles = BudgetService::record(
+            &mut agent,
+            result.tokens_used,
+            ic_cdk::api::performance_counter(1).saturating_sub(instructions_before),
+            finished_at,
+        );
+        CostService::record(&agent, &task, &result, cycles);
+
+        // A status set by the owner or the budget while the task ran is kept
+        if matches!(agent.status, AgentStatus::Active | AgentStatus::Ready) {
+            agent.status = if agent.performance_metrics.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+                Metrics::increment_counter("agents_unhealthy_total");
+                AgentStatus::Error(format!(
+                    "{} consecutive task failures",
+                    agent.performance_metrics.consecutive_failures
+                ))
+            } else {
+                AgentStatus::Ready
+            };
+        }
+        // Stop here rather than at the next task so a workflow's next step never starts
+        if let (AgentStatus::Ready, Some(reason)) = (&agent.status, BudgetService::over_budget(&agent, finished_at)) {
+            BudgetService::suspend(&mut agent, &reason);
+        }
 
-        Self::update_agent(&agent).await?;
+        Self::store_task_state(&agent);
+        result.signature = SigningService::sign(agent_id, &task, &result).await;
+        ArtifactService::offload(agent_id, &task, &mut result);
+        if result.artifacts.is_empty() && task.context.get(STRUCTURED_OUTPUT_KEY).is_some_and(|v| v == "true") {
+            result.segments = Some(parse_segments(&result.result));
+        }
+        TaskHistoryService::record(&agent, &task, &result);
+        Self::record_in_thread(&agent, &task, &result).await;
+        EventService::publish(Some(agent_id), &agent.user_id, AgentEventKind::TaskCompleted {
+            task_id: result.task_id.clone(),
+            success: result.success,
+        });
+
+        let event = if result.success { WebhookEvent::TaskCompleted } else { WebhookEvent::TaskFailed };
+        WebhookService::notify(agent_id, event, serde_json::json!({
+            "task_id": result.task_id,
+            "outcome": format!("{:?}", result.outcome),
+            "tokens_used": result.tokens_used,
+            "execution_time_ms": result.execution_time_ms,
+            "error": result.error_message,
+        }));
+        if let AgentStatus::Error(reason) = &agent.status {
+            WebhookService::notify(agent_id, WebhookEvent::AgentError, serde_json::json!({ "reason": reason }));
+        }
 
         Ok(result)
     }
 
+    fn unhealthy(agent_id: &str, reason: &str) -> ApiError {
+        ApiError::Failed { message: format!("Agent {} is unhealthy: {}. Reset it before running tasks", agent_id, reason) }
+    }
+
+    /// Look up an agent on behalf of `caller`, failing unless the caller owns it
+    pub fn authorize(agent_id: &str, caller: &str) -> Result<AutonomousAgent, String> {
+        let agent = Self::find_agent(agent_id)?;
+        if agent.user_id != caller {
+            return Err(format!("Not authorized to access agent {}", agent_id));
+        }
+        Ok(agent)
+    }
+
+    /// Hand an agent to another principal. A coordinator's members move with it.
+    pub async fn transfer_ownership(agent_id: &str, caller: &str, new_owner: &str) -> Result<(), String> {
+        let agent = Self::authorize(agent_id, caller)?;
+        if new_owner == caller {
+            return Err("Agent is already owned by the caller".to_string());
+        }
+        Self::validate_user_quotas(new_owner, &agent.instruction.subscription_tier).await?;
+
+        let mut agent_ids = vec![agent.agent_id.clone()];
+        agent_ids.extend(agent.member_ids.iter().cloned());
+
+        with_state_mut(|state| {
+            for id in &agent_ids {
+                if let Some(agent) = state.agents.get_mut(id) {
+                    if agent.user_id == caller {
+                        agent.user_id = new_owner.to_string();
+                        agent.instruction.user_id = new_owner.to_string();
+                        // Grants and endpoints were set up by the previous owner
+                        agent.delegations.clear();
+                        agent.webhooks.clear();
+                        agent.pending_tool_calls.clear();
+                        // Sealed under the previous owner's key
+                        agent.env.retain(|v| !v.is_secret());
+                    }
+                }
+            }
+        });
+        CertificationService::mark_dirty();
+        if let Ok(owner) = candid::Principal::from_text(new_owner) {
+            // Threads are resealed for the new owner; without both keys their messages are dropped
+            for user in [candid::Principal::from_text(caller).ok(), Some(owner)].into_iter().flatten() {
+                if let Err(e) = PrivacyService::ensure_key(user).await {
+                    ic_cdk::println!("Encryption key of {} unavailable during transfer: {}", user, e);
+                }
+            }
+            let llm = llm_service();
+            for id in &agent_ids {
+                llm.transfer_agent_thread(id, owner);
+            }
+        }
+        Metrics::increment_counter("agent_ownership_transfers_total");
+        Ok(())
+    }
+
+    /// Clear an unhealthy agent's failure streak and make it ready again
+    pub async fn reset_agent_health(agent_id: &str) -> Result<(), String> {
+        let mut agent = Self::get_agent(agent_id).await?;
+        agent.performance_metrics.consecutive_failures = 0;
+        agent.status = AgentStatus::Ready;
+        Self::update_agent(&agent).await
+    }
+
+    /// Replace the agent's budget. Lifts a budget suspension the new limits allow.
+    pub async fn set_budget(agent_id: &str, budget: AgentBudget) -> Result<(), String> {
+        let mut agent = Self::get_agent(agent_id).await?;
+        agent.budget = budget;
+        let now = crate::infra::clock::time();
+        if matches!(agent.status, AgentStatus::Suspended(SuspendReason::BudgetExceeded))
+            && BudgetService::over_budget(&agent, now).is_none()
+        {
+            agent.status = AgentStatus::Ready;
+        }
+        Self::update_agent(&agent).await
+    }
+
+    pub async fn get_budget(agent_id: &str) -> Result<BudgetStatus, String> {
+        let agent = Self::get_agent(agent_id).await?;
+        Ok(BudgetService::status(&agent, crate::infra::clock::time()))
+    }
+
+    /// Turn the guardrail self-critique pass on or off for the agent's tasks
+    pub async fn set_self_critique(agent_id: &str, enabled: bool) -> Result<(), String> {
+        let mut agent = Self::get_agent(agent_id).await?;
+        agent.analysis.agent_configuration.self_critique = enabled;
+        Self::update_agent(&agent).await
+    }
+
+    /// Quote recent commented task ratings in the agent's later task prompts
+    pub async fn set_feedback_learning(agent_id: &str, enabled: bool) -> Result<(), String> {
+        let mut agent = Self::get_agent(agent_id).await?;
+        agent.analysis.agent_configuration.learn_from_feedback = enabled;
+        Self::update_agent(&agent).await
+    }
+
+    /// Let the agent propose configuration changes from its own task history
+    pub async fn set_self_improvement(agent_id: &str, enabled: bool) -> Result<(), String> {
+        let mut agent = Self::get_agent(agent_id).await?;
+        agent.analysis.agent_configuration.self_improvement = enabled;
+        Self::update_agent(&agent).await
+    }
+
     /// Get agent status and performance
     pub async fn get_agent_status(agent_id: &str) -> Result<AgentStatusInfo, String> {
         let agent = Self::get_agent(agent_id).await?;
+        Ok(Self::status_info(&agent, &WorkflowService::queued_steps()))
+    }
 
-        Ok(AgentStatusInfo {
+    pub fn status_info(agent: &AutonomousAgent, queued_steps: &HashMap<String, u32>) -> AgentStatusInfo {
+        AgentStatusInfo {
             agent_id: agent.agent_id.clone(),
             status: agent.status.clone(),
             performance_metrics: agent.performance_metrics.clone(),
             model_bound: agent.model_binding.is_some(),
             created_at: agent.created_at,
             last_active: agent.last_active,
-        })
+            tasks_in_flight: with_state(|state| state.tasks_in_flight.get(&agent.agent_id).copied().unwrap_or(0)),
+            tasks_queued: queued_steps.get(&agent.agent_id).copied().unwrap_or(0),
+        }
     }
 
     /// List all agents for a user
@@ -195,14 +852,48 @@ impl AgentFactory {
                     status: agent.status.clone(),
                     created_at: agent.created_at,
                     last_active: agent.last_active,
+                    origin: agent.origin.clone(),
                 })
                 .collect::<Vec<_>>()
         }))
     }
 
+    /// Copy all agents into stable memory ahead of an upgrade
+    pub fn save_to_stable() {
+        let agents: Vec<AutonomousAgent> = with_state(|state| state.agents.values().cloned().collect());
+        AGENT_SNAPSHOT.with(|snapshot| {
+            let mut snapshot = snapshot.borrow_mut();
+            let stale: Vec<String> = snapshot.iter().map(|(id, _)| id).collect();
+            for id in stale {
+                snapshot.remove(&id);
+            }
+            for agent in agents {
+                snapshot.insert(agent.agent_id.clone(), Cbor(agent));
+            }
+        });
+    }
+
+    /// Restore agents saved by `save_to_stable` and release the snapshot
+    pub fn restore_from_stable() {
+        let agents: Vec<AutonomousAgent> = AGENT_SNAPSHOT.with(|snapshot| {
+            let mut snapshot = snapshot.borrow_mut();
+            let entries: Vec<(String, AutonomousAgent)> = snapshot.iter().map(|(id, agent)| (id, agent.0)).collect();
+            for (id, _) in &entries {
+                snapshot.remove(id);
+            }
+            entries.into_iter().map(|(_, agent)| agent).collect()
+        });
+        with_state_mut(|state| {
+            for agent in agents {
+                state.agents.insert(agent.agent_id.clone(), agent);
+            }
+        });
+        CertificationService::mark_dirty();
+    }
+
     // Private helper methods
 
-    async fn validate_user_quotas(user_id: &str, _tier: &SubscriptionTier) -> Result<(), String> {
+    pub(crate) async fn validate_user_quotas(user_id: &str, _tier: &SubscriptionTier) -> Result<(), ApiError> {
         // Call the economics canister to validate subscription quotas
         // This will be implemented when we integrate with the economics canister
         // For now, we'll use a simple validation
@@ -215,22 +906,63 @@ impl AgentFactory {
         // let subscription = econ_canister::get_user_subscription(user_id).await?;
         
         // For now, use a default limit
-        let max_agents = 25; // Default to Pro tier limit
+        let max_agents = MAX_AGENTS_PER_USER;
         
-        if user_agents.len() >= max_agents {
-            return Err(format!("Agent limit reached. Maximum: {}", max_agents));
+        // Recently archived agents still hold their slot, and agents placed
+        // on other shards count too
+        let archived = ArchiveService::count_in_grace_period(user_id, crate::infra::clock::time());
+        let routed = ShardingService::routed_agent_count(user_id);
+
+        if user_agents.len() + archived + routed >= max_agents {
+            WebhookService::notify_user(user_id, WebhookEvent::QuotaExceeded, serde_json::json!({
+                "quota": "agents",
+                "limit": max_agents,
+            }));
+            return Err(ApiError::QuotaExceeded { message: format!("Agent limit reached. Maximum: {}", max_agents) });
         }
 
         Ok(())
     }
 
+    /// Earliest of the task's own deadline and the tier's maximum execution time
+    fn effective_deadline(agent: &AutonomousAgent, task: &AgentTask, started_at: u64) -> u64 {
+        let max_execution_seconds: u64 = match agent.instruction.subscription_tier {
+            SubscriptionTier::Basic => 60,
+            SubscriptionTier::Pro => 180,
+            SubscriptionTier::Enterprise => 600,
+        };
+        let tier_deadline = started_at + max_execution_seconds * 1_000_000_000;
+        task.deadline.map_or(tier_deadline, |deadline| deadline.min(tier_deadline))
+    }
+
     fn generate_agent_id(user_id: &str) -> String {
-        let timestamp = ic_cdk::api::time();
-        format!("agent-{}-{}", user_id, timestamp)
+        let timestamp = crate::infra::clock::time();
+        // Agents created in one round (e.g. a coordinator and its members) share the timestamp
+        let seq = with_state_mut(|state| {
+            state.agent_seq += 1;
+            state.agent_seq
+        });
+        format!("agent-{}-{}-{}", user_id, timestamp, seq)
+    }
+
+    /// Task id that stays unique within a round, for tasks whose id must not repeat
+    pub fn next_task_id() -> String {
+        let timestamp = crate::infra::clock::time();
+        let seq = with_state_mut(|state| {
+            state.task_seq += 1;
+            state.task_seq
+        });
+        format!("task-{}-{}", timestamp, seq)
     }
 
     fn create_agent_config(analysis: &AnalyzedInstruction) -> Result<AgentConfig, String> {
-        let model_repo_id = with_state(|state| state.config.model_repo_canister_id.clone());
+        let (model_repo_id, mirrors, economics_id) = with_state(|state| {
+            (
+                state.config.model_repo_canister_id.clone(),
+                state.config.model_repo_mirrors.clone(),
+                state.config.economics_canister_id.clone(),
+            )
+        });
         
         Ok(AgentConfig {
             warm_set_target: 0.7,
@@ -243,6 +975,14 @@ impl AgentFactory {
             },
             ttl_seconds: 7200, // 2 hours
             model_repo_canister_id: model_repo_id,
+            model_repo_mirrors: mirrors,
+            economics_canister_id: economics_id,
+            context_policy: None,
+            // The layer cache is canister-wide and follows the canister config
+            cache_compression: None,
+            model_cache_compression: None,
+            model_pool_budget_bytes: None,
+            batching: None,
         })
     }
 
@@ -252,14 +992,9 @@ impl AgentFactory {
             .first()
             .ok_or("No recommended models available")?;
 
-        // Try to bind to the recommended model
-        match BindingService::bind_model(recommended_model.clone()).await {
-            Ok(_) => {
-                // Get the binding details
-                Ok(with_state(|state| {
-                    state.binding.clone()
-                }))
-            }
+        // Serve the recommended model, alongside the bound model if there is one
+        match ModelPoolService::serve(recommended_model).await {
+            Ok(binding) => Ok(Some(binding)),
             Err(_) => {
                 // Fallback to any available NOVAQ model
                 let fallback_models = vec![
@@ -269,8 +1004,8 @@ impl AgentFactory {
                 ];
 
                 for model in fallback_models {
-                    if BindingService::bind_model(model.clone()).await.is_ok() {
-                        return Ok(with_state(|state| state.binding.clone()));
+                    if let Ok(binding) = ModelPoolService::serve(&model).await {
+                        return Ok(Some(binding));
                     }
                 }
 
@@ -283,10 +1018,15 @@ impl AgentFactory {
         with_state_mut(|state| {
             state.agents.insert(agent.agent_id.clone(), agent);
         });
+        CertificationService::mark_dirty();
         Ok(())
     }
 
     async fn get_agent(agent_id: &str) -> Result<AutonomousAgent, String> {
+        Self::find_agent(agent_id)
+    }
+
+    pub fn find_agent(agent_id: &str) -> Result<AutonomousAgent, String> {
         with_state(|state| {
             state.agents.get(agent_id)
                 .cloned()
@@ -294,10 +1034,42 @@ impl AgentFactory {
         })
     }
 
+    /// Add a finished run to the stored agent's metrics in place, so tasks
+    /// finishing concurrently on one agent all count towards its failure streak
+    fn record_run_metrics(agent_id: &str, result: &AgentTaskResult, timed_out: bool) {
+        with_state_mut(|state| {
+            let Some(agent) = state.agents.get_mut(agent_id) else { return };
+            if timed_out {
+                agent.performance_metrics.tasks_timed_out += 1;
+            }
+            agent.performance_metrics.tasks_completed += 1;
+            agent.performance_metrics.total_tokens_used += result.tokens_used;
+            agent.performance_metrics.last_task_timestamp = crate::infra::clock::time();
+            Self::record_task_outcome(agent, result);
+        });
+    }
+
+    /// Copy the status and usage a task run owns onto the stored agent; its
+    /// metrics go through `record_run_metrics`. Everything else, such as
+    /// delegations, budget, env, tool access, risk rules, webhooks and skills,
+    /// keeps whatever was stored. A deleted agent stays deleted.
+    fn store_task_state(agent: &AutonomousAgent) {
+        with_state_mut(|state| {
+            if let Some(stored) = state.agents.get_mut(&agent.agent_id) {
+                stored.status = agent.status.clone();
+                stored.last_active = agent.last_active;
+                stored.idle_flagged_at = agent.idle_flagged_at;
+                stored.budget_usage = agent.budget_usage.clone();
+            }
+        });
+        CertificationService::mark_dirty();
+    }
+
     async fn update_agent(agent: &AutonomousAgent) -> Result<(), String> {
         with_state_mut(|state| {
             state.agents.insert(agent.agent_id.clone(), agent.clone());
         });
+        CertificationService::mark_dirty();
         Ok(())
     }
 
@@ -321,6 +1093,7 @@ impl AgentFactory {
             subscription_tier: original.subscription_tier.clone(),
             context: original.context.clone(),
             preferences: original.preferences.clone(),
+            skills: None, // Members get the tools of their capability, which the skills may not be bound to
         }
     }
 
@@ -338,188 +1111,507 @@ impl AgentFactory {
         specialized
     }
 
-    // Task execution methods for different agent types
-    async fn execute_code_task(_agent: &AutonomousAgent, task: &AgentTask) -> Result<AgentTaskResult, String> {
-        // Use the agent's model binding to generate code
-        let prompt = format!(
-            "You are a specialized code assistant. {}",
-            task.description
-        );
+    // Task execution for the different agent types
 
-        // Execute inference using the bound model
-        let inference_request = crate::domain::InferenceRequest {
-            seed: task.task_id.parse().unwrap_or(0),
-            prompt,
-            decode_params: crate::domain::DecodeParams::default(),
-            msg_id: task.task_id.clone(),
-        };
-
-        let response = crate::services::InferenceService::process_inference(inference_request).await?;
-
-        Ok(AgentTaskResult {
-            task_id: task.task_id.clone(),
-            success: true,
-            result: response.generated_text,
-            tokens_used: response.tokens.len() as u64,
-            execution_time_ms: response.inference_time_ms,
-            error_message: None,
-        })
+    /// From the agent type's `task.*` template, as overridden by the owner
+    fn task_prompt(agent: &AutonomousAgent, task: &AgentTask) -> Result<String, String> {
+        PromptTemplateService::task_prompt(
+            &agent.analysis.agent_configuration.agent_type,
+            Self::owner(agent),
+            &task.description,
+            &AgentEnvService::template_values(agent),
+        )
     }
 
-    async fn execute_data_task(_agent: &AutonomousAgent, task: &AgentTask) -> Result<AgentTaskResult, String> {
-        let prompt = format!(
-            "You are a data analyst. Analyze and provide insights for: {}",
-            task.description
-        );
-
-        let inference_request = crate::domain::InferenceRequest {
-            seed: task.task_id.parse().unwrap_or(0),
-            prompt,
-            decode_params: crate::domain::DecodeParams::default(),
-            msg_id: task.task_id.clone(),
-        };
-
-        let response = crate::services::InferenceService::process_inference(inference_request).await?;
-
-        Ok(AgentTaskResult {
-            task_id: task.task_id.clone(),
-            success: true,
-            result: response.generated_text,
-            tokens_used: response.tokens.len() as u64,
-            execution_time_ms: response.inference_time_ms,
-            error_message: None,
+    /// System prompt for the agent's thread with its owner
+    fn persona_prompt(agent: &AutonomousAgent) -> String {
+        let config = &agent.analysis.agent_configuration;
+        let rules = config.behavior_rules.iter().chain(&config.safety_constraints).cloned().collect();
+        let values = HashMap::from([
+            ("agent_type".to_string(), TemplateValue::Text(format!("{:?}", config.agent_type))),
+            ("communication_style".to_string(), TemplateValue::Text(format!("{:?}", config.communication_style))),
+            ("instruction".to_string(), TemplateValue::Text(agent.instruction.instruction_text.clone())),
+            ("rules".to_string(), TemplateValue::List(rules)),
+        ]);
+        // A broken override must not cut the agent off from its thread
+        let env = AgentEnvService::template_values(agent);
+        PromptTemplateService::render_named_with_env(PERSONA_TEMPLATE, Self::owner(agent), &values, &env).unwrap_or_else(|_| {
+            format!(
+                "You are a {:?} agent with a {:?} communication style, working for your owner on: {}",
+                config.agent_type, config.communication_style, agent.instruction.instruction_text
+            )
         })
     }
 
-    async fn execute_content_task(_agent: &AutonomousAgent, task: &AgentTask) -> Result<AgentTaskResult, String> {
-        let prompt = format!(
-            "You are a content creator. Create engaging content for: {}",
-            task.description
-        );
+    fn owner(agent: &AutonomousAgent) -> Option<candid::Principal> {
+        candid::Principal::from_text(&agent.user_id).ok()
+    }
 
-        let inference_request = crate::domain::InferenceRequest {
-            seed: task.task_id.parse().unwrap_or(0),
-            prompt,
-            decode_params: crate::domain::DecodeParams::default(),
-            msg_id: task.task_id.clone(),
+    /// Append the task and its outcome to the owner-visible agent thread
+    async fn record_in_thread(agent: &AutonomousAgent, task: &AgentTask, result: &AgentTaskResult) {
+        let Ok(owner) = candid::Principal::from_text(&agent.user_id) else {
+            return;
         };
+        if let Err(e) = PrivacyService::ensure_key(owner).await {
+            ic_cdk::println!("Task {} not recorded in the thread of {}: {}", result.task_id, agent.agent_id, e);
+            return;
+        }
+        let outcome = if result.success {
+            result.result.clone()
+        } else {
+            format!(
+                "Task {} {:?}: {}",
+                result.task_id,
+                result.outcome,
+                result.error_message.as_deref().unwrap_or("no details")
+            )
+        };
+        let description = RedactionService::redact(&owner, &task.description).await;
+        let outcome = RedactionService::redact(&owner, &outcome).await;
+        llm_service().record_agent_task(
+            &agent.agent_id,
+            owner,
+            Self::persona_prompt(agent),
+            &description,
+            outcome,
+            result.provenance.clone(),
+        );
+    }
 
-        let response = crate::services::InferenceService::process_inference(inference_request).await?;
-
-        Ok(AgentTaskResult {
-            task_id: task.task_id.clone(),
-            success: true,
-            result: response.generated_text,
-            tokens_used: response.tokens.len() as u64,
-            execution_time_ms: response.inference_time_ms,
-            error_message: None,
-        })
+    /// Run the task prompt through the LLM; failures become a failed result
+    /// rather than an error so they count against the agent's success rate.
+    /// Tasks in an experiment's traffic run with their variant's settings, and
+    /// tasks of an owner low on quota run degraded.
+    async fn execute_inference_task(agent: &AutonomousAgent, task: &AgentTask) -> AgentTaskResult {
+        if let Some(skill_id) = task.context.get(SKILL_KEY) {
+            let mut trace = TraceRecorder::new();
+            let result = Self::run_skill_task(agent, skill_id, task, &mut trace).await;
+            TraceService::save(&agent.agent_id, task, &result, trace);
+            return result;
+        }
+        let assignment = ExperimentService::assign(&agent.analysis.agent_configuration.agent_type, &task.task_id);
+        let variant = assignment.as_ref().map(|a| &a.variant);
+        let max_tokens = variant
+            .and_then(|v| v.decode_params.as_ref())
+            .unwrap_or(&DecodeParams::default())
+            .max_tokens
+            .unwrap_or(agent.config.max_tokens);
+        let degradation = DegradationService::plan(agent, max_tokens, crate::infra::clock::time());
+        let mut trace = TraceRecorder::new();
+        let mut result = Self::run_inference_task(agent, task, variant, degradation.as_ref(), &mut trace).await;
+        result.degradation = degradation;
+        TraceService::save(&agent.agent_id, task, &result, trace);
+        if let Some(assignment) = &assignment {
+            ExperimentService::record(assignment, result.success, result.execution_time_ms, result.tokens_used);
+        }
+        result
     }
 
-    async fn execute_problem_task(_agent: &AutonomousAgent, task: &AgentTask) -> Result<AgentTaskResult, String> {
-        let prompt = format!(
-            "You are a problem solver. Analyze and solve: {}",
-            task.description
+    async fn run_inference_task(
+        agent: &AutonomousAgent,
+        task: &AgentTask,
+        variant: Option<&ExperimentVariant>,
+        degradation: Option<&Degradation>,
+        trace: &mut TraceRecorder,
+    ) -> AgentTaskResult {
+        let namespace = task.context.get(KNOWLEDGE_NAMESPACE_KEY).map(String::as_str);
+        let retrieval_started_at = crate::infra::clock::time();
+        let passages = KnowledgeService::retrieve(&agent.agent_id, namespace, &task.description, None).await;
+        trace.record(
+            TraceStepKind::Retrieval,
+            retrieval_started_at,
+            &format!("{} passages from namespace {}", passages.len(), namespace.unwrap_or("default")),
         );
+        let started_at = crate::infra::clock::time();
+        // Otherwise the model the agent type recommends when the pool serves one
+        let model_id = variant.and_then(|v| v.model_id.clone()).or_else(|| {
+            ModelPoolService::route_preferring(None, &agent.analysis.model_requirements.recommended_models)
+                .ok()
+                .flatten()
+        });
+        let mut decode_params = variant.and_then(|v| v.decode_params.clone()).unwrap_or_default();
+        if let Some(degradation) = degradation {
+            decode_params.max_tokens = Some(degradation.max_tokens);
+        }
+        let task_prompt = match variant.and_then(|v| v.prompt_template.as_deref()) {
+            Some(template) => Ok(ExperimentService::render(template, &task.description)),
+            None => Self::task_prompt(agent, task),
+        };
+        let prompt = task_prompt.map_err(|e| format!("Prompt template failed: {}", e)).and_then(|task_prompt| {
+            let task_prompt = match FeedbackService::prompt_guidance(agent) {
+                Some(guidance) => format!("{}\n\n{}", guidance, task_prompt),
+                None => task_prompt,
+            };
+            AttachmentService::attach(
+                agent,
+                task,
+                KnowledgeService::augment_prompt(&task_prompt, &passages),
+                model_id.as_deref(),
+                Some(decode_params.max_tokens.unwrap_or(agent.config.max_tokens)),
+            )
+            .map_err(|e| format!("Attachment failed: {}", e))
+        });
+        let (prompt, attachment_sources) = match prompt {
+            Ok(attached) => attached,
+            Err(e) => {
+                trace.record(TraceStepKind::Prompt, started_at, "").error = Some(e.clone());
+                return AgentTaskResult {
+                    task_id: task.task_id.clone(),
+                    success: false,
+                    result: String::new(),
+                    tokens_used: 0,
+                    execution_time_ms: 0,
+                    error_message: Some(e),
+                    outcome: TaskOutcome::Failed,
+                    provenance: None,
+                    critique: None,
+                    degradation: None,
+                    signature: None,
+                    plan: None,
+                    artifacts: Vec::new(),
+                    segments: None,
+                    translation: None,
+                }
+            }
+        };
+        trace.record(TraceStepKind::Prompt, started_at, &prompt);
 
         let inference_request = crate::domain::InferenceRequest {
-            seed: task.task_id.parse().unwrap_or(0),
+            seed: None,
             prompt,
-            decode_params: crate::domain::DecodeParams::default(),
+            decode_params,
             msg_id: task.task_id.clone(),
+            model_id: model_id.clone(),
+            structured_output: None,
         };
 
-        let response = crate::services::InferenceService::process_inference(inference_request).await?;
+        let inference_started_at = crate::infra::clock::time();
+        // Owners share the canister's LLM slots fairly; Critical tasks go first
+        let lane = if matches!(task.priority, TaskPriority::Critical) { Lane::Critical } else { Lane::Standard };
+        let owner = Self::owner(agent).unwrap_or_else(ic_cdk::caller);
+        let inference = crate::services::InferenceService::process_inference_for(inference_request, owner, lane).await;
+        let step = trace.record(
+            TraceStepKind::Inference,
+            inference_started_at,
+            inference.as_ref().map_or("", |response| response.generated_text.as_str()),
+        );
+        match &inference {
+            Ok(response) => {
+                step.model_id = response.model_id.clone();
+                step.tokens = response.tokens.len() as u64;
+            }
+            Err(e) => {
+                step.model_id = model_id;
+                step.error = Some(e.clone());
+            }
+        }
 
-        Ok(AgentTaskResult {
-            task_id: task.task_id.clone(),
-            success: true,
-            result: response.generated_text,
-            tokens_used: response.tokens.len() as u64,
-            execution_time_ms: response.inference_time_ms,
-            error_message: None,
-        })
+        match inference {
+            Ok(response) => {
+                let mut tokens_used = response.tokens.len() as u64;
+                let (answer, critique) = if agent.analysis.agent_configuration.self_critique {
+                    let critique_started_at = crate::infra::clock::time();
+                    let (answer, record) = GuardrailService::review(
+                        &agent.analysis.agent_configuration,
+                        &agent.agent_id,
+                        &task.task_id,
+                        response.generated_text,
+                    )
+                    .await;
+                    trace
+                        .record(
+                            TraceStepKind::Critique,
+                            critique_started_at,
+                            &format!("{:?}: {}", record.verdict, record.critique),
+                        )
+                        .tokens = record.tokens_used;
+                    tokens_used += record.tokens_used;
+                    (answer, Some(record))
+                } else {
+                    (Some(response.generated_text), None)
+                };
+                let Some(answer) = answer else {
+                    return AgentTaskResult {
+                        task_id: task.task_id.clone(),
+                        success: false,
+                        result: String::new(),
+                        tokens_used,
+                        execution_time_ms: (crate::infra::clock::time() - started_at) / 1_000_000,
+                        error_message: Some("Answer broke the agent's rules and could not be revised".to_string()),
+                        outcome: TaskOutcome::Failed,
+                        provenance: None,
+                        critique,
+                        degradation: None,
+                        signature: None,
+                        plan: None,
+                        artifacts: Vec::new(),
+                        segments: None,
+                        translation: None,
+                    };
+                };
+
+                let (answer, translation) = match TranslationService::target_language(agent) {
+                    Some(language) => {
+                        let translation_started_at = crate::infra::clock::time();
+                        let keep_original = task.context.get(INCLUDE_ORIGINAL_KEY).is_some_and(|v| v == "true");
+                        let (answer, translation) =
+                            TranslationService::translate(&agent.agent_id, &task.task_id, language, answer, keep_original).await;
+                        let step = trace.record(TraceStepKind::Translation, translation_started_at, &answer);
+                        step.tokens = translation.tokens_used;
+                        step.error = translation.error.clone();
+                        tokens_used += translation.tokens_used;
+                        (answer, Some(translation))
+                    }
+                    None => (answer, None),
+                };
+
+                let mut sources = ProvenanceSource::passages(&passages);
+                sources.extend(attachment_sources);
+                let mut text = Self::attach_execution_output(agent, degradation, answer, &mut sources, trace);
+                if !passages.is_empty() {
+                    text.push_str("\n\n");
+                    text.push_str(&KnowledgeService::citations(&passages));
+                }
+                AgentTaskResult {
+                    task_id: task.task_id.clone(),
+                    success: true,
+                    provenance: Some(Provenance::new(QuantizedModel::Llama3_1_8B.model_id(), sources, &text)),
+                    result: text,
+                    tokens_used,
+                    execution_time_ms: (crate::infra::clock::time() - started_at) / 1_000_000,
+                    error_message: None,
+                    outcome: TaskOutcome::Succeeded,
+                    critique,
+                    degradation: None,
+                    signature: None,
+                    plan: None,
+                    artifacts: Vec::new(),
+                    segments: None,
+                    translation,
+                }
+            }
+            Err(e) => AgentTaskResult {
+                task_id: task.task_id.clone(),
+                success: false,
+                result: String::new(),
+                tokens_used: 0,
+                execution_time_ms: (crate::infra::clock::time() - started_at) / 1_000_000,
+                error_message: Some(e),
+                outcome: TaskOutcome::Failed,
+                provenance: None,
+                critique: None,
+                degradation: None,
+                signature: None,
+                plan: None,
+                artifacts: Vec::new(),
+                segments: None,
+                translation: None,
+            },
+        }
     }
 
-    async fn execute_research_task(_agent: &AutonomousAgent, task: &AgentTask) -> Result<AgentTaskResult, String> {
-        let prompt = format!(
-            "You are a researcher. Research and provide information about: {}",
-            task.description
-        );
-
-        let inference_request = crate::domain::InferenceRequest {
-            seed: task.task_id.parse().unwrap_or(0),
-            prompt,
-            decode_params: crate::domain::DecodeParams::default(),
-            msg_id: task.task_id.clone(),
+    /// Generic skill runner: the skill's steps in order, each prompt step an
+    /// LLM call and each tool step a bound tool, with the last step's output
+    /// checked against the skill's output schema
+    async fn run_skill_task(
+        agent: &AutonomousAgent,
+        skill_id: &str,
+        task: &AgentTask,
+        trace: &mut TraceRecorder,
+    ) -> AgentTaskResult {
+        let started_at = crate::infra::clock::time();
+        let failed = |tokens_used: u64, error: String| AgentTaskResult {
+            task_id: task.task_id.clone(),
+            success: false,
+            result: String::new(),
+            tokens_used,
+            execution_time_ms: (crate::infra::clock::time() - started_at) / 1_000_000,
+            error_message: Some(error),
+            outcome: TaskOutcome::Failed,
+            provenance: None,
+            critique: None,
+            degradation: None,
+            signature: None,
+            plan: None,
+            artifacts: Vec::new(),
+            segments: None,
+            translation: None,
         };
+        let skill = match SkillService::attached(agent, skill_id) {
+            Ok(skill) => skill,
+            Err(e) => return failed(0, e),
+        };
+        let lane = if matches!(task.priority, TaskPriority::Critical) { Lane::Critical } else { Lane::Standard };
+        let owner = Self::owner(agent).unwrap_or_else(ic_cdk::caller);
+        let mut sources = Vec::new();
+        let mut output = String::new();
+        let mut tokens_used = 0;
+
+        for (index, step) in skill.definition.steps.iter().enumerate() {
+            let step_started_at = crate::infra::clock::time();
+            match step {
+                SkillStep::Prompt { template } => {
+                    let prompt = SkillService::render(template, &task.description, &output);
+                    trace.record(TraceStepKind::Prompt, step_started_at, &prompt);
+                    let request = crate::domain::InferenceRequest {
+                        seed: None,
+                        prompt,
+                        decode_params: DecodeParams::default(),
+                        msg_id: format!("{}#{}", task.task_id, index + 1),
+                        model_id: None,
+                        structured_output: None,
+                    };
+                    let inference_started_at = crate::infra::clock::time();
+                    match crate::services::InferenceService::process_inference_for(request, owner, lane).await {
+                        Ok(response) => {
+                            let recorded = trace.record(TraceStepKind::Inference, inference_started_at, &response.generated_text);
+                            recorded.model_id = response.model_id.clone();
+                            recorded.tokens = response.tokens.len() as u64;
+                            tokens_used += response.tokens.len() as u64;
+                            output = response.generated_text;
+                        }
+                        Err(e) => {
+                            trace.record(TraceStepKind::Inference, inference_started_at, "").error = Some(e.clone());
+                            return failed(tokens_used, format!("Skill {} step {} failed: {}", skill_id, index + 1, e));
+                        }
+                    }
+                }
+                SkillStep::Tool { tool, arguments } => {
+                    let arguments = SkillService::render(arguments, &task.description, &output);
+                    sources.push(ProvenanceSource::tool_call(format!("{}#{}", tool, index + 1), &arguments));
+                    let detail = format!("{}\n{}", tool, arguments);
+                    match ToolService::run_bound(agent, tool, arguments).await {
+                        Ok(result) => {
+                            trace.record(TraceStepKind::ToolCall, step_started_at, &format!("{}\n--- output ---\n{}", detail, result));
+                            output = result;
+                        }
+                        Err(e) => {
+                            trace.record(TraceStepKind::ToolCall, step_started_at, &detail).error = Some(e.clone());
+                            return failed(tokens_used, format!("Skill {} step {} failed: {}", skill_id, index + 1, e));
+                        }
+                    }
+                }
+            }
+        }
 
-        let response = crate::services::InferenceService::process_inference(inference_request).await?;
-
-        Ok(AgentTaskResult {
+        if let Some(schema) = &skill.definition.output_schema {
+            if let Err(e) = SkillService::check_output(schema, &output) {
+                return failed(tokens_used, e);
+            }
+        }
+        Metrics::increment_counter("skill_runs_total");
+        AgentTaskResult {
             task_id: task.task_id.clone(),
             success: true,
-            result: response.generated_text,
-            tokens_used: response.tokens.len() as u64,
-            execution_time_ms: response.inference_time_ms,
+            provenance: Some(Provenance::new(QuantizedModel::Llama3_1_8B.model_id(), sources, &output)),
+            result: output,
+            tokens_used,
+            execution_time_ms: (crate::infra::clock::time() - started_at) / 1_000_000,
             error_message: None,
-        })
+            outcome: TaskOutcome::Succeeded,
+            critique: None,
+            degradation: None,
+            signature: None,
+            plan: None,
+            artifacts: Vec::new(),
+            segments: None,
+            translation: None,
+        }
     }
 
-    async fn execute_planning_task(_agent: &AutonomousAgent, task: &AgentTask) -> Result<AgentTaskResult, String> {
-        let prompt = format!(
-            "You are a planner. Create a plan for: {}",
-            task.description
-        );
-
-        let inference_request = crate::domain::InferenceRequest {
-            seed: task.task_id.parse().unwrap_or(0),
-            prompt,
-            decode_params: crate::domain::DecodeParams::default(),
-            msg_id: task.task_id.clone(),
-        };
-
-        let response = crate::services::InferenceService::process_inference(inference_request).await?;
+    /// Run the ```rhai blocks of a code assistant's answer in the sandbox and
+    /// append their output. Skipped when the tool is not allowed without approval
+    /// or degradation dropped the capabilities that need it.
+    fn attach_execution_output(
+        agent: &AutonomousAgent,
+        degradation: Option<&Degradation>,
+        mut text: String,
+        sources: &mut Vec<ProvenanceSource>,
+        trace: &mut TraceRecorder,
+    ) -> String {
+        if !matches!(agent.analysis.agent_configuration.agent_type, AgentType::CodeAssistant)
+            || ToolService::permission(agent, CODE_EXEC_TOOL) != ToolPermission::Allowed
+            || !DegradationService::allows_tool(agent, degradation, CODE_EXEC_TOOL)
+        {
+            return text;
+        }
+        let blocks = CodeSandbox::extract_blocks(&text);
+        if blocks.iter().any(|code| ApprovalService::tool_call_rule(agent, CODE_EXEC_TOOL, code).is_some()) {
+            return text;
+        }
 
-        Ok(AgentTaskResult {
-            task_id: task.task_id.clone(),
-            success: true,
-            result: response.generated_text,
-            tokens_used: response.tokens.len() as u64,
-            execution_time_ms: response.inference_time_ms,
-            error_message: None,
-        })
+        for (index, code) in blocks.into_iter().enumerate() {
+            sources.push(ProvenanceSource::tool_call(format!("{}#{}", CODE_EXEC_TOOL, index + 1), &code));
+            let call_started_at = crate::infra::clock::time();
+            let report = match CodeSandbox::execute(&CodeExecutionRequest { code: code.clone(), tests: Vec::new() }) {
+                Ok(result) => {
+                    let mut report = result.output.join("\n");
+                    if let Some(value) = result.return_value {
+                        report.push_str(&format!("\n=> {}", value));
+                    }
+                    if let Some(error) = result.error {
+                        report.push_str(&format!("\nerror: {}", error));
+                    }
+                    report
+                }
+                Err(e) => format!("error: {}", e),
+            };
+            trace.record(
+                TraceStepKind::ToolCall,
+                call_started_at,
+                &format!("{}\n{}\n--- output ---\n{}", CODE_EXEC_TOOL, code, report.trim()),
+            );
+            text.push_str(&format!("\n\nExecution output (block {}):\n```\n{}\n```", index + 1, report.trim()));
+            Metrics::increment_counter("code_executions_total");
+        }
+        text
     }
 
-    async fn execute_general_task(_agent: &AutonomousAgent, task: &AgentTask) -> Result<AgentTaskResult, String> {
-        let prompt = format!(
-            "You are a helpful assistant. Help with: {}",
-            task.description
-        );
-
-        let inference_request = crate::domain::InferenceRequest {
-            seed: task.task_id.parse().unwrap_or(0),
-            prompt,
-            decode_params: crate::domain::DecodeParams::default(),
-            msg_id: task.task_id.clone(),
-        };
+    /// Fold a finished task into the rolling window and recompute health
+    fn record_task_outcome(agent: &mut AutonomousAgent, result: &AgentTaskResult) {
+        agent.recent_tasks.push(TaskSample {
+            success: result.success,
+            latency_ms: result.execution_time_ms,
+        });
+        if agent.recent_tasks.len() > PERFORMANCE_WINDOW {
+            let overflow = agent.recent_tasks.len() - PERFORMANCE_WINDOW;
+            agent.recent_tasks.drain(..overflow);
+        }
 
-        let response = crate::services::InferenceService::process_inference(inference_request).await?;
+        let metrics = &mut agent.performance_metrics;
+        let window = agent.recent_tasks.len();
+        let successes = agent.recent_tasks.iter().filter(|s| s.success).count();
+        metrics.success_rate = successes as f32 / window as f32;
+
+        let mut latencies: Vec<u64> = agent.recent_tasks.iter().map(|s| s.latency_ms).collect();
+        latencies.sort_unstable();
+        metrics.p95_response_time_ms = latencies[((window as f64 * 0.95) as usize).min(window - 1)];
+        metrics.average_response_time_ms =
+            latencies.iter().sum::<u64>() as f64 / window as f64;
+
+        if result.success {
+            metrics.consecutive_failures = 0;
+        } else {
+            metrics.consecutive_failures += 1;
+        }
 
-        Ok(AgentTaskResult {
-            task_id: task.task_id.clone(),
-            success: true,
-            result: response.generated_text,
-            tokens_used: response.tokens.len() as u64,
-            execution_time_ms: response.inference_time_ms,
-            error_message: None,
-        })
+        // Latency only costs health once p95 exceeds 10s, bottoming out at 60s
+        let latency_score = 1.0
+            - ((metrics.p95_response_time_ms.saturating_sub(10_000)) as f32 / 50_000.0).min(1.0);
+        metrics.health_score = (0.8 * metrics.success_rate + 0.2 * latency_score).clamp(0.0, 1.0);
     }
 }
 
 // Additional data structures for agent management
 
-#[derive(Debug, Clone, CandidType)]
+/// What `clone_agent` copies besides configuration
+#[derive(Debug, Clone, Default, Deserialize, CandidType)]
+pub struct CloneOptions {
+    pub copy_memory: bool,
+    pub knowledge_namespaces: Vec<String>, // Empty copies no documents
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct AgentTask {
     pub task_id: String,
     pub description: String,
@@ -528,7 +1620,7 @@ pub struct AgentTask {
     pub context: HashMap<String, String>,
 }
 
-#[derive(Debug, Clone, CandidType)]
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub enum TaskPriority {
     Low,
     Normal,
@@ -536,7 +1628,8 @@ pub enum TaskPriority {
     Critical,
 }
 
-#[derive(Debug, Clone, CandidType)]
+// Deserialize for results proxied from other shards; Serialize for task history
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct AgentTaskResult {
     pub task_id: String,
     pub success: bool,
@@ -544,9 +1637,51 @@ pub struct AgentTaskResult {
     pub tokens_used: u64,
     pub execution_time_ms: u64,
     pub error_message: Option<String>,
+    pub outcome: TaskOutcome,
+    pub provenance: Option<Provenance>, // Set for successful inference results
+    pub critique: Option<CritiqueRecord>, // Set when the agent has self_critique enabled
+    pub degradation: Option<Degradation>, // Set when the owner's low quota trimmed the task
+    pub signature: Option<ResultSignature>, // Set for successful results while result signing is enabled
+    pub plan: Option<TaskPlan>, // Set for dry runs, which leave every other field empty
+    #[serde(default)]
+    pub artifacts: Vec<ArtifactRef>, // Set when the output was too large to return inline
+    pub segments: Option<Vec<OutputSegment>>, // Set when the task asked for structured output and the result is inline
+    pub translation: Option<Translation>, // Set when the agent prefers a language other than English
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum TaskOutcome {
+    Succeeded,
+    Failed,
+    TimedOut,
+    PendingApproval, // Held by a risk rule; nothing ran yet
+    Deferred,        // Queued until the LLM canister is reachable again
+    DryRun,          // Planned only; no LLM or tool was called
+}
+
+/// What a task would do, from a dry run
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TaskPlan {
+    pub skill_id: Option<String>,
+    pub model_id: Option<String>,     // None lets the LLM canister pick
+    pub prompts: Vec<String>,         // One per LLM call, as rendered now
+    pub knowledge_passages: u32,
+    pub tool_calls: Vec<PlannedToolCall>,
+    pub estimated_prompt_tokens: u64,
+    pub max_completion_tokens: u64,   // Upper bound across all LLM calls
+    pub approval_rule: Option<String>, // Risk rule that would hold the task for the owner
+    pub degradation: Option<Degradation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct PlannedToolCall {
+    pub tool: String,
+    pub arguments: Option<String>, // None when they come from the model's answer
+    pub requires_approval: bool,
+    pub denied: Option<String>,
 }
 
-#[derive(Debug, Clone, CandidType)]
+#[derive(Debug, Clone, CandidType, Deserialize)]
 pub struct AgentStatusInfo {
     pub agent_id: String,
     pub status: AgentStatus,
@@ -554,13 +1689,16 @@ pub struct AgentStatusInfo {
     pub model_bound: bool,
     pub created_at: u64,
     pub last_active: u64,
+    pub tasks_in_flight: u32,
+    pub tasks_queued: u32,
 }
 
-#[derive(Debug, Clone, CandidType)]
+#[derive(Debug, Clone, CandidType, Deserialize)]
 pub struct AgentSummary {
     pub agent_id: String,
     pub agent_type: AgentType,
     pub status: AgentStatus,
     pub created_at: u64,
     pub last_active: u64,
+    pub origin: Option<AgentOrigin>,
 }
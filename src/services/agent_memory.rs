@@ -0,0 +1,196 @@
+use crate::infra::{Guards, Metrics};
+use crate::services::{with_state, with_state_mut};
+use candid::CandidType;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+// Batches stay well under the 2 MiB reply limit
+const MAX_BATCH_ENTRIES: usize = 500;
+const MAX_BATCH_BYTES: usize = 1024 * 1024;
+const MAX_KEYS_PER_AGENT: usize = 10_000;
+const MAX_KEY_LEN: usize = 256;
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct MemoryRecord {
+    pub key: String,
+    pub value: Vec<u8>,
+    pub sha256: String, // Hex sha256 of value
+}
+
+/// One page of an agent's memory, ordered by key. Pass `next_cursor` back
+/// to export_memory for the following page, and the batch as exported to
+/// import_memory on the receiving agent.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct MemoryBatch {
+    pub entries: Vec<MemoryRecord>,
+    pub digest: String, // Hex sha256 over the entries; see AgentMemoryService
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct MemoryExportPage {
+    pub batch: MemoryBatch,
+    pub next_cursor: Option<String>,
+    pub total_keys: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, CandidType, Deserialize)]
+pub enum MemoryConflictPolicy {
+    Skip,      // Keep the existing value
+    Overwrite, // Replace it with the imported one
+    Merge,     // Merge JSON objects field by field, imported fields winning; other values are skipped
+}
+
+#[derive(Debug, Clone, Default, CandidType)]
+pub struct MemoryImportReport {
+    pub imported: u32, // Keys the agent did not have
+    pub overwritten: u32,
+    pub merged: u32,
+    pub skipped: u32,
+    pub unmergeable: Vec<String>, // Keys left alone under Merge because a value is not a JSON object
+}
+
+/// Bulk copy of an agent's key-value memory between agents or canisters.
+/// Every entry carries the sha256 of its value, and a batch digest covers
+/// the whole page: sha256 over each entry's key and value in order, each
+/// prefixed with its big-endian u32 byte length. Imports verify both before
+/// touching the agent, so a batch is applied completely or not at all.
+pub struct AgentMemoryService;
+
+impl AgentMemoryService {
+    pub fn export(agent_id: &str, cursor: Option<String>) -> Result<MemoryExportPage, String> {
+        with_state(|state| {
+            let memory = &state.agents.get(agent_id).ok_or_else(|| format!("Agent {} not found", agent_id))?.memory;
+            let mut keys: Vec<&String> = memory
+                .keys()
+                .filter(|key| cursor.as_ref().map_or(true, |cursor| *key > cursor))
+                .collect();
+            keys.sort();
+
+            let mut entries: Vec<MemoryRecord> = Vec::new();
+            let mut bytes = 0;
+            let mut next_cursor = None;
+            for key in keys {
+                let value = &memory[key];
+                // A single oversized value still goes out alone
+                if entries.len() == MAX_BATCH_ENTRIES || (!entries.is_empty() && bytes + value.len() > MAX_BATCH_BYTES) {
+                    next_cursor = entries.last().map(|e| e.key.clone());
+                    break;
+                }
+                bytes += value.len();
+                entries.push(MemoryRecord {
+                    key: key.clone(),
+                    value: value.clone(),
+                    sha256: hex::encode(Sha256::digest(value)),
+                });
+            }
+            Ok(MemoryExportPage {
+                batch: MemoryBatch {
+                    digest: batch_digest(&entries),
+                    entries,
+                },
+                next_cursor,
+                total_keys: memory.len() as u64,
+            })
+        })
+    }
+
+    pub fn import(agent_id: &str, batch: MemoryBatch, policy: MemoryConflictPolicy) -> Result<MemoryImportReport, String> {
+        Guards::check_memory_limits()?;
+        if batch.entries.len() > MAX_BATCH_ENTRIES {
+            return Err(format!("Invalid batch: at most {} entries", MAX_BATCH_ENTRIES));
+        }
+        for entry in &batch.entries {
+            if entry.key.is_empty() || entry.key.len() > MAX_KEY_LEN {
+                return Err(format!("Invalid key: keys must be 1 to {} bytes", MAX_KEY_LEN));
+            }
+            if hex::encode(Sha256::digest(&entry.value)) != entry.sha256 {
+                return Err(format!("Integrity check failed for key {}", entry.key));
+            }
+        }
+        if batch_digest(&batch.entries) != batch.digest {
+            return Err("Integrity check failed: batch digest does not match its entries".to_string());
+        }
+
+        with_state_mut(|state| {
+            let agent = state.agents.get_mut(agent_id).ok_or_else(|| format!("Agent {} not found", agent_id))?;
+            let new_keys = batch.entries.iter().filter(|e| !agent.memory.contains_key(&e.key)).count();
+            if agent.memory.len() + new_keys > MAX_KEYS_PER_AGENT {
+                return Err(format!("Key limit reached: agents hold at most {} memory keys", MAX_KEYS_PER_AGENT));
+            }
+
+            let mut report = MemoryImportReport::default();
+            for entry in batch.entries {
+                let Some(existing) = agent.memory.get(&entry.key) else {
+                    agent.memory.insert(entry.key, entry.value);
+                    report.imported += 1;
+                    continue;
+                };
+                match policy {
+                    MemoryConflictPolicy::Skip => report.skipped += 1,
+                    MemoryConflictPolicy::Overwrite => {
+                        agent.memory.insert(entry.key, entry.value);
+                        report.overwritten += 1;
+                    }
+                    MemoryConflictPolicy::Merge => match merge_json(existing, &entry.value) {
+                        Some(merged) => {
+                            agent.memory.insert(entry.key, merged);
+                            report.merged += 1;
+                        }
+                        None => report.unmergeable.push(entry.key),
+                    },
+                }
+            }
+            Metrics::add_to_counter("memory_entries_imported_total", (report.imported + report.overwritten + report.merged) as u64);
+            Ok(report)
+        })
+    }
+}
+
+fn batch_digest(entries: &[MemoryRecord]) -> String {
+    let mut hasher = Sha256::new();
+    for entry in entries {
+        for field in [entry.key.as_bytes(), &entry.value] {
+            hasher.update((field.len() as u32).to_be_bytes());
+            hasher.update(field);
+        }
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Both values as JSON objects merged, `incoming` winning on shared fields
+fn merge_json(existing: &[u8], incoming: &[u8]) -> Option<Vec<u8>> {
+    let Ok(Value::Object(mut merged)) = serde_json::from_slice::<Value>(existing) else {
+        return None;
+    };
+    let Ok(Value::Object(incoming)) = serde_json::from_slice::<Value>(incoming) else {
+        return None;
+    };
+    merged.extend(incoming);
+    serde_json::to_vec(&Value::Object(merged)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_json_objects_only() {
+        let merged = merge_json(br#"{"a":1,"b":2}"#, br#"{"b":3,"c":4}"#).unwrap();
+        let value: Value = serde_json::from_slice(&merged).unwrap();
+        assert_eq!(value, serde_json::json!({"a": 1, "b": 3, "c": 4}));
+        assert!(merge_json(b"plain text", br#"{"a":1}"#).is_none());
+        assert!(merge_json(br#"{"a":1}"#, b"[1,2]").is_none());
+    }
+
+    #[test]
+    fn test_batch_digest_separates_key_and_value() {
+        let record = |key: &str, value: &[u8]| MemoryRecord {
+            key: key.to_string(),
+            value: value.to_vec(),
+            sha256: String::new(),
+        };
+        assert_ne!(batch_digest(&[record("ab", b"c")]), batch_digest(&[record("a", b"bc")]));
+        assert_eq!(batch_digest(&[record("a", b"b")]), batch_digest(&[record("a", b"b")]));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::domain::api_version::ApiError;
+use crate::domain::instruction::UserInstruction;
+use crate::infra::clock::time;
+use crate::infra::Guards;
+use crate::services::agent_factory::{AgentFactory, AgentTask, AgentTaskResult, TaskPriority};
+use crate::services::delegation::{AccessScope, DelegationService};
+use crate::services::slo::{SloEndpoint, SloService};
+use crate::services::{InstructionAnalyzer, ShardingService};
+use candid::Principal;
+use std::collections::HashMap;
+
+/// create_agent and execute_task as the v1, v2, attested and shard endpoints
+/// run them. Endpoints authenticate the caller first; `owner` and `caller`
+/// are who quotas, access and rate limits apply to.
+pub struct AgentRequestService;
+
+impl AgentRequestService {
+    /// Placement may put the agent on another shard. Returns the agent id.
+    pub async fn create_agent(owner: Principal, mut instruction: UserInstruction) -> Result<String, ApiError> {
+        // Agents always belong to the principal that creates them
+        instruction.user_id = owner.to_string();
+        if let Some(targets) = ShardingService::forward_targets(&owner) {
+            AgentFactory::validate_user_quotas(&instruction.user_id, &instruction.subscription_tier).await?;
+            return ShardingService::forward_create(owner, instruction, &targets).await;
+        }
+        Self::create_local(owner, instruction).await
+    }
+
+    /// Create the agent on this canister
+    pub async fn create_local(owner: Principal, mut instruction: UserInstruction) -> Result<String, ApiError> {
+        instruction.user_id = owner.to_string();
+        let analysis = InstructionAnalyzer::analyze_instruction(instruction.clone())
+            .map_err(|message| ApiError::InvalidArgument { message })?;
+        let user_id = instruction.user_id.clone();
+        let agent = AgentFactory::create_agent(user_id, instruction, analysis).await?;
+        Ok(agent.agent_id)
+    }
+
+    /// Proxied to the agent's shard when it lives elsewhere. `dry_run` returns
+    /// the task's plan instead of running it.
+    pub async fn execute_task(
+        caller: Principal,
+        agent_id: &str,
+        description: String,
+        dry_run: bool,
+    ) -> Result<AgentTaskResult, ApiError> {
+        Self::execute(caller, agent_id, Self::task(description, HashMap::new()), dry_run).await
+    }
+
+    /// A new task with a sequenced id
+    pub fn task(description: String, context: HashMap<String, String>) -> AgentTask {
+        AgentTask {
+            task_id: AgentFactory::next_task_id(),
+            description,
+            priority: TaskPriority::Normal,
+            deadline: None,
+            context,
+        }
+    }
+
+    /// `execute_task` for a prepared task, such as one running a skill. A
+    /// proxied task keeps its description and context; its shard assigns the id.
+    pub async fn execute(
+        caller: Principal,
+        agent_id: &str,
+        task: AgentTask,
+        dry_run: bool,
+    ) -> Result<AgentTaskResult, ApiError> {
+        let shard = Self::admit(caller, agent_id).await?;
+        Self::run_admitted(caller, agent_id, shard, task, dry_run).await
+    }
+
+    /// The access and rate limit checks of `execute`, for callers with work to
+    /// do between them and the run. Returns the shard hosting the agent when
+    /// it lives elsewhere.
+    pub async fn admit(caller: Principal, agent_id: &str) -> Result<Option<Principal>, ApiError> {
+        // The owning shard checks access for proxied tasks
+        let shard = ShardingService::shard_of(agent_id);
+        if shard.is_none() {
+            DelegationService::authorize(agent_id, &caller.to_string(), AccessScope::Execute)?;
+        }
+        Guards::rate_limit_check_for(caller)?;
+        Guards::refresh_tier_for(caller).await;
+        Ok(shard)
+    }
+
+    /// Run a task that passed `admit`, recording its SLO and token usage
+    pub async fn run_admitted(
+        caller: Principal,
+        agent_id: &str,
+        shard: Option<Principal>,
+        task: AgentTask,
+        dry_run: bool,
+    ) -> Result<AgentTaskResult, ApiError> {
+        let started_at = time();
+        let result = match shard {
+            Some(shard) => ShardingService::execute_task(shard, caller, agent_id, task, dry_run).await,
+            None => Self::run_local(agent_id, task, dry_run).await,
+        };
+        if !dry_run {
+            SloService::record(SloEndpoint::ExecuteAgentTask, started_at, result.is_ok());
+        }
+        let result = result?;
+        Guards::record_token_usage_for(caller, result.tokens_used);
+        Ok(result)
+    }
+
+    /// A task proxied by a shard, which has already rate limited the caller
+    pub async fn execute_local(
+        caller: Principal,
+        agent_id: &str,
+        description: String,
+        context: HashMap<String, String>,
+        dry_run: bool,
+    ) -> Result<AgentTaskResult, ApiError> {
+        DelegationService::authorize(agent_id, &caller.to_string(), AccessScope::Execute)?;
+        Self::run_local(agent_id, Self::task(description, context), dry_run).await
+    }
+
+    async fn run_local(agent_id: &str, task: AgentTask, dry_run: bool) -> Result<AgentTaskResult, ApiError> {
+        if dry_run {
+            return AgentFactory::plan_task(agent_id, task).await;
+        }
+        AgentFactory::execute_task(agent_id, task).await
+    }
+}
@@ -0,0 +1,280 @@
+use crate::domain::instruction::{AgentConfiguration, SubscriptionTier};
+use crate::domain::AgentConfig;
+use crate::infra::stable::{memory, Cbor, Memory, AGENT_DEBUG_SNAPSHOTS_MEMORY_ID};
+use crate::infra::{Guards, Metrics};
+use crate::services::agent_factory::{AgentFactory, AgentPerformanceMetrics, AgentStatus, AutonomousAgent, TaskOutcome};
+use crate::services::archive::{ArchiveService, ArchivedAgent};
+use crate::services::{with_state, with_state_mut, CertificationService, TaskHistoryService};
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    // Keyed by "{agent_id}/{snapshot_id}" so an agent's snapshots are one range
+    static SNAPSHOTS: RefCell<StableBTreeMap<String, Cbor<AgentSnapshot>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(AGENT_DEBUG_SNAPSHOTS_MEMORY_ID)));
+}
+
+const SNAPSHOT_TASKS: usize = 20;
+const MAX_LABEL_LEN: usize = 100;
+// Task results are cut to this many characters in a snapshot
+const MAX_RESULT_CHARS: usize = 4_000;
+
+/// A finished task as captured in a snapshot
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SnapshotTask {
+    pub task_id: String,
+    pub description: String,
+    pub outcome: TaskOutcome,
+    pub result: String,
+    pub error_message: Option<String>,
+    pub tokens_used: u64,
+    pub recorded_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AgentSnapshot {
+    snapshot_id: String,
+    label: Option<String>,
+    user_id: String,
+    taken_by: Principal,
+    created_at: u64,
+    agent: ArchivedAgent, // Configuration and memory, compressed like an archive
+    recent_tasks: Vec<SnapshotTask>,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct AgentSnapshotInfo {
+    pub snapshot_id: String,
+    pub agent_id: String,
+    pub label: Option<String>,
+    pub taken_by: Principal,
+    pub created_at: u64,
+    pub tasks: u32,
+    pub stored_bytes: u64,
+}
+
+/// A snapshot opened for inspection
+#[derive(Debug, Clone, CandidType)]
+pub struct AgentSnapshotView {
+    pub info: AgentSnapshotInfo,
+    pub status: AgentStatus,
+    pub instruction_text: String,
+    pub agent_configuration: AgentConfiguration,
+    pub config: AgentConfig,
+    pub performance_metrics: AgentPerformanceMetrics,
+    pub memory: Vec<(String, Vec<u8>)>, // Ordered by key
+    pub recent_tasks: Vec<SnapshotTask>,
+}
+
+/// Snapshots an owner may keep across all of their agents
+#[derive(Debug, Clone, CandidType)]
+pub struct SnapshotQuota {
+    pub tier: SubscriptionTier,
+    pub max_snapshots: u32,
+    pub max_bytes: u64,
+    pub used_snapshots: u32,
+    pub used_bytes: u64,
+}
+
+/// Point-in-time copies of an agent for reproducing a failure: its
+/// configuration and memory plus its latest task results. Restoring rolls
+/// configuration and memory back; task history is an audit trail and stays,
+/// and so do the agent's delegations, webhooks and budget usage so a restore
+/// can neither revoke access nor reset spending.
+pub struct AgentSnapshotService;
+
+impl AgentSnapshotService {
+    pub fn snapshot(agent_id: &str, label: Option<String>, taken_by: Principal) -> Result<AgentSnapshotInfo, String> {
+        if label.as_ref().is_some_and(|l| l.chars().count() > MAX_LABEL_LEN) {
+            return Err(format!("Snapshot label exceeds {} characters", MAX_LABEL_LEN));
+        }
+        let agent = AgentFactory::find_agent(agent_id)?;
+        let now = time();
+        let recent_tasks: Vec<SnapshotTask> = {
+            let records = TaskHistoryService::live_records(agent_id);
+            let skip = records.len().saturating_sub(SNAPSHOT_TASKS);
+            records
+                .into_iter()
+                .skip(skip)
+                .map(|r| SnapshotTask {
+                    task_id: r.task.task_id,
+                    description: r.task.description,
+                    outcome: r.result.outcome,
+                    result: r.result.result.chars().take(MAX_RESULT_CHARS).collect(),
+                    error_message: r.result.error_message,
+                    tokens_used: r.result.tokens_used,
+                    recorded_at: r.recorded_at,
+                })
+                .collect()
+        };
+        // The id is the map key, so two snapshots in one round must not share it
+        let snapshot_id = with_state_mut(|state| {
+            state.snapshot_seq += 1;
+            format!("snapshot-{}-{}", now, state.snapshot_seq)
+        });
+        let snapshot = AgentSnapshot {
+            snapshot_id,
+            label,
+            user_id: agent.user_id.clone(),
+            taken_by,
+            created_at: now,
+            agent: ArchiveService::pack(&agent, now)?,
+            recent_tasks,
+        };
+
+        let quota = Self::quota(&agent.user_id);
+        let size = Self::size(&snapshot);
+        if quota.used_snapshots >= quota.max_snapshots {
+            return Err(format!(
+                "Snapshot limit reached for the {:?} tier. Maximum: {}",
+                quota.tier, quota.max_snapshots
+            ));
+        }
+        if quota.used_bytes + size > quota.max_bytes {
+            return Err(format!(
+                "Snapshot storage limit reached for the {:?} tier: {} of {} bytes used, snapshot needs {}",
+                quota.tier, quota.used_bytes, quota.max_bytes, size
+            ));
+        }
+
+        let info = Self::info(agent_id, &snapshot);
+        SNAPSHOTS.with(|s| s.borrow_mut().insert(Self::key(agent_id, &snapshot.snapshot_id), Cbor(snapshot)));
+        Metrics::increment_counter("agent_snapshots_total");
+        Ok(info)
+    }
+
+    /// Roll the agent's configuration and memory back to the snapshot
+    pub fn restore(agent_id: &str, snapshot_id: &str) -> Result<AutonomousAgent, String> {
+        let current = AgentFactory::find_agent(agent_id)?;
+        if with_state(|state| state.tasks_in_flight.get(agent_id).copied().unwrap_or(0) > 0) {
+            return Err(format!("Agent {} is running tasks; restore it once idle", agent_id));
+        }
+        let restored = ArchiveService::unpack(&Self::get(agent_id, snapshot_id)?.agent)?;
+        let agent = AutonomousAgent {
+            // An unhealthy agent stays unhealthy so the failure can be reproduced
+            status: match restored.status {
+                AgentStatus::Error(ref reason) => AgentStatus::Error(reason.clone()),
+                _ => AgentStatus::Ready,
+            },
+            last_active: time(),
+            idle_flagged_at: None,
+            user_id: current.user_id,
+            delegations: current.delegations,
+            webhooks: current.webhooks,
+            budget_usage: current.budget_usage,
+            ..restored
+        };
+        with_state_mut(|state| state.agents.insert(agent.agent_id.clone(), agent.clone()));
+        CertificationService::mark_dirty();
+        Metrics::increment_counter("agent_snapshot_restores_total");
+        Ok(agent)
+    }
+
+    pub fn open(agent_id: &str, snapshot_id: &str) -> Result<AgentSnapshotView, String> {
+        let snapshot = Self::get(agent_id, snapshot_id)?;
+        let agent = ArchiveService::unpack(&snapshot.agent)?;
+        let mut memory: Vec<(String, Vec<u8>)> = agent.memory.into_iter().collect();
+        memory.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(AgentSnapshotView {
+            info: Self::info(agent_id, &snapshot),
+            status: agent.status,
+            instruction_text: agent.instruction.instruction_text,
+            agent_configuration: agent.analysis.agent_configuration,
+            config: agent.config,
+            performance_metrics: agent.performance_metrics,
+            memory,
+            recent_tasks: snapshot.recent_tasks,
+        })
+    }
+
+    /// The agent's snapshots, oldest first
+    pub fn list(agent_id: &str) -> Vec<AgentSnapshotInfo> {
+        let prefix = Self::key(agent_id, "");
+        SNAPSHOTS.with(|s| {
+            s.borrow()
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(_, snapshot)| Self::info(agent_id, &snapshot.0))
+                .collect()
+        })
+    }
+
+    pub fn delete(agent_id: &str, snapshot_id: &str) -> Result<(), String> {
+        SNAPSHOTS
+            .with(|s| s.borrow_mut().remove(&Self::key(agent_id, snapshot_id)))
+            .map(|_| ())
+            .ok_or_else(|| format!("Snapshot {} of agent {} not found", snapshot_id, agent_id))
+    }
+
+    /// Drop every snapshot of agents owned by `owner`
+    pub fn purge_owner(owner: &Principal) -> u32 {
+        let owner = owner.to_text();
+        let keys: Vec<String> = SNAPSHOTS.with(|s| {
+            s.borrow().iter().filter(|(_, snapshot)| snapshot.0.user_id == owner).map(|(key, _)| key).collect()
+        });
+        SNAPSHOTS.with(|s| {
+            let mut snapshots = s.borrow_mut();
+            for key in &keys {
+                snapshots.remove(key);
+            }
+        });
+        keys.len() as u32
+    }
+
+    pub fn quota(owner: &str) -> SnapshotQuota {
+        let tier = Principal::from_text(owner)
+            .map(|owner| Guards::limits_for(owner).tier)
+            .unwrap_or(SubscriptionTier::Basic);
+        let (max_snapshots, max_bytes) = tier_limits(&tier);
+        let (used_snapshots, used_bytes) = SNAPSHOTS.with(|s| {
+            s.borrow()
+                .iter()
+                .filter(|(_, snapshot)| snapshot.0.user_id == owner)
+                .fold((0, 0), |(count, bytes), (_, snapshot)| (count + 1, bytes + Self::size(&snapshot.0)))
+        });
+        SnapshotQuota { tier, max_snapshots, max_bytes, used_snapshots, used_bytes }
+    }
+
+    fn get(agent_id: &str, snapshot_id: &str) -> Result<AgentSnapshot, String> {
+        SNAPSHOTS
+            .with(|s| s.borrow().get(&Self::key(agent_id, snapshot_id)).map(|snapshot| snapshot.0))
+            .ok_or_else(|| format!("Snapshot {} of agent {} not found", snapshot_id, agent_id))
+    }
+
+    fn key(agent_id: &str, snapshot_id: &str) -> String {
+        format!("{}/{}", agent_id, snapshot_id)
+    }
+
+    fn size(snapshot: &AgentSnapshot) -> u64 {
+        let tasks: usize = snapshot
+            .recent_tasks
+            .iter()
+            .map(|t| t.description.len() + t.result.len() + t.error_message.as_ref().map_or(0, |e| e.len()))
+            .sum();
+        (snapshot.agent.compressed.len() + tasks) as u64
+    }
+
+    fn info(agent_id: &str, snapshot: &AgentSnapshot) -> AgentSnapshotInfo {
+        AgentSnapshotInfo {
+            snapshot_id: snapshot.snapshot_id.clone(),
+            agent_id: agent_id.to_string(),
+            label: snapshot.label.clone(),
+            taken_by: snapshot.taken_by,
+            created_at: snapshot.created_at,
+            tasks: snapshot.recent_tasks.len() as u32,
+            stored_bytes: Self::size(snapshot),
+        }
+    }
+}
+
+/// Snapshot count and stored bytes allowed per owner
+fn tier_limits(tier: &SubscriptionTier) -> (u32, u64) {
+    match tier {
+        SubscriptionTier::Basic => (3, 1024 * 1024),
+        SubscriptionTier::Pro => (20, 16 * 1024 * 1024),
+        SubscriptionTier::Enterprise => (100, 128 * 1024 * 1024),
+    }
+}
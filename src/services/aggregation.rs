@@ -0,0 +1,118 @@
+use crate::domain::instruction::AggregationStrategy;
+use crate::services::agent_factory::AgentFactory;
+use crate::services::coordinator::CoordinatorService;
+use crate::services::workflow::Workflow;
+use std::collections::HashMap;
+
+/// Merged output of a multi-agent workflow
+#[derive(Debug, Clone)]
+pub struct Aggregate {
+    pub output: String,
+    pub summary: String,
+}
+
+/// Strategies for merging the outputs of agents that worked on the same task
+pub struct Aggregator;
+
+impl Aggregator {
+    pub async fn aggregate(strategy: &AggregationStrategy, workflow: &Workflow) -> Result<Aggregate, String> {
+        let outputs: Vec<(&str, &str)> = workflow
+            .steps
+            .iter()
+            .filter_map(|step| step.output.as_deref().map(|out| (step.agent_id.as_str(), out)))
+            .collect();
+        if outputs.is_empty() {
+            return Err("No step outputs to aggregate".to_string());
+        }
+
+        match strategy {
+            AggregationStrategy::Concatenate => Ok(Self::concatenate(&outputs)),
+            AggregationStrategy::MajorityVote => Ok(Self::majority_vote(&outputs)),
+            AggregationStrategy::Synthesize => {
+                let synthesizer = workflow
+                    .coordinator_id
+                    .as_deref()
+                    .unwrap_or(outputs[0].0);
+                let output = CoordinatorService::synthesize(synthesizer, workflow).await?;
+                Ok(Aggregate {
+                    output,
+                    summary: format!("Synthesized {} outputs via {}", outputs.len(), synthesizer),
+                })
+            }
+            AggregationStrategy::BestOfN => Self::best_of_n(workflow, &outputs).await,
+        }
+    }
+
+    fn concatenate(outputs: &[(&str, &str)]) -> Aggregate {
+        let output = outputs
+            .iter()
+            .map(|(agent_id, out)| format!("## {}\n{}", agent_id, out))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Aggregate {
+            output,
+            summary: format!("Concatenated {} outputs", outputs.len()),
+        }
+    }
+
+    /// Most common answer after whitespace and case normalization; ties go to the earliest
+    fn majority_vote(outputs: &[(&str, &str)]) -> Aggregate {
+        let mut votes: HashMap<String, (usize, usize)> = HashMap::new(); // normalized -> (count, first index)
+        for (i, (_, out)) in outputs.iter().enumerate() {
+            let entry = votes.entry(Self::normalize(out)).or_insert((0, i));
+            entry.0 += 1;
+        }
+
+        let (count, first_index) = votes
+            .values()
+            .copied()
+            .max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)))
+            .unwrap_or((1, 0));
+
+        Aggregate {
+            output: outputs[first_index].1.to_string(),
+            summary: format!("Majority vote: {}/{} agents agreed", count, outputs.len()),
+        }
+    }
+
+    /// Each agent scores its own answer; the highest score wins
+    async fn best_of_n(workflow: &Workflow, outputs: &[(&str, &str)]) -> Result<Aggregate, String> {
+        let mut best: Option<(usize, u32)> = None;
+
+        for (i, (agent_id, out)) in outputs.iter().enumerate() {
+            let agent = AgentFactory::find_agent(agent_id)?;
+            let prompt = format!(
+                "Rate how well the following answer accomplishes the task on a scale of 1 to 10. \
+                 Reply with the number only.\n\nTask: {}\n\nAnswer: {}",
+                workflow.task_description, out
+            );
+            let msg_id = format!("{}-score-{}", workflow.workflow_id, i);
+            let reply = CoordinatorService::complete(&agent, &msg_id, prompt).await?;
+            let score = Self::parse_score(&reply);
+
+            if best.map_or(true, |(_, best_score)| score > best_score) {
+                best = Some((i, score));
+            }
+        }
+
+        let (index, score) = best.unwrap_or((0, 0));
+        Ok(Aggregate {
+            output: outputs[index].1.to_string(),
+            summary: format!("Best of {}: {} self-scored {}/10", outputs.len(), outputs[index].0, score),
+        })
+    }
+
+    fn normalize(text: &str) -> String {
+        text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+    }
+
+    /// First integer in the reply, clamped to 0..=10
+    fn parse_score(reply: &str) -> u32 {
+        reply
+            .split(|c: char| !c.is_ascii_digit())
+            .find(|part| !part.is_empty())
+            .and_then(|digits| digits.parse::<u32>().ok())
+            .map(|score| score.min(10))
+            .unwrap_or(0)
+    }
+}
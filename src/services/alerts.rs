@@ -0,0 +1,273 @@
+use crate::infra::stable::{memory, Cbor, Memory, ALERT_RULES_MEMORY_ID};
+use crate::infra::{Metrics, Scheduler};
+use crate::services::events::{AgentEventKind, EventService};
+use crate::services::webhook::{WebhookEvent, WebhookService};
+use crate::services::with_state_mut;
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::Duration;
+
+thread_local! {
+    static RULES: RefCell<StableBTreeMap<String, Cbor<AlertRule>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(ALERT_RULES_MEMORY_ID)));
+}
+
+const EVALUATION_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_RULES: u64 = 50;
+const MAX_NAME_LEN: usize = 100;
+const MAX_SUSTAINED_MINUTES: u32 = 24 * 60;
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+
+/// What a rule watches. Counter-based metrics are measured over the
+/// interval since the rule's previous evaluation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum AlertMetric {
+    Gauge { name: String },
+    CounterRate { name: String },                // Increase per minute
+    ErrorRate { errors: String, total: String }, // Share of `total`'s increase that `errors` grew by
+    CyclesBalance,
+    QueueDepth, // Inference calls waiting for a scheduler slot
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum AlertComparison {
+    Above,
+    Below,
+}
+
+#[derive(Debug, Clone, Deserialize, CandidType)]
+pub struct AlertRuleSpec {
+    pub name: String,
+    pub metric: AlertMetric,
+    pub comparison: AlertComparison,
+    pub threshold: f64,
+    pub sustained_minutes: u32, // 0 raises on the first breaching evaluation
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct AlertRule {
+    pub rule_id: String,
+    pub name: String,
+    pub metric: AlertMetric,
+    pub comparison: AlertComparison,
+    pub threshold: f64,
+    pub sustained_minutes: u32,
+    pub created_by: Principal,
+    pub created_at: u64,
+    pub firing: bool,
+    pub breaching_since: Option<u64>,
+    pub last_value: Option<f64>,
+    pub last_evaluated_at: Option<u64>,
+    pub counter_baseline: Vec<u64>, // Counter values at the last evaluation
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AlertTransition {
+    Raised,
+    Resolved,
+}
+
+/// Operator alert rules over canister metrics, evaluated on a timer. A rule
+/// raises once its condition has held for `sustained_minutes` and resolves
+/// on the first evaluation where it no longer holds. Both transitions go to
+/// the event log and to Alert webhooks on the agents of the rule's creator.
+pub struct AlertService;
+
+impl AlertService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(EVALUATION_INTERVAL, || {
+            Self::evaluate(time());
+        });
+    }
+
+    pub fn add_rule(spec: AlertRuleSpec, created_by: Principal) -> Result<AlertRule, String> {
+        if spec.name.trim().is_empty() || spec.name.chars().count() > MAX_NAME_LEN {
+            return Err(format!("Alert name must be 1 to {} characters", MAX_NAME_LEN));
+        }
+        if !spec.threshold.is_finite() {
+            return Err("Alert threshold must be a finite number".to_string());
+        }
+        if spec.sustained_minutes > MAX_SUSTAINED_MINUTES {
+            return Err(format!("sustained_minutes cannot exceed {}", MAX_SUSTAINED_MINUTES));
+        }
+        if let AlertMetric::Gauge { name } | AlertMetric::CounterRate { name } = &spec.metric {
+            if name.trim().is_empty() {
+                return Err("Alert metric name cannot be empty".to_string());
+            }
+        }
+        if RULES.with(|r| r.borrow().len()) >= MAX_RULES {
+            return Err(format!("Alert rule limit reached. Maximum: {}", MAX_RULES));
+        }
+
+        let now = time();
+        let rule_id = with_state_mut(|state| {
+            state.alert_rule_seq += 1;
+            format!("alert-{}-{}", now, state.alert_rule_seq)
+        });
+        let rule = AlertRule {
+            rule_id,
+            name: spec.name,
+            metric: spec.metric,
+            comparison: spec.comparison,
+            threshold: spec.threshold,
+            sustained_minutes: spec.sustained_minutes,
+            created_by,
+            created_at: now,
+            firing: false,
+            breaching_since: None,
+            last_value: None,
+            last_evaluated_at: None,
+            counter_baseline: Vec::new(),
+        };
+        RULES.with(|r| r.borrow_mut().insert(rule.rule_id.clone(), Cbor(rule.clone())));
+        Ok(rule)
+    }
+
+    pub fn remove_rule(rule_id: &str) -> Result<(), String> {
+        RULES
+            .with(|r| r.borrow_mut().remove(&rule_id.to_string()))
+            .map(|_| ())
+            .ok_or_else(|| format!("Alert rule {} not found", rule_id))
+    }
+
+    pub fn list_rules() -> Vec<AlertRule> {
+        RULES.with(|r| r.borrow().iter().map(|(_, rule)| rule.0).collect())
+    }
+
+    pub fn evaluate(now: u64) {
+        for mut rule in Self::list_rules() {
+            let value = Self::measure(&mut rule, now);
+            rule.last_evaluated_at = Some(now);
+            if let Some(value) = value {
+                rule.last_value = Some(value);
+                match step(&mut rule, value, now) {
+                    Some(AlertTransition::Raised) => Self::announce(&rule, value, true),
+                    Some(AlertTransition::Resolved) => Self::announce(&rule, value, false),
+                    None => {}
+                }
+            }
+            RULES.with(|r| r.borrow_mut().insert(rule.rule_id.clone(), Cbor(rule)));
+        }
+    }
+
+    /// Current value of the rule's metric; None when there is nothing to judge yet
+    fn measure(rule: &mut AlertRule, now: u64) -> Option<f64> {
+        match &rule.metric {
+            AlertMetric::Gauge { name } => Metrics::get_gauge(name),
+            AlertMetric::CyclesBalance => Some(ic_cdk::api::canister_balance128() as f64),
+            AlertMetric::QueueDepth => {
+                let status = Scheduler::status();
+                Some((status.waiting_critical + status.waiting_standard) as f64)
+            }
+            AlertMetric::CounterRate { name } => {
+                let current = vec![Metrics::get_counter(name)];
+                let previous = std::mem::replace(&mut rule.counter_baseline, current.clone());
+                let elapsed = now.saturating_sub(rule.last_evaluated_at?);
+                let delta = current[0].saturating_sub(*previous.first()?);
+                (elapsed > 0).then(|| delta as f64 * NANOS_PER_MINUTE as f64 / elapsed as f64)
+            }
+            AlertMetric::ErrorRate { errors, total } => {
+                let current = vec![Metrics::get_counter(errors), Metrics::get_counter(total)];
+                let previous = std::mem::replace(&mut rule.counter_baseline, current.clone());
+                let [errors_before, total_before] = previous[..] else {
+                    return None;
+                };
+                let total_delta = current[1].saturating_sub(total_before);
+                // No traffic in the interval says nothing about the error rate
+                (total_delta > 0).then(|| current[0].saturating_sub(errors_before) as f64 / total_delta as f64)
+            }
+        }
+    }
+
+    fn announce(rule: &AlertRule, value: f64, raised: bool) {
+        let (kind, event, counter) = if raised {
+            (
+                AgentEventKind::AlertRaised { rule_id: rule.rule_id.clone(), name: rule.name.clone(), value },
+                WebhookEvent::AlertRaised,
+                "alerts_raised_total",
+            )
+        } else {
+            (
+                AgentEventKind::AlertResolved { rule_id: rule.rule_id.clone(), name: rule.name.clone(), value },
+                WebhookEvent::AlertResolved,
+                "alerts_resolved_total",
+            )
+        };
+        let created_by = rule.created_by.to_text();
+        EventService::publish(None, &created_by, kind);
+        WebhookService::notify_user(&created_by, event, serde_json::json!({
+            "rule_id": rule.rule_id,
+            "name": rule.name,
+            "metric": rule.metric,
+            "comparison": rule.comparison,
+            "threshold": rule.threshold,
+            "value": value,
+        }));
+        Metrics::increment_counter(counter);
+    }
+}
+
+/// Advance the rule's state with a new value, returning the transition it caused
+fn step(rule: &mut AlertRule, value: f64, now: u64) -> Option<AlertTransition> {
+    let breaching = match rule.comparison {
+        AlertComparison::Above => value > rule.threshold,
+        AlertComparison::Below => value < rule.threshold,
+    };
+    if !breaching {
+        rule.breaching_since = None;
+        return std::mem::take(&mut rule.firing).then_some(AlertTransition::Resolved);
+    }
+    let since = *rule.breaching_since.get_or_insert(now);
+    if !rule.firing && now.saturating_sub(since) >= rule.sustained_minutes as u64 * NANOS_PER_MINUTE {
+        rule.firing = true;
+        return Some(AlertTransition::Raised);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(sustained_minutes: u32) -> AlertRule {
+        AlertRule {
+            rule_id: "alert-1".to_string(),
+            name: "queue".to_string(),
+            metric: AlertMetric::QueueDepth,
+            comparison: AlertComparison::Above,
+            threshold: 10.0,
+            sustained_minutes,
+            created_by: Principal::anonymous(),
+            created_at: 0,
+            firing: false,
+            breaching_since: None,
+            last_value: None,
+            last_evaluated_at: None,
+            counter_baseline: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_step_raises_only_after_sustained_breach() {
+        let mut rule = rule(5);
+        assert_eq!(step(&mut rule, 20.0, 0), None);
+        assert_eq!(step(&mut rule, 20.0, 4 * NANOS_PER_MINUTE), None);
+        assert_eq!(step(&mut rule, 20.0, 5 * NANOS_PER_MINUTE), Some(AlertTransition::Raised));
+        assert_eq!(step(&mut rule, 20.0, 6 * NANOS_PER_MINUTE), None);
+        assert_eq!(step(&mut rule, 5.0, 7 * NANOS_PER_MINUTE), Some(AlertTransition::Resolved));
+        assert_eq!(step(&mut rule, 5.0, 8 * NANOS_PER_MINUTE), None);
+    }
+
+    #[test]
+    fn test_step_restarts_the_window_when_the_breach_lapses() {
+        let mut rule = rule(5);
+        step(&mut rule, 20.0, 0);
+        step(&mut rule, 5.0, 3 * NANOS_PER_MINUTE);
+        assert_eq!(step(&mut rule, 20.0, 4 * NANOS_PER_MINUTE), None);
+        assert_eq!(step(&mut rule, 20.0, 8 * NANOS_PER_MINUTE), None);
+        assert_eq!(step(&mut rule, 20.0, 9 * NANOS_PER_MINUTE), Some(AlertTransition::Raised));
+    }
+}
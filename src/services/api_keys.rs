@@ -0,0 +1,117 @@
+use crate::infra::stable::{memory, Cbor, Memory, API_KEYS_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::provenance::sha256_hex;
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    // Keyed by the sha256 of the secret; the secret itself is never stored
+    static API_KEYS: RefCell<StableBTreeMap<String, Cbor<ApiKeyRecord>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(API_KEYS_MEMORY_ID)));
+}
+
+const KEY_PREFIX: &str = "ohms-sk-";
+const MAX_KEYS_PER_PRINCIPAL: usize = 10;
+const MAX_LABEL_CHARS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiKeyRecord {
+    key_id: String,
+    principal: String,
+    label: String,
+    created_at: u64,
+    last_used_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ApiKeyInfo {
+    pub key_id: String,
+    pub label: String,
+    pub created_at: u64,
+    pub last_used_at: Option<u64>,
+}
+
+/// Bearer keys for the HTTP gateway. Requests made with a key act as the
+/// principal that created it.
+pub struct ApiKeyService;
+
+impl ApiKeyService {
+    /// Returns the secret. It is only shown here; afterwards the key is
+    /// identified by its `key_id`.
+    pub async fn create(owner: Principal, label: String) -> Result<String, String> {
+        if label.chars().count() > MAX_LABEL_CHARS {
+            return Err(format!("Label exceeds {} characters", MAX_LABEL_CHARS));
+        }
+        if Self::list(owner).len() >= MAX_KEYS_PER_PRINCIPAL {
+            return Err(format!("API key limit reached. Maximum: {}", MAX_KEYS_PER_PRINCIPAL));
+        }
+
+        let (random,): (Vec<u8>,) = ic_cdk::api::management_canister::main::raw_rand()
+            .await
+            .map_err(|(code, msg)| format!("Failed to generate key: {:?} {}", code, msg))?;
+        let secret = format!("{}{}", KEY_PREFIX, hex::encode(&random[..24]));
+        let hash = sha256_hex(&secret);
+        let record = ApiKeyRecord {
+            key_id: format!("key-{}", &hash[..12]),
+            principal: owner.to_text(),
+            label,
+            created_at: time(),
+            last_used_at: None,
+        };
+        API_KEYS.with(|k| k.borrow_mut().insert(hash, Cbor(record)));
+        Metrics::increment_counter("api_keys_created_total");
+        Ok(secret)
+    }
+
+    pub fn list(owner: Principal) -> Vec<ApiKeyInfo> {
+        let owner = owner.to_text();
+        API_KEYS.with(|k| {
+            k.borrow()
+                .iter()
+                .filter(|(_, record)| record.0.principal == owner)
+                .map(|(_, record)| ApiKeyInfo {
+                    key_id: record.0.key_id,
+                    label: record.0.label,
+                    created_at: record.0.created_at,
+                    last_used_at: record.0.last_used_at,
+                })
+                .collect()
+        })
+    }
+
+    pub fn revoke(owner: Principal, key_id: &str) -> Result<(), String> {
+        let owner = owner.to_text();
+        API_KEYS.with(|k| {
+            let mut keys = k.borrow_mut();
+            let hash = keys
+                .iter()
+                .find(|(_, record)| record.0.principal == owner && record.0.key_id == key_id)
+                .map(|(hash, _)| hash)
+                .ok_or_else(|| format!("API key {} not found", key_id))?;
+            keys.remove(&hash);
+            Ok(())
+        })
+    }
+
+    /// Principal the secret acts as. `touch` records the use, which only sticks
+    /// in update calls.
+    pub fn resolve(secret: &str, touch: bool) -> Option<Principal> {
+        if !secret.starts_with(KEY_PREFIX) {
+            return None;
+        }
+        let hash = sha256_hex(secret);
+        API_KEYS.with(|k| {
+            let mut keys = k.borrow_mut();
+            let mut record = keys.get(&hash)?.0;
+            let principal = Principal::from_text(&record.principal).ok()?;
+            if touch {
+                record.last_used_at = Some(time());
+                keys.insert(hash, Cbor(record));
+            }
+            Some(principal)
+        })
+    }
+}
@@ -0,0 +1,295 @@
+use crate::domain::instruction::SafetyLevel;
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentFactory, AgentTask, AgentTaskResult, AutonomousAgent, TaskOutcome};
+use crate::services::events::{AgentEventKind, EventService};
+use crate::services::payments::PaymentService;
+use crate::services::tools::{ToolCall, ToolCallResult, ToolService, PENDING_CALL_TTL_NS};
+use crate::services::webhook::{WebhookEvent, WebhookService};
+use crate::services::{with_state, with_state_mut};
+use candid::CandidType;
+use crate::infra::clock::time;
+use serde::{Deserialize, Serialize};
+
+const MAX_PENDING_TASKS_PER_AGENT: usize = 20;
+const PENDING_TASK_TTL_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const MAX_RULES_PER_AGENT: usize = 20;
+pub const STRICT_RULE: &str = "strict-safety-level";
+// What SafetyLevel::Strict counts as a significant action in a task description
+const SIGNIFICANT_ACTIONS: &[&str] = &[
+    "delete", "remove", "transfer", "send", "pay", "purchase", "withdraw", "deploy", "publish", "drop",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum RiskTarget {
+    Task,
+    ToolCall,
+}
+
+/// Owner-defined rule: a task description, or a tool call's tool name or
+/// arguments, containing any keyword (case-insensitive) needs approval.
+/// No keywords matches everything of the target kind.
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct RiskRule {
+    pub name: String,
+    pub target: RiskTarget,
+    pub keywords: Vec<String>,
+}
+
+/// Task held until the owner responds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingTask {
+    pub request_id: String,
+    pub task: AgentTask,
+    pub rule: String,
+    pub requested_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub enum ApprovalSubject {
+    Task(AgentTask),
+    ToolCall(ToolCall),
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ApprovalRequest {
+    pub request_id: String,
+    pub agent_id: String,
+    pub subject: ApprovalSubject,
+    pub rule: String, // Risk rule that matched, or STRICT_RULE
+    pub requested_at: u64,
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, CandidType, Deserialize)]
+pub enum ApprovalDecision {
+    Approve,
+    Reject,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub enum ApprovalOutcome {
+    TaskExecuted(AgentTaskResult),
+    ToolExecuted(ToolCallResult),
+    Rejected,
+}
+
+/// Human-in-the-loop gates. Tasks and tool calls matching the agent's risk
+/// rules, or any significant action of a Strict agent, wait for the owner's
+/// respond_to_approval. The owner is told through the event log and the
+/// ApprovalRequested webhook. A held paid task keeps its charge, which is
+/// refunded if the task is rejected, expires or fails once approved.
+pub struct ApprovalService;
+
+impl ApprovalService {
+    pub fn set_rules(agent_id: &str, rules: Vec<RiskRule>) -> Result<(), String> {
+        if rules.len() > MAX_RULES_PER_AGENT {
+            return Err(format!("An agent can have at most {} risk rules", MAX_RULES_PER_AGENT));
+        }
+        if rules.iter().any(|r| r.name.trim().is_empty() || r.name == STRICT_RULE) {
+            return Err(format!("Rule names must be non-empty and not {}", STRICT_RULE));
+        }
+        let mut agent = AgentFactory::find_agent(agent_id)?;
+        agent.risk_rules = rules;
+        Self::save(agent);
+        Ok(())
+    }
+
+    /// Rule requiring approval before the task runs
+    pub fn task_rule(agent: &AutonomousAgent, task: &AgentTask) -> Option<String> {
+        let strict = Self::is_strict(agent) && Self::contains_any(&task.description, SIGNIFICANT_ACTIONS);
+        Self::matching_rule(agent, RiskTarget::Task, &[&task.description])
+            .or_else(|| strict.then(|| STRICT_RULE.to_string()))
+    }
+
+    /// Rule requiring approval before the call runs. Strict agents need
+    /// approval for every tool call.
+    pub fn tool_call_rule(agent: &AutonomousAgent, tool: &str, arguments: &str) -> Option<String> {
+        Self::matching_rule(agent, RiskTarget::ToolCall, &[tool, arguments])
+            .or_else(|| Self::is_strict(agent).then(|| STRICT_RULE.to_string()))
+    }
+
+    /// Holds the task when a rule matches. The agent is saved by the caller.
+    pub fn gate_task(agent: &mut AutonomousAgent, task: &AgentTask) -> Result<Option<String>, String> {
+        let Some(rule) = Self::task_rule(agent, task) else {
+            return Ok(None);
+        };
+        let request_id = format!("approval-{}", task.task_id);
+        let now = time();
+        let (expired, pending): (Vec<PendingTask>, Vec<PendingTask>) = std::mem::take(&mut agent.pending_tasks)
+            .into_iter()
+            .partition(|p| now.saturating_sub(p.requested_at) >= PENDING_TASK_TTL_NS);
+        agent.pending_tasks = pending;
+        for pending in expired {
+            ic_cdk::spawn(async move {
+                PaymentService::refund_task(&pending.task).await;
+            });
+        }
+        if agent.pending_tasks.iter().any(|p| p.request_id == request_id) {
+            return Ok(Some(request_id));
+        }
+        if agent.pending_tasks.len() >= MAX_PENDING_TASKS_PER_AGENT {
+            return Err(format!("Pending approval limit reached. Maximum: {}", MAX_PENDING_TASKS_PER_AGENT));
+        }
+        agent.pending_tasks.push(PendingTask {
+            request_id: request_id.clone(),
+            task: task.clone(),
+            rule: rule.clone(),
+            requested_at: now,
+        });
+        Self::notify_requested(agent, &request_id, &rule, &task.description);
+        Ok(Some(request_id))
+    }
+
+    pub fn notify_requested(agent: &AutonomousAgent, request_id: &str, rule: &str, summary: &str) {
+        Metrics::increment_counter("approvals_requested_total");
+        EventService::publish(Some(&agent.agent_id), &agent.user_id, AgentEventKind::ApprovalRequested {
+            request_id: request_id.to_string(),
+            rule: rule.to_string(),
+        });
+        WebhookService::notify(&agent.agent_id, WebhookEvent::ApprovalRequested, serde_json::json!({
+            "request_id": request_id,
+            "rule": rule,
+            "summary": summary.chars().take(200).collect::<String>(),
+        }));
+    }
+
+    /// Pending tasks and tool calls of the agent, oldest first
+    pub fn list(agent_id: &str) -> Result<Vec<ApprovalRequest>, String> {
+        let agent = AgentFactory::find_agent(agent_id)?;
+        let now = time();
+        let tasks = agent
+            .pending_tasks
+            .iter()
+            .filter(|p| now.saturating_sub(p.requested_at) < PENDING_TASK_TTL_NS)
+            .map(|p| ApprovalRequest {
+                request_id: p.request_id.clone(),
+                agent_id: agent.agent_id.clone(),
+                subject: ApprovalSubject::Task(p.task.clone()),
+                rule: p.rule.clone(),
+                requested_at: p.requested_at,
+                expires_at: p.requested_at + PENDING_TASK_TTL_NS,
+            });
+        let calls = ToolService::list_pending(agent_id)?.into_iter().map(|call| ApprovalRequest {
+            request_id: call.call_id.clone(),
+            agent_id: agent.agent_id.clone(),
+            rule: Self::tool_call_rule(&agent, &call.tool, &call.arguments).unwrap_or_else(|| STRICT_RULE.to_string()),
+            requested_at: call.requested_at,
+            expires_at: call.requested_at + PENDING_CALL_TTL_NS,
+            subject: ApprovalSubject::ToolCall(call),
+        });
+        let mut requests: Vec<ApprovalRequest> = tasks.chain(calls).collect();
+        requests.sort_by_key(|r| r.requested_at);
+        Ok(requests)
+    }
+
+    /// Runs or drops the held task or tool call. Only the agent's owner can respond.
+    pub async fn respond(owner: &str, request_id: &str, decision: ApprovalDecision) -> Result<ApprovalOutcome, String> {
+        let (agent_id, is_task) = with_state(|state| {
+            state
+                .agents
+                .values()
+                .filter(|agent| agent.user_id == owner)
+                .find_map(|agent| {
+                    if agent.pending_tasks.iter().any(|p| p.request_id == request_id) {
+                        Some((agent.agent_id.clone(), true))
+                    } else if agent.pending_tool_calls.iter().any(|c| c.call_id == request_id) {
+                        Some((agent.agent_id.clone(), false))
+                    } else {
+                        None
+                    }
+                })
+        })
+        .ok_or_else(|| format!("No pending approval {} on your agents", request_id))?;
+
+        let outcome = match (is_task, decision) {
+            (false, ApprovalDecision::Approve) => ApprovalOutcome::ToolExecuted(ToolService::approve(&agent_id, owner, request_id).await?),
+            (false, ApprovalDecision::Reject) => {
+                ToolService::reject(&agent_id, owner, request_id)?;
+                ApprovalOutcome::Rejected
+            }
+            (true, decision) => {
+                let pending = Self::take_task(&agent_id, owner, request_id)?;
+                if time().saturating_sub(pending.requested_at) >= PENDING_TASK_TTL_NS {
+                    PaymentService::refund_task(&pending.task).await;
+                    return Err(format!("Approval {} expired before the owner responded", request_id));
+                }
+                match decision {
+                    ApprovalDecision::Approve => {
+                        let result = AgentFactory::execute_approved_task(&agent_id, pending.task.clone()).await;
+                        // A deferred task keeps its charge until it runs
+                        if !matches!(&result, Ok(r) if r.success || r.outcome == TaskOutcome::Deferred) {
+                            PaymentService::refund_task(&pending.task).await;
+                        }
+                        ApprovalOutcome::TaskExecuted(result?)
+                    }
+                    ApprovalDecision::Reject => {
+                        PaymentService::refund_task(&pending.task).await;
+                        ApprovalOutcome::Rejected
+                    }
+                }
+            }
+        };
+
+        let approved = decision == ApprovalDecision::Approve;
+        Metrics::increment_counter(if approved { "approvals_granted_total" } else { "approvals_rejected_total" });
+        EventService::publish(Some(&agent_id), owner, AgentEventKind::ApprovalResolved {
+            request_id: request_id.to_string(),
+            approved,
+        });
+        Ok(outcome)
+    }
+
+    fn take_task(agent_id: &str, owner: &str, request_id: &str) -> Result<PendingTask, String> {
+        let mut agent = AgentFactory::authorize(agent_id, owner)?;
+        let position = agent
+            .pending_tasks
+            .iter()
+            .position(|p| p.request_id == request_id)
+            .ok_or_else(|| format!("No pending approval {} on agent {}", request_id, agent_id))?;
+        let pending = agent.pending_tasks.remove(position);
+        Self::save(agent);
+        Ok(pending)
+    }
+
+    fn matching_rule(agent: &AutonomousAgent, target: RiskTarget, texts: &[&str]) -> Option<String> {
+        agent
+            .risk_rules
+            .iter()
+            .filter(|rule| rule.target == target)
+            .find(|rule| rule.keywords.is_empty() || texts.iter().any(|text| Self::contains_any(text, &rule.keywords)))
+            .map(|rule| rule.name.clone())
+    }
+
+    fn save(agent: AutonomousAgent) {
+        with_state_mut(|state| {
+            state.agents.insert(agent.agent_id.clone(), agent);
+        });
+    }
+
+    fn is_strict(agent: &AutonomousAgent) -> bool {
+        matches!(
+            agent.instruction.preferences.as_ref().map(|p| &p.safety_level),
+            Some(SafetyLevel::Strict)
+        )
+    }
+
+    fn contains_any<S: AsRef<str>>(text: &str, keywords: &[S]) -> bool {
+        let text = text.to_lowercase();
+        keywords
+            .iter()
+            .map(|k| k.as_ref().trim().to_lowercase())
+            .any(|k| !k.is_empty() && text.contains(&k))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyword_matching() {
+        assert!(ApprovalService::contains_any("Please DELETE the old records", SIGNIFICANT_ACTIONS));
+        assert!(!ApprovalService::contains_any("Summarize the report", SIGNIFICANT_ACTIONS));
+        assert!(!ApprovalService::contains_any("anything", &["  "]));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::infra::stable::{memory, Cbor, Memory, ARCHIVED_AGENTS_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentFactory, AgentStatus, AutonomousAgent};
+use crate::services::artifacts::ArtifactService;
+use crate::services::events::{AgentEventKind, EventService};
+use crate::services::knowledge::KnowledgeService;
+use crate::services::{with_state, with_state_mut, CertificationService};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::Duration;
+
+thread_local! {
+    static ARCHIVE: RefCell<StableBTreeMap<String, Cbor<ArchivedAgent>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(ARCHIVED_AGENTS_MEMORY_ID)));
+}
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+// Archived agents still count against the owner's quota for this long, so
+// archive/restore cannot be used to exceed it
+pub const ARCHIVE_GRACE_PERIOD_NS: u64 = 7 * NANOS_PER_DAY;
+pub const ARCHIVE_RETENTION_NS: u64 = 90 * NANOS_PER_DAY;
+const RETENTION_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// Also the form archived agents travel in when rebalanced to another shard
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ArchivedAgent {
+    pub agent_id: String,
+    pub user_id: String,
+    pub agent_type: String,
+    pub archived_at: u64,
+    pub original_bytes: u64,
+    pub compressed: Vec<u8>, // Deflated CBOR of the AutonomousAgent
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ArchivedAgentInfo {
+    pub agent_id: String,
+    pub agent_type: String,
+    pub archived_at: u64,
+    pub counts_toward_quota_until: u64,
+    pub restorable_until: u64,
+    pub original_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+/// Soft-deleted agents, compressed into stable memory. Task history, tool audit
+/// and knowledge documents are left in place so audit trails survive archival.
+pub struct ArchiveService;
+
+impl ArchiveService {
+    pub fn start_retention_timer() {
+        ic_cdk_timers::set_timer_interval(RETENTION_SWEEP_INTERVAL, || {
+            Self::purge_expired(time());
+        });
+    }
+
+    pub fn archive(agent_id: &str, owner: &str) -> Result<ArchivedAgentInfo, String> {
+        let agent = with_state(|state| state.agents.get(agent_id).cloned())
+            .ok_or_else(|| format!("Agent {} not found", agent_id))?;
+        if agent.user_id != owner {
+            return Err(format!("Not authorized to access agent {}", agent_id));
+        }
+        if Self::is_busy(agent_id) {
+            return Err(format!(
+                "Agent {} is running tasks or belongs to a coordinator; archive it once idle",
+                agent_id
+            ));
+        }
+
+        let archived = Self::pack(&agent, time())?;
+        let info = Self::info(&archived);
+
+        // Removing the agent is what stops execution: tasks and workflow steps
+        // look agents up in state.agents
+        with_state_mut(|state| state.agents.remove(agent_id));
+        ARCHIVE.with(|a| a.borrow_mut().insert(agent_id.to_string(), Cbor(archived)));
+        CertificationService::mark_dirty();
+
+        Metrics::increment_counter("agents_archived_total");
+        EventService::publish(Some(agent_id), owner, AgentEventKind::AgentArchived);
+        Ok(info)
+    }
+
+    /// Bring an archived agent back as Ready with its metrics and budget usage.
+    /// After the grace period it needs room in the owner's agent quota again.
+    pub async fn restore(agent_id: &str, owner: &str) -> Result<AutonomousAgent, String> {
+        let archived = Self::get(agent_id, owner)?;
+        let now = time();
+        let age = now.saturating_sub(archived.archived_at);
+        if age >= ARCHIVE_RETENTION_NS {
+            return Err(format!("Agent {} is past its retention window", agent_id));
+        }
+
+        let mut agent = Self::unpack(&archived)?;
+        if age >= ARCHIVE_GRACE_PERIOD_NS {
+            AgentFactory::validate_user_quotas(owner, &agent.instruction.subscription_tier).await?;
+        }
+        // Restored twice while the quota check was awaited
+        if ARCHIVE.with(|a| !a.borrow().contains_key(&agent_id.to_string())) {
+            return Err(format!("Archived agent {} not found", agent_id));
+        }
+        agent.status = AgentStatus::Ready;
+        agent.last_active = now;
+        agent.idle_flagged_at = None;
+
+        with_state_mut(|state| state.agents.insert(agent.agent_id.clone(), agent.clone()));
+        ARCHIVE.with(|a| a.borrow_mut().remove(&agent_id.to_string()));
+        CertificationService::mark_dirty();
+
+        Metrics::increment_counter("agents_restored_total");
+        EventService::publish(Some(agent_id), owner, AgentEventKind::AgentRestored);
+        Ok(agent)
+    }
+
+    /// Running tasks or a coordinator's member; such agents cannot leave
+    pub fn is_busy(agent_id: &str) -> bool {
+        with_state(|state| {
+            state.tasks_in_flight.get(agent_id).copied().unwrap_or(0) > 0
+                || state.agents.values().any(|a| a.member_ids.iter().any(|id| id == agent_id))
+        })
+    }
+
+    /// Compressed copy of `agent`, as stored in the archive and sent between canisters
+    pub fn pack(agent: &AutonomousAgent, archived_at: u64) -> Result<ArchivedAgent, String> {
+        let encoded = serde_cbor::to_vec(agent).map_err(|e| format!("Failed to encode agent: {}", e))?;
+        Ok(ArchivedAgent {
+            agent_id: agent.agent_id.clone(),
+            user_id: agent.user_id.clone(),
+            agent_type: format!("{:?}", agent.analysis.agent_configuration.agent_type),
+            archived_at,
+            original_bytes: encoded.len() as u64,
+            compressed: miniz_oxide::deflate::compress_to_vec(&encoded, COMPRESSION_LEVEL),
+        })
+    }
+
+    pub fn unpack(archived: &ArchivedAgent) -> Result<AutonomousAgent, String> {
+        let encoded = miniz_oxide::inflate::decompress_to_vec(&archived.compressed)
+            .map_err(|e| format!("Archived agent is corrupt: {:?}", e))?;
+        serde_cbor::from_slice(&encoded).map_err(|e| format!("Archived agent is corrupt: {}", e))
+    }
+
+    pub fn list(owner: &str) -> Vec<ArchivedAgentInfo> {
+        ARCHIVE.with(|a| {
+            a.borrow()
+                .iter()
+                .filter(|(_, archived)| archived.0.user_id == owner)
+                .map(|(_, archived)| Self::info(&archived.0))
+                .collect()
+        })
+    }
+
+    pub fn owned(owner: &str) -> Vec<ArchivedAgent> {
+        ARCHIVE.with(|a| {
+            a.borrow()
+                .iter()
+                .map(|(_, archived)| archived.0)
+                .filter(|archived| archived.user_id == owner)
+                .collect()
+        })
+    }
+
+    /// Up to `limit` archived agents, oldest first
+    pub fn oldest(limit: usize) -> Vec<ArchivedAgent> {
+        let mut archived: Vec<ArchivedAgent> = ARCHIVE.with(|a| a.borrow().iter().map(|(_, archived)| archived.0).collect());
+        archived.sort_by_key(|a| a.archived_at);
+        archived.truncate(limit);
+        archived
+    }
+
+    /// Drops the archive entry only; knowledge documents stay in place
+    pub fn remove(agent_id: &str) {
+        ARCHIVE.with(|a| a.borrow_mut().remove(&agent_id.to_string()));
+    }
+
+    pub fn is_archived(agent_id: &str) -> bool {
+        ARCHIVE.with(|a| a.borrow().contains_key(&agent_id.to_string()))
+    }
+
+    /// Takes over an archived agent from another shard, keeping its archival
+    /// time so grace period and retention carry over
+    pub fn import(archived: ArchivedAgent) -> Result<(), String> {
+        let known = with_state(|state| state.agents.contains_key(&archived.agent_id))
+            || ARCHIVE.with(|a| a.borrow().contains_key(&archived.agent_id));
+        if known {
+            return Err(format!("Agent {} already exists on this shard", archived.agent_id));
+        }
+        miniz_oxide::inflate::decompress_to_vec(&archived.compressed)
+            .map_err(|e| format!("Archived agent is corrupt: {:?}", e))?;
+        ARCHIVE.with(|a| a.borrow_mut().insert(archived.agent_id.clone(), Cbor(archived)));
+        Metrics::increment_counter("agents_imported_total");
+        Ok(())
+    }
+
+    /// Archived agents of `owner` still inside the quota grace period
+    pub fn count_in_grace_period(owner: &str, now: u64) -> usize {
+        ARCHIVE.with(|a| {
+            a.borrow()
+                .iter()
+                .filter(|(_, archived)| {
+                    archived.0.user_id == owner && now.saturating_sub(archived.0.archived_at) < ARCHIVE_GRACE_PERIOD_NS
+                })
+                .count()
+        })
+    }
+
+    /// Drop archives past retention together with their knowledge documents
+    /// and artifacts. Task history and tool audit entries expire on their own
+    /// schedules.
+    pub fn purge_expired(now: u64) -> u32 {
+        let expired: Vec<String> = ARCHIVE.with(|a| {
+            a.borrow()
+                .iter()
+                .filter(|(_, archived)| now.saturating_sub(archived.0.archived_at) >= ARCHIVE_RETENTION_NS)
+                .map(|(agent_id, _)| agent_id)
+                .collect()
+        });
+        for agent_id in &expired {
+            for document in KnowledgeService::list_documents(agent_id, None) {
+                let _ = KnowledgeService::delete(agent_id, &document.doc_id);
+            }
+            ArtifactService::forget_agent(agent_id);
+            ARCHIVE.with(|a| a.borrow_mut().remove(agent_id));
+        }
+        Metrics::add_to_counter("agents_purged_total", expired.len() as u64);
+        expired.len() as u32
+    }
+
+    fn get(agent_id: &str, owner: &str) -> Result<ArchivedAgent, String> {
+        let archived = ARCHIVE
+            .with(|a| a.borrow().get(&agent_id.to_string()).map(|archived| archived.0))
+            .ok_or_else(|| format!("Archived agent {} not found", agent_id))?;
+        if archived.user_id != owner {
+            return Err(format!("Not authorized to access agent {}", agent_id));
+        }
+        Ok(archived)
+    }
+
+    fn info(archived: &ArchivedAgent) -> ArchivedAgentInfo {
+        ArchivedAgentInfo {
+            agent_id: archived.agent_id.clone(),
+            agent_type: archived.agent_type.clone(),
+            archived_at: archived.archived_at,
+            counts_toward_quota_until: archived.archived_at + ARCHIVE_GRACE_PERIOD_NS,
+            restorable_until: archived.archived_at + ARCHIVE_RETENTION_NS,
+            original_bytes: archived.original_bytes,
+            stored_bytes: archived.compressed.len() as u64,
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::infra::stable::{memory, Cbor, Memory, ARTIFACTS_MEMORY_ID, ARTIFACT_CHUNKS_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentTask, AgentTaskResult};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+thread_local! {
+    static ARTIFACTS: RefCell<StableBTreeMap<String, Cbor<Artifact>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(ARTIFACTS_MEMORY_ID)));
+    // "{artifact_id}/{index:06}" -> raw bytes
+    static CHUNKS: RefCell<StableBTreeMap<String, Vec<u8>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(ARTIFACT_CHUNKS_MEMORY_ID)));
+}
+
+// Results above this are stored as an artifact and only a preview stays inline
+const INLINE_RESULT_BYTES: usize = 32 * 1024;
+const PREVIEW_CHARS: usize = 2_000;
+const CHUNK_BYTES: usize = 1_000_000; // One chunk per get_artifact reply
+const MAX_ARTIFACTS_PER_AGENT: usize = 50;
+const RESULT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ArtifactRef {
+    pub artifact_id: String,
+    pub name: String,
+    pub content_type: String,
+    pub size_bytes: u64,
+    pub chunk_count: u32,
+    pub sha256: String, // Of the whole content
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Artifact {
+    reference: ArtifactRef,
+    agent_id: String,
+    task_id: String,
+    chunk_sha256: Vec<String>,
+    created_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ArtifactPage {
+    pub artifact: ArtifactRef,
+    pub chunk_index: u32,
+    pub data: Vec<u8>,
+    pub sha256: String,           // Of this chunk
+    pub next_cursor: Option<u32>, // None after the last chunk
+}
+
+/// Task output too large to return inline. The full text is split into
+/// chunks in stable memory, each hashed, and the task result keeps a preview
+/// plus a reference; clients stream the chunks back through get_artifact and
+/// check them against the hashes. Only the newest artifacts of each agent are
+/// kept.
+pub struct ArtifactService;
+
+impl ArtifactService {
+    /// Move an oversized result into an artifact. Runs after signing, so the
+    /// signed result hash is the artifact's sha256.
+    pub fn offload(agent_id: &str, task: &AgentTask, result: &mut AgentTaskResult) {
+        if result.result.len() <= INLINE_RESULT_BYTES {
+            return;
+        }
+        let reference = Self::store(agent_id, &task.task_id, "result", RESULT_CONTENT_TYPE, result.result.as_bytes());
+        result.result = preview(&result.result, &reference);
+        result.artifacts.push(reference);
+        Metrics::increment_counter("artifacts_stored_total");
+    }
+
+    pub fn store(agent_id: &str, task_id: &str, name: &str, content_type: &str, content: &[u8]) -> ArtifactRef {
+        let now = time();
+        let sha256 = hex::encode(Sha256::digest(content));
+        let reference = ArtifactRef {
+            artifact_id: format!("artifact-{}-{}", now, &sha256[..12]),
+            name: name.to_string(),
+            content_type: content_type.to_string(),
+            size_bytes: content.len() as u64,
+            chunk_count: content.chunks(CHUNK_BYTES).count() as u32,
+            sha256,
+        };
+        let mut chunk_sha256 = Vec::new();
+        CHUNKS.with(|c| {
+            let mut chunks = c.borrow_mut();
+            for (index, chunk) in content.chunks(CHUNK_BYTES).enumerate() {
+                chunk_sha256.push(hex::encode(Sha256::digest(chunk)));
+                chunks.insert(Self::chunk_key(&reference.artifact_id, index as u32), chunk.to_vec());
+            }
+        });
+        let artifact = Artifact {
+            reference: reference.clone(),
+            agent_id: agent_id.to_string(),
+            task_id: task_id.to_string(),
+            chunk_sha256,
+            created_at: now,
+        };
+        ARTIFACTS.with(|a| a.borrow_mut().insert(reference.artifact_id.clone(), Cbor(artifact)));
+        Self::evict_oldest(agent_id);
+        reference
+    }
+
+    /// Agent the artifact belongs to, for the access check
+    pub fn agent_of(artifact_id: &str) -> Result<String, String> {
+        Ok(Self::artifact(artifact_id)?.agent_id)
+    }
+
+    /// Chunk `cursor` of the artifact, starting from the first
+    pub fn page(artifact_id: &str, cursor: Option<u32>) -> Result<ArtifactPage, String> {
+        let artifact = Self::artifact(artifact_id)?;
+        let chunk_index = cursor.unwrap_or(0);
+        if chunk_index >= artifact.reference.chunk_count {
+            return Err(format!(
+                "Artifact {} has {} chunks; cursor {} is past the end",
+                artifact_id, artifact.reference.chunk_count, chunk_index
+            ));
+        }
+        let data = CHUNKS
+            .with(|c| c.borrow().get(&Self::chunk_key(artifact_id, chunk_index)))
+            .ok_or_else(|| format!("Chunk {} of artifact {} is missing", chunk_index, artifact_id))?;
+        let next = chunk_index + 1;
+        Ok(ArtifactPage {
+            sha256: artifact.chunk_sha256[chunk_index as usize].clone(),
+            next_cursor: (next < artifact.reference.chunk_count).then_some(next),
+            artifact: artifact.reference,
+            chunk_index,
+            data,
+        })
+    }
+
+    pub fn forget_agent(agent_id: &str) {
+        let artifacts: Vec<Artifact> = ARTIFACTS.with(|a| {
+            a.borrow().iter().filter(|(_, artifact)| artifact.0.agent_id == agent_id).map(|(_, artifact)| artifact.0).collect()
+        });
+        for artifact in &artifacts {
+            Self::remove(artifact);
+        }
+    }
+
+    fn evict_oldest(agent_id: &str) {
+        let mut owned: Vec<Artifact> = ARTIFACTS.with(|a| {
+            a.borrow().iter().filter(|(_, artifact)| artifact.0.agent_id == agent_id).map(|(_, artifact)| artifact.0).collect()
+        });
+        if owned.len() <= MAX_ARTIFACTS_PER_AGENT {
+            return;
+        }
+        owned.sort_by_key(|artifact| artifact.created_at);
+        let overflow = owned.len() - MAX_ARTIFACTS_PER_AGENT;
+        for artifact in owned.iter().take(overflow) {
+            Self::remove(artifact);
+        }
+        Metrics::add_to_counter("artifacts_evicted_total", overflow as u64);
+    }
+
+    fn remove(artifact: &Artifact) {
+        let artifact_id = &artifact.reference.artifact_id;
+        CHUNKS.with(|c| {
+            let mut chunks = c.borrow_mut();
+            for index in 0..artifact.reference.chunk_count {
+                chunks.remove(&Self::chunk_key(artifact_id, index));
+            }
+        });
+        ARTIFACTS.with(|a| a.borrow_mut().remove(artifact_id));
+    }
+
+    fn artifact(artifact_id: &str) -> Result<Artifact, String> {
+        ARTIFACTS
+            .with(|a| a.borrow().get(&artifact_id.to_string()))
+            .map(|artifact| artifact.0)
+            .ok_or_else(|| format!("Artifact {} not found", artifact_id))
+    }
+
+    fn chunk_key(artifact_id: &str, index: u32) -> String {
+        format!("{}/{:06}", artifact_id, index)
+    }
+}
+
+/// The start of `text` and where the rest went
+fn preview(text: &str, reference: &ArtifactRef) -> String {
+    let head: String = text.chars().take(PREVIEW_CHARS).collect();
+    format!(
+        "{}\n\n[Output continues in artifact {} ({} bytes, {} chunks)]",
+        head, reference.artifact_id, reference.size_bytes, reference.chunk_count
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_keeps_the_head_and_names_the_artifact() {
+        let reference = ArtifactRef {
+            artifact_id: "artifact-1-abc".to_string(),
+            name: "result".to_string(),
+            content_type: RESULT_CONTENT_TYPE.to_string(),
+            size_bytes: 40_000,
+            chunk_count: 1,
+            sha256: String::new(),
+        };
+        let text = "é".repeat(20_000);
+        let preview = preview(&text, &reference);
+        assert!(preview.starts_with(&"é".repeat(PREVIEW_CHARS)));
+        assert!(!preview.starts_with(&"é".repeat(PREVIEW_CHARS + 1)));
+        assert!(preview.ends_with("[Output continues in artifact artifact-1-abc (40000 bytes, 1 chunks)]"));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentTask, AutonomousAgent};
+use crate::services::context_window::ContextWindow;
+use crate::services::knowledge::KnowledgeService;
+use crate::services::provenance::{sha256_hex, ProvenanceSource, SourceKind};
+
+/// Task context key holding a JSON array of "document:{doc_id}" and
+/// "memory:{key}" references
+pub const ATTACHMENTS_KEY: &str = "attachments";
+
+const MAX_ATTACHMENTS: usize = 10;
+// Used while no model is bound, and as a ceiling otherwise
+const MAX_ATTACHMENT_TOKENS: u64 = 8_000;
+// Less room than this left for an attachment leaves it out
+const MIN_EXCERPT_CHARS: usize = 200;
+const EXCERPT_MARK: &str = "…";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttachmentRef {
+    Document(String),
+    Memory(String),
+}
+
+impl AttachmentRef {
+    fn reference(&self) -> String {
+        match self {
+            AttachmentRef::Document(doc_id) => format!("document:{}", doc_id),
+            AttachmentRef::Memory(key) => format!("memory:{}", key),
+        }
+    }
+}
+
+/// An attachment's text, in parts that are included whole while they fit
+#[derive(Debug, Clone)]
+struct Loaded {
+    reference: String,
+    title: String,
+    parts: Vec<String>,
+}
+
+/// What made it into the prompt of one attachment
+#[derive(Debug, Clone, PartialEq)]
+struct Fitted {
+    reference: String,
+    title: String,
+    text: String,
+    complete: bool,
+}
+
+/// Documents from the agent's knowledge base and entries of its memory
+/// attached to a task by reference. Their text goes ahead of the task prompt
+/// in the order given, within what the context window leaves next to the
+/// prompt and the output; an attachment that only partly fits is cut to an
+/// excerpt, and one with no room left is dropped. Attachments that made it
+/// in are recorded as provenance sources.
+pub struct AttachmentService;
+
+impl AttachmentService {
+    pub fn refs(task: &AgentTask) -> Result<Vec<AttachmentRef>, String> {
+        let Some(raw) = task.context.get(ATTACHMENTS_KEY) else {
+            return Ok(Vec::new());
+        };
+        let entries: Vec<String> = serde_json::from_str(raw)
+            .map_err(|_| format!("Task context {} must be a JSON array of strings", ATTACHMENTS_KEY))?;
+        if entries.len() > MAX_ATTACHMENTS {
+            return Err(format!("Too many attachments. Maximum: {}", MAX_ATTACHMENTS));
+        }
+        entries.iter().map(|entry| parse_ref(entry)).collect()
+    }
+
+    /// `prompt` with the task's attachments ahead of it, and a source for
+    /// each attachment used
+    pub fn attach(
+        agent: &AutonomousAgent,
+        task: &AgentTask,
+        prompt: String,
+        model_id: Option<&str>,
+        max_tokens: Option<u32>,
+    ) -> Result<(String, Vec<ProvenanceSource>), String> {
+        let refs = Self::refs(task)?;
+        if refs.is_empty() {
+            return Ok((prompt, Vec::new()));
+        }
+        let loaded = refs.iter().map(|r| Self::load(agent, r)).collect::<Result<Vec<_>, _>>()?;
+
+        let budget_tokens = ContextWindow::prompt_budget(model_id, max_tokens)
+            .map_or(MAX_ATTACHMENT_TOKENS, |budget| budget.min(MAX_ATTACHMENT_TOKENS))
+            .saturating_sub(ContextWindow::estimate_tokens(&prompt));
+        let fitted = fit(loaded, budget_tokens as usize * 4);
+        Metrics::add_to_counter("task_attachments_used_total", fitted.len() as u64);
+        Metrics::add_to_counter("task_attachments_dropped_total", (refs.len() - fitted.len()) as u64);
+        if fitted.is_empty() {
+            return Ok((prompt, Vec::new()));
+        }
+
+        let mut block = String::from("Attached to this task:\n");
+        for attachment in &fitted {
+            let excerpt = if attachment.complete { "" } else { " (excerpt)" };
+            block.push_str(&format!("--- {}{} ---\n{}\n", attachment.title, excerpt, attachment.text));
+        }
+        let sources = fitted
+            .iter()
+            .map(|attachment| ProvenanceSource {
+                kind: SourceKind::Attachment,
+                reference: attachment.reference.clone(),
+                content_hash: sha256_hex(&attachment.text),
+            })
+            .collect();
+        Ok((format!("{}\n{}", block, prompt), sources))
+    }
+
+    fn load(agent: &AutonomousAgent, attachment: &AttachmentRef) -> Result<Loaded, String> {
+        match attachment {
+            AttachmentRef::Document(doc_id) => {
+                let (document, chunks) = KnowledgeService::document_chunks(&agent.agent_id, doc_id)?;
+                Ok(Loaded { reference: attachment.reference(), title: document.title, parts: chunks })
+            }
+            AttachmentRef::Memory(key) => {
+                let bytes = agent.memory.get(key).ok_or_else(|| format!("Memory key {} not found", key))?;
+                let text = String::from_utf8(bytes.clone()).map_err(|_| format!("Memory key {} does not hold text", key))?;
+                Ok(Loaded { reference: attachment.reference(), title: key.clone(), parts: vec![text] })
+            }
+        }
+    }
+}
+
+fn parse_ref(entry: &str) -> Result<AttachmentRef, String> {
+    match entry.split_once(':') {
+        Some(("document", doc_id)) if !doc_id.is_empty() => Ok(AttachmentRef::Document(doc_id.to_string())),
+        Some(("memory", key)) if !key.is_empty() => Ok(AttachmentRef::Memory(key.to_string())),
+        _ => Err(format!("Attachment {} must be document:{{doc_id}} or memory:{{key}}", entry)),
+    }
+}
+
+/// Whole parts while they fit, then an excerpt of the next part if enough
+/// room is left; later attachments get what earlier ones leave
+fn fit(attachments: Vec<Loaded>, mut budget_chars: usize) -> Vec<Fitted> {
+    let mut fitted = Vec::new();
+    for attachment in attachments {
+        let mut text = String::new();
+        let mut complete = true;
+        for part in &attachment.parts {
+            let needed = part.chars().count() + 1;
+            if needed <= budget_chars {
+                text.push_str(part);
+                text.push('\n');
+                budget_chars -= needed;
+                continue;
+            }
+            complete = false;
+            if budget_chars >= MIN_EXCERPT_CHARS {
+                let keep = budget_chars - EXCERPT_MARK.chars().count();
+                text.extend(part.chars().take(keep));
+                text.push_str(EXCERPT_MARK);
+                budget_chars = 0;
+            }
+            break;
+        }
+        let text = text.trim_end().to_string();
+        if !text.is_empty() {
+            fitted.push(Fitted { reference: attachment.reference, title: attachment.title, text, complete });
+        }
+    }
+    fitted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loaded(reference: &str, parts: &[usize]) -> Loaded {
+        Loaded {
+            reference: reference.to_string(),
+            title: reference.to_string(),
+            parts: parts.iter().map(|n| "x".repeat(*n)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_fit_cuts_to_excerpt_and_drops_what_has_no_room() {
+        let fitted = fit(vec![loaded("document:a", &[300, 300]), loaded("memory:b", &[500]), loaded("memory:c", &[10])], 700);
+        assert_eq!(fitted.len(), 2);
+        assert!(fitted[0].complete);
+        assert_eq!(fitted[0].text.chars().count(), 601);
+        // 98 chars left for b is under the excerpt minimum, so b is dropped and c fits whole
+        assert_eq!(fitted[1].reference, "memory:c");
+        assert!(fitted[1].complete);
+
+        let fitted = fit(vec![loaded("memory:b", &[500])], 300);
+        assert!(!fitted[0].complete);
+        assert!(fitted[0].text.ends_with(EXCERPT_MARK));
+        assert_eq!(fitted[0].text.chars().count(), 300);
+    }
+
+    #[test]
+    fn test_parse_ref() {
+        assert_eq!(parse_ref("document:doc-1-2"), Ok(AttachmentRef::Document("doc-1-2".to_string())));
+        assert_eq!(parse_ref("memory:brand:voice"), Ok(AttachmentRef::Memory("brand:voice".to_string())));
+        assert!(parse_ref("memory:").is_err());
+        assert!(parse_ref("file:x").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::infra::stable::{
+    memory, Cbor, Memory, ATTESTATION_AUDIT_MEMORY_ID, ATTESTATION_NONCES_MEMORY_ID, TRUSTED_COORDINATORS_MEMORY_ID,
+};
+use crate::infra::Metrics;
+use candid::{CandidType, Principal};
+use hmac::{Hmac, Mac};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::cell::RefCell;
+use std::time::Duration;
+
+thread_local! {
+    static COORDINATORS: RefCell<StableBTreeMap<Principal, Cbor<TrustedCoordinator>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(TRUSTED_COORDINATORS_MEMORY_ID)));
+    // "{coordinator}/{nonce}" -> expires_at; kept until the envelope expires
+    static NONCES: RefCell<StableBTreeMap<String, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(ATTESTATION_NONCES_MEMORY_ID)));
+    static AUDIT: RefCell<StableBTreeMap<u64, Cbor<AttestationAuditEntry>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(ATTESTATION_AUDIT_MEMORY_ID)));
+}
+
+const DOMAIN_TAG: &[u8] = b"ohms-agent-attestation-v1";
+// Envelopes must be short-lived so the nonce store stays small
+const MAX_ENVELOPE_LIFETIME_NS: u64 = 5 * 60 * 1_000_000_000;
+const MAX_NONCE_LEN: usize = 64;
+const MAX_OUTSTANDING_NONCES: u64 = 100_000;
+const MIN_SECRET_LEN: usize = 32;
+const MAX_AUDIT_ENTRIES: u64 = 10_000;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+const NONCE_PRUNE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TrustedCoordinator {
+    secret: String, // Shared HMAC key; never returned by any endpoint
+    registered_at: u64,
+    registered_by: String,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct CoordinatorInfo {
+    pub canister_id: Principal,
+    pub registered_at: u64,
+    pub registered_by: String,
+}
+
+/// A coordinator's statement that it is calling on behalf of `user`
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct CallerEnvelope {
+    pub coordinator: Principal, // Must be the calling canister
+    pub user: Principal,
+    pub nonce: String,   // Unique per coordinator while the envelope is live
+    pub expires_at: u64, // Nanoseconds; at most 5 minutes ahead
+    pub signature: String, // Hex HMAC-SHA256, see AttestationService
+}
+
+/// Accepted envelope, kept so actions taken by coordinators can be traced
+/// back to the users they acted for
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct AttestationAuditEntry {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub coordinator: Principal,
+    pub user: Principal,
+    pub nonce: String,
+    pub method: String,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct AttestationAuditPage {
+    pub entries: Vec<AttestationAuditEntry>,
+    pub next_cursor: Option<u64>,
+}
+
+/// Lets trusted coordinator canisters act on behalf of the users who called
+/// them. Each call carries an envelope naming the user, signed with the
+/// coordinator's shared key: the signature is HMAC-SHA256 over the tag
+/// "ohms-agent-attestation-v1" followed by the coordinator and user principal
+/// bytes, the nonce and expires_at (big-endian u64 nanoseconds), each field
+/// prefixed with its big-endian u32 byte length. A nonce is accepted once.
+pub struct AttestationService;
+
+impl AttestationService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(NONCE_PRUNE_INTERVAL, Self::prune_nonces);
+    }
+
+    pub fn register_coordinator(canister_id: Principal, secret: String, admin: Principal) -> Result<CoordinatorInfo, String> {
+        if canister_id == Principal::anonymous() || canister_id == Principal::management_canister() {
+            return Err("Invalid coordinator canister id".to_string());
+        }
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(format!("Secret must be at least {} bytes", MIN_SECRET_LEN));
+        }
+        let coordinator = TrustedCoordinator {
+            secret,
+            registered_at: time(),
+            registered_by: admin.to_text(),
+        };
+        let info = Self::info(canister_id, &coordinator);
+        COORDINATORS.with(|c| c.borrow_mut().insert(canister_id, Cbor(coordinator)));
+        Ok(info)
+    }
+
+    pub fn remove_coordinator(canister_id: Principal) -> Result<(), String> {
+        COORDINATORS
+            .with(|c| c.borrow_mut().remove(&canister_id))
+            .map(|_| ())
+            .ok_or_else(|| format!("Coordinator {} not found", canister_id))
+    }
+
+    pub fn list_coordinators() -> Vec<CoordinatorInfo> {
+        COORDINATORS.with(|c| c.borrow().iter().map(|(id, coordinator)| Self::info(id, &coordinator.0)).collect())
+    }
+
+    /// Check an envelope presented by `caller` for `method` and consume its
+    /// nonce. Returns the attested user.
+    pub fn verify(envelope: &CallerEnvelope, caller: Principal, method: &str) -> Result<Principal, String> {
+        if envelope.coordinator != caller {
+            return Err("Not authorized: envelope was issued for another coordinator".to_string());
+        }
+        let coordinator = COORDINATORS
+            .with(|c| c.borrow().get(&caller))
+            .ok_or_else(|| "Not authorized: caller is not a trusted coordinator".to_string())?
+            .0;
+        if envelope.user == Principal::anonymous() {
+            return Err("Invalid envelope: user cannot be anonymous".to_string());
+        }
+        if envelope.nonce.is_empty() || envelope.nonce.len() > MAX_NONCE_LEN {
+            return Err(format!("Invalid envelope: nonce must be 1 to {} bytes", MAX_NONCE_LEN));
+        }
+        let now = time();
+        Self::check_expiry(envelope.expires_at, now)?;
+        if !Self::signature_valid(&coordinator.secret, envelope) {
+            Metrics::increment_counter("attestation_rejected_total");
+            return Err("Invalid envelope signature".to_string());
+        }
+
+        let nonce_key = format!("{}/{}", caller, envelope.nonce);
+        NONCES.with(|n| {
+            let mut nonces = n.borrow_mut();
+            if nonces.contains_key(&nonce_key) {
+                return Err("Envelope nonce has already been used".to_string());
+            }
+            if nonces.len() >= MAX_OUTSTANDING_NONCES {
+                return Err("Too many live envelopes; retry later".to_string());
+            }
+            nonces.insert(nonce_key, envelope.expires_at);
+            Ok(())
+        })?;
+
+        Self::audit(envelope, method, now);
+        Metrics::increment_counter("attested_calls_total");
+        Ok(envelope.user)
+    }
+
+    /// Accepted envelopes after `cursor`, oldest first
+    pub fn list_audit(cursor: Option<u64>, limit: Option<u32>) -> AttestationAuditPage {
+        let limit = limit.map_or(DEFAULT_PAGE_SIZE, |l| (l as usize).clamp(1, MAX_PAGE_SIZE));
+        let start = cursor.map_or(0, |c| c + 1);
+        AUDIT.with(|a| {
+            let mut entries: Vec<AttestationAuditEntry> = a.borrow().range(start..).take(limit + 1).map(|(_, e)| e.0).collect();
+            let next_cursor = if entries.len() > limit {
+                entries.truncate(limit);
+                entries.last().map(|e| e.sequence)
+            } else {
+                None
+            };
+            AttestationAuditPage { entries, next_cursor }
+        })
+    }
+
+    pub fn audit_for(user: &Principal) -> Vec<AttestationAuditEntry> {
+        AUDIT.with(|a| a.borrow().iter().map(|(_, e)| e.0).filter(|e| e.user == *user).collect())
+    }
+
+    /// Entries made on `user`'s behalf keep their place in the log, with the
+    /// anonymous principal standing in for the user and the nonce dropped
+    pub fn erase_user(user: &Principal) -> u32 {
+        let erased = Self::audit_for(user);
+        AUDIT.with(|a| {
+            let mut audit = a.borrow_mut();
+            for mut entry in erased.iter().cloned() {
+                entry.user = Principal::anonymous();
+                entry.nonce = String::new();
+                audit.insert(entry.sequence, Cbor(entry));
+            }
+        });
+        erased.len() as u32
+    }
+
+    fn audit(envelope: &CallerEnvelope, method: &str, now: u64) {
+        AUDIT.with(|a| {
+            let mut audit = a.borrow_mut();
+            let sequence = audit.last_key_value().map(|(seq, _)| seq + 1).unwrap_or(0);
+            audit.insert(
+                sequence,
+                Cbor(AttestationAuditEntry {
+                    sequence,
+                    timestamp: now,
+                    coordinator: envelope.coordinator,
+                    user: envelope.user,
+                    nonce: envelope.nonce.clone(),
+                    method: method.to_string(),
+                }),
+            );
+            if sequence >= MAX_AUDIT_ENTRIES {
+                audit.remove(&(sequence - MAX_AUDIT_ENTRIES));
+            }
+        });
+    }
+
+    fn prune_nonces() {
+        let now = time();
+        NONCES.with(|n| {
+            let mut nonces = n.borrow_mut();
+            let expired: Vec<String> = nonces.iter().filter(|(_, expires_at)| *expires_at <= now).map(|(k, _)| k).collect();
+            for key in expired {
+                nonces.remove(&key);
+            }
+        });
+    }
+
+    fn check_expiry(expires_at: u64, now: u64) -> Result<(), String> {
+        if expires_at <= now {
+            return Err("Envelope has expired".to_string());
+        }
+        if expires_at - now > MAX_ENVELOPE_LIFETIME_NS {
+            return Err("Invalid envelope: expires_at must be at most 5 minutes ahead".to_string());
+        }
+        Ok(())
+    }
+
+    fn signature_valid(secret: &str, envelope: &CallerEnvelope) -> bool {
+        let Ok(signature) = hex::decode(&envelope.signature) else {
+            return false;
+        };
+        Self::mac(secret, envelope).verify_slice(&signature).is_ok()
+    }
+
+    fn mac(secret: &str, envelope: &CallerEnvelope) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+        let fields: [&[u8]; 5] = [
+            DOMAIN_TAG,
+            envelope.coordinator.as_slice(),
+            envelope.user.as_slice(),
+            envelope.nonce.as_bytes(),
+            &envelope.expires_at.to_be_bytes(),
+        ];
+        for field in fields {
+            mac.update(&(field.len() as u32).to_be_bytes());
+            mac.update(field);
+        }
+        mac
+    }
+
+    fn info(canister_id: Principal, coordinator: &TrustedCoordinator) -> CoordinatorInfo {
+        CoordinatorInfo {
+            canister_id,
+            registered_at: coordinator.registered_at,
+            registered_by: coordinator.registered_by.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn envelope(user: u8) -> CallerEnvelope {
+        CallerEnvelope {
+            coordinator: Principal::from_slice(&[1]),
+            user: Principal::from_slice(&[user]),
+            nonce: "n-1".to_string(),
+            expires_at: 1_000,
+            signature: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_signature_binds_every_field() {
+        let mut signed = envelope(2);
+        signed.signature = hex::encode(AttestationService::mac(SECRET, &signed).finalize().into_bytes());
+        assert!(AttestationService::signature_valid(SECRET, &signed));
+        assert!(!AttestationService::signature_valid("another secret of at least 32 bytes", &signed));
+
+        let mut forged = signed.clone();
+        forged.user = Principal::from_slice(&[3]);
+        assert!(!AttestationService::signature_valid(SECRET, &forged));
+        let mut forged = signed.clone();
+        forged.expires_at += 1;
+        assert!(!AttestationService::signature_valid(SECRET, &forged));
+        forged.signature = "not hex".to_string();
+        assert!(!AttestationService::signature_valid(SECRET, &forged));
+    }
+
+    #[test]
+    fn test_expiry_window() {
+        assert!(AttestationService::check_expiry(100, 100).is_err());
+        assert!(AttestationService::check_expiry(101, 100).is_ok());
+        assert!(AttestationService::check_expiry(100 + MAX_ENVELOPE_LIFETIME_NS + 1, 100).is_err());
+    }
+}
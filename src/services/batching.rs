@@ -0,0 +1,248 @@
+use crate::infra::resilience::sleep;
+use crate::infra::{Faults, Lane, Metrics, Resilience, Scheduler};
+use crate::services::dfinity_llm::QuantizedModel;
+use crate::services::inference::LLM_TARGET;
+use crate::services::journal::{CallIntent, CallKind, JournalService};
+use crate::services::keepalive::KeepaliveService;
+use crate::services::outage::OutageService;
+use crate::services::SettingsService;
+use candid::{CandidType, Principal};
+use ic_llm::ChatMessage as LlmChatMessage;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+thread_local! {
+    // Batches still accepting requests, by batch_key
+    static OPEN: RefCell<HashMap<String, Rc<RefCell<Batch>>>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct BatchingStatus {
+    pub enabled: bool,
+    pub window_ms: u64,
+    pub max_batch_size: u32,
+    pub open_batches: u32,
+    pub requests: u64,    // Requests seen while batching was enabled
+    pub llm_calls: u64,   // Calls those requests were served by
+    pub coalesced: u64,   // Requests that shared another request's call
+    pub mean_batch_size: f64,
+}
+
+#[derive(Default)]
+struct Batch {
+    size: u32,
+    result: Option<Result<String, String>>,
+    wakers: Vec<Waker>,
+}
+
+/// Optional micro-batching of LLM calls, enabled by AgentConfig.batching.
+/// ic_llm takes one conversation per call, so only identical requests can
+/// share a call: the first waits out the window, then makes the call under
+/// the scheduler for everyone who joined in the meantime. Requests join
+/// until the reply arrives or the batch is full. Distinct requests are
+/// still interleaved by the scheduler, one slot each. Requests only join
+/// batches of their own lane so Critical work never waits on a Standard
+/// call.
+pub struct BatchingService;
+
+impl BatchingService {
+    /// Reply text of one chat call, shared with identical concurrent requests
+    /// when batching is enabled. Errors are ready to surface: a full
+    /// scheduler queue as is, LLM failures with the outage retry hint.
+    pub async fn chat(
+        model: &QuantizedModel,
+        messages: Vec<LlmChatMessage>,
+        principal: Principal,
+        lane: Lane,
+    ) -> Result<String, String> {
+        KeepaliveService::observe_llm_call();
+        let config = SettingsService::settings().config.batching;
+        let (Some(config), Some(key)) = (config, batch_key(model, &messages, lane)) else {
+            return Self::send(model, messages, principal, lane).await;
+        };
+        Metrics::increment_counter("llm_batch_requests_total");
+
+        let joined = OPEN.with(|o| {
+            let open = o.borrow();
+            let batch = open.get(&key)?;
+            let mut state = batch.borrow_mut();
+            if state.result.is_some() || state.size >= config.max_batch_size {
+                return None;
+            }
+            state.size += 1;
+            Some(batch.clone())
+        });
+        if let Some(batch) = joined {
+            Metrics::increment_counter("llm_batch_coalesced_total");
+            return Follow { batch }.await;
+        }
+
+        // A full batch with the same key is replaced; it keeps running for its members
+        let batch = Rc::new(RefCell::new(Batch { size: 1, ..Batch::default() }));
+        OPEN.with(|o| o.borrow_mut().insert(key.clone(), batch.clone()));
+        let lead = Lead { key, batch };
+        if config.window_ms > 0 {
+            sleep(Duration::from_millis(config.window_ms)).await;
+        }
+        let result = Self::send(model, messages, principal, lane).await;
+        lead.finish(result.clone());
+        result
+    }
+
+    /// Minimal chat call for the keepalive timer, outside batching so it
+    /// never joins or delays a caller's request
+    pub async fn warm_up(model: &QuantizedModel) -> Result<(), String> {
+        let messages = vec![LlmChatMessage::User { content: "ping".to_string() }];
+        Self::send(model, messages, ic_cdk::api::id(), Lane::Standard).await.map(|_| ())
+    }
+
+    pub fn status() -> BatchingStatus {
+        let config = SettingsService::settings().config.batching;
+        let llm_calls = Metrics::get_counter("llm_batch_calls_total");
+        let requests = Metrics::get_counter("llm_batch_requests_total");
+        BatchingStatus {
+            enabled: config.is_some(),
+            window_ms: config.map_or(0, |c| c.window_ms),
+            max_batch_size: config.map_or(1, |c| c.max_batch_size),
+            open_batches: OPEN.with(|o| o.borrow().len() as u32),
+            requests,
+            llm_calls,
+            coalesced: Metrics::get_counter("llm_batch_coalesced_total"),
+            mean_batch_size: if llm_calls == 0 { 0.0 } else { requests as f64 / llm_calls as f64 },
+        }
+    }
+
+    async fn send(
+        model: &QuantizedModel,
+        messages: Vec<LlmChatMessage>,
+        principal: Principal,
+        lane: Lane,
+    ) -> Result<String, String> {
+        let _permit = Scheduler::acquire(principal, lane).await?;
+        Faults::llm_call(LLM_TARGET).map_err(|e| OutageService::unavailable_error(&e))?;
+        #[cfg(feature = "testing")]
+        if let Some(reply) = crate::infra::testing::TestSeams::llm_reply(&messages) {
+            return reply.map_err(|e| OutageService::unavailable_error(&e));
+        }
+        let intent = CallIntent {
+            kind: CallKind::Llm,
+            target: LLM_TARGET.to_string(),
+            method: "chat".to_string(),
+            principal: Some(principal),
+            billable_tokens: input_tokens(&messages),
+        };
+        let response = JournalService::record(
+            intent,
+            Resilience::guard(LLM_TARGET, ic_llm::chat(model.to_llm_model()).with_messages(messages).send()),
+        )
+        .await
+        .map_err(|e| OutageService::unavailable_error(&e))?;
+        Ok(response.message.content.unwrap_or_default())
+    }
+}
+
+/// Four chars per token, as quota accounting estimates
+fn input_tokens(messages: &[LlmChatMessage]) -> u64 {
+    let chars: usize = messages
+        .iter()
+        .map(|message| match message {
+            LlmChatMessage::User { content } | LlmChatMessage::System { content } => content.len(),
+            LlmChatMessage::Assistant(assistant) => assistant.content.as_ref().map_or(0, |c| c.len()),
+            _ => 0,
+        })
+        .sum();
+    (chars / 4) as u64
+}
+
+/// Requests with the same key get the same call: lane, model and the
+/// candid encoding of the messages. None when the messages cannot be encoded.
+fn batch_key(model: &QuantizedModel, messages: &[LlmChatMessage], lane: Lane) -> Option<String> {
+    let encoded = candid::encode_one(messages).ok()?;
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{:?}/{:?}/", lane, model).as_bytes());
+    hasher.update(&encoded);
+    Some(hex::encode(hasher.finalize()))
+}
+
+/// The request making a batch's call. Dropped without a result (the caller
+/// trapped or was cancelled), it fails the batch so members are not left waiting.
+struct Lead {
+    key: String,
+    batch: Rc<RefCell<Batch>>,
+}
+
+impl Lead {
+    fn finish(&self, result: Result<String, String>) {
+        let (size, wakers) = {
+            let mut state = self.batch.borrow_mut();
+            if state.result.is_some() {
+                return;
+            }
+            state.result = Some(result);
+            (state.size, std::mem::take(&mut state.wakers))
+        };
+        OPEN.with(|o| {
+            let mut open = o.borrow_mut();
+            if open.get(&self.key).is_some_and(|batch| Rc::ptr_eq(batch, &self.batch)) {
+                open.remove(&self.key);
+            }
+        });
+        Metrics::increment_counter("llm_batch_calls_total");
+        Metrics::record_histogram("llm_batch_size", size as f64);
+        // Members resume in their own message, as scheduler waiters do
+        for waker in wakers {
+            ic_cdk_timers::set_timer(Duration::ZERO, move || waker.wake());
+        }
+    }
+}
+
+impl Drop for Lead {
+    fn drop(&mut self) {
+        self.finish(Err(OutageService::unavailable_error("the shared LLM call was cancelled")));
+    }
+}
+
+/// A request waiting on another request's call
+struct Follow {
+    batch: Rc<RefCell<Batch>>,
+}
+
+impl Future for Follow {
+    type Output = Result<String, String>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.batch.borrow_mut();
+        match &state.result {
+            Some(result) => Poll::Ready(result.clone()),
+            None => {
+                state.wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(content: &str) -> Vec<LlmChatMessage> {
+        vec![LlmChatMessage::User { content: content.to_string() }]
+    }
+
+    #[test]
+    fn test_batch_key_separates_lanes_and_messages() {
+        let model = QuantizedModel::Llama3_1_8B;
+        let key = batch_key(&model, &user("hello"), Lane::Standard);
+        assert!(key.is_some());
+        assert_eq!(key, batch_key(&model, &user("hello"), Lane::Standard));
+        assert_ne!(key, batch_key(&model, &user("hello"), Lane::Critical));
+        assert_ne!(key, batch_key(&model, &user("hello!"), Lane::Standard));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::domain::{DecodeParams, InferenceRequest};
+use crate::infra::stable::{memory, Cbor, Memory, BENCHMARK_RESULTS_MEMORY_ID, BENCHMARK_SUITES_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::{with_state, CertificationService, InferenceService, ModelPoolService};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    static SUITES: RefCell<StableBTreeMap<String, Cbor<BenchmarkSuite>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BENCHMARK_SUITES_MEMORY_ID)));
+    // model_id -> runs, oldest first
+    static RESULTS: RefCell<StableBTreeMap<String, Cbor<Vec<BenchmarkResult>>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BENCHMARK_RESULTS_MEMORY_ID)));
+    // model_id -> start time of the run in progress
+    static RUNNING: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
+}
+
+pub const BUILTIN_SUITE: &str = "smoke";
+const MAX_CASES: usize = 50;
+const MAX_RUNS_PER_MODEL: usize = 20;
+const MAX_PROMPT_CHARS: usize = 4_000;
+// A run whose inference call trapped never clears its flag; it stops blocking after this
+const RUN_TIMEOUT_NS: u64 = 15 * 60 * 1_000_000_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct BenchmarkCase {
+    pub prompt: String,
+    // Case-insensitive substring the output must contain; `a|b` accepts either
+    pub expected_pattern: String,
+    pub max_tokens: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct BenchmarkSuite {
+    pub name: String,
+    pub cases: Vec<BenchmarkCase>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct BenchmarkCaseResult {
+    pub passed: bool,
+    pub latency_ms: u64,
+    pub tokens: u32,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct BenchmarkResult {
+    pub model_id: String,
+    pub manifest_digest: String,
+    pub suite: String,
+    pub run_at: u64,
+    pub pass_rate: f32,
+    pub mean_latency_ms: u64,
+    pub max_latency_ms: u64,
+    pub mean_tokens: f32,
+    pub total_tokens: u64,
+    pub cases: Vec<BenchmarkCaseResult>, // In suite order
+}
+
+/// Latest run of a suite against a model, as listed in the model catalog
+#[derive(Debug, Clone, CandidType)]
+pub struct BenchmarkSummary {
+    pub model_id: String,
+    pub suite: String,
+    pub pass_rate: f32,
+    pub mean_latency_ms: u64,
+    pub mean_tokens: f32,
+    pub run_at: u64,
+}
+
+/// Measures output quality of a served model: runs a stored suite of prompts
+/// through inference and checks each output against its expected pattern
+pub struct BenchmarkService;
+
+impl BenchmarkService {
+    pub fn save_suite(suite: BenchmarkSuite) -> Result<(), String> {
+        if suite.name.trim().is_empty() || suite.name == BUILTIN_SUITE {
+            return Err(format!("Suite name must be non-empty and not {}", BUILTIN_SUITE));
+        }
+        if suite.cases.is_empty() || suite.cases.len() > MAX_CASES {
+            return Err(format!("A suite needs 1 to {} cases", MAX_CASES));
+        }
+        if suite.cases.iter().any(|c| c.prompt.is_empty() || c.prompt.chars().count() > MAX_PROMPT_CHARS) {
+            return Err(format!("Prompts must be 1 to {} characters", MAX_PROMPT_CHARS));
+        }
+        if suite.cases.iter().any(|c| c.expected_pattern.split('|').all(|p| p.trim().is_empty())) {
+            return Err("Every case needs a non-empty expected_pattern".to_string());
+        }
+        SUITES.with(|s| s.borrow_mut().insert(suite.name.clone(), Cbor(suite)));
+        Ok(())
+    }
+
+    pub fn list_suites() -> Vec<BenchmarkSuite> {
+        let mut suites = vec![Self::builtin_suite()];
+        suites.extend(SUITES.with(|s| s.borrow().iter().map(|(_, suite)| suite.0).collect::<Vec<_>>()));
+        suites
+    }
+
+    pub async fn run(model_id: &str, suite_name: &str) -> Result<BenchmarkResult, String> {
+        let manifest_digest = with_state(|s| ModelPoolService::binding(s, model_id).map(|b| b.manifest_digest.clone()))
+            .ok_or_else(|| format!("Model {} is neither bound nor in the serving pool", model_id))?;
+        let suite = Self::suite(suite_name).ok_or_else(|| format!("Benchmark suite {} not found", suite_name))?;
+        let now = time();
+        let already_running = RUNNING.with(|r| {
+            let mut running = r.borrow_mut();
+            if running.get(model_id).is_some_and(|started| now.saturating_sub(*started) < RUN_TIMEOUT_NS) {
+                return true;
+            }
+            running.insert(model_id.to_string(), now);
+            false
+        });
+        if already_running {
+            return Err(format!("A benchmark of {} is already running", model_id));
+        }
+
+        let mut cases = Vec::with_capacity(suite.cases.len());
+        for (index, case) in suite.cases.iter().enumerate() {
+            let started = time();
+            let request = InferenceRequest {
+                seed: None,
+                prompt: case.prompt.clone(),
+                decode_params: DecodeParams { max_tokens: case.max_tokens, ..DecodeParams::default() },
+                msg_id: format!("bench-{}-{}-{}", suite.name, started, index),
+                model_id: Some(model_id.to_string()),
+                structured_output: None,
+            };
+            let outcome = InferenceService::process_inference(request).await;
+            let latency_ms = (time() - started) / 1_000_000;
+            cases.push(match outcome {
+                Ok(response) => BenchmarkCaseResult {
+                    passed: Self::matches(&response.generated_text, &case.expected_pattern),
+                    latency_ms,
+                    tokens: response.tokens.len() as u32,
+                    error: None,
+                },
+                Err(e) => BenchmarkCaseResult { passed: false, latency_ms, tokens: 0, error: Some(e) },
+            });
+        }
+        RUNNING.with(|r| r.borrow_mut().remove(model_id));
+
+        let result = Self::summarize(model_id.to_string(), manifest_digest, suite.name, time(), cases);
+        RESULTS.with(|r| {
+            let mut results = r.borrow_mut();
+            let mut runs = results.get(&result.model_id).map(|runs| runs.0).unwrap_or_default();
+            runs.push(result.clone());
+            if runs.len() > MAX_RUNS_PER_MODEL {
+                runs.drain(..runs.len() - MAX_RUNS_PER_MODEL);
+            }
+            results.insert(result.model_id.clone(), Cbor(runs));
+        });
+        CertificationService::mark_dirty();
+        Metrics::increment_counter("benchmark_runs_total");
+        Metrics::set_gauge("benchmark_last_pass_rate", result.pass_rate as f64);
+        Ok(result)
+    }
+
+    /// Runs for the model, newest first
+    pub fn results(model_id: &str) -> Vec<BenchmarkResult> {
+        let mut runs = RESULTS.with(|r| r.borrow().get(&model_id.to_string()).map(|runs| runs.0).unwrap_or_default());
+        runs.reverse();
+        runs
+    }
+
+    /// Latest run per model and suite
+    pub fn summaries() -> Vec<BenchmarkSummary> {
+        RESULTS.with(|r| {
+            let mut summaries: Vec<BenchmarkSummary> = Vec::new();
+            for (_, runs) in r.borrow().iter() {
+                for run in runs.0.iter().rev() {
+                    if summaries.iter().any(|s| s.model_id == run.model_id && s.suite == run.suite) {
+                        continue;
+                    }
+                    summaries.push(BenchmarkSummary {
+                        model_id: run.model_id.clone(),
+                        suite: run.suite.clone(),
+                        pass_rate: run.pass_rate,
+                        mean_latency_ms: run.mean_latency_ms,
+                        mean_tokens: run.mean_tokens,
+                        run_at: run.run_at,
+                    });
+                }
+            }
+            summaries
+        })
+    }
+
+    fn suite(name: &str) -> Option<BenchmarkSuite> {
+        if name == BUILTIN_SUITE {
+            return Some(Self::builtin_suite());
+        }
+        SUITES.with(|s| s.borrow().get(&name.to_string()).map(|suite| suite.0))
+    }
+
+    /// Small sanity suite available without configuration
+    fn builtin_suite() -> BenchmarkSuite {
+        let case = |prompt: &str, expected_pattern: &str| BenchmarkCase {
+            prompt: prompt.to_string(),
+            expected_pattern: expected_pattern.to_string(),
+            max_tokens: Some(64),
+        };
+        BenchmarkSuite {
+            name: BUILTIN_SUITE.to_string(),
+            cases: vec![
+                case("What is 2 + 3? Answer with the number only.", "5|five"),
+                case("What is the capital of France? Answer in one word.", "paris"),
+                case("Reply with the word OK and nothing else.", "ok"),
+            ],
+        }
+    }
+
+    fn matches(output: &str, pattern: &str) -> bool {
+        let output = output.to_lowercase();
+        pattern
+            .split('|')
+            .map(|p| p.trim().to_lowercase())
+            .any(|p| !p.is_empty() && output.contains(&p))
+    }
+
+    fn summarize(
+        model_id: String,
+        manifest_digest: String,
+        suite: String,
+        run_at: u64,
+        cases: Vec<BenchmarkCaseResult>,
+    ) -> BenchmarkResult {
+        let count = cases.len().max(1) as u64;
+        let total_tokens: u64 = cases.iter().map(|c| c.tokens as u64).sum();
+        BenchmarkResult {
+            model_id,
+            manifest_digest,
+            suite,
+            run_at,
+            pass_rate: cases.iter().filter(|c| c.passed).count() as f32 / count as f32,
+            mean_latency_ms: cases.iter().map(|c| c.latency_ms).sum::<u64>() / count,
+            max_latency_ms: cases.iter().map(|c| c.latency_ms).max().unwrap_or(0),
+            mean_tokens: total_tokens as f32 / count as f32,
+            total_tokens,
+            cases,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_and_summary() {
+        assert!(BenchmarkService::matches("The answer is Five.", "5|five"));
+        assert!(!BenchmarkService::matches("Lyon", "paris"));
+
+        let case = |passed, latency_ms, tokens| BenchmarkCaseResult { passed, latency_ms, tokens, error: None };
+        let result = BenchmarkService::summarize(
+            "m".to_string(),
+            "d".to_string(),
+            "s".to_string(),
+            0,
+            vec![case(true, 100, 4), case(false, 300, 8)],
+        );
+        assert_eq!(result.pass_rate, 0.5);
+        assert_eq!(result.mean_latency_ms, 200);
+        assert_eq!(result.max_latency_ms, 300);
+        assert_eq!(result.total_tokens, 12);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::infra::stable::{
+    memory, Cbor, Memory, BILLING_ACCRUALS_MEMORY_ID, BILLING_OUTBOX_MEMORY_ID, BILLING_TOTALS_MEMORY_ID,
+};
+use crate::infra::{Guards, Metrics, Resilience};
+use crate::services::dfinity_llm::QuantizedModel;
+use crate::services::pricing::PricingService;
+use crate::services::with_state;
+use candid::{CandidType, Principal};
+use ic_cdk::api::call::call;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+thread_local! {
+    // Usage not yet sealed into a batch
+    static ACCRUALS: RefCell<StableBTreeMap<Principal, Cbor<UsageRecord>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BILLING_ACCRUALS_MEMORY_ID)));
+    static OUTBOX: RefCell<StableBTreeMap<u64, Cbor<BillingBatch>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BILLING_OUTBOX_MEMORY_ID)));
+    static TOTALS: RefCell<StableBTreeMap<Principal, Cbor<BillingTotals>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BILLING_TOTALS_MEMORY_ID)));
+    static FLUSHING: Cell<bool> = Cell::new(false);
+}
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const MAX_RECORDS_PER_BATCH: usize = 500;
+// Past this, usage keeps accruing per principal instead of being sealed
+const MAX_PENDING_BATCHES: u64 = 1_000;
+// Delivered batches kept for reconciliation
+const MAX_RETAINED_BATCHES: u64 = 5_000;
+const MAX_DELIVERIES_PER_FLUSH: usize = 20;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+pub const ECONOMICS_METHOD: &str = "record_usage_batch";
+
+/// Usage of one principal over `period_start..=period_end`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub struct UsageRecord {
+    pub principal: Principal,
+    pub tokens: u64,
+    pub tasks: u32,
+    pub cycles: u64,
+    pub period_start: u64,
+    pub period_end: u64,
+    #[serde(default)]
+    pub estimated_cost_usd: f64, // Priced when each task was accrued
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct BillingBatch {
+    pub sequence: u64,
+    pub created_at: u64,
+    pub records: Vec<UsageRecord>,
+    pub attempts: u32,
+    pub last_attempt_at: Option<u64>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<u64>, // Set once the economics canister accepted the batch
+}
+
+/// What the economics canister receives. It must ignore a (source_canister,
+/// sequence) pair it has already applied: a batch whose reply was lost is sent again.
+#[derive(Debug, Clone, CandidType)]
+pub struct UsageBatch {
+    pub source_canister: Principal,
+    pub sequence: u64,
+    pub created_at: u64,
+    pub records: Vec<UsageRecord>,
+}
+
+/// Lifetime usage of a principal sealed into batches, for reconciliation
+#[derive(Debug, Clone, Default, Serialize, Deserialize, CandidType)]
+pub struct BillingTotals {
+    pub tokens: u64,
+    pub tasks: u64,
+    pub cycles: u64,
+    pub last_sequence: Option<u64>, // Latest batch carrying the principal's usage
+    #[serde(default)]
+    pub estimated_cost_usd: f64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct BillingBatchPage {
+    pub batches: Vec<BillingBatch>,
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct BillingStatus {
+    pub economics_canister_id: Option<String>,
+    pub next_sequence: u64,
+    pub last_delivered_sequence: Option<u64>,
+    pub pending_batches: u64,
+    pub oldest_pending_at: Option<u64>,
+    pub accrued_principals: u64, // Principals with usage not yet in a batch
+    pub last_error: Option<String>,
+}
+
+/// Exports per-principal usage to the economics canister. Task usage accrues
+/// per principal and is sealed on a timer into numbered batches kept in a
+/// stable outbox. Batches are sent in sequence order and stay pending until
+/// the economics canister accepts them, so each is delivered at least once;
+/// a failed batch holds back the ones after it until it goes through.
+pub struct BillingService;
+
+impl BillingService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(FLUSH_INTERVAL, || ic_cdk::spawn(async {
+            Self::flush().await;
+        }));
+    }
+
+    /// Add a finished task to `user_id`'s unbilled usage. Task tokens are
+    /// the reply's, so they are priced as output tokens of the default LLM.
+    pub fn accrue(user_id: &str, tokens: u64, cycles: u64, now: u64) {
+        let Ok(principal) = Principal::from_text(user_id) else {
+            return;
+        };
+        let tier = Guards::limits_for(principal).tier;
+        let cost = PricingService::token_cost(&QuantizedModel::Llama3_1_8B, 0, tokens, &tier) + PricingService::cycles_cost(cycles);
+        ACCRUALS.with(|a| {
+            let mut accruals = a.borrow_mut();
+            let mut record = accruals.get(&principal).map(|r| r.0).unwrap_or(UsageRecord {
+                principal,
+                tokens: 0,
+                tasks: 0,
+                cycles: 0,
+                period_start: now,
+                period_end: now,
+                estimated_cost_usd: 0.0,
+            });
+            record.tokens += tokens;
+            record.tasks += 1;
+            record.cycles += cycles;
+            record.period_end = now;
+            record.estimated_cost_usd += cost;
+            accruals.insert(principal, Cbor(record));
+        });
+    }
+
+    /// Seal accrued usage and send pending batches. Runs once at a time.
+    pub async fn flush() -> BillingStatus {
+        if FLUSHING.with(|f| f.replace(true)) {
+            return Self::status();
+        }
+        let _flushing = FlushGuard;
+        Self::seal(time());
+
+        let Some(economics) = with_state(|s| s.config.economics_canister_id.clone()) else {
+            return Self::status();
+        };
+        let Ok(target) = Principal::from_text(&economics) else {
+            return Self::status();
+        };
+        for sequence in Self::pending_sequences(MAX_DELIVERIES_PER_FLUSH) {
+            let Some(batch) = OUTBOX.with(|o| o.borrow().get(&sequence).map(|b| b.0)) else {
+                continue;
+            };
+            let payload = UsageBatch {
+                source_canister: ic_cdk::api::id(),
+                sequence,
+                created_at: batch.created_at,
+                records: batch.records.clone(),
+            };
+            let sent: Result<(Result<(), String>,), String> = Resilience::call(&economics, ECONOMICS_METHOD, || {
+                call(target, ECONOMICS_METHOD, (payload.clone(),))
+            })
+            .await;
+            let outcome = sent.and_then(|(accepted,)| accepted);
+            let delivered = outcome.is_ok();
+            OUTBOX.with(|o| {
+                let mut outbox = o.borrow_mut();
+                if let Some(Cbor(mut batch)) = outbox.get(&sequence) {
+                    batch.attempts += 1;
+                    batch.last_attempt_at = Some(time());
+                    match outcome {
+                        Ok(()) => {
+                            batch.delivered_at = batch.last_attempt_at;
+                            batch.last_error = None;
+                        }
+                        Err(e) => batch.last_error = Some(e),
+                    }
+                    outbox.insert(sequence, Cbor(batch));
+                }
+            });
+            if !delivered {
+                Metrics::increment_counter("billing_batch_failures_total");
+                break;
+            }
+            Metrics::increment_counter("billing_batches_delivered_total");
+        }
+        Self::prune();
+        Self::status()
+    }
+
+    pub fn status() -> BillingStatus {
+        let (next_sequence, last_delivered_sequence, pending) = OUTBOX.with(|o| {
+            let outbox = o.borrow();
+            let next = outbox.last_key_value().map_or(0, |(seq, _)| seq + 1);
+            let mut last_delivered = None;
+            let mut pending: Vec<BillingBatch> = Vec::new();
+            for (seq, batch) in outbox.iter() {
+                match batch.0.delivered_at {
+                    Some(_) => last_delivered = Some(seq),
+                    None => pending.push(batch.0),
+                }
+            }
+            (next, last_delivered, pending)
+        });
+        BillingStatus {
+            economics_canister_id: with_state(|s| s.config.economics_canister_id.clone()),
+            next_sequence,
+            last_delivered_sequence,
+            pending_batches: pending.len() as u64,
+            oldest_pending_at: pending.first().map(|b| b.created_at),
+            accrued_principals: ACCRUALS.with(|a| a.borrow().len()),
+            last_error: pending.first().and_then(|b| b.last_error.clone()),
+        }
+    }
+
+    /// Batches after `cursor` in sequence order, delivered or not
+    pub fn list_batches(cursor: Option<u64>, limit: Option<u32>) -> BillingBatchPage {
+        let limit = limit.map_or(DEFAULT_PAGE_SIZE, |l| (l as usize).clamp(1, MAX_PAGE_SIZE));
+        let start = cursor.map_or(0, |c| c + 1);
+        let mut batches: Vec<BillingBatch> =
+            OUTBOX.with(|o| o.borrow().range(start..).take(limit + 1).map(|(_, b)| b.0).collect());
+        let next_cursor = if batches.len() > limit {
+            batches.truncate(limit);
+            batches.last().map(|b| b.sequence)
+        } else {
+            None
+        };
+        BillingBatchPage { batches, next_cursor }
+    }
+
+    pub fn totals(principal: &Principal) -> BillingTotals {
+        TOTALS.with(|t| t.borrow().get(principal).map(|t| t.0)).unwrap_or_default()
+    }
+
+    fn seal(now: u64) {
+        let pending = OUTBOX.with(|o| o.borrow().iter().filter(|(_, b)| b.0.delivered_at.is_none()).count() as u64);
+        if pending >= MAX_PENDING_BATCHES {
+            return;
+        }
+        let records: Vec<UsageRecord> = ACCRUALS.with(|a| a.borrow().iter().map(|(_, r)| r.0).collect());
+        if records.is_empty() {
+            return;
+        }
+        let first_sequence = OUTBOX.with(|o| o.borrow().last_key_value().map_or(0, |(seq, _)| seq + 1));
+        let batches = into_batches(records, first_sequence, now);
+
+        for batch in batches {
+            for record in &batch.records {
+                ACCRUALS.with(|a| a.borrow_mut().remove(&record.principal));
+                TOTALS.with(|t| {
+                    let mut totals = t.borrow_mut();
+                    let mut total = totals.get(&record.principal).map(|t| t.0).unwrap_or_default();
+                    total.tokens += record.tokens;
+                    total.tasks += record.tasks as u64;
+                    total.cycles += record.cycles;
+                    total.estimated_cost_usd += record.estimated_cost_usd;
+                    total.last_sequence = Some(batch.sequence);
+                    totals.insert(record.principal, Cbor(total));
+                });
+            }
+            OUTBOX.with(|o| o.borrow_mut().insert(batch.sequence, Cbor(batch)));
+            Metrics::increment_counter("billing_batches_sealed_total");
+        }
+    }
+
+    fn pending_sequences(limit: usize) -> Vec<u64> {
+        OUTBOX.with(|o| {
+            o.borrow()
+                .iter()
+                .filter(|(_, batch)| batch.0.delivered_at.is_none())
+                .map(|(seq, _)| seq)
+                .take(limit)
+                .collect()
+        })
+    }
+
+    /// Drop the oldest delivered batches beyond the retention limit. The
+    /// newest batch is always kept so sequence numbers never repeat.
+    fn prune() {
+        OUTBOX.with(|o| {
+            let mut outbox = o.borrow_mut();
+            while outbox.len() > MAX_RETAINED_BATCHES {
+                match outbox.first_key_value() {
+                    Some((seq, batch)) if batch.0.delivered_at.is_some() => {
+                        outbox.remove(&seq);
+                    }
+                    _ => break,
+                }
+            }
+        });
+    }
+}
+
+struct FlushGuard;
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        FLUSHING.with(|f| f.set(false));
+    }
+}
+
+/// Records in principal order, split into consecutively numbered batches
+fn into_batches(mut records: Vec<UsageRecord>, first_sequence: u64, now: u64) -> Vec<BillingBatch> {
+    records.sort_by(|a, b| a.principal.cmp(&b.principal));
+    records
+        .chunks(MAX_RECORDS_PER_BATCH)
+        .zip(first_sequence..)
+        .map(|(records, sequence)| BillingBatch {
+            sequence,
+            created_at: now,
+            records: records.to_vec(),
+            attempts: 0,
+            last_attempt_at: None,
+            last_error: None,
+            delivered_at: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_batches_numbers_consecutive_chunks() {
+        let records: Vec<UsageRecord> = (0..MAX_RECORDS_PER_BATCH as u32 + 1)
+            .map(|i| UsageRecord {
+                principal: Principal::from_slice(&i.to_be_bytes()),
+                tokens: 1,
+                tasks: 1,
+                cycles: 1,
+                period_start: 0,
+                period_end: 0,
+                estimated_cost_usd: 0.0,
+            })
+            .rev()
+            .collect();
+        let batches = into_batches(records, 7, 42);
+        assert_eq!(batches.iter().map(|b| b.sequence).collect::<Vec<_>>(), vec![7, 8]);
+        assert_eq!(batches[0].records.len(), MAX_RECORDS_PER_BATCH);
+        assert_eq!(batches[1].records.len(), 1);
+        assert!(batches[0].records.windows(2).all(|w| w[0].principal < w[1].principal));
+        assert!(batches.iter().all(|b| b.created_at == 42 && b.delivered_at.is_none()));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::domain::*;
-use crate::services::{with_state, with_state_mut, ModelRepoClient, CacheService};
-use ic_cdk::api::time;
+use crate::services::{with_state, with_state_mut, CertificationService, ModelRepoClient, CacheService, EventService, AgentEventKind, WorkflowService, WarmSetService, SettingsService, ValidationHistoryService, CompatibilityService, ModelPoolService, modelrepo};
+use crate::infra::{Metrics, Span};
+use crate::infra::clock::time;
 use sha2::{Sha256, Digest};
 use base64::{Engine as _, engine::general_purpose};
 
@@ -8,25 +9,56 @@ pub struct BindingService;
 
 impl BindingService {
     pub async fn bind_model(model_id: String) -> Result<(), String> {
+        let span = Span::root("bind").attribute("ohms.model_id", &model_id);
+        let result = Self::bind(&span, model_id).await;
+        span.end(result.as_ref().err().map(String::as_str));
+        result
+    }
+
+    async fn bind(span: &Span, model_id: String) -> Result<(), String> {
         // Real binding: fetch manifest and prefetch chunks from ohms-model canister
-        let repo_canister = with_state(|s| s.config.model_repo_canister_id.clone());
-        if repo_canister.is_empty() { return Err("model_repo_canister_id not configured".to_string()); }
+        let (manifest, meta, repos) = Self::fetch_bindable(&model_id).await?;
 
-        let manifest = ModelRepoClient::get_manifest(&repo_canister, &model_id).await?;
-        // Ensure Active state (avoid binding Pending/Deprecated)
-        match manifest.state {
-            crate::services::modelrepo::ModelState::Active => {},
-            _ => return Err("model is not Active".to_string()),
-        }
+        let (prefetch_n, previous) = with_state(|s| {
+            (s.config.prefetch_depth, s.binding.as_ref().map(|b| (b.model_id.clone(), b.version.clone())))
+        });
+        let bound = (model_id.clone(), manifest.version.clone());
 
-        // Prefetch first N chunks
-        let prefetch_n = with_state(|s| s.config.prefetch_depth);
-        let mut loaded: u32 = 0;
-        for chunk in manifest.chunks.iter().take(prefetch_n as usize) {
-            let bytes = ModelRepoClient::get_chunk(&repo_canister, &model_id, &chunk.id).await?;
-            CacheService::put(chunk.id.clone(), bytes)?;
-            loaded += 1;
+        // Delta update: chunks whose content is already cached, from the previous
+        // version or another model, are reused. Of the chunks the previous
+        // version had cached, only those that changed are fetched.
+        let (chunks_reused, bytes_saved) = CacheService::link_cached_chunks(&manifest);
+        let mut fetch = match &previous {
+            Some((previous_model, previous_version)) if *previous_model == model_id => {
+                CacheService::changed_chunks(previous_version, &manifest)
+            }
+            _ => Vec::new(),
+        };
+
+        // Then the first N chunks of the warm set learned so far, falling back
+        // to layer order for chunks that have never been accessed
+        let warm_set = WarmSetService::compute_for(&manifest);
+        for chunk_id in WarmSetService::prefetch_order(&manifest, &warm_set).into_iter().take(prefetch_n as usize) {
+            if !fetch.contains(&chunk_id) {
+                fetch.push(chunk_id);
+            }
+        }
+        for chunk_id in &fetch {
+            let chunk_span = span.child("chunk_fetch").attribute("ohms.chunk_id", chunk_id);
+            let fetched = match ModelRepoClient::get_chunk(&repos, &manifest, chunk_id).await {
+                Ok(bytes) => CacheService::put_model_chunk(&model_id, &manifest.version, chunk_id.clone(), bytes),
+                Err(e) => Err(e),
+            };
+            chunk_span.end(fetched.as_ref().err().map(String::as_str));
+            if let Err(e) = fetched {
+                // Keep the chunk map of a current binding; drop the one this attempt started
+                if !ModelPoolService::in_use(&bound.0, &bound.1) {
+                    CacheService::release_model(&bound.0, &bound.1);
+                }
+                return Err(e);
+            }
         }
+        let loaded = Self::cached_chunks(&manifest);
 
         let binding = ModelBinding {
             model_id: model_id.clone(),
@@ -35,53 +67,99 @@ impl BindingService {
             chunks_loaded: loaded,
             total_chunks: manifest.chunks.len() as u32,
             version: manifest.version.clone(),
+            repos,
+            chunks_reused,
+            bytes_saved,
         };
 
-        with_state_mut(|state| {
+        // A pool model bound here is promoted out of the pool
+        let pooled = with_state_mut(|state| {
             state.manifest = Some(manifest);
+            state.model_meta = Some(meta);
             state.binding = Some(binding);
+            state.warm_set = warm_set;
             state.metrics.last_activity = time();
+            state.pool.remove(&model_id).map(|p| (model_id.clone(), p.binding.version))
         });
+        CertificationService::mark_dirty();
+        // Chunks only the previous version used become evictable
+        for replaced in previous.into_iter().chain(pooled).filter(|replaced| *replaced != bound) {
+            CacheService::release_model(&replaced.0, &replaced.1);
+        }
+        if bytes_saved > 0 {
+            Metrics::add_to_counter("bind_bytes_saved_total", bytes_saved);
+        }
+        EventService::publish(None, &ic_cdk::api::caller().to_string(), AgentEventKind::ModelBound { model_id });
         Ok(())
     }
     
+    /// Manifest, meta and verified repos of a model that passes every pre-bind
+    /// check: validation history, Active state and runtime compatibility
+    pub async fn fetch_bindable(model_id: &str) -> Result<(modelrepo::ModelManifest, modelrepo::ModelMeta, Vec<String>), String> {
+        let repos = with_state(|s| s.config.model_repos());
+        if repos.is_empty() { return Err("model_repo_canister_id not configured".to_string()); }
+        ValidationHistoryService::check_bindable(model_id)?;
+
+        let (manifest, repos) = ModelRepoClient::get_manifest(&repos, model_id).await?;
+        // Ensure Active state (avoid binding Pending/Deprecated)
+        match manifest.state {
+            crate::services::modelrepo::ModelState::Active => {},
+            _ => return Err("model is not Active".to_string()),
+        }
+        // Its ctx_window bounds every prompt once the model is bound
+        let meta = ModelRepoClient::get_model_meta(&repos, model_id)
+            .await
+            .map_err(|e| format!("Model meta unavailable: {}", e))?;
+        if let Err(e) = CompatibilityService::ensure(&manifest, &meta) {
+            Metrics::increment_counter("bind_incompatible_total");
+            return Err(e);
+        }
+        Ok((manifest, meta, repos))
+    }
+
     pub async fn prefetch_next(n: u32) -> Result<u32, String> {
-        let (repo_canister, model_id, already_loaded, manifest_opt) = with_state(|s| {
-            (s.config.model_repo_canister_id.clone(),
+        let (repos, model_id, manifest_opt, warm_set) = with_state(|s| {
+            // Bindings made before mirrors existed have no verified repo list
+            let repos = s.binding.as_ref().map(|b| b.repos.clone()).filter(|r| !r.is_empty());
+            (repos.unwrap_or_else(|| s.config.model_repos()),
              s.binding.as_ref().map(|b| b.model_id.clone()),
-             s.binding.as_ref().map(|b| b.chunks_loaded).unwrap_or(0),
-             s.manifest.clone())
+             s.manifest.clone(),
+             s.warm_set.clone())
         });
-        if repo_canister.is_empty() { return Err("model_repo_canister_id not configured".into()); }
+        if repos.is_empty() { return Err("model_repo_canister_id not configured".into()); }
         let model_id = model_id.ok_or_else(|| "no model bound".to_string())?;
         let manifest = manifest_opt.ok_or_else(|| "manifest not loaded".to_string())?;
+        // Chunks evicted since the last prefetch are fetched again, warm set first
         let mut loaded = 0u32;
-        for chunk in manifest.chunks.iter().skip(already_loaded as usize).take(n as usize) {
-            let bytes = ModelRepoClient::get_chunk(&repo_canister, &model_id, &chunk.id).await?;
-            CacheService::put(chunk.id.clone(), bytes)?;
+        for chunk_id in WarmSetService::prefetch_order(&manifest, &warm_set).iter().take(n as usize) {
+            let bytes = ModelRepoClient::get_chunk(&repos, &manifest, chunk_id).await?;
+            CacheService::put_model_chunk(&model_id, &manifest.version, chunk_id.clone(), bytes)?;
             loaded += 1;
         }
+        let cached = Self::cached_chunks(&manifest);
         with_state_mut(|s| {
             if let Some(b) = &mut s.binding {
-                b.chunks_loaded += loaded;
+                b.chunks_loaded = cached;
             }
         });
         Ok(loaded)
     }
+
+    fn cached_chunks(manifest: &modelrepo::ModelManifest) -> u32 {
+        with_state(|s| manifest.chunks.iter().filter(|c| CacheService::is_cached(s, manifest, &c.id)).count() as u32)
+    }
     
     pub fn set_config(config: AgentConfig) -> Result<(), String> {
-        with_state_mut(|state| {
-            state.config = config;
-        });
-        Ok(())
+        SettingsService::set_config(config)
     }
     
     pub fn get_config() -> Result<AgentConfig, String> {
         Ok(with_state(|state| state.config.clone()))
     }
     
+    /// queue_depth counts in-flight tasks plus workflow steps waiting to run
     pub fn get_health() -> AgentHealth {
-        with_state(|state| {
+        let mut health = with_state(|state| {
             let cache_hits = state.metrics.cache_hits;
             let cache_misses = state.metrics.cache_misses;
             let total_requests = cache_hits + cache_misses;
@@ -92,16 +170,19 @@ impl BindingService {
                 0.0
             };
             
-            let warm_set_utilization = state.cache_entries.len() as f32 / 100.0; // Mock calculation
+            let in_flight: u32 = state.tasks_in_flight.values().sum();
             
             AgentHealth {
                 model_bound: state.binding.is_some(),
                 cache_hit_rate: hit_rate,
-                warm_set_utilization,
-                queue_depth: 0, // TODO: Implement proper queue tracking
+                warm_set_utilization: 0.0,
+                queue_depth: in_flight,
                 last_inference_timestamp: state.metrics.last_activity,
             }
-        })
+        });
+        health.warm_set_utilization = CacheService::get_warm_set_utilization();
+        health.queue_depth += WorkflowService::queued_steps().values().sum::<u32>();
+        health
     }
     
     #[allow(dead_code)]
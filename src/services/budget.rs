@@ -0,0 +1,144 @@
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentStatus, AutonomousAgent, SuspendReason};
+use crate::services::billing::BillingService;
+use crate::services::events::{AgentEventKind, EventService};
+use crate::services::webhook::{WebhookEvent, WebhookService};
+use crate::services::with_state;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+// Execution cost on a 13-node subnet is 0.4 cycles per instruction
+const CYCLES_PER_10_INSTRUCTIONS: u64 = 4;
+
+/// Hard caps on what one agent may consume. Unset limits are not enforced.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, CandidType)]
+pub struct AgentBudget {
+    pub tokens_per_day: Option<u64>,
+    pub tasks_per_day: Option<u32>,
+    pub cycles_cap: Option<u64>, // Lifetime, across all tasks
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, CandidType)]
+pub struct BudgetUsage {
+    pub day: u64, // Days since the epoch the daily counters belong to
+    pub tokens_today: u64,
+    pub tasks_today: u32,
+    pub cycles_spent: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct BudgetStatus {
+    pub budget: AgentBudget,
+    pub usage: BudgetUsage,
+    pub exceeded: Option<String>,
+}
+
+/// Per-agent spend limits, checked before every task (including each workflow
+/// step) and after it so an overrunning task suspends the agent straight away
+pub struct BudgetService;
+
+impl BudgetService {
+    pub fn status(agent: &AutonomousAgent, now: u64) -> BudgetStatus {
+        BudgetStatus {
+            budget: agent.budget.clone(),
+            usage: Self::current_usage(&agent.budget_usage, now),
+            exceeded: Self::over_budget(agent, now),
+        }
+    }
+
+    /// Gate a task start. An agent suspended for its budget is let through again
+    /// once the day rolls over or the owner raises the limits.
+    pub fn check(agent: &mut AutonomousAgent, now: u64) -> Result<(), String> {
+        match Self::over_budget(agent, now) {
+            Some(reason) => {
+                Self::suspend(agent, &reason);
+                Err(format!("Agent {} is suspended: {}", agent.agent_id, reason))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Add a finished task to the agent's usage and the owner's unbilled usage,
+    /// returning the cycles charged. Counters are re-read from state first so
+    /// concurrent tasks on the same agent are not lost.
+    pub fn record(agent: &mut AutonomousAgent, tokens: u64, instructions: u64, now: u64) -> u64 {
+        let stored = with_state(|state| state.agents.get(&agent.agent_id).map(|a| a.budget_usage.clone()));
+        let mut usage = Self::current_usage(&stored.unwrap_or_else(|| agent.budget_usage.clone()), now);
+        let cycles = instructions / 10 * CYCLES_PER_10_INSTRUCTIONS;
+        usage.tokens_today += tokens;
+        usage.tasks_today += 1;
+        usage.cycles_spent += cycles;
+        agent.budget_usage = usage;
+        BillingService::accrue(&agent.user_id, tokens, cycles, now);
+        cycles
+    }
+
+    /// Reason the agent is over budget, if it is
+    pub fn over_budget(agent: &AutonomousAgent, now: u64) -> Option<String> {
+        Self::exceeded(&agent.budget, &Self::current_usage(&agent.budget_usage, now))
+    }
+
+    /// Move the agent to Suspended(BudgetExceeded) and tell the owner, once
+    pub fn suspend(agent: &mut AutonomousAgent, reason: &str) {
+        if matches!(agent.status, AgentStatus::Suspended(SuspendReason::BudgetExceeded)) {
+            return;
+        }
+        agent.status = AgentStatus::Suspended(SuspendReason::BudgetExceeded);
+        Metrics::increment_counter("agents_budget_suspended_total");
+        EventService::publish(Some(&agent.agent_id), &agent.user_id, AgentEventKind::AgentSuspended {
+            reason: reason.to_string(),
+        });
+        WebhookService::notify(&agent.agent_id, WebhookEvent::QuotaExceeded, serde_json::json!({ "reason": reason }));
+    }
+
+    fn current_usage(usage: &BudgetUsage, now: u64) -> BudgetUsage {
+        let today = now / NANOS_PER_DAY;
+        if usage.day == today {
+            usage.clone()
+        } else {
+            BudgetUsage {
+                day: today,
+                tokens_today: 0,
+                tasks_today: 0,
+                cycles_spent: usage.cycles_spent,
+            }
+        }
+    }
+
+    fn exceeded(budget: &AgentBudget, usage: &BudgetUsage) -> Option<String> {
+        if let Some(limit) = budget.tokens_per_day.filter(|limit| usage.tokens_today >= *limit) {
+            return Some(format!("daily token budget of {} used", limit));
+        }
+        if let Some(limit) = budget.tasks_per_day.filter(|limit| usage.tasks_today >= *limit) {
+            return Some(format!("daily task budget of {} used", limit));
+        }
+        if let Some(limit) = budget.cycles_cap.filter(|limit| usage.cycles_spent >= *limit) {
+            return Some(format!("cycles cap of {} reached", limit));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daily_limits_reset_but_cycles_do_not() {
+        let budget = AgentBudget {
+            tokens_per_day: Some(100),
+            tasks_per_day: None,
+            cycles_cap: Some(1_000),
+        };
+        let usage = BudgetUsage { day: 3, tokens_today: 150, tasks_today: 2, cycles_spent: 10 };
+        assert!(BudgetService::exceeded(&budget, &BudgetService::current_usage(&usage, 3 * NANOS_PER_DAY)).is_some());
+
+        let next_day = BudgetService::current_usage(&usage, 4 * NANOS_PER_DAY);
+        assert_eq!(next_day.tokens_today, 0);
+        assert!(BudgetService::exceeded(&budget, &next_day).is_none());
+
+        let spent = BudgetUsage { cycles_spent: 1_000, ..next_day };
+        assert!(BudgetService::exceeded(&budget, &spent).unwrap().contains("cycles"));
+    }
+}
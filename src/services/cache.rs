@@ -1,25 +1,193 @@
 use crate::domain::*;
-use crate::services::{with_state, with_state_mut};
-use ic_cdk::api::time;
+use crate::infra::{Guards, Metrics};
+use crate::services::modelrepo::ModelManifest;
+use crate::services::{with_state, with_state_mut, AgentState, WarmSetService};
+use candid::CandidType;
+use crate::infra::clock::time;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(60);
+// Raw bytes compressed per pass, to stay well inside the instruction limit
+const COMPACTION_BUDGET_BYTES: usize = 16 * 1024 * 1024;
+const DEFLATE_LEVEL: u8 = 6;
+
+#[derive(Debug, Clone, CandidType)]
+pub struct CacheStats {
+    pub entries: u32,
+    pub compressed_entries: u32,
+    pub raw_bytes: u64,
+    pub stored_bytes: u64,
+    pub compression_ratio: f32, // stored / raw, 1.0 when nothing is compressed
+    pub shared_entries: u32,    // Referenced by more than one model chunk
+    pub dedup_saved_bytes: u64, // Raw bytes that would be stored again without dedup
+}
 
 pub struct CacheService;
 
 impl CacheService {
+    pub fn start_compaction_timer() {
+        ic_cdk_timers::set_timer_interval(COMPACTION_INTERVAL, || {
+            Self::compress_cold();
+        });
+    }
+
+    /// `layer_id` is a chunk id of the bound model, or the key given to `put`.
+    /// Compressed entries are decompressed and kept raw, since an access puts
+    /// them back in the hot tier.
     pub fn get(layer_id: &str) -> Option<Vec<u8>> {
         let now = time();
         
+        let data = with_state_mut(|state| {
+            let key = state
+                .binding
+                .as_ref()
+                .and_then(|b| state.chunk_maps.get(&Self::chunk_map_key(&b.model_id, &b.version)))
+                .and_then(|chunks| chunks.get(layer_id))
+                .map_or(layer_id, String::as_str)
+                .to_string();
+            let Some(entry) = state.cache_entries.get_mut(&key) else {
+                state.metrics.cache_misses += 1;
+                return None;
+            };
+            if entry.codec != CompressionCodec::None {
+                match Self::decompress(entry.codec, &entry.data) {
+                    Ok(raw) => {
+                        entry.size_bytes = raw.len();
+                        entry.data = raw;
+                        entry.codec = CompressionCodec::None;
+                    }
+                    Err(e) => {
+                        Metrics::increment_counter("cache_corrupt_entries_total");
+                        ic_cdk::println!("Dropping corrupt cache entry {}: {}", layer_id, e);
+                        state.cache_entries.remove(&key);
+                        state.metrics.cache_misses += 1;
+                        return None;
+                    }
+                }
+            }
+            entry.last_accessed = now;
+            entry.access_count += 1;
+            state.metrics.cache_hits += 1;
+            Some(entry.data.clone())
+        });
+        WarmSetService::record_access(layer_id, data.is_some());
+        data
+    }
+    
+    /// Mark cached chunks of the bound model as just used without counting an
+    /// access, so compaction and LRU eviction pass them over. Returns how many
+    /// were cached.
+    pub fn touch(chunk_ids: &[String]) -> u32 {
+        let now = time();
         with_state_mut(|state| {
-            if let Some(entry) = state.cache_entries.get_mut(layer_id) {
-                entry.last_accessed = now;
-                entry.access_count += 1;
-                Some(entry.data.clone())
-            } else {
-                None
+            let Some(chunks) = state
+                .binding
+                .as_ref()
+                .and_then(|b| state.chunk_maps.get(&Self::chunk_map_key(&b.model_id, &b.version)))
+            else {
+                return 0;
+            };
+            let keys: Vec<String> = chunk_ids.iter().filter_map(|id| chunks.get(id).cloned()).collect();
+            let mut touched = 0;
+            for key in keys {
+                if let Some(entry) = state.cache_entries.get_mut(&key) {
+                    entry.last_accessed = now;
+                    touched += 1;
+                }
             }
+            touched
         })
     }
-    
+
     pub fn put(layer_id: String, data: Vec<u8>) -> Result<(), String> {
+        Self::insert(layer_id, data, None)
+    }
+
+    /// Store a chunk of the model version under its content hash and record it
+    /// in the version's chunk map. Content another model already stored is not
+    /// stored again.
+    pub fn put_model_chunk(model_id: &str, version: &str, chunk_id: String, data: Vec<u8>) -> Result<(), String> {
+        let key = hex::encode(Sha256::digest(&data));
+        let stored = with_state_mut(|state| {
+            let chunks = state.chunk_maps.entry(Self::chunk_map_key(model_id, version)).or_default();
+            chunks.insert(chunk_id, key.clone());
+            state.cache_entries.contains_key(&key)
+        });
+        if stored {
+            Metrics::add_to_counter("cache_dedup_bytes_saved_total", data.len() as u64);
+            return Ok(());
+        }
+        Self::insert(key, data, Some(model_id.to_string()))
+    }
+
+    /// Drop the model version's chunk map. Its chunks stay cached but become
+    /// evictable once no other model references them.
+    pub fn release_model(model_id: &str, version: &str) {
+        with_state_mut(|state| state.chunk_maps.remove(&Self::chunk_map_key(model_id, version)));
+    }
+
+    /// Link chunks of `manifest` whose content is already cached, going by the
+    /// sha256 in the manifest, into its chunk map. Returns the chunks and bytes
+    /// that need no fetch.
+    pub fn link_cached_chunks(manifest: &ModelManifest) -> (u32, u64) {
+        let map_key = Self::chunk_map_key(&manifest.model_id, &manifest.version);
+        with_state_mut(|state| {
+            let (mut chunks, mut bytes) = (0, 0);
+            for chunk in &manifest.chunks {
+                let key = chunk.sha256.to_ascii_lowercase();
+                if key.is_empty() || !state.cache_entries.contains_key(&key) {
+                    continue;
+                }
+                state.chunk_maps.entry(map_key.clone()).or_default().insert(chunk.id.clone(), key);
+                chunks += 1;
+                bytes += chunk.size;
+            }
+            (chunks, bytes)
+        })
+    }
+
+    /// Chunks of `manifest` that were cached for the previous version but whose
+    /// content changed
+    pub fn changed_chunks(previous_version: &str, manifest: &ModelManifest) -> Vec<String> {
+        with_state(|state| {
+            let Some(previous) = state.chunk_maps.get(&Self::chunk_map_key(&manifest.model_id, previous_version)) else {
+                return Vec::new();
+            };
+            manifest
+                .chunks
+                .iter()
+                .filter(|chunk| previous.get(&chunk.id).is_some_and(|key| state.cache_entries.contains_key(key)))
+                .filter(|chunk| !Self::is_cached(state, manifest, &chunk.id))
+                .map(|chunk| chunk.id.clone())
+                .collect()
+        })
+    }
+
+    pub fn is_cached(state: &AgentState, manifest: &ModelManifest, chunk_id: &str) -> bool {
+        state
+            .chunk_maps
+            .get(&Self::chunk_map_key(&manifest.model_id, &manifest.version))
+            .and_then(|chunks| chunks.get(chunk_id))
+            .is_some_and(|key| state.cache_entries.contains_key(key))
+    }
+
+    fn chunk_map_key(model_id: &str, version: &str) -> String {
+        format!("{}@{}", model_id, version)
+    }
+
+    /// Chunk-map references per cache key
+    fn ref_counts(state: &AgentState) -> HashMap<&str, u32> {
+        let mut counts = HashMap::new();
+        for key in state.chunk_maps.values().flat_map(|chunks| chunks.values()) {
+            *counts.entry(key.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    fn insert(layer_id: String, data: Vec<u8>, model_id: Option<String>) -> Result<(), String> {
+        Guards::check_memory_limits()?;
         let now = time();
         let size_bytes = data.len();
         
@@ -29,6 +197,10 @@ impl CacheService {
             last_accessed: now,
             access_count: 1,
             size_bytes,
+            raw_size_bytes: size_bytes,
+            codec: CompressionCodec::None,
+            incompressible: false,
+            model_id,
         };
         
         with_state_mut(|state| {
@@ -41,7 +213,7 @@ impl CacheService {
             let max_cache_size = 100 * 1024 * 1024; // 100MB limit for demo
             
             if current_size + size_bytes > max_cache_size {
-                Self::evict_lru(state, size_bytes);
+                Self::evict_lru(state, size_bytes, false);
             }
             
             state.cache_entries.insert(layer_id, entry);
@@ -61,17 +233,136 @@ impl CacheService {
         Ok(())
     }
     
-    fn evict_lru(state: &mut crate::services::AgentState, needed_space: usize) {
-        let mut entries: Vec<_> = state.cache_entries
-            .iter()
-            .map(|(k, v)| (k.clone(), v.last_accessed, v.size_bytes))
-            .collect();
+    /// Compress entries that have been idle longer than their model's hot tier,
+    /// oldest first. Returns how many were compressed.
+    pub fn compress_cold() -> usize {
+        let now = time();
+        let compressed = with_state_mut(|state| {
+            let mut cold: Vec<_> = state
+                .cache_entries
+                .values()
+                .filter(|e| e.codec == CompressionCodec::None && !e.incompressible)
+                .filter_map(|e| {
+                    let settings = state.config.compression_for(e.model_id.as_deref());
+                    let idle_ns = now.saturating_sub(e.last_accessed);
+                    (settings.codec != CompressionCodec::None && idle_ns >= settings.hot_tier_seconds * 1_000_000_000)
+                        .then(|| (e.layer_id.clone(), e.last_accessed, settings.codec))
+                })
+                .collect();
+            cold.sort_by_key(|(_, accessed, _)| *accessed);
+
+            let mut budget = COMPACTION_BUDGET_BYTES;
+            let mut compressed = 0;
+            for (key, _, codec) in cold {
+                let Some(entry) = state.cache_entries.get_mut(&key) else { continue };
+                if entry.size_bytes > budget {
+                    break;
+                }
+                budget -= entry.size_bytes;
+                let packed = Self::compress(codec, &entry.data);
+                if packed.len() < entry.data.len() {
+                    entry.size_bytes = packed.len();
+                    entry.data = packed;
+                    entry.codec = codec;
+                    compressed += 1;
+                } else {
+                    entry.incompressible = true;
+                }
+            }
+            compressed
+        });
+        if compressed > 0 {
+            Metrics::add_to_counter("cache_entries_compressed_total", compressed as u64);
+        }
+        let stats = Self::stats();
+        Metrics::set_gauge("cache_raw_bytes", stats.raw_bytes as f64);
+        Metrics::set_gauge("cache_stored_bytes", stats.stored_bytes as f64);
+        compressed
+    }
+
+    pub fn stats() -> CacheStats {
+        with_state(|state| {
+            let entries = &state.cache_entries;
+            let raw_bytes: u64 = entries.values().map(|e| e.raw_size_bytes as u64).sum();
+            let stored_bytes: u64 = entries.values().map(|e| e.size_bytes as u64).sum();
+            let refs = Self::ref_counts(state);
+            let shared: Vec<_> = entries.iter().filter_map(|(key, e)| {
+                refs.get(key.as_str()).filter(|count| **count > 1).map(|count| (e, *count))
+            }).collect();
+            CacheStats {
+                entries: entries.len() as u32,
+                compressed_entries: entries.values().filter(|e| e.codec != CompressionCodec::None).count() as u32,
+                raw_bytes,
+                stored_bytes,
+                compression_ratio: if raw_bytes == 0 { 1.0 } else { stored_bytes as f32 / raw_bytes as f32 },
+                shared_entries: shared.len() as u32,
+                dedup_saved_bytes: shared.iter().map(|(e, count)| e.raw_size_bytes as u64 * (*count as u64 - 1)).sum(),
+            }
+        })
+    }
+
+    fn compress(codec: CompressionCodec, data: &[u8]) -> Vec<u8> {
+        match codec {
+            CompressionCodec::None => data.to_vec(),
+            CompressionCodec::Lz4 => lz4_flex::block::compress_prepend_size(data),
+            CompressionCodec::Deflate => miniz_oxide::deflate::compress_to_vec(data, DEFLATE_LEVEL),
+        }
+    }
+
+    fn decompress(codec: CompressionCodec, data: &[u8]) -> Result<Vec<u8>, String> {
+        match codec {
+            CompressionCodec::None => Ok(data.to_vec()),
+            CompressionCodec::Lz4 => lz4_flex::block::decompress_size_prepended(data).map_err(|e| e.to_string()),
+            CompressionCodec::Deflate => miniz_oxide::inflate::decompress_to_vec(data).map_err(|e| format!("{:?}", e)),
+        }
+    }
+
+    /// Evict least recently used entries until the cache holds at most
+    /// `keep_fraction` of its current size. Returns the bytes freed. Under
+    /// memory pressure chunks still referenced by a model may go too.
+    pub fn shrink(keep_fraction: f32) -> usize {
+        with_state_mut(|state| {
+            let current_size: usize = state.cache_entries.values().map(|e| e.size_bytes).sum();
+            let target = (current_size as f32 * keep_fraction.clamp(0.0, 1.0)) as usize;
+            let before = state.cache_entries.len();
+            Self::evict_lru(state, current_size - target, true);
+            let freed = current_size - state.cache_entries.values().map(|e| e.size_bytes).sum::<usize>();
+            if before > state.cache_entries.len() {
+                Metrics::add_to_counter("cache_evictions_total", (before - state.cache_entries.len()) as u64);
+            }
+            freed
+        })
+    }
+
+    /// Chunks referenced by a model's chunk map are only evicted when
+    /// `include_referenced` is set, after unreferenced entries
+    fn evict_lru(state: &mut AgentState, needed_space: usize, include_referenced: bool) {
+        let mut entries: Vec<_> = {
+            let refs = Self::ref_counts(state);
+            let bound_chunks = state
+                .binding
+                .as_ref()
+                .and_then(|b| state.chunk_maps.get(&Self::chunk_map_key(&b.model_id, &b.version)));
+            let pinned: HashSet<&str> = state
+                .warm_set
+                .members
+                .iter()
+                .filter_map(|id| bound_chunks.and_then(|chunks| chunks.get(id)))
+                .map(String::as_str)
+                .collect();
+            state.cache_entries
+                .iter()
+                .map(|(k, v)| (k.clone(), refs.contains_key(k.as_str()), pinned.contains(k.as_str()), v.last_accessed, v.size_bytes))
+                .filter(|(_, referenced, _, _, _)| include_referenced || !referenced)
+                .collect()
+        };
             
-        // Sort by last accessed time (oldest first)
-        entries.sort_by_key(|(_, accessed, _)| *accessed);
+        // Warm-set chunks are pinned: they go only once everything else has,
+        // oldest first within each group
+        entries.sort_by_key(|(_, referenced, pinned, accessed, _)| (*referenced, *pinned, *accessed));
         
         let mut freed_space = 0;
-        for (key, _, size) in entries {
+        for (key, _, _, _, size) in entries {
             if freed_space >= needed_space {
                 break;
             }
@@ -92,6 +383,11 @@ impl CacheService {
         })
     }
     
+    /// Share of the warm set's bytes currently cached
+    pub fn get_warm_set_utilization() -> f32 {
+        WarmSetService::report().coverage
+    }
+    
     pub fn get_utilization() -> f32 {
         with_state(|state| {
             let current_size: usize = state.cache_entries
@@ -103,4 +399,59 @@ impl CacheService {
             current_size as f32 / max_size as f32
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs_round_trip() {
+        let data: Vec<u8> = (0..4096u32).map(|i| (i % 7) as u8).collect();
+        for codec in [CompressionCodec::Lz4, CompressionCodec::Deflate] {
+            let packed = CacheService::compress(codec, &data);
+            assert!(packed.len() < data.len());
+            assert_eq!(CacheService::decompress(codec, &packed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_model_compression_override() {
+        let mut config = AgentConfig::default();
+        let deflate = CacheCompression { codec: CompressionCodec::Deflate, hot_tier_seconds: 60 };
+        config.model_cache_compression = Some([("big".to_string(), deflate)].into_iter().collect());
+        assert_eq!(config.compression_for(Some("big")).codec, CompressionCodec::Deflate);
+        assert_eq!(config.compression_for(Some("small")).codec, CompressionCodec::Lz4);
+        assert_eq!(config.compression_for(None).codec, CompressionCodec::Lz4);
+    }
+
+    #[test]
+    fn test_eviction_keeps_referenced_chunks() {
+        let entry = |key: &str, last_accessed| CacheEntry {
+            layer_id: key.to_string(),
+            data: vec![0; 10],
+            last_accessed,
+            access_count: 1,
+            size_bytes: 10,
+            raw_size_bytes: 10,
+            codec: CompressionCodec::None,
+            incompressible: false,
+            model_id: None,
+        };
+        let mut state = AgentState::default();
+        for (key, accessed) in [("shared", 1), ("orphan", 2)] {
+            state.cache_entries.insert(key.to_string(), entry(key, accessed));
+        }
+        for model in ["a", "b"] {
+            state.chunk_maps.insert(model.to_string(), [("tokenizer".to_string(), "shared".to_string())].into_iter().collect());
+        }
+        assert_eq!(CacheService::ref_counts(&state).get("shared"), Some(&2));
+
+        CacheService::evict_lru(&mut state, 20, false);
+        assert!(state.cache_entries.contains_key("shared"));
+        assert!(!state.cache_entries.contains_key("orphan"));
+
+        CacheService::evict_lru(&mut state, 10, true);
+        assert!(state.cache_entries.is_empty());
+    }
+}
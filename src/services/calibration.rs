@@ -0,0 +1,333 @@
+use crate::domain::instruction::AnalyzedInstruction;
+use crate::infra::stable::{memory, Cbor, Memory, CALIBRATION_MEMORY_ID, CALIBRATION_OUTCOMES_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentTaskResult, AutonomousAgent, TaskOutcome};
+use crate::services::InstructionAnalyzer;
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::Duration;
+
+thread_local! {
+    static OUTCOMES: RefCell<StableBTreeMap<u64, Cbor<AnalysisOutcome>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CALIBRATION_OUTCOMES_MEMORY_ID)));
+    static CALIBRATION: RefCell<StableBTreeMap<u8, Cbor<Calibration>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CALIBRATION_MEMORY_ID)));
+}
+
+const CALIBRATION_KEY: u8 = 0;
+const MAX_OUTCOMES: u64 = 2_000;
+// Fewer outcomes than this keep the previous coefficients
+const MIN_SAMPLES: usize = 30;
+// How many recent outcomes a late rating is matched against
+const RATING_LOOKBACK: u64 = 200;
+const RECALIBRATION_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+const SLOPE_RANGE: std::ops::RangeInclusive<f32> = 0.0..=2.0;
+const DURATION_SCALE_RANGE: std::ops::RangeInclusive<f64> = 0.1..=10.0;
+const BUCKETS: usize = 10;
+
+/// What one task of an agent showed about its analysis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalysisOutcome {
+    pub agent_id: String,
+    pub task_id: String,
+    pub predicted_confidence: f32,     // Uncalibrated confidence_score of the agent's analysis
+    pub expected_duration_seconds: u64, // Uncalibrated estimate
+    pub success: bool,
+    pub duration_ms: u64,
+    pub rating: Option<u8>, // Set when the owner rates the task
+    pub recorded_at: u64,
+}
+
+/// Coefficients applied on top of the analyzer's heuristics. The default
+/// leaves them unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Calibration {
+    pub confidence_slope: f32,
+    pub confidence_intercept: f32,
+    pub duration_scale: f64,
+    pub samples: u64, // Outcomes the coefficients were fitted on
+    pub recalibrated_at: Option<u64>,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            confidence_slope: 1.0,
+            confidence_intercept: 0.0,
+            duration_scale: 1.0,
+            samples: 0,
+            recalibrated_at: None,
+        }
+    }
+}
+
+impl Calibration {
+    pub fn confidence(&self, raw: f32) -> f32 {
+        (self.confidence_slope * raw + self.confidence_intercept).clamp(0.0, 1.0)
+    }
+
+    pub fn duration_seconds(&self, raw: u64) -> u64 {
+        (raw as f64 * self.duration_scale).round() as u64
+    }
+}
+
+/// Outcomes whose uncalibrated confidence fell in [lower, upper)
+#[derive(Debug, Clone, CandidType)]
+pub struct ConfidenceBucket {
+    pub lower: f32,
+    pub upper: f32,
+    pub samples: u64,
+    pub mean_confidence: f32, // Calibrated
+    pub observed_quality: f32,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct CalibrationStats {
+    pub calibration: Calibration,
+    pub samples: u64,
+    pub rated_samples: u64,
+    pub success_rate: f32,
+    pub mean_absolute_error: f32, // Calibrated confidence vs observed quality
+    pub median_duration_ratio: f64, // Actual over calibrated expected duration
+    pub buckets: Vec<ConfidenceBucket>,
+}
+
+/// Grounds InstructionAnalyzer's confidence_score and duration estimates in
+/// what agents actually did. Every finished task records its outcome against
+/// the agent's analysis; owner ratings refine it. A timer refits the
+/// coefficients from the recent outcomes.
+///
+/// An outcome's quality is its owner rating scaled to 0-1 when rated,
+/// otherwise 1 for success and 0 for failure. Confidence is a least-squares
+/// line from uncalibrated confidence to quality; the duration scale is the
+/// geometric mean of actual over estimated duration.
+pub struct CalibrationService;
+
+impl CalibrationService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(RECALIBRATION_INTERVAL, || {
+            Self::recalibrate();
+        });
+    }
+
+    pub fn current() -> Calibration {
+        CALIBRATION.with(|c| c.borrow().get(&CALIBRATION_KEY).map(|c| c.0)).unwrap_or_default()
+    }
+
+    /// Tasks held for approval have no outcome yet and are skipped
+    pub fn record_outcome(agent: &AutonomousAgent, result: &AgentTaskResult) {
+        if result.outcome == TaskOutcome::PendingApproval {
+            return;
+        }
+        let (predicted_confidence, expected_duration_seconds) = Self::uncalibrated(&agent.analysis);
+        let outcome = AnalysisOutcome {
+            agent_id: agent.agent_id.clone(),
+            task_id: result.task_id.clone(),
+            predicted_confidence,
+            expected_duration_seconds,
+            success: result.success,
+            duration_ms: result.execution_time_ms,
+            rating: None,
+            recorded_at: time(),
+        };
+        OUTCOMES.with(|o| {
+            let mut outcomes = o.borrow_mut();
+            let sequence = outcomes.last_key_value().map(|(k, _)| k + 1).unwrap_or(0);
+            outcomes.insert(sequence, Cbor(outcome));
+            if sequence >= MAX_OUTCOMES {
+                outcomes.remove(&(sequence - MAX_OUTCOMES));
+            }
+        });
+    }
+
+    /// Attaches an owner rating to the task's recent outcome, if still kept
+    pub fn record_rating(agent_id: &str, task_id: &str, rating: u8) {
+        OUTCOMES.with(|o| {
+            let mut outcomes = o.borrow_mut();
+            let Some((last, _)) = outcomes.last_key_value() else {
+                return;
+            };
+            let found = outcomes
+                .range(last.saturating_sub(RATING_LOOKBACK)..)
+                .filter(|(_, outcome)| outcome.0.agent_id == agent_id && outcome.0.task_id == task_id)
+                .last();
+            if let Some((sequence, mut outcome)) = found {
+                outcome.0.rating = Some(rating);
+                outcomes.insert(sequence, outcome);
+            }
+        });
+    }
+
+    /// Refits the coefficients; too few outcomes keep the current ones
+    pub fn recalibrate() -> Calibration {
+        let outcomes = Self::outcomes();
+        let current = Self::current();
+        if outcomes.len() < MIN_SAMPLES {
+            return current;
+        }
+        let calibration = Calibration {
+            recalibrated_at: Some(time()),
+            ..Self::fit(&outcomes)
+        };
+        CALIBRATION.with(|c| c.borrow_mut().insert(CALIBRATION_KEY, Cbor(calibration.clone())));
+        Metrics::increment_counter("analyzer_recalibrations_total");
+        calibration
+    }
+
+    pub fn stats() -> CalibrationStats {
+        Self::summarize(&Self::outcomes(), Self::current())
+    }
+
+    /// Confidence and expected duration the analyzer produces before calibration
+    fn uncalibrated(analysis: &AnalyzedInstruction) -> (f32, u64) {
+        (
+            InstructionAnalyzer::uncalibrated_confidence(&analysis.original_instruction, &analysis.extracted_capabilities),
+            InstructionAnalyzer::uncalibrated_duration_seconds(&analysis.extracted_capabilities),
+        )
+    }
+
+    fn outcomes() -> Vec<AnalysisOutcome> {
+        OUTCOMES.with(|o| o.borrow().iter().map(|(_, outcome)| outcome.0).collect())
+    }
+
+    fn quality(outcome: &AnalysisOutcome) -> f32 {
+        match outcome.rating {
+            Some(rating) => (rating.clamp(1, 5) - 1) as f32 / 4.0,
+            None => outcome.success as u8 as f32,
+        }
+    }
+
+    fn fit(outcomes: &[AnalysisOutcome]) -> Calibration {
+        let n = outcomes.len() as f32;
+        let mean_x = outcomes.iter().map(|o| o.predicted_confidence).sum::<f32>() / n;
+        let mean_y = outcomes.iter().map(Self::quality).sum::<f32>() / n;
+        let covariance: f32 = outcomes
+            .iter()
+            .map(|o| (o.predicted_confidence - mean_x) * (Self::quality(o) - mean_y))
+            .sum();
+        let variance: f32 = outcomes.iter().map(|o| (o.predicted_confidence - mean_x).powi(2)).sum();
+        // Identical predictions carry no slope information
+        let slope = if variance > f32::EPSILON {
+            (covariance / variance).clamp(*SLOPE_RANGE.start(), *SLOPE_RANGE.end())
+        } else {
+            0.0
+        };
+
+        let ratios: Vec<f64> = outcomes.iter().filter_map(Self::duration_ratio).collect();
+        let duration_scale = if ratios.is_empty() {
+            1.0
+        } else {
+            (ratios.iter().map(|r| r.ln()).sum::<f64>() / ratios.len() as f64)
+                .exp()
+                .clamp(*DURATION_SCALE_RANGE.start(), *DURATION_SCALE_RANGE.end())
+        };
+
+        Calibration {
+            confidence_slope: slope,
+            confidence_intercept: mean_y - slope * mean_x,
+            duration_scale,
+            samples: outcomes.len() as u64,
+            recalibrated_at: None,
+        }
+    }
+
+    /// Actual over uncalibrated expected duration; failures and instant
+    /// results say nothing about duration
+    fn duration_ratio(outcome: &AnalysisOutcome) -> Option<f64> {
+        (outcome.success && outcome.duration_ms > 0 && outcome.expected_duration_seconds > 0)
+            .then(|| outcome.duration_ms as f64 / (outcome.expected_duration_seconds as f64 * 1_000.0))
+    }
+
+    fn summarize(outcomes: &[AnalysisOutcome], calibration: Calibration) -> CalibrationStats {
+        let n = outcomes.len().max(1) as f32;
+        let mut buckets: Vec<ConfidenceBucket> = (0..BUCKETS)
+            .map(|i| ConfidenceBucket {
+                lower: i as f32 / BUCKETS as f32,
+                upper: (i + 1) as f32 / BUCKETS as f32,
+                samples: 0,
+                mean_confidence: 0.0,
+                observed_quality: 0.0,
+            })
+            .collect();
+        for outcome in outcomes {
+            let index = ((outcome.predicted_confidence * BUCKETS as f32) as usize).min(BUCKETS - 1);
+            let bucket = &mut buckets[index];
+            bucket.samples += 1;
+            bucket.mean_confidence += calibration.confidence(outcome.predicted_confidence);
+            bucket.observed_quality += Self::quality(outcome);
+        }
+        for bucket in buckets.iter_mut().filter(|b| b.samples > 0) {
+            bucket.mean_confidence /= bucket.samples as f32;
+            bucket.observed_quality /= bucket.samples as f32;
+        }
+
+        let mut ratios: Vec<f64> = outcomes
+            .iter()
+            .filter_map(Self::duration_ratio)
+            .map(|r| r / calibration.duration_scale)
+            .collect();
+        ratios.sort_by(|a, b| a.total_cmp(b));
+
+        CalibrationStats {
+            samples: outcomes.len() as u64,
+            rated_samples: outcomes.iter().filter(|o| o.rating.is_some()).count() as u64,
+            success_rate: outcomes.iter().filter(|o| o.success).count() as f32 / n,
+            mean_absolute_error: outcomes
+                .iter()
+                .map(|o| (calibration.confidence(o.predicted_confidence) - Self::quality(o)).abs())
+                .sum::<f32>()
+                / n,
+            median_duration_ratio: ratios.get(ratios.len() / 2).copied().unwrap_or(1.0),
+            buckets: buckets.into_iter().filter(|b| b.samples > 0).collect(),
+            calibration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(predicted_confidence: f32, success: bool, rating: Option<u8>, duration_ms: u64) -> AnalysisOutcome {
+        AnalysisOutcome {
+            agent_id: "a".to_string(),
+            task_id: "t".to_string(),
+            predicted_confidence,
+            expected_duration_seconds: 30,
+            success,
+            duration_ms,
+            rating,
+            recorded_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_fit_learns_from_outcomes() {
+        // Tasks take twice the estimate, and low-confidence ones get the worst rating
+        let outcomes: Vec<AnalysisOutcome> = (0..40)
+            .map(|i| if i % 2 == 0 { outcome(0.9, true, None, 60_000) } else { outcome(0.4, false, Some(1), 0) })
+            .collect();
+        let calibration = CalibrationService::fit(&outcomes);
+        assert!((calibration.confidence(0.9) - 1.0).abs() < 1e-4);
+        assert!(calibration.confidence(0.4).abs() < 1e-4);
+        assert!((calibration.duration_scale - 2.0).abs() < 1e-9);
+        assert_eq!(calibration.duration_seconds(30), 60);
+
+        let stats = CalibrationService::summarize(&outcomes, calibration);
+        assert_eq!(stats.buckets.len(), 2);
+        assert_eq!(stats.rated_samples, 20);
+        assert!((stats.median_duration_ratio - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_identical_predictions_fall_back_to_mean_quality() {
+        let outcomes = vec![outcome(0.8, true, None, 0), outcome(0.8, true, Some(3), 0)];
+        let calibration = CalibrationService::fit(&outcomes);
+        assert_eq!(calibration.confidence_slope, 0.0);
+        assert!((calibration.confidence(0.8) - 0.75).abs() < 1e-6);
+        assert_eq!(calibration.duration_scale, 1.0);
+    }
+}
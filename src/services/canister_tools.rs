@@ -0,0 +1,303 @@
+use crate::infra::stable::{memory, Cbor, Memory, TOOL_AUDIT_MEMORY_ID, TOOL_REGISTRY_MEMORY_ID};
+use crate::infra::{Metrics, Resilience};
+use crate::services::tools::ToolCall;
+use candid::{CandidType, Principal, TypeEnv};
+use candid_parser::typing::ast_to_type;
+use candid_parser::{IDLArgs, IDLTypes};
+use ic_cdk::api::call::{call_raw128, msg_cycles_refunded128, RejectionCode};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    static REGISTRY: RefCell<StableBTreeMap<String, Cbor<CanisterTool>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(TOOL_REGISTRY_MEMORY_ID)));
+    static AUDIT: RefCell<StableBTreeMap<u64, Cbor<ToolAuditEntry>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(TOOL_AUDIT_MEMORY_ID)));
+}
+
+const MAX_TOOL_NAME_LEN: usize = 64;
+const MAX_CYCLES_PER_CALL: u64 = 100_000_000_000;
+const MAX_AUDIT_ENTRIES: u64 = 10_000;
+const MAX_AUDITED_TEXT: usize = 1_024;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum ToolRegistrationStatus {
+    Proposed, // Not callable until an admin approves it
+    Approved,
+    Disabled,
+}
+
+/// A method on another canister that agents can call as a tool
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct CanisterTool {
+    pub name: String,
+    pub description: String,
+    pub target: String,
+    pub method: String,
+    pub arg_schema: String, // Candid argument types, e.g. "(text, opt nat64)"
+    pub cycles_budget: u64, // Attached to every call; unused cycles are refunded
+    pub status: ToolRegistrationStatus,
+    pub proposed_by: String,
+    pub approved_by: Option<String>,
+    pub created_at: u64,
+}
+
+/// Record of one call made on behalf of an agent
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ToolAuditEntry {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub agent_id: String,
+    pub call_id: String,
+    pub tool: String,
+    pub target: String,
+    pub method: String,
+    pub requested_by: String,
+    pub arguments: String,
+    pub cycles_attached: u64,
+    pub cycles_refunded: u64,
+    pub success: bool,
+    pub output: String, // Reply as Candid text, or the error
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ToolAuditPage {
+    pub entries: Vec<ToolAuditEntry>,
+    pub next_cursor: Option<u64>,
+}
+
+/// Registry of admin-approved canister tools and the adapter that calls them
+pub struct CanisterToolService;
+
+impl CanisterToolService {
+    pub fn propose(
+        proposer: &str,
+        name: String,
+        description: String,
+        target: String,
+        method: String,
+        arg_schema: String,
+        cycles_budget: u64,
+    ) -> Result<(), String> {
+        if name.is_empty()
+            || name.len() > MAX_TOOL_NAME_LEN
+            || !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.'))
+        {
+            return Err(format!(
+                "Tool name must be 1-{} characters of a-z, 0-9, '_', '-' or '.'",
+                MAX_TOOL_NAME_LEN
+            ));
+        }
+        let target_principal = Principal::from_text(&target).map_err(|e| format!("Invalid target canister id: {}", e))?;
+        if target_principal == ic_cdk::id() {
+            return Err("Canister tools cannot target this canister".to_string());
+        }
+        if method.is_empty() {
+            return Err("Tool method must not be empty".to_string());
+        }
+        if cycles_budget > MAX_CYCLES_PER_CALL {
+            return Err(format!("Cycles budget exceeds the maximum of {}", MAX_CYCLES_PER_CALL));
+        }
+        Self::parse_schema(&arg_schema)?;
+
+        REGISTRY.with(|r| {
+            let mut registry = r.borrow_mut();
+            if registry.contains_key(&name) {
+                return Err(format!("Tool {} is already registered", name));
+            }
+            registry.insert(
+                name.clone(),
+                Cbor(CanisterTool {
+                    name,
+                    description,
+                    target,
+                    method,
+                    arg_schema,
+                    cycles_budget,
+                    status: ToolRegistrationStatus::Proposed,
+                    proposed_by: proposer.to_string(),
+                    approved_by: None,
+                    created_at: time(),
+                }),
+            );
+            Ok(())
+        })
+    }
+
+    /// Admin decision on a proposed tool, or disabling an approved one
+    pub fn set_status(name: &str, status: ToolRegistrationStatus, admin: &str) -> Result<(), String> {
+        let mut tool = Self::get(name)?;
+        tool.approved_by = match status {
+            ToolRegistrationStatus::Approved => Some(admin.to_string()),
+            _ => tool.approved_by,
+        };
+        tool.status = status;
+        REGISTRY.with(|r| r.borrow_mut().insert(name.to_string(), Cbor(tool)));
+        Ok(())
+    }
+
+    pub fn get(name: &str) -> Result<CanisterTool, String> {
+        REGISTRY.with(|r| {
+            r.borrow()
+                .get(&name.to_string())
+                .map(|tool| tool.0)
+                .ok_or_else(|| format!("Canister tool {} not found", name))
+        })
+    }
+
+    pub fn list() -> Vec<CanisterTool> {
+        REGISTRY.with(|r| r.borrow().iter().map(|(_, tool)| tool.0).collect())
+    }
+
+    pub fn is_approved(name: &str) -> bool {
+        Self::get(name).map_or(false, |tool| tool.status == ToolRegistrationStatus::Approved)
+    }
+
+    /// Call the tool with `arguments` (`call.arguments` with env vars expanded)
+    /// as Candid text checked against its schema. The audit keeps the
+    /// unexpanded arguments so secrets stay out of it. Not retried: the callee
+    /// may not be idempotent.
+    pub async fn call(agent_id: &str, tool: &CanisterTool, call: &ToolCall, arguments: &str) -> Result<String, String> {
+        if tool.status != ToolRegistrationStatus::Approved {
+            return Err(format!("Canister tool {} is not approved", tool.name));
+        }
+        let target = Principal::from_text(&tool.target).map_err(|e| e.to_string())?;
+        let args = Self::encode_args(&tool.arg_schema, arguments)?;
+        Resilience::check_breaker(&tool.target)?;
+
+        let result = call_raw128(target, &tool.method, args, tool.cycles_budget as u128).await;
+        let cycles_refunded = msg_cycles_refunded128() as u64;
+
+        let outcome = match result {
+            Ok(reply) => {
+                Resilience::record_success(&tool.target);
+                Ok(IDLArgs::from_bytes(&reply).map_or_else(|_| hex::encode(&reply), |args| args.to_string()))
+            }
+            Err((code, msg)) => {
+                // Application-level rejects say nothing about the callee's health
+                if code != RejectionCode::CanisterReject {
+                    Resilience::record_failure(&tool.target);
+                }
+                Err(format!("Canister tool {} failed: {:?}: {}", tool.name, code, msg))
+            }
+        };
+
+        Self::audit(agent_id, tool, call, cycles_refunded, &outcome);
+        outcome
+    }
+
+    /// Calls made for `agent_id` after `cursor`, oldest first
+    pub fn list_audit(agent_id: &str, cursor: Option<u64>, limit: Option<u32>) -> ToolAuditPage {
+        let limit = limit.map_or(DEFAULT_PAGE_SIZE, |l| (l as usize).clamp(1, MAX_PAGE_SIZE));
+        let start = cursor.map_or(0, |c| c + 1);
+
+        AUDIT.with(|a| {
+            let audit = a.borrow();
+            let mut entries: Vec<ToolAuditEntry> = Vec::new();
+            let mut next_cursor = None;
+
+            for (_, entry) in audit.range(start..) {
+                if entry.0.agent_id != agent_id {
+                    continue;
+                }
+                if entries.len() == limit {
+                    next_cursor = entries.last().map(|e| e.sequence);
+                    break;
+                }
+                entries.push(entry.0);
+            }
+
+            ToolAuditPage { entries, next_cursor }
+        })
+    }
+
+    /// Calls requested by `user` or made for one of `agent_ids`
+    pub fn audit_for(user: &str, agent_ids: &[String]) -> Vec<ToolAuditEntry> {
+        AUDIT.with(|a| {
+            a.borrow()
+                .iter()
+                .map(|(_, entry)| entry.0)
+                .filter(|entry| entry.requested_by == user || agent_ids.contains(&entry.agent_id))
+                .collect()
+        })
+    }
+
+    /// Tombstone the entries audit_for returns: the requester, arguments and
+    /// output are replaced while sequence, tool and cycle figures stay
+    pub fn erase_user(user: &str, agent_ids: &[String], tombstone: &str) -> u32 {
+        let erased = Self::audit_for(user, agent_ids);
+        AUDIT.with(|a| {
+            let mut audit = a.borrow_mut();
+            for mut entry in erased.iter().cloned() {
+                if entry.requested_by == user {
+                    entry.requested_by = tombstone.to_string();
+                }
+                entry.arguments = tombstone.to_string();
+                entry.output = tombstone.to_string();
+                audit.insert(entry.sequence, Cbor(entry));
+            }
+        });
+        erased.len() as u32
+    }
+
+    fn audit(agent_id: &str, tool: &CanisterTool, call: &ToolCall, cycles_refunded: u64, outcome: &Result<String, String>) {
+        AUDIT.with(|a| {
+            let mut audit = a.borrow_mut();
+            let sequence = audit.last_key_value().map(|(seq, _)| seq + 1).unwrap_or(0);
+            let (success, output) = match outcome {
+                Ok(output) => (true, output),
+                Err(error) => (false, error),
+            };
+            audit.insert(
+                sequence,
+                Cbor(ToolAuditEntry {
+                    sequence,
+                    timestamp: time(),
+                    agent_id: agent_id.to_string(),
+                    call_id: call.call_id.clone(),
+                    tool: tool.name.clone(),
+                    target: tool.target.clone(),
+                    method: tool.method.clone(),
+                    requested_by: call.requested_by.clone(),
+                    arguments: Self::truncate(&call.arguments),
+                    cycles_attached: tool.cycles_budget,
+                    cycles_refunded,
+                    success,
+                    output: Self::truncate(output),
+                }),
+            );
+            if sequence >= MAX_AUDIT_ENTRIES {
+                audit.remove(&(sequence - MAX_AUDIT_ENTRIES));
+            }
+        });
+        Metrics::increment_counter("canister_tool_calls_total");
+    }
+
+    fn parse_schema(schema: &str) -> Result<Vec<candid::types::Type>, String> {
+        let types: IDLTypes = schema.parse().map_err(|e| format!("Invalid argument schema: {}", e))?;
+        let env = TypeEnv::new();
+        types
+            .args
+            .iter()
+            .map(|t| ast_to_type(&env, t).map_err(|e| format!("Invalid argument schema: {}", e)))
+            .collect()
+    }
+
+    fn encode_args(schema: &str, arguments: &str) -> Result<Vec<u8>, String> {
+        let types = Self::parse_schema(schema)?;
+        let args: IDLArgs = arguments.parse().map_err(|e| format!("Arguments are not valid Candid: {}", e))?;
+        args.to_bytes_with_types(&TypeEnv::new(), &types)
+            .map_err(|e| format!("Arguments do not match schema {}: {}", schema, e))
+    }
+
+    fn truncate(text: &str) -> String {
+        match text.char_indices().nth(MAX_AUDITED_TEXT) {
+            Some((cut, _)) => format!("{}…", &text[..cut]),
+            None => text.to_string(),
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentFactory, AgentStatusInfo};
+use crate::services::{llm_service, with_state, BenchmarkService, BenchmarkSummary, WorkflowService};
+use candid::CandidType;
+use ic_certified_map::{fork, fork_hash, labeled, labeled_hash, leaf_hash, AsHashTree, Hash, HashTree, RbTree};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const AGENT_STATUS_LABEL: &[u8] = b"agent_status";
+const CATALOG_LABEL: &[u8] = b"catalog";
+// Certified snapshots trail a change by at most this long
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+struct CertifiedState {
+    agent_hashes: RbTree<String, Hash>,
+    agent_statuses: HashMap<String, AgentStatusInfo>,
+    catalog: ModelCatalog,
+    catalog_hash: Hash,
+    root_hash: Hash,
+}
+
+thread_local! {
+    static CERTIFIED: RefCell<CertifiedState> = RefCell::new(CertifiedState {
+        agent_hashes: RbTree::new(),
+        agent_statuses: HashMap::new(),
+        catalog: ModelCatalog::default(),
+        catalog_hash: [0; 32],
+        root_hash: [0; 32],
+    });
+    // Set by updates that change agent status, the binding or the catalog
+    static DIRTY: Cell<bool> = Cell::new(true);
+}
+
+#[derive(Debug, Clone, Default, CandidType)]
+pub struct ModelCatalogEntry {
+    pub model_id: String,
+    pub description: String,
+}
+
+/// Models offered for chat plus the model this canister is bound to
+#[derive(Debug, Clone, Default, CandidType)]
+pub struct ModelCatalog {
+    pub models: Vec<ModelCatalogEntry>,
+    pub bound_model_id: Option<String>,
+    pub bound_manifest_digest: Option<String>,
+    pub benchmarks: Vec<BenchmarkSummary>, // Latest run per model and suite
+}
+
+/// A query response a frontend can verify without trusting the boundary node.
+/// `witness` is a CBOR hash tree whose leaf at /agent_status/{agent_id} or
+/// /catalog is the sha256 of the candid encoding of the returned value, and
+/// whose root matches the certified data in `certificate`.
+#[derive(Debug, Clone, CandidType)]
+pub struct CertifiedAgentStatus {
+    pub status: AgentStatusInfo,
+    pub certificate: Vec<u8>,
+    pub witness: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct CertifiedModelCatalog {
+    pub catalog: ModelCatalog,
+    pub certificate: Vec<u8>,
+    pub witness: Vec<u8>,
+}
+
+/// Keeps the canister's certified data in step with agent status and the model
+/// catalog. Certified data can only be set from updates, so queries serve the
+/// last certified snapshot rather than live state.
+pub struct CertificationService;
+
+impl CertificationService {
+    pub fn start_timer() {
+        Self::refresh();
+        ic_cdk_timers::set_timer_interval(REFRESH_INTERVAL, || {
+            if DIRTY.with(|d| d.get()) {
+                Self::refresh();
+            }
+        });
+    }
+
+    /// Schedule a re-certification on the next timer tick
+    pub fn mark_dirty() {
+        DIRTY.with(|d| d.set(true));
+    }
+
+    /// Re-hash agent statuses and the catalog, and re-certify if anything changed
+    pub fn refresh() {
+        DIRTY.with(|d| d.set(false));
+        let queued_steps = WorkflowService::queued_steps();
+        let statuses: HashMap<String, AgentStatusInfo> = with_state(|state| {
+            state
+                .agents
+                .values()
+                .map(|agent| (agent.agent_id.clone(), AgentFactory::status_info(agent, &queued_steps)))
+                .collect()
+        });
+        let catalog = Self::current_catalog();
+
+        let root_hash = CERTIFIED.with(|c| {
+            let mut certified = c.borrow_mut();
+            let removed: Vec<String> = certified
+                .agent_statuses
+                .keys()
+                .filter(|id| !statuses.contains_key(*id))
+                .cloned()
+                .collect();
+            for agent_id in removed {
+                certified.agent_hashes.delete(agent_id.as_bytes());
+            }
+            for (agent_id, status) in &statuses {
+                let hash = candid_hash(status);
+                if certified.agent_hashes.get(agent_id.as_bytes()) != Some(&hash) {
+                    certified.agent_hashes.insert(agent_id.clone(), hash);
+                }
+            }
+            certified.agent_statuses = statuses;
+            certified.catalog_hash = candid_hash(&catalog);
+            certified.catalog = catalog;
+
+            let root_hash = Self::root_hash(&certified.agent_hashes, &certified.catalog_hash);
+            if root_hash == certified.root_hash {
+                return None;
+            }
+            certified.root_hash = root_hash;
+            Some(root_hash)
+        });
+
+        if let Some(root_hash) = root_hash {
+            ic_cdk::api::set_certified_data(&root_hash);
+            Metrics::increment_counter("certified_data_updates_total");
+        }
+    }
+
+    pub fn agent_status(agent_id: &str) -> Result<CertifiedAgentStatus, String> {
+        let certificate = Self::certificate()?;
+        CERTIFIED.with(|c| {
+            let certified = c.borrow();
+            let status = certified
+                .agent_statuses
+                .get(agent_id)
+                .cloned()
+                .ok_or_else(|| format!("Agent {} has no certified status yet", agent_id))?;
+            let witness = fork(
+                labeled(AGENT_STATUS_LABEL, certified.agent_hashes.witness(agent_id.as_bytes())),
+                HashTree::Pruned(labeled_hash(CATALOG_LABEL, &leaf_hash(&certified.catalog_hash))),
+            );
+            Ok(CertifiedAgentStatus {
+                status,
+                certificate,
+                witness: encode_witness(&witness)?,
+            })
+        })
+    }
+
+    pub fn model_catalog() -> Result<CertifiedModelCatalog, String> {
+        let certificate = Self::certificate()?;
+        CERTIFIED.with(|c| {
+            let certified = c.borrow();
+            let witness = fork(
+                HashTree::Pruned(labeled_hash(AGENT_STATUS_LABEL, &certified.agent_hashes.root_hash())),
+                labeled(CATALOG_LABEL, HashTree::Leaf(certified.catalog_hash.to_vec().into())),
+            );
+            Ok(CertifiedModelCatalog {
+                catalog: certified.catalog.clone(),
+                certificate,
+                witness: encode_witness(&witness)?,
+            })
+        })
+    }
+
+    fn certificate() -> Result<Vec<u8>, String> {
+        ic_cdk::api::data_certificate().ok_or_else(|| "Certificates are only available in query calls".to_string())
+    }
+
+    fn current_catalog() -> ModelCatalog {
+        let binding = with_state(|state| state.binding.clone());
+        ModelCatalog {
+            models: llm_service()
+                .get_available_models()
+                .iter()
+                .map(|model| ModelCatalogEntry {
+                    model_id: model.model_id().to_string(),
+                    description: model.description().to_string(),
+                })
+                .collect(),
+            bound_model_id: binding.as_ref().map(|b| b.model_id.clone()),
+            bound_manifest_digest: binding.map(|b| b.manifest_digest),
+            benchmarks: BenchmarkService::summaries(),
+        }
+    }
+
+    fn root_hash(agent_hashes: &RbTree<String, Hash>, catalog_hash: &Hash) -> Hash {
+        fork_hash(
+            &labeled_hash(AGENT_STATUS_LABEL, &agent_hashes.root_hash()),
+            &labeled_hash(CATALOG_LABEL, &leaf_hash(catalog_hash)),
+        )
+    }
+}
+
+fn candid_hash<T: CandidType>(value: &T) -> Hash {
+    // Encoding a derived CandidType value cannot fail
+    let bytes = candid::encode_one(value).unwrap_or_default();
+    Sha256::digest(&bytes).into()
+}
+
+fn encode_witness(witness: &HashTree) -> Result<Vec<u8>, String> {
+    let mut serializer = serde_cbor::ser::Serializer::new(Vec::new());
+    serializer.self_describe().map_err(|e| format!("Failed to encode witness: {}", e))?;
+    witness
+        .serialize(&mut serializer)
+        .map_err(|e| format!("Failed to encode witness: {}", e))?;
+    Ok(serializer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_witness_reconstructs_certified_root() {
+        let mut agent_hashes = RbTree::new();
+        agent_hashes.insert("agent_a".to_string(), [1u8; 32]);
+        agent_hashes.insert("agent_b".to_string(), [2u8; 32]);
+        let catalog_hash = [3u8; 32];
+        let root = CertificationService::root_hash(&agent_hashes, &catalog_hash);
+
+        let agent_witness = fork(
+            labeled(AGENT_STATUS_LABEL, agent_hashes.witness(b"agent_b")),
+            HashTree::Pruned(labeled_hash(CATALOG_LABEL, &leaf_hash(&catalog_hash))),
+        );
+        assert_eq!(agent_witness.reconstruct(), root);
+
+        let catalog_witness = fork(
+            HashTree::Pruned(labeled_hash(AGENT_STATUS_LABEL, &agent_hashes.root_hash())),
+            labeled(CATALOG_LABEL, HashTree::Leaf(catalog_hash.to_vec().into())),
+        );
+        assert_eq!(catalog_witness.reconstruct(), root);
+    }
+}
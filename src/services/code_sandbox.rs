@@ -0,0 +1,211 @@
+use candid::CandidType;
+use rhai::{Dynamic, Engine, EvalAltResult, ImmutableString, Scope};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+pub const CODE_EXEC_TOOL: &str = "code_executor";
+
+// Shared by the snippet and all of its tests
+const MAX_OPERATIONS: u64 = 1_000_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_STRING_SIZE: usize = 64 * 1024;
+const MAX_ARRAY_SIZE: usize = 10_000;
+const MAX_MAP_SIZE: usize = 10_000;
+const MAX_OUTPUT_LINES: usize = 200;
+const MAX_TESTS: usize = 50;
+
+/// Snippet to run, with optional test expressions evaluated against its functions and variables
+#[derive(Debug, Clone, Default, Serialize, Deserialize, CandidType)]
+pub struct CodeExecutionRequest {
+    pub code: String,
+    #[serde(default)]
+    pub tests: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TestOutcome {
+    pub index: u32,
+    pub passed: bool,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct CodeExecutionResult {
+    pub success: bool,
+    pub output: Vec<String>, // print() and debug() lines
+    pub return_value: Option<String>,
+    pub error: Option<String>,
+    pub operations: u64,
+    pub tests: Vec<TestOutcome>,
+}
+
+/// Embedded Rhai interpreter with operation, depth and size limits. Scripts have
+/// no file, network or module access; print output is captured.
+pub struct CodeSandbox;
+
+impl CodeSandbox {
+    /// Tool arguments are either a JSON `CodeExecutionRequest` or the bare script
+    pub fn run_tool(arguments: &str) -> Result<String, String> {
+        let request = serde_json::from_str::<CodeExecutionRequest>(arguments).unwrap_or_else(|_| CodeExecutionRequest {
+            code: arguments.to_string(),
+            tests: Vec::new(),
+        });
+        let result = Self::execute(&request)?;
+        serde_json::to_string(&result).map_err(|e| e.to_string())
+    }
+
+    pub fn execute(request: &CodeExecutionRequest) -> Result<CodeExecutionResult, String> {
+        if request.tests.len() > MAX_TESTS {
+            return Err(format!("At most {} tests per execution", MAX_TESTS));
+        }
+
+        let output = Rc::new(RefCell::new(Vec::new()));
+        // Rhai counts operations per evaluation; `spent` carries the total of finished ones
+        let spent = Rc::new(Cell::new(0u64));
+        let operations = Rc::new(Cell::new(0u64));
+        let engine = Self::engine(output.clone(), spent.clone(), operations.clone());
+
+        let mut result = CodeExecutionResult {
+            success: false,
+            output: Vec::new(),
+            return_value: None,
+            error: None,
+            operations: 0,
+            tests: Vec::new(),
+        };
+
+        let mut scope = Scope::new();
+        let ast = match engine.compile(&request.code) {
+            Ok(ast) => ast,
+            Err(e) => {
+                result.error = Some(format!("Compile error: {}", e));
+                return Ok(result);
+            }
+        };
+
+        match engine.eval_ast_with_scope::<Dynamic>(&mut scope, &ast) {
+            Ok(value) => {
+                result.success = true;
+                result.return_value = (!value.is_unit()).then(|| value.to_string());
+            }
+            Err(e) => result.error = Some(e.to_string()),
+        }
+        spent.set(operations.get());
+
+        // Each test sees the snippet's functions and a copy of its top-level variables
+        let functions = ast.clone_functions_only();
+        for (index, test) in request.tests.iter().enumerate() {
+            if spent.get() >= MAX_OPERATIONS {
+                result.tests.push(TestOutcome {
+                    index: index as u32,
+                    passed: false,
+                    message: Some("Operation budget exhausted".to_string()),
+                });
+                continue;
+            }
+            let outcome = engine
+                .compile(test)
+                .map_err(|e| format!("Compile error: {}", e))
+                .and_then(|test_ast| {
+                    engine
+                        .eval_ast_with_scope::<Dynamic>(&mut scope.clone(), &functions.merge(&test_ast))
+                        .map_err(|e| e.to_string())
+                })
+                .and_then(|value| match value.as_bool() {
+                    Ok(false) => Err("Test evaluated to false".to_string()),
+                    _ => Ok(()),
+                });
+            spent.set(operations.get());
+            result.tests.push(TestOutcome {
+                index: index as u32,
+                passed: outcome.is_ok(),
+                message: outcome.err(),
+            });
+        }
+
+        result.operations = operations.get();
+        result.output = output.take();
+        Ok(result)
+    }
+
+    fn engine(output: Rc<RefCell<Vec<String>>>, spent: Rc<Cell<u64>>, operations: Rc<Cell<u64>>) -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_ARRAY_SIZE)
+            .set_max_map_size(MAX_MAP_SIZE);
+
+        let print_output = output.clone();
+        engine.on_print(move |line| Self::capture(&print_output, line.to_string()));
+        let debug_output = output;
+        engine.on_debug(move |line, _, pos| Self::capture(&debug_output, format!("[{}] {}", pos, line)));
+        engine.on_progress(move |ops| {
+            let total = spent.get() + ops;
+            operations.set(total);
+            (total > MAX_OPERATIONS).then(|| "Operation budget exhausted".into())
+        });
+
+        engine.register_fn("assert", |condition: bool, message: ImmutableString| -> Result<(), Box<EvalAltResult>> {
+            if condition {
+                Ok(())
+            } else {
+                Err(format!("Assertion failed: {}", message).into())
+            }
+        });
+        engine
+    }
+
+    fn capture(output: &RefCell<Vec<String>>, line: String) {
+        let mut output = output.borrow_mut();
+        if output.len() < MAX_OUTPUT_LINES {
+            output.push(line);
+        }
+    }
+
+    /// Bodies of ```rhai fenced blocks in generated text
+    pub fn extract_blocks(text: &str) -> Vec<String> {
+        text.split("```rhai")
+            .skip(1)
+            .filter_map(|rest| rest.split_once("```").map(|(code, _)| code.trim().to_string()))
+            .filter(|code| !code.is_empty())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runaway_loop_hits_operation_limit() {
+        let request = CodeExecutionRequest {
+            code: "fn double(x) { x * 2 } print(double(21));".to_string(),
+            tests: vec![
+                "assert(double(2) == 4, \"double\")".to_string(),
+                "double(1) == 3".to_string(),
+                "loop {}".to_string(),
+                "double(2) == 4".to_string(),
+            ],
+        };
+        let result = CodeSandbox::execute(&request).unwrap();
+        assert!(result.success);
+        assert_eq!(result.output, vec!["42"]);
+        assert!(result.tests[0].passed);
+        assert!(!result.tests[1].passed);
+        // The runaway test spends the budget shared with everything after it
+        assert!(!result.tests[2].passed);
+        assert_eq!(result.tests[3].message.as_deref(), Some("Operation budget exhausted"));
+        assert!(result.operations <= MAX_OPERATIONS + 1);
+    }
+
+    #[test]
+    fn test_extract_rhai_blocks() {
+        let text = "Here:\n```rhai\nlet x = 1;\n```\nand ```rust\nfn a() {}\n```";
+        assert_eq!(CodeSandbox::extract_blocks(text), vec!["let x = 1;"]);
+    }
+}
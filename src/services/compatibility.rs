@@ -0,0 +1,188 @@
+use crate::services::modelrepo::{ModelManifest, ModelMeta};
+use crate::services::novaq_validation::NOVAQModelMeta;
+use crate::services::{with_state, ValidationHistoryService};
+use candid::CandidType;
+
+// Architectures the agent runtime has kernels for
+const SUPPORTED_ARCHS: &[&str] = &["llama", "mistral", "mixtral", "qwen2", "gemma", "phi3", "starcoder"];
+// Tokenizers built into the runtime; others must ship as a manifest chunk named after the tokenizer_id
+const BUILTIN_TOKENIZERS: &[&str] = &["llama", "llama3", "mistral", "qwen2", "gemma", "phi3", "gpt2"];
+const SUPPORTED_TARGET_BITS: std::ops::RangeInclusive<f32> = 1.0..=8.0;
+
+#[derive(Debug, Clone, PartialEq, CandidType)]
+pub enum CompatibilityCheck {
+    Architecture,
+    Tokenizer,
+    ContextWindow,
+    Quantization,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct CompatibilityIssue {
+    pub check: CompatibilityCheck,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct CompatibilityReport {
+    pub model_id: String,
+    pub version: String,
+    pub compatible: bool,
+    pub issues: Vec<CompatibilityIssue>,
+}
+
+impl CompatibilityReport {
+    /// The IncompatibleModel error bind_model fails with
+    pub fn to_error(&self) -> String {
+        let issues: Vec<String> = self.issues.iter().map(|i| format!("{:?}: {}", i.check, i.message)).collect();
+        format!("IncompatibleModel {}@{}: {}", self.model_id, self.version, issues.join("; "))
+    }
+}
+
+/// Whether the agent runtime can serve a model, from its ModelMeta and the
+/// NOVAQ metadata recorded when it was last validated
+pub struct CompatibilityService;
+
+impl CompatibilityService {
+    pub fn ensure(manifest: &ModelManifest, meta: &ModelMeta) -> Result<(), String> {
+        let report = Self::check(manifest, meta);
+        if report.compatible {
+            Ok(())
+        } else {
+            Err(report.to_error())
+        }
+    }
+
+    pub fn check(manifest: &ModelManifest, meta: &ModelMeta) -> CompatibilityReport {
+        let max_tokens = with_state(|s| s.config.max_tokens);
+        let novaq = ValidationHistoryService::latest(&manifest.model_id).and_then(|record| record.novaq_meta);
+        Self::evaluate(manifest, meta, novaq.as_ref(), max_tokens)
+    }
+
+    fn evaluate(
+        manifest: &ModelManifest,
+        meta: &ModelMeta,
+        novaq: Option<&NOVAQModelMeta>,
+        max_tokens: u32,
+    ) -> CompatibilityReport {
+        let mut issues = Vec::new();
+        let mut issue = |check, message: String| issues.push(CompatibilityIssue { check, message });
+
+        let arch = meta.arch.to_lowercase();
+        if !SUPPORTED_ARCHS.contains(&arch.as_str()) {
+            issue(
+                CompatibilityCheck::Architecture,
+                format!("Architecture {} is not supported; supported: {}", meta.arch, SUPPORTED_ARCHS.join(", ")),
+            );
+        }
+
+        let tokenizer = meta.tokenizer_id.to_lowercase();
+        if tokenizer.is_empty() {
+            issue(CompatibilityCheck::Tokenizer, "ModelMeta names no tokenizer".to_string());
+        } else if !BUILTIN_TOKENIZERS.contains(&tokenizer.as_str())
+            && !manifest.chunks.iter().any(|c| c.id == meta.tokenizer_id)
+        {
+            issue(
+                CompatibilityCheck::Tokenizer,
+                format!("Tokenizer {} is neither built in nor shipped as a manifest chunk", meta.tokenizer_id),
+            );
+        }
+        if meta.vocab_size == 0 {
+            issue(CompatibilityCheck::Tokenizer, "ModelMeta reports an empty vocabulary".to_string());
+        }
+
+        if meta.ctx_window <= max_tokens {
+            issue(
+                CompatibilityCheck::ContextWindow,
+                format!(
+                    "Context window of {} tokens leaves no room for prompts with max_tokens {}",
+                    meta.ctx_window, max_tokens
+                ),
+            );
+        }
+
+        if let Some(novaq) = novaq {
+            if !SUPPORTED_TARGET_BITS.contains(&novaq.target_bits) {
+                issue(
+                    CompatibilityCheck::Quantization,
+                    format!(
+                        "{}-bit NOVAQ quantization is outside the supported {}-{} bits",
+                        novaq.target_bits,
+                        SUPPORTED_TARGET_BITS.start(),
+                        SUPPORTED_TARGET_BITS.end()
+                    ),
+                );
+            }
+            if novaq.num_subspaces == 0 || novaq.l1_codebook_size == 0 {
+                issue(CompatibilityCheck::Quantization, "NOVAQ codebooks are empty".to_string());
+            }
+        }
+
+        CompatibilityReport {
+            model_id: manifest.model_id.clone(),
+            version: manifest.version.clone(),
+            compatible: issues.is_empty(),
+            issues,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::modelrepo::{ChunkInfo, ModelState};
+
+    fn manifest() -> ModelManifest {
+        ModelManifest {
+            model_id: "m".to_string(),
+            version: "1".to_string(),
+            chunks: vec![ChunkInfo { id: "sp-custom".to_string(), offset: 0, size: 1, sha256: String::new() }],
+            digest: String::new(),
+            state: ModelState::Active,
+            uploaded_at: 0,
+            activated_at: None,
+        }
+    }
+
+    fn meta(arch: &str, tokenizer_id: &str, ctx_window: u32) -> ModelMeta {
+        ModelMeta {
+            family: "f".to_string(),
+            arch: arch.to_string(),
+            tokenizer_id: tokenizer_id.to_string(),
+            vocab_size: 32_000,
+            ctx_window,
+            license: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_compatible_model_passes() {
+        let report = CompatibilityService::evaluate(&manifest(), &meta("Llama", "sp-custom", 4096), None, 2048);
+        assert!(report.compatible, "{:?}", report.issues);
+    }
+
+    #[test]
+    fn test_reports_every_incompatibility() {
+        let novaq = NOVAQModelMeta {
+            target_bits: 0.5,
+            num_subspaces: 4,
+            l1_codebook_size: 16,
+            l2_codebook_size: 16,
+            compression_ratio: 10.0,
+            bit_accuracy: 0.9,
+            quality_score: 0.9,
+        };
+        let report = CompatibilityService::evaluate(&manifest(), &meta("rwkv", "missing", 1024), Some(&novaq), 2048);
+        let checks: Vec<_> = report.issues.iter().map(|i| i.check.clone()).collect();
+        assert_eq!(
+            checks,
+            vec![
+                CompatibilityCheck::Architecture,
+                CompatibilityCheck::Tokenizer,
+                CompatibilityCheck::ContextWindow,
+                CompatibilityCheck::Quantization,
+            ]
+        );
+        assert!(report.to_error().starts_with("IncompatibleModel m@1: Architecture:"));
+    }
+}
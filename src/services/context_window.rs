@@ -0,0 +1,149 @@
+use crate::domain::ContextPolicy;
+use crate::services::dfinity_llm::{LlmError, MessageRole};
+use crate::services::{with_state, ModelPoolService};
+use std::fmt;
+
+/// Prompt plus requested output did not fit the bound model's context window
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextTooLong {
+    pub prompt_tokens: u64,
+    pub max_tokens: u32,
+    pub ctx_window: u32,
+}
+
+impl fmt::Display for ContextTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Context too long: {} prompt tokens plus {} max_tokens exceed the {} token context window",
+            self.prompt_tokens, self.max_tokens, self.ctx_window
+        )
+    }
+}
+
+impl From<ContextTooLong> for LlmError {
+    fn from(e: ContextTooLong) -> Self {
+        LlmError::ContextTooLong { prompt_tokens: e.prompt_tokens, max_tokens: e.max_tokens, ctx_window: e.ctx_window }
+    }
+}
+
+/// Keeps prompt_tokens + max_tokens within the `ctx_window` of the serving
+/// model's ModelMeta. Tokens use the four-chars-per-token estimate of quota
+/// accounting. Nothing is enforced until a model is bound.
+pub struct ContextWindow;
+
+impl ContextWindow {
+    /// `max_tokens` of None reserves the canister's configured max_tokens.
+    /// `model_id` names a pool model; None uses the bound model.
+    pub fn fit_prompt(model_id: Option<&str>, prompt: &str, max_tokens: Option<u32>) -> Result<String, ContextTooLong> {
+        let turns = Self::fit_turns_for(model_id, vec![(MessageRole::User, prompt.to_string())], max_tokens)?;
+        Ok(turns.into_iter().map(|(_, content)| content).collect())
+    }
+
+    /// Tokens the prompt may use next to `max_tokens` of output; None until a model is bound
+    pub fn prompt_budget(model_id: Option<&str>, max_tokens: Option<u32>) -> Option<u64> {
+        with_state(|s| {
+            let ctx_window = ModelPoolService::meta(s, model_id)?.ctx_window;
+            Some((ctx_window as u64).saturating_sub(max_tokens.unwrap_or(s.config.max_tokens) as u64))
+        })
+    }
+
+    pub fn fit_turns(
+        turns: Vec<(MessageRole, String)>,
+        max_tokens: Option<u32>,
+    ) -> Result<Vec<(MessageRole, String)>, ContextTooLong> {
+        Self::fit_turns_for(None, turns, max_tokens)
+    }
+
+    fn fit_turns_for(
+        model_id: Option<&str>,
+        turns: Vec<(MessageRole, String)>,
+        max_tokens: Option<u32>,
+    ) -> Result<Vec<(MessageRole, String)>, ContextTooLong> {
+        let (ctx_window, policy, default_max_tokens) = with_state(|s| {
+            (
+                ModelPoolService::meta(s, model_id).map(|meta| meta.ctx_window),
+                s.config.context_policy.unwrap_or_default(),
+                s.config.max_tokens,
+            )
+        });
+        match ctx_window {
+            Some(ctx_window) => Self::fit(turns, max_tokens.unwrap_or(default_max_tokens), ctx_window, policy),
+            None => Ok(turns),
+        }
+    }
+
+    /// Truncation drops the oldest non-system turns, then shortens the last
+    /// turn. System turns and the start of the last turn are always kept.
+    fn fit(
+        mut turns: Vec<(MessageRole, String)>,
+        max_tokens: u32,
+        ctx_window: u32,
+        policy: ContextPolicy,
+    ) -> Result<Vec<(MessageRole, String)>, ContextTooLong> {
+        let prompt_tokens = |turns: &[(MessageRole, String)]| turns.iter().map(|(_, c)| Self::estimate_tokens(c)).sum::<u64>();
+        let too_long = ContextTooLong { prompt_tokens: prompt_tokens(&turns), max_tokens, ctx_window };
+        let budget = (ctx_window as u64).saturating_sub(max_tokens as u64);
+        if too_long.prompt_tokens <= budget {
+            return Ok(turns);
+        }
+        if matches!(policy, ContextPolicy::Reject) || turns.is_empty() {
+            return Err(too_long);
+        }
+
+        let mut index = 0;
+        while index < turns.len() - 1 && prompt_tokens(&turns) > budget {
+            if matches!(turns[index].0, MessageRole::System) {
+                index += 1;
+            } else {
+                turns.remove(index);
+            }
+        }
+        let others = prompt_tokens(&turns[..turns.len() - 1]);
+        let Some(room) = budget.checked_sub(others).filter(|room| *room > 0) else {
+            return Err(too_long);
+        };
+        let content = &mut turns.last_mut().expect("last turn kept").1;
+        if let Some((cut, _)) = content.char_indices().nth(room as usize * 4) {
+            content.truncate(cut);
+        }
+        Ok(turns)
+    }
+
+    pub fn estimate_tokens(text: &str) -> u64 {
+        (text.len() / 4) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turns() -> Vec<(MessageRole, String)> {
+        vec![
+            (MessageRole::System, "s".repeat(40)),
+            (MessageRole::User, "u".repeat(400)),
+            (MessageRole::Assistant, "a".repeat(400)),
+            (MessageRole::User, "q".repeat(200)),
+        ]
+    }
+
+    #[test]
+    fn test_reject_reports_sizes() {
+        let err = ContextWindow::fit(turns(), 100, 250, ContextPolicy::Reject).unwrap_err();
+        assert_eq!(err, ContextTooLong { prompt_tokens: 260, max_tokens: 100, ctx_window: 250 });
+        assert!(ContextWindow::fit(turns(), 100, 400, ContextPolicy::Reject).is_ok());
+    }
+
+    #[test]
+    fn test_truncate_drops_oldest_turns_first() {
+        let fitted = ContextWindow::fit(turns(), 100, 170, ContextPolicy::Truncate).unwrap();
+        assert_eq!(fitted.len(), 2);
+        assert!(matches!(fitted[0].0, MessageRole::System));
+        assert_eq!(fitted[1].1, "q".repeat(200));
+
+        let cut = ContextWindow::fit(turns(), 100, 130, ContextPolicy::Truncate).unwrap();
+        assert_eq!(cut[1].1.len(), 80);
+        assert!(ContextWindow::fit(turns(), 100, 100, ContextPolicy::Truncate).is_err());
+    }
+}
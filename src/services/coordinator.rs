@@ -0,0 +1,187 @@
+use crate::domain::instruction::*;
+use crate::domain::{DecodeParams, InferenceRequest};
+use crate::services::agent_factory::{AgentFactory, AutonomousAgent};
+use crate::services::workflow::Workflow;
+use crate::services::InferenceService;
+
+const MAX_SUBTASKS: usize = 8;
+
+/// A subtask assigned to a member agent by the coordinator
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub agent_id: String,
+    pub subtask: String,
+}
+
+/// LLM-driven task decomposition, assignment and synthesis for coordinator agents
+pub struct CoordinatorService;
+
+impl CoordinatorService {
+    /// Split the task into subtasks and assign each to a member agent
+    pub async fn plan(coordinator: &AutonomousAgent, task_description: &str) -> Result<Vec<Assignment>, String> {
+        if coordinator.member_ids.is_empty() {
+            return Err(format!("Coordinator {} has no member agents", coordinator.agent_id));
+        }
+
+        let members: Vec<AutonomousAgent> = coordinator
+            .member_ids
+            .iter()
+            .map(|id| AgentFactory::authorize(id, &coordinator.user_id))
+            .collect::<Result<_, _>>()?;
+
+        let subtasks = Self::decompose(coordinator, task_description, members.len()).await?;
+        let strategy = &coordinator.analysis.coordination_requirements.task_distribution;
+
+        Ok(Self::assign(&members, subtasks, strategy))
+    }
+
+    /// Combine step outputs into a single answer using the given agent
+    pub async fn synthesize(coordinator_id: &str, workflow: &Workflow) -> Result<String, String> {
+        let coordinator = AgentFactory::find_agent(coordinator_id)?;
+
+        let mut prompt = format!(
+            "You are a coordinator. Combine the following results from your team into one complete, \
+             consistent answer to the original task.\n\nOriginal task: {}\n",
+            workflow.task_description
+        );
+        for step in &workflow.steps {
+            prompt.push_str(&format!(
+                "\nSubtask {}: {}\nResult: {}\n",
+                step.index + 1,
+                step.description,
+                step.output.as_deref().unwrap_or("(no output)")
+            ));
+        }
+
+        let msg_id = format!("{}-synthesis", workflow.workflow_id);
+        Self::complete(&coordinator, &msg_id, prompt).await
+    }
+
+    async fn decompose(
+        coordinator: &AutonomousAgent,
+        task_description: &str,
+        member_count: usize,
+    ) -> Result<Vec<String>, String> {
+        let max_subtasks = (member_count * 2).min(MAX_SUBTASKS);
+        let prompt = format!(
+            "You are a coordinator managing {} specialist agents. Break the following task into at most {} \
+             independent subtasks. Reply with one subtask per line as a numbered list and nothing else.\n\nTask: {}",
+            member_count, max_subtasks, task_description
+        );
+
+        let msg_id = format!("{}-plan-{}", coordinator.agent_id, crate::infra::clock::time());
+        let response = Self::complete(coordinator, &msg_id, prompt).await?;
+
+        let mut subtasks = Self::parse_subtasks(&response);
+        subtasks.truncate(max_subtasks);
+        if subtasks.is_empty() {
+            subtasks.push(task_description.to_string());
+        }
+        Ok(subtasks)
+    }
+
+    fn parse_subtasks(response: &str) -> Vec<String> {
+        response
+            .lines()
+            .map(|line| {
+                line.trim()
+                    .trim_start_matches(|c: char| c.is_ascii_digit())
+                    .trim_start_matches(['.', ')', '-', '*', ':'])
+                    .trim()
+                    .to_string()
+            })
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    fn assign(
+        members: &[AutonomousAgent],
+        subtasks: Vec<String>,
+        strategy: &TaskDistributionStrategy,
+    ) -> Vec<Assignment> {
+        let mut load = vec![0usize; members.len()];
+
+        // Healthiest members first for priority-based distribution
+        let mut by_health: Vec<usize> = (0..members.len()).collect();
+        by_health.sort_by(|&a, &b| {
+            members[b].performance_metrics.health_score
+                .partial_cmp(&members[a].performance_metrics.health_score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        subtasks
+            .into_iter()
+            .enumerate()
+            .map(|(i, subtask)| {
+                let member = match strategy {
+                    TaskDistributionStrategy::RoundRobin => i % members.len(),
+                    TaskDistributionStrategy::PriorityBased => by_health[i % members.len()],
+                    TaskDistributionStrategy::LoadBalanced => Self::least_loaded(members, &load),
+                    TaskDistributionStrategy::CapabilityBased => {
+                        Self::best_capability_match(members, &subtask).unwrap_or(i % members.len())
+                    }
+                };
+                load[member] += 1;
+                Assignment {
+                    agent_id: members[member].agent_id.clone(),
+                    subtask,
+                }
+            })
+            .collect()
+    }
+
+    fn least_loaded(members: &[AutonomousAgent], load: &[usize]) -> usize {
+        (0..members.len())
+            .min_by_key(|&i| (load[i], members[i].performance_metrics.tasks_completed))
+            .unwrap_or(0)
+    }
+
+    /// Member whose capability names share the most words with the subtask
+    fn best_capability_match(members: &[AutonomousAgent], subtask: &str) -> Option<usize> {
+        let text = subtask.to_lowercase();
+        members
+            .iter()
+            .enumerate()
+            .map(|(i, member)| {
+                let score = member
+                    .analysis
+                    .extracted_capabilities
+                    .iter()
+                    .flat_map(|c| c.name.split_whitespace().map(|w| w.to_lowercase()).collect::<Vec<_>>())
+                    .filter(|word| text.contains(word.as_str()))
+                    .count();
+                (i, score)
+            })
+            .filter(|(_, score)| *score > 0)
+            .max_by_key(|(_, score)| *score)
+            .map(|(i, _)| i)
+    }
+
+    pub(crate) async fn complete(agent: &AutonomousAgent, msg_id: &str, prompt: String) -> Result<String, String> {
+        let request = InferenceRequest {
+            seed: None,
+            prompt,
+            decode_params: DecodeParams::default(),
+            msg_id: msg_id.to_string(),
+            model_id: None,
+            structured_output: None,
+        };
+        InferenceService::process_inference(request)
+            .await
+            .map(|response| response.generated_text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_numbered_subtasks() {
+        let response = "1. Gather requirements\n2) Draft the design\n\n- Review it\n";
+        assert_eq!(
+            CoordinatorService::parse_subtasks(response),
+            vec!["Gather requirements", "Draft the design", "Review it"]
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use crate::domain::instruction::{AgentType, SubscriptionTier};
+use crate::infra::stable::{memory, Cbor, Memory, COST_SAMPLES_MEMORY_ID};
+use crate::infra::Guards;
+use crate::services::agent_factory::{AgentFactory, AgentTask, AgentTaskResult, AutonomousAgent, TaskOutcome};
+use crate::services::budget::BudgetService;
+use crate::services::context_window::ContextWindow;
+use crate::services::dfinity_llm::QuantizedModel;
+use crate::services::payments::PaymentService;
+use crate::services::pricing::PricingService;
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    static SAMPLES: RefCell<StableBTreeMap<String, Cbor<CostHistory>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(COST_SAMPLES_MEMORY_ID)));
+}
+
+const MAX_SAMPLES_PER_TYPE: usize = 500;
+// Fewer samples than this fall back to all agent types, then to the heuristic
+const MIN_SAMPLES_FOR_ESTIMATE: usize = 10;
+const HEURISTIC_CONFIDENCE: f32 = 0.2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CostSample {
+    pub prompt_tokens: u64, // Tokenizer estimate of the task description
+    pub tokens_used: u64,
+    pub cycles: u64,
+}
+
+/// Recent task costs of one agent type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CostHistory {
+    pub agent_type: AgentType,
+    pub samples: Vec<CostSample>, // Oldest first
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, CandidType)]
+pub enum CostBasis {
+    AgentTypeHistory, // Past tasks of agents of the same type
+    AllAgentsHistory, // Past tasks of every agent type
+    Heuristic,        // No history yet: the prompt plus up to the agent's max_tokens
+}
+
+/// `low` and `high` bound the middle 80% of past tasks
+#[derive(Debug, Clone, CandidType)]
+pub struct CostRange {
+    pub low: u64,
+    pub expected: u64,
+    pub high: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct UsdRange {
+    pub low: f64,
+    pub expected: f64,
+    pub high: f64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct CostEstimate {
+    pub agent_id: String,
+    pub prompt_tokens: u64,
+    pub tokens: CostRange,
+    pub cycles: CostRange,
+    pub usd: UsdRange,
+    pub ledger_price_per_task: Option<u64>, // Charged on top when pay-per-task is configured
+    pub basis: CostBasis,
+    pub samples: u64,
+    pub confidence: f32,
+    pub budget_warning: Option<String>, // Set when the high estimate would exceed the agent's budget
+}
+
+/// Task cost estimates before execution. The task description is measured
+/// with the quota tokenizer and the rest comes from what past tasks of the
+/// same agent type used beyond their description: p10, p50 and p90 of
+/// tokens and cycles. Fiat figures follow the pricing table, with the task
+/// description charged as input tokens and the rest as output tokens at the
+/// owner's tier. Deferred and held tasks ran nothing and are not recorded.
+pub struct CostService;
+
+impl CostService {
+    pub fn record(agent: &AutonomousAgent, task: &AgentTask, result: &AgentTaskResult, cycles: u64) {
+        if matches!(result.outcome, TaskOutcome::PendingApproval | TaskOutcome::Deferred) {
+            return;
+        }
+        let agent_type = agent.analysis.agent_configuration.agent_type.clone();
+        let key = format!("{:?}", agent_type);
+        SAMPLES.with(|s| {
+            let mut samples = s.borrow_mut();
+            let mut history = samples.get(&key).map(|h| h.0).unwrap_or(CostHistory {
+                agent_type,
+                samples: Vec::new(),
+            });
+            history.samples.push(CostSample {
+                prompt_tokens: ContextWindow::estimate_tokens(&task.description),
+                tokens_used: result.tokens_used,
+                cycles,
+            });
+            if history.samples.len() > MAX_SAMPLES_PER_TYPE {
+                let overflow = history.samples.len() - MAX_SAMPLES_PER_TYPE;
+                history.samples.drain(..overflow);
+            }
+            samples.insert(key, Cbor(history));
+        });
+    }
+
+    pub fn estimate(agent_id: &str, task_description: &str) -> Result<CostEstimate, String> {
+        if task_description.trim().is_empty() {
+            return Err("Invalid task description: it cannot be empty".to_string());
+        }
+        let agent = AgentFactory::find_agent(agent_id)?;
+        let agent_type = agent.analysis.agent_configuration.agent_type.clone();
+        let prompt_tokens = ContextWindow::estimate_tokens(task_description);
+
+        let own = SAMPLES.with(|s| s.borrow().get(&format!("{:?}", agent_type)).map(|h| h.0.samples)).unwrap_or_default();
+        let (samples, basis) = if own.len() >= MIN_SAMPLES_FOR_ESTIMATE {
+            (own, CostBasis::AgentTypeHistory)
+        } else {
+            let all: Vec<CostSample> = SAMPLES.with(|s| s.borrow().iter().flat_map(|(_, h)| h.0.samples).collect());
+            if all.len() >= MIN_SAMPLES_FOR_ESTIMATE {
+                (all, CostBasis::AllAgentsHistory)
+            } else {
+                (all, CostBasis::Heuristic)
+            }
+        };
+        let (tokens, cycles, confidence) = match basis {
+            CostBasis::Heuristic => (
+                CostRange {
+                    low: prompt_tokens,
+                    expected: prompt_tokens + agent.config.max_tokens as u64 / 2,
+                    high: prompt_tokens + agent.config.max_tokens as u64,
+                },
+                CostRange { low: 0, expected: 0, high: 0 },
+                HEURISTIC_CONFIDENCE,
+            ),
+            _ => {
+                let (tokens, cycles) = ranges_from(&samples, prompt_tokens);
+                let confidence = (0.5 + samples.len() as f32 / 200.0).min(0.95);
+                // Other agent types say less about this one
+                let confidence = if basis == CostBasis::AllAgentsHistory { confidence * 0.75 } else { confidence };
+                (tokens, cycles, confidence)
+            }
+        };
+
+        let tier = Principal::from_text(&agent.user_id)
+            .map(|owner| Guards::limits_for(owner).tier)
+            .unwrap_or(SubscriptionTier::Basic);
+        let model = QuantizedModel::Llama3_1_8B; // Tasks are answered by the default LLM
+        let usd = |tokens: u64, cycles: u64| {
+            let output_tokens = tokens.saturating_sub(prompt_tokens);
+            PricingService::token_cost(&model, tokens - output_tokens, output_tokens, &tier) + PricingService::cycles_cost(cycles)
+        };
+        let budget_warning = Self::budget_warning(&agent, tokens.high, cycles.high);
+        Ok(CostEstimate {
+            agent_id: agent_id.to_string(),
+            prompt_tokens,
+            usd: UsdRange {
+                low: usd(tokens.low, cycles.low),
+                expected: usd(tokens.expected, cycles.expected),
+                high: usd(tokens.high, cycles.high),
+            },
+            tokens,
+            cycles,
+            ledger_price_per_task: PaymentService::get_config().ok().map(|c| c.price_per_task),
+            basis,
+            samples: samples.len() as u64,
+            confidence,
+            budget_warning,
+        })
+    }
+
+    fn budget_warning(agent: &AutonomousAgent, tokens: u64, cycles: u64) -> Option<String> {
+        let status = BudgetService::status(agent, time());
+        if let Some(limit) = status.budget.tokens_per_day.filter(|limit| status.usage.tokens_today + tokens > *limit) {
+            return Some(format!("may exceed the daily token budget of {}", limit));
+        }
+        if let Some(limit) = status.budget.cycles_cap.filter(|limit| status.usage.cycles_spent + cycles > *limit) {
+            return Some(format!("may exceed the cycles cap of {}", limit));
+        }
+        None
+    }
+}
+
+/// Token ranges are the prompt plus the p10, p50 and p90 of what past tasks
+/// used beyond their own prompt; cycle ranges are those percentiles directly
+fn ranges_from(samples: &[CostSample], prompt_tokens: u64) -> (CostRange, CostRange) {
+    let mut overhead: Vec<u64> = samples.iter().map(|s| s.tokens_used.saturating_sub(s.prompt_tokens)).collect();
+    let mut cycles: Vec<u64> = samples.iter().map(|s| s.cycles).collect();
+    overhead.sort_unstable();
+    cycles.sort_unstable();
+    let range = |sorted: &[u64], offset: u64| CostRange {
+        low: offset + percentile(sorted, 0.10),
+        expected: offset + percentile(sorted, 0.50),
+        high: offset + percentile(sorted, 0.90),
+    };
+    (range(&overhead, prompt_tokens), range(&cycles, 0))
+}
+
+/// Nearest-rank percentile of sorted values; 0 when empty
+fn percentile(sorted: &[u64], percentile: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    sorted[((sorted.len() as f64 * percentile) as usize).min(sorted.len() - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges_add_prompt_to_past_overhead() {
+        let samples: Vec<CostSample> = (1..=10)
+            .map(|i| CostSample {
+                prompt_tokens: 50,
+                tokens_used: 50 + i * 100,
+                cycles: i * 1_000,
+            })
+            .collect();
+        let (tokens, cycles) = ranges_from(&samples, 20);
+        assert_eq!((tokens.low, tokens.expected, tokens.high), (220, 620, 1_020));
+        assert_eq!((cycles.low, cycles.expected, cycles.high), (2_000, 6_000, 10_000));
+    }
+}
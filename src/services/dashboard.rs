@@ -0,0 +1,106 @@
+use crate::domain::instruction::SubscriptionTier;
+use crate::infra::guards::RateLimitStatus;
+use crate::infra::Guards;
+use crate::services::agent_factory::{AgentFactory, AgentSummary, MAX_AGENTS_PER_USER};
+use crate::services::archive::ArchiveService;
+use crate::services::economics::EconomicsClient;
+use crate::services::events::{AgentEvent, EventService};
+use crate::services::health::{DetailedHealth, HealthService};
+use crate::services::{with_state, CacheService};
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+
+const RECENT_EVENTS: usize = 20;
+
+#[derive(Debug, Clone, CandidType)]
+pub struct BindingProgress {
+    pub model_bound: bool,
+    pub model_id: Option<String>,
+    pub chunks_loaded: u32,
+    pub total_chunks: u32,
+    pub chunks_reused: u32, // Reused from the cache by the last bind instead of fetched
+    pub bytes_saved: u64,
+    pub ctx_window: Option<u32>, // From the bound model's ModelMeta
+    pub cache_utilization: f32,
+    pub cache_entries: u32,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct QuotaStatus {
+    pub tier: SubscriptionTier,
+    pub tier_from_economics: bool, // False when the economics canister could not be asked
+    pub rate_limits: RateLimitStatus,
+    pub agents_used: u32,
+    pub archived_in_grace_period: u32,
+    pub agents_limit: u32,
+}
+
+/// Everything the dashboard renders, read in one round trip
+#[derive(Debug, Clone, CandidType)]
+pub struct Dashboard {
+    pub health: DetailedHealth,
+    pub binding: BindingProgress,
+    pub agents: Vec<AgentSummary>,
+    pub quota: QuotaStatus,
+    pub recent_events: Vec<AgentEvent>,
+    pub generated_at: u64,
+}
+
+pub struct DashboardService;
+
+impl DashboardService {
+    /// Runs inside a composite query so the subscription tier can be read from
+    /// the economics canister without an update call
+    pub async fn snapshot(principal: Principal) -> Result<Dashboard, String> {
+        let user_id = principal.to_string();
+        let agents = AgentFactory::list_user_agents(&user_id).await?;
+        let quota = Self::quota_status(principal, agents.len()).await;
+
+        Ok(Dashboard {
+            health: HealthService::get_detailed_health(),
+            binding: Self::binding_progress(),
+            agents,
+            quota,
+            recent_events: EventService::recent(&user_id, RECENT_EVENTS),
+            generated_at: time(),
+        })
+    }
+
+    fn binding_progress() -> BindingProgress {
+        let cache_utilization = CacheService::get_utilization();
+        with_state(|s| {
+            let binding = s.binding.as_ref();
+            BindingProgress {
+                model_bound: binding.is_some(),
+                model_id: binding.map(|b| b.model_id.clone()),
+                chunks_loaded: binding.map_or(0, |b| b.chunks_loaded),
+                total_chunks: binding.map_or(0, |b| b.total_chunks),
+                chunks_reused: binding.map_or(0, |b| b.chunks_reused),
+                bytes_saved: binding.map_or(0, |b| b.bytes_saved),
+                ctx_window: s.model_meta.as_ref().map(|meta| meta.ctx_window),
+                cache_utilization,
+                cache_entries: s.cache_entries.len() as u32,
+            }
+        })
+    }
+
+    async fn quota_status(principal: Principal, agents_used: usize) -> QuotaStatus {
+        let rate_limits = Guards::limits_for(principal);
+        let economics = with_state(|s| s.config.economics_canister_id.clone());
+        let looked_up = match economics {
+            Some(economics) => EconomicsClient::get_subscription_tier(&economics, principal).await.ok(),
+            None => None,
+        };
+        QuotaStatus {
+            tier_from_economics: looked_up.is_some(),
+            tier: match looked_up {
+                Some(tier) => tier.unwrap_or(SubscriptionTier::Basic),
+                None => rate_limits.tier.clone(),
+            },
+            rate_limits,
+            agents_used: agents_used as u32,
+            archived_in_grace_period: ArchiveService::count_in_grace_period(&principal.to_string(), time()) as u32,
+            agents_limit: MAX_AGENTS_PER_USER as u32,
+        }
+    }
+}
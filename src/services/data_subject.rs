@@ -0,0 +1,268 @@
+use crate::domain::api_version::{paginate, PageRequest};
+use crate::infra::stable::{memory, Cbor, Memory, USER_DATA_DELETIONS_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::agent_factory::AutonomousAgent;
+use crate::services::archive::ArchiveService;
+use crate::services::artifacts::ArtifactService;
+use crate::services::attestation::AttestationService;
+use crate::services::canister_tools::CanisterToolService;
+use crate::services::events::EventService;
+use crate::services::knowledge::KnowledgeService;
+use crate::services::marketplace::MarketplaceService;
+use crate::services::payments::PaymentService;
+use crate::services::privacy::PrivacyService;
+use crate::services::system_callers::SystemCallerService;
+use crate::services::system_ops::SystemOpsService;
+use crate::services::task_history::TaskHistoryService;
+use crate::services::trace::TraceService;
+use crate::services::{llm_service, with_state, with_state_mut, CertificationService, MemoryService};
+use base64::{engine::general_purpose, Engine as _};
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::time::Duration;
+
+thread_local! {
+    static DELETIONS: RefCell<StableBTreeMap<Principal, Cbor<DeletionRequest>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(USER_DATA_DELETIONS_MEMORY_ID)));
+}
+
+/// Stands in for erased personal fields in audit logs
+pub const ERASED: &str = "[erased]";
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+pub const DELETION_GRACE_PERIOD_NS: u64 = 30 * NANOS_PER_DAY;
+const DELETION_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, CandidType, Deserialize)]
+pub enum UserDataKind {
+    Agent,
+    ArchivedAgent,
+    Usage,
+    Conversation,
+    MemoryEntry,
+    Event,
+    ToolAudit,
+    AttestationAudit,
+    PaymentReceipt,
+    PublicListing,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct UserDataRecord {
+    pub kind: UserDataKind,
+    pub id: String,
+    pub json: String,
+}
+
+/// One page of a data subject export, ordered by kind and then id
+#[derive(Debug, Clone, CandidType)]
+pub struct UserDataPage {
+    pub records: Vec<UserDataRecord>,
+    pub next_cursor: Option<String>,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct DeletionRequest {
+    pub subject: Principal,
+    pub requested_by: Principal,
+    pub requested_at: u64,
+    pub execute_after: u64, // End of the grace period, during which the request can be cancelled
+}
+
+/// Data subject access and erasure. An export covers the subject's agents
+/// (live and archived) with their usage, conversations, memory entries, public
+/// listings, and the events, tool calls, attested calls and payment receipts
+/// that reference them. Erasure runs once the grace period is over: agents,
+/// conversations, memory, listings and ratings are deleted, and audit entries
+/// are kept in sequence with the subject's fields replaced by tombstones.
+/// Payment receipts and billing records are retained as financial records,
+/// and aggregated metrics (counters, SLA and calibration statistics) hold
+/// nothing per user and are left alone.
+pub struct DataSubjectService;
+
+impl DataSubjectService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(DELETION_SWEEP_INTERVAL, || Self::sweep(time()));
+    }
+
+    pub async fn export(subject: Principal, page: &PageRequest) -> Result<UserDataPage, String> {
+        // Conversations are sealed under the subject's key
+        if PrivacyService::has_key(&subject) {
+            PrivacyService::ensure_key(subject).await?;
+        }
+        let owner = subject.to_text();
+        let mut records = Vec::new();
+
+        let agents: Vec<AutonomousAgent> =
+            with_state(|state| state.agents.values().filter(|a| a.user_id == owner).cloned().collect());
+        let mut agent_ids: Vec<String> = agents.iter().map(|a| a.agent_id.clone()).collect();
+        for agent in &agents {
+            records.push(record(UserDataKind::Agent, &agent.agent_id, agent)?);
+            records.push(record(UserDataKind::Usage, &agent.agent_id, &SystemOpsService::usage(agent))?);
+        }
+        for archived in ArchiveService::owned(&owner) {
+            records.push(record(UserDataKind::ArchivedAgent, &archived.agent_id, &ArchiveService::unpack(&archived)?)?);
+            agent_ids.push(archived.agent_id);
+        }
+
+        let conversations = llm_service().list_conversations(subject);
+        for session in conversations.sessions {
+            records.push(record(UserDataKind::Conversation, &session.session_id, &session)?);
+        }
+        // Listed without content so the subject knows they exist
+        for skipped in conversations.skipped {
+            let entry = serde_json::json!({ "session_id": skipped.session_id, "sealed": true, "reason": skipped.reason });
+            records.push(record(UserDataKind::Conversation, &skipped.session_id, &entry)?);
+        }
+        let mut cursor = None;
+        loop {
+            let listed = MemoryService::list(&subject, "", cursor);
+            for info in listed.items {
+                let Ok(data) = MemoryService::retrieve(&MemoryService::namespaced(&subject, &info.key)?) else {
+                    continue;
+                };
+                let entry = serde_json::json!({
+                    "key": info.key,
+                    "version": info.version,
+                    "created_at": info.created_at,
+                    "expires_at": info.expires_at,
+                    "data_base64": general_purpose::STANDARD.encode(data),
+                });
+                records.push(record(UserDataKind::MemoryEntry, &info.key, &entry)?);
+            }
+            cursor = listed.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        for event in EventService::for_user(&owner) {
+            records.push(record(UserDataKind::Event, &format!("{:020}", event.sequence), &event)?);
+        }
+        for entry in CanisterToolService::audit_for(&owner, &agent_ids) {
+            records.push(record(UserDataKind::ToolAudit, &format!("{:020}", entry.sequence), &entry)?);
+        }
+        for entry in AttestationService::audit_for(&subject) {
+            records.push(record(UserDataKind::AttestationAudit, &format!("{:020}", entry.sequence), &entry)?);
+        }
+        for receipt in PaymentService::list_receipts(&owner) {
+            records.push(record(UserDataKind::PaymentReceipt, &receipt.receipt_id, &receipt)?);
+        }
+        for listing in MarketplaceService::authored(&owner) {
+            records.push(record(UserDataKind::PublicListing, &listing.listing_id, &listing)?);
+        }
+
+        let (records, next_cursor, total) = paginate(records, page, |r| format!("{:02}/{}", r.kind as u8, r.id));
+        Ok(UserDataPage { records, next_cursor, total })
+    }
+
+    /// Schedule erasure of `subject`'s data. Asking again returns the pending request.
+    pub fn request_deletion(subject: Principal, requested_by: Principal) -> Result<DeletionRequest, String> {
+        if subject == Principal::anonymous() {
+            return Err("Invalid subject: the anonymous principal has no data".to_string());
+        }
+        if let Some(pending) = Self::pending(&subject) {
+            return Ok(pending);
+        }
+        let now = time();
+        let request = DeletionRequest {
+            subject,
+            requested_by,
+            requested_at: now,
+            execute_after: now + DELETION_GRACE_PERIOD_NS,
+        };
+        DELETIONS.with(|d| d.borrow_mut().insert(subject, Cbor(request.clone())));
+        Metrics::increment_counter("user_data_deletions_requested_total");
+        Ok(request)
+    }
+
+    pub fn cancel_deletion(subject: &Principal) -> Result<(), String> {
+        DELETIONS
+            .with(|d| d.borrow_mut().remove(subject))
+            .map(|_| ())
+            .ok_or_else(|| format!("No pending deletion for {}", subject))
+    }
+
+    pub fn pending(subject: &Principal) -> Option<DeletionRequest> {
+        DELETIONS.with(|d| d.borrow().get(subject).map(|r| r.0))
+    }
+
+    /// Erase every subject whose grace period is over. A subject with a task
+    /// still running stays pending until the next sweep.
+    fn sweep(now: u64) {
+        let due: Vec<DeletionRequest> = DELETIONS.with(|d| {
+            d.borrow()
+                .iter()
+                .map(|(_, request)| request.0)
+                .filter(|request| request.execute_after <= now)
+                .collect()
+        });
+        for request in due {
+            match Self::erase(&request.subject) {
+                Ok(()) => {
+                    DELETIONS.with(|d| d.borrow_mut().remove(&request.subject));
+                }
+                Err(e) => ic_cdk::println!("Erasure of {} postponed: {}", request.subject, e),
+            }
+        }
+    }
+
+    fn erase(subject: &Principal) -> Result<(), String> {
+        let owner = subject.to_text();
+        let live_ids: Vec<String> = with_state(|state| {
+            state.agents.values().filter(|a| a.user_id == owner).map(|a| a.agent_id.clone()).collect()
+        });
+        let running = with_state(|state| {
+            live_ids.iter().find(|id| state.tasks_in_flight.get(*id).copied().unwrap_or(0) > 0).cloned()
+        });
+        if let Some(agent_id) = running {
+            return Err(format!("agent {} is running a task", agent_id));
+        }
+        let mut agent_ids = live_ids.clone();
+        agent_ids.extend(ArchiveService::owned(&owner).into_iter().map(|archived| archived.agent_id));
+
+        let mut erased = CanisterToolService::erase_user(&owner, &agent_ids, ERASED)
+            + EventService::erase_user(&owner, ERASED)
+            + AttestationService::erase_user(subject)
+            + MarketplaceService::erase_user(subject);
+        with_state_mut(|state| {
+            for agent_id in &live_ids {
+                state.agents.remove(agent_id);
+            }
+            // Coordinators of other users lose the erased members
+            for agent in state.agents.values_mut() {
+                agent.member_ids.retain(|id| !live_ids.contains(id));
+            }
+        });
+        CertificationService::mark_dirty();
+        for agent_id in &agent_ids {
+            TaskHistoryService::forget_agent(agent_id);
+            for document in KnowledgeService::list_documents(agent_id, None) {
+                let _ = KnowledgeService::delete(agent_id, &document.doc_id);
+            }
+            TraceService::forget_agent(agent_id);
+            ArtifactService::forget_agent(agent_id);
+            ArchiveService::remove(agent_id);
+        }
+        let purged = PrivacyService::purge(*subject);
+        erased += agent_ids.len() as u32 + purged.conversations_deleted + purged.memory_entries_deleted;
+
+        // The tombstone names the subject only by hash
+        SystemCallerService::record_erasure(ic_cdk::api::id(), hex::encode(Sha256::digest(subject.as_slice())), erased);
+        Metrics::increment_counter("user_data_erasures_total");
+        Ok(())
+    }
+}
+
+fn record(kind: UserDataKind, id: &str, value: &impl Serialize) -> Result<UserDataRecord, String> {
+    let json = serde_json::to_string(value).map_err(|e| format!("Failed to encode {:?} {}: {}", kind, id, e))?;
+    Ok(UserDataRecord {
+        kind,
+        id: id.to_string(),
+        json,
+    })
+}
@@ -0,0 +1,159 @@
+use crate::domain::instruction::{Capability, CapabilityPriority};
+use crate::infra::{Guards, Metrics};
+use crate::services::agent_factory::AutonomousAgent;
+use crate::services::budget::BudgetService;
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+// Share of the owner's token quota left below which tasks degrade
+const REDUCED_BELOW: f32 = 0.5;
+const MINIMAL_BELOW: f32 = 0.2;
+// A degraded answer still gets room for a few sentences
+const MIN_MAX_TOKENS: u32 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum DegradationLevel {
+    Reduced, // Optional capabilities dropped, Helpful ones shrunk
+    Minimal, // Only Essential and Important capabilities, both shrunk
+}
+
+/// What a task gave up to stay within the owner's remaining quota
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Degradation {
+    pub level: DegradationLevel,
+    pub remaining_quota: f32, // Share of the tightest token quota left when the task started
+    pub dropped_capabilities: Vec<String>,
+    pub max_tokens: u32,
+}
+
+/// Trims a task to the agent's most important capabilities when its owner
+/// is close to a token quota, instead of letting it fail once the quota
+/// runs out. The quotas are the owner's per-minute token bucket and the
+/// agent's daily token budget; the one with the smaller share left decides.
+pub struct DegradationService;
+
+impl DegradationService {
+    /// None while enough quota is left to run the task in full
+    pub fn plan(agent: &AutonomousAgent, max_tokens: u32, now: u64) -> Option<Degradation> {
+        let degradation = Self::preview(agent, max_tokens, now)?;
+        Metrics::increment_counter(&format!("tasks_degraded_total:{:?}", degradation.level));
+        Some(degradation)
+    }
+
+    /// Like plan, for a task that will not run
+    pub fn preview(agent: &AutonomousAgent, max_tokens: u32, now: u64) -> Option<Degradation> {
+        let remaining_quota = Self::remaining_quota(agent, now);
+        let level = Self::level(remaining_quota)?;
+        Some(Self::degrade(&agent.analysis.extracted_capabilities, level, remaining_quota, max_tokens))
+    }
+
+    /// A tool stays available while any capability that needs it survived
+    pub fn allows_tool(agent: &AutonomousAgent, degradation: Option<&Degradation>, tool: &str) -> bool {
+        let Some(degradation) = degradation else {
+            return true;
+        };
+        let needing: Vec<&Capability> = agent
+            .analysis
+            .extracted_capabilities
+            .iter()
+            .filter(|c| c.required_tools.iter().any(|t| t == tool))
+            .collect();
+        needing.is_empty() || needing.iter().any(|c| !degradation.dropped_capabilities.contains(&c.name))
+    }
+
+    fn remaining_quota(agent: &AutonomousAgent, now: u64) -> f32 {
+        let bucket = Principal::from_text(&agent.user_id).ok().map(|owner| {
+            let status = Guards::limits_for(owner);
+            status.tokens_remaining as f32 / status.limits.tokens_per_minute.max(1) as f32
+        });
+        let daily = agent.budget.tokens_per_day.map(|limit| {
+            let used = BudgetService::status(agent, now).usage.tokens_today;
+            limit.saturating_sub(used) as f32 / limit.max(1) as f32
+        });
+        bucket.into_iter().chain(daily).fold(1.0, f32::min)
+    }
+
+    fn level(remaining_quota: f32) -> Option<DegradationLevel> {
+        if remaining_quota < MINIMAL_BELOW {
+            Some(DegradationLevel::Minimal)
+        } else if remaining_quota < REDUCED_BELOW {
+            Some(DegradationLevel::Reduced)
+        } else {
+            None
+        }
+    }
+
+    /// Share of a capability's tokens kept at the level; 0 drops it
+    fn token_share(priority: &CapabilityPriority, level: DegradationLevel) -> f32 {
+        match (priority, level) {
+            (CapabilityPriority::Essential, DegradationLevel::Reduced) => 1.0,
+            (CapabilityPriority::Essential, DegradationLevel::Minimal) => 0.75,
+            (CapabilityPriority::Important, DegradationLevel::Reduced) => 0.75,
+            (CapabilityPriority::Important, DegradationLevel::Minimal) => 0.5,
+            (CapabilityPriority::Helpful, DegradationLevel::Reduced) => 0.5,
+            (CapabilityPriority::Helpful, DegradationLevel::Minimal) => 0.0,
+            (CapabilityPriority::Optional, _) => 0.0,
+        }
+    }
+
+    /// max_tokens shrinks by the share of estimated tokens the surviving
+    /// capabilities keep
+    fn degrade(capabilities: &[Capability], level: DegradationLevel, remaining_quota: f32, max_tokens: u32) -> Degradation {
+        let total: u32 = capabilities.iter().map(|c| c.estimated_tokens).sum();
+        let kept: f32 = capabilities
+            .iter()
+            .map(|c| c.estimated_tokens as f32 * Self::token_share(&c.priority, level))
+            .sum();
+        let share = if total == 0 {
+            Self::token_share(&CapabilityPriority::Essential, level)
+        } else {
+            kept / total as f32
+        };
+        Degradation {
+            level,
+            remaining_quota,
+            dropped_capabilities: capabilities
+                .iter()
+                .filter(|c| Self::token_share(&c.priority, level) == 0.0)
+                .map(|c| c.name.clone())
+                .collect(),
+            max_tokens: ((max_tokens as f32 * share).round() as u32).max(MIN_MAX_TOKENS.min(max_tokens)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::instruction::CapabilityCategory;
+
+    fn capability(name: &str, priority: CapabilityPriority, estimated_tokens: u32) -> Capability {
+        Capability {
+            name: name.to_string(),
+            description: String::new(),
+            category: CapabilityCategory::TextGeneration,
+            priority,
+            required_tools: Vec::new(),
+            estimated_tokens,
+        }
+    }
+
+    #[test]
+    fn test_lower_quota_drops_more_capabilities() {
+        let capabilities = vec![
+            capability("core", CapabilityPriority::Essential, 500),
+            capability("examples", CapabilityPriority::Helpful, 300),
+            capability("polish", CapabilityPriority::Optional, 200),
+        ];
+        assert_eq!(DegradationService::level(0.6), None);
+
+        let reduced = DegradationService::degrade(&capabilities, DegradationLevel::Reduced, 0.4, 1000);
+        assert_eq!(reduced.dropped_capabilities, vec!["polish".to_string()]);
+        assert_eq!(reduced.max_tokens, 650);
+
+        let minimal = DegradationService::degrade(&capabilities, DegradationLevel::Minimal, 0.1, 1000);
+        assert_eq!(minimal.dropped_capabilities, vec!["examples".to_string(), "polish".to_string()]);
+        assert_eq!(minimal.max_tokens, 375);
+        assert_eq!(DegradationService::degrade(&capabilities[2..], DegradationLevel::Minimal, 0.1, 1000).max_tokens, 64);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::domain::api_version::ApiError;
+use crate::services::agent_factory::{AgentFactory, AutonomousAgent};
+use crate::services::with_state_mut;
+use crate::infra::Metrics;
+use candid::CandidType;
+use crate::infra::clock::time;
+use serde::{Deserialize, Serialize};
+
+const MAX_DELEGATIONS_PER_AGENT: usize = 20;
+
+/// What a delegated principal may do with an agent. Each scope includes the ones below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, CandidType)]
+pub enum AccessScope {
+    Read,    // Status, task results and history
+    Execute, // Run tasks and workflows
+    Manage,  // Reset health and other operational changes
+}
+
+/// Access to an agent granted by its owner to another principal
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Delegation {
+    pub principal: String,
+    pub scope: AccessScope,
+    pub granted_at: u64,
+    pub expires_at: Option<u64>,
+}
+
+impl Delegation {
+    fn is_active(&self, now: u64) -> bool {
+        self.expires_at.map_or(true, |exp| exp > now)
+    }
+}
+
+/// Owner-managed delegations that let other principals act on an agent
+pub struct DelegationService;
+
+impl DelegationService {
+    /// Grant or replace `principal`'s access to the agent
+    pub fn grant(
+        agent_id: &str,
+        owner: &str,
+        principal: &str,
+        scope: AccessScope,
+        expires_at: Option<u64>,
+    ) -> Result<(), String> {
+        let mut agent = AgentFactory::authorize(agent_id, owner)?;
+        if principal == owner {
+            return Err("The owner already has full access".to_string());
+        }
+
+        let now = time();
+        if expires_at.map_or(false, |exp| exp <= now) {
+            return Err("Delegation expiry must be in the future".to_string());
+        }
+
+        agent.delegations.retain(|d| d.principal != principal && d.is_active(now));
+        if agent.delegations.len() >= MAX_DELEGATIONS_PER_AGENT {
+            return Err(format!("Delegation limit reached. Maximum: {}", MAX_DELEGATIONS_PER_AGENT));
+        }
+        agent.delegations.push(Delegation {
+            principal: principal.to_string(),
+            scope,
+            granted_at: now,
+            expires_at,
+        });
+
+        Self::save(agent);
+        Metrics::increment_counter("delegations_granted_total");
+        Ok(())
+    }
+
+    pub fn revoke(agent_id: &str, owner: &str, principal: &str) -> Result<(), String> {
+        let mut agent = AgentFactory::authorize(agent_id, owner)?;
+        let before = agent.delegations.len();
+        agent.delegations.retain(|d| d.principal != principal);
+        if agent.delegations.len() == before {
+            return Err(format!("No delegation for {} on agent {}", principal, agent_id));
+        }
+
+        Self::save(agent);
+        Ok(())
+    }
+
+    pub fn list(agent_id: &str, owner: &str) -> Result<Vec<Delegation>, String> {
+        let now = time();
+        let agent = AgentFactory::authorize(agent_id, owner)?;
+        Ok(agent.delegations.into_iter().filter(|d| d.is_active(now)).collect())
+    }
+
+    /// Look up an agent for `caller`, who must be its owner or hold an active
+    /// delegation covering `scope`
+    pub fn authorize(agent_id: &str, caller: &str, scope: AccessScope) -> Result<AutonomousAgent, ApiError> {
+        let agent = AgentFactory::find_agent(agent_id).map_err(|message| ApiError::NotFound { message })?;
+        if agent.user_id == caller {
+            return Ok(agent);
+        }
+
+        let now = time();
+        let allowed = agent
+            .delegations
+            .iter()
+            .any(|d| d.principal == caller && d.is_active(now) && d.scope >= scope);
+        if !allowed {
+            return Err(ApiError::Forbidden { message: format!("Not authorized to access agent {}", agent_id) });
+        }
+        Ok(agent)
+    }
+
+    fn save(agent: AutonomousAgent) {
+        with_state_mut(|state| {
+            state.agents.insert(agent.agent_id.clone(), agent);
+        });
+    }
+}
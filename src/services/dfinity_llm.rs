@@ -1,9 +1,19 @@
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::api::time;
+use crate::infra::clock::time;
 use ic_llm::{Model, ChatMessage as LlmChatMessage};
 use serde::Serialize;
+use base64::{Engine as _, engine::general_purpose};
+use crate::infra::{Guards, Lane};
+use crate::services::batching::BatchingService;
+use crate::services::outage::OutageService;
+use crate::services::privacy::PrivacyService;
+use crate::services::pricing::PricingService;
+use crate::services::context_window::ContextWindow;
+use crate::services::knowledge::KnowledgeService;
+use crate::services::provenance::{Provenance, ProvenanceSource};
+use crate::services::redaction::RedactionService;
 use std::collections::HashMap;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 // DFINITY LLM Model Types - mapped to actual ic-llm models
@@ -32,6 +42,20 @@ impl QuantizedModel {
         }
     }
 
+    /// Versioned model identifier recorded in provenance
+    pub fn model_id(&self) -> &str {
+        match self {
+            QuantizedModel::Llama3_1_8B => "llama3.1:8b",
+        }
+    }
+
+    pub fn from_model_id(model_id: &str) -> Option<Self> {
+        match model_id {
+            "llama3.1:8b" => Some(QuantizedModel::Llama3_1_8B),
+            _ => None,
+        }
+    }
+
     pub fn description(&self) -> &str {
         match self {
             QuantizedModel::Llama3_1_8B => "Fast and efficient general-purpose AI for content generation and code assistance",
@@ -57,6 +81,8 @@ pub struct ChatMessage {
     pub content: String,
     pub timestamp: u64,
     pub model: QuantizedModel,
+    #[serde(default)]
+    pub provenance: Option<Provenance>, // Assistant replies only
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -80,7 +106,8 @@ impl MessageRole {
     }
 }
 
-// Conversation session management
+// Conversation session management. Stored message content is sealed under
+// the owner's key; sessions handed out by DfinityLlmService are decrypted.
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct ConversationSession {
     pub session_id: String,
@@ -90,8 +117,35 @@ pub struct ConversationSession {
     pub created_at: u64,
     pub last_activity: u64,
     pub token_usage: TokenUsage,
+    #[serde(default)]
+    pub forked_from: Option<String>,
+    #[serde(default)]
+    pub forked_at_index: Option<u32>,
+    #[serde(default)]
+    pub settings: ConversationSettings,
+    #[serde(default)]
+    pub agent_id: Option<String>, // Set on an agent's dedicated thread with its owner
+}
+
+// Per-session prompt and output settings. The system prompt is sent with every
+// exchange; max_tokens and stop sequences are applied to the reply here.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ConversationSettings {
+    pub system_prompt: Option<String>,
+    // Must be None: ic-llm 1.1 takes no sampling parameters, so nothing could honour it
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop_sequences: Vec<String>,
 }
 
+const MAX_SYSTEM_PROMPT_CHARS: usize = 4_000;
+const MAX_STOP_SEQUENCES: usize = 4;
+const MAX_STOP_SEQUENCE_CHARS: usize = 32;
+const MAX_RESPONSE_TOKENS: u32 = 4_096;
+// Earlier turns sent along with each new message
+const MAX_HISTORY_MESSAGES: usize = 20;
+const MAX_AGENT_THREAD_MESSAGES: usize = 200;
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct TokenUsage {
     pub input_tokens: u64,
@@ -112,6 +166,75 @@ pub struct UserQuota {
     pub is_premium: bool,
 }
 
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+impl UserQuota {
+    fn free(user_principal: Principal, now: u64) -> Self {
+        Self {
+            user_principal,
+            daily_token_limit: 10000,      // Free tier: 10K tokens/day
+            monthly_token_limit: 300000,   // Free tier: 300K tokens/month
+            current_daily_usage: 0,
+            current_monthly_usage: 0,
+            last_reset: now,
+            is_premium: false,
+        }
+    }
+
+    /// Daily usage resets at UTC midnight, monthly usage on the first of the month
+    fn roll_over(&mut self, now: u64) {
+        if now / NANOS_PER_DAY != self.last_reset / NANOS_PER_DAY {
+            self.current_daily_usage = 0;
+        }
+        if month_start(now) != month_start(self.last_reset) {
+            self.current_monthly_usage = 0;
+        }
+        self.last_reset = now;
+    }
+
+    pub fn next_daily_reset(&self) -> u64 {
+        (self.last_reset / NANOS_PER_DAY + 1) * NANOS_PER_DAY
+    }
+
+    pub fn next_monthly_reset(&self) -> u64 {
+        next_month_start(self.last_reset)
+    }
+}
+
+/// Start of the UTC calendar month containing `nanos`
+fn month_start(nanos: u64) -> u64 {
+    let days = nanos / NANOS_PER_DAY;
+    let (_, _, day) = civil_from_days(days);
+    (days - (day - 1)) * NANOS_PER_DAY
+}
+
+/// Start of the UTC calendar month after the one containing `nanos`
+fn next_month_start(nanos: u64) -> u64 {
+    let days = nanos / NANOS_PER_DAY;
+    let (year, month, day) = civil_from_days(days);
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    (days - (day - 1) + days_in_month) * NANOS_PER_DAY
+}
+
+/// Year, month and day of days since 1970-01-01 (Howard Hinnant's algorithm)
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
 // Error types for LLM operations
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub enum LlmError {
@@ -123,14 +246,120 @@ pub enum LlmError {
     ServiceUnavailable { retry_after: u64 },
     ContentFiltered,
     InternalError { message: String },
+    ContextTooLong { prompt_tokens: u64, max_tokens: u32, ctx_window: u32 },
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Markdown,
+}
+
+#[derive(CandidType, Clone, Debug)]
+pub struct ConversationExport {
+    pub session_id: String,
+    pub user_principal: Principal,
+    pub content: String,
+}
+
+/// A session left out of a listing or export because its messages could not
+/// be opened, usually because the owner's key is not loaded
+#[derive(CandidType, Clone, Debug)]
+pub struct SkippedSession {
+    pub session_id: String,
+    pub user_principal: Principal,
+    pub reason: String,
+}
+
+#[derive(CandidType, Clone, Debug)]
+pub struct ConversationListing {
+    pub sessions: Vec<ConversationSession>,
+    pub skipped: Vec<SkippedSession>,
 }
 
+/// `next_cursor` is passed back as `after` and is `None` on the last page;
+/// a page may be short, or empty, when sessions in it were skipped
+#[derive(CandidType, Clone, Debug)]
+pub struct ConversationExportPage {
+    pub items: Vec<ConversationExport>,
+    pub skipped: Vec<SkippedSession>,
+    pub next_cursor: Option<String>,
+}
+
+const MAX_BULK_EXPORT: usize = 50;
+
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+pub struct SearchFilters {
+    pub session_ids: Option<Vec<String>>,
+    pub roles: Option<Vec<MessageRole>>,
+    pub model: Option<QuantizedModel>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(CandidType, Clone, Debug)]
+pub struct SearchHit {
+    pub session_id: String,
+    pub message_index: u32,
+    pub role: MessageRole,
+    pub timestamp: u64,
+    pub score: f32,
+    pub snippet: String,
+}
+
+#[derive(CandidType, Clone, Debug)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    pub total: u64,
+    pub next_offset: Option<u32>,
+}
+
+#[derive(CandidType, Clone, Debug)]
+pub enum StreamStatus {
+    Pending,
+    Complete,
+    Failed { error: LlmError },
+}
+
+// In-flight or finished reply started by `start_message`
+#[derive(Clone, Debug)]
+struct MessageStream {
+    session_id: String,
+    user_principal: Principal,
+    chunks: Vec<String>,
+    status: StreamStatus,
+    message: Option<ChatMessage>,
+    started_at: u64,
+}
+
+#[derive(CandidType, Clone, Debug)]
+pub struct MessageStreamPoll {
+    pub session_id: String,
+    pub chunks: Vec<String>,
+    pub next_cursor: u32,
+    pub status: StreamStatus,
+    pub message: Option<ChatMessage>, // Final assistant message once complete
+}
+
+const MAX_ACTIVE_STREAMS_PER_USER: usize = 4;
+// A reply still pending after this long was lost to a trapped LLM call
+const STREAM_TIMEOUT_NS: u64 = 5 * 60 * 1_000_000_000;
+// Finished streams stay pollable this long
+const STREAM_RETENTION_NS: u64 = 10 * 60 * 1_000_000_000;
+
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+const MAX_SEARCH_LIMIT: usize = 100;
+const SNIPPET_CONTEXT_CHARS: usize = 80;
+
 // Main DFINITY LLM Service
-#[derive(Debug)]
+// Cloning is cheap and shares state, so callers can hold a handle across awaits
+#[derive(Debug, Clone)]
 pub struct DfinityLlmService {
     conversations: Rc<RefCell<HashMap<String, ConversationSession>>>,
     user_quotas: Rc<RefCell<HashMap<Principal, UserQuota>>>,
     active_models: Vec<QuantizedModel>,
+    session_seq: Rc<Cell<u64>>,
+    streams: Rc<RefCell<HashMap<String, MessageStream>>>,
     // DFINITY LLM canister configuration
     #[allow(dead_code)]
     llm_canister_principal: Principal,
@@ -151,40 +380,44 @@ impl DfinityLlmService {
                 // Additional models will be added based on user feedback and demand
                 // The architecture is designed to easily add new models when they become available.
             ],
+            session_seq: Rc::new(Cell::new(0)),
+            streams: Rc::new(RefCell::new(HashMap::new())),
             llm_canister_principal,
         }
     }
 
     // Initialize user quota if not exists
     pub fn initialize_user_quota(&self, user_principal: Principal) -> Result<(), LlmError> {
-        let mut quotas = self.user_quotas.borrow_mut();
-
-        if !quotas.contains_key(&user_principal) {
-            let quota = UserQuota {
-                user_principal,
-                daily_token_limit: 10000,      // Free tier: 10K tokens/day
-                monthly_token_limit: 300000,   // Free tier: 300K tokens/month
-                current_daily_usage: 0,
-                current_monthly_usage: 0,
-                last_reset: time(),
-                is_premium: false,
-            };
-            quotas.insert(user_principal, quota);
-        }
-
+        self.user_quotas
+            .borrow_mut()
+            .entry(user_principal)
+            .or_insert_with(|| UserQuota::free(user_principal, time()));
         Ok(())
     }
 
+    /// The user's allowance with usage as of now; users who haven't chatted yet get the free tier
+    pub fn user_quota(&self, user_principal: Principal) -> UserQuota {
+        let now = time();
+        let mut quota = self.user_quotas
+            .borrow()
+            .get(&user_principal)
+            .cloned()
+            .unwrap_or_else(|| UserQuota::free(user_principal, now));
+        quota.roll_over(now);
+        quota
+    }
+
     // Check if user is within rate limits
     pub fn check_rate_limit(&self, user_principal: Principal, estimated_tokens: u64) -> Result<(), LlmError> {
-        let quotas = self.user_quotas.borrow();
-        let quota = quotas.get(&user_principal)
+        let mut quotas = self.user_quotas.borrow_mut();
+        let quota = quotas.get_mut(&user_principal)
             .ok_or(LlmError::AuthenticationFailed)?;
+        quota.roll_over(time());
 
         // Check daily limit
         if quota.current_daily_usage + estimated_tokens > quota.daily_token_limit {
             return Err(LlmError::RateLimitExceeded {
-                reset_time: quota.last_reset + 24 * 60 * 60 * 1_000_000_000, // 24 hours in nanoseconds
+                reset_time: quota.next_daily_reset(),
             });
         }
 
@@ -197,10 +430,18 @@ impl DfinityLlmService {
     }
 
     // Create new conversation session
-    pub fn create_conversation(&self, user_principal: Principal, model: QuantizedModel) -> Result<String, LlmError> {
+    pub fn create_conversation(
+        &self,
+        user_principal: Principal,
+        model: QuantizedModel,
+        settings: Option<ConversationSettings>,
+    ) -> Result<String, LlmError> {
+        let settings = settings.unwrap_or_default();
+        Self::validate_settings(&settings)?;
+        Self::check_memory()?;
         self.initialize_user_quota(user_principal)?;
 
-        let session_id = format!("conv_{}_{}", user_principal.to_string(), time());
+        let session_id = self.next_session_id(user_principal);
         let session = ConversationSession {
             session_id: session_id.clone(),
             user_principal,
@@ -214,6 +455,10 @@ impl DfinityLlmService {
                 total_tokens: 0,
                 estimated_cost: 0.0,
             },
+            forked_from: None,
+            forked_at_index: None,
+            settings,
+            agent_id: None,
         };
 
         let mut conversations = self.conversations.borrow_mut();
@@ -229,50 +474,283 @@ impl DfinityLlmService {
         user_message: String,
         user_principal: Principal,
     ) -> Result<ChatMessage, LlmError> {
-        // Validate session exists and belongs to user
-        let mut conversations = self.conversations.borrow_mut();
-        let session = conversations.get_mut(session_id)
+        let stored_message = RedactionService::redact(&user_principal, &user_message).await;
+        let estimated_tokens = self.append_user_message(session_id, stored_message, user_principal)?;
+        self.complete_exchange(session_id, user_principal, estimated_tokens, Some(user_message)).await
+    }
+
+    /// Answer a full message list without storing a conversation, as the
+    /// OpenAI-compatible gateway does. Quota is charged like a session message.
+    pub async fn complete_stateless(
+        &self,
+        user_principal: Principal,
+        model: QuantizedModel,
+        messages: Vec<(MessageRole, String)>,
+        settings: ConversationSettings,
+    ) -> Result<(String, TokenUsage), LlmError> {
+        Self::validate_settings(&settings)?;
+        if !messages.iter().any(|(role, _)| matches!(role, MessageRole::User)) {
+            return Err(LlmError::InvalidRequest {
+                message: "At least one user message is required".to_string(),
+            });
+        }
+        self.initialize_user_quota(user_principal)?;
+        let input_tokens = messages.iter().map(|(_, content)| (content.len() / 4) as u64).sum();
+        self.check_rate_limit(user_principal, input_tokens)?;
+
+        let history_start = messages.len().saturating_sub(MAX_HISTORY_MESSAGES);
+        let turns = settings
+            .system_prompt
+            .iter()
+            .map(|prompt| (MessageRole::System, prompt.clone()))
+            .chain(messages.into_iter().skip(history_start))
+            .collect();
+        let turns = ContextWindow::fit_turns(turns, settings.max_tokens)?;
+        let response = self.call_llm_canister_async(&model, Self::to_llm_messages(turns), user_principal).await?;
+        let content = Self::apply_output_settings(response, &settings);
+
+        let output_tokens = (content.len() / 4) as u64;
+        if let Some(quota) = self.user_quotas.borrow_mut().get_mut(&user_principal) {
+            quota.current_daily_usage += input_tokens + output_tokens;
+            quota.current_monthly_usage += input_tokens + output_tokens;
+        }
+        let usage = TokenUsage {
+            input_tokens,
+            output_tokens,
+            total_tokens: input_tokens + output_tokens,
+            estimated_cost: Self::calculate_cost(input_tokens, output_tokens, &model, user_principal),
+        };
+        Ok((content, usage))
+    }
+
+    // Start answering a message in the background and return a handle for `poll_message`
+    pub async fn start_message(
+        &self,
+        session_id: &str,
+        user_message: String,
+        user_principal: Principal,
+    ) -> Result<String, LlmError> {
+        // Redaction may call the LLM, so it runs before the stream limit is checked
+        let stored_message = RedactionService::redact(&user_principal, &user_message).await;
+        let now = time();
+        {
+            let mut streams = self.streams.borrow_mut();
+            streams.retain(|_, stream| {
+                matches!(stream.status, StreamStatus::Pending) || now.saturating_sub(stream.started_at) < STREAM_RETENTION_NS
+            });
+            let active = streams
+                .values()
+                .filter(|s| s.user_principal == user_principal && matches!(s.status, StreamStatus::Pending))
+                .filter(|s| now.saturating_sub(s.started_at) < STREAM_TIMEOUT_NS)
+                .count();
+            if active >= MAX_ACTIVE_STREAMS_PER_USER {
+                return Err(LlmError::RateLimitExceeded { reset_time: now + STREAM_TIMEOUT_NS });
+            }
+        }
+
+        let estimated_tokens = self.append_user_message(session_id, stored_message, user_principal)?;
+
+        let seq = self.session_seq.get() + 1;
+        self.session_seq.set(seq);
+        let handle = format!("stream_{}_{}", now, seq);
+        self.streams.borrow_mut().insert(
+            handle.clone(),
+            MessageStream {
+                session_id: session_id.to_string(),
+                user_principal,
+                chunks: Vec::new(),
+                status: StreamStatus::Pending,
+                message: None,
+                started_at: now,
+            },
+        );
+
+        let service = self.clone();
+        let session_id = session_id.to_string();
+        let stream_handle = handle.clone();
+        ic_cdk::spawn(async move {
+            let result = service.complete_exchange(&session_id, user_principal, estimated_tokens, Some(user_message)).await;
+            service.finish_stream(&stream_handle, result);
+        });
+
+        Ok(handle)
+    }
+
+    // Content appended to the stream after `cursor`, plus the final message once done
+    pub fn poll_message(
+        &self,
+        handle: &str,
+        cursor: Option<u32>,
+        user_principal: Principal,
+    ) -> Result<MessageStreamPoll, LlmError> {
+        let streams = self.streams.borrow();
+        let stream = streams.get(handle)
             .ok_or(LlmError::InvalidRequest {
-                message: "Conversation session not found".to_string(),
+                message: "Message stream not found".to_string(),
             })?;
 
-        if session.user_principal != user_principal {
+        if stream.user_principal != user_principal {
             return Err(LlmError::AuthenticationFailed);
         }
 
+        let status = match &stream.status {
+            StreamStatus::Pending if time().saturating_sub(stream.started_at) >= STREAM_TIMEOUT_NS => {
+                StreamStatus::Failed { error: LlmError::ServiceUnavailable { retry_after: 30 } }
+            }
+            status => status.clone(),
+        };
+        let start = (cursor.unwrap_or(0) as usize).min(stream.chunks.len());
+
+        Ok(MessageStreamPoll {
+            session_id: stream.session_id.clone(),
+            chunks: stream.chunks[start..].to_vec(),
+            next_cursor: stream.chunks.len() as u32,
+            status,
+            message: stream.message.clone(),
+        })
+    }
+
+    // ic-llm returns the whole reply at once, so it arrives as a single chunk
+    fn finish_stream(&self, handle: &str, result: Result<ChatMessage, LlmError>) {
+        let mut streams = self.streams.borrow_mut();
+        let Some(stream) = streams.get_mut(handle) else {
+            return;
+        };
+        match result {
+            Ok(message) => {
+                stream.chunks.push(message.content.clone());
+                stream.message = Some(message);
+                stream.status = StreamStatus::Complete;
+            }
+            Err(error) => stream.status = StreamStatus::Failed { error },
+        }
+    }
+
+    /// Conversations live on the heap, so new sessions and messages are refused
+    /// while the canister is at its memory hard limit
+    fn check_memory() -> Result<(), LlmError> {
+        Guards::check_memory_limits().map_err(|_| LlmError::ServiceUnavailable { retry_after: 60 })
+    }
+
+    // Validate the session, check quota and append the user's message.
+    // Returns the estimated prompt tokens.
+    fn append_user_message(
+        &self,
+        session_id: &str,
+        user_message: String,
+        user_principal: Principal,
+    ) -> Result<u64, LlmError> {
         // Check rate limits
         let estimated_tokens = (user_message.len() / 4) as u64; // Rough token estimation
         self.check_rate_limit(user_principal, estimated_tokens)?;
+        Self::check_memory()?;
+
+        let content = Self::seal(&user_principal, &user_message)?;
+
+        let mut conversations = self.conversations.borrow_mut();
+        let session = conversations.get_mut(session_id)
+            .ok_or(LlmError::InvalidRequest {
+                message: "Conversation session not found".to_string(),
+            })?;
+
+        if session.user_principal != user_principal {
+            return Err(LlmError::AuthenticationFailed);
+        }
 
-        // Add user message to conversation
         let user_chat_message = ChatMessage {
             role: MessageRole::User,
-            content: user_message.clone(),
+            content,
             timestamp: time(),
             model: session.model.clone(),
+            provenance: None,
         };
         session.messages.push(user_chat_message);
         session.last_activity = time();
 
+        Ok(estimated_tokens)
+    }
+
+    // Ask the LLM to answer the session's last message and append the reply.
+    // `live_message` is that message before redaction, which the LLM answers.
+    async fn complete_exchange(
+        &self,
+        session_id: &str,
+        user_principal: Principal,
+        estimated_tokens: u64,
+        live_message: Option<String>,
+    ) -> Result<ChatMessage, LlmError> {
+        // Snapshot the request first. The borrow must end before the LLM call:
+        // other messages run on this canister while it is awaited.
+        let (model, settings, mut turns, knowledge_query) = {
+            let conversations = self.conversations.borrow();
+            let session = conversations.get(session_id)
+                .ok_or(LlmError::InvalidRequest {
+                    message: "Conversation session not found".to_string(),
+                })?;
+            let mut session = Self::open_session(session)?;
+            if let (Some(live_message), Some(last)) = (live_message, session.messages.last_mut()) {
+                last.content = live_message;
+            }
+            (
+                session.model.clone(),
+                session.settings.clone(),
+                Self::build_turns(&session),
+                Self::agent_thread_query(&session),
+            )
+        };
+
+        let passages = match knowledge_query {
+            Some((agent_id, query)) => KnowledgeService::retrieve(&agent_id, None, &query, None).await,
+            None => Vec::new(),
+        };
+        if !passages.is_empty() {
+            // Sources go right before the turn they answer
+            let at = turns.len().saturating_sub(1);
+            turns.insert(at, (MessageRole::System, KnowledgeService::augment_prompt("", &passages)));
+        }
+        let turns = ContextWindow::fit_turns(turns, settings.max_tokens)?;
+
         // Call DFINITY LLM canister (abstracted implementation)
-        let response = self.call_llm_canister_async(&session.model, &user_message).await?;
+        let response = self.call_llm_canister_async(&model, Self::to_llm_messages(turns), user_principal).await?;
 
         // Create assistant response message
+        let mut content = Self::apply_output_settings(response, &settings);
+        if !passages.is_empty() {
+            content.push_str("\n\n");
+            content.push_str(&KnowledgeService::citations(&passages));
+        }
+        let stored_content = RedactionService::redact(&user_principal, &content).await;
+        let content = if RedactionService::live_unredacted(&user_principal) { content } else { stored_content.clone() };
+        let provenance = Provenance::new(model.model_id(), ProvenanceSource::passages(&passages), &content);
         let assistant_message = ChatMessage {
             role: MessageRole::Assistant,
-            content: response,
+            content,
             timestamp: time(),
-            model: session.model.clone(),
+            model,
+            provenance: Some(provenance),
+        };
+        let stored_message = ChatMessage {
+            content: Self::seal(&user_principal, &stored_content)?,
+            ..assistant_message.clone()
         };
 
+        // The session may have been deleted while the call was in flight
+        let mut conversations = self.conversations.borrow_mut();
+        let session = conversations.get_mut(session_id)
+            .ok_or(LlmError::InvalidRequest {
+                message: "Conversation session was deleted".to_string(),
+            })?;
+
         // Update token usage and conversation
         let response_tokens = (assistant_message.content.len() / 4) as u64;
         session.token_usage.input_tokens += estimated_tokens;
         session.token_usage.output_tokens += response_tokens;
         session.token_usage.total_tokens += estimated_tokens + response_tokens;
-        session.token_usage.estimated_cost = self.calculate_cost(
-            session.token_usage.total_tokens,
-            &session.model
+        session.token_usage.estimated_cost = Self::calculate_cost(
+            session.token_usage.input_tokens,
+            session.token_usage.output_tokens,
+            &session.model,
+            user_principal,
         );
 
         // Update user quota
@@ -282,42 +760,124 @@ impl DfinityLlmService {
             quota.current_monthly_usage += estimated_tokens + response_tokens;
         }
 
-        session.messages.push(assistant_message.clone());
+        session.messages.push(stored_message);
         session.last_activity = time();
 
         Ok(assistant_message)
     }
 
     // Real DFINITY LLM canister call using ic-llm crate
-    async fn call_llm_canister_async(&self, model: &QuantizedModel, message: &str) -> Result<String, LlmError> {
-        // Convert our message to DFINITY LLM format
-        let llm_messages = vec![
-            LlmChatMessage::User {
-                content: message.to_string(),
+    async fn call_llm_canister_async(
+        &self,
+        model: &QuantizedModel,
+        llm_messages: Vec<LlmChatMessage>,
+        user_principal: Principal,
+    ) -> Result<String, LlmError> {
+        BatchingService::chat(model, llm_messages, user_principal, Lane::Standard)
+            .await
+            .map_err(|_| LlmError::ServiceUnavailable { retry_after: OutageService::retry_after_secs() })
+    }
+
+    // System prompt followed by the most recent turns
+    fn build_turns(session: &ConversationSession) -> Vec<(MessageRole, String)> {
+        let history_start = session.messages.len().saturating_sub(MAX_HISTORY_MESSAGES);
+        session
+            .settings
+            .system_prompt
+            .iter()
+            .map(|prompt| (MessageRole::System, prompt.clone()))
+            .chain(session.messages[history_start..].iter().map(|m| (m.role.clone(), m.content.clone())))
+            .collect()
+    }
+
+    fn to_llm_messages(turns: Vec<(MessageRole, String)>) -> Vec<LlmChatMessage> {
+        turns.into_iter().map(|(role, content)| role.to_llm_chat_message(content)).collect()
+    }
+
+    // Agent threads are grounded in the agent's knowledge base: (agent_id, last user turn)
+    fn agent_thread_query(session: &ConversationSession) -> Option<(String, String)> {
+        match (&session.agent_id, session.messages.last()) {
+            (Some(agent_id), Some(last)) if matches!(last.role, MessageRole::User) => {
+                Some((agent_id.clone(), last.content.clone()))
             }
-        ];
-
-        // Call the DFINITY LLM canister using proper ic-llm API
-        match model {
-            QuantizedModel::Llama3_1_8B => {
-                let response = ic_llm::chat(model.to_llm_model())
-                    .with_messages(llm_messages)
-                    .send()
-                    .await;
-                Ok(response.message.content.unwrap_or_default())
-            },
+            _ => None,
         }
     }
 
-    // Calculate estimated cost (currently free for beta users)
-    fn calculate_cost(&self, _total_tokens: u64, model: &QuantizedModel) -> f64 {
-        // Currently free for beta users
-        // Future pricing will be based on usage tiers and model capabilities
-        match model {
-            QuantizedModel::Llama3_1_8B => 0.0, // Currently free
-            // Future pricing model:
-            // QuantizedModel::Llama3_1_8B => (_total_tokens as f64 / 1000.0) * 0.0001, // $0.10 per 1K tokens
+    /// Cut the reply at the first stop sequence, then at `max_tokens` using the
+    /// same four-chars-per-token estimate as quota accounting
+    fn apply_output_settings(mut response: String, settings: &ConversationSettings) -> String {
+        if let Some(cut) = settings
+            .stop_sequences
+            .iter()
+            .filter_map(|stop| response.find(stop.as_str()))
+            .min()
+        {
+            response.truncate(cut);
+        }
+
+        if let Some(max_tokens) = settings.max_tokens {
+            let max_chars = max_tokens as usize * 4;
+            if let Some((cut, _)) = response.char_indices().nth(max_chars) {
+                response.truncate(cut);
+            }
         }
+        response
+    }
+
+    fn validate_settings(settings: &ConversationSettings) -> Result<(), LlmError> {
+        let invalid = |message: String| Err(LlmError::InvalidRequest { message });
+
+        if settings.system_prompt.as_ref().map_or(false, |p| p.chars().count() > MAX_SYSTEM_PROMPT_CHARS) {
+            return invalid(format!("System prompt exceeds {} characters", MAX_SYSTEM_PROMPT_CHARS));
+        }
+        if settings.temperature.is_some() {
+            return invalid("Temperature is not supported: the LLM canister takes no sampling parameters".to_string());
+        }
+        if settings.max_tokens.map_or(false, |m| m == 0 || m > MAX_RESPONSE_TOKENS) {
+            return invalid(format!("max_tokens must be between 1 and {}", MAX_RESPONSE_TOKENS));
+        }
+        if settings.stop_sequences.len() > MAX_STOP_SEQUENCES {
+            return invalid(format!("At most {} stop sequences are allowed", MAX_STOP_SEQUENCES));
+        }
+        if settings
+            .stop_sequences
+            .iter()
+            .any(|s| s.is_empty() || s.chars().count() > MAX_STOP_SEQUENCE_CHARS)
+        {
+            return invalid(format!("Stop sequences must be 1 to {} characters", MAX_STOP_SEQUENCE_CHARS));
+        }
+        Ok(())
+    }
+
+    // Replace the prompt and decode settings used for future messages
+    pub fn update_conversation_settings(
+        &self,
+        session_id: &str,
+        settings: ConversationSettings,
+        user_principal: Principal,
+    ) -> Result<(), LlmError> {
+        Self::validate_settings(&settings)?;
+
+        let mut conversations = self.conversations.borrow_mut();
+        let session = conversations.get_mut(session_id)
+            .ok_or(LlmError::InvalidRequest {
+                message: "Conversation not found".to_string(),
+            })?;
+
+        if session.user_principal != user_principal {
+            return Err(LlmError::AuthenticationFailed);
+        }
+
+        session.settings = settings;
+        session.last_activity = time();
+        Ok(())
+    }
+
+    // USD from the pricing table at the user's subscription tier
+    fn calculate_cost(input_tokens: u64, output_tokens: u64, model: &QuantizedModel, user_principal: Principal) -> f64 {
+        let tier = Guards::limits_for(user_principal).tier;
+        PricingService::token_cost(model, input_tokens, output_tokens, &tier)
     }
 
     // Get available models for UI
@@ -340,6 +900,11 @@ impl DfinityLlmService {
 
     // Get conversation history
     pub fn get_conversation(&self, session_id: &str, user_principal: Principal) -> Result<ConversationSession, LlmError> {
+        Self::open_session(&self.owned_session(session_id, user_principal)?)
+    }
+
+    // The session as stored, content still sealed
+    fn owned_session(&self, session_id: &str, user_principal: Principal) -> Result<ConversationSession, LlmError> {
         let conversations = self.conversations.borrow();
         let session = conversations.get(session_id)
             .ok_or(LlmError::InvalidRequest {
@@ -354,12 +919,16 @@ impl DfinityLlmService {
     }
 
     // List user conversations
-    pub fn list_conversations(&self, user_principal: Principal) -> Vec<ConversationSession> {
+    pub fn list_conversations(&self, user_principal: Principal) -> ConversationListing {
         let conversations = self.conversations.borrow();
-        conversations.values()
-            .filter(|session| session.user_principal == user_principal)
-            .cloned()
-            .collect()
+        let mut listing = ConversationListing { sessions: Vec::new(), skipped: Vec::new() };
+        for session in conversations.values().filter(|session| session.user_principal == user_principal) {
+            match Self::open_session(session) {
+                Ok(opened) => listing.sessions.push(opened),
+                Err(e) => listing.skipped.push(Self::skipped(session, e)),
+            }
+        }
+        listing
     }
 
     // Delete conversation
@@ -378,6 +947,485 @@ impl DfinityLlmService {
         Ok(())
     }
 
+    // Keyword search across the user's messages, best matches first
+    pub fn search_conversations(
+        &self,
+        user_principal: Principal,
+        query: &str,
+        filters: SearchFilters,
+        offset: Option<u32>,
+        limit: Option<u32>,
+    ) -> Result<SearchResults, LlmError> {
+        let terms = Self::search_terms(query);
+        if terms.is_empty() {
+            return Err(LlmError::InvalidRequest {
+                message: "Search query has no searchable terms".to_string(),
+            });
+        }
+        let phrase = query.trim().to_lowercase();
+
+        let conversations = self.conversations.borrow();
+        let mut hits: Vec<SearchHit> = Vec::new();
+        for session in conversations.values().filter(|s| s.user_principal == user_principal) {
+            if filters.session_ids.as_ref().map_or(false, |ids| !ids.contains(&session.session_id)) {
+                continue;
+            }
+            for (index, message) in session.messages.iter().enumerate() {
+                if !Self::matches_filters(message, &filters) {
+                    continue;
+                }
+                let Ok(text) = Self::open(&user_principal, &message.content) else {
+                    continue;
+                };
+                let content = text.to_lowercase();
+                let Some(score) = Self::score_message(&content, &terms, &phrase) else {
+                    continue;
+                };
+                hits.push(SearchHit {
+                    session_id: session.session_id.clone(),
+                    message_index: index as u32,
+                    role: message.role.clone(),
+                    timestamp: message.timestamp,
+                    score,
+                    snippet: Self::snippet(&text, &content, &terms, &phrase),
+                });
+            }
+        }
+
+        // Best score first, newer messages break ties
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(b.timestamp.cmp(&a.timestamp))
+        });
+
+        let total = hits.len();
+        let offset = offset.unwrap_or(0) as usize;
+        let limit = limit.map_or(DEFAULT_SEARCH_LIMIT, |l| (l as usize).clamp(1, MAX_SEARCH_LIMIT));
+        let page: Vec<SearchHit> = hits.into_iter().skip(offset).take(limit).collect();
+        let next_offset = (offset + page.len() < total).then(|| (offset + page.len()) as u32);
+
+        Ok(SearchResults {
+            hits: page,
+            total: total as u64,
+            next_offset,
+        })
+    }
+
+    fn matches_filters(message: &ChatMessage, filters: &SearchFilters) -> bool {
+        filters.roles.as_ref().map_or(true, |roles| {
+            roles.iter().any(|r| std::mem::discriminant(r) == std::mem::discriminant(&message.role))
+        }) && filters.model.as_ref().map_or(true, |model| *model == message.model)
+            && filters.from.map_or(true, |from| message.timestamp >= from)
+            && filters.to.map_or(true, |to| message.timestamp <= to)
+    }
+
+    fn search_terms(query: &str) -> Vec<String> {
+        let mut terms: Vec<String> = query
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+            .map(|t| t.to_lowercase())
+            .collect();
+        terms.dedup();
+        terms
+    }
+
+    /// Term coverage dominates, repeated occurrences and an exact phrase add to it.
+    /// `None` when no term occurs in the content.
+    fn score_message(content: &str, terms: &[String], phrase: &str) -> Option<f32> {
+        let occurrences: Vec<usize> = terms.iter().map(|t| content.matches(t.as_str()).count()).collect();
+        let matched = occurrences.iter().filter(|&&n| n > 0).count();
+        if matched == 0 {
+            return None;
+        }
+
+        let coverage = matched as f32 / terms.len() as f32;
+        let frequency: f32 = occurrences.iter().map(|&n| (n.min(5) as f32).ln_1p()).sum();
+        let phrase_bonus = if terms.len() > 1 && content.contains(phrase) { 1.0 } else { 0.0 };
+        Some(coverage * 10.0 + frequency + phrase_bonus * 5.0)
+    }
+
+    /// Text around the first match, trimmed to char boundaries
+    fn snippet(original: &str, lowered: &str, terms: &[String], phrase: &str) -> String {
+        let first_match = lowered
+            .find(phrase)
+            .or_else(|| terms.iter().filter_map(|t| lowered.find(t.as_str())).min())
+            .unwrap_or(0);
+
+        // Lowercasing can change byte lengths, so map the position through char counts
+        let match_char = lowered[..first_match].chars().count();
+        let chars: Vec<char> = original.chars().collect();
+        let start = match_char.saturating_sub(SNIPPET_CONTEXT_CHARS);
+        let end = (match_char + SNIPPET_CONTEXT_CHARS).min(chars.len());
+
+        let mut snippet: String = chars[start.min(end)..end].iter().collect();
+        if start > 0 {
+            snippet.insert(0, '…');
+        }
+        if end < chars.len() {
+            snippet.push('…');
+        }
+        snippet
+    }
+
+    // Start a new session holding a copy of messages 0..=at_message_index
+    pub fn fork_conversation(
+        &self,
+        session_id: &str,
+        at_message_index: u32,
+        user_principal: Principal,
+    ) -> Result<String, LlmError> {
+        // Messages are copied sealed; the fork has the same owner
+        let source = self.owned_session(session_id, user_principal)?;
+        let at = at_message_index as usize;
+        if at >= source.messages.len() {
+            return Err(LlmError::InvalidRequest {
+                message: format!("Message index {} out of range", at_message_index),
+            });
+        }
+
+        let fork_id = self.next_session_id(user_principal);
+        let now = time();
+        let fork = ConversationSession {
+            session_id: fork_id.clone(),
+            user_principal,
+            model: source.model.clone(),
+            messages: source.messages[..=at].to_vec(),
+            created_at: now,
+            last_activity: now,
+            token_usage: TokenUsage {
+                input_tokens: 0,
+                output_tokens: 0,
+                total_tokens: 0,
+                estimated_cost: 0.0,
+            },
+            forked_from: Some(source.session_id),
+            forked_at_index: Some(at_message_index),
+            settings: source.settings,
+            agent_id: None, // A fork is an ordinary conversation
+        };
+
+        self.conversations.borrow_mut().insert(fork_id.clone(), fork);
+        Ok(fork_id)
+    }
+
+    // Replace a user message, drop everything after it and answer it again
+    pub async fn edit_and_regenerate(
+        &self,
+        session_id: &str,
+        message_index: u32,
+        new_content: String,
+        user_principal: Principal,
+    ) -> Result<ChatMessage, LlmError> {
+        let estimated_tokens = (new_content.len() / 4) as u64;
+        self.check_rate_limit(user_principal, estimated_tokens)?;
+        let sealed_content = Self::seal(&user_principal, &RedactionService::redact(&user_principal, &new_content).await)?;
+
+        {
+            let mut conversations = self.conversations.borrow_mut();
+            let session = conversations.get_mut(session_id)
+                .ok_or(LlmError::InvalidRequest {
+                    message: "Conversation not found".to_string(),
+                })?;
+
+            if session.user_principal != user_principal {
+                return Err(LlmError::AuthenticationFailed);
+            }
+
+            let index = message_index as usize;
+            match session.messages.get(index) {
+                Some(message) if matches!(message.role, MessageRole::User) => {}
+                Some(_) => {
+                    return Err(LlmError::InvalidRequest {
+                        message: "Only user messages can be edited".to_string(),
+                    });
+                }
+                None => {
+                    return Err(LlmError::InvalidRequest {
+                        message: format!("Message index {} out of range", message_index),
+                    });
+                }
+            }
+
+            session.messages.truncate(index + 1);
+            let edited = &mut session.messages[index];
+            edited.content = sealed_content;
+            edited.timestamp = time();
+            edited.model = session.model.clone();
+            session.last_activity = time();
+        }
+
+        self.complete_exchange(session_id, user_principal, estimated_tokens, Some(new_content)).await
+    }
+
+    // The agent's dedicated thread, created on first use with `persona` as system prompt
+    pub fn agent_thread(&self, agent_id: &str, owner: Principal, persona: String) -> String {
+        if let Some(session_id) = self.find_agent_thread(agent_id) {
+            return session_id;
+        }
+
+        let session_id = self.next_session_id(owner);
+        let now = time();
+        let session = ConversationSession {
+            session_id: session_id.clone(),
+            user_principal: owner,
+            model: QuantizedModel::Llama3_1_8B,
+            messages: Vec::new(),
+            created_at: now,
+            last_activity: now,
+            token_usage: TokenUsage {
+                input_tokens: 0,
+                output_tokens: 0,
+                total_tokens: 0,
+                estimated_cost: 0.0,
+            },
+            forked_from: None,
+            forked_at_index: None,
+            settings: ConversationSettings {
+                system_prompt: Some(persona),
+                ..Default::default()
+            },
+            agent_id: Some(agent_id.to_string()),
+        };
+        self.conversations.borrow_mut().insert(session_id.clone(), session);
+        session_id
+    }
+
+    // Append a task and its outcome to the agent's thread as a user/assistant turn.
+    // Task turns are accounted to the agent, not the owner's chat quota. Nothing
+    // is recorded unless the owner's key is loaded.
+    pub fn record_agent_task(
+        &self,
+        agent_id: &str,
+        owner: Principal,
+        persona: String,
+        task: &str,
+        outcome: String,
+        provenance: Option<Provenance>,
+    ) {
+        let (Ok(task), Ok(outcome)) = (Self::seal(&owner, task), Self::seal(&owner, &outcome)) else {
+            return;
+        };
+        let session_id = self.agent_thread(agent_id, owner, persona);
+        let mut conversations = self.conversations.borrow_mut();
+        let Some(session) = conversations.get_mut(&session_id) else {
+            return;
+        };
+
+        let now = time();
+        for (role, content, provenance) in [
+            (MessageRole::User, task, None),
+            (MessageRole::Assistant, outcome, provenance),
+        ] {
+            session.messages.push(ChatMessage {
+                role,
+                content,
+                timestamp: now,
+                model: session.model.clone(),
+                provenance,
+            });
+        }
+        if session.messages.len() > MAX_AGENT_THREAD_MESSAGES {
+            let overflow = session.messages.len() - MAX_AGENT_THREAD_MESSAGES;
+            session.messages.drain(..overflow);
+        }
+        session.last_activity = now;
+    }
+
+    pub fn get_agent_thread(&self, agent_id: &str) -> Option<ConversationSession> {
+        let session_id = self.find_agent_thread(agent_id)?;
+        self.conversations.borrow().get(&session_id).and_then(|session| Self::open_session(session).ok())
+    }
+
+    // Hand the thread over together with its agent, resealed under the new
+    // owner's key. Messages that cannot be resealed are dropped.
+    pub fn transfer_agent_thread(&self, agent_id: &str, new_owner: Principal) {
+        let Some(session_id) = self.find_agent_thread(agent_id) else {
+            return;
+        };
+        let mut conversations = self.conversations.borrow_mut();
+        let Some(session) = conversations.get_mut(&session_id) else {
+            return;
+        };
+        let previous_owner = session.user_principal;
+        let resealed: Result<Vec<String>, LlmError> = session
+            .messages
+            .iter()
+            .map(|m| Self::seal(&new_owner, &Self::open(&previous_owner, &m.content)?))
+            .collect();
+        match resealed {
+            Ok(contents) => {
+                for (message, content) in session.messages.iter_mut().zip(contents) {
+                    message.content = content;
+                }
+            }
+            Err(_) => session.messages.clear(),
+        }
+        session.user_principal = new_owner;
+    }
+
+    /// Delete every session of `user`, agent threads included, and their
+    /// in-flight replies. Returns how many sessions were deleted.
+    pub fn purge_user(&self, user: Principal) -> u32 {
+        let mut conversations = self.conversations.borrow_mut();
+        let before = conversations.len();
+        conversations.retain(|_, session| session.user_principal != user);
+        self.streams.borrow_mut().retain(|_, stream| stream.user_principal != user);
+        (before - conversations.len()) as u32
+    }
+
+    /// Message content as stored: sealed under `user`'s key, base64-encoded
+    fn seal(user: &Principal, content: &str) -> Result<String, LlmError> {
+        PrivacyService::seal(user, content.as_bytes())
+            .map(|sealed| general_purpose::STANDARD.encode(sealed))
+            .map_err(|message| LlmError::InternalError { message })
+    }
+
+    fn open(user: &Principal, content: &str) -> Result<String, LlmError> {
+        let internal = |message: String| LlmError::InternalError { message };
+        let sealed = general_purpose::STANDARD
+            .decode(content)
+            .map_err(|e| internal(format!("Stored message is not sealed: {}", e)))?;
+        let plaintext = PrivacyService::open(user, &sealed).map_err(internal)?;
+        String::from_utf8(plaintext).map_err(|_| internal("Stored message is not UTF-8".to_string()))
+    }
+
+    // Copy of the session with its messages decrypted
+    fn open_session(session: &ConversationSession) -> Result<ConversationSession, LlmError> {
+        let mut opened = session.clone();
+        for message in &mut opened.messages {
+            message.content = Self::open(&session.user_principal, &message.content)?;
+        }
+        Ok(opened)
+    }
+
+    fn skipped(session: &ConversationSession, error: LlmError) -> SkippedSession {
+        let reason = match error {
+            LlmError::InternalError { message } => message,
+            other => format!("{:?}", other),
+        };
+        SkippedSession {
+            session_id: session.session_id.clone(),
+            user_principal: session.user_principal,
+            reason,
+        }
+    }
+
+    fn find_agent_thread(&self, agent_id: &str) -> Option<String> {
+        self.conversations
+            .borrow()
+            .values()
+            .find(|s| s.agent_id.as_deref() == Some(agent_id))
+            .map(|s| s.session_id.clone())
+    }
+
+    // Unique even for several sessions created by one user within the same round
+    fn next_session_id(&self, user_principal: Principal) -> String {
+        let seq = self.session_seq.get() + 1;
+        self.session_seq.set(seq);
+        format!("conv_{}_{}_{}", user_principal.to_string(), time(), seq)
+    }
+
+    // Export a conversation transcript, optionally without system prompts
+    pub fn export_conversation(
+        &self,
+        session_id: &str,
+        user_principal: Principal,
+        format: ExportFormat,
+        redact_system_prompts: bool,
+    ) -> Result<String, LlmError> {
+        let session = self.get_conversation(session_id, user_principal)?;
+        Ok(Self::render_export(&session, format, redact_system_prompts))
+    }
+
+    // Admin bulk export, ordered by session id and paged after `after`
+    pub fn export_all_conversations(
+        &self,
+        format: ExportFormat,
+        redact_system_prompts: bool,
+        after: Option<String>,
+    ) -> ConversationExportPage {
+        let conversations = self.conversations.borrow();
+        let mut sessions: Vec<&ConversationSession> = conversations
+            .values()
+            .filter(|s| after.as_ref().map_or(true, |after| &s.session_id > after))
+            .collect();
+        sessions.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+
+        let more = sessions.len() > MAX_BULK_EXPORT;
+        sessions.truncate(MAX_BULK_EXPORT);
+        let next_cursor = if more { sessions.last().map(|s| s.session_id.clone()) } else { None };
+
+        let mut page = ConversationExportPage { items: Vec::new(), skipped: Vec::new(), next_cursor };
+        for session in sessions {
+            match Self::open_session(session) {
+                Ok(opened) => page.items.push(ConversationExport {
+                    content: Self::render_export(&opened, format, redact_system_prompts),
+                    session_id: opened.session_id,
+                    user_principal: opened.user_principal,
+                }),
+                Err(e) => page.skipped.push(Self::skipped(session, e)),
+            }
+        }
+        page
+    }
+
+    fn render_export(session: &ConversationSession, format: ExportFormat, redact_system_prompts: bool) -> String {
+        let messages = session
+            .messages
+            .iter()
+            .filter(|m| !(redact_system_prompts && matches!(m.role, MessageRole::System)));
+
+        match format {
+            ExportFormat::Json => serde_json::json!({
+                "session_id": session.session_id,
+                "user": session.user_principal.to_text(),
+                "model": session.model.display_name(),
+                "created_at": session.created_at,
+                "last_activity": session.last_activity,
+                "system_prompt": if redact_system_prompts { None } else { session.settings.system_prompt.as_deref() },
+                "token_usage": {
+                    "input_tokens": session.token_usage.input_tokens,
+                    "output_tokens": session.token_usage.output_tokens,
+                    "total_tokens": session.token_usage.total_tokens,
+                },
+                "messages": messages.map(|m| serde_json::json!({
+                    "role": format!("{:?}", m.role),
+                    "content": m.content,
+                    "timestamp": m.timestamp,
+                    "model": m.model.display_name(),
+                })).collect::<Vec<_>>(),
+            })
+            .to_string(),
+            ExportFormat::Markdown => {
+                let mut out = format!(
+                    "# Conversation {}\n\n- Model: {}\n- Created: {}\n- Last activity: {}\n- Tokens: {} in / {} out / {} total\n",
+                    session.session_id,
+                    session.model.display_name(),
+                    session.created_at,
+                    session.last_activity,
+                    session.token_usage.input_tokens,
+                    session.token_usage.output_tokens,
+                    session.token_usage.total_tokens,
+                );
+                if let Some(prompt) = session.settings.system_prompt.as_ref().filter(|_| !redact_system_prompts) {
+                    out.push_str(&format!("\n## System prompt\n\n{}\n", prompt));
+                }
+                for message in messages {
+                    out.push_str(&format!(
+                        "\n## {:?} ({}, {})\n\n{}\n",
+                        message.role,
+                        message.model.display_name(),
+                        message.timestamp,
+                        message.content
+                    ));
+                }
+                out
+            }
+        }
+    }
+
     // Switch model in existing conversation
     pub fn switch_model(&self, session_id: &str, new_model: QuantizedModel, user_principal: Principal) -> Result<(), LlmError> {
         let mut conversations = self.conversations.borrow_mut();
@@ -402,3 +1450,56 @@ impl Default for DfinityLlmService {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_scoring_prefers_full_coverage() {
+        let terms = DfinityLlmService::search_terms("stable memory");
+        let both = DfinityLlmService::score_message("use stable memory here", &terms, "stable memory").unwrap();
+        let one = DfinityLlmService::score_message("memory only", &terms, "stable memory").unwrap();
+        assert!(both > one);
+        assert!(DfinityLlmService::score_message("unrelated", &terms, "stable memory").is_none());
+    }
+
+    #[test]
+    fn test_output_settings_apply_stop_then_max_tokens() {
+        let settings = ConversationSettings {
+            max_tokens: Some(2),
+            stop_sequences: vec!["END".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(DfinityLlmService::apply_output_settings("abcEND tail".to_string(), &settings), "abc");
+        assert_eq!(DfinityLlmService::apply_output_settings("0123456789".to_string(), &settings), "01234567");
+    }
+
+    #[test]
+    fn test_settings_reject_temperature() {
+        let settings = ConversationSettings { temperature: Some(0.7), ..Default::default() };
+        assert!(matches!(
+            DfinityLlmService::validate_settings(&settings),
+            Err(LlmError::InvalidRequest { .. })
+        ));
+        assert!(DfinityLlmService::validate_settings(&ConversationSettings::default()).is_ok());
+    }
+
+    #[test]
+    fn test_quota_rolls_over_at_midnight_and_month_start() {
+        let day = |days: u64| days * NANOS_PER_DAY;
+        // 2024-02-15, leap year
+        assert_eq!(next_month_start(day(19_768) + 5), day(19_783));
+        assert_eq!(month_start(day(19_768)), day(19_754));
+
+        let mut quota = UserQuota::free(Principal::anonymous(), day(19_722) + 1); // 2023-12-31
+        quota.current_daily_usage = 100;
+        quota.current_monthly_usage = 500;
+        quota.roll_over(day(19_722) + 2);
+        assert_eq!((quota.current_daily_usage, quota.current_monthly_usage), (100, 500));
+        assert_eq!(quota.next_daily_reset(), day(19_723));
+        assert_eq!(quota.next_monthly_reset(), day(19_723));
+        quota.roll_over(day(19_723));
+        assert_eq!((quota.current_daily_usage, quota.current_monthly_usage), (0, 0));
+    }
+}
@@ -0,0 +1,19 @@
+use crate::domain::instruction::SubscriptionTier;
+use crate::infra::Resilience;
+use candid::Principal;
+use ic_cdk::api::call::call;
+
+/// Client for subscription lookups on the economics canister
+pub struct EconomicsClient;
+
+impl EconomicsClient {
+    /// `None` when the principal has no active subscription
+    pub async fn get_subscription_tier(canister_id: &str, user: Principal) -> Result<Option<SubscriptionTier>, String> {
+        let can_principal: Principal = canister_id.parse().map_err(|_| "invalid canister id")?;
+        let (tier,): (Option<SubscriptionTier>,) = Resilience::call(canister_id, "get_subscription_tier", || {
+            call(can_principal, "get_subscription_tier", (user,))
+        })
+        .await?;
+        Ok(tier)
+    }
+}
@@ -0,0 +1,397 @@
+use crate::infra::stable::{memory, Cbor, Memory, EMBEDDING_BACKEND_MEMORY_ID};
+use crate::infra::{Metrics, Resilience};
+use crate::services::modelrepo::ModelState;
+use crate::services::static_embedding::StaticEmbeddingModel;
+use crate::services::{with_state, ModelRepoClient};
+use candid::CandidType;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+thread_local! {
+    static CACHE: RefCell<EmbeddingCache> = RefCell::new(EmbeddingCache::default());
+    static BACKEND: RefCell<StableBTreeMap<u8, Cbor<StoredBackend>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(EMBEDDING_BACKEND_MEMORY_ID)));
+    // Loaded from the model repo on first use after an upgrade
+    static REPO_MODEL: RefCell<Option<Rc<StaticEmbeddingModel>>> = RefCell::new(None);
+}
+
+const CONFIG_KEY: u8 = 0;
+
+const MAX_CACHE_ENTRIES: usize = 5_000;
+const MAX_BATCH: usize = 32;
+const MAX_TEXT_CHARS: usize = 8_000;
+const DEFAULT_LOCAL_DIMENSIONS: u32 = 256;
+const OUTCALL_CYCLES: u128 = 30_000_000_000;
+const MAX_RESPONSE_BYTES: u64 = 2 * 1024 * 1024;
+const MAX_REPO_MODEL_BYTES: u64 = 64 * 1024 * 1024;
+// Providers differ in the last float digits between replicas; rounding lets the outcall reach consensus
+const CONSENSUS_PRECISION: f32 = 10_000.0;
+pub const TRANSFORM_METHOD: &str = "transform_embedding_response";
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum EmbeddingBackend {
+    /// Feature-hashed word and trigram vectors computed on the canister.
+    /// No model download or outcall; good enough for lexical-semantic overlap.
+    LocalHashing { dimensions: u32 },
+    /// OpenAI-compatible `POST {url}` with `{"model", "input"}` over an HTTPS outcall
+    Https {
+        url: String,
+        model: String,
+        dimensions: u32,
+        api_key: Option<String>, // Sent as a bearer token; never returned by get_embedding_backend
+    },
+    /// Static embedding model from the model repo, run on the canister.
+    /// See `StaticEmbeddingModel` for the format the repo must serve.
+    RepoModel { model_id: String },
+}
+
+impl Default for EmbeddingBackend {
+    fn default() -> Self {
+        EmbeddingBackend::LocalHashing { dimensions: DEFAULT_LOCAL_DIMENSIONS }
+    }
+}
+
+/// The backend plus, for a repo model, the version its vectors came from
+#[derive(Default, Serialize, Deserialize)]
+struct StoredBackend {
+    backend: EmbeddingBackend,
+    version: Option<String>,
+}
+
+#[derive(Default)]
+struct EmbeddingCache {
+    vectors: HashMap<[u8; 32], Vec<f32>>,
+    order: VecDeque<[u8; 32]>, // Insertion order for eviction
+}
+
+#[derive(Deserialize)]
+struct ProviderResponse {
+    data: Vec<ProviderEmbedding>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ProviderEmbedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Text embeddings for retrieval and memory search, cached and batched per backend
+pub struct EmbeddingService;
+
+impl EmbeddingService {
+    /// A repo model is downloaded and checked before the backend changes
+    pub async fn set_backend(backend: EmbeddingBackend) -> Result<(), String> {
+        match &backend {
+            EmbeddingBackend::LocalHashing { dimensions } | EmbeddingBackend::Https { dimensions, .. }
+                if *dimensions == 0 || *dimensions > 4_096 =>
+            {
+                return Err("Embedding dimensions must be between 1 and 4096".to_string());
+            }
+            EmbeddingBackend::Https { url, .. } if !url.starts_with("https://") => {
+                return Err("Embedding provider URL must use https".to_string());
+            }
+            _ => {}
+        }
+        let version = match &backend {
+            EmbeddingBackend::RepoModel { model_id } => Some(Self::load_repo_model(model_id).await?.version.clone()),
+            _ => {
+                REPO_MODEL.with(|m| m.borrow_mut().take());
+                None
+            }
+        };
+        BACKEND.with(|b| b.borrow_mut().insert(CONFIG_KEY, Cbor(StoredBackend { backend, version })));
+        CACHE.with(|c| *c.borrow_mut() = EmbeddingCache::default());
+        Ok(())
+    }
+
+    /// Configured backend with the API key removed
+    pub fn backend_info() -> EmbeddingBackend {
+        match Self::stored().backend {
+            EmbeddingBackend::Https { url, model, dimensions, api_key } => EmbeddingBackend::Https {
+                url,
+                model,
+                dimensions,
+                api_key: api_key.map(|_| "<redacted>".to_string()),
+            },
+            other => other,
+        }
+    }
+
+    /// Identifies the vectors the current backend produces; stored vectors with
+    /// a different fingerprint are not comparable with new ones
+    pub fn model_fingerprint() -> String {
+        Self::fingerprint(&Self::stored())
+    }
+
+    /// One vector per input, in order. Duplicates and cached texts are not re-embedded.
+    pub async fn embed_texts(texts: Vec<String>) -> Result<Vec<Vec<f32>>, String> {
+        let mut stored = Self::stored();
+        let repo_model = match &stored.backend {
+            EmbeddingBackend::RepoModel { model_id } => {
+                let model = Self::repo_model(model_id).await?;
+                if stored.version.as_deref() != Some(model.version.as_str()) {
+                    stored.version = Some(model.version.clone());
+                    Self::record_version(&model);
+                }
+                Some(model)
+            }
+            _ => None,
+        };
+        let fingerprint = Self::fingerprint(&stored);
+        let keys: Vec<[u8; 32]> = texts.iter().map(|t| Self::cache_key(&fingerprint, t)).collect();
+
+        // Other calls can evict entries while this one awaits a batch, so results
+        // are collected here and the cache only saves work
+        let mut found: HashMap<[u8; 32], Vec<f32>> = HashMap::new();
+        let mut missing: Vec<(usize, [u8; 32])> = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            if found.contains_key(key) || missing.iter().any(|(_, k)| k == key) {
+                continue;
+            }
+            match CACHE.with(|c| c.borrow().vectors.get(key).cloned()) {
+                Some(vector) => {
+                    found.insert(*key, vector);
+                }
+                None => missing.push((i, *key)),
+            }
+        }
+        Metrics::add_to_counter("embedding_cache_hits_total", (texts.len() - missing.len()) as u64);
+
+        for batch in missing.chunks(MAX_BATCH) {
+            let inputs: Vec<String> = batch.iter().map(|(i, _)| Self::clip(&texts[*i])).collect();
+            let vectors = match (&stored.backend, &repo_model) {
+                (EmbeddingBackend::LocalHashing { dimensions }, _) => {
+                    inputs.iter().map(|t| Self::hash_embedding(t, *dimensions as usize)).collect()
+                }
+                (EmbeddingBackend::Https { url, model, api_key, .. }, _) => {
+                    Self::fetch(url, model, api_key.as_deref(), &inputs).await?
+                }
+                (EmbeddingBackend::RepoModel { .. }, Some(model)) => inputs.iter().map(|t| model.embed(t)).collect(),
+                (EmbeddingBackend::RepoModel { model_id }, None) => {
+                    return Err(format!("Embedding model {} is not loaded", model_id));
+                }
+            };
+            CACHE.with(|c| {
+                let mut cache = c.borrow_mut();
+                for ((_, key), vector) in batch.iter().zip(vectors) {
+                    cache.insert(*key, vector.clone());
+                    found.insert(*key, vector);
+                }
+            });
+        }
+
+        keys.iter()
+            .map(|key| found.get(key).cloned().ok_or_else(|| "Embedding missing after fetch".to_string()))
+            .collect()
+    }
+
+    /// Cosine similarity; 0 when dimensions differ (e.g. after a backend change)
+    pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        if a.len() != b.len() || a.is_empty() {
+            return 0.0;
+        }
+        let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        let norm = a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm == 0.0 { 0.0 } else { dot / norm }
+    }
+
+    fn stored() -> StoredBackend {
+        BACKEND.with(|b| b.borrow().get(&CONFIG_KEY).map(|s| s.0)).unwrap_or_default()
+    }
+
+    async fn repo_model(model_id: &str) -> Result<Rc<StaticEmbeddingModel>, String> {
+        let loaded = REPO_MODEL.with(|m| m.borrow().clone()).filter(|m| m.model_id == model_id);
+        match loaded {
+            Some(model) => Ok(model),
+            None => Self::load_repo_model(model_id).await,
+        }
+    }
+
+    /// Whole model from the repos that agree on the manifest, each chunk checked against its sha256
+    async fn load_repo_model(model_id: &str) -> Result<Rc<StaticEmbeddingModel>, String> {
+        let repos = with_state(|s| s.config.model_repos());
+        if repos.is_empty() {
+            return Err("model_repo_canister_id not configured".to_string());
+        }
+        let (manifest, repos) = ModelRepoClient::get_manifest(&repos, model_id).await?;
+        if !matches!(manifest.state, ModelState::Active) {
+            return Err(format!("Embedding model {} is not active", model_id));
+        }
+        let size: u64 = manifest.chunks.iter().map(|c| c.size).sum();
+        if size > MAX_REPO_MODEL_BYTES {
+            return Err(format!("Embedding model {} is {} bytes; the limit is {}", model_id, size, MAX_REPO_MODEL_BYTES));
+        }
+
+        let mut chunks = manifest.chunks.clone();
+        chunks.sort_by_key(|c| c.offset);
+        let mut bytes = Vec::with_capacity(size as usize);
+        for chunk in &chunks {
+            bytes.extend(ModelRepoClient::get_chunk(&repos, &manifest, &chunk.id).await?);
+        }
+
+        let model = Rc::new(StaticEmbeddingModel::parse(model_id, &manifest.version, &bytes)?);
+        REPO_MODEL.with(|m| *m.borrow_mut() = Some(model.clone()));
+        Metrics::increment_counter("embedding_model_loads_total");
+        Ok(model)
+    }
+
+    /// A reload after an upgrade may find a newer version; vectors stored
+    /// under the old fingerprint then stop matching, as after a backend change
+    fn record_version(model: &StaticEmbeddingModel) {
+        BACKEND.with(|b| {
+            let mut map = b.borrow_mut();
+            let Some(Cbor(mut stored)) = map.get(&CONFIG_KEY) else { return };
+            if matches!(&stored.backend, EmbeddingBackend::RepoModel { model_id } if *model_id == model.model_id) {
+                stored.version = Some(model.version.clone());
+                map.insert(CONFIG_KEY, Cbor(stored));
+            }
+        });
+    }
+
+    /// Keep only the rounded vectors so every replica returns identical bytes
+    pub fn transform(args: TransformArgs) -> HttpResponse {
+        let body = serde_json::from_slice::<ProviderResponse>(&args.response.body)
+            .map(|response| {
+                let data: Vec<ProviderEmbedding> = response
+                    .data
+                    .into_iter()
+                    .map(|e| ProviderEmbedding {
+                        index: e.index,
+                        embedding: e.embedding.iter().map(|x| (x * CONSENSUS_PRECISION).round() / CONSENSUS_PRECISION).collect(),
+                    })
+                    .collect();
+                serde_json::json!({ "data": data }).to_string().into_bytes()
+            })
+            .unwrap_or_default();
+        HttpResponse {
+            status: args.response.status,
+            headers: vec![],
+            body,
+        }
+    }
+
+    async fn fetch(url: &str, model: &str, api_key: Option<&str>, inputs: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        Resilience::check_breaker(url)?;
+        let mut headers = vec![HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() }];
+        if let Some(key) = api_key {
+            headers.push(HttpHeader { name: "Authorization".to_string(), value: format!("Bearer {}", key) });
+        }
+        let request = CanisterHttpRequestArgument {
+            url: url.to_string(),
+            method: HttpMethod::POST,
+            body: Some(serde_json::json!({ "model": model, "input": inputs }).to_string().into_bytes()),
+            max_response_bytes: Some(MAX_RESPONSE_BYTES),
+            transform: Some(TransformContext::from_name(TRANSFORM_METHOD.to_string(), vec![])),
+            headers,
+        };
+
+        let response = match http_request(request, OUTCALL_CYCLES).await {
+            Ok((response,)) => response,
+            Err((code, msg)) => {
+                Resilience::record_failure(url);
+                return Err(format!("Embedding outcall failed: {:?}: {}", code, msg));
+            }
+        };
+        let status: u32 = response.status.0.try_into().unwrap_or(0);
+        if !(200..300).contains(&status) {
+            Resilience::record_failure(url);
+            return Err(format!("Embedding provider returned HTTP {}", status));
+        }
+        Resilience::record_success(url);
+        Metrics::increment_counter("embedding_outcalls_total");
+
+        let mut data = serde_json::from_slice::<ProviderResponse>(&response.body)
+            .map_err(|e| format!("Unreadable embedding response: {}", e))?
+            .data;
+        if data.len() != inputs.len() {
+            return Err(format!("Provider returned {} embeddings for {} inputs", data.len(), inputs.len()));
+        }
+        data.sort_by_key(|e| e.index);
+        Ok(data.into_iter().map(|e| e.embedding).collect())
+    }
+
+    /// Signed feature hashing of words and character trigrams, L2-normalised
+    fn hash_embedding(text: &str, dimensions: usize) -> Vec<f32> {
+        let mut vector = vec![0.0f32; dimensions];
+        for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+            let word = word.to_lowercase();
+            Self::add_feature(&mut vector, &word, 1.0);
+
+            let padded: Vec<char> = format!("#{}#", word).chars().collect();
+            for trigram in padded.windows(3) {
+                Self::add_feature(&mut vector, &trigram.iter().collect::<String>(), 0.5);
+            }
+        }
+
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        vector
+    }
+
+    fn add_feature(vector: &mut [f32], feature: &str, weight: f32) {
+        // FNV-1a: stable across builds, unlike the std hasher
+        let hash = feature.bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+        let index = (hash % vector.len() as u64) as usize;
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[index] += sign * weight;
+    }
+
+    fn fingerprint(stored: &StoredBackend) -> String {
+        match &stored.backend {
+            EmbeddingBackend::LocalHashing { dimensions } => format!("local:{}", dimensions),
+            EmbeddingBackend::Https { url, model, dimensions, .. } => format!("{}:{}:{}", url, model, dimensions),
+            EmbeddingBackend::RepoModel { model_id } => {
+                format!("repo:{}@{}", model_id, stored.version.as_deref().unwrap_or_default())
+            }
+        }
+    }
+
+    fn cache_key(fingerprint: &str, text: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(fingerprint.as_bytes());
+        hasher.update([0]);
+        hasher.update(text.as_bytes());
+        hasher.finalize().into()
+    }
+
+    fn clip(text: &str) -> String {
+        text.chars().take(MAX_TEXT_CHARS).collect()
+    }
+}
+
+impl EmbeddingCache {
+    fn insert(&mut self, key: [u8; 32], vector: Vec<f32>) {
+        if self.vectors.insert(key, vector).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > MAX_CACHE_ENTRIES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.vectors.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_embedding_similarity() {
+        let a = EmbeddingService::hash_embedding("stable memory upgrades", 256);
+        let b = EmbeddingService::hash_embedding("upgrading stable memory", 256);
+        let c = EmbeddingService::hash_embedding("cycles wallet balance", 256);
+        assert!(EmbeddingService::cosine(&a, &b) > EmbeddingService::cosine(&a, &c));
+        assert_eq!(a, EmbeddingService::hash_embedding("stable memory upgrades", 256));
+    }
+}
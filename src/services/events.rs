@@ -0,0 +1,201 @@
+use crate::infra::stable::{memory, Cbor, Memory, EVENTS_MEMORY_ID, EVENT_SUBSCRIBERS_MEMORY_ID};
+use crate::infra::Metrics;
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    static EVENTS: RefCell<StableBTreeMap<u64, Cbor<AgentEvent>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(EVENTS_MEMORY_ID)));
+    static SUBSCRIBERS: RefCell<StableBTreeMap<String, Cbor<EventSubscription>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(EVENT_SUBSCRIBERS_MEMORY_ID)));
+}
+
+const MAX_EVENTS: u64 = 10_000;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+const MAX_SUBSCRIBERS: u64 = 20;
+pub const DEFAULT_SUBSCRIBER_METHOD: &str = "on_agent_event";
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum AgentEventKind {
+    AgentCreated { agent_type: String },
+    TaskCompleted { task_id: String, success: bool },
+    ModelBound { model_id: String },
+    AgentSuspended { reason: String },
+    AgentArchived,
+    AgentRestored,
+    AgentFlaggedIdle { archive_after: u64 }, // Run a task before then to keep the agent
+    ApprovalRequested { request_id: String, rule: String }, // Answer with respond_to_approval
+    ApprovalResolved { request_id: String, approved: bool },
+    ConfigProposed { proposal_id: String }, // Answer with respond_to_config_proposal
+    AlertRaised { rule_id: String, name: String, value: f64 },
+    AlertResolved { rule_id: String, name: String, value: f64 },
+}
+
+/// Entry in the append-only agent lifecycle log
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct AgentEvent {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub agent_id: Option<String>,
+    pub user_id: String,
+    pub kind: AgentEventKind,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct EventPage {
+    pub events: Vec<AgentEvent>,
+    pub next_cursor: Option<u64>,
+}
+
+/// Canister notified with a one-way call for every new event
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct EventSubscription {
+    pub canister_id: String,
+    pub method: String,
+    pub subscribed_at: u64,
+}
+
+/// Stable, cursor-paginated event log with optional push to subscriber canisters
+pub struct EventService;
+
+impl EventService {
+    pub fn publish(agent_id: Option<&str>, user_id: &str, kind: AgentEventKind) {
+        let event = EVENTS.with(|e| {
+            let mut events = e.borrow_mut();
+            let sequence = events.last_key_value().map(|(seq, _)| seq + 1).unwrap_or(0);
+            let event = AgentEvent {
+                sequence,
+                timestamp: time(),
+                agent_id: agent_id.map(str::to_string),
+                user_id: user_id.to_string(),
+                kind,
+            };
+            events.insert(sequence, Cbor(event.clone()));
+
+            if sequence >= MAX_EVENTS {
+                events.remove(&(sequence - MAX_EVENTS));
+            }
+            event
+        });
+
+        Metrics::increment_counter("events_published_total");
+        Self::push(&event);
+    }
+
+    /// Events after `cursor` in sequence order, restricted to `user_id` unless None
+    pub fn list(cursor: Option<u64>, limit: Option<u32>, user_id: Option<&str>) -> EventPage {
+        let limit = limit.map_or(DEFAULT_PAGE_SIZE, |l| (l as usize).clamp(1, MAX_PAGE_SIZE));
+        let start = cursor.map_or(0, |c| c + 1);
+
+        EVENTS.with(|e| {
+            let events = e.borrow();
+            let mut page: Vec<AgentEvent> = Vec::new();
+            let mut next_cursor = None;
+
+            for (_, event) in events.range(start..) {
+                if user_id.map_or(false, |user| event.0.user_id != user) {
+                    continue;
+                }
+                if page.len() == limit {
+                    next_cursor = page.last().map(|ev| ev.sequence);
+                    break;
+                }
+                page.push(event.0);
+            }
+
+            EventPage { events: page, next_cursor }
+        })
+    }
+
+    /// Newest `limit` events for `user_id`, newest first
+    pub fn recent(user_id: &str, limit: usize) -> Vec<AgentEvent> {
+        EVENTS.with(|e| {
+            let events = e.borrow();
+            let Some((last, _)) = events.last_key_value() else {
+                return Vec::new();
+            };
+            let first = events.first_key_value().map_or(last, |(first, _)| first);
+            (first..=last)
+                .rev()
+                .filter_map(|sequence| events.get(&sequence))
+                .map(|event| event.0)
+                .filter(|event| event.user_id == user_id)
+                .take(limit)
+                .collect()
+        })
+    }
+
+    pub fn for_user(user_id: &str) -> Vec<AgentEvent> {
+        EVENTS.with(|e| e.borrow().iter().map(|(_, event)| event.0).filter(|event| event.user_id == user_id).collect())
+    }
+
+    /// Replace `user_id` on their events with `tombstone`, keeping the sequence intact
+    pub fn erase_user(user_id: &str, tombstone: &str) -> u32 {
+        EVENTS.with(|e| {
+            let mut events = e.borrow_mut();
+            let erased: Vec<AgentEvent> = events
+                .iter()
+                .map(|(_, event)| event.0)
+                .filter(|event| event.user_id == user_id)
+                .collect();
+            for mut event in erased.iter().cloned() {
+                event.user_id = tombstone.to_string();
+                events.insert(event.sequence, Cbor(event));
+            }
+            erased.len() as u32
+        })
+    }
+
+    pub fn subscribe(canister_id: Principal, method: Option<String>) -> Result<(), String> {
+        let key = canister_id.to_text();
+        SUBSCRIBERS.with(|s| {
+            let mut subscribers = s.borrow_mut();
+            if !subscribers.contains_key(&key) && subscribers.len() >= MAX_SUBSCRIBERS {
+                return Err(format!("Subscriber limit reached. Maximum: {}", MAX_SUBSCRIBERS));
+            }
+            subscribers.insert(
+                key.clone(),
+                Cbor(EventSubscription {
+                    canister_id: key,
+                    method: method.unwrap_or_else(|| DEFAULT_SUBSCRIBER_METHOD.to_string()),
+                    subscribed_at: time(),
+                }),
+            );
+            Ok(())
+        })
+    }
+
+    pub fn unsubscribe(canister_id: Principal) -> Result<(), String> {
+        SUBSCRIBERS.with(|s| {
+            s.borrow_mut()
+                .remove(&canister_id.to_text())
+                .map(|_| ())
+                .ok_or_else(|| format!("{} is not subscribed", canister_id))
+        })
+    }
+
+    pub fn list_subscribers() -> Vec<EventSubscription> {
+        SUBSCRIBERS.with(|s| s.borrow().iter().map(|(_, sub)| sub.0).collect())
+    }
+
+    pub fn is_subscriber(principal: &Principal) -> bool {
+        SUBSCRIBERS.with(|s| s.borrow().contains_key(&principal.to_text()))
+    }
+
+    /// Fire-and-forget: a slow or failing subscriber never blocks the publisher.
+    /// Subscribers that miss a notification catch up through `list`.
+    fn push(event: &AgentEvent) {
+        for subscription in Self::list_subscribers() {
+            let Ok(canister_id) = Principal::from_text(&subscription.canister_id) else {
+                continue;
+            };
+            if ic_cdk::api::call::notify(canister_id, &subscription.method, (event.clone(),)).is_err() {
+                Metrics::increment_counter("event_push_failures_total");
+            }
+        }
+    }
+}
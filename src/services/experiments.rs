@@ -0,0 +1,286 @@
+use crate::domain::instruction::AgentType;
+use crate::domain::DecodeParams;
+use crate::infra::stable::{memory, Cbor, Memory, EXPERIMENTS_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::seed::Seed;
+use crate::services::{with_state, ModelPoolService};
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    static EXPERIMENTS: RefCell<StableBTreeMap<String, Cbor<Experiment>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(EXPERIMENTS_MEMORY_ID)));
+}
+
+const MAX_VARIANTS: usize = 10;
+const TASK_PLACEHOLDER: &str = "{task}";
+
+/// One arm of an experiment. Unset fields keep what the agent would use anyway.
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ExperimentVariant {
+    pub name: String,
+    pub traffic_percent: u8,
+    pub prompt_template: Option<String>, // Replaces the agent type's task prompt; must contain {task}
+    pub model_id: Option<String>,        // Must be the bound model or in the serving pool
+    pub decode_params: Option<DecodeParams>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ExperimentSpec {
+    pub name: String,
+    pub agent_type: AgentType,
+    pub variants: Vec<ExperimentVariant>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum ExperimentStatus {
+    Running,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, CandidType)]
+pub struct VariantMetrics {
+    pub requests: u64,
+    pub successes: u64,
+    pub total_latency_ms: u64,
+    pub total_tokens: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Experiment {
+    pub experiment_id: String,
+    pub name: String,
+    pub agent_type: AgentType,
+    pub variants: Vec<ExperimentVariant>,
+    pub metrics: Vec<VariantMetrics>, // In variant order
+    pub status: ExperimentStatus,
+    pub created_by: Principal,
+    pub created_at: u64,
+    pub stopped_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct VariantResult {
+    pub name: String,
+    pub traffic_percent: u8,
+    pub requests: u64,
+    pub success_rate: f32,
+    pub mean_latency_ms: u64,
+    pub mean_tokens: f32,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ExperimentResults {
+    pub experiment_id: String,
+    pub name: String,
+    pub agent_type: AgentType,
+    pub status: ExperimentStatus,
+    pub variants: Vec<VariantResult>,
+}
+
+/// Variant a task was assigned to
+#[derive(Debug, Clone)]
+pub struct ExperimentAssignment {
+    pub experiment_id: String,
+    pub variant_index: usize,
+    pub variant: ExperimentVariant,
+}
+
+/// A/B tests of prompt templates, models and decode params on real agent
+/// tasks. Each agent type runs at most one experiment; a task lands in a
+/// variant with its variant's traffic_percent, and traffic the variants leave
+/// runs unchanged.
+pub struct ExperimentService;
+
+impl ExperimentService {
+    pub fn create(spec: ExperimentSpec, created_by: Principal) -> Result<Experiment, String> {
+        Self::validate(&spec)?;
+        if let Some(running) = Self::running_for(&spec.agent_type) {
+            return Err(format!(
+                "Experiment {} is already running for {:?}; stop it first",
+                running.experiment_id, spec.agent_type
+            ));
+        }
+        EXPERIMENTS.with(|e| {
+            let mut experiments = e.borrow_mut();
+            let experiment = Experiment {
+                experiment_id: format!("exp-{}", experiments.len() + 1),
+                name: spec.name,
+                agent_type: spec.agent_type,
+                metrics: vec![VariantMetrics::default(); spec.variants.len()],
+                variants: spec.variants,
+                status: ExperimentStatus::Running,
+                created_by,
+                created_at: time(),
+                stopped_at: None,
+            };
+            experiments.insert(experiment.experiment_id.clone(), Cbor(experiment.clone()));
+            Ok(experiment)
+        })
+    }
+
+    /// Results are kept; a stopped experiment assigns no more traffic
+    pub fn stop(experiment_id: &str) -> Result<(), String> {
+        let mut experiment = Self::get(experiment_id)?;
+        if experiment.status == ExperimentStatus::Stopped {
+            return Err(format!("Experiment {} is already stopped", experiment_id));
+        }
+        experiment.status = ExperimentStatus::Stopped;
+        experiment.stopped_at = Some(time());
+        EXPERIMENTS.with(|e| e.borrow_mut().insert(experiment_id.to_string(), Cbor(experiment)));
+        Ok(())
+    }
+
+    pub fn list() -> Vec<Experiment> {
+        EXPERIMENTS.with(|e| e.borrow().iter().map(|(_, experiment)| experiment.0).collect())
+    }
+
+    pub fn results(experiment_id: &str) -> Result<ExperimentResults, String> {
+        let experiment = Self::get(experiment_id)?;
+        Ok(Self::summarize(&experiment))
+    }
+
+    /// Variant of the running experiment for `agent_type` that serves the
+    /// task, if any. The same task always lands in the same variant.
+    pub fn assign(agent_type: &AgentType, task_id: &str) -> Option<ExperimentAssignment> {
+        let experiment = Self::running_for(agent_type)?;
+        let bucket = (Seed::derive(&[&experiment.experiment_id, task_id]) % 100) as u8;
+        let variant_index = Self::pick_variant(&experiment.variants, bucket)?;
+        Some(ExperimentAssignment {
+            experiment_id: experiment.experiment_id,
+            variant_index,
+            variant: experiment.variants[variant_index].clone(),
+        })
+    }
+
+    pub fn record(assignment: &ExperimentAssignment, success: bool, latency_ms: u64, tokens: u64) {
+        EXPERIMENTS.with(|e| {
+            let mut experiments = e.borrow_mut();
+            let Some(mut experiment) = experiments.get(&assignment.experiment_id).map(|experiment| experiment.0) else {
+                return;
+            };
+            let Some(metrics) = experiment.metrics.get_mut(assignment.variant_index) else {
+                return;
+            };
+            metrics.requests += 1;
+            metrics.successes += success as u64;
+            metrics.total_latency_ms += latency_ms;
+            metrics.total_tokens += tokens;
+            experiments.insert(assignment.experiment_id.clone(), Cbor(experiment));
+        });
+        Metrics::increment_counter(&format!(
+            "experiment_requests_total:{}/{}",
+            assignment.experiment_id, assignment.variant.name
+        ));
+    }
+
+    pub fn render(template: &str, task: &str) -> String {
+        template.replace(TASK_PLACEHOLDER, task)
+    }
+
+    fn get(experiment_id: &str) -> Result<Experiment, String> {
+        EXPERIMENTS
+            .with(|e| e.borrow().get(&experiment_id.to_string()).map(|experiment| experiment.0))
+            .ok_or_else(|| format!("Experiment {} not found", experiment_id))
+    }
+
+    fn running_for(agent_type: &AgentType) -> Option<Experiment> {
+        Self::list()
+            .into_iter()
+            .find(|experiment| experiment.status == ExperimentStatus::Running && experiment.agent_type == *agent_type)
+    }
+
+    fn validate(spec: &ExperimentSpec) -> Result<(), String> {
+        if spec.name.trim().is_empty() {
+            return Err("Experiment name must not be empty".to_string());
+        }
+        if spec.variants.is_empty() || spec.variants.len() > MAX_VARIANTS {
+            return Err(format!("An experiment needs 1 to {} variants", MAX_VARIANTS));
+        }
+        let total: u32 = spec.variants.iter().map(|v| v.traffic_percent as u32).sum();
+        if total == 0 || total > 100 {
+            return Err(format!("Variant traffic must add up to 1-100 percent, not {}", total));
+        }
+        for (index, variant) in spec.variants.iter().enumerate() {
+            if variant.name.trim().is_empty() || spec.variants[..index].iter().any(|v| v.name == variant.name) {
+                return Err("Variant names must be non-empty and unique".to_string());
+            }
+            if variant.prompt_template.as_ref().is_some_and(|t| !t.contains(TASK_PLACEHOLDER)) {
+                return Err(format!("Prompt template of {} must contain {}", variant.name, TASK_PLACEHOLDER));
+            }
+            if let Some(model_id) = &variant.model_id {
+                if !with_state(|s| ModelPoolService::binding(s, model_id).is_some()) {
+                    return Err(format!("Model {} of {} is neither bound nor in the serving pool", model_id, variant.name));
+                }
+            }
+            if let Some(params) = &variant.decode_params {
+                params.check_supported().map_err(|e| format!("Decode params of {}: {}", variant.name, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Variants take consecutive percent ranges of the 0-99 bucket space
+    fn pick_variant(variants: &[ExperimentVariant], bucket: u8) -> Option<usize> {
+        let mut upper = 0u32;
+        variants.iter().position(|variant| {
+            upper += variant.traffic_percent as u32;
+            (bucket as u32) < upper
+        })
+    }
+
+    fn summarize(experiment: &Experiment) -> ExperimentResults {
+        let variants = experiment
+            .variants
+            .iter()
+            .zip(experiment.metrics.iter())
+            .map(|(variant, metrics)| {
+                let requests = metrics.requests.max(1);
+                VariantResult {
+                    name: variant.name.clone(),
+                    traffic_percent: variant.traffic_percent,
+                    requests: metrics.requests,
+                    success_rate: metrics.successes as f32 / requests as f32,
+                    mean_latency_ms: metrics.total_latency_ms / requests,
+                    mean_tokens: metrics.total_tokens as f32 / requests as f32,
+                }
+            })
+            .collect();
+        ExperimentResults {
+            experiment_id: experiment.experiment_id.clone(),
+            name: experiment.name.clone(),
+            agent_type: experiment.agent_type.clone(),
+            status: experiment.status,
+            variants,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(name: &str, traffic_percent: u8) -> ExperimentVariant {
+        ExperimentVariant {
+            name: name.to_string(),
+            traffic_percent,
+            prompt_template: None,
+            model_id: None,
+            decode_params: None,
+        }
+    }
+
+    #[test]
+    fn test_traffic_split() {
+        let variants = vec![variant("a", 30), variant("b", 20)];
+        assert_eq!(ExperimentService::pick_variant(&variants, 0), Some(0));
+        assert_eq!(ExperimentService::pick_variant(&variants, 29), Some(0));
+        assert_eq!(ExperimentService::pick_variant(&variants, 30), Some(1));
+        assert_eq!(ExperimentService::pick_variant(&variants, 49), Some(1));
+        assert_eq!(ExperimentService::pick_variant(&variants, 50), None);
+        assert_eq!(ExperimentService::render("Answer briefly: {task}", "2+2"), "Answer briefly: 2+2");
+    }
+}
@@ -0,0 +1,156 @@
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentFactory, AutonomousAgent};
+use crate::services::calibration::CalibrationService;
+use crate::services::{with_state_mut, CertificationService, TaskHistoryService};
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use serde::{Deserialize, Serialize};
+
+const MAX_FEEDBACK_PER_AGENT: usize = 50;
+const MAX_COMMENT_CHARS: usize = 1_000;
+const RATINGS: std::ops::RangeInclusive<u8> = 1..=5;
+// Commented ratings quoted back to the agent, newest first
+const PROMPT_FEEDBACK_ITEMS: usize = 5;
+const PROMPT_COMMENT_CHARS: usize = 200;
+
+/// Rating of one task result
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TaskFeedback {
+    pub task_id: String,
+    pub rating: u8, // 1 (poor) to 5 (excellent)
+    pub comments: Option<String>,
+    pub submitted_by: Principal,
+    pub submitted_at: u64,
+}
+
+/// Ratings of task results. They roll into the agent's feedback_count and
+/// average_rating, and agents with learn_from_feedback enabled see recent
+/// commented ratings in later task prompts.
+pub struct FeedbackService;
+
+impl FeedbackService {
+    /// A second rating of the same task replaces the first
+    pub fn submit(
+        agent_id: &str,
+        task_id: &str,
+        rating: u8,
+        comments: Option<String>,
+        submitted_by: Principal,
+    ) -> Result<(), String> {
+        if !RATINGS.contains(&rating) {
+            return Err(format!("Rating must be between {} and {}", RATINGS.start(), RATINGS.end()));
+        }
+        let comments = comments.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+        if comments.as_ref().is_some_and(|c| c.chars().count() > MAX_COMMENT_CHARS) {
+            return Err(format!("Comments exceed {} characters", MAX_COMMENT_CHARS));
+        }
+        TaskHistoryService::get_task_result(agent_id, task_id)?;
+
+        let mut agent = AgentFactory::find_agent(agent_id)?;
+        let feedback = TaskFeedback {
+            task_id: task_id.to_string(),
+            rating,
+            comments,
+            submitted_by,
+            submitted_at: time(),
+        };
+        match agent.feedback.iter().position(|f| f.task_id == task_id) {
+            Some(index) => {
+                agent.feedback.remove(index);
+            }
+            None => agent.performance_metrics.feedback_count += 1,
+        }
+        agent.feedback.push(feedback);
+        if agent.feedback.len() > MAX_FEEDBACK_PER_AGENT {
+            let overflow = agent.feedback.len() - MAX_FEEDBACK_PER_AGENT;
+            agent.feedback.drain(..overflow);
+        }
+        agent.performance_metrics.average_rating = Self::average_rating(&agent.feedback);
+
+        CalibrationService::record_rating(agent_id, task_id, rating);
+        Metrics::increment_counter(&format!("task_feedback_total:{}", rating));
+        with_state_mut(|state| {
+            state.agents.insert(agent.agent_id.clone(), agent);
+        });
+        CertificationService::mark_dirty();
+        Ok(())
+    }
+
+    /// Retained ratings, oldest first
+    pub fn list(agent_id: &str) -> Result<Vec<TaskFeedback>, String> {
+        Ok(AgentFactory::find_agent(agent_id)?.feedback)
+    }
+
+    /// Prompt section quoting recent commented ratings when the agent learns
+    /// from feedback
+    pub fn prompt_guidance(agent: &AutonomousAgent) -> Option<String> {
+        if !agent.analysis.agent_configuration.learn_from_feedback {
+            return None;
+        }
+        Self::guidance(&agent.feedback)
+    }
+
+    fn guidance(feedback: &[TaskFeedback]) -> Option<String> {
+        let lines: Vec<String> = feedback
+            .iter()
+            .rev()
+            .filter_map(|f| {
+                let comment: String = f
+                    .comments
+                    .as_deref()?
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .take(PROMPT_COMMENT_CHARS)
+                    .collect();
+                Some(format!("- Rated {}/{}: {}", f.rating, RATINGS.end(), comment))
+            })
+            .take(PROMPT_FEEDBACK_ITEMS)
+            .collect();
+        if lines.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "Your owner's feedback on your recent answers; take it into account:\n{}",
+            lines.join("\n")
+        ))
+    }
+
+    /// Mean of the retained ratings
+    fn average_rating(feedback: &[TaskFeedback]) -> f32 {
+        if feedback.is_empty() {
+            return 0.0;
+        }
+        feedback.iter().map(|f| f.rating as f32).sum::<f32>() / feedback.len() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feedback(rating: u8, comments: Option<&str>) -> TaskFeedback {
+        TaskFeedback {
+            task_id: format!("task-{}", rating),
+            rating,
+            comments: comments.map(str::to_string),
+            submitted_by: Principal::anonymous(),
+            submitted_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_guidance_quotes_newest_comments() {
+        let feedback = vec![
+            feedback(2, Some("Answers are\ntoo long")),
+            feedback(4, None),
+            feedback(5, Some("Clear steps")),
+        ];
+        assert_eq!(
+            FeedbackService::guidance(&feedback).unwrap(),
+            "Your owner's feedback on your recent answers; take it into account:\n\
+             - Rated 5/5: Clear steps\n- Rated 2/5: Answers are too long"
+        );
+        assert_eq!(FeedbackService::guidance(&feedback[1..2]), None);
+        assert!((FeedbackService::average_rating(&feedback) - 11.0 / 3.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::infra::stable::{memory, Cbor, Memory, GC_CONFIG_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::archive::ArchiveService;
+use crate::services::events::{AgentEventKind, EventService};
+use crate::services::{with_state, with_state_mut};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::Duration;
+
+thread_local! {
+    static CONFIG: RefCell<StableBTreeMap<u8, Cbor<GcConfig>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(GC_CONFIG_MEMORY_ID)));
+}
+
+const CONFIG_KEY: u8 = 0;
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const GC_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct GcConfig {
+    pub enabled: bool,
+    pub idle_threshold_days: u32, // No task or update for this long flags the agent
+    pub grace_period_days: u32,   // Flagged agents still idle after this are archived
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_threshold_days: 90,
+            grace_period_days: 14,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, CandidType)]
+pub struct GcReport {
+    pub flagged: Vec<String>,
+    pub archived: Vec<String>,
+    pub reclaimed_heap_bytes: u64,
+}
+
+/// Finds agents abandoned by their owners: flags them with an event the owner
+/// can see, then archives the ones still idle once the grace period ends
+pub struct GcService;
+
+impl GcService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(GC_INTERVAL, || {
+            if Self::config().enabled {
+                Self::run(time());
+            }
+        });
+    }
+
+    pub fn set_config(config: GcConfig) -> Result<(), String> {
+        if config.idle_threshold_days == 0 || config.grace_period_days == 0 {
+            return Err("GC thresholds must be at least one day".to_string());
+        }
+        CONFIG.with(|c| c.borrow_mut().insert(CONFIG_KEY, Cbor(config)));
+        Ok(())
+    }
+
+    pub fn config() -> GcConfig {
+        CONFIG.with(|c| c.borrow().get(&CONFIG_KEY).map(|c| c.0)).unwrap_or_default()
+    }
+
+    pub fn run(now: u64) -> GcReport {
+        let config = Self::config();
+        let idle_threshold = config.idle_threshold_days as u64 * NANOS_PER_DAY;
+        let grace_period = config.grace_period_days as u64 * NANOS_PER_DAY;
+        let mut report = GcReport::default();
+
+        // (agent_id, owner, flagged_at) for every idle agent
+        let idle: Vec<(String, String, Option<u64>)> = with_state(|state| {
+            state
+                .agents
+                .values()
+                .filter(|a| now.saturating_sub(a.last_active) >= idle_threshold)
+                .map(|a| (a.agent_id.clone(), a.user_id.clone(), a.idle_flagged_at))
+                .collect()
+        });
+
+        for (agent_id, owner, flagged_at) in idle {
+            match flagged_at {
+                None => {
+                    with_state_mut(|state| {
+                        if let Some(agent) = state.agents.get_mut(&agent_id) {
+                            agent.idle_flagged_at = Some(now);
+                        }
+                    });
+                    EventService::publish(Some(&agent_id), &owner, AgentEventKind::AgentFlaggedIdle {
+                        archive_after: now + grace_period,
+                    });
+                    report.flagged.push(agent_id);
+                }
+                Some(flagged_at) if now.saturating_sub(flagged_at) >= grace_period => {
+                    // Busy agents and coordinator members are skipped until a later run
+                    if let Ok(info) = ArchiveService::archive(&agent_id, &owner) {
+                        report.reclaimed_heap_bytes += info.original_bytes;
+                        report.archived.push(agent_id);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        Metrics::add_to_counter("gc_agents_flagged_total", report.flagged.len() as u64);
+        Metrics::add_to_counter("gc_agents_archived_total", report.archived.len() as u64);
+        Metrics::add_to_counter("gc_reclaimed_heap_bytes_total", report.reclaimed_heap_bytes);
+        report
+    }
+}
@@ -0,0 +1,143 @@
+use crate::domain::instruction::AgentConfiguration;
+use crate::domain::{DecodeParams, InferenceRequest};
+use crate::infra::Metrics;
+use crate::services::InferenceService;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum CritiqueVerdict {
+    Passed,
+    Revised,     // Violations found and the draft was rewritten
+    Unavailable, // A critique or revision call failed; see `critique` for which
+}
+
+/// Outcome of the self-critique pass, kept on the task result
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct CritiqueRecord {
+    pub verdict: CritiqueVerdict,
+    pub critique: String,
+    pub tokens_used: u64,
+}
+
+/// Optional second look at a task answer against the agent's behavior rules
+/// and safety constraints, revising the answer when the model finds violations
+pub struct GuardrailService;
+
+impl GuardrailService {
+    /// Returns the answer to use, or None when the draft broke a rule and could
+    /// not be revised, along with the critique that decided it
+    pub async fn review(config: &AgentConfiguration, agent_id: &str, task_id: &str, draft: String) -> (Option<String>, CritiqueRecord) {
+        let rules = Self::rules(config);
+        let (critique, mut tokens_used) =
+            match Self::infer(Self::critique_prompt(&rules, &draft), &[agent_id, task_id, "critique"]).await {
+                Ok(response) => response,
+                Err(e) => {
+                    Metrics::increment_counter("self_critique_unavailable_total");
+                    return (Some(draft), CritiqueRecord {
+                        verdict: CritiqueVerdict::Unavailable,
+                        critique: e,
+                        tokens_used: 0,
+                    });
+                }
+            };
+
+        let Some(violations) = Self::parse_violations(&critique) else {
+            return (Some(draft), CritiqueRecord {
+                verdict: CritiqueVerdict::Passed,
+                critique,
+                tokens_used,
+            });
+        };
+
+        Metrics::increment_counter("self_critique_violations_total");
+        match Self::infer(Self::revision_prompt(&rules, &draft, &violations), &[agent_id, task_id, "revision"]).await {
+            Ok((revised, tokens)) => {
+                tokens_used += tokens;
+                (Some(revised), CritiqueRecord {
+                    verdict: CritiqueVerdict::Revised,
+                    critique: violations,
+                    tokens_used,
+                })
+            }
+            // The draft is known to break a rule, so it is not returned as is
+            Err(e) => (None, CritiqueRecord {
+                verdict: CritiqueVerdict::Unavailable,
+                critique: format!("{}\nRevision failed: {}", violations, e),
+                tokens_used,
+            }),
+        }
+    }
+
+    async fn infer(prompt: String, msg_parts: &[&str]) -> Result<(String, u64), String> {
+        let request = InferenceRequest {
+            seed: None,
+            prompt,
+            decode_params: DecodeParams::default(),
+            msg_id: msg_parts.join("-"),
+            model_id: None,
+            structured_output: None,
+        };
+        InferenceService::process_inference(request)
+            .await
+            .map(|response| (response.generated_text, response.tokens.len() as u64))
+    }
+
+    fn rules(config: &AgentConfiguration) -> String {
+        config
+            .behavior_rules
+            .iter()
+            .chain(&config.safety_constraints)
+            .map(|rule| format!("- {}", rule))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn critique_prompt(rules: &str, draft: &str) -> String {
+        format!(
+            "Check the answer below against these rules:\n{}\n\nAnswer:\n{}\n\n\
+             Reply with PASS on the first line if it follows every rule. Otherwise reply with \
+             VIOLATION on the first line, then list each broken rule and why.",
+            rules, draft
+        )
+    }
+
+    fn revision_prompt(rules: &str, draft: &str, violations: &str) -> String {
+        format!(
+            "Rewrite the answer below so it follows these rules:\n{}\n\nAnswer:\n{}\n\n\
+             Problems found:\n{}\n\nReply with the corrected answer only.",
+            rules, draft, violations
+        )
+    }
+
+    /// Violation details, or None when the critique passed. Anything other than
+    /// an explicit PASS counts as a violation so unclear critiques fail closed.
+    fn parse_violations(critique: &str) -> Option<String> {
+        let trimmed = critique.trim();
+        let first_line = trimmed.lines().next().unwrap_or("").trim().to_uppercase();
+        if first_line.starts_with("PASS") {
+            return None;
+        }
+        let details = trimmed
+            .lines()
+            .skip(usize::from(first_line.starts_with("VIOLATION")))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(if details.trim().is_empty() { trimmed.to_string() } else { details.trim().to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_violations() {
+        assert!(GuardrailService::parse_violations("PASS\nAll rules followed").is_none());
+        assert_eq!(
+            GuardrailService::parse_violations("VIOLATION\n- Shares private data").as_deref(),
+            Some("- Shares private data")
+        );
+        assert_eq!(GuardrailService::parse_violations("Not sure").as_deref(), Some("Not sure"));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::domain::AgentHealth;
+use crate::infra::resilience::{BreakerState, DependencyStatus};
+use crate::infra::guards::{MemoryLimits, MemoryPressure};
+use crate::infra::{Guards, Resilience};
+use crate::services::agent_factory::AgentStatus;
+use crate::services::inference::LLM_TARGET;
+use crate::services::outage::{LlmIncident, OutageService};
+use crate::services::webhook::DeliveryStatus;
+use crate::services::{with_state, PaymentService, SettingsService, BindingService, WorkflowService};
+use candid::CandidType;
+use crate::infra::clock::time;
+use std::cell::Cell;
+use std::time::Duration;
+
+thread_local! {
+    static HEARTBEAT_STARTED_AT: Cell<u64> = Cell::new(0);
+    static LAST_HEARTBEAT: Cell<u64> = Cell::new(0);
+}
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+const MISSED_HEARTBEATS_ALLOWED: u64 = 3;
+const MIN_READY_CYCLES: u128 = 500_000_000_000;
+
+/// Liveness, readiness and dependency probes for operators and the coordinator
+#[derive(Debug, Clone, CandidType)]
+pub struct DetailedHealth {
+    pub live: bool,
+    pub ready: bool,
+    pub not_ready_reasons: Vec<String>,
+    pub configured: bool, // Sealed once model_repo_canister_id has been set
+    pub basic: AgentHealth,
+    pub dependencies: Vec<DependencyStatus>,
+    pub timers_alive: bool,
+    pub last_heartbeat: u64,
+    pub agents_in_flight: u32,
+    pub tasks_in_flight: u32,
+    pub tasks_queued: u32,
+    pub running_workflows: u32,
+    pub pending_webhook_deliveries: u32,
+    pub stable_memory_bytes: u64,
+    pub heap_memory_bytes: u64,
+    pub memory_pressure: MemoryPressure,
+    pub memory_limits: MemoryLimits,
+    pub cycles_balance: u128,
+    pub llm_incident: Option<LlmIncident>, // Set while the LLM is failing or deferred tasks are queued
+}
+
+pub struct HealthService;
+
+impl HealthService {
+    /// Start the timer whose ticks prove the timer subsystem is running.
+    pub fn start_heartbeat() {
+        HEARTBEAT_STARTED_AT.with(|t| t.set(time()));
+        ic_cdk_timers::set_timer_interval(HEARTBEAT_INTERVAL, || {
+            LAST_HEARTBEAT.with(|t| t.set(time()));
+        });
+    }
+
+    pub fn get_detailed_health() -> DetailedHealth {
+        let now = time();
+        let mut not_ready_reasons = Vec::new();
+
+        let (repos, economics_canister) = with_state(|s| (s.config.model_repos(), s.config.economics_canister_id.clone()));
+        let ledger_canister = PaymentService::get_config().ok().map(|c| c.ledger_canister_id);
+        if repos.is_empty() {
+            not_ready_reasons.push("model_repo_canister_id not configured".to_string());
+        }
+
+        let mut dependencies = vec![Resilience::dependency_status(LLM_TARGET)];
+        dependencies.extend(economics_canister.iter().map(|id| Resilience::dependency_status(id)));
+        dependencies.extend(ledger_canister.iter().map(|id| Resilience::dependency_status(id)));
+        for dependency in &dependencies {
+            if dependency.state == BreakerState::Open {
+                not_ready_reasons.push(format!("circuit open for {}", dependency.target));
+            }
+        }
+        // Repos fail over to each other, so only all of them being down matters
+        let repo_dependencies: Vec<_> = repos.iter().map(|id| Resilience::dependency_status(id)).collect();
+        if !repo_dependencies.is_empty() && repo_dependencies.iter().all(|d| d.state == BreakerState::Open) {
+            not_ready_reasons.push("circuit open for every model repo".to_string());
+        }
+        dependencies.extend(repo_dependencies);
+
+        let last_heartbeat = LAST_HEARTBEAT.with(|t| t.get());
+        let heartbeat_deadline = HEARTBEAT_INTERVAL.as_nanos() as u64 * MISSED_HEARTBEATS_ALLOWED;
+        let reference = last_heartbeat.max(HEARTBEAT_STARTED_AT.with(|t| t.get()));
+        let timers_alive = reference > 0 && now.saturating_sub(reference) <= heartbeat_deadline;
+        if !timers_alive {
+            not_ready_reasons.push("timer heartbeat missed".to_string());
+        }
+
+        let cycles_balance = ic_cdk::api::canister_balance128();
+        if cycles_balance < MIN_READY_CYCLES {
+            not_ready_reasons.push(format!("cycles balance {} below {}", cycles_balance, MIN_READY_CYCLES));
+        }
+
+        let memory = Guards::memory_usage();
+        if memory.pressure == MemoryPressure::Hard {
+            not_ready_reasons.push("memory hard limit reached, writes are rejected".to_string());
+        }
+
+        let (agents_in_flight, tasks_in_flight, pending_webhook_deliveries) = with_state(|s| {
+            let in_flight = s.agents.values().filter(|a| matches!(a.status, AgentStatus::Active)).count();
+            let tasks: u32 = s.tasks_in_flight.values().sum();
+            let pending = s
+                .webhook_deliveries
+                .values()
+                .flatten()
+                .filter(|d| d.status == DeliveryStatus::Pending)
+                .count();
+            (in_flight as u32, tasks, pending as u32)
+        });
+
+        DetailedHealth {
+            live: true,
+            ready: not_ready_reasons.is_empty(),
+            not_ready_reasons,
+            configured: SettingsService::is_configured(),
+            basic: BindingService::get_health(),
+            dependencies,
+            timers_alive,
+            last_heartbeat,
+            agents_in_flight,
+            tasks_in_flight,
+            tasks_queued: WorkflowService::queued_steps().values().sum(),
+            running_workflows: WorkflowService::count_running(),
+            pending_webhook_deliveries,
+            stable_memory_bytes: memory.stable_bytes,
+            heap_memory_bytes: memory.heap_bytes,
+            memory_pressure: memory.pressure,
+            memory_limits: memory.limits,
+            cycles_balance,
+            llm_incident: OutageService::incident(),
+        }
+    }
+
+}
@@ -0,0 +1,339 @@
+use crate::domain::api_version::ApiError;
+use crate::infra::{Guards, Metrics};
+use crate::services::agent_factory::{AgentFactory, AgentTask, TaskPriority};
+use crate::services::api_keys::ApiKeyService;
+use crate::services::delegation::{AccessScope, DelegationService};
+use crate::services::dfinity_llm::{ConversationSettings, LlmError, MessageRole, QuantizedModel};
+use crate::services::{llm_service, with_state};
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+const AGENT_MODEL_PREFIX: &str = "agent:";
+const MAX_REQUEST_BYTES: usize = 256 * 1024;
+
+/// Request as delivered by the HTTP gateway protocol
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub upgrade: Option<bool>, // Ask the gateway to replay the request as an update
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<ChatCompletionMessage>,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+    stop: Option<StopSequences>,
+    #[serde(default)]
+    stream: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionMessage {
+    role: String,
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// OpenAI-compatible `/v1/models` and `/v1/chat/completions` over the canister
+/// HTTP interface, authenticated with `Authorization: Bearer <api key>`.
+/// Models are the DFINITY LLM models plus `agent:{agent_id}`; /v1/models lists
+/// the principal's own agents, and delegated agents can be called by id too.
+pub struct HttpGatewayService;
+
+impl HttpGatewayService {
+    pub fn handle_query(request: HttpRequest) -> HttpResponse {
+        match (request.method.to_uppercase().as_str(), Self::path(&request.url)) {
+            ("OPTIONS", _) => Self::response(204, Vec::new()),
+            ("GET", "/v1/models") => match Self::authenticate(&request, false) {
+                Ok(principal) => Self::json(200, Self::models(principal)),
+                Err(response) => response,
+            },
+            ("POST", "/v1/chat/completions") => HttpResponse {
+                upgrade: Some(true),
+                ..Self::response(200, Vec::new())
+            },
+            _ => Self::error(404, "invalid_request_error", "Unknown endpoint"),
+        }
+    }
+
+    pub async fn handle_update(request: HttpRequest) -> HttpResponse {
+        match (request.method.to_uppercase().as_str(), Self::path(&request.url)) {
+            ("POST", "/v1/chat/completions") => {
+                let principal = match Self::authenticate(&request, true) {
+                    Ok(principal) => principal,
+                    Err(response) => return response,
+                };
+                Metrics::increment_counter("gateway_chat_completions_total");
+                Self::chat_completion(principal, &request.body).await
+            }
+            _ => Self::error(404, "invalid_request_error", "Unknown endpoint"),
+        }
+    }
+
+    async fn chat_completion(principal: Principal, body: &[u8]) -> HttpResponse {
+        if body.len() > MAX_REQUEST_BYTES {
+            return Self::error(413, "invalid_request_error", "Request body too large");
+        }
+        let request: ChatCompletionRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => return Self::error(400, "invalid_request_error", &format!("Invalid request body: {}", e)),
+        };
+        if request.stream {
+            return Self::error(400, "invalid_request_error", "Streaming is not supported; set stream to false");
+        }
+        // Nothing downstream takes sampling parameters, so accepting it would silently ignore it
+        if request.temperature.is_some() {
+            return Self::error(400, "invalid_request_error", "temperature is not supported; omit it");
+        }
+        let mut messages = Vec::with_capacity(request.messages.len());
+        for message in &request.messages {
+            let role = match message.role.as_str() {
+                "system" | "developer" => MessageRole::System,
+                "user" => MessageRole::User,
+                "assistant" => MessageRole::Assistant,
+                other => return Self::error(400, "invalid_request_error", &format!("Unsupported role {}", other)),
+            };
+            if let Err(e) = Guards::validate_prompt_length(&message.content) {
+                return Self::error(400, "invalid_request_error", &e);
+            }
+            messages.push((role, message.content.clone()));
+        }
+
+        let completion = if let Some(agent_id) = request.model.strip_prefix(AGENT_MODEL_PREFIX) {
+            Self::agent_completion(principal, agent_id, &messages).await
+        } else if let Some(model) = QuantizedModel::from_model_id(&request.model) {
+            let settings = ConversationSettings {
+                system_prompt: None,
+                temperature: None,
+                max_tokens: request.max_tokens,
+                stop_sequences: match request.stop {
+                    Some(StopSequences::One(stop)) => vec![stop],
+                    Some(StopSequences::Many(stops)) => stops,
+                    None => Vec::new(),
+                },
+            };
+            llm_service()
+                .complete_stateless(principal, model, messages, settings)
+                .await
+                .map(|(content, usage)| (content, usage.input_tokens, usage.output_tokens))
+                .map_err(Self::llm_error)
+        } else {
+            Err(Self::error(404, "model_not_found", &format!("Model {} does not exist", request.model)))
+        };
+
+        match completion {
+            Ok((content, prompt_tokens, completion_tokens)) => {
+                let now = time();
+                Self::json(200, json!({
+                    "id": format!("chatcmpl-{}", now),
+                    "object": "chat.completion",
+                    "created": now / 1_000_000_000,
+                    "model": request.model,
+                    "choices": [{
+                        "index": 0,
+                        "message": { "role": "assistant", "content": content },
+                        "finish_reason": "stop",
+                    }],
+                    "usage": {
+                        "prompt_tokens": prompt_tokens,
+                        "completion_tokens": completion_tokens,
+                        "total_tokens": prompt_tokens + completion_tokens,
+                    },
+                }))
+            }
+            Err(response) => response,
+        }
+    }
+
+    /// Run the last user message as a task on the agent. Earlier turns are
+    /// passed along in the task context.
+    async fn agent_completion(
+        principal: Principal,
+        agent_id: &str,
+        messages: &[(MessageRole, String)],
+    ) -> Result<(String, u64, u64), HttpResponse> {
+        match DelegationService::authorize(agent_id, &principal.to_text(), AccessScope::Execute) {
+            Ok(_) => {}
+            Err(ApiError::NotFound { message }) => return Err(Self::error(404, "invalid_request_error", &message)),
+            Err(e) => return Err(Self::error(403, "permission_denied", e.message())),
+        }
+        let Some(last_user) = messages.iter().rposition(|(role, _)| matches!(role, MessageRole::User)) else {
+            return Err(Self::error(400, "invalid_request_error", "At least one user message is required"));
+        };
+        Guards::rate_limit_check_for(principal).map_err(|e| Self::error(429, "rate_limit_exceeded", e.message()))?;
+
+        let history = messages[..last_user]
+            .iter()
+            .map(|(role, content)| format!("{:?}: {}", role, content))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut context = HashMap::new();
+        if !history.is_empty() {
+            context.insert("conversation".to_string(), history);
+        }
+        let task = AgentTask {
+            task_id: AgentFactory::next_task_id(),
+            description: messages[last_user].1.clone(),
+            priority: TaskPriority::Normal,
+            deadline: None,
+            context,
+        };
+
+        let result = AgentFactory::execute_task_now(agent_id, task).await.map_err(|e| match &e {
+            ApiError::Unavailable { retry_after_secs, .. } => {
+                let mut response = Self::error(503, "service_unavailable", e.message());
+                if let Some(secs) = retry_after_secs {
+                    response.headers.push(("Retry-After".to_string(), secs.to_string()));
+                }
+                response
+            }
+            ApiError::QuotaExceeded { .. } => Self::error(429, "insufficient_quota", e.message()),
+            _ => Self::error(500, "server_error", e.message()),
+        })?;
+        Guards::record_token_usage_for(principal, result.tokens_used);
+        if !result.success {
+            let message = result.error_message.unwrap_or_else(|| "Task failed".to_string());
+            return Err(Self::error(500, "server_error", &message));
+        }
+        let prompt_tokens = (messages[last_user].1.len() / 4) as u64;
+        Ok((result.result, prompt_tokens, result.tokens_used.saturating_sub(prompt_tokens)))
+    }
+
+    fn models(principal: Principal) -> serde_json::Value {
+        let mut data: Vec<serde_json::Value> = llm_service()
+            .get_available_models()
+            .iter()
+            .map(|model| json!({ "id": model.model_id(), "object": "model", "created": 0, "owned_by": "dfinity" }))
+            .collect();
+        let owner = principal.to_text();
+        data.extend(with_state(|state| {
+            state
+                .agents
+                .values()
+                .filter(|agent| agent.user_id == owner)
+                .map(|agent| {
+                    json!({
+                        "id": format!("{}{}", AGENT_MODEL_PREFIX, agent.agent_id),
+                        "object": "model",
+                        "created": agent.created_at / 1_000_000_000,
+                        "owned_by": owner,
+                    })
+                })
+                .collect::<Vec<_>>()
+        }));
+        json!({ "object": "list", "data": data })
+    }
+
+    fn authenticate(request: &HttpRequest, touch: bool) -> Result<Principal, HttpResponse> {
+        let secret = request
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+            .and_then(|(_, value)| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        let Some(secret) = secret else {
+            return Err(Self::error(401, "invalid_api_key", "Missing bearer API key"));
+        };
+        ApiKeyService::resolve(secret, touch).ok_or_else(|| Self::error(401, "invalid_api_key", "Invalid API key"))
+    }
+
+    fn llm_error(error: LlmError) -> HttpResponse {
+        match error {
+            LlmError::InvalidRequest { message } => Self::error(400, "invalid_request_error", &message),
+            LlmError::RateLimitExceeded { .. } => Self::error(429, "rate_limit_exceeded", "Daily token limit reached"),
+            LlmError::QuotaExceeded => Self::error(429, "insufficient_quota", "Monthly token quota exceeded"),
+            LlmError::AuthenticationFailed => Self::error(401, "invalid_api_key", "Authentication failed"),
+            LlmError::ModelUnavailable { model } => {
+                Self::error(503, "model_unavailable", &format!("Model {} is unavailable", model.model_id()))
+            }
+            LlmError::ServiceUnavailable { retry_after } => {
+                let mut response = Self::error(503, "service_unavailable", "LLM service unavailable");
+                response.headers.push(("Retry-After".to_string(), retry_after.to_string()));
+                response
+            }
+            LlmError::ContentFiltered => Self::error(400, "content_filter", "Content was filtered"),
+            LlmError::InternalError { message } => Self::error(500, "server_error", &message),
+            LlmError::ContextTooLong { prompt_tokens, max_tokens, ctx_window } => Self::error(
+                400,
+                "context_length_exceeded",
+                &format!(
+                    "This model's maximum context length is {} tokens; the request needs {} ({} in the messages, {} for the completion)",
+                    ctx_window,
+                    prompt_tokens + max_tokens as u64,
+                    prompt_tokens,
+                    max_tokens
+                ),
+            ),
+        }
+    }
+
+    fn path(url: &str) -> &str {
+        url.split(['?', '#']).next().unwrap_or(url).trim_end_matches('/')
+    }
+
+    fn error(status_code: u16, code: &str, message: &str) -> HttpResponse {
+        let kind = match status_code {
+            401 => "authentication_error",
+            403 => "permission_error",
+            429 => "rate_limit_error",
+            500.. => "server_error",
+            _ => "invalid_request_error",
+        };
+        Self::json(status_code, json!({ "error": { "message": message, "type": kind, "code": code } }))
+    }
+
+    fn json(status_code: u16, body: serde_json::Value) -> HttpResponse {
+        let mut response = Self::response(status_code, body.to_string().into_bytes());
+        response.headers.push(("Content-Type".to_string(), "application/json".to_string()));
+        response
+    }
+
+    fn response(status_code: u16, body: Vec<u8>) -> HttpResponse {
+        HttpResponse {
+            status_code,
+            headers: vec![
+                ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+                ("Access-Control-Allow-Headers".to_string(), "Authorization, Content-Type".to_string()),
+                ("Access-Control-Allow-Methods".to_string(), "GET, POST, OPTIONS".to_string()),
+            ],
+            body,
+            upgrade: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chat_completion_request() {
+        let body = br#"{"model":"llama3.1:8b","messages":[{"role":"user","content":"hi"}],"stop":"\n"}"#;
+        let request: ChatCompletionRequest = serde_json::from_slice(body).unwrap();
+        assert_eq!(request.model, "llama3.1:8b");
+        assert!(matches!(request.stop, Some(StopSequences::One(ref s)) if s == "\n"));
+        assert!(!request.stream);
+        assert_eq!(HttpGatewayService::path("/v1/chat/completions/?x=1"), "/v1/chat/completions");
+    }
+}
@@ -1,19 +1,68 @@
 use crate::domain::*;
-use ic_cdk::api::time;
-use ic_llm::Model;
+use crate::infra::clock::time;
+use crate::infra::Lane;
+use crate::services::batching::BatchingService;
+use crate::services::context_window::ContextWindow;
+use crate::services::dfinity_llm::QuantizedModel;
+use crate::services::seed::Seed;
+use crate::services::ModelPoolService;
+
+/// Circuit breaker key for the DFINITY LLM canister
+pub const LLM_TARGET: &str = "llm";
 
 pub struct InferenceService;
 
 impl InferenceService {
-        pub async fn process_inference(request: InferenceRequest) -> Result<InferenceResponse, String> {
-        let start_time = time();
+    /// LLM failures surface as errors; while the LLM is unreachable they
+    /// carry a retry hint
+    pub async fn process_inference(request: InferenceRequest) -> Result<InferenceResponse, String> {
+        Self::run_inference(request, ic_cdk::caller(), Lane::Standard).await
+    }
 
-        // Call the DFINITY LLM canister directly for real AI responses
-        let generated_text = Self::call_dfinity_llm(&request.prompt, &request.decode_params).await
-            .unwrap_or_else(|_| "I'm here to help you with your requests and provide assistance.".to_string());
+    /// process_inference scheduled on behalf of `principal` in `lane`
+    pub async fn process_inference_for(
+        request: InferenceRequest,
+        principal: candid::Principal,
+        lane: Lane,
+    ) -> Result<InferenceResponse, String> {
+        Self::run_inference(request, principal, lane).await
+    }
+
+    async fn run_inference(
+        request: InferenceRequest,
+        principal: candid::Principal,
+        lane: Lane,
+    ) -> Result<InferenceResponse, String> {
+        let start_time = time();
+        request.decode_params.check_supported()?;
+        let decode_params = request.decode_params.resolved();
+        let model_id = ModelPoolService::route(request.model_id.as_deref())?;
+        // A prompt that does not fit is the caller's error, so it never falls back
+        let prompt = ContextWindow::fit_prompt(model_id.as_deref(), &request.prompt, decode_params.max_tokens)
+            .map_err(|e| e.to_string())?;
+
+        let seed_key = request
+            .seed
+            .map(|seed| Seed::output_key(&principal, seed, model_id.as_deref(), &prompt, decode_params.max_tokens));
+        let recorded = seed_key.as_deref().and_then(Seed::recorded_output);
+        let replayed = recorded.is_some();
+        let generated_text = match recorded {
+            Some(text) => text,
+            None => {
+                // Call the DFINITY LLM canister directly for real AI responses
+                let text = Self::call_dfinity_llm(&prompt, principal, lane).await?;
+                if let Some(key) = seed_key {
+                    Seed::record_output(key, &text);
+                }
+                text
+            }
+        };
 
         let tokens = Self::tokenize_response(&generated_text);
-        let inference_time_ms = time() - start_time;
+        let inference_time_ms = (time() - start_time) / 1_000_000;
+        if let Some(model_id) = model_id.as_ref().filter(|_| !replayed) {
+            ModelPoolService::record_usage(model_id, tokens.len() as u64, inference_time_ms);
+        }
 
         // Simple metrics for now
         let cache_hits = 1;
@@ -25,6 +74,11 @@ impl InferenceService {
             inference_time_ms,
             cache_hits,
             cache_misses,
+            effective_seed: request.seed,
+            replayed,
+            decode_params,
+            model_id,
+            segments: request.structured_output.unwrap_or(false).then(|| parse_segments(&generated_text)),
         })
     }
 
@@ -65,8 +119,12 @@ impl InferenceService {
         words
     }
 
-    /// Call DFINITY LLM canister directly for real AI responses
-    async fn call_dfinity_llm(prompt: &str, _decode_params: &DecodeParams) -> Result<String, String> {
+    /// Call DFINITY LLM canister for real AI responses, batched with identical prompts
+    async fn call_dfinity_llm(
+        prompt: &str,
+        principal: candid::Principal,
+        lane: Lane,
+    ) -> Result<String, String> {
         // Create chat messages for the LLM
         let messages = vec![
             ic_llm::ChatMessage::User {
@@ -74,15 +132,10 @@ impl InferenceService {
             }
         ];
 
-        // Build the chat request with Llama 3.1 8B model
-        let response = ic_llm::chat(Model::Llama3_1_8B)
-            .with_messages(messages)
-            .send()
-            .await;
-
-        // Extract the content from the assistant message
-        Ok(response.message.content.unwrap_or_else(|| {
-            "I'm here to help you with your questions and requests. Please ask me anything!".to_string()
-        }))
+        // Llama 3.1 8B answers inference requests
+        let content = BatchingService::chat(&QuantizedModel::Llama3_1_8B, messages, principal, lane).await?;
+        Some(content)
+            .filter(|content| !content.trim().is_empty())
+            .ok_or_else(|| "LLM returned an empty response".to_string())
     }
 }
\ No newline at end of file
@@ -1,4 +1,7 @@
 use crate::domain::instruction::*;
+use crate::services::calibration::{Calibration, CalibrationService};
+use crate::services::code_sandbox::CODE_EXEC_TOOL;
+use crate::services::sla::SlaService;
 
 /// Service for analyzing user instructions and generating agent configurations
 pub struct InstructionAnalyzer;
@@ -11,8 +14,11 @@ impl InstructionAnalyzer {
         let agent_configuration = Self::generate_agent_configuration(&instruction, &extracted_capabilities)?;
         let coordination_requirements = Self::analyze_coordination_needs(&instruction, &extracted_capabilities)?;
         let estimated_complexity = Self::estimate_complexity(&instruction, &extracted_capabilities);
-        let estimated_duration = Self::estimate_duration(&instruction, &extracted_capabilities);
-        let confidence_score = Self::calculate_confidence(&instruction, &extracted_capabilities);
+        let calibration = CalibrationService::current();
+        let estimated_duration =
+            SlaService::historical_estimate(&estimated_complexity, &agent_configuration.agent_type)
+                .unwrap_or_else(|| Self::estimate_duration(&extracted_capabilities, &calibration));
+        let confidence_score = calibration.confidence(Self::uncalibrated_confidence(&instruction, &extracted_capabilities));
 
         Ok(AnalyzedInstruction {
             original_instruction: instruction,
@@ -38,7 +44,11 @@ impl InstructionAnalyzer {
                 description: "Generate code in various programming languages".to_string(),
                 category: CapabilityCategory::CodeGeneration,
                 priority: CapabilityPriority::Essential,
-                required_tools: vec!["code_editor".to_string(), "syntax_checker".to_string()],
+                required_tools: vec![
+                    "code_editor".to_string(),
+                    "syntax_checker".to_string(),
+                    CODE_EXEC_TOOL.to_string(),
+                ],
                 estimated_tokens: 2048,
             });
         }
@@ -207,6 +217,10 @@ impl InstructionAnalyzer {
         let memory_configuration = Self::generate_memory_config(instruction);
         let tool_access = Self::determine_tool_access(capabilities);
         let safety_constraints = Self::generate_safety_constraints(instruction);
+        let self_critique = matches!(
+            instruction.preferences.as_ref().map(|p| &p.safety_level),
+            Some(SafetyLevel::Strict)
+        );
 
         Ok(AgentConfiguration {
             agent_type,
@@ -217,6 +231,9 @@ impl InstructionAnalyzer {
             memory_configuration,
             tool_access,
             safety_constraints,
+            self_critique,
+            learn_from_feedback: false,
+            self_improvement: false,
         })
     }
 
@@ -241,6 +258,16 @@ impl InstructionAnalyzer {
             CoordinationType::Collaborative
         };
 
+        let aggregation = if Self::contains_keywords(&text, &["vote", "consensus", "agree"]) {
+            AggregationStrategy::MajorityVote
+        } else if Self::contains_keywords(&text, &["best answer", "best of", "pick the best"]) {
+            AggregationStrategy::BestOfN
+        } else if matches!(coordination_type, CoordinationType::Sequential) {
+            AggregationStrategy::Concatenate
+        } else {
+            AggregationStrategy::Synthesize
+        };
+
         let agent_count = if requires_coordination {
             capabilities.len().max(2) as u32
         } else {
@@ -253,6 +280,7 @@ impl InstructionAnalyzer {
             agent_count,
             communication_protocol: CommunicationProtocol::Direct,
             task_distribution: TaskDistributionStrategy::CapabilityBased,
+            aggregation,
         })
     }
 
@@ -271,10 +299,10 @@ impl InstructionAnalyzer {
         }
     }
 
-    /// Estimate task duration
-    fn estimate_duration(_instruction: &UserInstruction, capabilities: &[Capability]) -> DurationEstimate {
-        let base_tokens: u32 = capabilities.iter().map(|c| c.estimated_tokens).sum();
-        let base_seconds = (base_tokens as f64 / 100.0).max(30.0) as u64; // Rough estimate
+    /// Estimate task duration, scaled by how long tasks have actually taken.
+    /// Used until the complexity level and agent type have their own history.
+    fn estimate_duration(capabilities: &[Capability], calibration: &Calibration) -> DurationEstimate {
+        let base_seconds = calibration.duration_seconds(Self::uncalibrated_duration_seconds(capabilities));
 
         DurationEstimate {
             min_duration_seconds: base_seconds / 2,
@@ -284,8 +312,14 @@ impl InstructionAnalyzer {
         }
     }
 
-    /// Calculate confidence score for analysis
-    fn calculate_confidence(instruction: &UserInstruction, capabilities: &[Capability]) -> f32 {
+    /// Heuristic expected duration before calibration
+    pub fn uncalibrated_duration_seconds(capabilities: &[Capability]) -> u64 {
+        let base_tokens: u32 = capabilities.iter().map(|c| c.estimated_tokens).sum();
+        (base_tokens as f64 / 100.0).max(30.0) as u64 // Rough estimate
+    }
+
+    /// Heuristic confidence score before calibration
+    pub fn uncalibrated_confidence(instruction: &UserInstruction, capabilities: &[Capability]) -> f32 {
         let mut confidence: f32 = 0.8; // Base confidence
 
         // Increase confidence for specific keywords
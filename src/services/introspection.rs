@@ -0,0 +1,210 @@
+use crate::domain::instruction::{AgentType, Capability, MemoryConfiguration};
+use crate::services::agent_factory::{AgentFactory, AgentStatus, AutonomousAgent};
+use crate::services::budget::{AgentBudget, BudgetService, BudgetStatus};
+use crate::services::canister_tools::{CanisterToolService, ToolRegistrationStatus};
+use crate::services::code_sandbox::CODE_EXEC_TOOL;
+use crate::services::gc::{GcConfig, GcService};
+use crate::services::knowledge::{KnowledgeService, KnowledgeStats};
+use crate::services::self_improvement::REVIEW_INTERVAL;
+use crate::services::skills::AttachedSkill;
+use crate::services::tools::{ToolPermission, ToolService};
+use crate::services::{with_state, ModelPoolService};
+use candid::CandidType;
+use crate::infra::clock::time;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq, CandidType)]
+pub enum ToolKind {
+    CodeExecutor,
+    Canister,
+}
+
+/// A tool on the agent's allowlist that has an executor on this canister
+#[derive(Debug, Clone, CandidType)]
+pub struct AvailableTool {
+    pub name: String,
+    pub description: String,
+    pub kind: ToolKind,
+    pub requires_approval: bool, // Calls wait for the owner under a Strict safety level
+}
+
+/// The model the agent's tasks are routed to
+#[derive(Debug, Clone, CandidType)]
+pub struct AgentModel {
+    pub model_id: String,
+    pub version: String,
+    pub primary: bool, // The bound model rather than a pool model
+    pub family: Option<String>,
+    pub arch: Option<String>,
+    pub ctx_window: Option<u32>,
+    pub license: Option<String>,
+    pub chunks_loaded: u32,
+    pub total_chunks: u32,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct AgentMemorySummary {
+    pub entries: u32,
+    pub bytes: u64,
+    pub configuration: MemoryConfiguration,
+}
+
+/// Recurring or pending work the canister runs for the agent
+#[derive(Debug, Clone, PartialEq, CandidType)]
+pub struct AgentSchedule {
+    pub name: String,
+    pub interval_seconds: Option<u64>,
+    pub next_at: Option<u64>, // None when the timer's phase is not tracked
+}
+
+/// What an agent can do, in one structure for UIs to render
+#[derive(Debug, Clone, CandidType)]
+pub struct AgentDescription {
+    pub agent_id: String,
+    pub agent_type: AgentType,
+    pub status: AgentStatus,
+    pub capabilities: Vec<Capability>,
+    pub tools: Vec<AvailableTool>,
+    pub skills: Vec<AttachedSkill>,
+    pub model: Option<AgentModel>, // None while no model is bound
+    pub memory: AgentMemorySummary,
+    pub knowledge: KnowledgeStats,
+    pub schedules: Vec<AgentSchedule>,
+    pub budget: BudgetStatus,
+    pub described_at: u64,
+}
+
+/// Read-only view assembled from the services that own each part. Tools are
+/// listed after permission checks: only allowlisted tools with an executor
+/// here appear, and the model is the one routing would pick for a task now.
+pub struct IntrospectionService;
+
+impl IntrospectionService {
+    pub fn describe(agent_id: &str) -> Result<AgentDescription, String> {
+        let agent = AgentFactory::find_agent(agent_id)?;
+        let now = time();
+        let configuration = &agent.analysis.agent_configuration;
+        Ok(AgentDescription {
+            agent_id: agent.agent_id.clone(),
+            agent_type: configuration.agent_type.clone(),
+            status: agent.status.clone(),
+            capabilities: agent.analysis.extracted_capabilities.clone(),
+            tools: Self::tools(&agent),
+            skills: agent.skills.clone(),
+            model: Self::model(&agent),
+            memory: AgentMemorySummary {
+                entries: agent.memory.len() as u32,
+                bytes: agent.memory.iter().map(|(key, value)| (key.len() + value.len()) as u64).sum(),
+                configuration: configuration.memory_configuration.clone(),
+            },
+            knowledge: KnowledgeService::stats(Some(agent_id)),
+            schedules: schedules(configuration.self_improvement, &agent.budget, agent.idle_flagged_at, &GcService::config(), now),
+            budget: BudgetService::status(&agent, now),
+            described_at: now,
+        })
+    }
+
+    fn tools(agent: &AutonomousAgent) -> Vec<AvailableTool> {
+        agent
+            .analysis
+            .agent_configuration
+            .tool_access
+            .iter()
+            .filter_map(|name| {
+                let requires_approval = match ToolService::permission(agent, name) {
+                    ToolPermission::Allowed => false,
+                    ToolPermission::RequiresApproval => true,
+                    ToolPermission::Denied(_) => return None,
+                };
+                let (kind, description) = if name == CODE_EXEC_TOOL {
+                    (ToolKind::CodeExecutor, "Runs a sandboxed script and returns its output".to_string())
+                } else {
+                    let tool = CanisterToolService::get(name).ok()?;
+                    if tool.status != ToolRegistrationStatus::Approved {
+                        return None;
+                    }
+                    (ToolKind::Canister, tool.description)
+                };
+                Some(AvailableTool { name: name.clone(), description, kind, requires_approval })
+            })
+            .collect()
+    }
+
+    fn model(agent: &AutonomousAgent) -> Option<AgentModel> {
+        let model_id = ModelPoolService::route_preferring(None, &agent.analysis.model_requirements.recommended_models)
+            .ok()
+            .flatten()?;
+        with_state(|s| {
+            let binding = ModelPoolService::binding(s, &model_id)?;
+            let meta = ModelPoolService::meta(s, Some(&model_id));
+            Some(AgentModel {
+                model_id: model_id.clone(),
+                version: binding.version.clone(),
+                primary: s.binding.as_ref().is_some_and(|b| b.model_id == model_id),
+                family: meta.map(|m| m.family.clone()),
+                arch: meta.map(|m| m.arch.clone()),
+                ctx_window: meta.map(|m| m.ctx_window),
+                license: meta.map(|m| m.license.clone()),
+                chunks_loaded: binding.chunks_loaded,
+                total_chunks: binding.total_chunks,
+            })
+        })
+    }
+}
+
+/// Self-improvement reviews, the daily budget reset and a pending idle archive
+fn schedules(
+    self_improvement: bool,
+    budget: &AgentBudget,
+    idle_flagged_at: Option<u64>,
+    gc: &GcConfig,
+    now: u64,
+) -> Vec<AgentSchedule> {
+    let mut schedules = Vec::new();
+    if self_improvement {
+        schedules.push(AgentSchedule {
+            name: "self_improvement_review".to_string(),
+            interval_seconds: Some(REVIEW_INTERVAL.as_secs()),
+            next_at: None,
+        });
+    }
+    if budget.tokens_per_day.is_some() || budget.tasks_per_day.is_some() {
+        schedules.push(AgentSchedule {
+            name: "budget_reset".to_string(),
+            interval_seconds: Some(SECONDS_PER_DAY),
+            next_at: Some((now / NANOS_PER_DAY + 1) * NANOS_PER_DAY),
+        });
+    }
+    if let Some(flagged_at) = idle_flagged_at.filter(|_| gc.enabled) {
+        schedules.push(AgentSchedule {
+            name: "idle_archive".to_string(),
+            interval_seconds: None,
+            next_at: Some(flagged_at + gc.grace_period_days as u64 * NANOS_PER_DAY),
+        });
+    }
+    schedules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedules_follow_the_agent_settings() {
+        let gc = GcConfig::default();
+        assert!(schedules(false, &AgentBudget::default(), None, &gc, 0).is_empty());
+
+        let budget = AgentBudget { tasks_per_day: Some(5), ..AgentBudget::default() };
+        let now = 3 * NANOS_PER_DAY + 7;
+        let found = schedules(true, &budget, Some(NANOS_PER_DAY), &gc, now);
+        let names: Vec<&str> = found.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["self_improvement_review", "budget_reset", "idle_archive"]);
+        assert_eq!(found[1].next_at, Some(4 * NANOS_PER_DAY));
+        assert_eq!(found[2].next_at, Some((1 + gc.grace_period_days as u64) * NANOS_PER_DAY));
+
+        let disabled = GcConfig { enabled: false, ..gc };
+        assert_eq!(schedules(false, &budget, Some(NANOS_PER_DAY), &disabled, now).len(), 1);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::infra::stable::{memory, Cbor, Memory, CALL_JOURNAL_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::billing::BillingService;
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::Duration;
+
+thread_local! {
+    static JOURNAL: RefCell<StableBTreeMap<u64, Cbor<JournalEntry>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CALL_JOURNAL_MEMORY_ID)));
+}
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// No reply takes this long, so an entry still pending was cut off by a trap
+const ORPHAN_AFTER_NS: u64 = 30 * 60 * 1_000_000_000;
+// Resolved entries kept for inspection; unresolved ones are never dropped
+const MAX_RETAINED_ENTRIES: u64 = 5_000;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, CandidType)]
+pub enum CallKind {
+    Llm,       // Billable: an orphan is charged to its principal
+    ModelRepo, // Read-only: an orphan is safe to fetch again
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, CandidType)]
+pub enum JournalState {
+    Pending,     // Intent recorded, no completion yet
+    Completed,
+    Failed,
+    Compensated, // Orphaned and its usage accrued after the fact
+    Abandoned,   // Orphaned with nothing to compensate
+}
+
+/// What is about to be called, written before the call is made
+#[derive(Debug, Clone)]
+pub struct CallIntent {
+    pub kind: CallKind,
+    pub target: String,
+    pub method: String,
+    pub principal: Option<Principal>,
+    pub billable_tokens: u64, // Input tokens charged if the call is compensated
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub kind: CallKind,
+    pub target: String,
+    pub method: String,
+    pub principal: Option<Principal>,
+    pub billable_tokens: u64,
+    pub started_at: u64,
+    pub state: JournalState,
+    pub resolved_at: Option<u64>,
+    pub note: Option<String>, // Error of a failed call, or who resolved an orphan and how
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct JournalPage {
+    pub entries: Vec<JournalEntry>,
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct JournalRecovery {
+    pub compensated: u32,
+    pub abandoned: u32,
+    pub still_pending: u32, // Too recent to be orphans
+}
+
+/// Journal of outbound LLM and model repo calls. An intent entry is
+/// committed with the call and completed in the message that handles the
+/// reply, so a trap after the reply rolls the completion back with the rest
+/// of that message's state and leaves the entry pending. Pending entries
+/// older than any reply could be are orphans: LLM calls are compensated by
+/// accruing their input tokens to the caller's usage, repo reads are
+/// abandoned since the next request fetches again. Calls cannot be in flight
+/// across an upgrade, so every pending entry is an orphan after one.
+pub struct JournalService;
+
+impl JournalService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(SWEEP_INTERVAL, || {
+            Self::recover(false);
+        });
+    }
+
+    /// Run `call` between an intent and a completion record
+    pub async fn record<T>(intent: CallIntent, call: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+        let id = Self::begin(intent);
+        let result = call.await;
+        Self::finish(id, result.as_ref().err());
+        result
+    }
+
+    fn begin(intent: CallIntent) -> u64 {
+        JOURNAL.with(|j| {
+            let mut journal = j.borrow_mut();
+            let id = journal.last_key_value().map_or(0, |(id, _)| id + 1);
+            let entry = JournalEntry {
+                id,
+                kind: intent.kind,
+                target: intent.target,
+                method: intent.method,
+                principal: intent.principal,
+                billable_tokens: intent.billable_tokens,
+                started_at: time(),
+                state: JournalState::Pending,
+                resolved_at: None,
+                note: None,
+            };
+            journal.insert(id, Cbor(entry));
+            id
+        })
+    }
+
+    fn finish(id: u64, error: Option<&String>) {
+        let (state, note) = match error {
+            None => (JournalState::Completed, None),
+            Some(e) => (JournalState::Failed, Some(e.clone())),
+        };
+        Self::resolve(id, state, note);
+        Self::prune();
+    }
+
+    /// Settle orphaned entries; `after_upgrade` treats every pending entry as one
+    pub fn recover(after_upgrade: bool) -> JournalRecovery {
+        let now = time();
+        let pending: Vec<JournalEntry> = JOURNAL.with(|j| {
+            j.borrow().iter().filter(|(_, e)| e.0.state == JournalState::Pending).map(|(_, e)| e.0).collect()
+        });
+        let mut recovery = JournalRecovery { compensated: 0, abandoned: 0, still_pending: 0 };
+        for entry in pending {
+            if !after_upgrade && now.saturating_sub(entry.started_at) < ORPHAN_AFTER_NS {
+                recovery.still_pending += 1;
+                continue;
+            }
+            match Self::compensate(&entry, now) {
+                JournalState::Compensated => recovery.compensated += 1,
+                _ => recovery.abandoned += 1,
+            }
+        }
+        recovery
+    }
+
+    fn compensate(entry: &JournalEntry, now: u64) -> JournalState {
+        let state = match (entry.kind, entry.principal) {
+            (CallKind::Llm, Some(principal)) => {
+                BillingService::accrue(&principal.to_text(), entry.billable_tokens, 0, now);
+                Metrics::increment_counter("journal_compensated_total");
+                JournalState::Compensated
+            }
+            _ => {
+                Metrics::increment_counter("journal_abandoned_total");
+                JournalState::Abandoned
+            }
+        };
+        Self::resolve(entry.id, state, Some("orphaned: no completion was recorded".to_string()));
+        state
+    }
+
+    /// Close a pending entry by hand, e.g. after reconciling with the callee
+    pub fn resolve_manually(id: u64, state: JournalState, admin: Principal) -> Result<JournalEntry, String> {
+        if matches!(state, JournalState::Pending) {
+            return Err("An entry can only be resolved to a final state".to_string());
+        }
+        let entry = Self::get(id).ok_or_else(|| format!("Journal entry {} not found", id))?;
+        if entry.state != JournalState::Pending {
+            return Err(format!("Journal entry {} is already resolved", id));
+        }
+        Self::resolve(id, state, Some(format!("resolved by {}", admin)));
+        Self::get(id).ok_or_else(|| format!("Journal entry {} not found", id))
+    }
+
+    pub fn get(id: u64) -> Option<JournalEntry> {
+        JOURNAL.with(|j| j.borrow().get(&id).map(|e| e.0))
+    }
+
+    /// Entries after `cursor` in call order, or only the pending ones
+    pub fn list(unresolved_only: bool, cursor: Option<u64>, limit: Option<u32>) -> JournalPage {
+        let limit = limit.map_or(DEFAULT_PAGE_SIZE, |l| (l as usize).clamp(1, MAX_PAGE_SIZE));
+        let start = cursor.map_or(0, |c| c + 1);
+        let mut entries: Vec<JournalEntry> = JOURNAL.with(|j| {
+            j.borrow()
+                .range(start..)
+                .map(|(_, e)| e.0)
+                .filter(|e| !unresolved_only || e.state == JournalState::Pending)
+                .take(limit + 1)
+                .collect()
+        });
+        let next_cursor = if entries.len() > limit {
+            entries.truncate(limit);
+            entries.last().map(|e| e.id)
+        } else {
+            None
+        };
+        JournalPage { entries, next_cursor }
+    }
+
+    fn resolve(id: u64, state: JournalState, note: Option<String>) {
+        JOURNAL.with(|j| {
+            let mut journal = j.borrow_mut();
+            if let Some(Cbor(mut entry)) = journal.get(&id) {
+                entry.state = state;
+                entry.resolved_at = Some(time());
+                entry.note = note;
+                journal.insert(id, Cbor(entry));
+            }
+        });
+    }
+
+    /// Drop the oldest resolved entries beyond the retention limit, stopping
+    /// at the first unresolved one. The newest entry is always kept so ids never repeat.
+    fn prune() {
+        JOURNAL.with(|j| {
+            let mut journal = j.borrow_mut();
+            while journal.len() > MAX_RETAINED_ENTRIES {
+                match journal.first_key_value() {
+                    Some((id, entry)) if entry.0.state != JournalState::Pending => {
+                        journal.remove(&id);
+                    }
+                    _ => break,
+                }
+            }
+        });
+    }
+}
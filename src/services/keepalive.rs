@@ -0,0 +1,186 @@
+use crate::infra::stable::{memory, Cbor, Memory, KEEPALIVE_CONFIG_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::batching::BatchingService;
+use crate::services::dfinity_llm::QuantizedModel;
+use crate::services::outage::OutageService;
+use crate::services::{with_state, CacheService};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+thread_local! {
+    static CONFIG: RefCell<StableBTreeMap<u8, Cbor<KeepaliveConfig>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(KEEPALIVE_CONFIG_MEMORY_ID)));
+    static LAST_LLM_CALL: Cell<u64> = Cell::new(0);
+    static LAST_PING: Cell<u64> = Cell::new(0);
+    static PING_IN_FLIGHT: Cell<bool> = Cell::new(false);
+}
+
+const CONFIG_KEY: u8 = 0;
+const TICK_INTERVAL: Duration = Duration::from_secs(30);
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+// An LLM call this long after the previous call or ping counts as a cold start
+const COLD_AFTER_NS: u64 = 5 * 60 * NANOS_PER_SECOND;
+const MIN_INTERVAL_SECONDS: u64 = 30;
+const MAX_INTERVAL_SECONDS: u64 = 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct KeepaliveConfig {
+    pub enabled: bool,
+    pub interval_seconds: u64,   // Idle time after which a ping is sent
+    pub touch_warm_chunks: bool, // Also refresh the warm set's cache entries on each ping
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_seconds: 240,
+            touch_warm_chunks: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct KeepaliveStatus {
+    pub config: KeepaliveConfig,
+    pub last_llm_call_at: u64, // 0 until the first call since the last upgrade
+    pub last_ping_at: u64,
+    pub pings_sent: u64,
+    pub ping_failures: u64,
+    pub chunks_touched: u64,
+    pub llm_calls: u64,
+    pub cold_starts: u64,
+    pub cold_start_rate: f64,
+}
+
+/// Keeps the inference path warm while the canister is idle. Once nothing
+/// has called the LLM for `interval_seconds`, a tiny chat request goes out
+/// through the scheduler and breaker like any other call, and the warm set's
+/// cache entries are marked used so compaction leaves them uncompressed.
+/// Every LLM call made for a caller is checked against the last call or ping
+/// to measure how often callers still hit a cold start.
+pub struct KeepaliveService;
+
+impl KeepaliveService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(TICK_INTERVAL, || {
+            let config = Self::config();
+            let last_activity = Self::last_activity();
+            if !PING_IN_FLIGHT.with(|p| p.get()) && ping_due(&config, last_activity, time()) {
+                ic_cdk::spawn(Self::ping(config));
+            }
+        });
+    }
+
+    pub fn set_config(config: KeepaliveConfig) -> Result<(), String> {
+        if !(MIN_INTERVAL_SECONDS..=MAX_INTERVAL_SECONDS).contains(&config.interval_seconds) {
+            return Err(format!(
+                "interval_seconds must be between {} and {}",
+                MIN_INTERVAL_SECONDS, MAX_INTERVAL_SECONDS
+            ));
+        }
+        CONFIG.with(|c| c.borrow_mut().insert(CONFIG_KEY, Cbor(config)));
+        Ok(())
+    }
+
+    pub fn config() -> KeepaliveConfig {
+        CONFIG.with(|c| c.borrow().get(&CONFIG_KEY).map(|c| c.0)).unwrap_or_default()
+    }
+
+    /// Called for every LLM call made on behalf of a caller; pings are not counted
+    pub fn observe_llm_call() {
+        let now = time();
+        Metrics::increment_counter("llm_calls_observed_total");
+        if now.saturating_sub(Self::last_activity()) >= COLD_AFTER_NS {
+            Metrics::increment_counter("llm_cold_starts_total");
+        }
+        LAST_LLM_CALL.with(|t| t.set(now));
+        Metrics::set_gauge("llm_cold_start_rate", Self::cold_start_rate());
+    }
+
+    pub fn status() -> KeepaliveStatus {
+        KeepaliveStatus {
+            config: Self::config(),
+            last_llm_call_at: LAST_LLM_CALL.with(|t| t.get()),
+            last_ping_at: LAST_PING.with(|t| t.get()),
+            pings_sent: Metrics::get_counter("keepalive_pings_total"),
+            ping_failures: Metrics::get_counter("keepalive_ping_failures_total"),
+            chunks_touched: Metrics::get_counter("keepalive_chunks_touched_total"),
+            llm_calls: Metrics::get_counter("llm_calls_observed_total"),
+            cold_starts: Metrics::get_counter("llm_cold_starts_total"),
+            cold_start_rate: Self::cold_start_rate(),
+        }
+    }
+
+    async fn ping(config: KeepaliveConfig) {
+        let _in_flight = PingInFlight::start();
+        LAST_PING.with(|t| t.set(time()));
+        if config.touch_warm_chunks {
+            let warm = with_state(|state| state.warm_set.chunk_ids.clone());
+            let touched = CacheService::touch(&warm);
+            Metrics::add_to_counter("keepalive_chunks_touched_total", touched as u64);
+        }
+        // Pinging through an open breaker would only delay its recovery
+        if !OutageService::llm_unavailable() {
+            match BatchingService::warm_up(&QuantizedModel::Llama3_1_8B).await {
+                Ok(()) => Metrics::increment_counter("keepalive_pings_total"),
+                Err(e) => {
+                    Metrics::increment_counter("keepalive_ping_failures_total");
+                    ic_cdk::println!("Keepalive ping failed: {}", e);
+                }
+            }
+        }
+    }
+
+    fn last_activity() -> u64 {
+        LAST_LLM_CALL.with(|t| t.get()).max(LAST_PING.with(|t| t.get()))
+    }
+
+    fn cold_start_rate() -> f64 {
+        let calls = Metrics::get_counter("llm_calls_observed_total");
+        if calls == 0 {
+            return 0.0;
+        }
+        Metrics::get_counter("llm_cold_starts_total") as f64 / calls as f64
+    }
+}
+
+/// Marks a ping as in flight until dropped, including when its callback
+/// traps, so a failed ping never stops later ones
+struct PingInFlight;
+
+impl PingInFlight {
+    fn start() -> Self {
+        PING_IN_FLIGHT.with(|p| p.set(true));
+        Self
+    }
+}
+
+impl Drop for PingInFlight {
+    fn drop(&mut self) {
+        PING_IN_FLIGHT.with(|p| p.set(false));
+    }
+}
+
+fn ping_due(config: &KeepaliveConfig, last_activity: u64, now: u64) -> bool {
+    config.enabled && now.saturating_sub(last_activity) >= config.interval_seconds * NANOS_PER_SECOND
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ping_due_only_when_enabled_and_idle() {
+        let mut config = KeepaliveConfig { enabled: true, ..KeepaliveConfig::default() };
+        let interval = config.interval_seconds * NANOS_PER_SECOND;
+        assert!(!ping_due(&config, 1_000, 1_000 + interval - 1));
+        assert!(ping_due(&config, 1_000, 1_000 + interval));
+        config.enabled = false;
+        assert!(!ping_due(&config, 0, u64::MAX));
+    }
+}
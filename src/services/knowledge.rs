@@ -0,0 +1,548 @@
+use crate::infra::stable::{memory, Cbor, Memory, KNOWLEDGE_CHUNKS_MEMORY_ID, KNOWLEDGE_DOCUMENTS_MEMORY_ID};
+use crate::infra::{Guards, Metrics};
+use crate::services::embedding::EmbeddingService;
+use crate::services::with_state_mut;
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+thread_local! {
+    // "{agent_id}/{namespace}/{doc_id}"
+    static DOCUMENTS: RefCell<StableBTreeMap<String, Cbor<KnowledgeDocument>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(KNOWLEDGE_DOCUMENTS_MEMORY_ID)));
+    // "{agent_id}/{namespace}/{doc_id}/{chunk_index:06}"
+    static CHUNKS: RefCell<StableBTreeMap<String, Cbor<KnowledgeChunk>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(KNOWLEDGE_CHUNKS_MEMORY_ID)));
+}
+
+const CHUNK_CHARS: usize = 800;
+const CHUNK_OVERLAP_CHARS: usize = 100;
+const MAX_DOCUMENTS_PER_AGENT: usize = 100;
+const MAX_CHUNKS_PER_DOCUMENT: usize = 1_000;
+// Retrieval loads and tokenizes every chunk in scope, so this bounds its cost
+const MAX_CHUNKS_PER_AGENT: usize = 5_000;
+const MAX_NAMESPACE_LEN: usize = 64;
+pub const DEFAULT_TOP_K: usize = 4;
+const MAX_TOP_K: usize = 20;
+const MAX_REINDEX_DOCUMENTS_PER_CALL: usize = 10; // Keeps one call within the instruction limit
+// BM25 parameters
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+// Share of the hybrid score taken by embedding similarity when both are available
+const SEMANTIC_WEIGHT: f32 = 0.5;
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct KnowledgeDocument {
+    pub doc_id: String,
+    pub agent_id: String,
+    pub namespace: String,
+    pub title: String,
+    pub chunk_count: u32,
+    pub char_count: u64,
+    pub created_at: u64,
+    #[serde(default)]
+    pub embedding_model: Option<String>, // None when the document was stored without vectors
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KnowledgeChunk {
+    doc_id: String,
+    title: String,
+    index: u32,
+    text: String,
+    #[serde(default)]
+    embedding: Option<Vec<f32>>,
+}
+
+#[derive(Debug, Clone, Default, CandidType)]
+pub struct KnowledgeStats {
+    pub documents: u64,
+    pub chunks: u64,
+    pub embedded_chunks: u64,
+    pub stale_documents: u64, // Missing vectors or embedded with a previous backend
+    pub text_bytes: u64,
+    pub vector_bytes: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ReindexReport {
+    pub reindexed: Vec<String>,
+    pub remaining: u64, // Stale documents left for the next call
+}
+
+/// Passage returned by retrieval, citable as `[n]` in prompts
+#[derive(Debug, Clone, CandidType)]
+pub struct RetrievedPassage {
+    pub doc_id: String,
+    pub title: String,
+    pub chunk_index: u32,
+    pub score: f32,
+    pub text: String,
+}
+
+/// Per-agent document store with chunking and ranked passage retrieval
+pub struct KnowledgeService;
+
+impl KnowledgeService {
+    pub async fn ingest(agent_id: &str, namespace: &str, title: String, text: &str) -> Result<String, String> {
+        Self::validate_namespace(namespace)?;
+        Guards::check_memory_limits()?;
+        if text.trim().is_empty() {
+            return Err("Document is empty".to_string());
+        }
+        if Self::list_documents(agent_id, None).len() >= MAX_DOCUMENTS_PER_AGENT {
+            return Err(format!("Document limit reached. Maximum: {}", MAX_DOCUMENTS_PER_AGENT));
+        }
+
+        let chunks = Self::chunk_text(text, CHUNK_CHARS, CHUNK_OVERLAP_CHARS);
+        if chunks.len() > MAX_CHUNKS_PER_DOCUMENT {
+            return Err(format!("Document too large. Maximum: {} chunks", MAX_CHUNKS_PER_DOCUMENT));
+        }
+        if Self::chunk_count(agent_id) + chunks.len() > MAX_CHUNKS_PER_AGENT {
+            return Err(format!("Knowledge base full. Maximum: {} chunks per agent", MAX_CHUNKS_PER_AGENT));
+        }
+        // Without embeddings the document is still searchable lexically
+        let embedding_model = EmbeddingService::model_fingerprint();
+        let embeddings = EmbeddingService::embed_texts(chunks.clone()).await.ok();
+
+        let now = time();
+        let doc_id = with_state_mut(|state| {
+            state.knowledge_seq += 1;
+            format!("doc-{}-{}", now, state.knowledge_seq)
+        });
+        let prefix = format!("{}/{}/{}", agent_id, namespace, doc_id);
+
+        CHUNKS.with(|c| {
+            let mut store = c.borrow_mut();
+            for (index, chunk) in chunks.iter().enumerate() {
+                store.insert(
+                    format!("{}/{:06}", prefix, index),
+                    Cbor(KnowledgeChunk {
+                        doc_id: doc_id.clone(),
+                        title: title.clone(),
+                        index: index as u32,
+                        text: chunk.clone(),
+                        embedding: embeddings.as_ref().and_then(|e| e.get(index).cloned()),
+                    }),
+                );
+            }
+        });
+        DOCUMENTS.with(|d| {
+            d.borrow_mut().insert(
+                prefix,
+                Cbor(KnowledgeDocument {
+                    doc_id: doc_id.clone(),
+                    agent_id: agent_id.to_string(),
+                    namespace: namespace.to_string(),
+                    title,
+                    chunk_count: chunks.len() as u32,
+                    char_count: text.chars().count() as u64,
+                    created_at: now,
+                    embedding_model: embeddings.as_ref().map(|_| embedding_model),
+                }),
+            );
+        });
+
+        Metrics::increment_counter("knowledge_documents_ingested_total");
+        Ok(doc_id)
+    }
+
+    pub fn delete(agent_id: &str, doc_id: &str) -> Result<(), String> {
+        let document = Self::list_documents(agent_id, None)
+            .into_iter()
+            .find(|d| d.doc_id == doc_id)
+            .ok_or_else(|| format!("Document {} not found", doc_id))?;
+        let prefix = format!("{}/{}/{}", agent_id, document.namespace, doc_id);
+
+        DOCUMENTS.with(|d| d.borrow_mut().remove(&prefix));
+        CHUNKS.with(|c| {
+            let mut store = c.borrow_mut();
+            let keys = Self::keys_with_prefix(&store, &format!("{}/", prefix));
+            for key in keys {
+                store.remove(&key);
+            }
+        });
+        Ok(())
+    }
+
+    /// A document's chunks in order, for attaching it whole to a task
+    pub fn document_chunks(agent_id: &str, doc_id: &str) -> Result<(KnowledgeDocument, Vec<String>), String> {
+        let document = Self::list_documents(agent_id, None)
+            .into_iter()
+            .find(|d| d.doc_id == doc_id)
+            .ok_or_else(|| format!("Document {} not found", doc_id))?;
+        let prefix = format!("{}/{}/{}/", agent_id, document.namespace, doc_id);
+        let chunks = CHUNKS.with(|c| {
+            c.borrow()
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(_, chunk)| chunk.0.text)
+                .collect()
+        });
+        Ok((document, chunks))
+    }
+
+    /// Copy every document in `namespace` to another agent under new ids. Stored
+    /// vectors are copied as-is, so no embedding calls are needed.
+    pub fn copy_namespace(from_agent: &str, to_agent: &str, namespace: &str) -> Result<u32, String> {
+        let documents = Self::list_documents(from_agent, Some(namespace));
+        if Self::list_documents(to_agent, None).len() + documents.len() > MAX_DOCUMENTS_PER_AGENT {
+            return Err(format!("Document limit reached. Maximum: {}", MAX_DOCUMENTS_PER_AGENT));
+        }
+        let copied_chunks: usize = documents.iter().map(|d| d.chunk_count as usize).sum();
+        if Self::chunk_count(to_agent) + copied_chunks > MAX_CHUNKS_PER_AGENT {
+            return Err(format!("Knowledge base full. Maximum: {} chunks per agent", MAX_CHUNKS_PER_AGENT));
+        }
+
+        let now = time();
+        for document in &documents {
+            let doc_id = with_state_mut(|state| {
+                state.knowledge_seq += 1;
+                format!("doc-{}-{}", now, state.knowledge_seq)
+            });
+            let source_prefix = format!("{}/{}/{}/", from_agent, namespace, document.doc_id);
+            let target = format!("{}/{}/{}", to_agent, namespace, doc_id);
+
+            CHUNKS.with(|c| {
+                let mut store = c.borrow_mut();
+                let chunks: Vec<KnowledgeChunk> = store
+                    .range(source_prefix.clone()..)
+                    .take_while(|(key, _)| key.starts_with(&source_prefix))
+                    .map(|(_, chunk)| chunk.0)
+                    .collect();
+                for mut chunk in chunks {
+                    let key = format!("{}/{:06}", target, chunk.index);
+                    chunk.doc_id = doc_id.clone();
+                    store.insert(key, Cbor(chunk));
+                }
+            });
+            DOCUMENTS.with(|d| {
+                d.borrow_mut().insert(
+                    target,
+                    Cbor(KnowledgeDocument {
+                        doc_id,
+                        agent_id: to_agent.to_string(),
+                        created_at: now,
+                        ..document.clone()
+                    }),
+                )
+            });
+        }
+        Ok(documents.len() as u32)
+    }
+
+    pub fn list_documents(agent_id: &str, namespace: Option<&str>) -> Vec<KnowledgeDocument> {
+        let prefix = Self::scope_prefix(agent_id, namespace);
+        DOCUMENTS.with(|d| {
+            d.borrow()
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(_, doc)| doc.0)
+                .collect()
+        })
+    }
+
+    fn chunk_count(agent_id: &str) -> usize {
+        Self::list_documents(agent_id, None).iter().map(|d| d.chunk_count as usize).sum()
+    }
+
+    /// Re-embed documents stored without vectors or with a previous backend's.
+    /// Bounded per call; repeat until `remaining` is 0.
+    pub async fn reindex(agent_id: &str, namespace: Option<&str>) -> Result<ReindexReport, String> {
+        let model = EmbeddingService::model_fingerprint();
+        let stale: Vec<KnowledgeDocument> = Self::list_documents(agent_id, namespace)
+            .into_iter()
+            .filter(|d| d.embedding_model.as_deref() != Some(model.as_str()))
+            .collect();
+
+        let mut reindexed = Vec::new();
+        for document in stale.iter().take(MAX_REINDEX_DOCUMENTS_PER_CALL) {
+            let key = format!("{}/{}/{}", agent_id, document.namespace, document.doc_id);
+            let chunk_prefix = format!("{}/", key);
+            let chunks: Vec<(String, KnowledgeChunk)> = CHUNKS.with(|c| {
+                c.borrow()
+                    .range(chunk_prefix.clone()..)
+                    .take_while(|(k, _)| k.starts_with(&chunk_prefix))
+                    .map(|(k, chunk)| (k, chunk.0))
+                    .collect()
+            });
+            let vectors = EmbeddingService::embed_texts(chunks.iter().map(|(_, c)| c.text.clone()).collect()).await?;
+
+            // The document may have been deleted while the embeddings were computed
+            let Some(mut document) = DOCUMENTS.with(|d| d.borrow().get(&key).map(|doc| doc.0)) else {
+                continue;
+            };
+            CHUNKS.with(|c| {
+                let mut store = c.borrow_mut();
+                for ((chunk_key, mut chunk), vector) in chunks.into_iter().zip(vectors) {
+                    chunk.embedding = Some(vector);
+                    store.insert(chunk_key, Cbor(chunk));
+                }
+            });
+            document.embedding_model = Some(model.clone());
+            DOCUMENTS.with(|d| d.borrow_mut().insert(key, Cbor(document.clone())));
+            reindexed.push(document.doc_id);
+        }
+
+        Metrics::add_to_counter("knowledge_documents_reindexed_total", reindexed.len() as u64);
+        Ok(ReindexReport {
+            remaining: (stale.len() - reindexed.len()) as u64,
+            reindexed,
+        })
+    }
+
+    /// Sizes for one agent's knowledge base, or every agent's when `agent_id` is None
+    pub fn stats(agent_id: Option<&str>) -> KnowledgeStats {
+        let prefix = agent_id.map_or(String::new(), |id| Self::scope_prefix(id, None));
+        let model = EmbeddingService::model_fingerprint();
+        let mut stats = KnowledgeStats::default();
+
+        DOCUMENTS.with(|d| {
+            for (_, document) in d.borrow().range(prefix.clone()..).take_while(|(key, _)| key.starts_with(&prefix)) {
+                stats.documents += 1;
+                if document.0.embedding_model.as_deref() != Some(model.as_str()) {
+                    stats.stale_documents += 1;
+                }
+            }
+        });
+        CHUNKS.with(|c| {
+            for (_, chunk) in c.borrow().range(prefix.clone()..).take_while(|(key, _)| key.starts_with(&prefix)) {
+                stats.chunks += 1;
+                stats.text_bytes += chunk.0.text.len() as u64;
+                if let Some(vector) = &chunk.0.embedding {
+                    stats.embedded_chunks += 1;
+                    stats.vector_bytes += (vector.len() * std::mem::size_of::<f32>()) as u64;
+                }
+            }
+        });
+        stats
+    }
+
+    /// Top-k passages for `query`: BM25 over the agent's chunks, blended with
+    /// embedding similarity for chunks that have an embedding
+    pub async fn retrieve(agent_id: &str, namespace: Option<&str>, query: &str, top_k: Option<u32>) -> Vec<RetrievedPassage> {
+        let terms = Self::tokenize(query);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        let top_k = top_k.map_or(DEFAULT_TOP_K, |k| (k as usize).clamp(1, MAX_TOP_K));
+        let prefix = Self::scope_prefix(agent_id, namespace);
+
+        // Ingests racing past the per-agent cap must not make retrieval unbounded
+        let chunks: Vec<KnowledgeChunk> = CHUNKS.with(|c| {
+            c.borrow()
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .take(MAX_CHUNKS_PER_AGENT)
+                .map(|(_, chunk)| chunk.0)
+                .collect()
+        });
+        if chunks.is_empty() {
+            return Vec::new();
+        }
+        let tokenized: Vec<Vec<String>> = chunks.iter().map(|c| Self::tokenize(&c.text)).collect();
+        let lexical = Self::bm25_scores(&terms, &tokenized);
+        let max_lexical = lexical.iter().cloned().fold(0.0f32, f32::max);
+
+        // Vectors from another backend are not comparable with the query's
+        let current_model = EmbeddingService::model_fingerprint();
+        let current: HashSet<String> = Self::list_documents(agent_id, namespace)
+            .into_iter()
+            .filter(|d| d.embedding_model.as_deref() == Some(current_model.as_str()))
+            .map(|d| d.doc_id)
+            .collect();
+
+        let query_embedding = if !current.is_empty() {
+            EmbeddingService::embed_texts(vec![query.to_string()]).await.ok().and_then(|mut v| v.pop())
+        } else {
+            None
+        };
+
+        let mut ranked: Vec<(usize, f32)> = chunks
+            .iter()
+            .zip(lexical)
+            .map(|(chunk, lexical)| {
+                let lexical = if max_lexical > 0.0 { lexical / max_lexical } else { 0.0 };
+                let semantic = match (&query_embedding, &chunk.embedding) {
+                    (Some(q), Some(c)) if current.contains(&chunk.doc_id) => Some(EmbeddingService::cosine(q, c).max(0.0)),
+                    _ => None,
+                };
+                semantic.map_or(lexical, |s| (1.0 - SEMANTIC_WEIGHT) * lexical + SEMANTIC_WEIGHT * s)
+            })
+            .enumerate()
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        ranked
+            .into_iter()
+            .take(top_k)
+            .map(|(i, score)| {
+                let chunk = &chunks[i];
+                RetrievedPassage {
+                    doc_id: chunk.doc_id.clone(),
+                    title: chunk.title.clone(),
+                    chunk_index: chunk.index,
+                    score,
+                    text: chunk.text.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Prepend numbered passages to `prompt`. Returns the prompt unchanged when
+    /// nothing relevant is found.
+    pub fn augment_prompt(prompt: &str, passages: &[RetrievedPassage]) -> String {
+        if passages.is_empty() {
+            return prompt.to_string();
+        }
+        let mut augmented = String::from(
+            "Use the following sources where relevant and cite them as [n]:\n",
+        );
+        for (n, passage) in passages.iter().enumerate() {
+            augmented.push_str(&format!("[{}] {}: {}\n", n + 1, passage.title, passage.text));
+        }
+        augmented.push('\n');
+        augmented.push_str(prompt);
+        augmented
+    }
+
+    /// Source list appended to answers built from `passages`
+    pub fn citations(passages: &[RetrievedPassage]) -> String {
+        let mut out = String::from("Sources:");
+        for (n, passage) in passages.iter().enumerate() {
+            out.push_str(&format!(
+                "\n[{}] {} ({}, chunk {})",
+                n + 1,
+                passage.title,
+                passage.doc_id,
+                passage.chunk_index
+            ));
+        }
+        out
+    }
+
+    /// Split on whitespace into windows of about `size` chars, overlapping by `overlap`
+    fn chunk_text(text: &str, size: usize, overlap: usize) -> Vec<String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let mut chunks = Vec::new();
+        let mut start = 0;
+
+        while start < words.len() {
+            let mut end = start;
+            let mut len = 0;
+            while end < words.len() && (len == 0 || len + words[end].chars().count() < size) {
+                len += words[end].chars().count() + 1;
+                end += 1;
+            }
+            chunks.push(words[start..end].join(" "));
+            if end == words.len() {
+                break;
+            }
+
+            // Step back over roughly `overlap` chars of trailing words, always advancing
+            let mut back = end;
+            let mut carried = 0;
+            while back > start + 1 && carried < overlap {
+                back -= 1;
+                carried += words[back].chars().count() + 1;
+            }
+            start = back.max(start + 1);
+        }
+        chunks
+    }
+
+    fn bm25_scores(terms: &[String], docs: &[Vec<String>]) -> Vec<f32> {
+        let n = docs.len() as f32;
+        if docs.is_empty() {
+            return Vec::new();
+        }
+        let avg_len = docs.iter().map(Vec::len).sum::<usize>() as f32 / n;
+
+        let idf: HashMap<&str, f32> = terms
+            .iter()
+            .map(|t| {
+                let df = docs.iter().filter(|d| d.contains(t)).count() as f32;
+                (t.as_str(), ((n - df + 0.5) / (df + 0.5) + 1.0).ln())
+            })
+            .collect();
+
+        docs.iter()
+            .map(|doc| {
+                let len_norm = 1.0 - B + B * doc.len() as f32 / avg_len.max(1.0);
+                terms
+                    .iter()
+                    .map(|t| {
+                        let tf = doc.iter().filter(|w| *w == t).count() as f32;
+                        idf[t.as_str()] * tf * (K1 + 1.0) / (tf + K1 * len_norm)
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn tokenize(text: &str) -> Vec<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|t| t.chars().count() > 1)
+            .map(|t| t.to_lowercase())
+            .collect()
+    }
+
+    fn validate_namespace(namespace: &str) -> Result<(), String> {
+        if namespace.is_empty() || namespace.len() > MAX_NAMESPACE_LEN || namespace.contains('/') {
+            return Err(format!(
+                "Namespace must be 1-{} characters without '/'",
+                MAX_NAMESPACE_LEN
+            ));
+        }
+        Ok(())
+    }
+
+    fn scope_prefix(agent_id: &str, namespace: Option<&str>) -> String {
+        match namespace {
+            Some(namespace) => format!("{}/{}/", agent_id, namespace),
+            None => format!("{}/", agent_id),
+        }
+    }
+
+    fn keys_with_prefix<V: ic_stable_structures::Storable>(
+        map: &StableBTreeMap<String, V, Memory>,
+        prefix: &str,
+    ) -> Vec<String> {
+        map.range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_overlap_and_cover_text() {
+        let text = (0..300).map(|i| format!("w{}", i)).collect::<Vec<_>>().join(" ");
+        let chunks = KnowledgeService::chunk_text(&text, 200, 40);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.chars().count() <= 200));
+        assert!(chunks[0].starts_with("w0 "));
+        assert!(chunks.last().unwrap().ends_with("w299"));
+        let first_tail = chunks[0].split(' ').last().unwrap();
+        assert!(chunks[1].contains(first_tail));
+    }
+
+    #[test]
+    fn test_bm25_ranks_matching_chunk_first() {
+        let docs = vec![
+            KnowledgeService::tokenize("the canister stores state in stable memory"),
+            KnowledgeService::tokenize("cycles pay for computation"),
+        ];
+        let scores = KnowledgeService::bm25_scores(&KnowledgeService::tokenize("stable memory"), &docs);
+        assert!(scores[0] > 0.0);
+        assert_eq!(scores[1], 0.0);
+    }
+}
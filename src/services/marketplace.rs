@@ -0,0 +1,445 @@
+use crate::domain::api_version::{paginate, PageRequest};
+use crate::domain::instruction::AgentType;
+use crate::infra::stable::{memory, Cbor, Memory, PUBLIC_LISTINGS_MEMORY_ID, PUBLIC_LISTING_RATINGS_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentFactory, AgentPerformanceMetrics, AutonomousAgent};
+use crate::services::archive::{ArchiveService, ArchivedAgent};
+use crate::services::budget::{AgentBudget, BudgetUsage};
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    static LISTINGS: RefCell<StableBTreeMap<String, Cbor<PublicListing>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PUBLIC_LISTINGS_MEMORY_ID)));
+    // "{listing_id}/{rater}" -> rating
+    static RATINGS: RefCell<StableBTreeMap<String, u8, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PUBLIC_LISTING_RATINGS_MEMORY_ID)));
+}
+
+const MAX_TITLE_CHARS: usize = 100;
+const MAX_DESCRIPTION_CHARS: usize = 2_000;
+const MAX_TAGS: usize = 10;
+const MAX_TAG_CHARS: usize = 32;
+const RATINGS_RANGE: std::ops::RangeInclusive<u8> = 1..=5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum ListingCategory {
+    Roleplay,
+    Assistant,
+    Coding,
+    Writing,
+    Research,
+    Education,
+    Productivity,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, CandidType, Deserialize)]
+pub enum ListingSort {
+    TopRated,
+    MostUsed,
+    Newest,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct PublishRequest {
+    pub title: String,
+    pub description: String,
+    pub category: ListingCategory,
+    pub tags: Vec<String>,
+}
+
+/// Browse filters; `text` matches title, description and tags, ignoring case
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ListingQuery {
+    pub text: Option<String>,
+    pub category: Option<ListingCategory>,
+    pub sort: ListingSort,
+}
+
+/// Attribution carried by agents instantiated from a public listing
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct AgentOrigin {
+    pub listing_id: String,
+    pub author: String,
+    pub source_agent_id: String,
+    pub instantiated_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PublicListing {
+    author: String,
+    title: String,
+    description: String,
+    category: ListingCategory,
+    tags: Vec<String>,
+    agent_type: AgentType,
+    template: ArchivedAgent, // Sanitized copy of the agent at publish time
+    published_at: u64,
+    updated_at: u64,
+    instantiations: u64,
+    rating_total: u64,
+    rating_count: u32,
+}
+
+#[derive(Debug, Clone, Serialize, CandidType)]
+pub struct ListingInfo {
+    pub listing_id: String, // The published agent's id
+    pub author: String,
+    pub title: String,
+    pub description: String,
+    pub category: ListingCategory,
+    pub tags: Vec<String>,
+    pub agent_type: AgentType,
+    pub published_at: u64,
+    pub updated_at: u64,
+    pub instantiations: u64,
+    pub rating_count: u32,
+    pub average_rating: f32, // 0 before any rating
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ListingPage {
+    pub items: Vec<ListingInfo>,
+    pub next_cursor: Option<String>,
+    pub total: u64,
+}
+
+/// Public agent listings. Publishing stores a template of the agent with its
+/// memory, metrics, feedback, grants, webhooks, pending work and budget
+/// stripped, so later changes to the agent are not visible until it is
+/// published again. Instantiating a listing creates a new agent owned by the
+/// caller that records the listing and author it came from. Knowledge
+/// documents stay with the author's agent.
+pub struct MarketplaceService;
+
+impl MarketplaceService {
+    /// Publish `agent_id` or refresh its listing; ratings and counts carry over
+    pub fn publish(agent_id: &str, author: &str, request: PublishRequest) -> Result<ListingInfo, String> {
+        let agent = AgentFactory::authorize(agent_id, author)?;
+        if !agent.member_ids.is_empty() {
+            return Err("Coordinator agents cannot be published; publish their members instead".to_string());
+        }
+        let request = validate(request)?;
+        let agent_type = agent.analysis.agent_configuration.agent_type.clone();
+        let now = time();
+        let template = ArchiveService::pack(&sanitize(agent), now)?;
+
+        let listing = LISTINGS.with(|l| {
+            let mut listings = l.borrow_mut();
+            let listing = match listings.get(&agent_id.to_string()) {
+                Some(Cbor(existing)) => PublicListing {
+                    title: request.title,
+                    description: request.description,
+                    category: request.category,
+                    tags: request.tags,
+                    agent_type,
+                    template,
+                    updated_at: now,
+                    ..existing
+                },
+                None => PublicListing {
+                    author: author.to_string(),
+                    title: request.title,
+                    description: request.description,
+                    category: request.category,
+                    tags: request.tags,
+                    agent_type,
+                    template,
+                    published_at: now,
+                    updated_at: now,
+                    instantiations: 0,
+                    rating_total: 0,
+                    rating_count: 0,
+                },
+            };
+            listings.insert(agent_id.to_string(), Cbor(listing.clone()));
+            listing
+        });
+        Metrics::increment_counter("agents_published_total");
+        Ok(info(agent_id, &listing))
+    }
+
+    /// Agents already instantiated from the listing keep running
+    pub fn unpublish(listing_id: &str, caller: &str) -> Result<(), String> {
+        let listing = Self::listing(listing_id)?;
+        if listing.author != caller {
+            return Err(format!("Not authorized to unpublish listing {}", listing_id));
+        }
+        Self::remove(listing_id);
+        Ok(())
+    }
+
+    pub fn browse(query: &ListingQuery, page: &PageRequest) -> ListingPage {
+        let text = query.text.as_ref().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty());
+        let listings: Vec<ListingInfo> = LISTINGS.with(|l| {
+            l.borrow()
+                .iter()
+                .filter(|(_, listing)| query.category.map_or(true, |category| listing.0.category == category))
+                .filter(|(_, listing)| text.as_ref().map_or(true, |text| matches_text(&listing.0, text)))
+                .map(|(id, listing)| info(&id, &listing.0))
+                .collect()
+        });
+        let (items, next_cursor, total) = paginate(listings, page, |l| sort_key(l, query.sort));
+        ListingPage { items, next_cursor, total }
+    }
+
+    pub fn get(listing_id: &str) -> Result<ListingInfo, String> {
+        Ok(info(listing_id, &Self::listing(listing_id)?))
+    }
+
+    /// Rate a listing from 1 to 5. A second rating by the same principal replaces the first.
+    pub fn rate(listing_id: &str, rater: Principal, rating: u8) -> Result<ListingInfo, String> {
+        if !RATINGS_RANGE.contains(&rating) {
+            return Err(format!("Rating must be between {} and {}", RATINGS_RANGE.start(), RATINGS_RANGE.end()));
+        }
+        let mut listing = Self::listing(listing_id)?;
+        if listing.author == rater.to_text() {
+            return Err("Authors cannot rate their own listings".to_string());
+        }
+        let previous = RATINGS.with(|r| r.borrow_mut().insert(rating_key(listing_id, &rater), rating));
+        match previous {
+            Some(previous) => listing.rating_total -= previous as u64,
+            None => listing.rating_count += 1,
+        }
+        listing.rating_total += rating as u64;
+        LISTINGS.with(|l| l.borrow_mut().insert(listing_id.to_string(), Cbor(listing.clone())));
+        Ok(info(listing_id, &listing))
+    }
+
+    /// Create an agent for `owner` from the listing's template
+    pub async fn instantiate(listing_id: &str, owner: &str) -> Result<AutonomousAgent, String> {
+        let listing = Self::listing(listing_id)?;
+        let template = ArchiveService::unpack(&listing.template)?;
+        let origin = AgentOrigin {
+            listing_id: listing_id.to_string(),
+            author: listing.author.clone(),
+            source_agent_id: template.agent_id.clone(),
+            instantiated_at: time(),
+        };
+        let agent = AgentFactory::instantiate_template(template, owner, origin).await?;
+
+        // The listing may have been unpublished while quotas were checked
+        LISTINGS.with(|l| {
+            let mut listings = l.borrow_mut();
+            if let Some(Cbor(mut listing)) = listings.get(&listing_id.to_string()) {
+                listing.instantiations += 1;
+                listings.insert(listing_id.to_string(), Cbor(listing));
+            }
+        });
+        Metrics::increment_counter("public_agents_instantiated_total");
+        Ok(agent)
+    }
+
+    /// Listings published by `author`
+    pub fn authored(author: &str) -> Vec<ListingInfo> {
+        LISTINGS.with(|l| {
+            l.borrow()
+                .iter()
+                .filter(|(_, listing)| listing.0.author == author)
+                .map(|(id, listing)| info(&id, &listing.0))
+                .collect()
+        })
+    }
+
+    /// Remove `user`'s listings and ratings. Returns how many were removed.
+    pub fn erase_user(user: &Principal) -> u32 {
+        let author = user.to_text();
+        let authored: Vec<String> = LISTINGS.with(|l| {
+            l.borrow()
+                .iter()
+                .filter(|(_, listing)| listing.0.author == author)
+                .map(|(id, _)| id)
+                .collect()
+        });
+        for listing_id in &authored {
+            Self::remove(listing_id);
+        }
+
+        let suffix = format!("/{}", author);
+        let rated: Vec<(String, u8)> = RATINGS.with(|r| {
+            r.borrow().iter().filter(|(key, _)| key.ends_with(&suffix)).collect()
+        });
+        for (key, rating) in &rated {
+            RATINGS.with(|r| r.borrow_mut().remove(key));
+            let listing_id = &key[..key.len() - suffix.len()];
+            LISTINGS.with(|l| {
+                let mut listings = l.borrow_mut();
+                if let Some(Cbor(mut listing)) = listings.get(&listing_id.to_string()) {
+                    listing.rating_total -= *rating as u64;
+                    listing.rating_count -= 1;
+                    listings.insert(listing_id.to_string(), Cbor(listing));
+                }
+            });
+        }
+        (authored.len() + rated.len()) as u32
+    }
+
+    fn listing(listing_id: &str) -> Result<PublicListing, String> {
+        LISTINGS
+            .with(|l| l.borrow().get(&listing_id.to_string()).map(|l| l.0))
+            .ok_or_else(|| format!("Listing {} not found", listing_id))
+    }
+
+    fn remove(listing_id: &str) {
+        LISTINGS.with(|l| l.borrow_mut().remove(&listing_id.to_string()));
+        let prefix = format!("{}/", listing_id);
+        let keys: Vec<String> = RATINGS.with(|r| {
+            r.borrow()
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, _)| key)
+                .collect()
+        });
+        RATINGS.with(|r| {
+            let mut ratings = r.borrow_mut();
+            for key in &keys {
+                ratings.remove(key);
+            }
+        });
+    }
+}
+
+/// Strip everything tied to the author's use of the agent
+fn sanitize(agent: AutonomousAgent) -> AutonomousAgent {
+    AutonomousAgent {
+        memory: HashMap::new(),
+        performance_metrics: AgentPerformanceMetrics::default(),
+        recent_tasks: Vec::new(),
+        delegations: Vec::new(),
+        webhooks: Vec::new(),
+        pending_tool_calls: Vec::new(),
+        budget: AgentBudget::default(),
+        budget_usage: BudgetUsage::default(),
+        idle_flagged_at: None,
+        pending_tasks: Vec::new(),
+        feedback: Vec::new(),
+        config_proposals: Vec::new(),
+        env: Vec::new(),
+        ..agent
+    }
+}
+
+fn validate(request: PublishRequest) -> Result<PublishRequest, String> {
+    let title = request.title.trim().to_string();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+        return Err(format!("Invalid title: 1 to {} characters", MAX_TITLE_CHARS));
+    }
+    let description = request.description.trim().to_string();
+    if description.chars().count() > MAX_DESCRIPTION_CHARS {
+        return Err(format!("Invalid description: at most {} characters", MAX_DESCRIPTION_CHARS));
+    }
+    let mut tags: Vec<String> = request
+        .tags
+        .iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    if tags.len() > MAX_TAGS || tags.iter().any(|tag| tag.chars().count() > MAX_TAG_CHARS) {
+        return Err(format!("Invalid tags: at most {} of up to {} characters", MAX_TAGS, MAX_TAG_CHARS));
+    }
+    Ok(PublishRequest {
+        title,
+        description,
+        category: request.category,
+        tags,
+    })
+}
+
+fn matches_text(listing: &PublicListing, text: &str) -> bool {
+    listing.title.to_lowercase().contains(text)
+        || listing.description.to_lowercase().contains(text)
+        || listing.tags.iter().any(|tag| tag.contains(text))
+}
+
+fn info(listing_id: &str, listing: &PublicListing) -> ListingInfo {
+    ListingInfo {
+        listing_id: listing_id.to_string(),
+        author: listing.author.clone(),
+        title: listing.title.clone(),
+        description: listing.description.clone(),
+        category: listing.category,
+        tags: listing.tags.clone(),
+        agent_type: listing.agent_type.clone(),
+        published_at: listing.published_at,
+        updated_at: listing.updated_at,
+        instantiations: listing.instantiations,
+        rating_count: listing.rating_count,
+        average_rating: average_rating(listing.rating_total, listing.rating_count),
+    }
+}
+
+fn average_rating(total: u64, count: u32) -> f32 {
+    if count == 0 {
+        0.0
+    } else {
+        total as f32 / count as f32
+    }
+}
+
+fn rating_key(listing_id: &str, rater: &Principal) -> String {
+    format!("{}/{}", listing_id, rater)
+}
+
+/// Cursor keys ascend in the requested order, ties broken by listing id
+fn sort_key(listing: &ListingInfo, sort: ListingSort) -> String {
+    let rank = match sort {
+        ListingSort::TopRated => u64::MAX - (listing.average_rating * 10_000.0) as u64,
+        ListingSort::MostUsed => u64::MAX - listing.instantiations,
+        ListingSort::Newest => u64::MAX - listing.published_at,
+    };
+    format!("{:020}/{}", rank, listing.listing_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(id: &str, rating: f32, instantiations: u64, published_at: u64) -> ListingInfo {
+        ListingInfo {
+            listing_id: id.to_string(),
+            author: String::new(),
+            title: String::new(),
+            description: String::new(),
+            category: ListingCategory::Other,
+            tags: Vec::new(),
+            agent_type: AgentType::GeneralAssistant,
+            published_at,
+            updated_at: published_at,
+            instantiations,
+            rating_count: 0,
+            average_rating: rating,
+        }
+    }
+
+    #[test]
+    fn test_sort_keys_order_best_first() {
+        let a = listing("a", 4.5, 3, 10);
+        let b = listing("b", 3.0, 9, 20);
+        assert!(sort_key(&a, ListingSort::TopRated) < sort_key(&b, ListingSort::TopRated));
+        assert!(sort_key(&b, ListingSort::MostUsed) < sort_key(&a, ListingSort::MostUsed));
+        assert!(sort_key(&b, ListingSort::Newest) < sort_key(&a, ListingSort::Newest));
+        assert!(sort_key(&listing("a", 1.0, 0, 0), ListingSort::TopRated) < sort_key(&listing("b", 1.0, 0, 0), ListingSort::TopRated));
+    }
+
+    #[test]
+    fn test_validate_normalizes_tags() {
+        let request = |title: &str, tags: Vec<&str>| PublishRequest {
+            title: title.to_string(),
+            description: String::new(),
+            category: ListingCategory::Roleplay,
+            tags: tags.into_iter().map(String::from).collect(),
+        };
+        let valid = validate(request(" Pirate ", vec!["Fantasy", "fantasy ", " "])).unwrap();
+        assert_eq!(valid.title, "Pirate");
+        assert_eq!(valid.tags, vec!["fantasy".to_string()]);
+        assert!(validate(request("  ", vec![])).is_err());
+        assert!(validate(request("x", vec!["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k"])).is_err());
+    }
+}
@@ -1,12 +1,109 @@
 use crate::domain::*;
-use crate::services::{with_state, with_state_mut};
-use ic_cdk::api::time;
+use crate::infra::Guards;
+use crate::services::{with_state, with_state_mut, AgentState};
+use crate::services::embedding::EmbeddingService;
+use crate::services::knowledge::KnowledgeService;
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use serde::Deserialize;
 use serde_json::Value;
 
+const DEFAULT_SEARCH_RESULTS: usize = 5;
+const MAX_SEARCH_RESULTS: usize = 50;
+const MAX_VERSIONS_KEPT: u32 = 100;
+const MAX_KEY_LEN: usize = 256;
+const MAX_VALUE_BYTES: usize = 1024 * 1024;
+const MAX_TTL_SECONDS: u64 = 365 * 24 * 60 * 60;
+const MAX_ENTRIES_PER_PRINCIPAL: usize = 1_000;
+const LIST_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Clone, CandidType)]
+pub struct MemoryMatch {
+    pub key: String,
+    pub score: f32,
+}
+
+/// Older versions kept per key, by the entry's retention policy. The
+/// current version is not counted; 0 keeps no history.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct MemoryVersioning {
+    pub session: u32,
+    pub daily: u32,
+    pub weekly: u32,
+    pub persistent: u32,
+}
+
+impl Default for MemoryVersioning {
+    fn default() -> Self {
+        Self { session: 1, daily: 3, weekly: 5, persistent: 10 }
+    }
+}
+
+impl MemoryVersioning {
+    fn keep(&self, policy: &RetentionPolicy) -> usize {
+        let kept = match policy {
+            RetentionPolicy::Session => self.session,
+            RetentionPolicy::Daily => self.daily,
+            RetentionPolicy::Weekly => self.weekly,
+            RetentionPolicy::Persistent => self.persistent,
+        };
+        kept as usize
+    }
+}
+
+/// One version of a memory entry, decrypted
+#[derive(Debug, Clone, CandidType)]
+pub struct MemoryVersion {
+    pub key: String,
+    pub version: u32,
+    pub data: Vec<u8>,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub current: bool,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct MemoryVersionInfo {
+    pub version: u32,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub size_bytes: u64,
+    pub current: bool,
+}
+
+/// A caller's entry, without its namespace prefix
+#[derive(Debug, Clone, CandidType)]
+pub struct MemoryEntryInfo {
+    pub key: String,
+    pub version: u32,
+    pub size_bytes: u64,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub encrypted: bool,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct MemoryListPage {
+    pub items: Vec<MemoryEntryInfo>,
+    pub next_cursor: Option<String>,
+}
+
+/// Key-value memory with TTLs. Entries written through the public API live
+/// in the writer's namespace, "{principal}/{key}", so callers only ever see
+/// their own keys.
 pub struct MemoryService;
 
 impl MemoryService {
-    pub fn store(key: String, data: Vec<u8>, ttl_seconds: u64, encrypt: bool) -> Result<(), String> {
+    /// Write `key`. A value already stored under it becomes an older version,
+    /// kept as `retention_policy` allows.
+    pub fn store(
+        key: String,
+        data: Vec<u8>,
+        ttl_seconds: u64,
+        encrypt: bool,
+        retention_policy: RetentionPolicy,
+    ) -> Result<(), String> {
+        Guards::check_memory_limits()?;
         let now = time();
         let expires_at = now + ttl_seconds * 1_000_000_000; // Convert to nanoseconds
         
@@ -17,19 +114,206 @@ impl MemoryService {
         };
         
         let entry = MemoryEntry {
-            key: key.clone(),
+            key,
             data: encrypted_data,
             created_at: now,
             expires_at,
             encrypted: encrypt,
+            version: 0,
+            retention_policy,
         };
-        
+        with_state_mut(|state| Self::write(state, entry));
+        Ok(())
+    }
+
+    /// Full key of `key` in `owner`'s namespace
+    pub fn namespaced(owner: &Principal, key: &str) -> Result<String, String> {
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return Err(format!("Invalid key: keys must be 1 to {} bytes", MAX_KEY_LEN));
+        }
+        Ok(format!("{}/{}", owner, key))
+    }
+
+    /// store for `owner`'s namespace, with the limits public writes get
+    pub fn put(
+        owner: &Principal,
+        key: &str,
+        data: Vec<u8>,
+        ttl_seconds: u64,
+        encrypt: bool,
+        retention_policy: RetentionPolicy,
+    ) -> Result<(), String> {
+        let full_key = Self::namespaced(owner, key)?;
+        if data.len() > MAX_VALUE_BYTES {
+            return Err(format!("Invalid value: at most {} bytes", MAX_VALUE_BYTES));
+        }
+        if ttl_seconds == 0 || ttl_seconds > MAX_TTL_SECONDS {
+            return Err(format!("Invalid ttl: must be 1 to {} seconds", MAX_TTL_SECONDS));
+        }
+        let prefix = format!("{}/", owner);
+        let at_limit = with_state(|state| {
+            !state.memory_entries.contains_key(&full_key)
+                && state.memory_entries.keys().filter(|k| k.starts_with(&prefix)).count() >= MAX_ENTRIES_PER_PRINCIPAL
+        });
+        if at_limit {
+            return Err(format!("Entry limit reached: at most {} memory entries per principal", MAX_ENTRIES_PER_PRINCIPAL));
+        }
+        Self::store(full_key, data, ttl_seconds, encrypt, retention_policy)
+    }
+
+    /// Removes the entry and its older versions
+    pub fn delete(key: &str) -> Result<(), String> {
+        with_state_mut(|state| {
+            state.memory_versions.remove(key);
+            state.memory_entries.remove(key).map(|_| ()).ok_or_else(|| "Entry not found".to_string())
+        })
+    }
+
+    /// Removes every entry in `owner`'s namespace with its older versions.
+    /// Returns how many current entries were removed.
+    pub fn purge_owner(owner: &Principal) -> u32 {
+        let namespace = format!("{}/", owner);
         with_state_mut(|state| {
-            state.memory_entries.insert(key, entry);
+            let before = state.memory_entries.len();
+            state.memory_entries.retain(|key, _| !key.starts_with(&namespace));
+            state.memory_versions.retain(|key, _| !key.starts_with(&namespace));
+            (before - state.memory_entries.len()) as u32
+        })
+    }
+
+    /// Live entries of `owner` whose key starts with `prefix`, ordered by key
+    pub fn list(owner: &Principal, prefix: &str, cursor: Option<String>) -> MemoryListPage {
+        let namespace = format!("{}/", owner);
+        let now = time();
+        let mut items: Vec<MemoryEntryInfo> = with_state(|state| {
+            state
+                .memory_entries
+                .values()
+                .filter(|entry| entry.expires_at > now)
+                .filter_map(|entry| {
+                    let key = entry.key.strip_prefix(&namespace)?;
+                    let after_cursor = cursor.as_deref().map_or(true, |cursor| key > cursor);
+                    (key.starts_with(prefix) && after_cursor).then(|| MemoryEntryInfo {
+                        key: key.to_string(),
+                        version: entry.version,
+                        size_bytes: entry.data.len() as u64,
+                        created_at: entry.created_at,
+                        expires_at: entry.expires_at,
+                        encrypted: entry.encrypted,
+                    })
+                })
+                .collect()
         });
-        
+        items.sort_by(|a, b| a.key.cmp(&b.key));
+        let next_cursor = if items.len() > LIST_PAGE_SIZE {
+            items.truncate(LIST_PAGE_SIZE);
+            items.last().map(|item| item.key.clone())
+        } else {
+            None
+        };
+        MemoryListPage { items, next_cursor }
+    }
+
+    /// Makes `entry` the current version of its key, numbering it after the
+    /// newest version and moving the replaced entry into the history
+    fn write(state: &mut AgentState, mut entry: MemoryEntry) {
+        let keep = state.memory_versioning.keep(&entry.retention_policy);
+        let history = state.memory_versions.entry(entry.key.clone()).or_default();
+        let previous = state.memory_entries.remove(&entry.key);
+        let latest = previous.as_ref().map(|p| p.version).or_else(|| history.last().map(|h| h.version));
+        entry.version = latest.unwrap_or(0) + 1;
+        if let Some(previous) = previous {
+            history.push(previous);
+        }
+        trim_history(history, keep);
+        if history.is_empty() {
+            state.memory_versions.remove(&entry.key);
+        }
+        state.memory_entries.insert(entry.key.clone(), entry);
+    }
+
+    /// A version of `key`, current or kept in its history
+    pub fn get_version(key: &str, version: u32) -> Result<MemoryVersion, String> {
+        let (entry, current) = with_state(|state| Self::find_version(state, key, version))?;
+        let data = if entry.encrypted {
+            Self::decrypt_data(&entry.data)?
+        } else {
+            entry.data.clone()
+        };
+        Ok(MemoryVersion {
+            key: entry.key,
+            version: entry.version,
+            data,
+            created_at: entry.created_at,
+            expires_at: entry.expires_at,
+            current,
+        })
+    }
+
+    /// Versions of `key`, oldest first
+    pub fn list_versions(key: &str) -> Vec<MemoryVersionInfo> {
+        let info = |entry: &MemoryEntry, current| MemoryVersionInfo {
+            version: entry.version,
+            created_at: entry.created_at,
+            expires_at: entry.expires_at,
+            size_bytes: entry.data.len() as u64,
+            current,
+        };
+        with_state(|state| {
+            let mut versions: Vec<MemoryVersionInfo> = state
+                .memory_versions
+                .get(key)
+                .map(|history| history.iter().map(|entry| info(entry, false)).collect())
+                .unwrap_or_default();
+            versions.extend(state.memory_entries.get(key).map(|entry| info(entry, true)));
+            versions
+        })
+    }
+
+    /// Write an older version back as the newest one. It keeps its original
+    /// lifetime counted from now, and the value it replaces joins the history.
+    pub fn restore_version(key: &str, version: u32) -> Result<u32, String> {
+        Guards::check_memory_limits()?;
+        let now = time();
+        with_state_mut(|state| {
+            let (old, current) = Self::find_version(state, key, version)?;
+            if current {
+                return Err(format!("Version {} of {} is already current", version, key));
+            }
+            let restored = MemoryEntry {
+                created_at: now,
+                expires_at: now + old.expires_at.saturating_sub(old.created_at),
+                ..old
+            };
+            Self::write(state, restored);
+            Ok(state.memory_entries.get(key).map_or(0, |entry| entry.version))
+        })
+    }
+
+    pub fn set_versioning(versioning: MemoryVersioning) -> Result<(), String> {
+        let limits = [versioning.session, versioning.daily, versioning.weekly, versioning.persistent];
+        if limits.iter().any(|kept| *kept > MAX_VERSIONS_KEPT) {
+            return Err(format!("Invalid versioning: at most {} versions per key", MAX_VERSIONS_KEPT));
+        }
+        with_state_mut(|state| state.memory_versioning = versioning);
         Ok(())
     }
+
+    pub fn versioning() -> MemoryVersioning {
+        with_state(|state| state.memory_versioning.clone())
+    }
+
+    fn find_version(state: &AgentState, key: &str, version: u32) -> Result<(MemoryEntry, bool), String> {
+        if let Some(entry) = state.memory_entries.get(key).filter(|entry| entry.version == version) {
+            return Ok((entry.clone(), true));
+        }
+        state
+            .memory_versions
+            .get(key)
+            .and_then(|history| history.iter().find(|entry| entry.version == version))
+            .map(|entry| (entry.clone(), false))
+            .ok_or_else(|| format!("Version {} of memory entry {} not found", version, key))
+    }
     
     pub fn retrieve(key: &str) -> Result<Vec<u8>, String> {
         let now = time();
@@ -44,8 +328,9 @@ impl MemoryService {
                     };
                     Ok(data)
                 } else {
-                    // Entry expired, remove it
+                    // Entry expired, remove it with its history
                     state.memory_entries.remove(key);
+                    state.memory_versions.remove(key);
                     Err("Entry expired".to_string())
                 }
             } else {
@@ -54,15 +339,59 @@ impl MemoryService {
         })
     }
     
+    /// Live text entries of `owner` ranked by embedding similarity to `query`
+    pub async fn search(owner: &Principal, query: &str, top_k: Option<u32>) -> Result<Vec<MemoryMatch>, String> {
+        let top_k = top_k.map_or(DEFAULT_SEARCH_RESULTS, |k| (k as usize).clamp(1, MAX_SEARCH_RESULTS));
+        let now = time();
+        let namespace = format!("{}/", owner);
+        let (keys, mut texts): (Vec<String>, Vec<String>) = with_state(|state| {
+            state.memory_entries
+                .values()
+                .filter(|entry| entry.expires_at > now)
+                .filter_map(|entry| {
+                    let key = entry.key.strip_prefix(&namespace)?;
+                    let data = if entry.encrypted {
+                        Self::decrypt_data(&entry.data).ok()?
+                    } else {
+                        entry.data.clone()
+                    };
+                    String::from_utf8(data).ok().map(|text| (key.to_string(), text))
+                })
+                .unzip()
+        });
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        texts.push(query.to_string());
+        let mut vectors = EmbeddingService::embed_texts(texts).await?;
+        let query_vector = vectors.pop().unwrap_or_default();
+
+        let mut matches: Vec<MemoryMatch> = keys
+            .into_iter()
+            .zip(vectors)
+            .map(|(key, vector)| MemoryMatch {
+                key,
+                score: EmbeddingService::cosine(&query_vector, &vector),
+            })
+            .collect();
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+        matches.truncate(top_k);
+        Ok(matches)
+    }
+
     pub fn clear_expired() {
         let now = time();
         
         with_state_mut(|state| {
             state.memory_entries.retain(|_, entry| entry.expires_at > now);
+            let AgentState { memory_entries, memory_versions, .. } = state;
+            memory_versions.retain(|key, _| memory_entries.contains_key(key));
         });
     }
     
     pub fn get_stats() -> Value {
+        let knowledge = KnowledgeService::stats(None);
         with_state(|state| {
             let now = time();
             let active_entries = state.memory_entries
@@ -75,14 +404,32 @@ impl MemoryService {
                 .map(|entry| entry.data.len())
                 .sum();
             
+            let stored_versions: usize = state.memory_versions.values().map(Vec::len).sum();
+            let version_bytes: usize = state.memory_versions
+                .values()
+                .flatten()
+                .map(|entry| entry.data.len())
+                .sum();
+
             serde_json::json!({
                 "active_entries": active_entries,
+                "versioned_keys": state.memory_versions.len(),
+                "stored_versions": stored_versions,
+                "version_bytes": version_bytes,
                 "total_entries": state.memory_entries.len(),
                 "total_size_bytes": total_size,
                 "encrypted_entries": state.memory_entries
                     .values()
                     .filter(|entry| entry.encrypted)
-                    .count()
+                    .count(),
+                "knowledge": {
+                    "documents": knowledge.documents,
+                    "chunks": knowledge.chunks,
+                    "vectors": knowledge.embedded_chunks,
+                    "stale_documents": knowledge.stale_documents,
+                    "text_bytes": knowledge.text_bytes,
+                    "vector_bytes": knowledge.vector_bytes
+                }
             })
         })
     }
@@ -103,4 +450,38 @@ impl MemoryService {
         // Same XOR operation for decryption
         Self::encrypt_data(encrypted)
     }
-}
\ No newline at end of file
+}
+
+/// Drop the oldest versions beyond `keep`
+fn trim_history(history: &mut Vec<MemoryEntry>, keep: usize) {
+    if history.len() > keep {
+        history.drain(..history.len() - keep);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(version: u32) -> MemoryEntry {
+        MemoryEntry {
+            key: "k".to_string(),
+            data: vec![version as u8],
+            created_at: 0,
+            expires_at: 1,
+            encrypted: false,
+            version,
+            retention_policy: RetentionPolicy::Daily,
+        }
+    }
+
+    #[test]
+    fn test_trim_history_keeps_newest() {
+        let mut history: Vec<MemoryEntry> = (1..=5).map(entry).collect();
+        trim_history(&mut history, 3);
+        assert_eq!(history.iter().map(|e| e.version).collect::<Vec<_>>(), vec![3, 4, 5]);
+        trim_history(&mut history, 0);
+        assert!(history.is_empty());
+        assert_eq!(MemoryVersioning::default().keep(&RetentionPolicy::Persistent), 10);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::infra::stable::{memory, Cbor, Memory, METRIC_SERIES_MEMORY_ID};
+use crate::infra::Metrics;
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::Duration;
+
+thread_local! {
+    // Keyed by the start of the five-minute slot the sample was taken in
+    static SAMPLES: RefCell<StableBTreeMap<u64, Cbor<MetricSample>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(METRIC_SERIES_MEMORY_ID)));
+}
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const SAMPLE_NS: u64 = 5 * 60 * 1_000_000_000;
+const RETENTION_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+const MAX_SERIES_PER_SAMPLE: usize = 500;
+const MAX_POINTS: u64 = 2_000;
+
+/// Every tracked metric at one instant
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct MetricSample {
+    counters: Vec<(String, u64)>, // Sorted by name
+    gauges: Vec<(String, f64)>,   // Sorted by name; includes histogram quantiles
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+#[derive(Debug, Clone, Deserialize, CandidType)]
+pub struct MetricRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType)]
+pub struct MetricPoint {
+    pub timestamp: u64, // Start of the step
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct MetricSeries {
+    pub name: String,
+    pub kind: Option<MetricKind>, // None when the metric has no samples in the range
+    pub step_seconds: u64,
+    pub points: Vec<MetricPoint>,
+}
+
+/// Metric history kept on the canister: every five minutes all counters,
+/// gauges and histogram quantiles are sampled into a stable ring holding
+/// thirty days, so dashboards can plot trends without an external store.
+/// Histogram `name` is sampled as `name_p50`, `name_p95` and `name_p99`.
+pub struct MetricSeriesService;
+
+impl MetricSeriesService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(SAMPLE_INTERVAL, || {
+            Self::sample(time());
+        });
+    }
+
+    pub fn sample(now: u64) {
+        let (mut counters, mut gauges) = Metrics::sample();
+        counters.sort_by(|a, b| a.0.cmp(&b.0));
+        counters.truncate(MAX_SERIES_PER_SAMPLE);
+        gauges.sort_by(|a, b| a.0.cmp(&b.0));
+        gauges.truncate(MAX_SERIES_PER_SAMPLE);
+        SAMPLES.with(|s| {
+            let mut samples = s.borrow_mut();
+            samples.insert(now - now % SAMPLE_NS, Cbor(MetricSample { counters, gauges }));
+            let cutoff = now.saturating_sub(RETENTION_NS);
+            while let Some((oldest, _)) = samples.first_key_value() {
+                if oldest >= cutoff {
+                    break;
+                }
+                samples.remove(&oldest);
+            }
+        });
+    }
+
+    /// Points of `name` over the range, one per `step_seconds` (rounded up to
+    /// the five-minute sample resolution). A counter point is its value at the
+    /// end of the step; a gauge point is the mean of the step's samples.
+    pub fn query(name: &str, range: MetricRange, step_seconds: u64) -> Result<MetricSeries, String> {
+        if range.end <= range.start {
+            return Err("Metric range end must be after its start".to_string());
+        }
+        let step_ns = step_seconds.saturating_mul(1_000_000_000).max(SAMPLE_NS);
+        let step_ns = step_ns.div_ceil(SAMPLE_NS) * SAMPLE_NS;
+        if (range.end - range.start).div_ceil(step_ns) > MAX_POINTS {
+            return Err(format!("Range and step give more than {} points; use a larger step", MAX_POINTS));
+        }
+
+        let mut kind = None;
+        let values: Vec<(u64, f64)> = SAMPLES.with(|s| {
+            s.borrow()
+                .range(range.start - range.start % SAMPLE_NS..range.end)
+                .filter_map(|(at, sample)| {
+                    let sample = sample.0;
+                    if let Ok(i) = sample.counters.binary_search_by(|(n, _)| n.as_str().cmp(name)) {
+                        kind = Some(MetricKind::Counter);
+                        return Some((at, sample.counters[i].1 as f64));
+                    }
+                    let i = sample.gauges.binary_search_by(|(n, _)| n.as_str().cmp(name)).ok()?;
+                    kind = Some(MetricKind::Gauge);
+                    Some((at, sample.gauges[i].1))
+                })
+                .collect()
+        });
+        Ok(MetricSeries {
+            name: name.to_string(),
+            points: kind.map_or_else(Vec::new, |kind| downsample(&values, range.start, step_ns, kind)),
+            kind,
+            step_seconds: step_ns / 1_000_000_000,
+        })
+    }
+}
+
+/// Fold time-ordered samples into steps of `step_ns` from `start`, skipping
+/// steps without samples
+fn downsample(values: &[(u64, f64)], start: u64, step_ns: u64, kind: MetricKind) -> Vec<MetricPoint> {
+    let mut points: Vec<MetricPoint> = Vec::new();
+    let mut samples_in_step = 0;
+    for &(at, value) in values {
+        let timestamp = start + at.saturating_sub(start) / step_ns * step_ns;
+        match points.last_mut() {
+            Some(point) if point.timestamp == timestamp => {
+                samples_in_step += 1;
+                point.value = match kind {
+                    MetricKind::Counter => value,
+                    MetricKind::Gauge => point.value + (value - point.value) / samples_in_step as f64,
+                };
+            }
+            _ => {
+                samples_in_step = 1;
+                points.push(MetricPoint { timestamp, value });
+            }
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downsample_keeps_last_counter_and_averages_gauges() {
+        let values = [(0, 1.0), (SAMPLE_NS, 3.0), (2 * SAMPLE_NS, 8.0), (5 * SAMPLE_NS, 10.0)];
+        let step_ns = 3 * SAMPLE_NS;
+
+        let counters = downsample(&values, 0, step_ns, MetricKind::Counter);
+        assert_eq!(
+            counters,
+            vec![MetricPoint { timestamp: 0, value: 8.0 }, MetricPoint { timestamp: step_ns, value: 10.0 }]
+        );
+
+        let gauges = downsample(&values, 0, step_ns, MetricKind::Gauge);
+        assert_eq!(gauges[0].value, 4.0);
+        assert_eq!(gauges[1].value, 10.0);
+    }
+
+    #[test]
+    fn test_downsample_skips_steps_without_samples() {
+        let values = [(0, 1.0), (10 * SAMPLE_NS, 2.0)];
+        let points = downsample(&values, 0, SAMPLE_NS, MetricKind::Gauge);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1].timestamp, 10 * SAMPLE_NS);
+    }
+}
@@ -11,18 +11,162 @@ pub mod instruction_analyzer;
 pub mod agent_factory;
 pub mod novaq_validation;
 pub mod dfinity_llm;
+pub mod seed;
+pub mod task_history;
+pub mod workflow;
+pub mod coordinator;
+pub mod aggregation;
+pub mod delegation;
+pub mod webhook;
+pub mod events;
+pub mod payments;
+pub mod economics;
+pub mod health;
+pub mod tools;
+pub mod canister_tools;
+pub mod code_sandbox;
+pub mod knowledge;
+pub mod upload;
+pub mod embedding;
+pub mod static_embedding;
+pub mod provenance;
+pub mod guardrails;
+pub mod budget;
+pub mod archive;
+pub mod gc;
+pub mod certification;
+pub mod dashboard;
+pub mod api_keys;
+pub mod settings;
+pub mod warm_set;
+pub mod context_window;
+pub mod benchmark;
+pub mod validation_history;
+pub mod compatibility;
+pub mod model_pool;
+pub mod experiments;
+pub mod prompt_templates;
+pub mod approvals;
+pub mod feedback;
+pub mod calibration;
+pub mod degradation;
+pub mod sla;
+pub mod sharding;
+pub mod signing;
+pub mod trace;
+pub mod replay;
+pub mod outage;
+pub mod attestation;
+pub mod system_callers;
+pub mod system_ops;
+pub mod agent_memory;
+pub mod privacy;
+pub mod data_subject;
+pub mod marketplace;
+pub mod billing;
+pub mod cost;
+pub mod pricing;
+pub mod batching;
+pub mod journal;
+pub mod skills;
+pub mod self_improvement;
+pub mod agent_snapshots;
+pub mod alerts;
+pub mod slo;
+pub mod metric_series;
+pub mod agent_env;
+pub mod attachments;
+pub mod artifacts;
+pub mod translation;
+pub mod redaction;
+pub mod quota;
+pub mod keepalive;
+pub mod standby;
+pub mod introspection;
+pub mod http_gateway;
+pub mod agent_requests;
 
 pub use binding::BindingService;
 pub use inference::InferenceService;
-pub use memory::MemoryService;
-pub use cache::CacheService;
-pub use modelrepo::ModelRepoClient;
+pub use memory::{MemoryService, MemoryMatch, MemoryEntryInfo, MemoryListPage, MemoryVersion, MemoryVersionInfo, MemoryVersioning};
+pub use cache::{CacheService, CacheStats};
+pub use modelrepo::{ModelRepoClient, RepoStatus};
 pub use instruction_analyzer::InstructionAnalyzer;
-pub use agent_factory::{AgentFactory, AutonomousAgent, AgentTask, AgentTaskResult, AgentStatusInfo, AgentSummary};
+pub use agent_factory::{AgentFactory, AutonomousAgent, AgentTask, AgentTaskResult, AgentStatusInfo, AgentSummary, CloneOptions};
+pub use seed::Seed;
+pub use task_history::{TaskHistoryService, TaskRecord, TaskHistoryPage};
+pub use workflow::{WorkflowService, Workflow};
+pub use coordinator::CoordinatorService;
+pub use delegation::{DelegationService, Delegation, AccessScope};
+pub use webhook::{WebhookService, WebhookEvent, WebhookInfo, WebhookDelivery};
+pub use events::{EventService, AgentEvent, AgentEventKind, EventPage, EventSubscription};
+pub use payments::{PaymentService, PaymentConfig, PaymentReceipt};
+pub use health::{HealthService, DetailedHealth};
+pub use tools::{ToolService, ToolCall, ToolCallResult};
+pub use upload::{UploadService, UploadPurpose, UploadStatus};
+pub use knowledge::{KnowledgeService, KnowledgeDocument, KnowledgeStats, ReindexReport, RetrievedPassage};
+pub use embedding::{EmbeddingService, EmbeddingBackend};
+pub use provenance::{Provenance, ProvenanceSource, SourceKind};
+pub use guardrails::{GuardrailService, CritiqueRecord, CritiqueVerdict};
+pub use budget::{BudgetService, AgentBudget, BudgetUsage, BudgetStatus};
+pub use archive::{ArchiveService, ArchivedAgent, ArchivedAgentInfo};
+pub use gc::{GcService, GcConfig, GcReport};
+pub use validation_history::{ValidationHistoryService, ValidationRecord};
+pub use batching::{BatchingService, BatchingStatus};
+pub use self_improvement::{SelfImprovementService, ConfigProposal, ConfigChange, ProposalState};
+pub use skills::{SkillService, Skill, SkillDefinition, SkillStep, SkillOutputSchema, AttachedSkill};
+pub use agent_snapshots::{AgentSnapshotService, AgentSnapshotInfo, AgentSnapshotView, SnapshotQuota, SnapshotTask};
+pub use alerts::{AlertService, AlertRule, AlertRuleSpec, AlertMetric, AlertComparison};
+pub use slo::{SloService, SloEndpoint, SloObjective, SloReport, EndpointSlo};
+pub use metric_series::{MetricSeriesService, MetricSeries, MetricRange, MetricPoint, MetricKind};
+pub use agent_env::{AgentEnvService, AgentEnvVar, AgentEnvVarInfo};
+pub use attachments::{AttachmentService, ATTACHMENTS_KEY};
+pub use artifacts::{ArtifactService, ArtifactRef, ArtifactPage};
+pub use translation::{TranslationService, Translation, INCLUDE_ORIGINAL_KEY};
+pub use redaction::{RedactionService, RedactionPolicy, RedactionPolicyInput, PiiCategory, CustomPattern};
+pub use quota::{QuotaService, MyQuota};
+pub use keepalive::{KeepaliveService, KeepaliveConfig, KeepaliveStatus};
+pub use standby::{StandbyService, StandbyConfig, StandbyStatus};
+pub use introspection::{IntrospectionService, AgentDescription, AvailableTool, ToolKind, AgentModel, AgentMemorySummary, AgentSchedule};
+pub use journal::{JournalService, JournalEntry, JournalPage, JournalRecovery, JournalState, CallKind};
+pub use pricing::{ModelRate, PricingService, PricingTable, TierDiscount};
+pub use cost::{CostEstimate, CostService};
+pub use billing::{BillingBatchPage, BillingService, BillingStatus, BillingTotals};
+pub use marketplace::{ListingCategory, ListingInfo, ListingPage, ListingQuery, ListingSort, MarketplaceService, PublishRequest};
+pub use data_subject::{DataSubjectService, DeletionRequest, UserDataKind, UserDataPage, UserDataRecord};
+pub use privacy::{PrivacyService, VetKdConfig, PurgeReport};
+pub use agent_memory::{AgentMemoryService, MemoryBatch, MemoryRecord, MemoryExportPage, MemoryConflictPolicy, MemoryImportReport};
+pub use system_ops::{SystemOpsService, BatchInferenceResult, AgentUsage, UsageExportPage, MigrationReport};
+pub use system_callers::{SystemCallerService, SystemCaller, SystemAuditEntry, SystemAuditAction, SystemAuditPage};
+pub use attestation::{AttestationService, CallerEnvelope, CoordinatorInfo, AttestationAuditEntry, AttestationAuditPage};
+pub use outage::{OutageService, DeferredTask, LlmIncident};
+pub use replay::{ReplayService, ReplayOverrides, ReplayReport, TemplateVersion, DiffLine, DiffOp};
+pub use trace::{TraceService, ExecutionTrace, TraceStep, TraceStepKind};
+pub use signing::{SigningService, SigningConfig, SigningPublicKey, ResultSignature};
+pub use sharding::{ShardingService, ShardingConfig, ShardingStatus, AgentRoute, PlacementStrategy, Shard, ShardState, ShardLoad, ShardAgentPage, ClusterAgent, ClusterAgentPage, RebalanceReport};
+pub use sla::{SlaService, SlaReport, SlaGroup};
+pub use degradation::{DegradationService, Degradation, DegradationLevel};
+pub use calibration::{CalibrationService, Calibration, CalibrationStats, ConfidenceBucket};
+pub use feedback::{FeedbackService, TaskFeedback};
+pub use approvals::{ApprovalService, ApprovalRequest, ApprovalDecision, ApprovalOutcome, RiskRule};
+pub use prompt_templates::{PromptTemplateService, PromptTemplate, PromptTemplateInput, TemplateScope, TemplateValue, TemplateVariable, VariableKind};
+pub use experiments::{ExperimentService, Experiment, ExperimentSpec, ExperimentVariant, ExperimentStatus, ExperimentResults};
+pub use model_pool::{ModelPoolService, PooledModel, PoolUsage, PoolModelStats};
+pub use compatibility::{CompatibilityService, CompatibilityReport, CompatibilityIssue, CompatibilityCheck};
+pub use benchmark::{BenchmarkService, BenchmarkSuite, BenchmarkCase, BenchmarkResult, BenchmarkSummary};
+pub use context_window::{ContextWindow, ContextTooLong};
+pub use warm_set::{WarmSetService, WarmSet, WarmSetReport, ChunkAccess};
+pub use settings::{SettingsService, CanisterSettings};
+pub use api_keys::{ApiKeyService, ApiKeyInfo};
+pub use http_gateway::{HttpGatewayService, HttpRequest, HttpResponse};
+pub use agent_requests::AgentRequestService;
+pub use dashboard::{DashboardService, Dashboard};
+pub use certification::{CertificationService, CertifiedAgentStatus, CertifiedModelCatalog, ModelCatalog};
+pub use canister_tools::{CanisterToolService, CanisterTool, ToolRegistrationStatus, ToolAuditPage};
 pub use novaq_validation::{NOVAQValidationService, NOVAQValidationResult, NOVAQModelMeta};
 // Note: Currently supports only Llama 3.1 8B
 // Architecture is designed to easily add new models when they become available
-pub use dfinity_llm::{DfinityLlmService, QuantizedModel, ChatMessage, MessageRole, ConversationSession, TokenUsage, UserQuota, LlmError};
+pub use dfinity_llm::{DfinityLlmService, QuantizedModel, ChatMessage, MessageRole, ConversationSession, TokenUsage, UserQuota, LlmError, ExportFormat, ConversationExport, ConversationExportPage, ConversationListing, SkippedSession, SearchFilters, SearchResults, ConversationSettings, MessageStreamPoll};
 use modelrepo::ModelManifest;
 
 thread_local! {
@@ -34,10 +178,29 @@ pub struct AgentState {
     pub config: AgentConfig,
     pub binding: Option<ModelBinding>,
     pub manifest: Option<ModelManifest>,
+    pub model_meta: Option<modelrepo::ModelMeta>, // Of the bound model, fetched at bind time
+    pub pool: HashMap<String, PooledModel>, // Models served alongside the bound model
+    pub pool_usage: HashMap<String, PoolUsage>, // Of the bound and pool models
     pub memory_entries: HashMap<String, MemoryEntry>,
+    pub memory_versions: HashMap<String, Vec<MemoryEntry>>, // Overwritten entries by key, oldest first
+    pub memory_versioning: MemoryVersioning,
     pub cache_entries: HashMap<String, CacheEntry>,
+    pub chunk_maps: HashMap<String, HashMap<String, String>>, // "model_id@version" -> chunk_id -> cache key (content sha256)
     pub metrics: AgentMetrics,
     pub agents: HashMap<String, AutonomousAgent>,
+    pub webhook_deliveries: HashMap<String, Vec<WebhookDelivery>>,
+    pub webhook_seq: u64,
+    pub tool_call_seq: u64,
+    pub knowledge_seq: u64,
+    pub upload_seq: u64,
+    pub workflow_seq: u64,
+    pub agent_seq: u64,
+    pub task_seq: u64,
+    pub snapshot_seq: u64,
+    pub alert_rule_seq: u64,
+    pub chunk_access: HashMap<String, ChunkAccess>,
+    pub warm_set: WarmSet,
+    pub tasks_in_flight: HashMap<String, u32>, // agent_id -> executing tasks
     pub llm_service: Option<DfinityLlmService>, // Lazy initialization
 }
 
@@ -47,10 +210,29 @@ impl Default for AgentState {
             config: AgentConfig::default(),
             binding: None,
             manifest: None,
+            model_meta: None,
+            pool: HashMap::new(),
+            pool_usage: HashMap::new(),
             memory_entries: HashMap::new(),
+            memory_versions: HashMap::new(),
+            memory_versioning: MemoryVersioning::default(),
             cache_entries: HashMap::new(),
+            chunk_maps: HashMap::new(),
             metrics: AgentMetrics::default(),
             agents: HashMap::new(),
+            webhook_deliveries: HashMap::new(),
+            webhook_seq: 0,
+            tool_call_seq: 0,
+            knowledge_seq: 0,
+            upload_seq: 0,
+            workflow_seq: 0,
+            agent_seq: 0,
+            task_seq: 0,
+            snapshot_seq: 0,
+            alert_rule_seq: 0,
+            chunk_access: HashMap::new(),
+            warm_set: WarmSet::default(),
+            tasks_in_flight: HashMap::new(),
             llm_service: None, // Don't initialize LLM service by default
         }
     }
@@ -65,6 +247,11 @@ pub struct AgentMetrics {
     pub last_activity: u64,
 }
 
+/// Shared handle to the chat service, created on first use
+pub fn llm_service() -> DfinityLlmService {
+    with_state_mut(|state| state.llm_service.get_or_insert_with(DfinityLlmService::new).clone())
+}
+
 pub fn with_state<R>(f: impl FnOnce(&AgentState) -> R) -> R {
     STATE.with(|s| {
         let mut state_ref = s.borrow_mut();
@@ -0,0 +1,318 @@
+use crate::domain::ModelBinding;
+use crate::infra::Metrics;
+use crate::services::modelrepo::{ModelManifest, ModelMeta};
+use crate::services::{with_state, with_state_mut, AgentState, BindingService, CacheService, ModelRepoClient};
+use candid::CandidType;
+use crate::infra::clock::time;
+
+/// A model served alongside the bound model
+#[derive(Debug, Clone)]
+pub struct PooledModel {
+    pub binding: ModelBinding,
+    pub manifest: ModelManifest,
+    pub meta: ModelMeta,
+    pub memory_budget_bytes: u64, // Most of the model kept cached
+}
+
+/// Requests served per model, bound or pooled
+#[derive(Debug, Clone, Default)]
+pub struct PoolUsage {
+    pub requests: u64,
+    pub tokens: u64,
+    pub total_latency_ms: u64,
+    pub last_used: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct PoolModelStats {
+    pub model_id: String,
+    pub version: String,
+    pub primary: bool, // The bound model, which serves requests that name no model
+    pub ctx_window: u32,
+    pub memory_budget_bytes: Option<u64>, // None for the bound model, which uses the whole cache
+    pub cached_bytes: u64,
+    pub chunks_loaded: u32,
+    pub total_chunks: u32,
+    pub requests: u64,
+    pub tokens: u64,
+    pub mean_latency_ms: u64,
+    pub last_used: u64,
+}
+
+/// Serves several models at once: the bound model plus pool models, each
+/// holding at most its memory budget in the cache. Pool budgets share
+/// `model_pool_budget_bytes`; adding a model that does not fit removes the
+/// least recently used pool models.
+pub struct ModelPoolService;
+
+impl ModelPoolService {
+    /// `memory_budget_bytes` of None budgets the whole model
+    pub async fn add(model_id: &str, memory_budget_bytes: Option<u64>) -> Result<PoolModelStats, String> {
+        if with_state(|s| s.binding.as_ref().is_some_and(|b| b.model_id == model_id)) {
+            return Err(format!("Model {} is already the bound model", model_id));
+        }
+        let (manifest, meta, repos) = BindingService::fetch_bindable(model_id).await?;
+        let model_bytes: u64 = manifest.chunks.iter().map(|c| c.size).sum();
+        let budget = memory_budget_bytes.unwrap_or(model_bytes).min(model_bytes);
+        if budget == 0 {
+            return Err("memory_budget_bytes must be at least 1".to_string());
+        }
+
+        let evicted = with_state(|s| {
+            let members: Vec<(String, u64, u64)> = s
+                .pool
+                .iter()
+                .filter(|(id, _)| id.as_str() != model_id)
+                .map(|(id, p)| (id.clone(), p.memory_budget_bytes, s.pool_usage.get(id).map_or(0, |u| u.last_used)))
+                .collect();
+            Self::lru_victims(members, budget, s.config.pool_budget_bytes())
+        })?;
+        for victim in &evicted {
+            Self::remove(victim)?;
+            Metrics::increment_counter("pool_evictions_total");
+        }
+
+        // Layer order within the budget, since pool models have no warm set
+        let (chunks_reused, bytes_saved) = CacheService::link_cached_chunks(&manifest);
+        let prefetch_n = with_state(|s| s.config.prefetch_depth) as usize;
+        let mut cached_bytes = Self::cached_bytes(&manifest);
+        let fetch: Vec<(String, u64)> = with_state(|s| {
+            manifest
+                .chunks
+                .iter()
+                .filter(|c| !CacheService::is_cached(s, &manifest, &c.id))
+                .map(|c| (c.id.clone(), c.size))
+                .collect()
+        });
+        for (chunk_id, size) in fetch.into_iter().take(prefetch_n) {
+            if cached_bytes + size > budget {
+                break;
+            }
+            let fetched = match ModelRepoClient::get_chunk(&repos, &manifest, &chunk_id).await {
+                Ok(bytes) => CacheService::put_model_chunk(model_id, &manifest.version, chunk_id, bytes),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = fetched {
+                if !Self::in_use(model_id, &manifest.version) {
+                    CacheService::release_model(model_id, &manifest.version);
+                }
+                return Err(e);
+            }
+            cached_bytes += size;
+        }
+
+        let binding = ModelBinding {
+            model_id: model_id.to_string(),
+            bound_at: time(),
+            manifest_digest: manifest.digest.clone(),
+            chunks_loaded: Self::cached_chunks(&manifest),
+            total_chunks: manifest.chunks.len() as u32,
+            version: manifest.version.clone(),
+            repos,
+            chunks_reused,
+            bytes_saved,
+        };
+        let version = manifest.version.clone();
+        let replaced = with_state_mut(|s| {
+            s.pool_usage.entry(model_id.to_string()).or_default().last_used = time();
+            s.pool
+                .insert(model_id.to_string(), PooledModel { binding, manifest, meta, memory_budget_bytes: budget })
+                .map(|previous| previous.binding.version)
+        });
+        if let Some(previous_version) = replaced.filter(|previous_version| *previous_version != version) {
+            CacheService::release_model(model_id, &previous_version);
+        }
+        Metrics::set_gauge("pool_models", with_state(|s| s.pool.len()) as f64);
+        Self::stats()
+            .into_iter()
+            .find(|stats| stats.model_id == model_id)
+            .ok_or_else(|| format!("Model {} left the pool while being added", model_id))
+    }
+
+    /// The cached chunks become evictable once no other binding shares them
+    pub fn remove(model_id: &str) -> Result<(), String> {
+        let removed = with_state_mut(|s| s.pool.remove(model_id))
+            .ok_or_else(|| format!("Model {} is not in the serving pool", model_id))?;
+        CacheService::release_model(model_id, &removed.binding.version);
+        Metrics::set_gauge("pool_models", with_state(|s| s.pool.len()) as f64);
+        Ok(())
+    }
+
+    /// Binding of the bound model or a pool model, binding or pooling it first
+    /// when it is served by neither. The first model served becomes the bound model.
+    pub async fn serve(model_id: &str) -> Result<ModelBinding, String> {
+        let (bound, serving) = with_state(|s| (s.binding.is_some(), Self::binding(s, model_id).cloned()));
+        if let Some(binding) = serving {
+            return Ok(binding);
+        }
+        if bound {
+            Self::add(model_id, None).await?;
+        } else {
+            BindingService::bind_model(model_id.to_string()).await?;
+        }
+        with_state(|s| Self::binding(s, model_id).cloned())
+            .ok_or_else(|| format!("Model {} is not served after binding", model_id))
+    }
+
+    /// Model that serves a request: the requested model, which must be served,
+    /// else the bound model. None when nothing is bound.
+    pub fn route(requested: Option<&str>) -> Result<Option<String>, String> {
+        Self::route_preferring(requested, &[])
+    }
+
+    /// Like route, but with no model requested the first served model of
+    /// `preferred` is used before the bound model
+    pub fn route_preferring(requested: Option<&str>, preferred: &[String]) -> Result<Option<String>, String> {
+        with_state(|s| {
+            let primary = s.binding.as_ref().map(|b| b.model_id.as_str());
+            let pooled: Vec<&str> = s.pool.keys().map(String::as_str).collect();
+            Self::pick(requested, preferred, primary, &pooled)
+        })
+    }
+
+    pub fn record_usage(model_id: &str, tokens: u64, latency_ms: u64) {
+        let now = time();
+        with_state_mut(|s| {
+            let usage = s.pool_usage.entry(model_id.to_string()).or_default();
+            usage.requests += 1;
+            usage.tokens += tokens;
+            usage.total_latency_ms += latency_ms;
+            usage.last_used = now;
+        });
+        Metrics::increment_counter(&format!("pool_requests_total:{}", model_id));
+    }
+
+    /// Meta of the bound model, or of a pool model when `model_id` names one
+    pub fn meta<'a>(state: &'a AgentState, model_id: Option<&str>) -> Option<&'a ModelMeta> {
+        match model_id.and_then(|id| state.pool.get(id)) {
+            Some(pooled) => Some(&pooled.meta),
+            None => state.model_meta.as_ref(),
+        }
+    }
+
+    pub fn binding<'a>(state: &'a AgentState, model_id: &str) -> Option<&'a ModelBinding> {
+        state
+            .binding
+            .as_ref()
+            .filter(|b| b.model_id == model_id)
+            .or_else(|| state.pool.get(model_id).map(|p| &p.binding))
+    }
+
+    /// Whether the bound model or a pool model uses this version's chunk map
+    pub fn in_use(model_id: &str, version: &str) -> bool {
+        with_state(|s| Self::binding(s, model_id).is_some_and(|b| b.version == version))
+    }
+
+    /// Bound model first, then pool models by id
+    pub fn stats() -> Vec<PoolModelStats> {
+        let (bound, mut pooled) = with_state(|s| {
+            let bound = s.binding.clone().zip(s.manifest.clone()).zip(s.model_meta.clone());
+            let pooled: Vec<PooledModel> = s.pool.values().cloned().collect();
+            (bound, pooled)
+        });
+        pooled.sort_by(|a, b| a.binding.model_id.cmp(&b.binding.model_id));
+        let members = bound
+            .map(|((binding, manifest), meta)| (binding, manifest, meta, None))
+            .into_iter()
+            .chain(pooled.into_iter().map(|p| (p.binding, p.manifest, p.meta, Some(p.memory_budget_bytes))));
+        members
+            .map(|(binding, manifest, meta, memory_budget_bytes)| {
+                let usage = with_state(|s| s.pool_usage.get(&binding.model_id).cloned().unwrap_or_default());
+                PoolModelStats {
+                    primary: memory_budget_bytes.is_none(),
+                    ctx_window: meta.ctx_window,
+                    memory_budget_bytes,
+                    cached_bytes: Self::cached_bytes(&manifest),
+                    chunks_loaded: Self::cached_chunks(&manifest),
+                    total_chunks: binding.total_chunks,
+                    requests: usage.requests,
+                    tokens: usage.tokens,
+                    mean_latency_ms: usage.total_latency_ms / usage.requests.max(1),
+                    last_used: usage.last_used,
+                    model_id: binding.model_id,
+                    version: binding.version,
+                }
+            })
+            .collect()
+    }
+
+    fn cached_bytes(manifest: &ModelManifest) -> u64 {
+        with_state(|s| {
+            manifest
+                .chunks
+                .iter()
+                .filter(|c| CacheService::is_cached(s, manifest, &c.id))
+                .map(|c| c.size)
+                .sum()
+        })
+    }
+
+    fn cached_chunks(manifest: &ModelManifest) -> u32 {
+        with_state(|s| manifest.chunks.iter().filter(|c| CacheService::is_cached(s, manifest, &c.id)).count() as u32)
+    }
+
+    fn pick(
+        requested: Option<&str>,
+        preferred: &[String],
+        primary: Option<&str>,
+        pooled: &[&str],
+    ) -> Result<Option<String>, String> {
+        let served = |id: &str| primary == Some(id) || pooled.contains(&id);
+        if let Some(requested) = requested {
+            return if served(requested) {
+                Ok(Some(requested.to_string()))
+            } else {
+                Err(format!("Model {} is not in the serving pool", requested))
+            };
+        }
+        Ok(preferred
+            .iter()
+            .find(|id| served(id))
+            .cloned()
+            .or_else(|| primary.map(str::to_string)))
+    }
+
+    /// Least recently used members to remove so that `needed` more bytes fit
+    /// the pool budget. Members are (model_id, budget, last_used).
+    fn lru_victims(mut members: Vec<(String, u64, u64)>, needed: u64, pool_budget: u64) -> Result<Vec<String>, String> {
+        if needed > pool_budget {
+            return Err(format!("A budget of {} bytes exceeds the pool budget of {} bytes", needed, pool_budget));
+        }
+        members.sort_by_key(|(_, _, last_used)| *last_used);
+        let mut used: u64 = members.iter().map(|(_, budget, _)| budget).sum();
+        let mut victims = Vec::new();
+        for (model_id, budget, _) in members {
+            if used + needed <= pool_budget {
+                break;
+            }
+            used -= budget;
+            victims.push(model_id);
+        }
+        Ok(victims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routing() {
+        let pooled = ["coder"];
+        let preferred = vec!["writer".to_string(), "coder".to_string()];
+        assert_eq!(ModelPoolService::pick(Some("coder"), &[], Some("base"), &pooled), Ok(Some("coder".to_string())));
+        assert!(ModelPoolService::pick(Some("writer"), &[], Some("base"), &pooled).is_err());
+        assert_eq!(ModelPoolService::pick(None, &preferred, Some("base"), &pooled), Ok(Some("coder".to_string())));
+        assert_eq!(ModelPoolService::pick(None, &[], Some("base"), &pooled), Ok(Some("base".to_string())));
+        assert_eq!(ModelPoolService::pick(None, &[], None, &[]), Ok(None));
+    }
+
+    #[test]
+    fn test_lru_victims_free_just_enough() {
+        let members = vec![("a".to_string(), 40, 3), ("b".to_string(), 40, 1), ("c".to_string(), 20, 2)];
+        assert_eq!(ModelPoolService::lru_victims(members.clone(), 30, 100), Ok(vec!["b".to_string()]));
+        assert_eq!(ModelPoolService::lru_victims(members.clone(), 50, 100), Ok(vec!["b".to_string(), "c".to_string()]));
+        assert_eq!(ModelPoolService::lru_victims(members.clone(), 0, 100), Ok(vec![]));
+        assert!(ModelPoolService::lru_victims(members, 101, 100).is_err());
+    }
+}
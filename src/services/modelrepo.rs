@@ -1,6 +1,11 @@
 use candid::{CandidType, Principal};
 use ic_cdk::api::call::call;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::infra::resilience::DependencyStatus;
+use crate::infra::{Faults, Metrics, Resilience};
+use crate::services::{with_state, ValidationHistoryService};
+use crate::services::journal::{CallIntent, CallKind, JournalService};
 use crate::services::novaq_validation::{NOVAQValidationService, NOVAQValidationResult, NOVAQModelMeta};
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
@@ -35,42 +40,228 @@ pub struct ModelMeta {
     pub license: String,
 }
 
+/// A versioned skill as the model repo serves it; `definition` is the
+/// JSON-encoded SkillDefinition and `sha256` its hex digest
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SkillArtifact {
+    pub skill_id: String,
+    pub version: String,
+    pub definition: Vec<u8>,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct RepoStatus {
+    pub canister_id: String,
+    pub primary: bool,
+    pub serving_binding: bool, // Digest matched at the last bind
+    pub chunks_served: u64,
+    pub dependency: DependencyStatus,
+}
+
+/// Client for the configured model repos. Each call goes to the repos in
+/// order and moves on to the next when one fails with an xnet error.
 pub struct ModelRepoClient;
 
 impl ModelRepoClient {
-    pub async fn get_manifest(canister_id: &str, model_id: &str) -> Result<ModelManifest, String> {
-        let can_principal: Principal = canister_id.parse().map_err(|_| "invalid canister id")?;
-        let arg = (model_id.to_string(),);
-        let (opt_manifest,): (Option<ModelManifest>,) = call(can_principal, "get_manifest", arg)
-            .await
-            .map_err(|e| format!("xnet get_manifest failed: {:?}", e))?;
-        opt_manifest.ok_or_else(|| "manifest not found".to_string())
+    /// Manifest from the first repo that answers, with the repos serving the
+    /// same digest. The remaining mirrors are asked too; a mirror with another
+    /// digest, or that cannot be reached, serves no chunks for this binding.
+    pub async fn get_manifest(repos: &[String], model_id: &str) -> Result<(ModelManifest, Vec<String>), String> {
+        #[cfg(feature = "testing")]
+        if let Some(manifest) = crate::infra::testing::TestSeams::repo_manifest(model_id) {
+            return manifest.map(|manifest| (manifest, repos.to_vec()));
+        }
+        let mut errors = Vec::new();
+        let mut found = None;
+        for (index, repo) in repos.iter().enumerate() {
+            match Self::manifest_from(repo, model_id).await {
+                Ok(Some(manifest)) => {
+                    found = Some((index, manifest));
+                    break;
+                }
+                Ok(None) => return Err("manifest not found".to_string()),
+                Err(e) => {
+                    Metrics::increment_counter("repo_call_failures_total");
+                    errors.push(format!("{}: {}", repo, e));
+                }
+            }
+        }
+        let Some((index, manifest)) = found else {
+            return Err(format!("No model repo reachable: {}", errors.join("; ")));
+        };
+
+        let mut verified = vec![repos[index].clone()];
+        for repo in &repos[index + 1..] {
+            match Self::manifest_from(repo, model_id).await {
+                Ok(Some(mirror)) if mirror.digest == manifest.digest => verified.push(repo.clone()),
+                Ok(Some(_)) => {
+                    Metrics::increment_counter("repo_digest_mismatches_total");
+                    ic_cdk::println!("Model repo {} has another digest for {}; not used for this binding", repo, model_id);
+                }
+                Ok(None) => {
+                    Metrics::increment_counter("repo_mirror_missing_total");
+                    ic_cdk::println!("Model repo {} does not have {}; not used for this binding", repo, model_id);
+                }
+                Err(e) => {
+                    Metrics::increment_counter("repo_call_failures_total");
+                    ic_cdk::println!("Model repo {} unreachable, not used for this binding: {}", repo, e);
+                }
+            }
+        }
+        Ok((manifest, verified))
+    }
+
+    pub async fn get_model_meta(repos: &[String], model_id: &str) -> Result<ModelMeta, String> {
+        #[cfg(feature = "testing")]
+        if let Some(meta) = crate::infra::testing::TestSeams::repo_meta(model_id) {
+            return meta;
+        }
+        let mut errors = Vec::new();
+        for repo in repos {
+            let target: Principal = repo.parse().map_err(|_| "invalid canister id")?;
+            let result: Result<(Option<ModelMeta>,), String> = JournalService::record(
+                Self::intent(repo, "get_model_meta"),
+                Resilience::call(repo, "get_model_meta", || Faults::repo_call(call(target, "get_model_meta", (model_id.to_string(),)))),
+            )
+            .await;
+            match result {
+                Ok((meta,)) => return meta.ok_or_else(|| "meta not found".to_string()),
+                Err(e) => {
+                    Metrics::increment_counter("repo_call_failures_total");
+                    errors.push(format!("{}: {}", repo, e));
+                }
+            }
+        }
+        Err(format!("No model repo reachable: {}", errors.join("; ")))
+    }
+
+    /// A missing chunk also moves on to the next repo: the repos agreed on the
+    /// manifest, so any of them may serve it. So does a chunk whose bytes do
+    /// not match its sha256 in the manifest; nothing unverified is returned.
+    pub async fn get_chunk(repos: &[String], manifest: &ModelManifest, chunk_id: &str) -> Result<Vec<u8>, String> {
+        let expected = manifest
+            .chunks
+            .iter()
+            .find(|c| c.id == chunk_id)
+            .map(|c| c.sha256.clone())
+            .ok_or_else(|| format!("Chunk {} is not in the manifest of {}", chunk_id, manifest.model_id))?;
+        let mut errors = Vec::new();
+        for repo in repos {
+            match Self::chunk_from(repo, &manifest.model_id, chunk_id).await {
+                Ok(Some(mut bytes)) => {
+                    // An injected corruption is caught below like a bad mirror's bytes
+                    Faults::corrupt_chunk(&mut bytes);
+                    if hex::encode(Sha256::digest(&bytes)).eq_ignore_ascii_case(&expected) {
+                        Metrics::increment_counter(&Self::served_counter(repo));
+                        return Ok(bytes);
+                    }
+                    Metrics::increment_counter("repo_chunk_hash_mismatches_total");
+                    ic_cdk::println!("Model repo {} served chunk {} of {} with the wrong sha256", repo, chunk_id, manifest.model_id);
+                    errors.push(format!("{}: chunk failed its sha256 check", repo));
+                    continue;
+                }
+                Ok(None) => errors.push(format!("{}: chunk not found", repo)),
+                Err(e) => errors.push(format!("{}: {}", repo, e)),
+            }
+            Metrics::increment_counter("repo_call_failures_total");
+        }
+        Err(format!("Chunk {} unavailable: {}", chunk_id, errors.join("; ")))
+    }
+
+    async fn chunk_from(repo: &str, model_id: &str, chunk_id: &str) -> Result<Option<Vec<u8>>, String> {
+        // The mock stands in for every repo, so an injected corruption fails over as a real one does
+        #[cfg(feature = "testing")]
+        if let Some(chunk) = crate::infra::testing::TestSeams::repo_chunk(model_id, chunk_id) {
+            return chunk.map(Some);
+        }
+        let target: Principal = repo.parse().map_err(|_| "invalid canister id")?;
+        let (bytes,): (Option<Vec<u8>>,) = JournalService::record(
+            Self::intent(repo, "get_chunk"),
+            Resilience::call(repo, "get_chunk", || Faults::repo_call(call(target, "get_chunk", (model_id.to_string(), chunk_id.to_string())))),
+        )
+        .await?;
+        Ok(bytes)
+    }
+
+    /// The first repo that has the skill serves it; `version` of None asks for the latest
+    pub async fn get_skill(repos: &[String], skill_id: &str, version: Option<&str>) -> Result<SkillArtifact, String> {
+        #[cfg(feature = "testing")]
+        if let Some(artifact) = crate::infra::testing::TestSeams::repo_skill(skill_id, version) {
+            return artifact;
+        }
+        let mut errors = Vec::new();
+        for repo in repos {
+            let target: Principal = repo.parse().map_err(|_| "invalid canister id")?;
+            let version = version.map(str::to_string);
+            let result: Result<(Option<SkillArtifact>,), String> = JournalService::record(
+                Self::intent(repo, "get_skill"),
+                Resilience::call(repo, "get_skill", || Faults::repo_call(call(target, "get_skill", (skill_id.to_string(), version.clone())))),
+            )
+            .await;
+            match result {
+                Ok((Some(artifact),)) => return Ok(artifact),
+                Ok((None,)) => errors.push(format!("{}: skill not found", repo)),
+                Err(e) => {
+                    Metrics::increment_counter("repo_call_failures_total");
+                    errors.push(format!("{}: {}", repo, e));
+                }
+            }
+        }
+        Err(format!("Skill {} unavailable: {}", skill_id, errors.join("; ")))
+    }
+
+    pub fn repo_status() -> Vec<RepoStatus> {
+        let (repos, serving) = with_state(|s| {
+            (s.config.model_repos(), s.binding.as_ref().map(|b| b.repos.clone()).unwrap_or_default())
+        });
+        repos
+            .iter()
+            .enumerate()
+            .map(|(index, repo)| RepoStatus {
+                canister_id: repo.clone(),
+                primary: index == 0,
+                serving_binding: serving.contains(repo),
+                chunks_served: Metrics::get_counter(&Self::served_counter(repo)),
+                dependency: Resilience::dependency_status(repo),
+            })
+            .collect()
+    }
+
+    async fn manifest_from(repo: &str, model_id: &str) -> Result<Option<ModelManifest>, String> {
+        let target: Principal = repo.parse().map_err(|_| "invalid canister id")?;
+        let (manifest,): (Option<ModelManifest>,) = JournalService::record(
+            Self::intent(repo, "get_manifest"),
+            Resilience::call(repo, "get_manifest", || Faults::repo_call(call(target, "get_manifest", (model_id.to_string(),)))),
+        )
+        .await?;
+        Ok(manifest)
     }
 
-    pub async fn get_model_meta(canister_id: &str, model_id: &str) -> Result<ModelMeta, String> {
-        let can_principal: Principal = canister_id.parse().map_err(|_| "invalid canister id")?;
-        let arg = (model_id.to_string(),);
-        let (opt_meta,): (Option<ModelMeta>,) = call(can_principal, "get_model_meta", arg)
-            .await
-            .map_err(|e| format!("xnet get_model_meta failed: {:?}", e))?;
-        opt_meta.ok_or_else(|| "meta not found".to_string())
+    fn intent(repo: &str, method: &str) -> CallIntent {
+        CallIntent {
+            kind: CallKind::ModelRepo,
+            target: repo.to_string(),
+            method: method.to_string(),
+            principal: None,
+            billable_tokens: 0,
+        }
     }
 
-    pub async fn get_chunk(canister_id: &str, model_id: &str, chunk_id: &str) -> Result<Vec<u8>, String> {
-        let can_principal: Principal = canister_id.parse().map_err(|_| "invalid canister id")?;
-        let arg = (model_id.to_string(), chunk_id.to_string());
-        let (opt_bytes,): (Option<Vec<u8>>,) = call(can_principal, "get_chunk", arg)
-            .await
-            .map_err(|e| format!("xnet get_chunk failed: {:?}", e))?;
-        opt_bytes.ok_or_else(|| "chunk not found".to_string())
+    fn served_counter(repo: &str) -> String {
+        format!("repo_chunks_served_total:{}", repo)
     }
     
-    /// Validate NOVAQ compressed model
+    /// Validate NOVAQ compressed model. The result is kept in the validation
+    /// history, where a failure blocks binding the model.
     pub async fn validate_novaq_model(
         model_id: &str,
         model_data: &[u8],
     ) -> Result<NOVAQValidationResult, String> {
-        NOVAQValidationService::validate_novaq_model(model_id, model_data).await
+        let result = NOVAQValidationService::validate_novaq_model(model_id, model_data).await?;
+        let novaq_meta = NOVAQValidationService::extract_novaq_metadata(model_data).await.ok();
+        ValidationHistoryService::record(&result, novaq_meta, model_data, ic_cdk::api::caller());
+        Ok(result)
     }
     
     /// Extract NOVAQ model metadata
@@ -56,7 +56,7 @@ impl NOVAQValidationService {
             quality_score,
             validation_passed,
             issues,
-            validation_timestamp: ic_cdk::api::time(),
+            validation_timestamp: crate::infra::clock::time(),
         })
     }
     
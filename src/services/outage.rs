@@ -0,0 +1,169 @@
+use crate::domain::api_version::ApiError;
+use crate::infra::resilience::BreakerState;
+use crate::infra::stable::{memory, Cbor, Memory, DEFERRED_TASKS_MEMORY_ID};
+use crate::infra::{Metrics, Resilience};
+use crate::services::agent_factory::{AgentFactory, AgentTask};
+use crate::services::inference::LLM_TARGET;
+use crate::services::payments::PaymentService;
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::Duration;
+
+thread_local! {
+    // Oldest first
+    static DEFERRED: RefCell<StableBTreeMap<u64, Cbor<DeferredTask>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(DEFERRED_TASKS_MEMORY_ID)));
+}
+
+const MAX_DEFERRED: u64 = 1_000;
+const MAX_DEFERRED_PER_AGENT: usize = 50;
+const DEFERRED_TTL_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const DRAIN_INTERVAL: Duration = Duration::from_secs(60);
+const DRAIN_BATCH: usize = 10;
+// When the breaker has not opened yet there is no better estimate
+const DEFAULT_RETRY_AFTER_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct DeferredTask {
+    pub agent_id: String,
+    pub task: AgentTask,
+    pub deferred_at: u64,
+}
+
+/// Published in health while the LLM canister is failing or tasks wait for it
+#[derive(Debug, Clone, CandidType)]
+pub struct LlmIncident {
+    pub state: BreakerState,
+    pub retry_after_secs: u64,
+    pub consecutive_failures: u32,
+    pub last_success_at: Option<u64>,
+    pub deferred_tasks: u64,
+}
+
+/// Keeps agents usable while the LLM canister is unreachable. Once its
+/// circuit breaker opens, tasks are queued and run in order after the
+/// breaker lets calls through again, and direct inference fails with a
+/// retry hint instead of a made-up reply. A paid task that expires in the
+/// queue or fails when it runs is refunded. There is no local model runtime
+/// to fall back to yet, so nothing is answered during the outage.
+pub struct OutageService;
+
+impl OutageService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(DRAIN_INTERVAL, || ic_cdk::spawn(Self::drain()));
+    }
+
+    /// True while the breaker is open and not yet due for a probe
+    pub fn llm_unavailable() -> bool {
+        Resilience::retry_after_secs(LLM_TARGET).is_some_and(|secs| secs > 0)
+    }
+
+    pub fn retry_after_secs() -> u64 {
+        Resilience::retry_after_secs(LLM_TARGET)
+            .filter(|secs| *secs > 0)
+            .unwrap_or(DEFAULT_RETRY_AFTER_SECS)
+    }
+
+    /// Error text for an LLM outage, with the retry hint
+    pub fn unavailable_error(detail: &str) -> String {
+        format!("LLM unavailable, retry in {} seconds: {}", Self::retry_after_secs(), detail)
+    }
+
+    pub fn unavailable(detail: &str) -> ApiError {
+        ApiError::Unavailable {
+            message: Self::unavailable_error(detail),
+            retry_after_secs: Some(Self::retry_after_secs()),
+        }
+    }
+
+    /// Queue a task until the LLM recovers; returns its place in the queue
+    pub fn defer(agent_id: &str, task: &AgentTask) -> Result<u64, ApiError> {
+        let (total, for_agent) = DEFERRED.with(|d| {
+            let deferred = d.borrow();
+            let for_agent = deferred.iter().filter(|(_, t)| t.0.agent_id == agent_id).count();
+            (deferred.len(), for_agent)
+        });
+        if total >= MAX_DEFERRED || for_agent >= MAX_DEFERRED_PER_AGENT {
+            return Err(Self::unavailable("the deferred task queue is full"));
+        }
+        let deferred = DeferredTask {
+            agent_id: agent_id.to_string(),
+            task: task.clone(),
+            deferred_at: time(),
+        };
+        Self::push(deferred);
+        Metrics::increment_counter("tasks_deferred_total");
+        Ok(total + 1)
+    }
+
+    pub fn deferred(agent_id: &str) -> Vec<DeferredTask> {
+        DEFERRED.with(|d| {
+            d.borrow()
+                .iter()
+                .filter(|(_, t)| t.0.agent_id == agent_id)
+                .map(|(_, t)| t.0)
+                .collect()
+        })
+    }
+
+    fn push(deferred: DeferredTask) {
+        DEFERRED.with(|d| {
+            let mut queue = d.borrow_mut();
+            let seq = queue.last_key_value().map(|(k, _)| k + 1).unwrap_or(0);
+            queue.insert(seq, Cbor(deferred));
+        });
+    }
+
+    /// None while the LLM is healthy and nothing is queued
+    pub fn incident() -> Option<LlmIncident> {
+        let status = Resilience::dependency_status(LLM_TARGET);
+        let deferred_tasks = DEFERRED.with(|d| d.borrow().len());
+        if status.state == BreakerState::Closed && deferred_tasks == 0 {
+            return None;
+        }
+        Some(LlmIncident {
+            state: status.state,
+            retry_after_secs: Self::retry_after_secs(),
+            consecutive_failures: status.consecutive_failures,
+            last_success_at: status.last_success_at,
+            deferred_tasks,
+        })
+    }
+
+    /// Runs the oldest queued tasks one at a time. A task that fails because
+    /// the breaker opened again goes back to the end of the queue.
+    async fn drain() {
+        if Self::llm_unavailable() {
+            return;
+        }
+        let batch: Vec<(u64, DeferredTask)> =
+            DEFERRED.with(|d| d.borrow().iter().take(DRAIN_BATCH).map(|(k, t)| (k, t.0)).collect());
+        let now = time();
+        for (seq, deferred) in batch {
+            DEFERRED.with(|d| d.borrow_mut().remove(&seq));
+            if now.saturating_sub(deferred.deferred_at) >= DEFERRED_TTL_NS {
+                Metrics::increment_counter("deferred_tasks_expired_total");
+                PaymentService::refund_task(&deferred.task).await;
+                continue;
+            }
+            let agent_id = deferred.agent_id.clone();
+            match AgentFactory::execute_deferred_task(&agent_id, deferred.task.clone()).await {
+                Ok(result) if !result.success => {
+                    PaymentService::refund_task(&deferred.task).await;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if Self::llm_unavailable() {
+                        Self::push(deferred);
+                        return;
+                    }
+                    ic_cdk::println!("Deferred task of {} failed: {}", agent_id, e);
+                    PaymentService::refund_task(&deferred.task).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::infra::stable::{memory, Cbor, Memory, PAYMENT_CONFIG_MEMORY_ID, PAYMENT_RECEIPTS_MEMORY_ID};
+use crate::infra::{Metrics, Resilience};
+use crate::services::agent_factory::AgentTask;
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::call;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::time::Duration;
+
+thread_local! {
+    static RECEIPTS: RefCell<StableBTreeMap<String, Cbor<PaymentReceipt>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PAYMENT_RECEIPTS_MEMORY_ID)));
+    static CONFIG: RefCell<StableBTreeMap<u8, Cbor<PaymentConfig>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PAYMENT_CONFIG_MEMORY_ID)));
+}
+
+const CONFIG_KEY: u8 = 0;
+// Task context key naming a paid task's receipt, so the charge travels with
+// a task that is deferred and is refunded if the task later fails
+pub const PAYMENT_RECEIPT_KEY: &str = "payment_receipt";
+const REFUND_RETRY_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const MAX_REFUND_RETRIES_PER_TICK: usize = 20;
+// Inside the ledger's 24h deduplication window
+const REFUND_DEDUP_WINDOW_NS: u64 = 23 * 60 * 60 * 1_000_000_000;
+
+/// Ledger and price used for pay-per-task execution
+#[derive(Debug, Clone, Default, Serialize, Deserialize, CandidType)]
+pub struct PaymentConfig {
+    pub ledger_canister_id: String,
+    pub price_per_task: u64, // In the ledger's smallest unit
+    pub ledger_fee: u64,     // Deducted from refunds since the canister pays it
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum PaymentStatus {
+    Charged,
+    Refunded,
+    RefundFailed(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct PaymentReceipt {
+    pub receipt_id: String,
+    pub payer: String,
+    pub agent_id: String,
+    pub task_id: String,
+    pub amount: u64,
+    pub ledger_canister_id: String,
+    pub block_index: u64,
+    pub status: PaymentStatus,
+    pub refund_block_index: Option<u64>,
+    pub created_at: u64,
+    #[serde(default)]
+    pub refund_created_at: Option<u64>, // Ledger created_at_time of the refund, kept across retries
+}
+
+// Minimal ICRC-1 / ICRC-2 ledger interface
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Clone, Debug)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Clone, Debug)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+/// Pay-per-task charging through an ICRC-2 ledger, with receipts and refunds
+pub struct PaymentService;
+
+impl PaymentService {
+    /// Retry failed refunds, a few per tick
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(REFUND_RETRY_INTERVAL, || ic_cdk::spawn(Self::retry_failed_refunds()));
+    }
+
+    pub fn set_config(config: PaymentConfig) -> Result<(), String> {
+        Principal::from_text(&config.ledger_canister_id)
+            .map_err(|e| format!("Invalid ledger canister id: {}", e))?;
+        if config.price_per_task <= config.ledger_fee {
+            return Err("price_per_task must exceed the ledger fee".to_string());
+        }
+        CONFIG.with(|c| c.borrow_mut().insert(CONFIG_KEY, Cbor(config)));
+        Ok(())
+    }
+
+    pub fn get_config() -> Result<PaymentConfig, String> {
+        CONFIG.with(|c| c.borrow().get(&CONFIG_KEY).map(|c| c.0))
+            .ok_or_else(|| "Pay-per-task payments are not configured".to_string())
+    }
+
+    /// Pull the task price from the payer's ICRC-2 allowance. The payer must
+    /// have approved this canister as spender beforehand.
+    pub async fn charge(payer: Principal, agent_id: &str, task_id: &str) -> Result<PaymentReceipt, String> {
+        let config = Self::get_config()?;
+        let ledger = Principal::from_text(&config.ledger_canister_id).map_err(|e| e.to_string())?;
+
+        // Fixed created_at_time and memo let the ledger dedupe retried calls
+        let args = TransferFromArgs {
+            spender_subaccount: None,
+            from: Account { owner: payer, subaccount: None },
+            to: Account { owner: ic_cdk::id(), subaccount: None },
+            amount: Nat::from(config.price_per_task),
+            fee: None,
+            memo: Some(Self::memo(task_id)),
+            created_at_time: Some(time()),
+        };
+        let (result,): (Result<Nat, TransferFromError>,) =
+            Resilience::call(&config.ledger_canister_id, "icrc2_transfer_from", || {
+                call(ledger, "icrc2_transfer_from", (args.clone(),))
+            })
+            .await?;
+
+        let block_index = match result {
+            // The ledger only reports a duplicate of a transfer with these exact
+            // args, which is a retry of this call whose first reply was lost
+            Ok(block) | Err(TransferFromError::Duplicate { duplicate_of: block }) => Self::to_u64(&block)?,
+            Err(TransferFromError::InsufficientAllowance { allowance }) => {
+                return Err(format!(
+                    "Insufficient allowance: {} approved, {} required. Call icrc2_approve on the ledger first",
+                    allowance, config.price_per_task
+                ));
+            }
+            Err(e) => return Err(format!("Payment failed: {:?}", e)),
+        };
+        if let Ok(receipt) = Self::get_receipt(&Self::receipt_id(block_index)) {
+            return Ok(receipt);
+        }
+
+        let receipt = PaymentReceipt {
+            receipt_id: Self::receipt_id(block_index),
+            payer: payer.to_text(),
+            agent_id: agent_id.to_string(),
+            task_id: task_id.to_string(),
+            amount: config.price_per_task,
+            ledger_canister_id: config.ledger_canister_id,
+            block_index,
+            status: PaymentStatus::Charged,
+            refund_block_index: None,
+            created_at: time(),
+            refund_created_at: None,
+        };
+        Self::save(&receipt);
+        Metrics::increment_counter("payments_charged_total");
+        Ok(receipt)
+    }
+
+    /// Return the charge minus the ledger fee to the payer
+    pub async fn refund(mut receipt: PaymentReceipt) -> PaymentReceipt {
+        if receipt.status == PaymentStatus::Refunded {
+            return receipt;
+        }
+        // Retries reuse the first attempt's time so the ledger dedupes a refund
+        // whose reply was lost
+        let now = time();
+        if receipt.refund_created_at.map_or(true, |at| now.saturating_sub(at) >= REFUND_DEDUP_WINDOW_NS) {
+            receipt.refund_created_at = Some(now);
+        }
+
+        match Self::transfer_back(&receipt).await {
+            Ok(block_index) => {
+                receipt.status = PaymentStatus::Refunded;
+                receipt.refund_block_index = Some(block_index);
+                Metrics::increment_counter("payments_refunded_total");
+            }
+            Err(e) => {
+                receipt.status = PaymentStatus::RefundFailed(e);
+                Metrics::increment_counter("payment_refund_failures_total");
+            }
+        }
+        Self::save(&receipt);
+        receipt
+    }
+
+    /// Refund the charge `task` carries once it has failed for good; None
+    /// when the task was not paid for
+    pub async fn refund_task(task: &AgentTask) -> Option<PaymentReceipt> {
+        let receipt = Self::get_receipt(task.context.get(PAYMENT_RECEIPT_KEY)?)
+            .ok()
+            .filter(|receipt| receipt.task_id == task.task_id)?;
+        Some(Self::refund(receipt).await)
+    }
+
+    async fn retry_failed_refunds() {
+        let failed: Vec<PaymentReceipt> = RECEIPTS.with(|r| {
+            r.borrow()
+                .iter()
+                .map(|(_, receipt)| receipt.0)
+                .filter(|receipt| matches!(receipt.status, PaymentStatus::RefundFailed(_)))
+                .take(MAX_REFUND_RETRIES_PER_TICK)
+                .collect()
+        });
+        for receipt in failed {
+            Self::refund(receipt).await;
+        }
+    }
+
+    pub fn get_receipt(receipt_id: &str) -> Result<PaymentReceipt, String> {
+        RECEIPTS.with(|r| {
+            r.borrow()
+                .get(&receipt_id.to_string())
+                .map(|receipt| receipt.0)
+                .ok_or_else(|| format!("Receipt {} not found", receipt_id))
+        })
+    }
+
+    pub fn list_receipts(payer: &str) -> Vec<PaymentReceipt> {
+        RECEIPTS.with(|r| {
+            r.borrow()
+                .iter()
+                .map(|(_, receipt)| receipt.0)
+                .filter(|receipt| receipt.payer == payer)
+                .collect()
+        })
+    }
+
+    async fn transfer_back(receipt: &PaymentReceipt) -> Result<u64, String> {
+        let fee = Self::get_config().map(|c| c.ledger_fee).unwrap_or(0);
+        let ledger = Principal::from_text(&receipt.ledger_canister_id).map_err(|e| e.to_string())?;
+        let payer = Principal::from_text(&receipt.payer).map_err(|e| e.to_string())?;
+
+        let args = TransferArg {
+            from_subaccount: None,
+            to: Account { owner: payer, subaccount: None },
+            amount: Nat::from(receipt.amount.saturating_sub(fee)),
+            fee: None,
+            memo: Some(Self::memo(&format!("refund-{}", receipt.task_id))),
+            created_at_time: Some(receipt.refund_created_at.unwrap_or_else(time)),
+        };
+        let (result,): (Result<Nat, TransferError>,) =
+            Resilience::call(&receipt.ledger_canister_id, "icrc1_transfer", || {
+                call(ledger, "icrc1_transfer", (args.clone(),))
+            })
+            .await?;
+
+        match result {
+            Ok(block) | Err(TransferError::Duplicate { duplicate_of: block }) => Self::to_u64(&block),
+            Err(e) => Err(format!("Refund failed: {:?}", e)),
+        }
+    }
+
+    fn save(receipt: &PaymentReceipt) {
+        RECEIPTS.with(|r| {
+            r.borrow_mut().insert(receipt.receipt_id.clone(), Cbor(receipt.clone()));
+        });
+    }
+
+    fn receipt_id(block_index: u64) -> String {
+        format!("rcpt-{}", block_index)
+    }
+
+    /// 32-byte memo tying the ledger transaction to the task
+    fn memo(task_id: &str) -> Vec<u8> {
+        Sha256::digest(task_id.as_bytes()).to_vec()
+    }
+
+    fn to_u64(block: &Nat) -> Result<u64, String> {
+        block.0.clone().try_into().map_err(|_| format!("Block index {} out of range", block))
+    }
+}
@@ -0,0 +1,154 @@
+use crate::domain::instruction::SubscriptionTier;
+use crate::infra::stable::{memory, Cbor, Memory, PRICING_TABLE_MEMORY_ID};
+use crate::services::dfinity_llm::QuantizedModel;
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    static TABLE: RefCell<StableBTreeMap<u8, Cbor<PricingTable>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PRICING_TABLE_MEMORY_ID)));
+}
+
+const TABLE_KEY: u8 = 0;
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ModelRate {
+    pub model: QuantizedModel,
+    pub usd_per_million_input_tokens: f64,
+    pub usd_per_million_output_tokens: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TierDiscount {
+    pub tier: SubscriptionTier,
+    pub percent: u8, // 0 to 100, taken off token charges
+}
+
+/// Rates in USD. Models without a rate are not charged for tokens.
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct PricingTable {
+    pub models: Vec<ModelRate>,
+    pub tier_discounts: Vec<TierDiscount>,
+    pub usd_per_trillion_cycles: f64, // 1T cycles is pegged to 1 XDR
+    pub updated_at: u64,
+}
+
+impl Default for PricingTable {
+    fn default() -> Self {
+        Self {
+            // Free during the beta
+            models: vec![ModelRate {
+                model: QuantizedModel::Llama3_1_8B,
+                usd_per_million_input_tokens: 0.0,
+                usd_per_million_output_tokens: 0.0,
+            }],
+            tier_discounts: Vec::new(),
+            usd_per_trillion_cycles: 1.35,
+            updated_at: 0,
+        }
+    }
+}
+
+/// Admin-managed price list behind every USD figure: conversation token
+/// usage, billing records and task cost estimates
+pub struct PricingService;
+
+impl PricingService {
+    pub fn set_table(mut table: PricingTable) -> Result<(), String> {
+        validate(&table)?;
+        table.updated_at = time();
+        TABLE.with(|t| t.borrow_mut().insert(TABLE_KEY, Cbor(table)));
+        Ok(())
+    }
+
+    pub fn table() -> PricingTable {
+        TABLE.with(|t| t.borrow().get(&TABLE_KEY).map(|t| t.0)).unwrap_or_default()
+    }
+
+    /// USD for `input_tokens` and `output_tokens` on `model`, after the tier's discount
+    pub fn token_cost(model: &QuantizedModel, input_tokens: u64, output_tokens: u64, tier: &SubscriptionTier) -> f64 {
+        token_cost(&Self::table(), model, input_tokens, output_tokens, tier)
+    }
+
+    pub fn cycles_cost(cycles: u64) -> f64 {
+        cycles as f64 / 1e12 * Self::table().usd_per_trillion_cycles
+    }
+}
+
+fn validate(table: &PricingTable) -> Result<(), String> {
+    let rates = table
+        .models
+        .iter()
+        .flat_map(|rate| [rate.usd_per_million_input_tokens, rate.usd_per_million_output_tokens])
+        .chain([table.usd_per_trillion_cycles]);
+    if rates.into_iter().any(|rate| !rate.is_finite() || rate < 0.0) {
+        return Err("Invalid pricing: rates must be finite and not negative".to_string());
+    }
+    for (i, rate) in table.models.iter().enumerate() {
+        if table.models[..i].iter().any(|other| other.model == rate.model) {
+            return Err(format!("Invalid pricing: {:?} is listed twice", rate.model));
+        }
+    }
+    for (i, discount) in table.tier_discounts.iter().enumerate() {
+        if discount.percent > 100 {
+            return Err(format!("Invalid pricing: the {:?} discount exceeds 100%", discount.tier));
+        }
+        if table.tier_discounts[..i].iter().any(|other| other.tier == discount.tier) {
+            return Err(format!("Invalid pricing: {:?} is listed twice", discount.tier));
+        }
+    }
+    Ok(())
+}
+
+fn token_cost(table: &PricingTable, model: &QuantizedModel, input_tokens: u64, output_tokens: u64, tier: &SubscriptionTier) -> f64 {
+    let Some(rate) = table.models.iter().find(|rate| rate.model == *model) else {
+        return 0.0;
+    };
+    let discount = table.tier_discounts.iter().find(|d| d.tier == *tier).map_or(0, |d| d.percent);
+    let cost = input_tokens as f64 / 1e6 * rate.usd_per_million_input_tokens
+        + output_tokens as f64 / 1e6 * rate.usd_per_million_output_tokens;
+    cost * (100 - discount) as f64 / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> PricingTable {
+        PricingTable {
+            models: vec![ModelRate {
+                model: QuantizedModel::Llama3_1_8B,
+                usd_per_million_input_tokens: 1.0,
+                usd_per_million_output_tokens: 3.0,
+            }],
+            tier_discounts: vec![TierDiscount { tier: SubscriptionTier::Enterprise, percent: 25 }],
+            usd_per_trillion_cycles: 1.35,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_token_cost_applies_rates_and_discount() {
+        let model = QuantizedModel::Llama3_1_8B;
+        assert!((token_cost(&table(), &model, 1_000_000, 1_000_000, &SubscriptionTier::Basic) - 4.0).abs() < 1e-9);
+        assert!((token_cost(&table(), &model, 1_000_000, 1_000_000, &SubscriptionTier::Enterprise) - 3.0).abs() < 1e-9);
+        assert_eq!(token_cost(&PricingTable { models: Vec::new(), ..table() }, &model, 10, 10, &SubscriptionTier::Basic), 0.0);
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_tables() {
+        assert!(validate(&table()).is_ok());
+        let mut duplicate = table();
+        duplicate.models.push(duplicate.models[0].clone());
+        assert!(validate(&duplicate).is_err());
+        let mut negative = table();
+        negative.usd_per_trillion_cycles = -1.0;
+        assert!(validate(&negative).is_err());
+        let mut discount = table();
+        discount.tier_discounts[0].percent = 101;
+        assert!(validate(&discount).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use crate::infra::stable::{memory, Cbor, Memory, USER_KEY_SALTS_MEMORY_ID, VETKD_CONFIG_MEMORY_ID};
+use crate::infra::{Metrics, Resilience};
+use crate::services::{llm_service, AgentSnapshotService, MemoryService};
+use candid::{CandidType, Principal};
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use ic_cdk::api::call::{call, call_with_payment128};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use ic_vetkeys::{DerivedPublicKey, EncryptedVetKey, TransportSecretKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+thread_local! {
+    static SALTS: RefCell<StableBTreeMap<Principal, Cbor<UserKeySalt>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(USER_KEY_SALTS_MEMORY_ID)));
+    static CONFIG: RefCell<StableBTreeMap<u8, Cbor<VetKdConfig>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(VETKD_CONFIG_MEMORY_ID)));
+    // Derived keys are never written to stable memory; they are derived again after an upgrade
+    static KEYS: RefCell<HashMap<Principal, [u8; 32]>> = RefCell::new(HashMap::new());
+    static NONCE_SEQ: Cell<u32> = Cell::new(0);
+}
+
+const CONFIG_KEY: u8 = 0;
+const MANAGEMENT_CANISTER: &str = "aaaaa-aa";
+const KEY_CONTEXT: &[u8] = b"ohms-agent-conversations-v1";
+const SYMMETRIC_KEY_DOMAIN: &str = "ohms-agent-conversation-key-v1";
+// Enough for key_1; the unused part is refunded
+const DERIVE_KEY_CYCLES: u128 = 26_153_846_153;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UserKeySalt {
+    salt: Vec<u8>,
+    created_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct VetKdConfig {
+    pub key_name: String, // "dfx_test_key" locally, "test_key_1" or "key_1" on mainnet
+}
+
+impl Default for VetKdConfig {
+    fn default() -> Self {
+        Self {
+            key_name: "key_1".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, CandidType)]
+pub struct PurgeReport {
+    pub conversations_deleted: u32,
+    pub memory_entries_deleted: u32,
+    pub snapshots_deleted: u32,
+    pub key_destroyed: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum VetKdCurve {
+    #[serde(rename = "bls12_381_g2")]
+    Bls12_381G2,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct VetKdKeyId {
+    curve: VetKdCurve,
+    name: String,
+}
+
+#[derive(CandidType, Clone, Debug)]
+struct VetKdPublicKeyArgs {
+    canister_id: Option<Principal>,
+    context: Vec<u8>,
+    key_id: VetKdKeyId,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct VetKdPublicKeyReply {
+    public_key: Vec<u8>,
+}
+
+#[derive(CandidType, Clone, Debug)]
+struct VetKdDeriveKeyArgs {
+    input: Vec<u8>,
+    context: Vec<u8>,
+    transport_public_key: Vec<u8>,
+    key_id: VetKdKeyId,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct VetKdDeriveKeyReply {
+    encrypted_key: Vec<u8>,
+}
+
+/// Per-user encryption of conversation content at rest. Each user's key is a
+/// vetKD key derived by this canister for the input `principal || salt`, with
+/// a random salt kept per user. Only this canister can have the key derived,
+/// and forgetting the salt makes it underivable, which shreds everything
+/// sealed under it. Content is sealed with ChaCha20-Poly1305; a sealed value
+/// is the 12-byte nonce followed by the ciphertext.
+pub struct PrivacyService;
+
+impl PrivacyService {
+    pub fn set_config(config: VetKdConfig) -> Result<(), String> {
+        if config.key_name.trim().is_empty() {
+            return Err("key_name cannot be empty".to_string());
+        }
+        CONFIG.with(|c| c.borrow_mut().insert(CONFIG_KEY, Cbor(config)));
+        Ok(())
+    }
+
+    pub fn config() -> VetKdConfig {
+        CONFIG.with(|c| c.borrow().get(&CONFIG_KEY).map(|c| c.0)).unwrap_or_default()
+    }
+
+    /// Derive `user`'s key unless it is loaded already. Must run in an update
+    /// call before anything of the user's is sealed or opened.
+    pub async fn ensure_key(user: Principal) -> Result<(), String> {
+        if KEYS.with(|k| k.borrow().contains_key(&user)) {
+            return Ok(());
+        }
+        let (random,): (Vec<u8>,) = ic_cdk::api::management_canister::main::raw_rand()
+            .await
+            .map_err(|(code, msg)| format!("Failed to get randomness: {:?} {}", code, msg))?;
+        // Another call may have created the salt while this one waited
+        let salt = SALTS.with(|s| {
+            let mut salts = s.borrow_mut();
+            if let Some(existing) = salts.get(&user) {
+                return existing.0.salt;
+            }
+            let salt = Sha256::digest([b"salt".as_slice(), &random].concat()).to_vec();
+            salts.insert(user, Cbor(UserKeySalt { salt: salt.clone(), created_at: time() }));
+            salt
+        });
+
+        let key = Self::derive_key(&user, &salt, &random).await?;
+        // A purge while the key was being derived destroyed this salt
+        if SALTS.with(|s| s.borrow().get(&user).map(|stored| stored.0.salt)) != Some(salt) {
+            return Err("Encryption key was destroyed while it was being derived".to_string());
+        }
+        KEYS.with(|k| k.borrow_mut().insert(user, key));
+        Metrics::increment_counter("user_keys_derived_total");
+        Ok(())
+    }
+
+    /// Whether `user` has a key, loaded or not
+    pub fn has_key(user: &Principal) -> bool {
+        SALTS.with(|s| s.borrow().contains_key(user))
+    }
+
+    pub fn seal(user: &Principal, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        let key = Self::key(user)?;
+        let nonce = Self::next_nonce();
+        let mut sealed = nonce.to_vec();
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| "Encryption failed".to_string())?;
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    pub fn open(user: &Principal, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        let key = Self::key(user)?;
+        open_with(&key, sealed)
+    }
+
+    /// Delete all of `user`'s conversations and memory entries and destroy
+    /// their key. Sealed copies that outlive the deletion, such as older
+    /// stable-memory pages, can no longer be decrypted.
+    pub fn purge(user: Principal) -> PurgeReport {
+        let conversations_deleted = llm_service().purge_user(user);
+        let memory_entries_deleted = MemoryService::purge_owner(&user);
+        let snapshots_deleted = AgentSnapshotService::purge_owner(&user);
+        let key_destroyed = SALTS.with(|s| s.borrow_mut().remove(&user)).is_some();
+        KEYS.with(|k| k.borrow_mut().remove(&user));
+        Metrics::increment_counter("user_data_purges_total");
+        PurgeReport {
+            conversations_deleted,
+            memory_entries_deleted,
+            snapshots_deleted,
+            key_destroyed,
+        }
+    }
+
+    fn key(user: &Principal) -> Result<[u8; 32], String> {
+        KEYS.with(|k| k.borrow().get(user).copied())
+            .ok_or_else(|| format!("Encryption key of {} is not loaded", user))
+    }
+
+    /// Unique for the canister's lifetime: time never repeats across upgrades,
+    /// and the sequence separates values sealed within one round
+    fn next_nonce() -> [u8; NONCE_LEN] {
+        let seq = NONCE_SEQ.with(|s| {
+            let seq = s.get().wrapping_add(1);
+            s.set(seq);
+            seq
+        });
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..8].copy_from_slice(&time().to_be_bytes());
+        nonce[8..].copy_from_slice(&seq.to_be_bytes());
+        nonce
+    }
+
+    /// The transport key only protects the vetKey on its way back to this
+    /// canister, so a fresh one is seeded from `random` for every derivation
+    async fn derive_key(user: &Principal, salt: &[u8], random: &[u8]) -> Result<[u8; 32], String> {
+        let key_name = Self::config().key_name;
+        let key_id = || VetKdKeyId {
+            curve: VetKdCurve::Bls12_381G2,
+            name: key_name.clone(),
+        };
+        let seed = Sha256::digest([b"transport".as_slice(), random].concat()).to_vec();
+        let transport_key = TransportSecretKey::from_seed(seed).map_err(|e| format!("Invalid transport key: {:?}", e))?;
+        let input = [user.as_slice(), salt].concat();
+        let management = Principal::management_canister();
+
+        let public_key_args = VetKdPublicKeyArgs {
+            canister_id: None,
+            context: KEY_CONTEXT.to_vec(),
+            key_id: key_id(),
+        };
+        let (public_key,): (VetKdPublicKeyReply,) = Resilience::call(MANAGEMENT_CANISTER, "vetkd_public_key", || {
+            call(management, "vetkd_public_key", (public_key_args.clone(),))
+        })
+        .await?;
+        let derive_args = VetKdDeriveKeyArgs {
+            input: input.clone(),
+            context: KEY_CONTEXT.to_vec(),
+            transport_public_key: transport_key.public_key(),
+            key_id: key_id(),
+        };
+        let (derived,): (VetKdDeriveKeyReply,) = Resilience::call(MANAGEMENT_CANISTER, "vetkd_derive_key", || {
+            call_with_payment128(management, "vetkd_derive_key", (derive_args.clone(),), DERIVE_KEY_CYCLES)
+        })
+        .await?;
+
+        let public_key =
+            DerivedPublicKey::deserialize(&public_key.public_key).map_err(|e| format!("Invalid vetKD public key: {:?}", e))?;
+        let vetkey = EncryptedVetKey::deserialize(&derived.encrypted_key)
+            .map_err(|e| format!("Invalid vetKD key: {:?}", e))?
+            .decrypt_and_verify(&transport_key, &public_key, &input)
+            .map_err(|e| format!("vetKD key failed verification: {:?}", e))?;
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&vetkey.derive_symmetric_key(SYMMETRIC_KEY_DOMAIN, key.len()));
+        Ok(key)
+    }
+}
+
+fn open_with(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_LEN {
+        return Err("Sealed value is truncated".to_string());
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Decryption failed: wrong key or tampered content".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_rejects_other_keys_and_tampering() {
+        let key = [7u8; 32];
+        let nonce = [1u8; NONCE_LEN];
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            ChaCha20Poly1305::new(Key::from_slice(&key))
+                .encrypt(Nonce::from_slice(&nonce), b"hello".as_slice())
+                .unwrap(),
+        );
+        assert_eq!(open_with(&key, &sealed).unwrap(), b"hello");
+        assert!(open_with(&[8u8; 32], &sealed).is_err());
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(open_with(&key, &sealed).is_err());
+        assert!(open_with(&key, &nonce[..4]).is_err());
+    }
+}
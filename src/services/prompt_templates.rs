@@ -0,0 +1,483 @@
+use crate::domain::instruction::AgentType;
+use crate::infra::stable::{memory, Cbor, Memory, PROMPT_TEMPLATES_MEMORY_ID};
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    // "{scope}/{name}" -> versions, oldest first. Scope is "global" or the owner's principal.
+    static TEMPLATES: RefCell<StableBTreeMap<String, Cbor<Vec<PromptTemplate>>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PROMPT_TEMPLATES_MEMORY_ID)));
+}
+
+pub const PERSONA_TEMPLATE: &str = "persona";
+const GLOBAL_SCOPE: &str = "global";
+// Same limit as prompts sent to `infer`
+const DEFAULT_MAX_CHARS: u32 = 10_000;
+const MAX_BODY_CHARS: usize = 8_000;
+const MAX_VERSIONS: usize = 50;
+const TRUNCATION_MARK: &str = "…";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum VariableKind {
+    Text,
+    Integer,
+    List, // Rendered one "- item" per line
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TemplateVariable {
+    pub name: String,
+    pub kind: VariableKind,
+    pub required: bool,
+    pub truncatable: bool, // May be shortened to keep the prompt within max_chars
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum TemplateValue {
+    Text(String),
+    Integer(i64),
+    List(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum TemplateScope {
+    Global, // Admins; applies to every agent
+    Owner,  // The caller's override; applies to the caller's agents
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct PromptTemplateInput {
+    pub name: String,
+    pub scope: TemplateScope,
+    pub body: String, // `{{variable}}` placeholders; `\{{` is a literal `{{`
+    pub variables: Vec<TemplateVariable>,
+    pub max_chars: Option<u32>,
+    pub note: Option<String>, // Why this version was made
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct PromptTemplate {
+    pub name: String,
+    pub version: u32, // 0 for built-in templates
+    pub owner: Option<Principal>, // None for built-in and global templates
+    pub body: String,
+    pub variables: Vec<TemplateVariable>,
+    pub max_chars: Option<u32>,
+    pub note: Option<String>,
+    pub created_by: Option<Principal>,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Variable(String),
+}
+
+/// Named, versioned prompt templates. An owner's override wins over the
+/// global template, which wins over the built-in default. Every change is a
+/// new version, so prompt changes stay auditable.
+pub struct PromptTemplateService;
+
+impl PromptTemplateService {
+    pub fn save(input: PromptTemplateInput, caller: Principal) -> Result<PromptTemplate, String> {
+        let owner = Self::owner(input.scope, caller);
+        Self::validate(&input)?;
+        let template = PromptTemplate {
+            name: input.name,
+            version: 0,
+            owner,
+            body: input.body,
+            variables: input.variables,
+            max_chars: input.max_chars,
+            note: input.note,
+            created_by: Some(caller),
+            created_at: time(),
+        };
+        Ok(Self::append(template))
+    }
+
+    /// Saves `version` again as the newest version
+    pub fn revert(name: &str, scope: TemplateScope, version: u32, caller: Principal) -> Result<PromptTemplate, String> {
+        let owner = Self::owner(scope, caller);
+        let mut template = Self::history(name, owner)
+            .into_iter()
+            .find(|t| t.version == version)
+            .ok_or_else(|| format!("Template {} has no version {}", name, version))?;
+        template.note = Some(format!("Revert to version {}", version));
+        template.created_by = Some(caller);
+        template.created_at = time();
+        Ok(Self::append(template))
+    }
+
+    /// Versions of the global template, or of `owner`'s override, newest first
+    pub fn history(name: &str, owner: Option<Principal>) -> Vec<PromptTemplate> {
+        let key = Self::key(name, owner);
+        let mut versions = TEMPLATES.with(|t| t.borrow().get(&key).map(|v| v.0).unwrap_or_default());
+        versions.reverse();
+        versions
+    }
+
+    /// One version of the global template or of `owner`'s override; version 0
+    /// is the built-in template
+    pub fn version(name: &str, owner: Option<Principal>, version: u32) -> Result<PromptTemplate, String> {
+        if version == 0 {
+            return Self::builtin(name).ok_or_else(|| format!("Template {} has no built-in version", name));
+        }
+        Self::history(name, owner)
+            .into_iter()
+            .find(|t| t.version == version)
+            .ok_or_else(|| format!("Template {} has no version {}", name, version))
+    }
+
+    /// Template used for `owner`'s agents
+    pub fn resolve(name: &str, owner: Option<Principal>) -> Option<PromptTemplate> {
+        let latest = |owner| Self::history(name, owner).into_iter().next();
+        owner.and_then(|owner| latest(Some(owner))).or_else(|| latest(None)).or_else(|| Self::builtin(name))
+    }
+
+    /// Templates in effect for `owner`, built-in ones included
+    pub fn list(owner: Option<Principal>) -> Vec<PromptTemplate> {
+        let mut names: Vec<String> = Self::builtin_names();
+        let scopes = [Some(GLOBAL_SCOPE.to_string()), owner.map(|o| o.to_text())];
+        TEMPLATES.with(|t| {
+            for (key, _) in t.borrow().iter() {
+                if let Some((scope, name)) = key.split_once('/') {
+                    if scopes.iter().flatten().any(|s| s == scope) && !names.iter().any(|n| n == name) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        });
+        names.iter().filter_map(|name| Self::resolve(name, owner)).collect()
+    }
+
+    pub fn render_named(name: &str, owner: Option<Principal>, values: &HashMap<String, TemplateValue>) -> Result<String, String> {
+        Self::render_named_with_env(name, owner, values, &[])
+    }
+
+    /// Like `render_named`, plus the agent's `env_*` values for the variables
+    /// the template declares; the rest are left out, so any template renders
+    /// whatever the agent's environment holds
+    pub fn render_named_with_env(
+        name: &str,
+        owner: Option<Principal>,
+        values: &HashMap<String, TemplateValue>,
+        env: &[(String, String)],
+    ) -> Result<String, String> {
+        let template = Self::resolve(name, owner).ok_or_else(|| format!("Prompt template {} not found", name))?;
+        let mut values = values.clone();
+        for (variable, value) in env {
+            if template.variables.iter().any(|v| &v.name == variable) {
+                values.entry(variable.clone()).or_insert_with(|| TemplateValue::Text(value.clone()));
+            }
+        }
+        Self::render(&template, &values)
+    }
+
+    /// Task prompt for an agent type, from the `task.{AgentType}` template
+    pub fn task_prompt(agent_type: &AgentType, owner: Option<Principal>, task: &str, env: &[(String, String)]) -> Result<String, String> {
+        let values = HashMap::from([("task".to_string(), TemplateValue::Text(task.to_string()))]);
+        Self::render_named_with_env(&Self::task_template_name(agent_type), owner, &values, env)
+    }
+
+    pub fn task_template_name(agent_type: &AgentType) -> String {
+        match agent_type {
+            AgentType::Custom(_) => "task.Custom".to_string(),
+            other => format!("task.{:?}", other),
+        }
+    }
+
+    /// Values are inserted in one pass, so placeholders inside them are never
+    /// expanded; control characters other than newlines and tabs are dropped.
+    /// When the result exceeds max_chars, truncatable values are shortened,
+    /// longest first.
+    pub fn render(template: &PromptTemplate, values: &HashMap<String, TemplateValue>) -> Result<String, String> {
+        let segments = Self::parse(&template.body)?;
+        if let Some(unknown) = values.keys().find(|k| !template.variables.iter().any(|v| &v.name == *k)) {
+            return Err(format!("Template {} has no variable {}", template.name, unknown));
+        }
+
+        let mut rendered: HashMap<&str, String> = HashMap::new();
+        for variable in &template.variables {
+            let text = match (values.get(&variable.name), variable.kind) {
+                (None, _) if variable.required => {
+                    return Err(format!("Template {} needs variable {}", template.name, variable.name))
+                }
+                (None, _) => String::new(),
+                (Some(TemplateValue::Text(text)), VariableKind::Text) => Self::escape(text),
+                (Some(TemplateValue::Integer(n)), VariableKind::Integer) => n.to_string(),
+                (Some(TemplateValue::List(items)), VariableKind::List) => items
+                    .iter()
+                    .map(|item| format!("- {}", Self::escape(item).replace('\n', " ")))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                (Some(_), kind) => {
+                    return Err(format!("Variable {} of {} must be {:?}", variable.name, template.name, kind))
+                }
+            };
+            rendered.insert(variable.name.as_str(), text);
+        }
+
+        let budget = template.max_chars.unwrap_or(DEFAULT_MAX_CHARS) as usize;
+        let length = |rendered: &HashMap<&str, String>| {
+            segments
+                .iter()
+                .map(|segment| match segment {
+                    Segment::Literal(text) => text.chars().count(),
+                    Segment::Variable(name) => rendered.get(name.as_str()).map_or(0, |v| v.chars().count()),
+                })
+                .sum::<usize>()
+        };
+        let mut truncatable: Vec<&str> = template
+            .variables
+            .iter()
+            .filter(|v| v.truncatable)
+            .map(|v| v.name.as_str())
+            .collect();
+        truncatable.sort_by_key(|name| std::cmp::Reverse(rendered[name].chars().count()));
+        for name in truncatable {
+            let excess = length(&rendered).saturating_sub(budget);
+            if excess == 0 {
+                break;
+            }
+            let value = rendered.get_mut(name).expect("rendered above");
+            let keep = value.chars().count().saturating_sub(excess + TRUNCATION_MARK.chars().count());
+            *value = value.chars().take(keep).collect::<String>() + TRUNCATION_MARK;
+        }
+        if length(&rendered) > budget {
+            return Err(format!("Template {} renders past its {} character budget", template.name, budget));
+        }
+
+        let prompt: String = segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text.as_str(),
+                Segment::Variable(name) => rendered.get(name.as_str()).map_or("", String::as_str),
+            })
+            .collect();
+        Ok(prompt.trim_end().to_string())
+    }
+
+    fn append(mut template: PromptTemplate) -> PromptTemplate {
+        let key = Self::key(&template.name, template.owner);
+        TEMPLATES.with(|t| {
+            let mut templates = t.borrow_mut();
+            let mut versions = templates.get(&key).map(|v| v.0).unwrap_or_default();
+            template.version = versions.last().map_or(1, |latest| latest.version + 1);
+            versions.push(template.clone());
+            if versions.len() > MAX_VERSIONS {
+                versions.drain(..versions.len() - MAX_VERSIONS);
+            }
+            templates.insert(key, Cbor(versions));
+        });
+        template
+    }
+
+    fn owner(scope: TemplateScope, caller: Principal) -> Option<Principal> {
+        match scope {
+            TemplateScope::Global => None,
+            TemplateScope::Owner => Some(caller),
+        }
+    }
+
+    fn validate(input: &PromptTemplateInput) -> Result<(), String> {
+        if input.name.is_empty() || !input.name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+            return Err("Template names use letters, digits, '.', '_' and '-'".to_string());
+        }
+        if input.body.trim().is_empty() || input.body.chars().count() > MAX_BODY_CHARS {
+            return Err(format!("Template body must be 1 to {} characters", MAX_BODY_CHARS));
+        }
+        if input.max_chars == Some(0) {
+            return Err("max_chars must be at least 1".to_string());
+        }
+        for (index, variable) in input.variables.iter().enumerate() {
+            if !Self::is_variable_name(&variable.name) || input.variables[..index].iter().any(|v| v.name == variable.name) {
+                return Err(format!("Variable name {} is invalid or repeated", variable.name));
+            }
+        }
+        for segment in Self::parse(&input.body)? {
+            if let Segment::Variable(name) = segment {
+                if !input.variables.iter().any(|v| v.name == name) {
+                    return Err(format!("Placeholder {{{{{}}}}} is not a declared variable", name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse(body: &str) -> Result<Vec<Segment>, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut rest = body;
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix("\\{{") {
+                literal.push_str("{{");
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("{{") {
+                let end = after.find("}}").ok_or("Unterminated {{ placeholder")?;
+                let name = after[..end].trim();
+                if !Self::is_variable_name(name) {
+                    return Err(format!("Invalid placeholder {{{{{}}}}}", name));
+                }
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Variable(name.to_string()));
+                rest = &after[end + 2..];
+            } else {
+                let next = rest.chars().next().expect("rest is not empty");
+                literal.push(next);
+                rest = &rest[next.len_utf8()..];
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(segments)
+    }
+
+    fn escape(value: &str) -> String {
+        value.chars().filter(|c| !c.is_control() || matches!(c, '\n' | '\t')).collect()
+    }
+
+    fn is_variable_name(name: &str) -> bool {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    fn key(name: &str, owner: Option<Principal>) -> String {
+        let scope = owner.map_or_else(|| GLOBAL_SCOPE.to_string(), |o| o.to_text());
+        format!("{}/{}", scope, name)
+    }
+
+    fn builtin_names() -> Vec<String> {
+        let mut names: Vec<String> = [
+            AgentType::GeneralAssistant,
+            AgentType::CodeAssistant,
+            AgentType::ContentCreator,
+            AgentType::DataAnalyst,
+            AgentType::ProblemSolver,
+            AgentType::Coordinator,
+            AgentType::Researcher,
+            AgentType::Planner,
+            AgentType::Executor,
+            AgentType::Custom(String::new()),
+        ]
+        .iter()
+        .map(Self::task_template_name)
+        .collect();
+        names.push(PERSONA_TEMPLATE.to_string());
+        names
+    }
+
+    /// Defaults used until a global template or override is saved
+    fn builtin(name: &str) -> Option<PromptTemplate> {
+        let text = |name: &str, required: bool, truncatable: bool| TemplateVariable {
+            name: name.to_string(),
+            kind: VariableKind::Text,
+            required,
+            truncatable,
+        };
+        let (body, variables) = match name {
+            PERSONA_TEMPLATE => (
+                "You are a {{agent_type}} agent with a {{communication_style}} communication style, \
+                 working for your owner on: {{instruction}}\n{{rules}}",
+                vec![
+                    text("agent_type", true, false),
+                    text("communication_style", true, false),
+                    text("instruction", true, true),
+                    TemplateVariable { name: "rules".to_string(), kind: VariableKind::List, required: false, truncatable: true },
+                ],
+            ),
+            "task.CodeAssistant" => (
+                "You are a specialized code assistant. To demonstrate or check logic, \
+                 include a runnable ```rhai block; its output is attached to your answer. {{task}}",
+                vec![text("task", true, true)],
+            ),
+            "task.DataAnalyst" => ("You are a data analyst. Analyze and provide insights for: {{task}}", vec![text("task", true, true)]),
+            "task.ContentCreator" => ("You are a content creator. Create engaging content for: {{task}}", vec![text("task", true, true)]),
+            "task.ProblemSolver" => ("You are a problem solver. Analyze and solve: {{task}}", vec![text("task", true, true)]),
+            "task.Researcher" => (
+                "You are a researcher. Research and provide information about: {{task}}",
+                vec![text("task", true, true)],
+            ),
+            "task.Planner" => ("You are a planner. Create a plan for: {{task}}", vec![text("task", true, true)]),
+            "task.Coordinator" => (
+                "You are a coordinator leading a team of specialist agents. Respond to: {{task}}",
+                vec![text("task", true, true)],
+            ),
+            "task.GeneralAssistant" | "task.Executor" | "task.Custom" => {
+                ("You are a helpful assistant. Help with: {{task}}", vec![text("task", true, true)])
+            }
+            _ => return None,
+        };
+        Some(PromptTemplate {
+            name: name.to_string(),
+            version: 0,
+            owner: None,
+            body: body.to_string(),
+            variables,
+            max_chars: None,
+            note: Some("Built-in default".to_string()),
+            created_by: None,
+            created_at: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(body: &str, max_chars: Option<u32>) -> PromptTemplate {
+        PromptTemplate {
+            name: "t".to_string(),
+            version: 1,
+            owner: None,
+            body: body.to_string(),
+            variables: vec![
+                TemplateVariable { name: "task".to_string(), kind: VariableKind::Text, required: true, truncatable: true },
+                TemplateVariable { name: "n".to_string(), kind: VariableKind::Integer, required: false, truncatable: false },
+            ],
+            max_chars,
+            note: None,
+            created_by: None,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_render_substitutes_once_and_escapes() {
+        let values = HashMap::from([("task".to_string(), TemplateValue::Text("say {{n}}\u{7}".to_string()))]);
+        let rendered = PromptTemplateService::render(&template("\\{{literal}} Do: {{ task }}", None), &values).unwrap();
+        assert_eq!(rendered, "{{literal}} Do: say {{n}}");
+
+        let wrong = HashMap::from([("n".to_string(), TemplateValue::Text("x".to_string()))]);
+        assert!(PromptTemplateService::render(&template("{{task}}", None), &wrong).is_err());
+        assert!(PromptTemplateService::parse("{{task").is_err());
+    }
+
+    #[test]
+    fn test_render_truncates_to_budget() {
+        let values = HashMap::from([
+            ("task".to_string(), TemplateValue::Text("x".repeat(50))),
+            ("n".to_string(), TemplateValue::Integer(7)),
+        ]);
+        let rendered = PromptTemplateService::render(&template("Task {{n}}: {{task}}", Some(20)), &values).unwrap();
+        assert_eq!(rendered.chars().count(), 20);
+        assert!(rendered.starts_with("Task 7: xxx") && rendered.ends_with(TRUNCATION_MARK));
+        assert!(PromptTemplateService::render(&template("Task {{n}}: {{task}}", Some(5)), &values).is_err());
+    }
+
+    #[test]
+    fn test_builtin_task_templates_render() {
+        for name in PromptTemplateService::builtin_names() {
+            let template = PromptTemplateService::builtin(&name).expect("built-in");
+            assert!(PromptTemplateService::parse(&template.body).is_ok(), "{}", name);
+        }
+    }
+}
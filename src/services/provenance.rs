@@ -0,0 +1,65 @@
+use crate::services::knowledge::RetrievedPassage;
+use candid::CandidType;
+use crate::infra::clock::time;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum SourceKind {
+    KnowledgePassage,
+    ToolCall,
+    Attachment,
+}
+
+/// One input that contributed to an answer
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ProvenanceSource {
+    pub kind: SourceKind,
+    pub reference: String,    // "{doc_id}#{chunk_index}", "{tool}#{n}" or an attachment's "document:{doc_id}" / "memory:{key}"
+    pub content_hash: String, // sha256 hex of the content the model saw or the tool ran
+}
+
+/// Where an answer came from, with hashes an auditor can check against the sources later
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Provenance {
+    pub model: String,
+    pub sources: Vec<ProvenanceSource>,
+    pub output_hash: String,
+    pub created_at: u64,
+}
+
+impl Provenance {
+    pub fn new(model: &str, sources: Vec<ProvenanceSource>, output: &str) -> Self {
+        Provenance {
+            model: model.to_string(),
+            sources,
+            output_hash: sha256_hex(output),
+            created_at: time(),
+        }
+    }
+}
+
+impl ProvenanceSource {
+    pub fn passages(passages: &[RetrievedPassage]) -> Vec<ProvenanceSource> {
+        passages
+            .iter()
+            .map(|p| ProvenanceSource {
+                kind: SourceKind::KnowledgePassage,
+                reference: format!("{}#{}", p.doc_id, p.chunk_index),
+                content_hash: sha256_hex(&p.text),
+            })
+            .collect()
+    }
+
+    pub fn tool_call(reference: String, input: &str) -> ProvenanceSource {
+        ProvenanceSource {
+            kind: SourceKind::ToolCall,
+            reference,
+            content_hash: sha256_hex(input),
+        }
+    }
+}
+
+pub fn sha256_hex(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
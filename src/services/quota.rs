@@ -0,0 +1,95 @@
+use crate::domain::instruction::SubscriptionTier;
+use crate::infra::clock::time;
+use crate::infra::guards::{RateLimitStatus, TierLimits};
+use crate::infra::Guards;
+use crate::services::agent_factory::MAX_AGENTS_PER_USER;
+use crate::services::archive::ArchiveService;
+use crate::services::sharding::ShardingService;
+use crate::services::{llm_service, with_state};
+use candid::{CandidType, Principal};
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+
+/// Everything that limits the caller, with the time each limit next resets
+#[derive(Debug, Clone, CandidType)]
+pub struct MyQuota {
+    pub tier: SubscriptionTier,
+    pub daily_token_limit: u64,
+    pub daily_tokens_used: u64,
+    pub daily_tokens_remaining: u64,
+    pub daily_reset_at: u64,
+    pub monthly_token_limit: u64,
+    pub monthly_tokens_used: u64,
+    pub monthly_tokens_remaining: u64,
+    pub monthly_reset_at: u64,
+    pub agents_used: u32, // Owned here, archived in their grace period, and placed on other shards
+    pub agents_limit: u32,
+    pub rate_limits: RateLimitStatus,
+    pub rate_limits_full_at: u64, // When both per-minute buckets are back to their full allowance
+    pub generated_at: u64,
+}
+
+/// Read-only view over the per-minute buckets in Guards, the conversation
+/// token quota, and the agent limit, so clients can show what is left
+/// without tripping a limit to find out.
+pub struct QuotaService;
+
+impl QuotaService {
+    pub fn for_principal(principal: Principal) -> MyQuota {
+        let now = time();
+        let user_id = principal.to_string();
+        let tokens = llm_service().user_quota(principal);
+        let rate_limits = Guards::limits_for(principal);
+        let owned = with_state(|s| s.agents.values().filter(|agent| agent.user_id == user_id).count());
+        let agents_used = owned
+            + ArchiveService::count_in_grace_period(&user_id, now)
+            + ShardingService::routed_agent_count(&user_id);
+
+        MyQuota {
+            tier: rate_limits.tier.clone(),
+            daily_token_limit: tokens.daily_token_limit,
+            daily_tokens_used: tokens.current_daily_usage,
+            daily_tokens_remaining: tokens.daily_token_limit.saturating_sub(tokens.current_daily_usage),
+            daily_reset_at: tokens.next_daily_reset(),
+            monthly_token_limit: tokens.monthly_token_limit,
+            monthly_tokens_used: tokens.current_monthly_usage,
+            monthly_tokens_remaining: tokens.monthly_token_limit.saturating_sub(tokens.current_monthly_usage),
+            monthly_reset_at: tokens.next_monthly_reset(),
+            agents_used: agents_used as u32,
+            agents_limit: MAX_AGENTS_PER_USER as u32,
+            rate_limits_full_at: full_at(&rate_limits, now),
+            rate_limits,
+            generated_at: now,
+        }
+    }
+}
+
+/// Buckets refill continuously, so the slower of the two decides
+fn full_at(status: &RateLimitStatus, now: u64) -> u64 {
+    let TierLimits { requests_per_minute, tokens_per_minute } = status.limits;
+    let refill = |missing: u64, per_minute: u64| missing.saturating_mul(NANOS_PER_MINUTE).div_ceil(per_minute.max(1));
+    let requests = refill(requests_per_minute.saturating_sub(status.requests_remaining) as u64, requests_per_minute as u64);
+    let tokens = refill(tokens_per_minute.saturating_sub(status.tokens_remaining), tokens_per_minute);
+    now + requests.max(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_at_waits_for_the_slower_bucket() {
+        let status = RateLimitStatus {
+            tier: SubscriptionTier::Basic,
+            limits: TierLimits::for_tier(&SubscriptionTier::Basic),
+            requests_remaining: 15,
+            tokens_remaining: 15_000,
+            tier_refreshed_at: 0,
+        };
+        // Half the requests and a quarter of the tokens are missing
+        assert_eq!(full_at(&status, 1_000), 1_000 + NANOS_PER_MINUTE / 2);
+
+        let full = RateLimitStatus { requests_remaining: 30, tokens_remaining: 20_000, ..status };
+        assert_eq!(full_at(&full, 1_000), 1_000);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::domain::{DecodeParams, InferenceRequest};
+use crate::infra::stable::{memory, Cbor, Memory, REDACTION_POLICIES_MEMORY_ID};
+use crate::infra::{Lane, Metrics};
+use crate::services::InferenceService;
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use regex::{Captures, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    // Keyed by the tenant's principal
+    static POLICIES: RefCell<StableBTreeMap<String, Cbor<RedactionPolicy>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(REDACTION_POLICIES_MEMORY_ID)));
+    // Compiled on first use, in the order they are applied
+    static BUILTIN: Vec<(PiiCategory, Regex)> = builtin_patterns();
+}
+
+const MAX_CUSTOM_PATTERNS: usize = 20;
+const MAX_PATTERN_CHARS: usize = 200;
+const MAX_LABEL_LEN: usize = 32;
+const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+// Shorter terms from LLM detection are too likely to hit unrelated words
+const MIN_DETECTED_CHARS: usize = 3;
+const DETECTED_LABEL: &str = "PII";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum PiiCategory {
+    Email,
+    CreditCard, // Digit runs that pass the Luhn check
+    Iban,
+    NationalId, // US social security numbers
+    IpAddress,
+    Phone,
+    Profanity,
+}
+
+impl PiiCategory {
+    fn label(&self) -> &'static str {
+        match self {
+            PiiCategory::Email => "EMAIL",
+            PiiCategory::CreditCard => "CREDIT_CARD",
+            PiiCategory::Iban => "IBAN",
+            PiiCategory::NationalId => "NATIONAL_ID",
+            PiiCategory::IpAddress => "IP_ADDRESS",
+            PiiCategory::Phone => "PHONE",
+            PiiCategory::Profanity => "PROFANITY",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct CustomPattern {
+    pub label: String, // Shown in the marker, as [REDACTED:label]
+    pub pattern: String,
+}
+
+#[derive(Debug, Clone, Deserialize, CandidType)]
+pub struct RedactionPolicyInput {
+    pub categories: Vec<PiiCategory>,
+    pub custom_patterns: Vec<CustomPattern>,
+    pub llm_detection: bool, // Also ask the LLM for names, addresses and other PII the patterns miss
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct RedactionPolicy {
+    pub categories: Vec<PiiCategory>,
+    pub custom_patterns: Vec<CustomPattern>,
+    pub llm_detection: bool,
+    pub managed: bool,         // Set by an admin; the tenant cannot change or clear it
+    pub live_unredacted: bool, // The tenant's opt-in to unredacted replies; what is stored is redacted either way
+    pub updated_at: u64,
+}
+
+/// Scrubs PII and profanity from conversation messages and memory values
+/// before they are persisted, replacing each match with a marker such as
+/// `[REDACTED:EMAIL]`. Each tenant (the owning principal) has its own policy;
+/// compliance admins can set a managed policy the tenant cannot loosen.
+/// Replies are returned redacted too unless the tenant opts in to live
+/// unredacted replies.
+pub struct RedactionService;
+
+impl RedactionService {
+    /// The tenant's own policy; fails while an admin manages it
+    pub fn set(tenant: &Principal, input: RedactionPolicyInput) -> Result<RedactionPolicy, String> {
+        if Self::get(tenant).is_some_and(|p| p.managed) {
+            return Err("Redaction policy is managed by an administrator".to_string());
+        }
+        Self::write(tenant, input, false)
+    }
+
+    /// Admin override; None removes the policy and hands control back to the tenant
+    pub fn set_managed(tenant: &Principal, input: Option<RedactionPolicyInput>) -> Result<Option<RedactionPolicy>, String> {
+        match input {
+            Some(input) => Self::write(tenant, input, true).map(Some),
+            None => {
+                POLICIES.with(|p| p.borrow_mut().remove(&tenant.to_text()));
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn clear(tenant: &Principal) -> Result<(), String> {
+        match Self::get(tenant) {
+            Some(policy) if policy.managed => Err("Redaction policy is managed by an administrator".to_string()),
+            Some(_) => {
+                POLICIES.with(|p| p.borrow_mut().remove(&tenant.to_text()));
+                Ok(())
+            }
+            None => Err("No redaction policy is set".to_string()),
+        }
+    }
+
+    pub fn set_live_unredacted(tenant: &Principal, enabled: bool) -> Result<RedactionPolicy, String> {
+        let mut policy = Self::get(tenant).ok_or_else(|| "No redaction policy is set".to_string())?;
+        policy.live_unredacted = enabled;
+        policy.updated_at = time();
+        POLICIES.with(|p| p.borrow_mut().insert(tenant.to_text(), Cbor(policy.clone())));
+        Ok(policy)
+    }
+
+    pub fn get(tenant: &Principal) -> Option<RedactionPolicy> {
+        POLICIES.with(|p| p.borrow().get(&tenant.to_text())).map(|policy| policy.0)
+    }
+
+    /// Whether replies go back to the tenant as generated
+    pub fn live_unredacted(tenant: &Principal) -> bool {
+        Self::get(tenant).map_or(true, |policy| policy.live_unredacted)
+    }
+
+    /// `text` as it may be persisted for `tenant`. When LLM detection fails
+    /// only the patterns are applied.
+    pub async fn redact(tenant: &Principal, text: &str) -> String {
+        let Some(policy) = Self::get(tenant) else {
+            return text.to_string();
+        };
+        let detected = if policy.llm_detection && !text.trim().is_empty() {
+            Self::detect(tenant, text).await
+        } else {
+            Vec::new()
+        };
+        let custom = policy
+            .custom_patterns
+            .iter()
+            .filter_map(|custom| Some((custom.label.clone(), compile(&custom.pattern).ok()?)))
+            .collect::<Vec<_>>();
+        let redacted = apply(text, &detected, &policy.categories, &custom);
+        if redacted != text {
+            Metrics::increment_counter("redactions_total");
+        }
+        redacted
+    }
+
+    /// Memory values are redacted when they are UTF-8 text and stored as given otherwise
+    pub async fn redact_bytes(tenant: &Principal, data: Vec<u8>) -> Vec<u8> {
+        match String::from_utf8(data) {
+            Ok(text) => Self::redact(tenant, &text).await.into_bytes(),
+            Err(e) => e.into_bytes(),
+        }
+    }
+
+    async fn detect(tenant: &Principal, text: &str) -> Vec<String> {
+        let request = InferenceRequest {
+            seed: None,
+            prompt: format!(
+                "List every personal name, street address, date of birth, account number or other detail \
+                 that identifies a person in the text below, one per line, exactly as written. \
+                 Reply NONE if there are none.\n\nText:\n{}",
+                text
+            ),
+            decode_params: DecodeParams::default(),
+            msg_id: format!("redaction-{}", time()),
+            model_id: None,
+            structured_output: None,
+        };
+        match InferenceService::process_inference_for(request, *tenant, Lane::Standard).await {
+            Ok(response) => parse_detected(&response.generated_text, text),
+            Err(e) => {
+                ic_cdk::println!("PII detection unavailable, applying patterns only: {}", e);
+                Metrics::increment_counter("redaction_detection_failed_total");
+                Vec::new()
+            }
+        }
+    }
+
+    fn write(tenant: &Principal, input: RedactionPolicyInput, managed: bool) -> Result<RedactionPolicy, String> {
+        if input.custom_patterns.len() > MAX_CUSTOM_PATTERNS {
+            return Err(format!("Too many custom patterns. Maximum: {}", MAX_CUSTOM_PATTERNS));
+        }
+        for custom in &input.custom_patterns {
+            if custom.label.is_empty()
+                || custom.label.len() > MAX_LABEL_LEN
+                || !custom.label.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+            {
+                return Err(format!("Pattern labels are 1 to {} characters of A-Z, 0-9 and '_'", MAX_LABEL_LEN));
+            }
+            if custom.pattern.chars().count() > MAX_PATTERN_CHARS {
+                return Err(format!("Pattern {} exceeds {} characters", custom.label, MAX_PATTERN_CHARS));
+            }
+            compile(&custom.pattern).map_err(|e| format!("Pattern {} is invalid: {}", custom.label, e))?;
+        }
+        let mut categories = input.categories;
+        categories.dedup();
+        let policy = RedactionPolicy {
+            categories,
+            custom_patterns: input.custom_patterns,
+            llm_detection: input.llm_detection,
+            managed,
+            // The opt-in belongs to the tenant and survives policy changes
+            live_unredacted: Self::get(tenant).is_some_and(|p| p.live_unredacted),
+            updated_at: time(),
+        };
+        POLICIES.with(|p| p.borrow_mut().insert(tenant.to_text(), Cbor(policy.clone())));
+        Ok(policy)
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).size_limit(PATTERN_SIZE_LIMIT).build()
+}
+
+fn builtin_patterns() -> Vec<(PiiCategory, Regex)> {
+    [
+        (PiiCategory::Email, r"(?i)\b[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,}\b"),
+        (PiiCategory::CreditCard, r"\b(?:\d[ -]?){12,18}\d\b"),
+        (PiiCategory::Iban, r"\b[A-Z]{2}\d{2}(?: ?[A-Z0-9]{4}){3,7}(?: ?[A-Z0-9]{1,3})?\b"),
+        (PiiCategory::NationalId, r"\b\d{3}-\d{2}-\d{4}\b"),
+        (PiiCategory::IpAddress, r"\b(?:(?:25[0-5]|2[0-4]\d|1?\d?\d)\.){3}(?:25[0-5]|2[0-4]\d|1?\d?\d)\b"),
+        (PiiCategory::Phone, r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{3}\)|\b\d{3})[ .-]?\d{3}[ .-]\d{4}\b"),
+        (
+            PiiCategory::Profanity,
+            r"(?i)\b(?:fuck(?:s|ed|er|ing)?|motherfucker|shit(?:s|ty)?|bullshit|bitch(?:es)?|bastards?|assholes?|cunts?|dickheads?)\b",
+        ),
+    ]
+    .into_iter()
+    .map(|(category, pattern)| (category, Regex::new(pattern).expect("built-in pattern compiles")))
+    .collect()
+}
+
+/// Replace detected terms, then every enabled category, then custom patterns
+fn apply(text: &str, detected: &[String], categories: &[PiiCategory], custom: &[(String, Regex)]) -> String {
+    let mut redacted = text.to_string();
+    for term in detected {
+        redacted = redacted.replace(term.as_str(), &marker(DETECTED_LABEL));
+    }
+    BUILTIN.with(|builtin| {
+        for (category, regex) in builtin.iter().filter(|(category, _)| categories.contains(category)) {
+            let replaced = regex.replace_all(&redacted, |caps: &Captures| {
+                let found = &caps[0];
+                if *category == PiiCategory::CreditCard && !luhn_valid(found) {
+                    return found.to_string();
+                }
+                marker(category.label())
+            });
+            redacted = replaced.into_owned();
+        }
+    });
+    for (label, regex) in custom {
+        redacted = regex.replace_all(&redacted, marker(label).as_str()).into_owned();
+    }
+    redacted
+}
+
+fn marker(label: &str) -> String {
+    format!("[REDACTED:{}]", label)
+}
+
+fn luhn_valid(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| if i % 2 == 1 { if d * 2 > 9 { d * 2 - 9 } else { d * 2 } } else { d })
+        .sum();
+    digits.len() >= 13 && sum % 10 == 0
+}
+
+/// Terms from the detection reply that really occur in `text`, longest
+/// first so a full name is replaced before a part of it
+fn parse_detected(reply: &str, text: &str) -> Vec<String> {
+    if reply.trim().to_uppercase().starts_with("NONE") {
+        return Vec::new();
+    }
+    let mut terms: Vec<String> = reply
+        .lines()
+        .map(|line| {
+            let line = line.trim().trim_start_matches(['-', '*', '•']).trim_start();
+            // Numbering such as "1." or "2)", but not the number of a street address
+            let line = match line.split_once(['.', ')']) {
+                Some((n, rest)) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => rest,
+                _ => line,
+            };
+            line.trim().trim_matches('"').to_string()
+        })
+        .filter(|term| term.chars().count() >= MIN_DETECTED_CHARS && text.contains(term.as_str()))
+        .collect();
+    terms.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    terms.dedup();
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_redacts_enabled_categories_only() {
+        let text = "Mail ana@example.org or call +1 415-555-0132 from 10.0.0.7. Card 4111 1111 1111 1111, \
+                    order 1234 5678 9012 3456. SSN 078-05-1120. Damn this shit.";
+        let all = [
+            PiiCategory::Email,
+            PiiCategory::CreditCard,
+            PiiCategory::NationalId,
+            PiiCategory::IpAddress,
+            PiiCategory::Phone,
+            PiiCategory::Profanity,
+        ];
+        assert_eq!(
+            apply(text, &[], &all, &[]),
+            "Mail [REDACTED:EMAIL] or call [REDACTED:PHONE] from [REDACTED:IP_ADDRESS]. Card [REDACTED:CREDIT_CARD], \
+             order 1234 5678 9012 3456. SSN [REDACTED:NATIONAL_ID]. Damn this [REDACTED:PROFANITY]."
+        );
+        assert_eq!(apply(text, &[], &[PiiCategory::Email], &[]).matches("[REDACTED").count(), 1);
+    }
+
+    #[test]
+    fn test_detected_terms_and_custom_patterns() {
+        let text = "Ana Lima lives at 12 Rua Augusta; ticket OHMS-4821.";
+        let detected = parse_detected("1. Ana Lima\n- \"12 Rua Augusta\"\n- Ana\n- Bob Stone\n- AL", text);
+        assert_eq!(detected, vec!["12 Rua Augusta".to_string(), "Ana Lima".to_string(), "Ana".to_string()]);
+        let custom = [("TICKET".to_string(), compile(r"OHMS-\d+").unwrap())];
+        assert_eq!(
+            apply(text, &detected, &[], &custom),
+            "[REDACTED:PII] lives at [REDACTED:PII]; ticket [REDACTED:TICKET]."
+        );
+        assert!(parse_detected("NONE", text).is_empty());
+    }
+}
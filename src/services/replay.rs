@@ -0,0 +1,180 @@
+use crate::domain::DecodeParams;
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentFactory, AgentTaskResult, TaskOutcome};
+use crate::services::experiments::ExperimentVariant;
+use crate::services::prompt_templates::{PromptTemplateService, TemplateScope, TemplateValue};
+use crate::services::{with_state, ModelPoolService, TaskHistoryService};
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// Longer outputs are diffed on their first lines only
+const MAX_DIFF_LINES: usize = 400;
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct TemplateVersion {
+    pub scope: TemplateScope, // Owner reads the agent owner's override
+    pub version: u32,         // 0 for the built-in template
+}
+
+/// What to change when replaying a task; fields left out keep the agent's
+/// current settings
+#[derive(Debug, Clone, Default, CandidType, Deserialize)]
+pub struct ReplayOverrides {
+    pub model_id: Option<String>, // Must be the bound model or in the serving pool
+    pub prompt_template: Option<TemplateVersion>,
+    pub decode_params: Option<DecodeParams>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, CandidType)]
+pub enum DiffOp {
+    Same,
+    Removed, // Only in the original output
+    Added,   // Only in the replayed output
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ReplayReport {
+    pub original: AgentTaskResult,
+    pub replay: AgentTaskResult,
+    pub identical: bool,
+    pub text_diff: Vec<DiffLine>,
+    pub diff_truncated: bool, // Outputs were longer than the diffed lines
+    pub tokens_delta: i64,     // Replay minus original
+    pub latency_delta_ms: i64, // Replay minus original
+}
+
+/// Re-executes a task from the agent's history with a different model,
+/// prompt template version or decode params, and compares the outcome with
+/// the original. The LLM takes no seed, so differences also include its own
+/// sampling variance. Replays only count toward budgets; they are not added
+/// to task history.
+pub struct ReplayService;
+
+impl ReplayService {
+    pub async fn replay(agent_id: &str, task_id: &str, overrides: ReplayOverrides) -> Result<ReplayReport, String> {
+        let record = TaskHistoryService::get_task_result(agent_id, task_id)?;
+        if record.result.outcome == TaskOutcome::PendingApproval {
+            return Err(format!("Task {} never ran, so there is nothing to compare against", task_id));
+        }
+        if let Some(model_id) = &overrides.model_id {
+            if !with_state(|s| ModelPoolService::binding(s, model_id).is_some()) {
+                return Err(format!("Model {} is neither bound nor in the serving pool", model_id));
+            }
+        }
+        if let Some(params) = &overrides.decode_params {
+            params.check_supported()?;
+        }
+
+        let agent = AgentFactory::find_agent(agent_id)?;
+        let prompt_template = match &overrides.prompt_template {
+            Some(selected) => {
+                let owner = match selected.scope {
+                    TemplateScope::Global => None,
+                    TemplateScope::Owner => Some(Principal::from_text(&agent.user_id).map_err(|e| e.to_string())?),
+                };
+                let name = PromptTemplateService::task_template_name(&agent.analysis.agent_configuration.agent_type);
+                let template = PromptTemplateService::version(&name, owner, selected.version)?;
+                let values = HashMap::from([("task".to_string(), TemplateValue::Text(record.task.description.clone()))]);
+                Some(PromptTemplateService::render(&template, &values)?)
+            }
+            None => None,
+        };
+        let variant = ExperimentVariant {
+            name: "replay".to_string(),
+            traffic_percent: 0,
+            prompt_template,
+            model_id: overrides.model_id,
+            decode_params: overrides.decode_params,
+        };
+
+        let replay = AgentFactory::rerun_task(agent_id, &record.task, &variant).await?;
+        Metrics::increment_counter("tasks_replayed_total");
+        Ok(Self::compare(record.result, replay))
+    }
+
+    fn compare(original: AgentTaskResult, replay: AgentTaskResult) -> ReplayReport {
+        let (text_diff, diff_truncated) = Self::diff_lines(&original.result, &replay.result);
+        ReplayReport {
+            identical: original.result == replay.result,
+            text_diff,
+            diff_truncated,
+            tokens_delta: replay.tokens_used as i64 - original.tokens_used as i64,
+            latency_delta_ms: replay.execution_time_ms as i64 - original.execution_time_ms as i64,
+            original,
+            replay,
+        }
+    }
+
+    /// Line diff from the longest common subsequence of the two texts
+    fn diff_lines(original: &str, replay: &str) -> (Vec<DiffLine>, bool) {
+        let a: Vec<&str> = original.lines().collect();
+        let b: Vec<&str> = replay.lines().collect();
+        let truncated = a.len() > MAX_DIFF_LINES || b.len() > MAX_DIFF_LINES;
+        let (a, b) = (&a[..a.len().min(MAX_DIFF_LINES)], &b[..b.len().min(MAX_DIFF_LINES)]);
+
+        // common[i][j]: LCS length of a[i..] and b[j..]
+        let mut common = vec![vec![0u16; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                common[i][j] = if a[i] == b[j] {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+
+        let line = |op, text: &str| DiffLine { op, text: text.to_string() };
+        let mut diff = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            if a[i] == b[j] {
+                diff.push(line(DiffOp::Same, a[i]));
+                i += 1;
+                j += 1;
+            } else if common[i + 1][j] >= common[i][j + 1] {
+                diff.push(line(DiffOp::Removed, a[i]));
+                i += 1;
+            } else {
+                diff.push(line(DiffOp::Added, b[j]));
+                j += 1;
+            }
+        }
+        diff.extend(a[i..].iter().map(|text| line(DiffOp::Removed, text)));
+        diff.extend(b[j..].iter().map(|text| line(DiffOp::Added, text)));
+        (diff, truncated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines_keeps_common_lines() {
+        let (diff, truncated) = ReplayService::diff_lines("intro\nold step\nsummary", "intro\nnew step\nsummary\nextra");
+        let ops: Vec<(DiffOp, &str)> = diff.iter().map(|l| (l.op, l.text.as_str())).collect();
+        assert_eq!(
+            ops,
+            vec![
+                (DiffOp::Same, "intro"),
+                (DiffOp::Removed, "old step"),
+                (DiffOp::Added, "new step"),
+                (DiffOp::Same, "summary"),
+                (DiffOp::Added, "extra"),
+            ]
+        );
+        assert!(!truncated);
+
+        let long = "line\n".repeat(MAX_DIFF_LINES + 1);
+        let (diff, truncated) = ReplayService::diff_lines(&long, &long);
+        assert!(truncated && diff.len() == MAX_DIFF_LINES && diff.iter().all(|l| l.op == DiffOp::Same));
+    }
+}
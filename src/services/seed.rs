@@ -0,0 +1,114 @@
+use crate::infra::clock::time;
+use crate::infra::stable::{memory, Cbor, Memory, SEEDED_OUTPUTS_MEMORY_ID, SEEDED_OUTPUT_ORDER_MEMORY_ID};
+use crate::infra::Metrics;
+use candid::Principal;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+thread_local! {
+    // sha256 of a seeded request -> the output first produced for it
+    static OUTPUTS: RefCell<StableBTreeMap<String, Cbor<SeededOutput>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SEEDED_OUTPUTS_MEMORY_ID)));
+    // "{recorded_at:020}/{key}", oldest first
+    static ORDER: RefCell<StableBTreeMap<String, u8, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SEEDED_OUTPUT_ORDER_MEMORY_ID)));
+}
+
+const MAX_SEEDED_OUTPUTS: u64 = 10_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SeededOutput {
+    generated_text: String,
+    recorded_at: u64,
+}
+
+/// Stable seeds derived from request identity, wherever a stable pseudo-random
+/// order is needed (experiment buckets, shard placement), and seeded
+/// inference. The LLM canister takes no seed, so a seeded request is made
+/// deterministic by recording its first output: an identical request from the
+/// same caller (seed, model, fitted prompt and max_tokens) gets that output
+/// back. The oldest recordings are dropped past MAX_SEEDED_OUTPUTS.
+pub struct Seed;
+
+impl Seed {
+    /// Recording key of a seeded request
+    pub fn output_key(caller: &Principal, seed: u64, model_id: Option<&str>, prompt: &str, max_tokens: Option<u32>) -> String {
+        let mut hasher = Sha256::new();
+        for part in [
+            caller.to_text(),
+            seed.to_string(),
+            model_id.unwrap_or_default().to_string(),
+            max_tokens.map_or(String::new(), |m| m.to_string()),
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update([0u8]);
+        }
+        hasher.update(prompt.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    pub fn recorded_output(key: &str) -> Option<String> {
+        OUTPUTS.with(|o| o.borrow().get(&key.to_string()).map(|output| output.0.generated_text))
+    }
+
+    /// Keep the first output of a seeded request; a later one for the same key
+    /// (two identical requests in flight at once) is dropped
+    pub fn record_output(key: String, generated_text: &str) {
+        if OUTPUTS.with(|o| o.borrow().contains_key(&key)) {
+            return;
+        }
+        let now = time();
+        ORDER.with(|order| {
+            let mut order = order.borrow_mut();
+            while order.len() >= MAX_SEEDED_OUTPUTS {
+                let Some((oldest, _)) = order.first_key_value() else { break };
+                order.remove(&oldest);
+                if let Some((_, evicted)) = oldest.split_once('/') {
+                    OUTPUTS.with(|o| o.borrow_mut().remove(&evicted.to_string()));
+                }
+            }
+            order.insert(format!("{:020}/{}", now, key), 0);
+        });
+        OUTPUTS.with(|o| {
+            o.borrow_mut().insert(key, Cbor(SeededOutput { generated_text: generated_text.to_string(), recorded_at: now }))
+        });
+        Metrics::increment_counter("seeded_outputs_recorded_total");
+    }
+
+    /// Stable 64-bit seed derived from string parts (sha256, first 8 bytes)
+    pub fn derive(parts: &[&str]) -> u64 {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part.as_bytes());
+            hasher.update([0u8]);
+        }
+        let digest = hasher.finalize();
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        u64::from_be_bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_key_covers_every_part() {
+        let caller = Principal::anonymous();
+        let key = Seed::output_key(&caller, 7, Some("m"), "hello", Some(64));
+        assert_eq!(key, Seed::output_key(&caller, 7, Some("m"), "hello", Some(64)));
+        assert_ne!(key, Seed::output_key(&caller, 8, Some("m"), "hello", Some(64)));
+        assert_ne!(key, Seed::output_key(&caller, 7, None, "hello", Some(64)));
+        assert_ne!(key, Seed::output_key(&caller, 7, Some("m"), "hello", Some(65)));
+        assert_ne!(key, Seed::output_key(&caller, 7, Some("m"), "hello!", Some(64)));
+    }
+
+    #[test]
+    fn test_derive_is_stable() {
+        assert_eq!(Seed::derive(&["msg-1", "hello"]), Seed::derive(&["msg-1", "hello"]));
+        assert_ne!(Seed::derive(&["msg-1", "hello"]), Seed::derive(&["msg-1hello"]));
+    }
+}
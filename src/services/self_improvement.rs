@@ -0,0 +1,241 @@
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentFactory, AutonomousAgent, TaskOutcome};
+use crate::services::approvals::ApprovalDecision;
+use crate::services::events::{AgentEventKind, EventService};
+use crate::services::feedback::TaskFeedback;
+use crate::services::task_history::TaskRecord;
+use crate::services::{with_state, with_state_mut, TaskHistoryService};
+use candid::CandidType;
+use crate::infra::clock::time;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub(crate) const REVIEW_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+// Tasks finished since the last proposal before an agent reviews itself again
+const MIN_TASKS_FOR_REVIEW: usize = 10;
+const MAX_PROPOSALS_PER_AGENT: usize = 10;
+const FAILURE_RATE_THRESHOLD: f32 = 0.3;
+const TIMEOUT_RATE_THRESHOLD: f32 = 0.2;
+const LOW_RATING_THRESHOLD: f32 = 2.5;
+const MIN_RATINGS: usize = 3;
+const MIN_MAX_TOKENS: u32 = 256;
+// Comment phrases that recur across ratings, and the behavior rule that answers them
+const COMMENT_RULES: &[(&[&str], &str)] = &[
+    (&["too long", "verbose", "wordy"], "Keep answers concise and lead with the result."),
+    (&["too short", "more detail", "incomplete"], "Give complete answers with the detail needed to act on them."),
+    (&["wrong", "incorrect", "inaccurate"], "Double-check facts and say when you are unsure."),
+];
+// Ratings naming a phrase of a rule before it is proposed
+const MIN_COMMENT_MATCHES: usize = 2;
+
+/// One field of the agent's configuration, before and after
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum ConfigChange {
+    AddBehaviorRule { rule: String },
+    SetSelfCritique { from: bool, to: bool },
+    SetLearnFromFeedback { from: bool, to: bool },
+    SetMaxTokens { from: u32, to: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum ProposalState {
+    PendingOwnerApproval,
+    Accepted,
+    Rejected,
+    Superseded, // A later review replaced it before the owner answered
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ConfigProposal {
+    pub proposal_id: String,
+    pub created_at: u64,
+    pub tasks_reviewed: u32,
+    pub changes: Vec<ConfigChange>,
+    pub rationale: Vec<String>, // Why, one line per change
+    pub state: ProposalState,
+    pub decided_at: Option<u64>,
+}
+
+/// Agents with self_improvement enabled review their recent task history
+/// and ratings on a timer and propose configuration changes as a diff. A
+/// proposal waits for the owner: accepting applies it as long as the fields
+/// it changes still hold their `from` values, rejecting just closes it.
+/// Only one proposal is pending per agent; a new one supersedes it.
+pub struct SelfImprovementService;
+
+impl SelfImprovementService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(REVIEW_INTERVAL, || {
+            let agent_ids: Vec<String> = with_state(|s| {
+                s.agents
+                    .values()
+                    .filter(|a| a.analysis.agent_configuration.self_improvement)
+                    .map(|a| a.agent_id.clone())
+                    .collect()
+            });
+            for agent_id in agent_ids {
+                let _ = Self::review(&agent_id);
+            }
+        });
+    }
+
+    /// Review the tasks finished since the last proposal; None when there
+    /// are too few of them or nothing to change
+    pub fn review(agent_id: &str) -> Result<Option<ConfigProposal>, String> {
+        let mut agent = AgentFactory::find_agent(agent_id)?;
+        let since = agent.config_proposals.last().map_or(0, |p| p.created_at);
+        let records: Vec<TaskRecord> =
+            TaskHistoryService::live_records(agent_id).into_iter().filter(|r| r.recorded_at > since).collect();
+        if records.len() < MIN_TASKS_FOR_REVIEW {
+            return Ok(None);
+        }
+        let Some((changes, rationale)) = propose(&agent, &records) else {
+            return Ok(None);
+        };
+
+        let now = time();
+        for pending in agent.config_proposals.iter_mut().filter(|p| p.state == ProposalState::PendingOwnerApproval) {
+            pending.state = ProposalState::Superseded;
+            pending.decided_at = Some(now);
+        }
+        let proposal = ConfigProposal {
+            proposal_id: format!("proposal-{}-{}", agent_id, now),
+            created_at: now,
+            tasks_reviewed: records.len() as u32,
+            changes,
+            rationale,
+            state: ProposalState::PendingOwnerApproval,
+            decided_at: None,
+        };
+        agent.config_proposals.push(proposal.clone());
+        if agent.config_proposals.len() > MAX_PROPOSALS_PER_AGENT {
+            let overflow = agent.config_proposals.len() - MAX_PROPOSALS_PER_AGENT;
+            agent.config_proposals.drain(..overflow);
+        }
+        EventService::publish(Some(agent_id), &agent.user_id, AgentEventKind::ConfigProposed {
+            proposal_id: proposal.proposal_id.clone(),
+        });
+        Metrics::increment_counter("config_proposals_total");
+        Self::save(agent);
+        Ok(Some(proposal))
+    }
+
+    /// Retained proposals, oldest first
+    pub fn list(agent_id: &str) -> Result<Vec<ConfigProposal>, String> {
+        Ok(AgentFactory::find_agent(agent_id)?.config_proposals)
+    }
+
+    pub fn respond(agent_id: &str, owner: &str, proposal_id: &str, decision: ApprovalDecision) -> Result<ConfigProposal, String> {
+        let mut agent = AgentFactory::authorize(agent_id, owner)?;
+        let index = agent
+            .config_proposals
+            .iter()
+            .position(|p| p.proposal_id == proposal_id && p.state == ProposalState::PendingOwnerApproval)
+            .ok_or_else(|| format!("No pending proposal {} on agent {}", proposal_id, agent_id))?;
+        let changes = agent.config_proposals[index].changes.clone();
+        let state = match decision {
+            ApprovalDecision::Approve => {
+                for change in &changes {
+                    apply(&mut agent, change)?;
+                }
+                Metrics::increment_counter("config_proposals_accepted_total");
+                ProposalState::Accepted
+            }
+            ApprovalDecision::Reject => {
+                Metrics::increment_counter("config_proposals_rejected_total");
+                ProposalState::Rejected
+            }
+        };
+        let proposal = &mut agent.config_proposals[index];
+        proposal.state = state;
+        proposal.decided_at = Some(time());
+        let proposal = proposal.clone();
+        Self::save(agent);
+        Ok(proposal)
+    }
+
+    fn save(agent: AutonomousAgent) {
+        with_state_mut(|state| {
+            state.agents.insert(agent.agent_id.clone(), agent);
+        });
+    }
+}
+
+/// Changes the records and ratings call for, with a reason for each
+fn propose(agent: &AutonomousAgent, records: &[TaskRecord]) -> Option<(Vec<ConfigChange>, Vec<String>)> {
+    let configuration = &agent.analysis.agent_configuration;
+    let mut changes = Vec::new();
+    let mut rationale = Vec::new();
+    let total = records.len() as f32;
+
+    let failures = records.iter().filter(|r| !r.result.success).count();
+    if !configuration.self_critique && failures as f32 / total > FAILURE_RATE_THRESHOLD {
+        changes.push(ConfigChange::SetSelfCritique { from: false, to: true });
+        rationale.push(format!("{} of the last {} tasks failed; critique answers before returning them", failures, records.len()));
+    }
+
+    let timeouts = records.iter().filter(|r| r.result.outcome == TaskOutcome::TimedOut).count();
+    let max_tokens = agent.config.max_tokens;
+    if timeouts as f32 / total > TIMEOUT_RATE_THRESHOLD && max_tokens > MIN_MAX_TOKENS {
+        let to = (max_tokens * 3 / 4).max(MIN_MAX_TOKENS);
+        changes.push(ConfigChange::SetMaxTokens { from: max_tokens, to });
+        rationale.push(format!("{} of the last {} tasks ran past their deadline; shorter answers finish sooner", timeouts, records.len()));
+    }
+
+    let since = records.first().map_or(0, |r| r.recorded_at);
+    let ratings: Vec<&TaskFeedback> = agent.feedback.iter().filter(|f| f.submitted_at >= since).collect();
+    let average = ratings.iter().map(|f| f.rating as f32).sum::<f32>() / ratings.len().max(1) as f32;
+    if !configuration.learn_from_feedback && ratings.len() >= MIN_RATINGS && average <= LOW_RATING_THRESHOLD {
+        changes.push(ConfigChange::SetLearnFromFeedback { from: false, to: true });
+        rationale.push(format!("Recent tasks average {:.1} stars; quote the owner's comments in task prompts", average));
+    }
+
+    for (phrases, rule) in COMMENT_RULES {
+        let matches = ratings
+            .iter()
+            .filter_map(|f| f.comments.as_deref())
+            .filter(|c| {
+                let comment = c.to_lowercase();
+                phrases.iter().any(|p| comment.contains(p))
+            })
+            .count();
+        if matches >= MIN_COMMENT_MATCHES && !configuration.behavior_rules.iter().any(|r| r == rule) {
+            changes.push(ConfigChange::AddBehaviorRule { rule: rule.to_string() });
+            rationale.push(format!("{} ratings said \"{}\"", matches, phrases[0]));
+        }
+    }
+
+    (!changes.is_empty()).then_some((changes, rationale))
+}
+
+/// Fails without touching anything else when the field moved since the proposal
+fn apply(agent: &mut AutonomousAgent, change: &ConfigChange) -> Result<(), String> {
+    let configuration = &mut agent.analysis.agent_configuration;
+    let stale = || Err(format!("The agent's configuration changed since the proposal ({:?})", change));
+    match change {
+        ConfigChange::AddBehaviorRule { rule } => {
+            if !configuration.behavior_rules.contains(rule) {
+                configuration.behavior_rules.push(rule.clone());
+            }
+        }
+        ConfigChange::SetSelfCritique { from, to } => {
+            if configuration.self_critique != *from {
+                return stale();
+            }
+            configuration.self_critique = *to;
+        }
+        ConfigChange::SetLearnFromFeedback { from, to } => {
+            if configuration.learn_from_feedback != *from {
+                return stale();
+            }
+            configuration.learn_from_feedback = *to;
+        }
+        ConfigChange::SetMaxTokens { from, to } => {
+            if agent.config.max_tokens != *from {
+                return stale();
+            }
+            agent.config.max_tokens = *to;
+        }
+    }
+    Ok(())
+}
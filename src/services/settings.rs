@@ -0,0 +1,148 @@
+use crate::domain::{AgentConfig, AgentInitArgs};
+use crate::infra::stable::{memory, Cbor, Memory, CANISTER_SETTINGS_MEMORY_ID};
+use crate::services::{with_state, with_state_mut};
+use candid::{CandidType, Principal};
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    static SETTINGS: RefCell<StableBTreeMap<u8, Cbor<CanisterSettings>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CANISTER_SETTINGS_MEMORY_ID)));
+}
+
+const SETTINGS_KEY: u8 = 0;
+// Longer windows add more latency than batching saves
+const MAX_BATCHING_WINDOW_MS: u64 = 2_000;
+
+/// Deployment settings that survive upgrades. Written through on every change.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, CandidType)]
+pub struct CanisterSettings {
+    pub config: AgentConfig,
+    pub admins: Vec<Principal>,
+    // ic-llm 1.1 always calls the mainnet LLM canister; this records which one
+    // the deployment expects so a mismatch shows up in get_settings
+    pub llm_canister_id: Option<String>,
+    // Sealed: set once model_repo_canister_id is known and never cleared, so a
+    // later set_config cannot leave binds without a repository
+    pub configured: bool,
+}
+
+pub struct SettingsService;
+
+impl SettingsService {
+    /// Apply init or upgrade arguments on top of the settings saved before the
+    /// upgrade. Fields left out of the arguments keep their saved values.
+    pub fn apply_init_args(args: Option<AgentInitArgs>) -> Result<(), String> {
+        let mut settings = Self::load();
+        if let Some(args) = args {
+            if let Some(config) = args.config {
+                Self::check_sealed(&settings, &config)?;
+                settings.config = config;
+            }
+            if let Some(economics) = args.economics_canister_id {
+                settings.config.economics_canister_id = Some(economics);
+            }
+            if !args.admins.is_empty() {
+                settings.admins = args.admins;
+            }
+            if args.llm_canister_id.is_some() {
+                settings.llm_canister_id = args.llm_canister_id;
+            }
+        }
+        Self::validate_config(&settings.config)?;
+        if settings.admins.contains(&Principal::anonymous()) {
+            return Err("The anonymous principal cannot be an admin".to_string());
+        }
+        if let Some(llm) = &settings.llm_canister_id {
+            Self::parse_canister_id("llm_canister_id", llm)?;
+        }
+        Self::save(settings);
+        Ok(())
+    }
+
+    pub fn set_config(config: AgentConfig) -> Result<(), String> {
+        Self::validate_config(&config)?;
+        let mut settings = Self::load();
+        Self::check_sealed(&settings, &config)?;
+        settings.config = config;
+        Self::save(settings);
+        Ok(())
+    }
+
+    /// Applies to upgrade arguments as much as to set_config
+    fn check_sealed(settings: &CanisterSettings, config: &AgentConfig) -> Result<(), String> {
+        if settings.configured && config.model_repo_canister_id.is_empty() {
+            return Err("model_repo_canister_id is sealed once configured and cannot be cleared".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn settings() -> CanisterSettings {
+        Self::load()
+    }
+
+    pub fn is_configured() -> bool {
+        Self::load().configured
+    }
+
+    /// Admins named at deploy time, plus the canister's controllers
+    pub fn is_admin(principal: &Principal) -> bool {
+        let admins = Self::load().admins;
+        admins.contains(principal) || ic_cdk::api::is_controller(principal)
+    }
+
+    pub fn validate_config(config: &AgentConfig) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&config.warm_set_target) {
+            return Err("warm_set_target must be between 0.0 and 1.0".to_string());
+        }
+        if config.max_tokens == 0 || config.concurrency_limit == 0 {
+            return Err("max_tokens and concurrency_limit must be at least 1".to_string());
+        }
+        if !config.model_repo_canister_id.is_empty() {
+            Self::parse_canister_id("model_repo_canister_id", &config.model_repo_canister_id)?;
+        }
+        for mirror in config.model_repo_mirrors.iter().flatten() {
+            Self::parse_canister_id("model_repo_mirrors", mirror)?;
+        }
+        if config.model_repo_canister_id.is_empty() && config.model_repo_mirrors.as_ref().is_some_and(|m| !m.is_empty()) {
+            return Err("model_repo_mirrors require a primary model_repo_canister_id".to_string());
+        }
+        if config.model_pool_budget_bytes == Some(0) {
+            return Err("model_pool_budget_bytes must be at least 1".to_string());
+        }
+        if let Some(batching) = &config.batching {
+            if batching.max_batch_size < 2 {
+                return Err("batching.max_batch_size must be at least 2".to_string());
+            }
+            if batching.window_ms > MAX_BATCHING_WINDOW_MS {
+                return Err(format!("batching.window_ms must be at most {}", MAX_BATCHING_WINDOW_MS));
+            }
+        }
+        if let Some(economics) = &config.economics_canister_id {
+            Self::parse_canister_id("economics_canister_id", economics)?;
+        }
+        Ok(())
+    }
+
+    fn parse_canister_id(field: &str, id: &str) -> Result<Principal, String> {
+        Principal::from_text(id).map_err(|e| format!("Invalid {} {}: {}", field, id, e))
+    }
+
+    fn load() -> CanisterSettings {
+        SETTINGS
+            .with(|s| s.borrow().get(&SETTINGS_KEY).map(|settings| settings.0))
+            .unwrap_or_else(|| CanisterSettings {
+                config: with_state(|state| state.config.clone()),
+                ..CanisterSettings::default()
+            })
+    }
+
+    /// Persist and mirror the config into heap state, where the rest of the
+    /// canister reads it
+    fn save(mut settings: CanisterSettings) {
+        settings.configured |= !settings.config.model_repo_canister_id.is_empty();
+        with_state_mut(|state| state.config = settings.config.clone());
+        SETTINGS.with(|s| s.borrow_mut().insert(SETTINGS_KEY, Cbor(settings)));
+    }
+}
@@ -0,0 +1,583 @@
+use crate::domain::api_version::{paginate, ApiError, PageRequest};
+use crate::domain::instruction::UserInstruction;
+use crate::infra::guards::MemoryPressure;
+use crate::infra::stable::{memory, Cbor, Memory, AGENT_ROUTES_MEMORY_ID, SHARDING_CONFIG_MEMORY_ID, SHARD_REGISTRY_MEMORY_ID};
+use crate::infra::{Guards, Metrics, Resilience};
+use crate::services::agent_factory::{AgentFactory, AgentStatusInfo, AgentSummary, AgentTask, AgentTaskResult};
+use crate::services::archive::{ArchiveService, ArchivedAgent};
+use crate::services::seed::Seed;
+use crate::services::with_state;
+use candid::{CandidType, Principal};
+use ic_cdk::api::call::call;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::Duration;
+
+thread_local! {
+    static CONFIG: RefCell<StableBTreeMap<u8, Cbor<ShardingConfig>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SHARDING_CONFIG_MEMORY_ID)));
+    static ROUTES: RefCell<StableBTreeMap<String, Cbor<AgentRoute>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(AGENT_ROUTES_MEMORY_ID)));
+    static SHARDS: RefCell<StableBTreeMap<Principal, Cbor<Shard>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SHARD_REGISTRY_MEMORY_ID)));
+}
+
+const CONFIG_KEY: u8 = 0;
+const DEFAULT_LOCAL_AGENT_LIMIT: u32 = 5_000;
+// New agents go to other shards once local agents reach this share of the limit
+const FORWARD_AT_PERCENT: u64 = 90;
+const LOAD_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const MAX_REBALANCE_BATCH: u32 = 50;
+
+/// Where create_agent places new agents
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum PlacementStrategy {
+    #[default]
+    LocalFirst, // This canister until it nears its limits, then the other shards in turn
+    Hash,        // Each owner's agents on one shard, by rendezvous hashing of the owner
+    LeastLoaded, // The shard with the lowest reported agent utilization
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ShardingConfig {
+    pub local_agent_limit: u32,
+    #[serde(default)]
+    pub placement: PlacementStrategy,
+}
+
+impl Default for ShardingConfig {
+    fn default() -> Self {
+        Self {
+            local_agent_limit: DEFAULT_LOCAL_AGENT_LIMIT,
+            placement: PlacementStrategy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum ShardState {
+    Active,
+    Draining, // Keeps serving its agents but gets no new ones
+}
+
+/// What a shard reported about itself
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ShardLoad {
+    pub agents: u32,
+    pub agent_limit: u32,
+    pub memory_pressure: MemoryPressure,
+    pub reported_at: u64,
+}
+
+impl ShardLoad {
+    fn utilization(&self) -> f32 {
+        self.agents as f32 / self.agent_limit.max(1) as f32
+    }
+
+    fn accepts(&self) -> bool {
+        self.agents < self.agent_limit && self.memory_pressure == MemoryPressure::Normal
+    }
+}
+
+/// Another ohms-agent canister of the cluster. Shards must register each
+/// other: a canister only serves shard calls from its registered shards.
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Shard {
+    pub canister_id: Principal,
+    pub state: ShardState,
+    pub registered_at: u64,
+    pub load: Option<ShardLoad>, // None until the first load refresh answers
+}
+
+/// Agent living on another shard that this canister placed or moved there
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct AgentRoute {
+    pub agent_id: String,
+    pub canister_id: Principal,
+    pub owner: String,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ShardingStatus {
+    pub config: ShardingConfig,
+    pub local: ShardLoad,
+    pub shards: Vec<Shard>,
+    pub routed_agents: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct RebalanceReport {
+    pub moved: u32,
+    pub errors: Vec<String>,
+}
+
+/// One shard's page of an owner's agents
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ShardAgentPage {
+    pub items: Vec<AgentSummary>,
+    pub next_cursor: Option<String>,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ClusterAgent {
+    pub canister_id: Principal,
+    pub agent: AgentSummary,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct ClusterAgentPage {
+    pub items: Vec<ClusterAgent>,
+    pub next_cursor: Option<String>,
+    pub total: u64,
+    pub unavailable_shards: Vec<Principal>, // Left out of this page because they did not answer
+}
+
+/// Shard registry and agent routing for a cluster of ohms-agent canisters.
+/// create_agent places each new agent by the configured strategy and records
+/// a route when it lands elsewhere; task and status calls for routed agents
+/// are proxied to the owning shard with the original caller. Archived agents
+/// can be moved off a full canister, and an owner's agents can be listed
+/// across the cluster.
+pub struct ShardingService;
+
+impl ShardingService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(LOAD_REFRESH_INTERVAL, || ic_cdk::spawn(Self::refresh_loads()));
+    }
+
+    pub fn set_config(config: ShardingConfig) -> Result<(), String> {
+        if config.local_agent_limit == 0 {
+            return Err("local_agent_limit must be positive".to_string());
+        }
+        CONFIG.with(|c| c.borrow_mut().insert(CONFIG_KEY, Cbor(config)));
+        Ok(())
+    }
+
+    pub fn config() -> ShardingConfig {
+        CONFIG.with(|c| c.borrow().get(&CONFIG_KEY).map(|c| c.0)).unwrap_or_default()
+    }
+
+    pub fn register(canister_id: Principal) -> Result<Shard, String> {
+        if canister_id == ic_cdk::api::id() || canister_id == Principal::anonymous() {
+            return Err("Shards must be other canisters".to_string());
+        }
+        if Self::shard(&canister_id).is_some() {
+            return Err(format!("Shard {} is already registered", canister_id));
+        }
+        let shard = Shard {
+            canister_id,
+            state: ShardState::Active,
+            registered_at: time(),
+            load: None,
+        };
+        SHARDS.with(|s| s.borrow_mut().insert(canister_id, Cbor(shard.clone())));
+        ic_cdk::spawn(Self::refresh_load(canister_id));
+        Ok(shard)
+    }
+
+    /// Refused while agents are routed to the shard; drain it instead
+    pub fn deregister(canister_id: Principal) -> Result<(), String> {
+        Self::shard(&canister_id).ok_or_else(|| format!("Shard {} not found", canister_id))?;
+        let routed = Self::routes().iter().filter(|r| r.canister_id == canister_id).count();
+        if routed > 0 {
+            return Err(format!("Shard {} still hosts {} routed agents", canister_id, routed));
+        }
+        SHARDS.with(|s| s.borrow_mut().remove(&canister_id));
+        Ok(())
+    }
+
+    pub fn set_state(canister_id: Principal, state: ShardState) -> Result<(), String> {
+        let mut shard = Self::shard(&canister_id).ok_or_else(|| format!("Shard {} not found", canister_id))?;
+        shard.state = state;
+        SHARDS.with(|s| s.borrow_mut().insert(canister_id, Cbor(shard)));
+        Ok(())
+    }
+
+    pub fn status() -> ShardingStatus {
+        ShardingStatus {
+            config: Self::config(),
+            local: Self::local_load(),
+            shards: Self::shards(),
+            routed_agents: ROUTES.with(|r| r.borrow().len()),
+        }
+    }
+
+    pub fn local_load() -> ShardLoad {
+        ShardLoad {
+            agents: with_state(|state| state.agents.len()) as u32,
+            agent_limit: Self::config().local_agent_limit,
+            memory_pressure: Guards::memory_usage().pressure,
+            reported_at: time(),
+        }
+    }
+
+    pub fn is_registered(principal: &Principal) -> bool {
+        Self::shard(principal).is_some()
+    }
+
+    /// Canisters to try for a new agent of `owner`, best first. May include
+    /// this canister.
+    pub fn placement(owner: &Principal) -> Vec<Principal> {
+        let active: Vec<Shard> = Self::shards().into_iter().filter(|s| s.state == ShardState::Active).collect();
+        Self::rank(owner, Self::config(), &Self::local_load(), &active, ic_cdk::api::id())
+    }
+
+    /// Shards to create a new agent of `owner` on, best first; None while it
+    /// belongs on this canister
+    pub fn forward_targets(owner: &Principal) -> Option<Vec<Principal>> {
+        let own_id = ic_cdk::api::id();
+        let ranked = Self::placement(owner);
+        if SHARDS.with(|s| s.borrow().is_empty()) || ranked.first() == Some(&own_id) {
+            return None;
+        }
+        Some(ranked.into_iter().filter(|&c| c != own_id).collect())
+    }
+
+    /// Whether a shard's shard_create_agent may create here. A full shard
+    /// sends the caller on to its next choice.
+    pub fn accepts_forwarded() -> Result<(), ApiError> {
+        if !Self::local_load().accepts() {
+            return Err(ApiError::Unavailable { message: format!("Shard {} is full", ic_cdk::api::id()), retry_after_secs: None });
+        }
+        Ok(())
+    }
+
+    /// Canister owning the agent, when it is not this one
+    pub fn shard_of(agent_id: &str) -> Option<Principal> {
+        ROUTES.with(|r| r.borrow().get(&agent_id.to_string()).map(|route| route.0.canister_id))
+    }
+
+    /// Agents of `owner` placed on other shards through this canister
+    pub fn routed_agent_count(owner: &str) -> usize {
+        Self::routes().iter().filter(|r| r.owner == owner).count()
+    }
+
+    /// Creates the agent on the first of `targets` that accepts it
+    pub async fn forward_create(owner: Principal, instruction: UserInstruction, targets: &[Principal]) -> Result<String, ApiError> {
+        let mut errors = Vec::new();
+        for &shard in targets {
+            let created: Result<(Result<String, ApiError>,), String> =
+                Resilience::call(&shard.to_text(), "shard_create_agent", || {
+                    call(shard, "shard_create_agent", (owner, instruction.clone()))
+                })
+                .await;
+            match created.map_err(ApiError::from).and_then(|(result,)| result) {
+                Ok(agent_id) => {
+                    Self::add_route(&agent_id, shard, &owner.to_string());
+                    Metrics::increment_counter(&format!("agents_forwarded_total:{}", shard));
+                    return Ok(agent_id);
+                }
+                Err(e) => errors.push(format!("{}: {}", shard, e)),
+            }
+        }
+        if errors.is_empty() {
+            return Err(ApiError::QuotaExceeded {
+                message: "Local agent limit reached and no other shard is available".to_string(),
+            });
+        }
+        Err(ApiError::Unavailable {
+            message: format!("No shard accepted the agent ({})", errors.join("; ")),
+            retry_after_secs: None,
+        })
+    }
+
+    pub async fn execute_task(
+        shard: Principal,
+        caller: Principal,
+        agent_id: &str,
+        task: AgentTask,
+        dry_run: bool,
+    ) -> Result<AgentTaskResult, ApiError> {
+        let (result,): (Result<AgentTaskResult, ApiError>,) =
+            Resilience::call(&shard.to_text(), "shard_execute_task", || {
+                call(
+                    shard,
+                    "shard_execute_task",
+                    (caller, agent_id.to_string(), task.description.clone(), Some(dry_run), Some(task.context.clone())),
+                )
+            })
+            .await
+            .map_err(|message| Self::unreachable(shard, message))?;
+        result
+    }
+
+    pub async fn agent_status(shard: Principal, caller: Principal, agent_id: &str) -> Result<AgentStatusInfo, ApiError> {
+        let (result,): (Result<AgentStatusInfo, ApiError>,) =
+            Resilience::call(&shard.to_text(), "shard_get_agent_status", || {
+                call(shard, "shard_get_agent_status", (caller, agent_id.to_string()))
+            })
+            .await
+            .map_err(|message| Self::unreachable(shard, message))?;
+        result
+    }
+
+    /// The shard owning the agent did not answer; the caller may retry
+    fn unreachable(shard: Principal, message: String) -> ApiError {
+        ApiError::Unavailable { message, retry_after_secs: Resilience::retry_after_secs(&shard.to_text()) }
+    }
+
+    pub async fn restore_agent(shard: Principal, owner: Principal, agent_id: &str) -> Result<(), String> {
+        let (result,): (Result<(), String>,) = Resilience::call(&shard.to_text(), "shard_restore_agent", || {
+            call(shard, "shard_restore_agent", (owner, agent_id.to_string()))
+        })
+        .await?;
+        result
+    }
+
+    /// Moves up to `limit` of the oldest archived agents to the active shards
+    /// with the most room. Restores of moved agents are proxied to their new
+    /// shard; their knowledge documents stay here.
+    pub async fn rebalance_archived(limit: u32) -> RebalanceReport {
+        let mut report = RebalanceReport { moved: 0, errors: Vec::new() };
+        let mut targets: Vec<Shard> = Self::shards()
+            .into_iter()
+            .filter(|s| s.state == ShardState::Active && s.load.as_ref().is_some_and(ShardLoad::accepts))
+            .collect();
+        targets.sort_by(|a, b| Self::utilization(a).total_cmp(&Self::utilization(b)));
+        if targets.is_empty() {
+            report.errors.push("No active shard with reported room".to_string());
+            return report;
+        }
+
+        for (index, archived) in ArchiveService::oldest(limit.min(MAX_REBALANCE_BATCH) as usize).into_iter().enumerate() {
+            let shard = targets[index % targets.len()].canister_id;
+            let imported: Result<(Result<(), String>,), String> =
+                Resilience::call(&shard.to_text(), "shard_import_archived", || {
+                    call(shard, "shard_import_archived", (archived.clone(),))
+                })
+                .await;
+            match imported.and_then(|(result,)| result) {
+                // Restored here while the import was in flight; the copy on the shard is never routed to
+                Ok(()) if with_state(|state| state.agents.contains_key(&archived.agent_id)) => {
+                    report.errors.push(format!("{} was restored during the move", archived.agent_id));
+                }
+                Ok(()) => {
+                    ArchiveService::remove(&archived.agent_id);
+                    Self::add_route(&archived.agent_id, shard, &archived.user_id);
+                    report.moved += 1;
+                }
+                Err(e) => report.errors.push(format!("{} to {}: {}", archived.agent_id, shard, e)),
+            }
+        }
+        Metrics::add_to_counter("archived_agents_rebalanced_total", report.moved as u64);
+        report
+    }
+
+    /// The owner's agents on this canister and every registered shard, merged
+    /// into one page ordered by agent id
+    pub async fn list_cluster_agents(owner: Principal, page: &PageRequest) -> Result<ClusterAgentPage, String> {
+        let local = AgentFactory::list_user_agents(&owner.to_string()).await?;
+        let mut pages = vec![(ic_cdk::api::id(), Self::page_of(local, page))];
+        let mut unavailable_shards = Vec::new();
+        for shard in Self::shards() {
+            let canister_id = shard.canister_id;
+            let listed: Result<(Result<ShardAgentPage, String>,), String> =
+                Resilience::call(&canister_id.to_text(), "shard_list_user_agents", || {
+                    call(canister_id, "shard_list_user_agents", (owner, page.clone()))
+                })
+                .await;
+            match listed.and_then(|(result,)| result) {
+                Ok(shard_page) => pages.push((canister_id, shard_page)),
+                Err(_) => unavailable_shards.push(canister_id),
+            }
+        }
+        Ok(ClusterAgentPage {
+            unavailable_shards,
+            ..Self::merge_pages(pages, page)
+        })
+    }
+
+    /// This canister's page of an owner's agents, for shard_list_user_agents
+    pub fn page_of(agents: Vec<AgentSummary>, page: &PageRequest) -> ShardAgentPage {
+        let (items, next_cursor, total) = paginate(agents, page, |agent| agent.agent_id.clone());
+        ShardAgentPage { items, next_cursor, total }
+    }
+
+    pub fn import_archived(archived: ArchivedAgent) -> Result<(), String> {
+        Self::accepts_forwarded()?;
+        ArchiveService::import(archived)
+    }
+
+    async fn refresh_loads() {
+        for shard in Self::shards() {
+            Self::refresh_load(shard.canister_id).await;
+        }
+    }
+
+    async fn refresh_load(canister_id: Principal) {
+        let reported: Result<(ShardLoad,), String> =
+            Resilience::call(&canister_id.to_text(), "shard_load", || call(canister_id, "shard_load", ())).await;
+        let Ok((load,)) = reported else {
+            return;
+        };
+        // Deregistered while the call was in flight
+        if let Some(mut shard) = Self::shard(&canister_id) {
+            shard.load = Some(load);
+            SHARDS.with(|s| s.borrow_mut().insert(canister_id, Cbor(shard)));
+        }
+    }
+
+    /// Send later calls for `agent_id` to the shard that now holds it
+    pub fn add_route(agent_id: &str, canister_id: Principal, owner: &str) {
+        let route = AgentRoute {
+            agent_id: agent_id.to_string(),
+            canister_id,
+            owner: owner.to_string(),
+            created_at: time(),
+        };
+        ROUTES.with(|r| r.borrow_mut().insert(agent_id.to_string(), Cbor(route)));
+    }
+
+    fn shard(canister_id: &Principal) -> Option<Shard> {
+        SHARDS.with(|s| s.borrow().get(canister_id).map(|shard| shard.0))
+    }
+
+    fn shards() -> Vec<Shard> {
+        SHARDS.with(|s| s.borrow().iter().map(|(_, shard)| shard.0).collect())
+    }
+
+    fn routes() -> Vec<AgentRoute> {
+        ROUTES.with(|r| r.borrow().iter().map(|(_, route)| route.0).collect())
+    }
+
+    /// Shards that have not reported sort last
+    fn utilization(shard: &Shard) -> f32 {
+        shard.load.as_ref().map_or(f32::MAX, ShardLoad::utilization)
+    }
+
+    fn near_limit(load: &ShardLoad) -> bool {
+        load.memory_pressure != MemoryPressure::Normal
+            || load.agents as u64 * 100 >= load.agent_limit as u64 * FORWARD_AT_PERCENT
+    }
+
+    fn rank(owner: &Principal, config: ShardingConfig, local: &ShardLoad, active: &[Shard], own_id: Principal) -> Vec<Principal> {
+        // Shards known to be full are skipped; unreported ones are tried
+        let mut others: Vec<&Shard> = active.iter().filter(|s| s.load.as_ref().map_or(true, ShardLoad::accepts)).collect();
+        let local_fits = !Self::near_limit(local);
+        match config.placement {
+            PlacementStrategy::LocalFirst => {
+                // Rotate so successive forwards spread over the shards
+                if !others.is_empty() {
+                    let start = ROUTES.with(|r| r.borrow().len()) as usize % others.len();
+                    others.rotate_left(start);
+                }
+                local_fits
+                    .then_some(own_id)
+                    .into_iter()
+                    .chain(others.iter().map(|s| s.canister_id))
+                    .collect()
+            }
+            PlacementStrategy::Hash => {
+                let owner = owner.to_text();
+                let mut candidates: Vec<Principal> = others.iter().map(|s| s.canister_id).collect();
+                if local_fits {
+                    candidates.push(own_id);
+                }
+                candidates.sort_by_key(|c| std::cmp::Reverse(Seed::derive(&[&owner, &c.to_text()])));
+                candidates
+            }
+            PlacementStrategy::LeastLoaded => {
+                let mut candidates: Vec<(Principal, f32)> =
+                    others.iter().map(|s| (s.canister_id, Self::utilization(s))).collect();
+                if local_fits {
+                    candidates.push((own_id, local.utilization()));
+                }
+                candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+                candidates.into_iter().map(|(c, _)| c).collect()
+            }
+        }
+    }
+
+    /// Merges per-shard pages sorted by agent id into one page of the
+    /// requested size. There is a next page when the merge overflowed or any
+    /// shard had more.
+    fn merge_pages(pages: Vec<(Principal, ShardAgentPage)>, page: &PageRequest) -> ClusterAgentPage {
+        let total = pages.iter().map(|(_, p)| p.total).sum();
+        let shard_has_more = pages.iter().any(|(_, p)| p.next_cursor.is_some());
+        let merged: Vec<ClusterAgent> = pages
+            .into_iter()
+            .flat_map(|(canister_id, p)| p.items.into_iter().map(move |agent| ClusterAgent { canister_id, agent }))
+            .collect();
+        let (items, next_cursor, _) = paginate(merged, page, |item| item.agent.agent_id.clone());
+        let next_cursor = next_cursor.or_else(|| {
+            shard_has_more.then(|| items.last().map(|item| item.agent.agent_id.clone())).flatten()
+        });
+        ClusterAgentPage {
+            items,
+            next_cursor,
+            total,
+            unavailable_shards: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::instruction::AgentType;
+    use crate::services::agent_factory::AgentStatus;
+
+    fn load(agents: u32) -> ShardLoad {
+        ShardLoad { agents, agent_limit: 100, memory_pressure: MemoryPressure::Normal, reported_at: 0 }
+    }
+
+    fn shard(id: u8, agents: Option<u32>) -> Shard {
+        Shard {
+            canister_id: Principal::from_slice(&[id]),
+            state: ShardState::Active,
+            registered_at: 0,
+            load: agents.map(load),
+        }
+    }
+
+    fn summary(agent_id: &str) -> AgentSummary {
+        AgentSummary {
+            agent_id: agent_id.to_string(),
+            agent_type: AgentType::GeneralAssistant,
+            status: AgentStatus::Ready,
+            created_at: 0,
+            last_active: 0,
+            origin: None,
+        }
+    }
+
+    #[test]
+    fn test_placement_strategies() {
+        let own = Principal::from_slice(&[9]);
+        let owner = Principal::from_slice(&[42]);
+        let shards = vec![shard(1, Some(80)), shard(2, Some(100)), shard(3, Some(10))];
+        let config = |placement| ShardingConfig { local_agent_limit: 100, placement };
+
+        let least = ShardingService::rank(&owner, config(PlacementStrategy::LeastLoaded), &load(50), &shards, own);
+        assert_eq!(least, vec![shards[2].canister_id, own, shards[0].canister_id]);
+
+        // Near the limit this canister drops out; full shards never qualify
+        let hashed = ShardingService::rank(&owner, config(PlacementStrategy::Hash), &load(95), &shards, own);
+        assert_eq!(hashed.len(), 2);
+        assert!(!hashed.contains(&own) && !hashed.contains(&shards[1].canister_id));
+        assert_eq!(hashed, ShardingService::rank(&owner, config(PlacementStrategy::Hash), &load(95), &shards, own));
+    }
+
+    #[test]
+    fn test_merged_pages_continue_while_any_shard_has_more() {
+        let request = PageRequest { cursor: None, limit: Some(2) };
+        let a = ShardAgentPage { items: vec![summary("a1"), summary("a3")], next_cursor: Some("a3".to_string()), total: 3 };
+        let b = ShardAgentPage { items: vec![summary("a2")], next_cursor: None, total: 1 };
+        let page = ShardingService::merge_pages(
+            vec![(Principal::from_slice(&[1]), a), (Principal::from_slice(&[2]), b)],
+            &request,
+        );
+        let ids: Vec<&str> = page.items.iter().map(|i| i.agent.agent_id.as_str()).collect();
+        assert_eq!(ids, vec!["a1", "a2"]);
+        assert_eq!(page.next_cursor.as_deref(), Some("a2"));
+        assert_eq!(page.total, 4);
+
+        let only = ShardAgentPage { items: vec![summary("b1")], next_cursor: Some("b1".to_string()), total: 5 };
+        let page = ShardingService::merge_pages(vec![(Principal::from_slice(&[1]), only)], &request);
+        assert_eq!(page.next_cursor.as_deref(), Some("b1"));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::infra::stable::{memory, Cbor, Memory, SIGNING_CONFIG_MEMORY_ID};
+use crate::infra::{Metrics, Resilience};
+use crate::services::agent_factory::{AgentTask, AgentTaskResult, TaskOutcome};
+use candid::CandidType;
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
+};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+thread_local! {
+    static CONFIG: RefCell<StableBTreeMap<u8, Cbor<SigningConfig>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SIGNING_CONFIG_MEMORY_ID)));
+    // (key_name, public key); fetched again after an upgrade
+    static PUBLIC_KEY: RefCell<Option<(String, String)>> = RefCell::new(None);
+}
+
+const CONFIG_KEY: u8 = 0;
+const MANAGEMENT_CANISTER: &str = "aaaaa-aa";
+const DOMAIN_TAG: &[u8] = b"ohms-agent-result-v1";
+const DERIVATION_PATH: &[u8] = b"task-results";
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SigningConfig {
+    pub enabled: bool,
+    pub key_name: String, // "dfx_test_key" locally, "test_key_1" or "key_1" on mainnet
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            key_name: "key_1".to_string(),
+        }
+    }
+}
+
+/// Threshold-ECDSA signature over a task result. Verify `signature` against
+/// `digest` with the key from get_signing_public_key after recomputing the
+/// digest as described on SigningService.
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ResultSignature {
+    pub key_name: String,
+    pub prompt_sha256: String, // Hex sha256 of the task description
+    pub result_sha256: String, // Hex sha256 of the result text
+    pub signed_at: u64,
+    pub digest: String,    // Hex
+    pub signature: String, // Hex secp256k1 r || s
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct SigningPublicKey {
+    pub key_name: String,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub public_key: String, // Hex SEC1 compressed secp256k1 point
+}
+
+/// Signs successful task results so their origin can be proven off-chain.
+/// The signed digest is sha256 over the tag "ohms-agent-result-v1" followed
+/// by agent_id, task_id, the prompt hash, the result hash and signed_at
+/// (big-endian u64 nanoseconds), each field prefixed with its big-endian
+/// u32 byte length. Threshold-ECDSA keys are derived per canister, so a
+/// valid signature also proves which canister produced the result.
+/// Each signature costs cycles, so signing is off until an admin enables it.
+pub struct SigningService;
+
+impl SigningService {
+    pub fn set_config(config: SigningConfig) -> Result<(), String> {
+        if config.key_name.trim().is_empty() {
+            return Err("key_name cannot be empty".to_string());
+        }
+        CONFIG.with(|c| c.borrow_mut().insert(CONFIG_KEY, Cbor(config)));
+        Ok(())
+    }
+
+    pub fn config() -> SigningConfig {
+        CONFIG.with(|c| c.borrow().get(&CONFIG_KEY).map(|c| c.0)).unwrap_or_default()
+    }
+
+    /// None when signing is off, the task did not succeed or the signing call
+    /// failed. A failed signature never fails the task itself.
+    pub async fn sign(agent_id: &str, task: &AgentTask, result: &AgentTaskResult) -> Option<ResultSignature> {
+        let config = Self::config();
+        if !config.enabled || result.outcome != TaskOutcome::Succeeded {
+            return None;
+        }
+        let prompt_sha256: [u8; 32] = Sha256::digest(task.description.as_bytes()).into();
+        let result_sha256: [u8; 32] = Sha256::digest(result.result.as_bytes()).into();
+        let signed_at = time();
+        let digest = Self::digest(agent_id, &result.task_id, &prompt_sha256, &result_sha256, signed_at);
+
+        let argument = SignWithEcdsaArgument {
+            message_hash: digest.to_vec(),
+            derivation_path: Self::derivation_path(),
+            key_id: Self::key_id(&config.key_name),
+        };
+        let signed = Resilience::call(MANAGEMENT_CANISTER, "sign_with_ecdsa", || sign_with_ecdsa(argument.clone())).await;
+        match signed {
+            Ok((response,)) => {
+                Metrics::increment_counter("results_signed_total");
+                Some(ResultSignature {
+                    key_name: config.key_name,
+                    prompt_sha256: hex::encode(prompt_sha256),
+                    result_sha256: hex::encode(result_sha256),
+                    signed_at,
+                    digest: hex::encode(digest),
+                    signature: hex::encode(response.signature),
+                })
+            }
+            Err(e) => {
+                ic_cdk::println!("Signing result of task {} failed: {}", result.task_id, e);
+                Metrics::increment_counter("result_signing_failures_total");
+                None
+            }
+        }
+    }
+
+    /// Public key of the configured key, for verifying signatures off-chain
+    pub async fn public_key() -> Result<SigningPublicKey, String> {
+        let key_name = Self::config().key_name;
+        let cached = PUBLIC_KEY.with(|k| k.borrow().clone()).filter(|(name, _)| *name == key_name);
+        let public_key = match cached {
+            Some((_, public_key)) => public_key,
+            None => {
+                let argument = EcdsaPublicKeyArgument {
+                    canister_id: None,
+                    derivation_path: Self::derivation_path(),
+                    key_id: Self::key_id(&key_name),
+                };
+                let (response,) =
+                    Resilience::call(MANAGEMENT_CANISTER, "ecdsa_public_key", || ecdsa_public_key(argument.clone())).await?;
+                let public_key = hex::encode(response.public_key);
+                PUBLIC_KEY.with(|k| *k.borrow_mut() = Some((key_name.clone(), public_key.clone())));
+                public_key
+            }
+        };
+        Ok(SigningPublicKey {
+            key_name,
+            derivation_path: Self::derivation_path(),
+            public_key,
+        })
+    }
+
+    fn key_id(key_name: &str) -> EcdsaKeyId {
+        EcdsaKeyId {
+            curve: EcdsaCurve::Secp256k1,
+            name: key_name.to_string(),
+        }
+    }
+
+    fn derivation_path() -> Vec<Vec<u8>> {
+        vec![DERIVATION_PATH.to_vec()]
+    }
+
+    fn digest(agent_id: &str, task_id: &str, prompt_sha256: &[u8], result_sha256: &[u8], signed_at: u64) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        let fields: [&[u8]; 6] = [
+            DOMAIN_TAG,
+            agent_id.as_bytes(),
+            task_id.as_bytes(),
+            prompt_sha256,
+            result_sha256,
+            &signed_at.to_be_bytes(),
+        ];
+        for field in fields {
+            hasher.update((field.len() as u32).to_be_bytes());
+            hasher.update(field);
+        }
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest_separates_fields() {
+        let prompt = [1u8; 32];
+        let result = [2u8; 32];
+        let digest = SigningService::digest("agent-1", "task-1", &prompt, &result, 7);
+        assert_eq!(digest, SigningService::digest("agent-1", "task-1", &prompt, &result, 7));
+        assert_ne!(digest, SigningService::digest("agent-1", "task-1", &prompt, &result, 8));
+        assert_ne!(digest, SigningService::digest("agent-1", "task-1", &result, &prompt, 7));
+        // Moving bytes between adjacent fields changes the digest
+        assert_ne!(digest, SigningService::digest("agent-1t", "ask-1", &prompt, &result, 7));
+    }
+}
@@ -0,0 +1,296 @@
+use crate::domain::instruction::SkillRef;
+use crate::infra::stable::{memory, Cbor, Memory, SKILLS_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::agent_factory::{AgentFactory, AutonomousAgent};
+use crate::services::modelrepo::{ModelRepoClient, SkillArtifact};
+use crate::services::tools::{ToolPermission, ToolService};
+use crate::services::{with_state, with_state_mut};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+thread_local! {
+    // Fetched skill versions, keyed "{skill_id}@{version}". Versions are immutable in the repo.
+    static SKILLS: RefCell<StableBTreeMap<String, Cbor<Skill>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SKILLS_MEMORY_ID)));
+}
+
+// Task context key naming the attached skill a task runs
+pub const SKILL_KEY: &str = "skill";
+pub const INPUT_PLACEHOLDER: &str = "{{input}}";
+pub const PREVIOUS_PLACEHOLDER: &str = "{{previous}}";
+const MAX_SKILLS_PER_AGENT: usize = 20;
+const MAX_SKILL_STEPS: usize = 10;
+const MAX_DEFINITION_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum SkillStep {
+    Prompt { template: String },              // Sent to the LLM
+    Tool { tool: String, arguments: String }, // `arguments` is a template too
+}
+
+/// Fields the final output must have; the output is then parsed as a JSON object
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SkillOutputSchema {
+    pub required_fields: Vec<String>,
+}
+
+/// What a skill artifact's JSON definition holds. Templates may use
+/// {{input}} for the task description and {{previous}} for the last step's output.
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SkillDefinition {
+    pub name: String,
+    pub description: String,
+    pub steps: Vec<SkillStep>,
+    #[serde(default)]
+    pub tool_bindings: Vec<String>, // Tools the steps call; the agent must have access to each
+    #[serde(default)]
+    pub output_schema: Option<SkillOutputSchema>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Skill {
+    pub skill_id: String,
+    pub version: String,
+    pub sha256: String,
+    pub fetched_at: u64,
+    pub definition: SkillDefinition,
+}
+
+/// A skill version attached to an agent
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct AttachedSkill {
+    pub skill_id: String,
+    pub version: String,
+    pub attached_at: u64,
+}
+
+/// Reusable skills — prompt chains with tool bindings and an output schema —
+/// published as versioned artifacts in the model repo. A fetched version is
+/// verified against its digest and kept, so agents keep running it after the
+/// repo moves on. AgentFactory runs a task through a skill when the task
+/// context names one the agent has attached.
+pub struct SkillService;
+
+impl SkillService {
+    /// Fetch `skill` and attach it, replacing another version of the same skill
+    pub async fn attach(agent_id: &str, skill: SkillRef) -> Result<Skill, String> {
+        AgentFactory::find_agent(agent_id)?;
+        let fetched = Self::fetch(&skill).await?;
+        // The agent may have changed while the repo answered
+        let mut agent = AgentFactory::find_agent(agent_id)?;
+        Self::check_tools(&agent, &fetched)?;
+        agent.skills.retain(|s| s.skill_id != fetched.skill_id);
+        if agent.skills.len() >= MAX_SKILLS_PER_AGENT {
+            return Err(format!("Skill limit reached. Maximum: {}", MAX_SKILLS_PER_AGENT));
+        }
+        agent.skills.push(AttachedSkill {
+            skill_id: fetched.skill_id.clone(),
+            version: fetched.version.clone(),
+            attached_at: time(),
+        });
+        Self::save(agent);
+        Metrics::increment_counter("skills_attached_total");
+        Ok(fetched)
+    }
+
+    /// Attachments for an agent being created, all or nothing
+    pub async fn resolve_for_new_agent(agent: &AutonomousAgent, skills: &[SkillRef]) -> Result<Vec<AttachedSkill>, String> {
+        if skills.len() > MAX_SKILLS_PER_AGENT {
+            return Err(format!("Skill limit reached. Maximum: {}", MAX_SKILLS_PER_AGENT));
+        }
+        let mut attached: Vec<AttachedSkill> = Vec::new();
+        for skill in skills {
+            let fetched = Self::fetch(skill).await?;
+            Self::check_tools(agent, &fetched)?;
+            attached.retain(|s| s.skill_id != fetched.skill_id);
+            attached.push(AttachedSkill {
+                skill_id: fetched.skill_id,
+                version: fetched.version,
+                attached_at: time(),
+            });
+        }
+        Ok(attached)
+    }
+
+    pub fn detach(agent_id: &str, skill_id: &str) -> Result<(), String> {
+        let mut agent = AgentFactory::find_agent(agent_id)?;
+        let before = agent.skills.len();
+        agent.skills.retain(|s| s.skill_id != skill_id);
+        if agent.skills.len() == before {
+            return Err(format!("Skill {} is not attached to agent {}", skill_id, agent_id));
+        }
+        Self::save(agent);
+        Ok(())
+    }
+
+    pub fn list_attached(agent: &AutonomousAgent) -> Vec<Skill> {
+        agent.skills.iter().filter_map(|s| Self::cached(&s.skill_id, &s.version)).collect()
+    }
+
+    /// The version of `skill_id` the agent runs
+    pub fn attached(agent: &AutonomousAgent, skill_id: &str) -> Result<Skill, String> {
+        let attached = agent
+            .skills
+            .iter()
+            .find(|s| s.skill_id == skill_id)
+            .ok_or_else(|| format!("Skill {} is not attached to agent {}", skill_id, agent.agent_id))?;
+        Self::cached(&attached.skill_id, &attached.version)
+            .ok_or_else(|| format!("Skill {}@{} is missing from the skill store", attached.skill_id, attached.version))
+    }
+
+    /// A pinned version already fetched is served locally; anything else asks the repo
+    pub async fn fetch(skill: &SkillRef) -> Result<Skill, String> {
+        if let Some(cached) = skill.version.as_deref().and_then(|v| Self::cached(&skill.skill_id, v)) {
+            return Ok(cached);
+        }
+        let repos = with_state(|s| s.config.model_repos());
+        if repos.is_empty() {
+            return Err("model_repo_canister_id not configured".to_string());
+        }
+        let artifact = ModelRepoClient::get_skill(&repos, &skill.skill_id, skill.version.as_deref()).await?;
+        let fetched = Self::verify(skill, artifact, time())?;
+        SKILLS.with(|s| s.borrow_mut().insert(store_key(&fetched.skill_id, &fetched.version), Cbor(fetched.clone())));
+        Metrics::increment_counter("skills_fetched_total");
+        Ok(fetched)
+    }
+
+    /// Tool steps run without waiting for approval, so every binding must be allowed outright
+    fn check_tools(agent: &AutonomousAgent, skill: &Skill) -> Result<(), String> {
+        for tool in &skill.definition.tool_bindings {
+            match ToolService::permission(agent, tool) {
+                ToolPermission::Allowed => {}
+                ToolPermission::RequiresApproval => {
+                    return Err(format!("Skill {} needs tool {}, which requires approval on this agent", skill.skill_id, tool))
+                }
+                ToolPermission::Denied(reason) => return Err(format!("Skill {} needs tool {}: {}", skill.skill_id, tool, reason)),
+            }
+        }
+        Ok(())
+    }
+
+    fn verify(requested: &SkillRef, artifact: SkillArtifact, now: u64) -> Result<Skill, String> {
+        if artifact.skill_id != requested.skill_id
+            || requested.version.as_ref().is_some_and(|v| *v != artifact.version)
+        {
+            return Err(format!(
+                "Model repo answered with {}@{} for skill {}",
+                artifact.skill_id, artifact.version, requested.skill_id
+            ));
+        }
+        if artifact.definition.len() > MAX_DEFINITION_BYTES {
+            return Err(format!("Skill definition exceeds {} bytes", MAX_DEFINITION_BYTES));
+        }
+        let digest = hex::encode(Sha256::digest(&artifact.definition));
+        if !digest.eq_ignore_ascii_case(&artifact.sha256) {
+            Metrics::increment_counter("skill_digest_mismatches_total");
+            return Err(format!("Skill {}@{} does not match its digest", artifact.skill_id, artifact.version));
+        }
+        let definition: SkillDefinition = serde_json::from_slice(&artifact.definition)
+            .map_err(|e| format!("Skill {}@{} has an invalid definition: {}", artifact.skill_id, artifact.version, e))?;
+        validate(&definition)?;
+        Ok(Skill {
+            skill_id: artifact.skill_id,
+            version: artifact.version,
+            sha256: digest,
+            fetched_at: now,
+            definition,
+        })
+    }
+
+    fn cached(skill_id: &str, version: &str) -> Option<Skill> {
+        SKILLS.with(|s| s.borrow().get(&store_key(skill_id, version)).map(|skill| skill.0))
+    }
+
+    fn save(agent: AutonomousAgent) {
+        with_state_mut(|state| {
+            state.agents.insert(agent.agent_id.clone(), agent);
+        });
+    }
+
+    /// Fill a step template
+    pub fn render(template: &str, input: &str, previous: &str) -> String {
+        template.replace(INPUT_PLACEHOLDER, input).replace(PREVIOUS_PLACEHOLDER, previous)
+    }
+
+    /// The output's JSON object, which must have every required field
+    pub fn check_output(schema: &SkillOutputSchema, output: &str) -> Result<(), String> {
+        if schema.required_fields.is_empty() {
+            return Ok(());
+        }
+        let body = match (output.find('{'), output.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &output[start..=end],
+            _ => return Err("Skill output is not a JSON object".to_string()),
+        };
+        let value: serde_json::Value =
+            serde_json::from_str(body).map_err(|e| format!("Skill output is not a JSON object: {}", e))?;
+        let object = value.as_object().ok_or_else(|| "Skill output is not a JSON object".to_string())?;
+        let missing: Vec<&str> =
+            schema.required_fields.iter().filter(|f| !object.contains_key(*f)).map(String::as_str).collect();
+        if !missing.is_empty() {
+            return Err(format!("Skill output is missing fields: {}", missing.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+fn store_key(skill_id: &str, version: &str) -> String {
+    format!("{}@{}", skill_id, version)
+}
+
+fn validate(definition: &SkillDefinition) -> Result<(), String> {
+    if definition.steps.is_empty() || definition.steps.len() > MAX_SKILL_STEPS {
+        return Err(format!("A skill must have 1 to {} steps", MAX_SKILL_STEPS));
+    }
+    for step in &definition.steps {
+        match step {
+            SkillStep::Prompt { template } if template.trim().is_empty() => {
+                return Err("Skill prompt templates must not be empty".to_string());
+            }
+            SkillStep::Tool { tool, .. } if !definition.tool_bindings.contains(tool) => {
+                return Err(format!("Skill step calls tool {} without binding it", tool));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(steps: Vec<SkillStep>, tool_bindings: Vec<String>) -> SkillDefinition {
+        SkillDefinition {
+            name: "summarize".to_string(),
+            description: String::new(),
+            steps,
+            tool_bindings,
+            output_schema: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_requires_bound_tools() {
+        let tool_step = SkillStep::Tool { tool: "code_exec".to_string(), arguments: PREVIOUS_PLACEHOLDER.to_string() };
+        assert!(validate(&definition(vec![], vec![])).is_err());
+        assert!(validate(&definition(vec![tool_step.clone()], vec![])).is_err());
+        assert!(validate(&definition(vec![tool_step], vec!["code_exec".to_string()])).is_ok());
+    }
+
+    #[test]
+    fn test_check_output_finds_required_fields() {
+        let schema = SkillOutputSchema { required_fields: vec!["title".to_string(), "tags".to_string()] };
+        assert!(SkillService::check_output(&schema, "```json\n{\"title\": \"a\", \"tags\": []}\n```").is_ok());
+        assert!(SkillService::check_output(&schema, "{\"title\": \"a\"}").unwrap_err().contains("tags"));
+        assert!(SkillService::check_output(&schema, "no json here").is_err());
+    }
+
+    #[test]
+    fn test_render_fills_input_and_previous() {
+        assert_eq!(SkillService::render("{{input}} -> {{previous}}", "task", "draft"), "task -> draft");
+    }
+}
@@ -0,0 +1,212 @@
+use crate::domain::instruction::{AgentType, ComplexityLevel, DurationEstimate};
+use crate::infra::stable::{memory, Cbor, Memory, SLA_DURATIONS_MEMORY_ID};
+use crate::services::agent_factory::{AgentTaskResult, AutonomousAgent, TaskOutcome};
+use candid::CandidType;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    static DURATIONS: RefCell<StableBTreeMap<String, Cbor<DurationHistory>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SLA_DURATIONS_MEMORY_ID)));
+}
+
+const MAX_SAMPLES_PER_GROUP: usize = 500;
+// Fewer samples than this leave estimates to the analyzer's heuristic
+const MIN_SAMPLES_FOR_ESTIMATE: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DurationSample {
+    pub actual_ms: u64,
+    pub estimate: DurationEstimate, // What the agent's analysis promised
+}
+
+/// Recent task durations of one complexity level and agent type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DurationHistory {
+    pub complexity: ComplexityLevel,
+    pub agent_type: AgentType,
+    pub samples: Vec<DurationSample>, // Oldest first
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct SlaGroup {
+    pub complexity: ComplexityLevel,
+    pub agent_type: AgentType,
+    pub samples: u64,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub within_expected_rate: f32, // Finished by expected_duration_seconds
+    pub within_max_rate: f32,      // Finished by max_duration_seconds
+    pub p95_overrun_ms: u64,       // Past max_duration_seconds; 0 when p95 of tasks finish in time
+    pub mean_absolute_error: f32,  // |actual - expected| over expected
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct SlaReport {
+    pub groups: Vec<SlaGroup>,
+    pub samples: u64,
+    pub within_max_rate: f32,
+}
+
+/// Actual task durations per complexity level and agent type, compared with
+/// the DurationEstimate each agent's analysis made. Once a group has enough
+/// history, new analyses estimate from it instead of the heuristic.
+/// Failed tasks end early for unrelated reasons and are not recorded;
+/// timed-out ones are.
+pub struct SlaService;
+
+impl SlaService {
+    pub fn record(agent: &AutonomousAgent, result: &AgentTaskResult) {
+        if !matches!(result.outcome, TaskOutcome::Succeeded | TaskOutcome::TimedOut) {
+            return;
+        }
+        let complexity = agent.analysis.estimated_complexity.clone();
+        let agent_type = agent.analysis.agent_configuration.agent_type.clone();
+        let key = Self::key(&complexity, &agent_type);
+        DURATIONS.with(|d| {
+            let mut durations = d.borrow_mut();
+            let mut history = durations.get(&key).map(|h| h.0).unwrap_or(DurationHistory {
+                complexity,
+                agent_type,
+                samples: Vec::new(),
+            });
+            history.samples.push(DurationSample {
+                actual_ms: result.execution_time_ms,
+                estimate: agent.analysis.estimated_duration.clone(),
+            });
+            if history.samples.len() > MAX_SAMPLES_PER_GROUP {
+                let overflow = history.samples.len() - MAX_SAMPLES_PER_GROUP;
+                history.samples.drain(..overflow);
+            }
+            durations.insert(key, Cbor(history));
+        });
+    }
+
+    /// Estimate from the group's history, when it has enough samples
+    pub fn historical_estimate(complexity: &ComplexityLevel, agent_type: &AgentType) -> Option<DurationEstimate> {
+        let history = DURATIONS.with(|d| d.borrow().get(&Self::key(complexity, agent_type)))?.0;
+        Self::estimate_from(&history.samples)
+    }
+
+    pub fn report() -> SlaReport {
+        let groups: Vec<SlaGroup> =
+            DURATIONS.with(|d| d.borrow().iter().map(|(_, history)| Self::summarize(&history.0)).collect());
+        let samples: u64 = groups.iter().map(|g| g.samples).sum();
+        let within_max = groups.iter().map(|g| g.within_max_rate * g.samples as f32).sum::<f32>();
+        SlaReport {
+            within_max_rate: if samples == 0 { 1.0 } else { within_max / samples as f32 },
+            samples,
+            groups,
+        }
+    }
+
+    fn key(complexity: &ComplexityLevel, agent_type: &AgentType) -> String {
+        format!("{:?}/{:?}", complexity, agent_type)
+    }
+
+    /// p10, p50 and p95 of the actual durations. Confidence grows with the
+    /// sample count.
+    fn estimate_from(samples: &[DurationSample]) -> Option<DurationEstimate> {
+        if samples.len() < MIN_SAMPLES_FOR_ESTIMATE {
+            return None;
+        }
+        let mut actual: Vec<u64> = samples.iter().map(|s| s.actual_ms).collect();
+        actual.sort_unstable();
+        let seconds = |percentile: f64| Self::percentile(&actual, percentile).div_ceil(1_000).max(1);
+        Some(DurationEstimate {
+            min_duration_seconds: seconds(0.10),
+            expected_duration_seconds: seconds(0.50),
+            max_duration_seconds: seconds(0.95),
+            confidence: (0.5 + samples.len() as f32 / 200.0).min(0.95),
+        })
+    }
+
+    fn summarize(history: &DurationHistory) -> SlaGroup {
+        let samples = &history.samples;
+        let n = samples.len().max(1) as f32;
+        let mut actual: Vec<u64> = samples.iter().map(|s| s.actual_ms).collect();
+        actual.sort_unstable();
+        let mut overruns: Vec<u64> = samples
+            .iter()
+            .map(|s| s.actual_ms.saturating_sub(s.estimate.max_duration_seconds * 1_000))
+            .collect();
+        overruns.sort_unstable();
+
+        SlaGroup {
+            complexity: history.complexity.clone(),
+            agent_type: history.agent_type.clone(),
+            samples: samples.len() as u64,
+            p50_ms: Self::percentile(&actual, 0.50),
+            p95_ms: Self::percentile(&actual, 0.95),
+            within_expected_rate: samples
+                .iter()
+                .filter(|s| s.actual_ms <= s.estimate.expected_duration_seconds * 1_000)
+                .count() as f32
+                / n,
+            within_max_rate: samples
+                .iter()
+                .filter(|s| s.actual_ms <= s.estimate.max_duration_seconds * 1_000)
+                .count() as f32
+                / n,
+            p95_overrun_ms: Self::percentile(&overruns, 0.95),
+            mean_absolute_error: samples
+                .iter()
+                .map(|s| {
+                    let expected = (s.estimate.expected_duration_seconds * 1_000).max(1) as f32;
+                    (s.actual_ms as f32 - expected).abs() / expected
+                })
+                .sum::<f32>()
+                / n,
+        }
+    }
+
+    /// Nearest-rank percentile of sorted values; 0 when empty
+    fn percentile(sorted: &[u64], percentile: f64) -> u64 {
+        if sorted.is_empty() {
+            return 0;
+        }
+        sorted[((sorted.len() as f64 * percentile) as usize).min(sorted.len() - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(actual_ms: u64) -> DurationSample {
+        DurationSample {
+            actual_ms,
+            estimate: DurationEstimate {
+                min_duration_seconds: 15,
+                expected_duration_seconds: 30,
+                max_duration_seconds: 90,
+                confidence: 0.7,
+            },
+        }
+    }
+
+    #[test]
+    fn test_report_and_estimate_follow_actual_durations() {
+        let samples: Vec<DurationSample> = (1..=20).map(|i| sample(i * 10_000)).collect();
+        let history = DurationHistory {
+            complexity: ComplexityLevel::Simple,
+            agent_type: AgentType::GeneralAssistant,
+            samples,
+        };
+
+        let group = SlaService::summarize(&history);
+        assert_eq!(group.p50_ms, 110_000);
+        assert_eq!(group.p95_ms, 200_000);
+        assert_eq!(group.within_expected_rate, 0.15);
+        assert_eq!(group.within_max_rate, 0.45);
+        assert_eq!(group.p95_overrun_ms, 110_000);
+
+        let estimate = SlaService::estimate_from(&history.samples).unwrap();
+        assert_eq!(
+            (estimate.min_duration_seconds, estimate.expected_duration_seconds, estimate.max_duration_seconds),
+            (30, 110, 200)
+        );
+        assert!(SlaService::estimate_from(&history.samples[..5]).is_none());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::infra::histogram::Histogram;
+use crate::infra::stable::{memory, Cbor, Memory, SLO_OBJECTIVES_MEMORY_ID, SLO_WINDOWS_MEMORY_ID};
+use crate::infra::Metrics;
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    static OBJECTIVES: RefCell<StableBTreeMap<String, Cbor<SloObjective>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SLO_OBJECTIVES_MEMORY_ID)));
+    // Keyed "{endpoint:?}/{bucket start:020}" so an endpoint's window is one range in time order
+    static WINDOWS: RefCell<StableBTreeMap<String, Cbor<SloBucket>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SLO_WINDOWS_MEMORY_ID)));
+}
+
+const BUCKET_NS: u64 = 5 * 60 * 1_000_000_000;
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const MAX_WINDOW_HOURS: u32 = 30 * 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum SloEndpoint {
+    Infer,
+    ExecuteAgentTask,
+    BindModel,
+}
+
+impl SloEndpoint {
+    const ALL: [SloEndpoint; 3] = [SloEndpoint::Infer, SloEndpoint::ExecuteAgentTask, SloEndpoint::BindModel];
+
+    fn default_objective(self) -> SloObjective {
+        let (latency_threshold_ms, target) = match self {
+            SloEndpoint::Infer => (5_000, 0.95),
+            SloEndpoint::ExecuteAgentTask => (30_000, 0.95),
+            SloEndpoint::BindModel => (120_000, 0.9),
+        };
+        SloObjective { endpoint: self, latency_threshold_ms, target, window_hours: 24 * 7 }
+    }
+}
+
+/// `target` of the calls in the window must succeed within the threshold;
+/// a target of 0.95 is the objective p95 < latency_threshold_ms
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SloObjective {
+    pub endpoint: SloEndpoint,
+    pub latency_threshold_ms: u64,
+    pub target: f32,
+    pub window_hours: u32,
+}
+
+/// Calls of one endpoint that started within one five-minute bucket
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SloBucket {
+    latency_ms: Histogram,
+    errors: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct EndpointSlo {
+    pub objective: SloObjective,
+    pub calls: u64,
+    pub errors: u64,
+    pub slow: u64, // Succeeded past the latency threshold
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub compliance: f32,             // Share of calls that were good; 1 with no calls
+    pub error_budget_remaining: f32, // Share of the allowed bad calls still unspent; negative once overspent
+    pub met: bool,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct SloReport {
+    pub generated_at: u64,
+    pub endpoints: Vec<EndpointSlo>,
+}
+
+/// Latency and error SLOs for the endpoints users wait on. Calls are
+/// bucketed by five minutes into bounded histograms; a report folds the
+/// buckets inside each objective's rolling window. Calls rejected by guards
+/// before any work is done are not recorded.
+pub struct SloService;
+
+impl SloService {
+    pub fn record(endpoint: SloEndpoint, started_at: u64, succeeded: bool) {
+        let now = time();
+        let latency_ms = now.saturating_sub(started_at) / 1_000_000;
+        let key = Self::key(endpoint, started_at - started_at % BUCKET_NS);
+        WINDOWS.with(|w| {
+            let mut windows = w.borrow_mut();
+            let mut bucket = windows.get(&key).map(|b| b.0).unwrap_or_default();
+            if succeeded {
+                bucket.latency_ms.record(latency_ms as f64);
+            } else {
+                bucket.errors += 1;
+            }
+            windows.insert(key, Cbor(bucket));
+        });
+        Metrics::record_histogram(&format!("endpoint_latency_ms:{:?}", endpoint), latency_ms as f64);
+        Self::prune(endpoint, now);
+    }
+
+    pub fn set_objective(objective: SloObjective) -> Result<(), String> {
+        if !(objective.target > 0.0 && objective.target < 1.0) {
+            return Err("SLO target must be between 0 and 1 exclusive".to_string());
+        }
+        if objective.latency_threshold_ms == 0 {
+            return Err("SLO latency threshold must be positive".to_string());
+        }
+        if objective.window_hours == 0 || objective.window_hours > MAX_WINDOW_HOURS {
+            return Err(format!("SLO window must be 1 to {} hours", MAX_WINDOW_HOURS));
+        }
+        OBJECTIVES.with(|o| o.borrow_mut().insert(format!("{:?}", objective.endpoint), Cbor(objective)));
+        Ok(())
+    }
+
+    pub fn objective(endpoint: SloEndpoint) -> SloObjective {
+        OBJECTIVES
+            .with(|o| o.borrow().get(&format!("{:?}", endpoint)).map(|o| o.0))
+            .unwrap_or_else(|| endpoint.default_objective())
+    }
+
+    pub fn report() -> SloReport {
+        let now = time();
+        let endpoints = SloEndpoint::ALL
+            .iter()
+            .map(|&endpoint| {
+                let objective = Self::objective(endpoint);
+                let since = now.saturating_sub(objective.window_hours as u64 * NANOS_PER_HOUR);
+                let mut latency_ms = Histogram::default();
+                let mut errors = 0;
+                let mut slow = 0;
+                for bucket in Self::buckets(endpoint, since) {
+                    latency_ms.merge(&bucket.latency_ms);
+                    errors += bucket.errors;
+                    slow += bucket.latency_ms.count() - bucket.latency_ms.cumulative(&[objective.latency_threshold_ms as f64])[0];
+                }
+                summarize(objective, &latency_ms, errors, slow)
+            })
+            .collect();
+        SloReport { generated_at: now, endpoints }
+    }
+
+    /// Buckets that started at or after `since`, oldest first
+    fn buckets(endpoint: SloEndpoint, since: u64) -> Vec<SloBucket> {
+        let prefix = format!("{:?}/", endpoint);
+        let start = Self::key(endpoint, since - since % BUCKET_NS);
+        WINDOWS.with(|w| {
+            w.borrow()
+                .range(start..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(_, bucket)| bucket.0)
+                .collect()
+        })
+    }
+
+    /// Drop the endpoint's buckets that fell out of the longest allowed window
+    fn prune(endpoint: SloEndpoint, now: u64) {
+        let cutoff = Self::key(endpoint, now.saturating_sub(MAX_WINDOW_HOURS as u64 * NANOS_PER_HOUR));
+        let prefix = format!("{:?}/", endpoint);
+        WINDOWS.with(|w| {
+            let mut windows = w.borrow_mut();
+            let expired: Vec<String> =
+                windows.range(prefix.clone()..cutoff).map(|(key, _)| key).collect();
+            for key in expired {
+                windows.remove(&key);
+            }
+        });
+    }
+
+    fn key(endpoint: SloEndpoint, bucket_start: u64) -> String {
+        format!("{:?}/{:020}", endpoint, bucket_start)
+    }
+}
+
+fn summarize(objective: SloObjective, latency_ms: &Histogram, errors: u64, slow: u64) -> EndpointSlo {
+    let calls = latency_ms.count() + errors;
+    let bad = errors + slow;
+    let compliance = if calls == 0 { 1.0 } else { (calls - bad) as f32 / calls as f32 };
+    let allowed = (1.0 - objective.target) * calls as f32;
+    let error_budget_remaining = if calls == 0 {
+        1.0
+    } else if allowed > 0.0 {
+        1.0 - bad as f32 / allowed
+    } else {
+        0.0
+    };
+    EndpointSlo {
+        met: compliance >= objective.target,
+        objective,
+        calls,
+        errors,
+        slow,
+        p50_ms: latency_ms.quantile(0.50),
+        p95_ms: latency_ms.quantile(0.95),
+        p99_ms: latency_ms.quantile(0.99),
+        compliance,
+        error_budget_remaining,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize_spends_error_budget_on_errors_and_slow_calls() {
+        let objective = SloEndpoint::Infer.default_objective();
+        let mut latency_ms = Histogram::default();
+        for _ in 0..195 {
+            latency_ms.record(100.0);
+        }
+        // 200 calls allow 10 bad ones at a 0.95 target; 5 are spent
+        let slo = summarize(objective.clone(), &latency_ms, 5, 0);
+        assert_eq!(slo.calls, 200);
+        assert!((slo.error_budget_remaining - 0.5).abs() < 1e-6);
+        assert!(slo.met);
+
+        let slo = summarize(objective, &latency_ms, 5, 10);
+        assert!(slo.error_budget_remaining < 0.0);
+        assert!(!slo.met);
+    }
+
+    #[test]
+    fn test_summarize_without_calls_keeps_the_whole_budget() {
+        let slo = summarize(SloEndpoint::BindModel.default_objective(), &Histogram::default(), 0, 0);
+        assert_eq!(slo.compliance, 1.0);
+        assert_eq!(slo.error_budget_remaining, 1.0);
+        assert_eq!(slo.p95_ms, None);
+    }
+}
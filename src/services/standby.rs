@@ -0,0 +1,242 @@
+use crate::infra::stable::{memory, Cbor, Memory, STANDBY_CONFIG_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::modelrepo::ModelManifest;
+use crate::services::{with_state, BindingService, CacheService, ModelPoolService, ModelRepoClient};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+thread_local! {
+    static CONFIG: RefCell<StableBTreeMap<u8, Cbor<StandbyConfig>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(STANDBY_CONFIG_MEMORY_ID)));
+    // Manifest and verified repos of the standby model; fetched again after an upgrade
+    static PREPARED: RefCell<Option<(ModelManifest, Vec<String>)>> = RefCell::new(None);
+    static LAST_REFILL: RefCell<Option<(u64, Option<String>)>> = RefCell::new(None);
+    static REFILLING: Cell<bool> = Cell::new(false);
+}
+
+const CONFIG_KEY: u8 = 0;
+const REFILL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Chunks fetched per refill, so one run never holds the loader for long
+const REFILL_BATCH: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct StandbyConfig {
+    pub model_id: String,
+    pub prefetch_percent: u8, // Of the model's bytes, in layer order
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct StandbyStatus {
+    pub model_id: String,
+    pub version: Option<String>, // None until the manifest has been fetched
+    pub prefetch_percent: u8,
+    pub target_bytes: u64,
+    pub cached_bytes: u64, // Of the target chunks
+    pub chunks_cached: u32,
+    pub total_chunks: u32,
+    pub ready: bool,
+    pub last_refill_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// A secondary model kept partly cached next to the bound model. The first
+/// `prefetch_percent` of its bytes are fetched ahead of time and held in its
+/// own chunk map, so they are only evicted under memory pressure; a refill
+/// timer tops them up after evictions and upgrades. Promoting the standby
+/// binds it, reusing every cached chunk, and the previous bound model takes
+/// its place as the standby.
+pub struct StandbyService;
+
+impl StandbyService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(REFILL_INTERVAL, || ic_cdk::spawn(Self::refill()));
+    }
+
+    pub async fn set(config: StandbyConfig) -> Result<StandbyStatus, String> {
+        if config.prefetch_percent == 0 || config.prefetch_percent > 100 {
+            return Err("prefetch_percent must be between 1 and 100".to_string());
+        }
+        if with_state(|s| s.binding.as_ref().is_some_and(|b| b.model_id == config.model_id)) {
+            return Err(format!("Model {} is already the bound model", config.model_id));
+        }
+        let (manifest, _, repos) = BindingService::fetch_bindable(&config.model_id).await?;
+        Self::release_prepared();
+        CONFIG.with(|c| c.borrow_mut().insert(CONFIG_KEY, Cbor(config)));
+        CacheService::link_cached_chunks(&manifest);
+        PREPARED.with(|p| *p.borrow_mut() = Some((manifest, repos)));
+        Self::refill().await;
+        Self::status().ok_or_else(|| "Standby model was cleared while being prepared".to_string())
+    }
+
+    /// Its chunks stay cached but become evictable
+    pub fn clear() -> Result<(), String> {
+        CONFIG
+            .with(|c| c.borrow_mut().remove(&CONFIG_KEY))
+            .ok_or_else(|| "No standby model is set".to_string())?;
+        Self::release_prepared();
+        LAST_REFILL.with(|l| *l.borrow_mut() = None);
+        Ok(())
+    }
+
+    pub fn config() -> Option<StandbyConfig> {
+        CONFIG.with(|c| c.borrow().get(&CONFIG_KEY).map(|c| c.0))
+    }
+
+    /// Bind the standby model and make the previously bound model the standby
+    pub async fn promote() -> Result<(), String> {
+        let config = Self::config().ok_or_else(|| "No standby model is set".to_string())?;
+        let previous = with_state(|s| s.binding.as_ref().map(|b| b.model_id.clone()));
+        let started_at = time();
+        BindingService::bind_model(config.model_id.clone()).await?;
+        Metrics::increment_counter("standby_promotions_total");
+        Metrics::record_histogram("standby_promotion_ms", (time() - started_at) as f64 / 1_000_000.0);
+
+        // bind_model took over the chunk map, so there is nothing to release
+        PREPARED.with(|p| *p.borrow_mut() = None);
+        match previous.filter(|previous| *previous != config.model_id) {
+            Some(model_id) => {
+                CONFIG.with(|c| c.borrow_mut().insert(CONFIG_KEY, Cbor(StandbyConfig { model_id, ..config })));
+                ic_cdk::spawn(Self::refill());
+            }
+            None => {
+                CONFIG.with(|c| c.borrow_mut().remove(&CONFIG_KEY));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn status() -> Option<StandbyStatus> {
+        let config = Self::config()?;
+        let (last_refill_at, last_error) = LAST_REFILL
+            .with(|l| l.borrow().clone())
+            .map_or((None, None), |(at, error)| (Some(at), error));
+        let mut status = StandbyStatus {
+            model_id: config.model_id.clone(),
+            version: None,
+            prefetch_percent: config.prefetch_percent,
+            target_bytes: 0,
+            cached_bytes: 0,
+            chunks_cached: 0,
+            total_chunks: 0,
+            ready: false,
+            last_refill_at,
+            last_error,
+        };
+        let Some((manifest, _)) = PREPARED.with(|p| p.borrow().clone()) else {
+            return Some(status);
+        };
+        let chunks = Self::chunk_states(&manifest);
+        let target = target_chunks(&chunks, config.prefetch_percent);
+        status.version = Some(manifest.version.clone());
+        status.total_chunks = chunks.len() as u32;
+        status.target_bytes = target.iter().map(|(_, size, _)| size).sum();
+        status.cached_bytes = target.iter().filter(|(_, _, cached)| *cached).map(|(_, size, _)| size).sum();
+        status.chunks_cached = chunks.iter().filter(|(_, _, cached)| *cached).count() as u32;
+        status.ready = status.cached_bytes >= status.target_bytes;
+        Some(status)
+    }
+
+    /// Fetch the target chunks that are not cached, a batch at a time
+    async fn refill() {
+        let Some(config) = Self::config() else { return };
+        // A bound or pooled standby is already cached by its binding
+        if with_state(|s| ModelPoolService::binding(s, &config.model_id).is_some()) {
+            return;
+        }
+        if REFILLING.with(|r| r.replace(true)) {
+            return;
+        }
+        let result = Self::fill(&config).await;
+        REFILLING.with(|r| r.set(false));
+        if let Err(e) = &result {
+            Metrics::increment_counter("standby_refill_failures_total");
+            ic_cdk::println!("Standby refill of {} failed: {}", config.model_id, e);
+        }
+        LAST_REFILL.with(|l| *l.borrow_mut() = Some((time(), result.err())));
+        if let Some(status) = Self::status() {
+            let coverage = if status.target_bytes == 0 { 1.0 } else { status.cached_bytes as f64 / status.target_bytes as f64 };
+            Metrics::set_gauge("standby_coverage", coverage);
+        }
+    }
+
+    async fn fill(config: &StandbyConfig) -> Result<(), String> {
+        let prepared = PREPARED.with(|p| p.borrow().clone()).filter(|(m, _)| m.model_id == config.model_id);
+        let (manifest, repos) = match prepared {
+            Some(prepared) => prepared,
+            None => {
+                let (manifest, _, repos) = BindingService::fetch_bindable(&config.model_id).await?;
+                CacheService::link_cached_chunks(&manifest);
+                PREPARED.with(|p| *p.borrow_mut() = Some((manifest.clone(), repos.clone())));
+                (manifest, repos)
+            }
+        };
+        let chunks = Self::chunk_states(&manifest);
+        let missing: Vec<String> = target_chunks(&chunks, config.prefetch_percent)
+            .into_iter()
+            .filter(|(_, _, cached)| !cached)
+            .map(|(id, _, _)| id)
+            .take(REFILL_BATCH)
+            .collect();
+        for chunk_id in missing {
+            let bytes = ModelRepoClient::get_chunk(&repos, &manifest, &chunk_id).await?;
+            CacheService::put_model_chunk(&manifest.model_id, &manifest.version, chunk_id, bytes)?;
+            Metrics::increment_counter("standby_chunks_fetched_total");
+        }
+        Ok(())
+    }
+
+    /// Drop the standby's chunk map unless a binding shares it
+    fn release_prepared() {
+        if let Some((manifest, _)) = PREPARED.with(|p| p.borrow_mut().take()) {
+            if !ModelPoolService::in_use(&manifest.model_id, &manifest.version) {
+                CacheService::release_model(&manifest.model_id, &manifest.version);
+            }
+        }
+    }
+
+    /// (chunk_id, size, cached) in layer order
+    fn chunk_states(manifest: &ModelManifest) -> Vec<(String, u64, bool)> {
+        with_state(|s| {
+            manifest
+                .chunks
+                .iter()
+                .map(|c| (c.id.clone(), c.size, CacheService::is_cached(s, manifest, &c.id)))
+                .collect()
+        })
+    }
+}
+
+/// The leading chunks that cover `percent` of the model's bytes; the last
+/// one may run past it
+fn target_chunks(chunks: &[(String, u64, bool)], percent: u8) -> Vec<(String, u64, bool)> {
+    let total: u64 = chunks.iter().map(|(_, size, _)| size).sum();
+    let target = total * percent.min(100) as u64 / 100;
+    let mut covered = 0;
+    chunks
+        .iter()
+        .take_while(|(_, size, _)| {
+            let take = covered < target;
+            covered += size;
+            take
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_chunks_cover_the_percentage_in_layer_order() {
+        let chunks: Vec<(String, u64, bool)> = (0..4).map(|i| (format!("c{}", i), 10, i == 2)).collect();
+        let ids = |target: Vec<(String, u64, bool)>| target.into_iter().map(|(id, _, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids(target_chunks(&chunks, 50)), vec!["c0", "c1"]);
+        assert_eq!(ids(target_chunks(&chunks, 55)), vec!["c0", "c1", "c2"]);
+        assert_eq!(target_chunks(&chunks, 100).len(), 4);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+const MAGIC: &[u8; 4] = b"OSEM";
+const FORMAT_VERSION: u32 = 1;
+const MAX_DIMENSIONS: u32 = 4_096;
+const MAX_WORD_CHARS: usize = 100; // Longer words are skipped, as WordPiece does
+
+/// Small embedding model served by the model repo and run on the canister.
+/// Every vocabulary token has a fixed vector; a text embeds as the
+/// L2-normalised mean of its WordPiece tokens' vectors (Model2Vec-style), so
+/// no matrix multiplication happens per call.
+///
+/// The model's chunks, concatenated in manifest order, hold (little endian):
+/// `b"OSEM"`, format version `u32`, dimensions `u32`, vocabulary size `u32`;
+/// then per token a `u16` byte length and its UTF-8 text, "##" marking word
+/// continuations; then the `vocab_size × dimensions` matrix as `f32`.
+pub struct StaticEmbeddingModel {
+    pub model_id: String,
+    pub version: String,
+    dimensions: usize,
+    vocab: HashMap<String, u32>,
+    weights: Vec<f32>,
+}
+
+impl StaticEmbeddingModel {
+    pub fn parse(model_id: &str, version: &str, bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err(format!("{} is not a static embedding model", model_id));
+        }
+        let format = reader.u32()?;
+        if format != FORMAT_VERSION {
+            return Err(format!("Unsupported static embedding format {}", format));
+        }
+        let dimensions = reader.u32()?;
+        if dimensions == 0 || dimensions > MAX_DIMENSIONS {
+            return Err(format!("Embedding dimensions must be between 1 and {}", MAX_DIMENSIONS));
+        }
+        let vocab_size = reader.u32()?;
+
+        let mut vocab = HashMap::with_capacity(vocab_size as usize);
+        for index in 0..vocab_size {
+            let len = reader.u16()? as usize;
+            let token = std::str::from_utf8(reader.take(len)?).map_err(|_| format!("Token {} is not UTF-8", index))?;
+            vocab.insert(token.to_string(), index);
+        }
+
+        let expected = vocab_size as usize * dimensions as usize * 4;
+        let matrix = reader.take(expected)?;
+        if reader.offset != bytes.len() {
+            return Err(format!("{} trailing bytes after the weight matrix", bytes.len() - reader.offset));
+        }
+        let weights = matrix.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+
+        Ok(Self {
+            model_id: model_id.to_string(),
+            version: version.to_string(),
+            dimensions: dimensions as usize,
+            vocab,
+            weights,
+        })
+    }
+
+    pub fn dimensions(&self) -> u32 {
+        self.dimensions as u32
+    }
+
+    /// A zero vector when no token of the text is in the vocabulary
+    pub fn embed(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; self.dimensions];
+        let mut count = 0;
+        for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+            for token in self.word_pieces(&word.to_lowercase()) {
+                let row = &self.weights[token as usize * self.dimensions..][..self.dimensions];
+                vector.iter_mut().zip(row).for_each(|(v, w)| *v += w);
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return vector;
+        }
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        vector
+    }
+
+    /// Greedy longest-match WordPiece; a word with an unmatched remainder has no tokens
+    fn word_pieces(&self, word: &str) -> Vec<u32> {
+        let chars: Vec<char> = word.chars().collect();
+        if chars.len() > MAX_WORD_CHARS {
+            return Vec::new();
+        }
+        let mut tokens = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            let piece = (start + 1..=chars.len()).rev().find_map(|end| {
+                let text: String = chars[start..end].iter().collect();
+                let text = if start > 0 { format!("##{}", text) } else { text };
+                self.vocab.get(&text).map(|&token| (token, end))
+            });
+            match piece {
+                Some((token, end)) => {
+                    tokens.push(token);
+                    start = end;
+                }
+                None => return Vec::new(),
+            }
+        }
+        tokens
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.offset.checked_add(len).filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or_else(|| "Static embedding model is truncated".to_string())?;
+        let slice = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(slice)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        self.take(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.take(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(vocab: &[(&str, [f32; 2])]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for value in [FORMAT_VERSION, 2, vocab.len() as u32] {
+            bytes.extend(value.to_le_bytes());
+        }
+        for (token, _) in vocab {
+            bytes.extend((token.len() as u16).to_le_bytes());
+            bytes.extend(token.as_bytes());
+        }
+        for (_, row) in vocab {
+            row.iter().for_each(|w| bytes.extend(w.to_le_bytes()));
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_embeds_the_mean_of_word_pieces() {
+        let bytes = model(&[("stable", [1.0, 0.0]), ("memory", [0.0, 1.0]), ("up", [1.0, 1.0]), ("##grade", [1.0, -1.0])]);
+        let model = StaticEmbeddingModel::parse("tiny", "1", &bytes).unwrap();
+        assert_eq!(model.dimensions(), 2);
+
+        let v = model.embed("Stable, MEMORY");
+        assert!((v[0] - v[1]).abs() < 1e-6 && (v[0] - 0.70710677).abs() < 1e-6);
+        // "upgrade" is up + ##grade; the unknown word adds nothing
+        assert_eq!(model.embed("upgrade zzz"), vec![1.0, 0.0]);
+        assert_eq!(model.embed("zzz"), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_rejects_truncated_or_foreign_bytes() {
+        let bytes = model(&[("stable", [1.0, 0.0])]);
+        assert!(StaticEmbeddingModel::parse("tiny", "1", &bytes[..bytes.len() - 1]).is_err());
+        assert!(StaticEmbeddingModel::parse("tiny", "1", b"GGUF....").is_err());
+    }
+}
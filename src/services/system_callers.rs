@@ -0,0 +1,134 @@
+use crate::infra::stable::{memory, Cbor, Memory, SYSTEM_AUDIT_MEMORY_ID, SYSTEM_CALLERS_MEMORY_ID};
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    static CALLERS: RefCell<StableBTreeMap<Principal, Cbor<SystemCaller>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SYSTEM_CALLERS_MEMORY_ID)));
+    static AUDIT: RefCell<StableBTreeMap<u64, Cbor<SystemAuditEntry>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SYSTEM_AUDIT_MEMORY_ID)));
+}
+
+const MAX_AUDIT_ENTRIES: u64 = 10_000;
+const MAX_LABEL_LEN: usize = 128;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 200;
+// Canister ids are opaque principals, whose last byte is this class tag
+const OPAQUE_PRINCIPAL_TAG: u8 = 0x01;
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SystemCaller {
+    pub canister_id: Principal,
+    pub label: String,
+    pub added_at: u64,
+    pub added_by: Principal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum SystemAuditAction {
+    CallerAdded { canister_id: Principal, label: String },
+    CallerRemoved { canister_id: Principal },
+    Called { method: String },
+    UserDataErased { subject_sha256: String, records_erased: u32 }, // Tombstone of a data subject deletion
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SystemAuditEntry {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub actor: Principal, // Admin for allowlist changes, the system canister for calls
+    pub action: SystemAuditAction,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct SystemAuditPage {
+    pub entries: Vec<SystemAuditEntry>,
+    pub next_cursor: Option<u64>,
+}
+
+/// Allowlist of canisters trusted with the bulk "system" endpoints (batch
+/// inference, agent migration, usage export). Every allowlist change and
+/// every system call is kept in an audit log.
+pub struct SystemCallerService;
+
+impl SystemCallerService {
+    pub fn add(canister_id: Principal, label: String, admin: Principal) -> Result<SystemCaller, String> {
+        if canister_id.as_slice().last() != Some(&OPAQUE_PRINCIPAL_TAG) || canister_id == Principal::management_canister() {
+            return Err("Invalid system caller: only canister ids can be allowlisted".to_string());
+        }
+        if label.len() > MAX_LABEL_LEN {
+            return Err(format!("Invalid label: at most {} bytes", MAX_LABEL_LEN));
+        }
+        let caller = SystemCaller {
+            canister_id,
+            label: label.clone(),
+            added_at: time(),
+            added_by: admin,
+        };
+        CALLERS.with(|c| c.borrow_mut().insert(canister_id, Cbor(caller.clone())));
+        Self::audit(admin, SystemAuditAction::CallerAdded { canister_id, label });
+        Ok(caller)
+    }
+
+    pub fn remove(canister_id: Principal, admin: Principal) -> Result<(), String> {
+        CALLERS
+            .with(|c| c.borrow_mut().remove(&canister_id))
+            .ok_or_else(|| format!("System caller {} not found", canister_id))?;
+        Self::audit(admin, SystemAuditAction::CallerRemoved { canister_id });
+        Ok(())
+    }
+
+    pub fn list() -> Vec<SystemCaller> {
+        CALLERS.with(|c| c.borrow().iter().map(|(_, caller)| caller.0).collect())
+    }
+
+    pub fn is_allowed(principal: &Principal) -> bool {
+        CALLERS.with(|c| c.borrow().contains_key(principal))
+    }
+
+    pub fn record_call(caller: Principal, method: &str) {
+        Self::audit(caller, SystemAuditAction::Called { method: method.to_string() });
+    }
+
+    pub fn record_erasure(actor: Principal, subject_sha256: String, records_erased: u32) {
+        Self::audit(actor, SystemAuditAction::UserDataErased { subject_sha256, records_erased });
+    }
+
+    /// Audit entries after `cursor`, oldest first
+    pub fn list_audit(cursor: Option<u64>, limit: Option<u32>) -> SystemAuditPage {
+        let limit = limit.map_or(DEFAULT_PAGE_SIZE, |l| (l as usize).clamp(1, MAX_PAGE_SIZE));
+        let start = cursor.map_or(0, |c| c + 1);
+        AUDIT.with(|a| {
+            let mut entries: Vec<SystemAuditEntry> = a.borrow().range(start..).take(limit + 1).map(|(_, e)| e.0).collect();
+            let next_cursor = if entries.len() > limit {
+                entries.truncate(limit);
+                entries.last().map(|e| e.sequence)
+            } else {
+                None
+            };
+            SystemAuditPage { entries, next_cursor }
+        })
+    }
+
+    fn audit(actor: Principal, action: SystemAuditAction) {
+        AUDIT.with(|a| {
+            let mut audit = a.borrow_mut();
+            let sequence = audit.last_key_value().map(|(seq, _)| seq + 1).unwrap_or(0);
+            audit.insert(
+                sequence,
+                Cbor(SystemAuditEntry {
+                    sequence,
+                    timestamp: time(),
+                    actor,
+                    action,
+                }),
+            );
+            if sequence >= MAX_AUDIT_ENTRIES {
+                audit.remove(&(sequence - MAX_AUDIT_ENTRIES));
+            }
+        });
+    }
+}
@@ -0,0 +1,184 @@
+use crate::domain::api_version::{paginate, PageRequest};
+use crate::domain::{InferenceRequest, InferenceResponse};
+use crate::infra::{Guards, Lane, Metrics, Resilience};
+use crate::services::agent_factory::{AgentStatus, AutonomousAgent};
+use crate::services::archive::{ArchiveService, ArchivedAgent};
+use crate::services::{with_state, with_state_mut, CertificationService, InferenceService, ShardingService};
+use candid::{CandidType, Principal};
+use ic_cdk::api::call::call;
+use crate::infra::clock::time;
+use serde::{Deserialize, Serialize};
+
+const MAX_BATCH_INFERENCES: usize = 32;
+const MAX_MIGRATION_BATCH: usize = 20;
+
+/// One entry of a batch; exactly one of response and error is set
+#[derive(Debug, Clone, CandidType)]
+pub struct BatchInferenceResult {
+    pub msg_id: String,
+    pub response: Option<InferenceResponse>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, CandidType)]
+pub struct AgentUsage {
+    pub agent_id: String,
+    pub owner: String,
+    pub tasks_completed: u32,
+    pub tasks_timed_out: u32,
+    pub total_tokens_used: u64,
+    pub tokens_today: u64,
+    pub tasks_today: u32,
+    pub cycles_spent: u64,
+    pub last_task_timestamp: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct UsageExportPage {
+    pub items: Vec<AgentUsage>,
+    pub next_cursor: Option<String>,
+    pub total: u64,
+}
+
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct MigrationReport {
+    pub moved: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// Bulk operations behind the system endpoints. Callers are allowlisted
+/// canisters, so these skip per-user rate limits and quotas.
+pub struct SystemOpsService;
+
+impl SystemOpsService {
+    /// Runs the requests one after another. The scheduler shares LLM slots
+    /// by principal, so a batch waits its turn behind other callers.
+    pub async fn batch_infer(requests: Vec<InferenceRequest>, caller: Principal) -> Result<Vec<BatchInferenceResult>, String> {
+        if requests.len() > MAX_BATCH_INFERENCES {
+            return Err(format!("Invalid batch: at most {} requests", MAX_BATCH_INFERENCES));
+        }
+        let mut results = Vec::with_capacity(requests.len());
+        for request in requests {
+            let msg_id = request.msg_id.clone();
+            let outcome = match Guards::validate_prompt_length(&request.prompt).and(Guards::validate_msg_id(&request.msg_id)) {
+                Ok(()) => InferenceService::process_inference_for(request, caller, Lane::Standard).await,
+                Err(e) => Err(e),
+            };
+            let (response, error) = match outcome {
+                Ok(response) => (Some(response), None),
+                Err(e) => (None, Some(e)),
+            };
+            results.push(BatchInferenceResult { msg_id, response, error });
+        }
+        Metrics::add_to_counter("batch_inferences_total", results.len() as u64);
+        Ok(results)
+    }
+
+    /// Usage counters of every live agent, ordered by agent id
+    pub fn export_usage(page: &PageRequest) -> UsageExportPage {
+        let usage: Vec<AgentUsage> = with_state(|state| state.agents.values().map(Self::usage).collect());
+        let (items, next_cursor, total) = paginate(usage, page, |u| u.agent_id.clone());
+        UsageExportPage { items, next_cursor, total }
+    }
+
+    pub fn usage(agent: &AutonomousAgent) -> AgentUsage {
+        AgentUsage {
+            agent_id: agent.agent_id.clone(),
+            owner: agent.user_id.clone(),
+            tasks_completed: agent.performance_metrics.tasks_completed,
+            tasks_timed_out: agent.performance_metrics.tasks_timed_out,
+            total_tokens_used: agent.performance_metrics.total_tokens_used,
+            tokens_today: agent.budget_usage.tokens_today,
+            tasks_today: agent.budget_usage.tasks_today,
+            cycles_spent: agent.budget_usage.cycles_spent,
+            last_task_timestamp: agent.performance_metrics.last_task_timestamp,
+        }
+    }
+
+    /// Move live agents to `target`, which must allowlist this canister as a
+    /// system caller. Each agent is taken out of service while it is sent and
+    /// put back if the target refuses it. Knowledge documents, task history
+    /// and traces stay here.
+    pub async fn migrate_agents(agent_ids: Vec<String>, target: Principal) -> Result<MigrationReport, String> {
+        if agent_ids.len() > MAX_MIGRATION_BATCH {
+            return Err(format!("Invalid batch: at most {} agents", MAX_MIGRATION_BATCH));
+        }
+        if target == ic_cdk::api::id() {
+            return Err("Invalid target: agents are already here".to_string());
+        }
+        let mut report = MigrationReport { moved: Vec::new(), errors: Vec::new() };
+        for agent_id in agent_ids {
+            let Some(agent) = with_state(|state| state.agents.get(&agent_id).cloned()) else {
+                report.errors.push(format!("Agent {} not found", agent_id));
+                continue;
+            };
+            if ArchiveService::is_busy(&agent_id) {
+                report.errors.push(format!("{} is running tasks or belongs to a coordinator", agent_id));
+                continue;
+            }
+            let packed = match ArchiveService::pack(&agent, time()) {
+                Ok(packed) => packed,
+                Err(e) => {
+                    report.errors.push(format!("{}: {}", agent_id, e));
+                    continue;
+                }
+            };
+
+            // Out of state.agents, no task or workflow step can start on it
+            with_state_mut(|state| state.agents.remove(&agent_id));
+            CertificationService::mark_dirty();
+            let imported: Result<(Result<MigrationReport, String>,), String> =
+                Resilience::call(&target.to_text(), "system_import_agents", || {
+                    call(target, "system_import_agents", (vec![packed.clone()],))
+                })
+                .await;
+            match imported.and_then(|(result,)| result) {
+                Ok(remote) if remote.moved.contains(&agent_id) => {
+                    if ShardingService::is_registered(&target) {
+                        ShardingService::add_route(&agent_id, target, &agent.user_id);
+                    }
+                    report.moved.push(agent_id);
+                }
+                outcome => {
+                    let error = match outcome {
+                        Ok(remote) => remote.errors.join("; "),
+                        Err(e) => e,
+                    };
+                    with_state_mut(|state| state.agents.insert(agent_id.clone(), agent));
+                    CertificationService::mark_dirty();
+                    report.errors.push(format!("{} to {}: {}", agent_id, target, error));
+                }
+            }
+        }
+        Metrics::add_to_counter("agents_migrated_total", report.moved.len() as u64);
+        Ok(report)
+    }
+
+    /// Receiving side of migrate_agents. Agents arrive Ready and keep their
+    /// owners, metrics and budgets.
+    pub fn import_agents(agents: Vec<ArchivedAgent>) -> Result<MigrationReport, String> {
+        if agents.len() > MAX_MIGRATION_BATCH {
+            return Err(format!("Invalid batch: at most {} agents", MAX_MIGRATION_BATCH));
+        }
+        Guards::check_memory_limits()?;
+        let mut report = MigrationReport { moved: Vec::new(), errors: Vec::new() };
+        for archived in agents {
+            if with_state(|state| state.agents.contains_key(&archived.agent_id)) || ArchiveService::is_archived(&archived.agent_id) {
+                report.errors.push(format!("Agent {} already exists here", archived.agent_id));
+                continue;
+            }
+            match ArchiveService::unpack(&archived) {
+                Ok(mut agent) if agent.agent_id == archived.agent_id => {
+                    agent.status = AgentStatus::Ready;
+                    agent.idle_flagged_at = None;
+                    with_state_mut(|state| state.agents.insert(agent.agent_id.clone(), agent));
+                    CertificationService::mark_dirty();
+                    report.moved.push(archived.agent_id);
+                }
+                Ok(_) => report.errors.push(format!("Agent {} does not match the packed agent", archived.agent_id)),
+                Err(e) => report.errors.push(format!("{}: {}", archived.agent_id, e)),
+            }
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,173 @@
+use crate::domain::instruction::RetentionPolicy;
+use crate::infra::stable::{memory, Cbor, Memory, TASK_HISTORY_MEMORY_ID, TASK_HISTORY_SEQUENCES_MEMORY_ID};
+use crate::services::agent_factory::{AgentTask, AgentTaskResult, AutonomousAgent};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    // "{agent_id}/{sequence:020}" -> record, so each agent's records are one ordered range
+    static RECORDS: RefCell<StableBTreeMap<String, Cbor<TaskRecord>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(TASK_HISTORY_MEMORY_ID)));
+    // agent_id -> next sequence; kept when the history empties so cursors never point at newer records
+    static SEQUENCES: RefCell<StableBTreeMap<String, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(TASK_HISTORY_SEQUENCES_MEMORY_ID)));
+}
+
+const MAX_RECORDS_PER_AGENT: usize = 200;
+const PAGE_SIZE: usize = 20;
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Stored outcome of a single task execution
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TaskRecord {
+    pub sequence: u64,
+    pub task: AgentTask,
+    pub result: AgentTaskResult,
+    pub recorded_at: u64,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct TaskHistoryPage {
+    pub records: Vec<TaskRecord>,
+    pub next_cursor: Option<u64>,
+    pub total: u64,
+}
+
+/// Bounded per-agent task history with retention driven by the agent's RetentionPolicy
+pub struct TaskHistoryService;
+
+impl TaskHistoryService {
+    pub fn record(agent: &AutonomousAgent, task: &AgentTask, result: &AgentTaskResult) {
+        let now = time();
+        let retention = &agent.analysis.agent_configuration.memory_configuration.retention_policy;
+        let expires_at = Self::retention_seconds(retention, agent.config.ttl_seconds)
+            .map(|secs| now + secs * NANOS_PER_SECOND);
+        let agent_id = &agent.agent_id;
+
+        let sequence = SEQUENCES.with(|s| {
+            let mut sequences = s.borrow_mut();
+            let sequence = sequences.get(agent_id).unwrap_or(0);
+            sequences.insert(agent_id.clone(), sequence + 1);
+            sequence
+        });
+
+        RECORDS.with(|r| {
+            let mut records = r.borrow_mut();
+            let stored: Vec<(String, Option<u64>)> = Self::range(&records, agent_id, 0)
+                .map(|(key, record)| (key, record.0.expires_at))
+                .collect();
+            let expired: Vec<&String> = stored
+                .iter()
+                .filter(|(_, expires_at)| expires_at.is_some_and(|exp| exp <= now))
+                .map(|(key, _)| key)
+                .collect();
+            let kept = stored.len() - expired.len();
+            // Oldest first, so the overflow is the front of what is left
+            let overflow = (kept + 1).saturating_sub(MAX_RECORDS_PER_AGENT);
+            let oldest = stored
+                .iter()
+                .filter(|(_, expires_at)| expires_at.map_or(true, |exp| exp > now))
+                .map(|(key, _)| key)
+                .take(overflow);
+            for key in expired.into_iter().chain(oldest) {
+                records.remove(key);
+            }
+
+            records.insert(Self::key(agent_id, sequence), Cbor(TaskRecord {
+                sequence,
+                task: task.clone(),
+                result: result.clone(),
+                recorded_at: now,
+                expires_at,
+            }));
+        });
+    }
+
+    pub fn get_task_result(agent_id: &str, task_id: &str) -> Result<TaskRecord, String> {
+        Self::live_records(agent_id)
+            .into_iter()
+            .rev()
+            .find(|r| r.task.task_id == task_id)
+            .ok_or_else(|| format!("Task {} not found for agent {}", task_id, agent_id))
+    }
+
+    /// Unexpired records, oldest first
+    pub fn live_records(agent_id: &str) -> Vec<TaskRecord> {
+        let now = time();
+        RECORDS.with(|r| {
+            Self::range(&r.borrow(), agent_id, 0)
+                .map(|(_, record)| record.0)
+                .filter(|r| r.expires_at.map_or(true, |exp| exp > now))
+                .collect()
+        })
+    }
+
+    /// Page through history oldest first; `cursor` is the sequence number to start from
+    pub fn list_task_history(agent_id: &str, cursor: Option<u64>) -> TaskHistoryPage {
+        let now = time();
+        let start = cursor.unwrap_or(0);
+        RECORDS.with(|r| {
+            let records = r.borrow();
+            let live = |record: &TaskRecord| record.expires_at.map_or(true, |exp| exp > now);
+            let total = Self::range(&records, agent_id, 0).filter(|(_, record)| live(&record.0)).count();
+            let page: Vec<TaskRecord> = Self::range(&records, agent_id, start)
+                .map(|(_, record)| record.0)
+                .filter(|record| live(record))
+                .take(PAGE_SIZE + 1)
+                .collect();
+
+            let (records, next_cursor) = if page.len() > PAGE_SIZE {
+                let next = page[PAGE_SIZE].sequence;
+                (page[..PAGE_SIZE].to_vec(), Some(next))
+            } else {
+                (page, None)
+            };
+
+            TaskHistoryPage {
+                records,
+                next_cursor,
+                total: total as u64,
+            }
+        })
+    }
+
+    /// Drop every record of the agent; its sequence is kept
+    pub fn forget_agent(agent_id: &str) {
+        RECORDS.with(|r| {
+            let mut records = r.borrow_mut();
+            let keys: Vec<String> = Self::range(&records, agent_id, 0).map(|(key, _)| key).collect();
+            for key in keys {
+                records.remove(&key);
+            }
+        });
+    }
+
+    /// The agent's records from `sequence` on, oldest first
+    fn range<'a>(
+        records: &'a StableBTreeMap<String, Cbor<TaskRecord>, Memory>,
+        agent_id: &str,
+        sequence: u64,
+    ) -> impl Iterator<Item = (String, Cbor<TaskRecord>)> + 'a {
+        let prefix = format!("{}/", agent_id);
+        records
+            .range(Self::key(agent_id, sequence)..)
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+    }
+
+    fn key(agent_id: &str, sequence: u64) -> String {
+        format!("{}/{:020}", agent_id, sequence)
+    }
+
+    fn retention_seconds(policy: &RetentionPolicy, session_ttl_seconds: u64) -> Option<u64> {
+        match policy {
+            RetentionPolicy::Session => Some(session_ttl_seconds),
+            RetentionPolicy::Daily => Some(24 * 60 * 60),
+            RetentionPolicy::Weekly => Some(7 * 24 * 60 * 60),
+            RetentionPolicy::Persistent => None,
+        }
+    }
+}
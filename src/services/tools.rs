@@ -0,0 +1,246 @@
+use crate::domain::instruction::SafetyLevel;
+use crate::services::agent_env::AgentEnvService;
+use crate::services::agent_factory::{AgentFactory, AutonomousAgent};
+use crate::services::approvals::{ApprovalService, STRICT_RULE};
+use crate::services::canister_tools::CanisterToolService;
+use crate::services::code_sandbox::{CodeSandbox, CODE_EXEC_TOOL};
+use crate::services::with_state_mut;
+use crate::infra::Metrics;
+use candid::CandidType;
+use crate::infra::clock::time;
+use serde::{Deserialize, Serialize};
+
+const MAX_PENDING_CALLS_PER_AGENT: usize = 20;
+pub const PENDING_CALL_TTL_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Tool call requested on behalf of an agent
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ToolCall {
+    pub call_id: String,
+    pub tool: String,
+    pub arguments: String,
+    pub requested_by: String,
+    pub requested_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub enum ToolCallResult {
+    Executed { call_id: String, output: String },
+    PendingApproval { call_id: String }, // Waiting for the owner's respond_to_approval
+}
+
+/// What dispatch does with a call, from the agent's tool_access and safety level
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolPermission {
+    Allowed,
+    RequiresApproval,
+    Denied(String),
+}
+
+/// Permission-checked tool dispatch with human-in-the-loop approval
+pub struct ToolService;
+
+impl ToolService {
+    /// `tool_access` is an allowlist; Strict agents need approval for every call
+    pub fn permission(agent: &AutonomousAgent, tool: &str) -> ToolPermission {
+        let config = &agent.analysis.agent_configuration;
+        if !config.tool_access.iter().any(|t| t == tool) {
+            return ToolPermission::Denied(format!("Agent {} has no access to tool {}", agent.agent_id, tool));
+        }
+
+        let safety_level = agent.instruction.preferences.as_ref().map(|p| &p.safety_level);
+        if Self::requires_approval(safety_level) {
+            ToolPermission::RequiresApproval
+        } else {
+            ToolPermission::Allowed
+        }
+    }
+
+    pub async fn dispatch(
+        agent_id: &str,
+        tool: String,
+        arguments: String,
+        requested_by: &str,
+    ) -> Result<ToolCallResult, String> {
+        let mut agent = AgentFactory::find_agent(agent_id)?;
+        let call = ToolCall {
+            call_id: Self::next_call_id(),
+            tool,
+            arguments,
+            requested_by: requested_by.to_string(),
+            requested_at: time(),
+        };
+
+        // Owner risk rules also see the arguments
+        let permission = match Self::permission(&agent, &call.tool) {
+            ToolPermission::Allowed if ApprovalService::tool_call_rule(&agent, &call.tool, &call.arguments).is_some() => {
+                ToolPermission::RequiresApproval
+            }
+            permission => permission,
+        };
+        match permission {
+            ToolPermission::Denied(reason) => {
+                Metrics::increment_counter("tool_calls_denied_total");
+                Err(reason)
+            }
+            ToolPermission::RequiresApproval => {
+                let now = time();
+                agent.pending_tool_calls.retain(|c| now.saturating_sub(c.requested_at) < PENDING_CALL_TTL_NS);
+                if agent.pending_tool_calls.len() >= MAX_PENDING_CALLS_PER_AGENT {
+                    return Err(format!("Pending tool call limit reached. Maximum: {}", MAX_PENDING_CALLS_PER_AGENT));
+                }
+                let call_id = call.call_id.clone();
+                let rule = ApprovalService::tool_call_rule(&agent, &call.tool, &call.arguments)
+                    .unwrap_or_else(|| STRICT_RULE.to_string());
+                let summary = format!("{}({})", call.tool, call.arguments);
+                agent.pending_tool_calls.push(call);
+                ApprovalService::notify_requested(&agent, &call_id, &rule, &summary);
+                Self::save(agent);
+                Metrics::increment_counter("tool_calls_pending_total");
+                Ok(ToolCallResult::PendingApproval { call_id })
+            }
+            ToolPermission::Allowed => Self::run(&agent, call).await,
+        }
+    }
+
+    /// Owner approval: removes the call from the queue and runs it
+    pub async fn approve(agent_id: &str, owner: &str, call_id: &str) -> Result<ToolCallResult, String> {
+        let call = Self::take_pending(agent_id, owner, call_id)?;
+        let agent = AgentFactory::find_agent(agent_id)?;
+
+        // tool_access may have changed since the call was queued
+        if let ToolPermission::Denied(reason) = Self::permission(&agent, &call.tool) {
+            return Err(reason);
+        }
+        Metrics::increment_counter("tool_calls_approved_total");
+        Self::run(&agent, call).await
+    }
+
+    pub fn reject(agent_id: &str, owner: &str, call_id: &str) -> Result<(), String> {
+        Self::take_pending(agent_id, owner, call_id)?;
+        Metrics::increment_counter("tool_calls_rejected_total");
+        Ok(())
+    }
+
+    pub fn list_pending(agent_id: &str) -> Result<Vec<ToolCall>, String> {
+        let now = time();
+        let agent = AgentFactory::find_agent(agent_id)?;
+        Ok(agent
+            .pending_tool_calls
+            .into_iter()
+            .filter(|c| now.saturating_sub(c.requested_at) < PENDING_CALL_TTL_NS)
+            .collect())
+    }
+
+    /// Add or remove a tool on the agent's allowlist. Only approved canister
+    /// tools can be added; the analyzer assigns the rest at creation.
+    pub fn set_tool_access(agent_id: &str, tool: &str, enabled: bool) -> Result<(), String> {
+        let mut agent = AgentFactory::find_agent(agent_id)?;
+        let tool_access = &mut agent.analysis.agent_configuration.tool_access;
+        if enabled {
+            if !CanisterToolService::is_approved(tool) {
+                return Err(format!("Tool {} is not an approved canister tool", tool));
+            }
+            if !tool_access.iter().any(|t| t == tool) {
+                tool_access.push(tool.to_string());
+            }
+        } else {
+            tool_access.retain(|t| t != tool);
+        }
+        Self::save(agent);
+        Ok(())
+    }
+
+    fn requires_approval(safety_level: Option<&SafetyLevel>) -> bool {
+        match safety_level {
+            Some(SafetyLevel::Strict) => true,
+            Some(SafetyLevel::Standard) | Some(SafetyLevel::Flexible) | Some(SafetyLevel::Experimental) | None => false,
+        }
+    }
+
+    fn take_pending(agent_id: &str, owner: &str, call_id: &str) -> Result<ToolCall, String> {
+        let mut agent = AgentFactory::authorize(agent_id, owner)?;
+        let now = time();
+        let position = agent
+            .pending_tool_calls
+            .iter()
+            .position(|c| c.call_id == call_id)
+            .ok_or_else(|| format!("No pending tool call {} on agent {}", call_id, agent_id))?;
+        let call = agent.pending_tool_calls.remove(position);
+        Self::save(agent);
+
+        if now.saturating_sub(call.requested_at) >= PENDING_CALL_TTL_NS {
+            return Err(format!("Tool call {} expired before it was approved", call_id));
+        }
+        Ok(call)
+    }
+
+    /// Run a tool a skill step is bound to. There is nobody to approve the
+    /// call mid-task, so anything short of Allowed fails the step.
+    pub async fn run_bound(agent: &AutonomousAgent, tool: &str, arguments: String) -> Result<String, String> {
+        match Self::permission(agent, tool) {
+            ToolPermission::Allowed if ApprovalService::tool_call_rule(agent, tool, &arguments).is_none() => {}
+            ToolPermission::Denied(reason) => return Err(reason),
+            _ => return Err(format!("Tool {} requires approval and cannot run inside a skill", tool)),
+        }
+        let call = ToolCall {
+            call_id: Self::next_call_id(),
+            tool: tool.to_string(),
+            arguments,
+            requested_by: agent.user_id.clone(),
+            requested_at: time(),
+        };
+        Metrics::increment_counter("tool_calls_total");
+        Self::execute(agent, &call).await.inspect_err(|_| {
+            Metrics::increment_counter("tool_call_failures_total");
+        })
+    }
+
+    async fn run(agent: &AutonomousAgent, call: ToolCall) -> Result<ToolCallResult, String> {
+        Metrics::increment_counter("tool_calls_total");
+        let output = Self::execute(agent, &call).await.inspect_err(|_| {
+            Metrics::increment_counter("tool_call_failures_total");
+        })?;
+        Ok(ToolCallResult::Executed { call_id: call.call_id, output })
+    }
+
+    /// `${NAME}` in the arguments is the agent's env var NAME; secrets are
+    /// expanded for canister tools only, never into sandboxed code, and only
+    /// for calls the owner requested since the caller receives the output
+    async fn execute(agent: &AutonomousAgent, call: &ToolCall) -> Result<String, String> {
+        if call.tool == CODE_EXEC_TOOL {
+            return CodeSandbox::run_tool(&AgentEnvService::expand(agent, &call.arguments, false).await?);
+        }
+        if let Ok(tool) = CanisterToolService::get(&call.tool) {
+            let allow_secrets = call.requested_by == agent.user_id;
+            let arguments = AgentEnvService::expand(agent, &call.arguments, allow_secrets).await?;
+            return CanisterToolService::call(&agent.agent_id, &tool, call, &arguments).await;
+        }
+        Err(format!("Tool {} has no executor on this canister", call.tool))
+    }
+
+    fn next_call_id() -> String {
+        with_state_mut(|state| {
+            state.tool_call_seq += 1;
+            format!("call-{}-{}", time(), state.tool_call_seq)
+        })
+    }
+
+    fn save(agent: AutonomousAgent) {
+        with_state_mut(|state| {
+            state.agents.insert(agent.agent_id.clone(), agent);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_strict_requires_approval() {
+        assert!(ToolService::requires_approval(Some(&SafetyLevel::Strict)));
+        assert!(!ToolService::requires_approval(Some(&SafetyLevel::Standard)));
+        assert!(!ToolService::requires_approval(None));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::infra::stable::{memory, Cbor, Memory, EXECUTION_TRACES_MEMORY_ID};
+use crate::infra::Span;
+use crate::services::agent_factory::{AgentTask, AgentTaskResult, TaskOutcome};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+thread_local! {
+    // Keyed "{agent_id}/{task_id}"
+    static TRACES: RefCell<StableBTreeMap<String, Cbor<ExecutionTrace>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(EXECUTION_TRACES_MEMORY_ID)));
+}
+
+const MAX_TRACES_PER_AGENT: usize = 20;
+const MAX_STEPS: usize = 32;
+const MAX_DETAIL_CHARS: usize = 4_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum TraceStepKind {
+    Retrieval, // Knowledge passages looked up for the prompt
+    Prompt,    // Final prompt sent to the model
+    Inference,
+    Critique, // Self-critique, including the revision when one ran
+    ToolCall,
+    Translation, // Answer translated into the owner's preferred language
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TraceStep {
+    pub kind: TraceStepKind,
+    pub detail: String,
+    pub model_id: Option<String>,
+    pub tokens: u64,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub started_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ExecutionTrace {
+    pub agent_id: String,
+    pub task_id: String,
+    pub description: String,
+    pub outcome: TaskOutcome,
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub tokens_used: u64,
+    pub steps: Vec<TraceStep>,
+    pub truncated: bool, // Steps past the limit were dropped or details were cut
+}
+
+/// Steps of one task run, collected while it executes
+pub struct TraceRecorder {
+    started_at: u64,
+    steps: Vec<TraceStep>,
+    truncated: bool,
+}
+
+impl TraceRecorder {
+    pub fn new() -> Self {
+        Self {
+            started_at: time(),
+            steps: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    /// Step that began at `started_at` and ends now
+    pub fn record(&mut self, kind: TraceStepKind, started_at: u64, detail: &str) -> &mut TraceStep {
+        let step = self.push(kind, detail, (time().saturating_sub(started_at)) / 1_000_000);
+        step.started_at = started_at;
+        step
+    }
+
+    fn push(&mut self, kind: TraceStepKind, detail: &str, duration_ms: u64) -> &mut TraceStep {
+        let (detail, cut) = truncate(detail);
+        self.truncated |= cut;
+        if self.steps.len() == MAX_STEPS {
+            // Later steps overwrite the last slot so the final one is kept
+            self.truncated = true;
+            self.steps.pop();
+        }
+        self.steps.push(TraceStep {
+            kind,
+            detail,
+            model_id: None,
+            tokens: 0,
+            duration_ms,
+            error: None,
+            started_at: 0,
+        });
+        self.steps.last_mut().expect("step was just pushed")
+    }
+}
+
+impl Default for TraceRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-task record of what a task run did: prompt, model calls, critique and
+/// tool calls with their tokens and durations. Only the newest traces of each
+/// agent are kept, and long prompts and outputs are cut.
+pub struct TraceService;
+
+impl TraceService {
+    pub fn save(agent_id: &str, task: &AgentTask, result: &AgentTaskResult, recorder: TraceRecorder) {
+        Self::export_spans(agent_id, task, result, &recorder);
+        let (description, cut) = truncate(&task.description);
+        let trace = ExecutionTrace {
+            agent_id: agent_id.to_string(),
+            task_id: task.task_id.clone(),
+            description,
+            outcome: result.outcome.clone(),
+            started_at: recorder.started_at,
+            duration_ms: result.execution_time_ms,
+            tokens_used: result.tokens_used,
+            steps: recorder.steps,
+            truncated: recorder.truncated || cut,
+        };
+        TRACES.with(|t| {
+            let mut traces = t.borrow_mut();
+            traces.insert(Self::key(agent_id, &task.task_id), Cbor(trace));
+
+            let prefix = format!("{}/", agent_id);
+            let mut agent_traces: Vec<(String, u64)> = traces
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, trace)| (key, trace.0.started_at))
+                .collect();
+            if agent_traces.len() > MAX_TRACES_PER_AGENT {
+                agent_traces.sort_by_key(|(_, started_at)| *started_at);
+                let overflow = agent_traces.len() - MAX_TRACES_PER_AGENT;
+                for (key, _) in agent_traces.into_iter().take(overflow) {
+                    traces.remove(&key);
+                }
+            }
+        });
+    }
+
+    /// A task span with an llm_call or tool_call child per model or tool step
+    fn export_spans(agent_id: &str, task: &AgentTask, result: &AgentTaskResult, recorder: &TraceRecorder) {
+        let task_span = Span::root("task")
+            .starting_at(recorder.started_at)
+            .attribute("ohms.agent_id", agent_id)
+            .attribute("ohms.task_id", &task.task_id)
+            .attribute("ohms.outcome", format!("{:?}", result.outcome))
+            .attribute("ohms.tokens", result.tokens_used);
+        for step in &recorder.steps {
+            let name = match step.kind {
+                TraceStepKind::Inference | TraceStepKind::Critique | TraceStepKind::Translation => "llm_call",
+                TraceStepKind::ToolCall => "tool_call",
+                TraceStepKind::Retrieval | TraceStepKind::Prompt => continue,
+            };
+            let mut span = task_span
+                .child(name)
+                .starting_at(step.started_at)
+                .attribute("ohms.step", format!("{:?}", step.kind))
+                .attribute("ohms.tokens", step.tokens);
+            if let Some(model_id) = &step.model_id {
+                span = span.attribute("ohms.model_id", model_id);
+            }
+            if step.kind == TraceStepKind::ToolCall {
+                // The detail starts with the tool's name
+                span = span.attribute("ohms.tool", step.detail.lines().next().unwrap_or_default());
+            }
+            span.end_at(step.started_at + step.duration_ms * 1_000_000, step.error.as_deref());
+        }
+        task_span.end(result.error_message.as_deref().filter(|_| !result.success));
+    }
+
+    pub fn forget_agent(agent_id: &str) {
+        let prefix = format!("{}/", agent_id);
+        TRACES.with(|t| {
+            let mut traces = t.borrow_mut();
+            let keys: Vec<String> = traces
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, _)| key)
+                .collect();
+            for key in keys {
+                traces.remove(&key);
+            }
+        });
+    }
+
+    pub fn get(agent_id: &str, task_id: &str) -> Result<ExecutionTrace, String> {
+        TRACES
+            .with(|t| t.borrow().get(&Self::key(agent_id, task_id)))
+            .map(|trace| trace.0)
+            .ok_or_else(|| format!("No trace for task {} of agent {}", task_id, agent_id))
+    }
+
+    fn key(agent_id: &str, task_id: &str) -> String {
+        format!("{}/{}", agent_id, task_id)
+    }
+}
+
+/// Text cut to MAX_DETAIL_CHARS, and whether it was cut
+fn truncate(text: &str) -> (String, bool) {
+    match text.char_indices().nth(MAX_DETAIL_CHARS) {
+        Some((end, _)) => (text[..end].to_string(), true),
+        None => (text.to_string(), false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder_bounds_steps_and_details() {
+        let mut recorder = TraceRecorder {
+            started_at: 0,
+            steps: Vec::new(),
+            truncated: false,
+        };
+        recorder.push(TraceStepKind::Prompt, "short", 1).tokens = 3;
+        assert!(!recorder.truncated);
+        assert_eq!(recorder.steps[0].tokens, 3);
+
+        recorder.push(TraceStepKind::Prompt, &"é".repeat(MAX_DETAIL_CHARS + 1), 1);
+        assert!(recorder.truncated);
+        assert_eq!(recorder.steps[1].detail.chars().count(), MAX_DETAIL_CHARS);
+
+        for _ in 0..MAX_STEPS {
+            recorder.push(TraceStepKind::ToolCall, "call", 1);
+        }
+        recorder.push(TraceStepKind::Inference, "last", 1);
+        assert_eq!(recorder.steps.len(), MAX_STEPS);
+        assert_eq!(recorder.steps.last().unwrap().kind, TraceStepKind::Inference);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::domain::{DecodeParams, InferenceRequest};
+use crate::infra::Metrics;
+use crate::services::agent_factory::AutonomousAgent;
+use crate::services::InferenceService;
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+/// Task context key; "true" keeps the untranslated answer on the result
+pub const INCLUDE_ORIGINAL_KEY: &str = "include_original";
+
+const LANGUAGE_NAMES: &[(&str, &str)] = &[
+    ("ar", "Arabic"),
+    ("de", "German"),
+    ("es", "Spanish"),
+    ("fr", "French"),
+    ("hi", "Hindi"),
+    ("id", "Indonesian"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("nl", "Dutch"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ru", "Russian"),
+    ("sv", "Swedish"),
+    ("sw", "Swahili"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+    ("vi", "Vietnamese"),
+    ("zh", "Chinese"),
+];
+
+/// Outcome of the translation pass, kept on the task result
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Translation {
+    pub language: String,
+    pub original: Option<String>, // Set when the task asked to keep the untranslated answer
+    pub tokens_used: u64,
+    pub error: Option<String>, // The answer is returned untranslated when set
+}
+
+/// Final answers translated into the language the agent's owner prefers.
+/// Agents answer in English, as their prompts are; a language other than
+/// English in AgentPreferences adds one LLM pass over the answer.
+pub struct TranslationService;
+
+impl TranslationService {
+    /// The preferred language when it is not English
+    pub fn target_language(agent: &AutonomousAgent) -> Option<String> {
+        let language = agent.instruction.preferences.as_ref()?.language.trim().to_lowercase();
+        let base = language.split(['-', '_']).next().unwrap_or_default();
+        if base.is_empty() || base == "en" || base == "english" {
+            return None;
+        }
+        Some(language)
+    }
+
+    /// The answer to return and the translation record. A failed call leaves
+    /// the answer untranslated rather than failing the task.
+    pub async fn translate(agent_id: &str, task_id: &str, language: String, answer: String, keep_original: bool) -> (String, Translation) {
+        let request = InferenceRequest {
+            seed: None,
+            prompt: Self::prompt(&language_name(&language), &answer),
+            decode_params: DecodeParams::default(),
+            msg_id: format!("{}-{}-translation", agent_id, task_id),
+            model_id: None,
+            structured_output: None,
+        };
+        match InferenceService::process_inference(request).await {
+            Ok(response) => {
+                Metrics::increment_counter("translations_total");
+                let translation = Translation {
+                    language,
+                    original: keep_original.then_some(answer),
+                    tokens_used: response.tokens.len() as u64,
+                    error: None,
+                };
+                (response.generated_text.trim().to_string(), translation)
+            }
+            Err(e) => {
+                Metrics::increment_counter("translations_failed_total");
+                let translation = Translation { language, original: None, tokens_used: 0, error: Some(e) };
+                (answer, translation)
+            }
+        }
+    }
+
+    fn prompt(language_name: &str, answer: &str) -> String {
+        format!(
+            "Translate the text below into {}. Keep its formatting, and leave code, \
+             URLs, identifiers and numbers unchanged.\n\nText:\n{}\n\nReply with the translation only.",
+            language_name, answer
+        )
+    }
+}
+
+/// English name of a language tag such as "es" or "pt-BR"; unknown tags are used as given
+fn language_name(language: &str) -> String {
+    let mut parts = language.split(['-', '_']);
+    let base = parts.next().unwrap_or_default();
+    let Some((_, name)) = LANGUAGE_NAMES.iter().find(|(code, _)| *code == base) else {
+        return language.to_string();
+    };
+    match parts.next() {
+        Some(region) => format!("{} ({})", name, region.to_uppercase()),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_name() {
+        assert_eq!(language_name("es"), "Spanish");
+        assert_eq!(language_name("pt-br"), "Portuguese (BR)");
+        assert_eq!(language_name("zh_tw"), "Chinese (TW)");
+        assert_eq!(language_name("Esperanto"), "Esperanto");
+    }
+}
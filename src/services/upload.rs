@@ -0,0 +1,243 @@
+use crate::infra::stable::{memory, Cbor, Memory, UPLOAD_CHUNKS_MEMORY_ID, UPLOAD_SESSIONS_MEMORY_ID};
+use crate::infra::{Guards, Metrics};
+use crate::services::with_state_mut;
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+thread_local! {
+    static SESSIONS: RefCell<StableBTreeMap<String, Cbor<UploadSession>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(UPLOAD_SESSIONS_MEMORY_ID)));
+    // "{upload_id}/{index:06}" -> raw bytes
+    static CHUNKS: RefCell<StableBTreeMap<String, Vec<u8>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(UPLOAD_CHUNKS_MEMORY_ID)));
+}
+
+const MAX_UPLOAD_BYTES: u64 = 100 * 1024 * 1024;
+const MAX_CHUNK_BYTES: usize = 1_900_000; // Leaves room for the rest of a 2 MB ingress message
+const MAX_CHUNKS: u32 = 10_000;
+const MAX_OPEN_UPLOADS_PER_OWNER: usize = 5;
+const UPLOAD_TTL_NS: u64 = 60 * 60 * 1_000_000_000;
+
+/// What the assembled payload is for; decides how `finish_upload` consumes it
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum UploadPurpose {
+    KnowledgeDocument { agent_id: String, namespace: String, title: String },
+    NovaqModel { model_id: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UploadSession {
+    upload_id: String,
+    owner: String,
+    purpose: UploadPurpose,
+    total_size: u64,
+    sha256: String,
+    received: Vec<bool>,
+    bytes_received: u64,
+    created_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct UploadStatus {
+    pub upload_id: String,
+    pub purpose: UploadPurpose,
+    pub total_size: u64,
+    pub chunk_count: u32,
+    pub chunks_received: u32,
+    pub missing_chunks: Vec<u32>,
+    pub bytes_received: u64,
+    pub expires_at: u64,
+}
+
+/// Chunked uploads for payloads larger than one ingress message. Chunks are
+/// hash-checked on arrival and the whole payload again on finish.
+pub struct UploadService;
+
+impl UploadService {
+    pub fn begin(owner: &str, purpose: UploadPurpose, total_size: u64, chunk_count: u32, sha256: String) -> Result<String, String> {
+        if total_size == 0 || total_size > MAX_UPLOAD_BYTES {
+            return Err(format!("Upload size must be between 1 and {} bytes", MAX_UPLOAD_BYTES));
+        }
+        Guards::check_memory_limits()?;
+        if chunk_count == 0 || chunk_count > MAX_CHUNKS {
+            return Err(format!("Chunk count must be between 1 and {}", MAX_CHUNKS));
+        }
+        if total_size > chunk_count as u64 * MAX_CHUNK_BYTES as u64 {
+            return Err(format!("{} chunks cannot hold {} bytes", chunk_count, total_size));
+        }
+        Self::parse_hash(&sha256)?;
+
+        Self::sweep_expired();
+        let open = SESSIONS.with(|s| s.borrow().iter().filter(|(_, session)| session.0.owner == owner).count());
+        if open >= MAX_OPEN_UPLOADS_PER_OWNER {
+            return Err(format!("Open upload limit reached. Maximum: {}", MAX_OPEN_UPLOADS_PER_OWNER));
+        }
+
+        let now = time();
+        let upload_id = with_state_mut(|state| {
+            state.upload_seq += 1;
+            format!("upload-{}-{}", now, state.upload_seq)
+        });
+        let session = UploadSession {
+            upload_id: upload_id.clone(),
+            owner: owner.to_string(),
+            purpose,
+            total_size,
+            sha256: sha256.to_lowercase(),
+            received: vec![false; chunk_count as usize],
+            bytes_received: 0,
+            created_at: now,
+        };
+        SESSIONS.with(|s| s.borrow_mut().insert(upload_id.clone(), Cbor(session)));
+        Ok(upload_id)
+    }
+
+    /// Store chunk `index`. Re-sending a chunk replaces it, so clients can retry freely.
+    pub fn put_chunk(owner: &str, upload_id: &str, index: u32, bytes: Vec<u8>, sha256: &str) -> Result<UploadStatus, String> {
+        let mut session = Self::session(owner, upload_id)?;
+        Guards::check_memory_limits()?;
+        let slot = index as usize;
+        if slot >= session.received.len() {
+            return Err(format!("Chunk index {} out of range", index));
+        }
+        if bytes.is_empty() || bytes.len() > MAX_CHUNK_BYTES {
+            return Err(format!("Chunks must be between 1 and {} bytes", MAX_CHUNK_BYTES));
+        }
+        if Sha256::digest(&bytes).as_slice() != Self::parse_hash(sha256)?.as_slice() {
+            return Err(format!("Chunk {} does not match its sha256", index));
+        }
+
+        let key = Self::chunk_key(upload_id, index);
+        let replaced = CHUNKS.with(|c| c.borrow().get(&key).map_or(0, |old| old.len() as u64));
+        let bytes_received = session.bytes_received - replaced + bytes.len() as u64;
+        if bytes_received > session.total_size {
+            return Err(format!("Upload exceeds its declared size of {} bytes", session.total_size));
+        }
+
+        CHUNKS.with(|c| c.borrow_mut().insert(key, bytes));
+        session.received[slot] = true;
+        session.bytes_received = bytes_received;
+        let status = Self::status_of(&session);
+        SESSIONS.with(|s| s.borrow_mut().insert(upload_id.to_string(), Cbor(session)));
+        Ok(status)
+    }
+
+    pub fn status(owner: &str, upload_id: &str) -> Result<UploadStatus, String> {
+        Self::session(owner, upload_id).map(|session| Self::status_of(&session))
+    }
+
+    /// Assemble and verify the payload, closing the upload either way once complete
+    pub fn finish(owner: &str, upload_id: &str) -> Result<(UploadPurpose, Vec<u8>), String> {
+        let session = Self::session(owner, upload_id)?;
+        let missing: Vec<u32> = Self::missing(&session);
+        if !missing.is_empty() {
+            return Err(format!("Upload incomplete: {} chunks missing, first {}", missing.len(), missing[0]));
+        }
+
+        let mut payload = Vec::with_capacity(session.total_size as usize);
+        CHUNKS.with(|c| {
+            let chunks = c.borrow();
+            for index in 0..session.received.len() as u32 {
+                if let Some(bytes) = chunks.get(&Self::chunk_key(upload_id, index)) {
+                    payload.extend_from_slice(&bytes);
+                }
+            }
+        });
+        Self::remove(&session);
+
+        if payload.len() as u64 != session.total_size {
+            return Err(format!("Upload is {} bytes, expected {}", payload.len(), session.total_size));
+        }
+        if hex::encode(Sha256::digest(&payload)) != session.sha256 {
+            Metrics::increment_counter("upload_hash_mismatches_total");
+            return Err("Assembled upload does not match its sha256".to_string());
+        }
+
+        Metrics::increment_counter("uploads_completed_total");
+        Ok((session.purpose, payload))
+    }
+
+    pub fn abort(owner: &str, upload_id: &str) -> Result<(), String> {
+        let session = Self::session(owner, upload_id)?;
+        Self::remove(&session);
+        Ok(())
+    }
+
+    fn session(owner: &str, upload_id: &str) -> Result<UploadSession, String> {
+        let session = SESSIONS
+            .with(|s| s.borrow().get(&upload_id.to_string()).map(|session| session.0))
+            .ok_or_else(|| format!("Upload {} not found", upload_id))?;
+        if session.owner != owner {
+            return Err(format!("Not authorized to access upload {}", upload_id));
+        }
+        if time().saturating_sub(session.created_at) >= UPLOAD_TTL_NS {
+            Self::remove(&session);
+            return Err(format!("Upload {} expired", upload_id));
+        }
+        Ok(session)
+    }
+
+    fn sweep_expired() {
+        let now = time();
+        let expired: Vec<UploadSession> = SESSIONS.with(|s| {
+            s.borrow()
+                .iter()
+                .map(|(_, session)| session.0)
+                .filter(|session| now.saturating_sub(session.created_at) >= UPLOAD_TTL_NS)
+                .collect()
+        });
+        for session in expired {
+            Self::remove(&session);
+        }
+    }
+
+    fn remove(session: &UploadSession) {
+        CHUNKS.with(|c| {
+            let mut chunks = c.borrow_mut();
+            for index in 0..session.received.len() as u32 {
+                chunks.remove(&Self::chunk_key(&session.upload_id, index));
+            }
+        });
+        SESSIONS.with(|s| s.borrow_mut().remove(&session.upload_id));
+    }
+
+    fn status_of(session: &UploadSession) -> UploadStatus {
+        let missing = Self::missing(session);
+        UploadStatus {
+            upload_id: session.upload_id.clone(),
+            purpose: session.purpose.clone(),
+            total_size: session.total_size,
+            chunk_count: session.received.len() as u32,
+            chunks_received: (session.received.len() - missing.len()) as u32,
+            missing_chunks: missing,
+            bytes_received: session.bytes_received,
+            expires_at: session.created_at + UPLOAD_TTL_NS,
+        }
+    }
+
+    fn missing(session: &UploadSession) -> Vec<u32> {
+        session
+            .received
+            .iter()
+            .enumerate()
+            .filter(|(_, received)| !**received)
+            .map(|(index, _)| index as u32)
+            .collect()
+    }
+
+    fn chunk_key(upload_id: &str, index: u32) -> String {
+        format!("{}/{:06}", upload_id, index)
+    }
+
+    fn parse_hash(sha256: &str) -> Result<Vec<u8>, String> {
+        let hash = hex::decode(sha256).map_err(|_| "sha256 must be hex encoded".to_string())?;
+        if hash.len() != 32 {
+            return Err("sha256 must be 32 bytes".to_string());
+        }
+        Ok(hash)
+    }
+}
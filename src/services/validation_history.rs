@@ -0,0 +1,123 @@
+use crate::infra::stable::{memory, Cbor, Memory, VALIDATION_HISTORY_MEMORY_ID};
+use crate::infra::Metrics;
+use crate::services::novaq_validation::{NOVAQModelMeta, NOVAQValidationResult};
+use candid::{CandidType, Principal};
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+thread_local! {
+    // "{model_id}/{digest}" -> validations of that content, oldest first
+    static VALIDATIONS: RefCell<StableBTreeMap<String, Cbor<Vec<ValidationRecord>>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(VALIDATION_HISTORY_MEMORY_ID)));
+}
+
+const MAX_RECORDS_PER_DIGEST: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ValidationRecord {
+    pub result: NOVAQValidationResult,
+    pub digest: String, // sha256 of the validated model data
+    pub validated_by: Principal,
+    pub overridden_by: Option<Principal>, // Admin who allowed binding despite a failure
+    pub overridden_at: Option<u64>,
+    #[serde(default)]
+    pub novaq_meta: Option<NOVAQModelMeta>, // Quantization parameters, checked before binding
+}
+
+/// Keeps every NOVAQ validation result. A model whose latest validation failed
+/// cannot be bound until an admin overrides that validation.
+pub struct ValidationHistoryService;
+
+impl ValidationHistoryService {
+    pub fn record(
+        result: &NOVAQValidationResult,
+        novaq_meta: Option<NOVAQModelMeta>,
+        model_data: &[u8],
+        validated_by: Principal,
+    ) {
+        let digest = hex::encode(Sha256::digest(model_data));
+        let key = Self::key(&result.model_id, &digest);
+        let record = ValidationRecord {
+            result: result.clone(),
+            digest,
+            validated_by,
+            overridden_by: None,
+            overridden_at: None,
+            novaq_meta,
+        };
+        VALIDATIONS.with(|v| {
+            let mut validations = v.borrow_mut();
+            let mut records = validations.get(&key).map(|records| records.0).unwrap_or_default();
+            records.push(record);
+            if records.len() > MAX_RECORDS_PER_DIGEST {
+                records.drain(..records.len() - MAX_RECORDS_PER_DIGEST);
+            }
+            validations.insert(key, Cbor(records));
+        });
+        if !result.validation_passed {
+            Metrics::increment_counter("novaq_validations_failed_total");
+        }
+    }
+
+    /// Every validation of the model, newest first
+    pub fn history(model_id: &str) -> Vec<ValidationRecord> {
+        let prefix = format!("{}/", model_id);
+        let mut records: Vec<ValidationRecord> = VALIDATIONS.with(|v| {
+            v.borrow()
+                .range(prefix.clone()..)
+                .take_while(|(key, _)| key.starts_with(&prefix))
+                // Digests are hex, so a longer model id sharing the prefix has another '/'
+                .filter(|(key, _)| !key[prefix.len()..].contains('/'))
+                .flat_map(|(_, records)| records.0)
+                .collect()
+        });
+        records.sort_by(|a, b| b.result.validation_timestamp.cmp(&a.result.validation_timestamp));
+        records
+    }
+
+    pub fn latest(model_id: &str) -> Option<ValidationRecord> {
+        Self::history(model_id).into_iter().next()
+    }
+
+    /// Allow binding the model although its latest validation failed
+    pub fn override_latest(model_id: &str, admin: Principal) -> Result<ValidationRecord, String> {
+        let latest = Self::latest(model_id).ok_or_else(|| format!("Model {} has no validations", model_id))?;
+        if latest.result.validation_passed {
+            return Err(format!("The latest validation of {} passed; nothing to override", model_id));
+        }
+        let key = Self::key(model_id, &latest.digest);
+        VALIDATIONS.with(|v| {
+            let mut validations = v.borrow_mut();
+            let mut records = validations.get(&key).map(|records| records.0).unwrap_or_default();
+            let record = records
+                .iter_mut()
+                .rev()
+                .find(|r| r.result.validation_timestamp == latest.result.validation_timestamp)
+                .ok_or_else(|| format!("Validation of {} not found", model_id))?;
+            record.overridden_by = Some(admin);
+            record.overridden_at = Some(time());
+            let updated = record.clone();
+            validations.insert(key, Cbor(records));
+            Ok(updated)
+        })
+    }
+
+    /// Models that were never validated can be bound
+    pub fn check_bindable(model_id: &str) -> Result<(), String> {
+        match Self::latest(model_id) {
+            Some(latest) if !latest.result.validation_passed && latest.overridden_by.is_none() => Err(format!(
+                "The latest validation of {} failed ({}); an admin must override it before binding",
+                model_id,
+                latest.result.issues.join("; ")
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn key(model_id: &str, digest: &str) -> String {
+        format!("{}/{}", model_id, digest)
+    }
+}
@@ -0,0 +1,203 @@
+use crate::infra::Metrics;
+use crate::services::modelrepo::ModelManifest;
+use crate::services::{with_state, with_state_mut, CacheService};
+use candid::CandidType;
+use crate::infra::clock::time;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+// Hits lose half their weight every hour, so the warm set follows shifts in load
+const HIT_HALF_LIFE_NS: f64 = 60.0 * 60.0 * 1_000_000_000.0;
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Access history of one model chunk
+#[derive(Debug, Clone, Default)]
+pub struct ChunkAccess {
+    pub hits: u64,
+    pub misses: u64,
+    pub score: f64, // Decayed access count as of `last_access`
+    pub last_access: u64,
+}
+
+/// Chunks that should stay cached for the bound model. Members are prefetched
+/// first and evicted last.
+#[derive(Debug, Clone, Default)]
+pub struct WarmSet {
+    pub chunk_ids: Vec<String>, // Highest priority first
+    pub members: HashSet<String>,
+    pub target_bytes: u64,
+    pub computed_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct WarmSetReport {
+    pub chunk_ids: Vec<String>,
+    pub target_bytes: u64,
+    pub warm_bytes: u64,
+    pub cached_warm_bytes: u64,
+    pub coverage: f32,
+    pub computed_at: u64,
+}
+
+/// Learns which chunks of the bound model are used and keeps the hottest ones,
+/// up to `warm_set_target` of the model's size, resident in the cache
+pub struct WarmSetService;
+
+impl WarmSetService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(REFRESH_INTERVAL, Self::refresh);
+    }
+
+    pub fn record_access(chunk_id: &str, hit: bool) {
+        let now = time();
+        with_state_mut(|state| {
+            let access = state.chunk_access.entry(chunk_id.to_string()).or_default();
+            access.score = Self::decayed(access.score, access.last_access, now) + 1.0;
+            access.last_access = now;
+            if hit {
+                access.hits += 1;
+            } else {
+                access.misses += 1;
+            }
+        });
+    }
+
+    /// Recompute the warm set from access history and publish its coverage
+    pub fn refresh() {
+        let now = time();
+        let warm_set = with_state(|state| {
+            state
+                .manifest
+                .as_ref()
+                .map(|manifest| Self::compute(manifest, &state.chunk_access, state.config.warm_set_target, now))
+        });
+        with_state_mut(|state| state.warm_set = warm_set.unwrap_or_default());
+        Metrics::set_gauge("warm_set_coverage", Self::report().coverage as f64);
+    }
+
+    /// Warm set `manifest` would get from the current access history
+    pub fn compute_for(manifest: &ModelManifest) -> WarmSet {
+        with_state(|state| Self::compute(manifest, &state.chunk_access, state.config.warm_set_target, time()))
+    }
+
+    /// Manifest chunks that are not cached yet, warm-set members first, then in
+    /// layer order
+    pub fn prefetch_order(manifest: &ModelManifest, warm_set: &WarmSet) -> Vec<String> {
+        with_state(|state| {
+            let cached = |id: &String| CacheService::is_cached(state, manifest, id);
+            let warm = warm_set.chunk_ids.iter().filter(|id| !cached(id)).cloned();
+            let rest = manifest
+                .chunks
+                .iter()
+                .map(|chunk| &chunk.id)
+                .filter(|id| !cached(id) && !warm_set.members.contains(*id))
+                .cloned();
+            warm.chain(rest).collect()
+        })
+    }
+
+    pub fn report() -> WarmSetReport {
+        with_state(|state| {
+            let sizes: HashMap<&str, u64> = state
+                .manifest
+                .iter()
+                .flat_map(|m| m.chunks.iter().map(|c| (c.id.as_str(), c.size)))
+                .collect();
+            let size = |id: &String| sizes.get(id.as_str()).copied().unwrap_or(0);
+            let warm_bytes: u64 = state.warm_set.chunk_ids.iter().map(size).sum();
+            let cached_warm_bytes: u64 = state
+                .warm_set
+                .chunk_ids
+                .iter()
+                .filter(|id| state.manifest.as_ref().is_some_and(|m| CacheService::is_cached(state, m, id)))
+                .map(size)
+                .sum();
+            WarmSetReport {
+                chunk_ids: state.warm_set.chunk_ids.clone(),
+                target_bytes: state.warm_set.target_bytes,
+                warm_bytes,
+                cached_warm_bytes,
+                coverage: if warm_bytes == 0 { 0.0 } else { cached_warm_bytes as f32 / warm_bytes as f32 },
+                computed_at: state.warm_set.computed_at,
+            }
+        })
+    }
+
+    /// Rank chunks by decayed hits, breaking ties by layer order since inference
+    /// walks the layers front to back, and take them until `target` of the model
+    /// size is covered. With no history this is simply the first layers.
+    pub fn compute(manifest: &ModelManifest, access: &HashMap<String, ChunkAccess>, target: f32, now: u64) -> WarmSet {
+        let total_bytes: u64 = manifest.chunks.iter().map(|c| c.size).sum();
+        let target_bytes = (total_bytes as f64 * target.clamp(0.0, 1.0) as f64) as u64;
+
+        let mut ranked: Vec<(usize, f64)> = manifest
+            .chunks
+            .iter()
+            .enumerate()
+            .map(|(layer, chunk)| {
+                let score = access
+                    .get(&chunk.id)
+                    .map_or(0.0, |a| Self::decayed(a.score, a.last_access, now));
+                (layer, score)
+            })
+            .collect();
+        ranked.sort_by(|(la, sa), (lb, sb)| sb.total_cmp(sa).then(la.cmp(lb)));
+
+        let mut chunk_ids = Vec::new();
+        let mut used = 0u64;
+        for (layer, _) in ranked {
+            let chunk = &manifest.chunks[layer];
+            if used + chunk.size > target_bytes {
+                continue;
+            }
+            used += chunk.size;
+            chunk_ids.push(chunk.id.clone());
+        }
+
+        WarmSet {
+            members: chunk_ids.iter().cloned().collect(),
+            chunk_ids,
+            target_bytes,
+            computed_at: now,
+        }
+    }
+
+    fn decayed(score: f64, since: u64, now: u64) -> f64 {
+        score * 0.5f64.powf(now.saturating_sub(since) as f64 / HIT_HALF_LIFE_NS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::modelrepo::{ChunkInfo, ModelState};
+
+    fn manifest(sizes: &[u64]) -> ModelManifest {
+        ModelManifest {
+            model_id: "m".to_string(),
+            version: "1".to_string(),
+            chunks: sizes
+                .iter()
+                .enumerate()
+                .map(|(i, size)| ChunkInfo { id: format!("c{}", i), offset: 0, size: *size, sha256: String::new() })
+                .collect(),
+            digest: String::new(),
+            state: ModelState::Active,
+            uploaded_at: 0,
+            activated_at: None,
+        }
+    }
+
+    #[test]
+    fn test_warm_set_prefers_hot_chunks_within_target() {
+        let manifest = manifest(&[10, 10, 10, 10]);
+        let cold = WarmSetService::compute(&manifest, &HashMap::new(), 0.5, 0);
+        assert_eq!(cold.chunk_ids, vec!["c0", "c1"]);
+
+        let mut access = HashMap::new();
+        access.insert("c3".to_string(), ChunkAccess { hits: 5, misses: 0, score: 5.0, last_access: 0 });
+        let warm = WarmSetService::compute(&manifest, &access, 0.5, 0);
+        assert_eq!(warm.chunk_ids, vec!["c3", "c0"]);
+        assert_eq!(warm.target_bytes, 20);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::services::agent_factory::AgentFactory;
+use crate::services::{with_state, with_state_mut};
+use crate::infra::Metrics;
+use candid::CandidType;
+use hmac::{Hmac, Mac};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use crate::infra::clock::time;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::Duration;
+
+const MAX_WEBHOOKS_PER_AGENT: usize = 5;
+const MAX_DELIVERIES_PER_AGENT: usize = 100;
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_BASE_SECONDS: u64 = 10;
+const OUTCALL_CYCLES: u128 = 2_000_000_000;
+const MAX_RESPONSE_BYTES: u64 = 2_048;
+pub const TRANSFORM_METHOD: &str = "transform_webhook_response";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum WebhookEvent {
+    TaskCompleted,
+    TaskFailed,
+    AgentError,
+    QuotaExceeded,
+    ApprovalRequested,
+    AlertRaised,   // Operator alert rules of the agent's owner
+    AlertResolved,
+}
+
+/// Endpoint registered by an agent's owner. The secret never leaves the canister.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub webhook_id: String,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<WebhookEvent>,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct WebhookInfo {
+    pub webhook_id: String,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, CandidType)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed(String), // Gave up after MAX_ATTEMPTS
+}
+
+#[derive(Debug, Clone, CandidType)]
+pub struct WebhookDelivery {
+    pub delivery_id: String,
+    pub webhook_id: String,
+    pub event: WebhookEvent,
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub last_attempt_at: Option<u64>,
+    pub last_response_status: Option<u32>,
+    pub last_error: Option<String>,
+}
+
+/// Signed HTTPS notifications for agent events, retried with exponential backoff
+pub struct WebhookService;
+
+impl WebhookService {
+    pub fn register(agent_id: &str, url: String, secret: String, events: Vec<WebhookEvent>) -> Result<String, String> {
+        if !url.starts_with("https://") {
+            return Err("Webhook URL must use https".to_string());
+        }
+        if secret.len() < 16 {
+            return Err("Webhook secret must be at least 16 characters".to_string());
+        }
+        if events.is_empty() {
+            return Err("Webhook must subscribe to at least one event".to_string());
+        }
+
+        let mut agent = AgentFactory::find_agent(agent_id)?;
+        if agent.webhooks.len() >= MAX_WEBHOOKS_PER_AGENT {
+            return Err(format!("Webhook limit reached. Maximum: {}", MAX_WEBHOOKS_PER_AGENT));
+        }
+
+        let now = time();
+        let webhook_id = with_state_mut(|state| {
+            state.webhook_seq += 1;
+            format!("wh-{}-{}", now, state.webhook_seq) // Sequence resets on upgrade, time does not
+        });
+        agent.webhooks.push(Webhook {
+            webhook_id: webhook_id.clone(),
+            url,
+            secret,
+            events,
+            created_at: now,
+        });
+        with_state_mut(|state| {
+            state.agents.insert(agent.agent_id.clone(), agent);
+        });
+        Ok(webhook_id)
+    }
+
+    pub fn remove(agent_id: &str, webhook_id: &str) -> Result<(), String> {
+        let mut agent = AgentFactory::find_agent(agent_id)?;
+        let before = agent.webhooks.len();
+        agent.webhooks.retain(|w| w.webhook_id != webhook_id);
+        if agent.webhooks.len() == before {
+            return Err(format!("Webhook {} not found", webhook_id));
+        }
+        with_state_mut(|state| {
+            state.agents.insert(agent.agent_id.clone(), agent);
+        });
+        Ok(())
+    }
+
+    pub fn list(agent_id: &str) -> Result<Vec<WebhookInfo>, String> {
+        let agent = AgentFactory::find_agent(agent_id)?;
+        Ok(agent
+            .webhooks
+            .into_iter()
+            .map(|w| WebhookInfo {
+                webhook_id: w.webhook_id,
+                url: w.url,
+                events: w.events,
+                created_at: w.created_at,
+            })
+            .collect())
+    }
+
+    /// Most recent deliveries first
+    pub fn list_deliveries(agent_id: &str) -> Vec<WebhookDelivery> {
+        with_state(|state| {
+            state
+                .webhook_deliveries
+                .get(agent_id)
+                .map(|deliveries| deliveries.iter().rev().cloned().collect())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Queue a delivery to every webhook on the agent subscribed to `event`
+    pub fn notify(agent_id: &str, event: WebhookEvent, data: serde_json::Value) {
+        let webhooks: Vec<Webhook> = with_state(|state| {
+            state
+                .agents
+                .get(agent_id)
+                .map(|agent| agent.webhooks.iter().filter(|w| w.events.contains(&event)).cloned().collect())
+                .unwrap_or_default()
+        });
+        if webhooks.is_empty() {
+            return;
+        }
+
+        let now = time();
+        let payload = serde_json::json!({
+            "event": event,
+            "agent_id": agent_id,
+            "timestamp": now,
+            "data": data,
+        })
+        .to_string();
+
+        for webhook in webhooks {
+            let delivery_id = with_state_mut(|state| {
+                state.webhook_seq += 1;
+                let delivery_id = format!("{}-{}", webhook.webhook_id, state.webhook_seq);
+                let deliveries = state.webhook_deliveries.entry(agent_id.to_string()).or_default();
+                deliveries.push(WebhookDelivery {
+                    delivery_id: delivery_id.clone(),
+                    webhook_id: webhook.webhook_id.clone(),
+                    event,
+                    payload: payload.clone(),
+                    status: DeliveryStatus::Pending,
+                    attempts: 0,
+                    last_attempt_at: None,
+                    last_response_status: None,
+                    last_error: None,
+                });
+                if deliveries.len() > MAX_DELIVERIES_PER_AGENT {
+                    let overflow = deliveries.len() - MAX_DELIVERIES_PER_AGENT;
+                    deliveries.drain(..overflow);
+                }
+                delivery_id
+            });
+            Self::schedule(agent_id.to_string(), delivery_id, Duration::ZERO);
+        }
+    }
+
+    /// Notify `event` on every agent the user owns
+    pub fn notify_user(user_id: &str, event: WebhookEvent, data: serde_json::Value) {
+        let agent_ids: Vec<String> = with_state(|state| {
+            state
+                .agents
+                .values()
+                .filter(|agent| agent.user_id == user_id)
+                .map(|agent| agent.agent_id.clone())
+                .collect()
+        });
+        for agent_id in agent_ids {
+            Self::notify(&agent_id, event, data.clone());
+        }
+    }
+
+    /// Strip everything replicas may disagree on so the outcall reaches consensus
+    pub fn transform(args: TransformArgs) -> HttpResponse {
+        HttpResponse {
+            status: args.response.status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    fn schedule(agent_id: String, delivery_id: String, delay: Duration) {
+        ic_cdk_timers::set_timer(delay, move || {
+            ic_cdk::spawn(Self::deliver(agent_id, delivery_id));
+        });
+    }
+
+    async fn deliver(agent_id: String, delivery_id: String) {
+        let Some((delivery, webhook)) = Self::lookup(&agent_id, &delivery_id) else {
+            return; // Webhook removed or delivery evicted
+        };
+
+        let timestamp = time().to_string();
+        let signature = Self::sign(&webhook.secret, format!("{}.{}", timestamp, delivery.payload).as_bytes());
+        let request = CanisterHttpRequestArgument {
+            url: webhook.url.clone(),
+            method: HttpMethod::POST,
+            body: Some(delivery.payload.into_bytes()),
+            max_response_bytes: Some(MAX_RESPONSE_BYTES),
+            transform: Some(TransformContext::from_name(TRANSFORM_METHOD.to_string(), vec![])),
+            headers: vec![
+                HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() },
+                HttpHeader { name: "X-OHMS-Event".to_string(), value: format!("{:?}", delivery.event) },
+                HttpHeader { name: "X-OHMS-Timestamp".to_string(), value: timestamp },
+                HttpHeader { name: "X-OHMS-Signature".to_string(), value: format!("sha256={}", signature) },
+                // Every replica sends the request; receivers should dedupe on this key
+                HttpHeader { name: "Idempotency-Key".to_string(), value: delivery_id.clone() },
+            ],
+        };
+
+        let outcome = match http_request(request, OUTCALL_CYCLES).await {
+            Ok((response,)) => {
+                let status: u32 = response.status.0.try_into().unwrap_or(0);
+                if (200..300).contains(&status) {
+                    Ok(status)
+                } else {
+                    Err((Some(status), format!("Endpoint returned HTTP {}", status)))
+                }
+            }
+            Err((code, msg)) => Err((None, format!("{:?}: {}", code, msg))),
+        };
+
+        let retry = with_state_mut(|state| {
+            let delivery = state
+                .webhook_deliveries
+                .get_mut(&agent_id)
+                .and_then(|deliveries| deliveries.iter_mut().find(|d| d.delivery_id == delivery_id))?;
+            delivery.attempts += 1;
+            delivery.last_attempt_at = Some(time());
+
+            match outcome {
+                Ok(status) => {
+                    delivery.last_response_status = Some(status);
+                    delivery.last_error = None;
+                    delivery.status = DeliveryStatus::Delivered;
+                    None
+                }
+                Err((status, error)) => {
+                    delivery.last_response_status = status;
+                    delivery.last_error = Some(error.clone());
+                    if delivery.attempts >= MAX_ATTEMPTS {
+                        delivery.status = DeliveryStatus::Failed(error);
+                        None
+                    } else {
+                        Some(Duration::from_secs(RETRY_BASE_SECONDS << (delivery.attempts - 1)))
+                    }
+                }
+            }
+        });
+
+        match retry {
+            Some(delay) => {
+                Metrics::increment_counter("webhook_retries_total");
+                Self::schedule(agent_id, delivery_id, delay);
+            }
+            None => Metrics::increment_counter("webhook_deliveries_total"),
+        }
+    }
+
+    fn lookup(agent_id: &str, delivery_id: &str) -> Option<(WebhookDelivery, Webhook)> {
+        with_state(|state| {
+            let delivery = state
+                .webhook_deliveries
+                .get(agent_id)?
+                .iter()
+                .find(|d| d.delivery_id == delivery_id && d.status == DeliveryStatus::Pending)?
+                .clone();
+            let webhook = state
+                .agents
+                .get(agent_id)?
+                .webhooks
+                .iter()
+                .find(|w| w.webhook_id == delivery.webhook_id)?
+                .clone();
+            Some((delivery, webhook))
+        })
+    }
+
+    /// Hex HMAC-SHA256 of `message`, sent as `sha256=<hex>` over `"{timestamp}.{body}"`
+    fn sign(secret: &str, message: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(message);
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_matches_reference_hmac() {
+        assert_eq!(
+            WebhookService::sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use crate::infra::stable::{memory, Cbor, Memory, WORKFLOWS_MEMORY_ID};
+use crate::domain::instruction::{AgentType, AggregationStrategy};
+use crate::services::agent_factory::{AgentFactory, AgentTask, TaskPriority};
+use crate::services::aggregation::Aggregator;
+use crate::services::coordinator::{Assignment, CoordinatorService};
+use crate::services::{with_state_mut, CertificationService};
+use candid::CandidType;
+use crate::infra::clock::time;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+thread_local! {
+    static WORKFLOWS: RefCell<StableBTreeMap<String, Cbor<Workflow>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(WORKFLOWS_MEMORY_ID)));
+}
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+// A Running workflow not checkpointed for this long is assumed to have trapped
+const STALE_AFTER_NS: u64 = 5 * 60 * 1_000_000_000;
+// Finished workflows are kept this long after their last checkpoint
+const COMPLETED_RETENTION_NS: u64 = 7 * NANOS_PER_DAY;
+const INTERRUPTED_RETENTION_NS: u64 = 30 * NANOS_PER_DAY; // Left unresumed this long, they are abandoned
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Multi-step task executed across agents, checkpointed after every step
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Workflow {
+    pub workflow_id: String,
+    pub owner: String,
+    pub task_description: String,
+    pub steps: Vec<WorkflowStep>,
+    pub next_step: u32,
+    pub status: WorkflowStatus,
+    pub created_at: u64,
+    pub updated_at: u64,
+    #[serde(default = "default_chain_outputs")]
+    pub chain_outputs: bool, // Feed each step the previous step's output
+    #[serde(default)]
+    pub coordinator_id: Option<String>,
+    #[serde(default)]
+    pub final_output: Option<String>,
+    #[serde(default)]
+    pub aggregation: Option<AggregationStrategy>, // None: the last step's output is final
+    #[serde(default)]
+    pub aggregation_summary: Option<String>,
+}
+
+fn default_chain_outputs() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct WorkflowStep {
+    pub index: u32,
+    pub agent_id: String,
+    #[serde(default)]
+    pub description: String,
+    pub status: StepStatus,
+    pub output: Option<String>,
+    pub tokens_used: u64,
+    pub completed_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CandidType)]
+pub enum WorkflowStatus {
+    Running,
+    Interrupted, // Trapped, failed or upgraded mid-way; resumable
+    Completed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum StepStatus {
+    Pending,
+    Completed,
+    Failed(String),
+}
+
+/// Sequential workflow engine with stable-memory checkpoints
+pub struct WorkflowService;
+
+impl WorkflowService {
+    pub fn start_timer() {
+        ic_cdk_timers::set_timer_interval(PRUNE_INTERVAL, || {
+            Self::prune(time());
+        });
+    }
+
+    /// Start a workflow where each agent in order builds on the previous step's output
+    pub async fn start_workflow(
+        owner: String,
+        agent_ids: Vec<String>,
+        task_description: String,
+    ) -> Result<Workflow, String> {
+        if agent_ids.is_empty() {
+            return Err("Workflow requires at least one agent".to_string());
+        }
+
+        let assignments = agent_ids
+            .into_iter()
+            .map(|agent_id| Assignment {
+                agent_id,
+                subtask: task_description.clone(),
+            })
+            .collect();
+
+        let workflow = Self::new_workflow(owner, task_description, assignments, true, None, None);
+        Self::checkpoint(&workflow);
+
+        Self::run(workflow).await
+    }
+
+    /// Run the same task on every agent and merge the outputs with `strategy`
+    pub async fn start_parallel(
+        owner: String,
+        agent_ids: Vec<String>,
+        task_description: String,
+        strategy: AggregationStrategy,
+    ) -> Result<Workflow, String> {
+        if agent_ids.is_empty() {
+            return Err("Workflow requires at least one agent".to_string());
+        }
+
+        let assignments = agent_ids
+            .into_iter()
+            .map(|agent_id| Assignment {
+                agent_id,
+                subtask: task_description.clone(),
+            })
+            .collect();
+
+        let workflow = Self::new_workflow(owner, task_description, assignments, false, None, Some(strategy));
+        Self::checkpoint(&workflow);
+
+        Self::run(workflow).await
+    }
+
+    /// Let a coordinator agent decompose the task, run the subtasks on its
+    /// members and synthesize a combined answer
+    pub async fn start_coordinated(
+        owner: String,
+        coordinator_id: &str,
+        task_description: String,
+    ) -> Result<Workflow, String> {
+        let coordinator = AgentFactory::find_agent(coordinator_id)?;
+        if !matches!(coordinator.analysis.agent_configuration.agent_type, AgentType::Coordinator) {
+            return Err(format!("Agent {} is not a coordinator", coordinator_id));
+        }
+
+        let assignments = CoordinatorService::plan(&coordinator, &task_description).await?;
+        let strategy = coordinator.analysis.coordination_requirements.aggregation.clone();
+        let workflow = Self::new_workflow(
+            owner,
+            task_description,
+            assignments,
+            false,
+            Some(coordinator_id.to_string()),
+            Some(strategy),
+        );
+        Self::checkpoint(&workflow);
+
+        Self::run(workflow).await
+    }
+
+    fn new_workflow(
+        owner: String,
+        task_description: String,
+        assignments: Vec<Assignment>,
+        chain_outputs: bool,
+        coordinator_id: Option<String>,
+        aggregation: Option<AggregationStrategy>,
+    ) -> Workflow {
+        let now = time();
+        let workflow_id = with_state_mut(|state| {
+            state.workflow_seq += 1;
+            format!("wf-{}-{}", now, state.workflow_seq)
+        });
+        let steps = assignments
+            .into_iter()
+            .enumerate()
+            .map(|(index, assignment)| WorkflowStep {
+                index: index as u32,
+                agent_id: assignment.agent_id,
+                description: assignment.subtask,
+                status: StepStatus::Pending,
+                output: None,
+                tokens_used: 0,
+                completed_at: None,
+            })
+            .collect();
+
+        Workflow {
+            workflow_id,
+            owner,
+            task_description,
+            steps,
+            next_step: 0,
+            status: WorkflowStatus::Running,
+            created_at: now,
+            updated_at: now,
+            chain_outputs,
+            coordinator_id,
+            final_output: None,
+            aggregation,
+            aggregation_summary: None,
+        }
+    }
+
+    /// Continue a workflow from its last checkpoint
+    pub async fn resume_workflow(workflow_id: &str) -> Result<Workflow, String> {
+        let mut workflow = Self::get_workflow(workflow_id)?;
+
+        match workflow.status {
+            WorkflowStatus::Completed => return Ok(workflow),
+            WorkflowStatus::Running if time() < workflow.updated_at + STALE_AFTER_NS => {
+                return Err(format!("Workflow {} is still running", workflow_id));
+            }
+            _ => {}
+        }
+
+        workflow.status = WorkflowStatus::Running;
+        Self::run(workflow).await
+    }
+
+    pub fn get_workflow(workflow_id: &str) -> Result<Workflow, String> {
+        WORKFLOWS.with(|w| {
+            w.borrow()
+                .get(&workflow_id.to_string())
+                .map(|wf| wf.0)
+                .ok_or_else(|| format!("Workflow {} not found", workflow_id))
+        })
+    }
+
+    /// Steps of running workflows not yet started, per agent
+    pub fn queued_steps() -> HashMap<String, u32> {
+        let now = time();
+        let mut queued = HashMap::new();
+        WORKFLOWS.with(|w| {
+            for (_, wf) in w.borrow().iter() {
+                let wf = wf.0;
+                if effective_status(&wf, now) != WorkflowStatus::Running {
+                    continue;
+                }
+                // The step at next_step is in flight and counted by the agent factory
+                for step in wf.steps.iter().skip(wf.next_step as usize + 1) {
+                    *queued.entry(step.agent_id.clone()).or_insert(0) += 1;
+                }
+            }
+        });
+        queued
+    }
+
+    pub fn count_running() -> u32 {
+        let now = time();
+        WORKFLOWS.with(|w| {
+            w.borrow()
+                .iter()
+                .filter(|(_, wf)| effective_status(&wf.0, now) == WorkflowStatus::Running)
+                .count() as u32
+        })
+    }
+
+    /// Mark in-flight workflows as interrupted and resume them on a timer.
+    /// Must run in post_upgrade after agents have been restored.
+    pub fn rehydrate_after_upgrade() {
+        let interrupted: Vec<String> = WORKFLOWS.with(|w| {
+            let mut workflows = w.borrow_mut();
+            let running: Vec<(String, Workflow)> = workflows
+                .iter()
+                .filter(|(_, wf)| wf.0.status == WorkflowStatus::Running)
+                .map(|(id, wf)| (id, wf.0))
+                .collect();
+
+            running
+                .into_iter()
+                .map(|(id, mut wf)| {
+                    wf.status = WorkflowStatus::Interrupted;
+                    workflows.insert(id.clone(), Cbor(wf));
+                    id
+                })
+                .collect()
+        });
+
+        if interrupted.is_empty() {
+            return;
+        }
+
+        ic_cdk_timers::set_timer(Duration::ZERO, move || {
+            ic_cdk::spawn(async move {
+                for workflow_id in interrupted {
+                    if let Err(e) = Self::resume_workflow(&workflow_id).await {
+                        ic_cdk::println!("resume of {} failed: {}", workflow_id, e);
+                    }
+                }
+            });
+        });
+    }
+
+    async fn run(mut workflow: Workflow) -> Result<Workflow, String> {
+        while (workflow.next_step as usize) < workflow.steps.len() {
+            let index = workflow.next_step as usize;
+            let agent_id = workflow.steps[index].agent_id.clone();
+            let previous_output = if workflow.chain_outputs {
+                index.checked_sub(1).and_then(|prev| workflow.steps[prev].output.clone())
+            } else {
+                None
+            };
+
+            let task = AgentTask {
+                task_id: format!("{}-step-{}", workflow.workflow_id, index),
+                description: Self::step_description(&workflow.steps[index].description, previous_output.as_deref()),
+                priority: TaskPriority::Normal,
+                deadline: None,
+                context: HashMap::new(),
+            };
+
+            // Refresh the checkpoint so the step counts as live while awaiting
+            workflow.updated_at = time();
+            Self::checkpoint(&workflow);
+
+            let outcome = AgentFactory::execute_task_now(&agent_id, task)
+                .await
+                .map_err(String::from)
+                .and_then(|result| {
+                    if result.success {
+                        Ok(result)
+                    } else {
+                        Err(result.error_message.unwrap_or_else(|| "Task failed".to_string()))
+                    }
+                });
+
+            match outcome {
+                Ok(result) => {
+                    let step = &mut workflow.steps[index];
+                    step.status = StepStatus::Completed;
+                    step.output = Some(result.result);
+                    step.tokens_used = result.tokens_used;
+                    step.completed_at = Some(time());
+                    workflow.next_step += 1;
+                }
+                Err(e) => {
+                    workflow.steps[index].status = StepStatus::Failed(e.clone());
+                    workflow.status = WorkflowStatus::Interrupted;
+                    workflow.updated_at = time();
+                    Self::checkpoint(&workflow);
+                    return Err(format!(
+                        "Workflow {} interrupted at step {}: {}",
+                        workflow.workflow_id, index, e
+                    ));
+                }
+            }
+
+            workflow.updated_at = time();
+            Self::checkpoint(&workflow);
+        }
+
+        if workflow.final_output.is_none() {
+            match workflow.aggregation.clone() {
+                Some(strategy) => match Aggregator::aggregate(&strategy, &workflow).await {
+                    Ok(aggregate) => {
+                        workflow.final_output = Some(aggregate.output);
+                        workflow.aggregation_summary = Some(aggregate.summary);
+                    }
+                    Err(e) => {
+                        workflow.status = WorkflowStatus::Interrupted;
+                        workflow.updated_at = time();
+                        Self::checkpoint(&workflow);
+                        return Err(format!(
+                            "Workflow {} interrupted during aggregation: {}",
+                            workflow.workflow_id, e
+                        ));
+                    }
+                },
+                None => {
+                    workflow.final_output = workflow.steps.last().and_then(|step| step.output.clone());
+                }
+            }
+        }
+
+        workflow.status = WorkflowStatus::Completed;
+        workflow.updated_at = time();
+        Self::checkpoint(&workflow);
+        Ok(workflow)
+    }
+
+    /// Drop completed and abandoned workflows past their retention
+    pub fn prune(now: u64) -> u32 {
+        WORKFLOWS.with(|w| {
+            let mut workflows = w.borrow_mut();
+            let expired: Vec<String> = workflows
+                .iter()
+                .filter(|(_, wf)| retention_over(&wf.0, now))
+                .map(|(id, _)| id)
+                .collect();
+            for id in &expired {
+                workflows.remove(id);
+            }
+            expired.len() as u32
+        })
+    }
+
+    fn step_description(task_description: &str, previous_output: Option<&str>) -> String {
+        match previous_output {
+            Some(output) => format!(
+                "{}\n\nBuild on the previous step's output:\n{}",
+                task_description, output
+            ),
+            None => task_description.to_string(),
+        }
+    }
+
+    fn checkpoint(workflow: &Workflow) {
+        WORKFLOWS.with(|w| {
+            w.borrow_mut()
+                .insert(workflow.workflow_id.clone(), Cbor(workflow.clone()));
+        });
+        CertificationService::mark_dirty();
+    }
+}
+
+/// A Running workflow whose update trapped is never checkpointed again, so
+/// past STALE_AFTER_NS it counts as Interrupted
+fn effective_status(workflow: &Workflow, now: u64) -> WorkflowStatus {
+    match workflow.status {
+        WorkflowStatus::Running if now.saturating_sub(workflow.updated_at) >= STALE_AFTER_NS => WorkflowStatus::Interrupted,
+        ref status => status.clone(),
+    }
+}
+
+fn retention_over(workflow: &Workflow, now: u64) -> bool {
+    let retention = match effective_status(workflow, now) {
+        WorkflowStatus::Running => return false,
+        WorkflowStatus::Completed => COMPLETED_RETENTION_NS,
+        WorkflowStatus::Interrupted => INTERRUPTED_RETENTION_NS,
+    };
+    now.saturating_sub(workflow.updated_at) >= retention
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_finished_or_stale_workflows_expire() {
+        let mut workflow = Workflow {
+            workflow_id: "wf-1-1".to_string(),
+            owner: "owner".to_string(),
+            task_description: String::new(),
+            steps: Vec::new(),
+            next_step: 0,
+            status: WorkflowStatus::Running,
+            created_at: 0,
+            updated_at: 0,
+            chain_outputs: true,
+            coordinator_id: None,
+            final_output: None,
+            aggregation: None,
+            aggregation_summary: None,
+        };
+        assert!(!retention_over(&workflow, STALE_AFTER_NS - 1));
+        assert_eq!(effective_status(&workflow, STALE_AFTER_NS), WorkflowStatus::Interrupted);
+        assert!(!retention_over(&workflow, INTERRUPTED_RETENTION_NS - 1));
+        assert!(retention_over(&workflow, INTERRUPTED_RETENTION_NS));
+        workflow.status = WorkflowStatus::Completed;
+        assert!(!retention_over(&workflow, COMPLETED_RETENTION_NS - 1));
+        assert!(retention_over(&workflow, COMPLETED_RETENTION_NS));
+        workflow.status = WorkflowStatus::Interrupted;
+        assert!(!retention_over(&workflow, COMPLETED_RETENTION_NS));
+        assert!(retention_over(&workflow, INTERRUPTED_RETENTION_NS));
+    }
+}